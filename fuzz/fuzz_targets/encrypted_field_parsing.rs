@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ntp_proto::{fuzz_encrypted_field, ExtensionHeaderVersion};
+use rand::{rngs::StdRng, set_thread_rng, SeedableRng};
+
+fuzz_target!(|parts: (u64, Vec<u8>, Vec<u8>, ExtensionHeaderVersion)| {
+    set_thread_rng(StdRng::seed_from_u64(parts.0));
+
+    fuzz_encrypted_field(&parts.1, &parts.2, parts.3);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ntp_proto::{fuzz_extension_fields, ExtensionHeaderVersion};
+
+fuzz_target!(|parts: (Vec<u8>, ExtensionHeaderVersion)| {
+    fuzz_extension_fields(&parts.0, parts.1);
+});
@@ -121,6 +121,11 @@ fuzz_target!(|parts: (
                 leap_indicator: NtpLeapIndicator::NoWarning,
                 accumulated_steps: NtpDuration::from_seconds(0.0),
                 accumulated_steps_threshold: None,
+                leap_smear: None,
+                monotonic_epoch: 0,
+                monotonic_offset: NtpDuration::ZERO,
+                frequency_correction: None,
+                steering_withheld: None,
             },
         })),
         keyset,
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ntp_proto::fuzz_v5_header;
+
+fuzz_target!(|data: Vec<u8>| {
+    fuzz_v5_header(&data);
+});
@@ -0,0 +1,551 @@
+//! Receiving batches of UDP packets with the `recvmmsg` system call of Linux.
+//!
+//! A busy server finds many requests waiting each time it is woken up.
+//! Receiving all of them with a single system call, into buffers that are
+//! allocated once, saves most of the per packet overhead. Every received
+//! packet keeps its own kernel receive timestamp.
+//!
+//! Responses are sent one by one, right after they are made: their transmit
+//! timestamp is covered by their MAC or NTS authenticator, so it cannot be
+//! refreshed just before a batch of them leaves.
+//!
+//! This is kept out of the daemon, as these system calls need unsafe code.
+#![cfg(target_os = "linux")]
+
+use std::{
+    io, mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    ptr,
+};
+
+use libc::{c_int, c_uint, sa_family_t, socklen_t};
+use tokio::{io::Interest, net::UdpSocket};
+
+/// Largest number of packets in a single batch
+pub const MAX_BATCH_SIZE: usize = 64;
+
+/// Room for the control message holding the receive timestamp, aligned like
+/// the control message header
+type ControlBuffer = [u64; 8];
+
+/// Kernel receive timestamp of a packet, since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub seconds: i64,
+    pub nanos: u32,
+}
+
+/// A packet in a [`RecvBatch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub remote_addr: SocketAddr,
+    pub timestamp: Option<Timestamp>,
+    /// Buffer the packet was received in
+    index: usize,
+    length: usize,
+}
+
+fn check_sizes(batch_size: usize, packet_size: usize) {
+    assert!(
+        (1..=MAX_BATCH_SIZE).contains(&batch_size),
+        "batch size must be between 1 and {MAX_BATCH_SIZE}"
+    );
+    assert!(packet_size > 0, "packet size must not be zero");
+}
+
+/// Buffers for a batch of received packets, which are reused for every batch
+pub struct RecvBatch {
+    buffers: Box<[u8]>,
+    packet_size: usize,
+    names: Box<[libc::sockaddr_storage]>,
+    control: Box<[ControlBuffer]>,
+    packets: Vec<Packet>,
+    truncated: usize,
+}
+
+impl RecvBatch {
+    /// Buffers for up to `batch_size` packets of `packet_size` bytes. Longer
+    /// packets are dropped.
+    ///
+    /// # Panics
+    ///
+    /// When `batch_size` is zero or larger than [`MAX_BATCH_SIZE`], or
+    /// `packet_size` is zero.
+    pub fn new(batch_size: usize, packet_size: usize) -> RecvBatch {
+        check_sizes(batch_size, packet_size);
+        RecvBatch {
+            buffers: vec![0; batch_size * packet_size].into_boxed_slice(),
+            packet_size,
+            // SAFETY: all zeroes is a valid sockaddr_storage
+            names: vec![unsafe { mem::zeroed() }; batch_size].into_boxed_slice(),
+            control: vec![[0; 8]; batch_size].into_boxed_slice(),
+            packets: Vec::with_capacity(batch_size),
+            truncated: 0,
+        }
+    }
+
+    /// The packets received last, together with their contents
+    pub fn iter(&self) -> impl Iterator<Item = (&Packet, &[u8])> {
+        self.packets.iter().map(|packet| {
+            let buffer = &self.buffers[packet.index * self.packet_size..];
+            (packet, &buffer[..packet.length])
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Number of packets dropped from the last batch for not fitting in
+    /// their buffer
+    pub fn truncated(&self) -> usize {
+        self.truncated
+    }
+}
+
+/// A UDP socket that receives the kernel receive timestamp of every packet
+#[derive(Debug)]
+pub struct BatchSocket {
+    socket: UdpSocket,
+}
+
+impl BatchSocket {
+    /// Open a socket bound to `addr`. With `reuse_port`, several sockets can
+    /// be bound to the same address, and the kernel spreads the incoming
+    /// packets over them by the address of their sender.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn bind(addr: SocketAddr, reuse_port: bool) -> io::Result<BatchSocket> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        // SAFETY: socket takes no pointers
+        let fd = unsafe {
+            libc::socket(
+                domain,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just opened, and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        if reuse_port {
+            enable_option(&fd, libc::SOL_SOCKET, libc::SO_REUSEPORT)?;
+        }
+        enable_option(&fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS)?;
+
+        // SAFETY: all zeroes is a valid sockaddr_storage
+        let mut name = unsafe { mem::zeroed() };
+        let name_length = write_raw_addr(&addr, &mut name);
+        // SAFETY: name holds an address of name_length bytes
+        let result =
+            unsafe { libc::bind(fd.as_raw_fd(), ptr::from_ref(&name).cast(), name_length) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(BatchSocket {
+            socket: UdpSocket::from_std(std::net::UdpSocket::from(fd))?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Wait for packets, and receive as many as fit in `batch`, replacing its
+    /// previous contents. Returns the number of packets received, not
+    /// counting those that were dropped.
+    pub async fn recv(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        let fd = self.socket.as_raw_fd();
+        self.socket
+            .async_io(Interest::READABLE, || recv_batch(fd, batch))
+            .await
+    }
+
+    /// Send a single packet to `addr`
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.socket.send_to(buf, addr).await
+    }
+}
+
+/// Turn on a boolean socket option
+fn enable_option(fd: &OwnedFd, level: c_int, name: c_int) -> io::Result<()> {
+    let value: c_int = 1;
+    // SAFETY: value lives for the duration of the call, and its size is
+    // passed along
+    let result = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            ptr::from_ref(&value).cast(),
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive the waiting packets into `batch`, without blocking
+fn recv_batch(fd: c_int, batch: &mut RecvBatch) -> io::Result<usize> {
+    let batch_size = batch.names.len();
+
+    // SAFETY: all zeroes is a valid iovec and mmsghdr, with null pointers
+    // and zero lengths
+    let mut iovecs: [libc::iovec; MAX_BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut headers: [libc::mmsghdr; MAX_BATCH_SIZE] = unsafe { mem::zeroed() };
+
+    for (iovec, buffer) in iovecs
+        .iter_mut()
+        .zip(batch.buffers.chunks_exact_mut(batch.packet_size))
+    {
+        iovec.iov_base = buffer.as_mut_ptr().cast();
+        iovec.iov_len = buffer.len();
+    }
+
+    let iovecs = iovecs.as_mut_ptr();
+    for (index, ((header, name), control)) in headers
+        .iter_mut()
+        .zip(batch.names.iter_mut())
+        .zip(batch.control.iter_mut())
+        .enumerate()
+    {
+        let header = &mut header.msg_hdr;
+        header.msg_name = ptr::from_mut(name).cast();
+        header.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as socklen_t;
+        header.msg_iov = iovecs.wrapping_add(index);
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr().cast();
+        header.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+    }
+
+    // SAFETY: the first batch_size headers point to the buffers, names and
+    // control buffers of the batch and to the iovecs, which all outlive the
+    // call, with matching lengths
+    let count = unsafe {
+        libc::recvmmsg(
+            fd,
+            headers.as_mut_ptr(),
+            batch_size as c_uint,
+            libc::MSG_DONTWAIT,
+            ptr::null_mut(),
+        )
+    };
+    let Ok(count) = usize::try_from(count) else {
+        return Err(io::Error::last_os_error());
+    };
+
+    batch.packets.clear();
+    batch.truncated = 0;
+    for (index, header) in headers[..count].iter().enumerate() {
+        // The kernel cut off the rest of a packet larger than its buffer,
+        // what remains cannot be handled as if it were the whole packet
+        if header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+            batch.truncated += 1;
+            continue;
+        }
+        // Only UDP over IP arrives here, anything else is skipped
+        let Some(remote_addr) = socket_addr(&batch.names[index], header.msg_hdr.msg_namelen) else {
+            continue;
+        };
+        batch.packets.push(Packet {
+            remote_addr,
+            timestamp: receive_timestamp(&header.msg_hdr),
+            index,
+            length: header.msg_len as usize,
+        });
+    }
+
+    Ok(batch.packets.len())
+}
+
+/// The receive timestamp in the control messages of a received packet
+fn receive_timestamp(header: &libc::msghdr) -> Option<Timestamp> {
+    // SAFETY: the kernel filled in msg_controllen bytes of control messages,
+    // which the CMSG macros stay within
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(header);
+        while let Some(message) = cmsg.as_ref() {
+            if message.cmsg_level == libc::SOL_SOCKET && message.cmsg_type == libc::SCM_TIMESTAMPNS
+            {
+                let time = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::timespec>());
+                #[allow(
+                    clippy::useless_conversion,
+                    reason = "time_t is only 32 bits on some platforms"
+                )]
+                return Some(Timestamp {
+                    seconds: i64::from(time.tv_sec),
+                    nanos: u32::try_from(time.tv_nsec).ok()?,
+                });
+            }
+            cmsg = libc::CMSG_NXTHDR(header, cmsg);
+        }
+    }
+
+    None
+}
+
+/// The address the kernel stored in `name`
+fn socket_addr(name: &libc::sockaddr_storage, length: socklen_t) -> Option<SocketAddr> {
+    let length = length as usize;
+    match c_int::from(name.ss_family) {
+        libc::AF_INET if length >= mem::size_of::<libc::sockaddr_in>() => {
+            // SAFETY: the kernel stored a sockaddr_in, which fits in a
+            // sockaddr_storage and needs no stricter alignment
+            let addr = unsafe { &*ptr::from_ref(name).cast::<libc::sockaddr_in>() };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 if length >= mem::size_of::<libc::sockaddr_in6>() => {
+            // SAFETY: the kernel stored a sockaddr_in6, which fits in a
+            // sockaddr_storage and needs no stricter alignment
+            let addr = unsafe { &*ptr::from_ref(name).cast::<libc::sockaddr_in6>() };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// Store `addr` in `name` the way the kernel expects it, returning its length
+fn write_raw_addr(addr: &SocketAddr, name: &mut libc::sockaddr_storage) -> socklen_t {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let raw = libc::sockaddr_in {
+                sin_family: libc::AF_INET as sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(*addr.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            // SAFETY: a sockaddr_in fits in a sockaddr_storage and needs no
+            // stricter alignment
+            unsafe { ptr::from_mut(name).cast::<libc::sockaddr_in>().write(raw) };
+            mem::size_of::<libc::sockaddr_in>() as socklen_t
+        }
+        SocketAddr::V6(addr) => {
+            let raw = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            // SAFETY: a sockaddr_in6 fits in a sockaddr_storage and needs no
+            // stricter alignment
+            unsafe { ptr::from_mut(name).cast::<libc::sockaddr_in6>().write(raw) };
+            mem::size_of::<libc::sockaddr_in6>() as socklen_t
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn localhost() -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0))
+    }
+
+    fn client() -> std::net::UdpSocket {
+        let client = std::net::UdpSocket::bind(localhost()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn test_addr_roundtrip() {
+        for addr in [
+            "127.0.0.1:123".parse().unwrap(),
+            "[2001:db8::1]:4460".parse().unwrap(),
+            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 123, 5, 2)),
+        ] {
+            // SAFETY: all zeroes is a valid sockaddr_storage
+            let mut name = unsafe { mem::zeroed() };
+            let length = write_raw_addr(&addr, &mut name);
+            assert_eq!(socket_addr(&name, length), Some(addr));
+            assert_eq!(socket_addr(&name, 4), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_batch() {
+        let socket = BatchSocket::bind(localhost(), false).unwrap();
+        let client = client();
+        for i in 0..6_u8 {
+            client
+                .send_to(&[i; 10][..usize::from(i)], socket.local_addr().unwrap())
+                .unwrap();
+        }
+
+        let mut batch = RecvBatch::new(4, 32);
+        assert_eq!(socket.recv(&mut batch).await.unwrap(), 4);
+        for (i, (packet, contents)) in batch.iter().enumerate() {
+            assert_eq!(contents, &[i as u8; 10][..i]);
+            assert_eq!(packet.remote_addr, client.local_addr().unwrap());
+
+            let timestamp = packet.timestamp.unwrap();
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
+            assert!(now.as_secs().abs_diff(timestamp.seconds as u64) < 5);
+        }
+
+        // The rest of the packets is left for the next batch
+        assert_eq!(socket.recv(&mut batch).await.unwrap(), 2);
+        let contents: Vec<_> = batch.iter().map(|(_, contents)| contents).collect();
+        assert_eq!(contents, [&[4; 4][..], &[5; 5]]);
+    }
+
+    #[tokio::test]
+    async fn test_recv_truncated() {
+        let socket = BatchSocket::bind(localhost(), false).unwrap();
+        let client = client();
+        for packet in [&[1; 32][..], &[2; 33], &[3; 40], &[4; 8]] {
+            client
+                .send_to(packet, socket.local_addr().unwrap())
+                .unwrap();
+        }
+
+        // Packets longer than their buffer are dropped, not cut short
+        let mut batch = RecvBatch::new(4, 32);
+        assert_eq!(socket.recv(&mut batch).await.unwrap(), 2);
+        assert_eq!(batch.truncated(), 2);
+        let contents: Vec<_> = batch.iter().map(|(_, contents)| contents).collect();
+        assert_eq!(contents, [&[1; 32][..], &[4; 8]]);
+
+        client
+            .send_to(&[5; 5], socket.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(socket.recv(&mut batch).await.unwrap(), 1);
+        assert_eq!(batch.truncated(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_to() {
+        let socket = BatchSocket::bind(localhost(), false).unwrap();
+        let client = client();
+
+        socket
+            .send_to(b"one", client.local_addr().unwrap())
+            .await
+            .unwrap();
+        // Cannot be sent from an IPv4 socket
+        assert!(
+            socket
+                .send_to(b"two", "[::1]:123".parse().unwrap())
+                .await
+                .is_err()
+        );
+
+        let mut buf = [0; 32];
+        let (length, addr) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..length], b"one");
+        assert_eq!(addr, socket.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reuse_port() {
+        let first = BatchSocket::bind(localhost(), true).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = BatchSocket::bind(addr, true).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+
+        assert!(BatchSocket::bind(addr, false).is_err());
+    }
+
+    /// Answer requests from a single client as fast as possible, returning
+    /// how many per second
+    async fn requests_per_second(server_addr: SocketAddr) -> f64 {
+        const REQUESTS: usize = 1_000_000;
+        const IN_FLIGHT: usize = 256;
+
+        let start = std::time::Instant::now();
+        let answered = tokio::task::spawn_blocking(move || {
+            let client = client();
+            client.connect(server_addr).unwrap();
+            let mut buf = [0; 48];
+            let mut answered = 0;
+            for _ in 0..IN_FLIGHT {
+                client.send(&[0x23; 48]).unwrap();
+            }
+            for sent in IN_FLIGHT..REQUESTS + IN_FLIGHT {
+                if client.recv(&mut buf).is_err() {
+                    break;
+                }
+                answered += 1;
+                if sent < REQUESTS {
+                    client.send(&[0x23; 48]).unwrap();
+                }
+            }
+            answered
+        })
+        .await
+        .unwrap();
+
+        answered as f64 / start.elapsed().as_secs_f64()
+    }
+
+    /// Rough throughput of receiving requests in batches, compared to
+    /// receiving them one by one, run with
+    /// `cargo test --release -p ntp-mmsg -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore = "load test"]
+    async fn load_test() {
+        let socket = UdpSocket::bind(localhost()).await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let baseline = tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            loop {
+                let (length, remote_addr) = socket.recv_from(&mut buf).await.unwrap();
+                socket.send_to(&buf[..length], remote_addr).await.unwrap();
+            }
+        });
+        let per_packet = requests_per_second(server_addr).await;
+        baseline.abort();
+
+        let socket = BatchSocket::bind(localhost(), false).unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut recv = RecvBatch::new(32, 1024);
+            loop {
+                socket.recv(&mut recv).await.unwrap();
+                for (packet, request) in recv.iter() {
+                    socket.send_to(request, packet.remote_addr).await.unwrap();
+                }
+            }
+        });
+        let batched = requests_per_second(server_addr).await;
+
+        println!(
+            "{per_packet:.0} requests per second one by one, {batched:.0} in batches ({:+.0}%)",
+            (batched / per_packet - 1.0) * 100.0
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+use crate::{config::DelayAsymmetry, time_types::NtpDuration};
+
+// Number of recent measurements used to estimate the asymmetry
+const ESTIMATE_WINDOW: usize = 32;
+// Minimum number of measurements before the estimate is used
+const ESTIMATE_MIN_SAMPLES: usize = 8;
+
+/// Corrects the offsets of a source for the asymmetry of its network path
+#[derive(Debug, Clone)]
+pub(super) struct AsymmetryCorrection {
+    config: DelayAsymmetry,
+    // Delay and uncorrected offset (in seconds) of recent measurements
+    samples: VecDeque<(f64, f64)>,
+}
+
+impl AsymmetryCorrection {
+    pub(super) fn new(config: DelayAsymmetry) -> Self {
+        AsymmetryCorrection {
+            config,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Correct `offset`, computed assuming a symmetric path, of a measurement
+    /// with the given round trip `delay`
+    pub(super) fn correct(&mut self, offset: NtpDuration, delay: NtpDuration) -> NtpDuration {
+        let correction = match self.config {
+            DelayAsymmetry::None => return offset,
+            DelayAsymmetry::Fraction(fraction) => (fraction - 0.5) * delay.to_seconds(),
+            DelayAsymmetry::Absolute(asymmetry) => asymmetry,
+            DelayAsymmetry::Auto => self.estimate(offset.to_seconds(), delay.to_seconds()),
+        };
+
+        offset - NtpDuration::from_seconds(correction)
+    }
+
+    /// Record a measurement and estimate its asymmetry. When queueing on one
+    /// direction of the path increases the delay, the offset moves along
+    /// with half that increase. The slope of a least squares fit of offset
+    /// against delay gives the fraction of the variation in delay that is
+    /// asymmetric, which is applied to the delay in excess of the lowest one
+    /// seen recently.
+    fn estimate(&mut self, offset: f64, delay: f64) -> f64 {
+        if self.samples.len() >= ESTIMATE_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((delay, offset));
+
+        if self.samples.len() < ESTIMATE_MIN_SAMPLES {
+            return 0.0;
+        }
+
+        let count = self.samples.len() as f64;
+        let mean_delay = self.samples.iter().map(|(delay, _)| delay).sum::<f64>() / count;
+        let mean_offset = self.samples.iter().map(|(_, offset)| offset).sum::<f64>() / count;
+        let (covariance, delay_variance) = self.samples.iter().fold(
+            (0.0, 0.0),
+            |(covariance, variance), (sample_delay, sample_offset)| {
+                (
+                    covariance + (sample_delay - mean_delay) * (sample_offset - mean_offset),
+                    variance + (sample_delay - mean_delay) * (sample_delay - mean_delay),
+                )
+            },
+        );
+        if delay_variance <= 0.0 {
+            return 0.0;
+        }
+
+        let slope = (covariance / delay_variance).clamp(-0.5, 0.5);
+        let min_delay = self
+            .samples
+            .iter()
+            .map(|(delay, _)| *delay)
+            .fold(f64::INFINITY, f64::min);
+        slope * (delay - min_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correct(correction: &mut AsymmetryCorrection, offset: f64, delay: f64) -> f64 {
+        correction
+            .correct(
+                NtpDuration::from_seconds(offset),
+                NtpDuration::from_seconds(delay),
+            )
+            .to_seconds()
+    }
+
+    #[test]
+    fn test_static() {
+        let mut none = AsymmetryCorrection::new(DelayAsymmetry::None);
+        assert!((correct(&mut none, 0.01, 0.1) - 0.01).abs() < 1e-9);
+
+        // Requests take 60ms, responses 40ms, so the symmetric offset is
+        // 10ms too large
+        let mut fraction = AsymmetryCorrection::new(DelayAsymmetry::Fraction(0.6));
+        assert!(correct(&mut fraction, 0.01, 0.1).abs() < 1e-9);
+
+        let mut absolute = AsymmetryCorrection::new(DelayAsymmetry::Absolute(-0.005));
+        assert!((correct(&mut absolute, 0.01, 0.1) - 0.015).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_auto() {
+        let mut auto = AsymmetryCorrection::new(DelayAsymmetry::Auto);
+
+        // Queueing only on the way to the source: the offset increases by
+        // half of every increase in delay
+        for i in 0..ESTIMATE_MIN_SAMPLES {
+            let extra_delay = 0.001 * (i % 4) as f64;
+            let corrected = correct(&mut auto, 0.002 + extra_delay / 2.0, 0.01 + extra_delay);
+            if i + 1 == ESTIMATE_MIN_SAMPLES {
+                assert!((corrected - 0.002).abs() < 1e-9);
+            }
+        }
+
+        // Without variation in delay there is nothing to estimate from
+        let mut constant = AsymmetryCorrection::new(DelayAsymmetry::Auto);
+        for _ in 0..ESTIMATE_WINDOW {
+            assert!((correct(&mut constant, 0.002, 0.01) - 0.002).abs() < 1e-9);
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{ClockId, packet::NtpLeapIndicator, time_types::NtpDuration};
 
-use super::{SourceSnapshot, config::AlgorithmConfig, source::KalmanState};
+use super::{
+    SourceSnapshot,
+    config::{AlgorithmConfig, CombineMode},
+    source::KalmanState,
+};
 
 pub(super) struct Combine {
     pub estimate: KalmanState,
@@ -36,38 +40,106 @@ fn vote_leap(selection: &[SourceSnapshot]) -> Option<NtpLeapIndicator> {
     }
 }
 
+/// Estimate of a single source as it enters the combination
+fn source_estimate(snapshot: &SourceSnapshot, algo_config: &AlgorithmConfig) -> KalmanState {
+    if algo_config.ignore_server_dispersion {
+        snapshot.state
+    } else {
+        snapshot
+            .state
+            .add_server_dispersion(snapshot.source_uncertainty.to_seconds())
+    }
+}
+
+/// Estimate of a source with its uncertainty scaled by the weight of the
+/// source. Mixing these makes the pull of a source on the combined estimate
+/// proportional to its weight.
+fn weighted_estimate(snapshot: &SourceSnapshot, estimate: KalmanState) -> KalmanState {
+    KalmanState {
+        uncertainty: (1.0 / snapshot.weight) * estimate.uncertainty,
+        ..estimate
+    }
+}
+
+/// The sources in the middle when ordered by offset, taking their weights
+/// into account. This is the source at which half of the total weight is
+/// reached, or the two sources on either side of that point when it falls
+/// exactly between them.
+fn median_sources(mut basis: Vec<&SourceSnapshot>) -> Vec<&SourceSnapshot> {
+    basis.sort_by(|a, b| a.state.offset().total_cmp(&b.state.offset()));
+
+    let half = basis.iter().map(|snapshot| snapshot.weight).sum::<f64>() / 2.0;
+    let mut cumulative = 0.0;
+    for (i, snapshot) in basis.iter().enumerate() {
+        cumulative += snapshot.weight;
+        if cumulative > half {
+            return vec![*snapshot];
+        }
+        if cumulative == half {
+            return basis[i..(i + 2).min(basis.len())].to_vec();
+        }
+    }
+
+    // Only reachable through rounding, in which case the last source is the
+    // one that reaches half of the weight
+    basis.split_off(basis.len().saturating_sub(1))
+}
+
 pub(super) fn combine(
     selection: &[SourceSnapshot],
     algo_config: &AlgorithmConfig,
 ) -> Option<Combine> {
-    selection.first().map(|first| {
-        let mut estimate = first.state;
-        if !algo_config.ignore_server_dispersion {
-            estimate = estimate.add_server_dispersion(first.source_uncertainty.to_seconds());
-        }
+    // Preferred sources only get special treatment after they survived
+    // selection, which is done without regard for preference.
+    let basis: Vec<&SourceSnapshot> = if selection.iter().any(|snapshot| snapshot.prefer) {
+        selection
+            .iter()
+            .filter(|snapshot| snapshot.prefer)
+            .collect()
+    } else {
+        selection.iter().collect()
+    };
+
+    let basis = match algo_config.combine_mode {
+        CombineMode::WeightedAverage => basis,
+        CombineMode::Median => median_sources(basis),
+        CombineMode::Best => basis
+            .into_iter()
+            .min_by(|a, b| {
+                let a = weighted_estimate(a, source_estimate(a, algo_config));
+                let b = weighted_estimate(b, source_estimate(b, algo_config));
+                a.uncertainty
+                    .determinant()
+                    .total_cmp(&b.uncertainty.determinant())
+            })
+            .into_iter()
+            .collect(),
+    };
+
+    basis.first().map(|first| {
+        let mut estimate = source_estimate(first, algo_config);
+        let mut weighted = weighted_estimate(first, estimate);
 
         let mut used_sources = vec![(first.index, estimate.uncertainty.determinant())];
 
-        for snapshot in selection.iter().skip(1) {
-            let source_estimate = if algo_config.ignore_server_dispersion {
-                snapshot.state
-            } else {
-                snapshot
-                    .state
-                    .add_server_dispersion(snapshot.source_uncertainty.to_seconds())
-            };
+        for snapshot in basis.iter().skip(1) {
+            let source_estimate = source_estimate(snapshot, algo_config);
 
             used_sources.push((snapshot.index, source_estimate.uncertainty.determinant()));
 
+            // The weights only decide how the states are mixed, the
+            // uncertainty of the result is that of the unweighted sources
+            weighted = weighted.merge(&weighted_estimate(snapshot, source_estimate));
             estimate = estimate.merge(&source_estimate);
         }
+        estimate.state = weighted.state;
 
         used_sources.sort_by(|a, b| a.1.total_cmp(&b.1));
 
         Combine {
             estimate,
             sources: used_sources.iter().map(|v| v.0).collect(),
-            delay: selection
+            delay: basis
                 .iter()
                 .map(|v| NtpDuration::from_seconds(v.delay) + v.source_delay)
                 .min()
@@ -108,6 +180,9 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
             last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            prefer: false,
+            trust: false,
         }
     }
 
@@ -220,6 +295,201 @@ mod tests {
         assert_eq!(result.sources, vec![ClockId(1), ClockId(0)]);
     }
 
+    #[test]
+    fn test_default_weights_unchanged() {
+        let selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 1e-6]),
+                Matrix::new([[1e-6, 1e-9], [1e-9, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([1e-3, 0.0]),
+                Matrix::new([[3e-6, 0.0], [0.0, 2e-12]]),
+                2e-3,
+            ),
+        ];
+
+        // Combination as done before sources had weights
+        let expected = selected[0]
+            .state
+            .add_server_dispersion(selected[0].source_uncertainty.to_seconds())
+            .merge(
+                &selected[1]
+                    .state
+                    .add_server_dispersion(selected[1].source_uncertainty.to_seconds()),
+            );
+
+        let result = combine(&selected, &AlgorithmConfig::default()).unwrap();
+        assert_eq!(result.estimate.offset(), expected.offset());
+        assert_eq!(result.estimate.frequency(), expected.frequency());
+        assert_eq!(
+            result.estimate.offset_variance(),
+            expected.offset_variance()
+        );
+        assert_eq!(
+            result.estimate.frequency_variance(),
+            expected.frequency_variance()
+        );
+        assert_eq!(
+            result.estimate.uncertainty.entry(0, 1),
+            expected.uncertainty.entry(0, 1)
+        );
+    }
+
+    #[test]
+    fn test_weights() {
+        let selected = vec![
+            SourceSnapshot {
+                index: ClockId(1),
+                weight: 10.0,
+                ..snapshot_for_state(
+                    Vector::new_vector([0.0, 0.0]),
+                    Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                )
+            },
+            SourceSnapshot {
+                index: ClockId(2),
+                ..snapshot_for_state(
+                    Vector::new_vector([1.1e-3, 1.1e-6]),
+                    Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                )
+            },
+        ];
+
+        // The heavier source pulls ten times as hard on the estimate
+        let result = combine(&selected, &AlgorithmConfig::default()).unwrap();
+        assert!((result.estimate.offset() - 1e-4).abs() < 1e-12);
+        assert!((result.estimate.frequency() - 1e-7).abs() < 1e-15);
+        assert_eq!(result.sources, vec![ClockId(1), ClockId(2)]);
+
+        // But the uncertainty of the estimate is as without weights
+        let expected = selected[0]
+            .state
+            .add_server_dispersion(selected[0].source_uncertainty.to_seconds())
+            .merge(
+                &selected[1]
+                    .state
+                    .add_server_dispersion(selected[1].source_uncertainty.to_seconds()),
+            );
+        assert_eq!(
+            result.estimate.offset_variance(),
+            expected.offset_variance()
+        );
+        assert_eq!(
+            result.estimate.frequency_variance(),
+            expected.frequency_variance()
+        );
+    }
+
+    #[test]
+    fn test_prefer() {
+        let selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            SourceSnapshot {
+                index: ClockId(1),
+                prefer: true,
+                leap_indicator: NtpLeapIndicator::Leap61,
+                ..snapshot_for_state(
+                    Vector::new_vector([1e-3, 0.0]),
+                    Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                )
+            },
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+        ];
+
+        // Only the preferred source determines the estimate
+        let result = combine(&selected, &AlgorithmConfig::default()).unwrap();
+        assert_eq!(result.estimate.offset(), 1e-3);
+        assert_eq!(result.sources, vec![ClockId(1)]);
+
+        // But it does not get to decide on leap seconds by itself
+        assert_eq!(result.leap_indicator, Some(NtpLeapIndicator::NoWarning));
+    }
+
+    fn snapshots_for_offsets(offsets: &[f64]) -> Vec<SourceSnapshot> {
+        offsets
+            .iter()
+            .enumerate()
+            .map(|(i, offset)| SourceSnapshot {
+                index: ClockId(i as u64),
+                ..snapshot_for_state(
+                    Vector::new_vector([*offset, 0.0]),
+                    Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                    1e-3,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_median() {
+        let algconfig = AlgorithmConfig {
+            combine_mode: CombineMode::Median,
+            ..Default::default()
+        };
+
+        // The outlying source doesn't pull the estimate
+        let selected = snapshots_for_offsets(&[1e-3, 0.0, 5e-3]);
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.estimate.offset(), 1e-3);
+        assert_eq!(result.sources, vec![ClockId(0)]);
+
+        // With an even number of sources, the middle two are averaged
+        let selected = snapshots_for_offsets(&[4e-3, 0.0, 5e-3, 2e-3]);
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!((result.estimate.offset() - 3e-3).abs() < 1e-12);
+        assert_eq!(result.sources.len(), 2);
+        assert!(result.sources.contains(&ClockId(0)));
+        assert!(result.sources.contains(&ClockId(3)));
+
+        // A heavy source moves the median towards itself
+        let mut selected = snapshots_for_offsets(&[0.0, 1e-3, 2e-3]);
+        selected[2].weight = 3.0;
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.estimate.offset(), 2e-3);
+        assert_eq!(result.sources, vec![ClockId(2)]);
+    }
+
+    #[test]
+    fn test_best() {
+        let algconfig = AlgorithmConfig {
+            combine_mode: CombineMode::Best,
+            ..Default::default()
+        };
+
+        let mut selected = snapshots_for_offsets(&[0.0, 1e-3, 2e-3]);
+        selected[1].state.uncertainty = Matrix::new([[1e-7, 0.0], [0.0, 1e-13]]);
+        selected[1].source_uncertainty = NtpDuration::from_seconds(1e-4);
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.estimate.offset(), 1e-3);
+        assert_eq!(result.sources, vec![ClockId(1)]);
+
+        // The weight of a source counts when picking the best one, but not
+        // towards the uncertainty of the estimate
+        selected[2].weight = 1000.0;
+        let result = combine(&selected, &algconfig).unwrap();
+        assert_eq!(result.sources, vec![ClockId(2)]);
+        assert_eq!(
+            result.estimate.offset_variance(),
+            selected[2]
+                .state
+                .add_server_dispersion(selected[2].source_uncertainty.to_seconds())
+                .offset_variance()
+        );
+    }
+
     fn snapshot_for_leap(leap: NtpLeapIndicator) -> SourceSnapshot {
         SourceSnapshot {
             index: ClockId(0),
@@ -235,6 +505,9 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.0),
             leap_indicator: leap,
             last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            prefer: false,
+            trust: false,
         }
     }
 
@@ -1,113 +1,238 @@
+#[cfg(feature = "serde")]
 use serde::Deserialize;
 
 use crate::time_types::NtpDuration;
 
-#[derive(Debug, Copy, Clone, Deserialize)]
-#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+/// How the estimates of the sources that survived selection are combined
+/// into the estimate the clock is steered by
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum CombineMode {
+    /// Average of the sources, weighted by their uncertainty
+    #[default]
+    WeightedAverage,
+    /// The source in the middle when ordered by offset, or the average of
+    /// the two middle ones. A single source that is off, even subtly, can't
+    /// move the result past the honest ones.
+    Median,
+    /// The source with the smallest uncertainty
+    Best,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
 pub struct AlgorithmConfig {
     /// Probability bound below which we start moving towards decreasing
     /// our precision estimate. (probability, 0-1)
-    #[serde(default = "default_precision_low_probability")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_precision_low_probability")
+    )]
     pub precision_low_probability: f64,
     /// Probability bound above which we start moving towards increasing
     /// our precision estimate. (probability, 0-1)
-    #[serde(default = "default_precision_high_probability")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_precision_high_probability")
+    )]
     pub precision_high_probability: f64,
     /// Amount of hysteresis in changing the precision estimate. (count, 1+)
-    #[serde(default = "default_precision_hysteresis")]
+    #[cfg_attr(feature = "serde", serde(default = "default_precision_hysteresis"))]
     pub precision_hysteresis: i32,
     /// Lower bound on the amount of effect our precision estimate
     /// has on the total noise estimate before we allow decreasing
     /// of the precision estimate. (weight, 0-1)
-    #[serde(default = "default_precision_minimum_weight")]
+    #[cfg_attr(feature = "serde", serde(default = "default_precision_minimum_weight"))]
     pub precision_minimum_weight: f64,
 
     /// Amount which a measurement contributes to the state, below
     /// which we start increasing the poll interval. (weight, 0-1)
-    #[serde(default = "default_poll_interval_low_weight")]
+    #[cfg_attr(feature = "serde", serde(default = "default_poll_interval_low_weight"))]
     pub poll_interval_low_weight: f64,
     /// Amount which a measurement contributes to the state, above
     /// which we start decreasing the poll_interval interval. (weight, 0-1)
-    #[serde(default = "default_poll_interval_high_weight")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_poll_interval_high_weight")
+    )]
     pub poll_interval_high_weight: f64,
     /// Amount of hysteresis in changing the poll interval (count, 1+)
-    #[serde(default = "default_poll_interval_hysteresis")]
+    #[cfg_attr(feature = "serde", serde(default = "default_poll_interval_hysteresis"))]
     pub poll_interval_hysteresis: i32,
     /// Probability threshold for when a measurement is considered a
     /// significant enough outlier that we decide something weird is
     /// going on and we need to do more measurements. (probability, 0-1)
-    #[serde(default = "default_poll_interval_step_threshold")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_poll_interval_step_threshold")
+    )]
     pub poll_interval_step_threshold: f64,
+    /// Factor by which the poll interval may differ from the interval
+    /// suggested by the clock wander and measurement noise before that
+    /// pushes the poll interval towards it. Zero disables this. (factor, 1+)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_poll_interval_wander_factor")
+    )]
+    pub poll_interval_wander_factor: f64,
 
     /// Threshold (in number of standard deviations) above which
     /// measurements with a significantly larger network delay
     /// are rejected. (standard deviations, 0+)
-    #[serde(default = "default_delay_outlier_threshold")]
+    #[cfg_attr(feature = "serde", serde(default = "default_delay_outlier_threshold"))]
     pub delay_outlier_threshold: f64,
+    /// Number of recent measurements against which the offset of a new
+    /// measurement is compared to reject gross outliers before they reach
+    /// the filter, 0 disables this. (count, 0+)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub outlier_window: usize,
+    /// Distance from the median of the recent measurements (in median
+    /// absolute deviations) above which a measurement is rejected as an
+    /// outlier. (median absolute deviations, 0+)
+    #[cfg_attr(feature = "serde", serde(default = "default_outlier_threshold"))]
+    pub outlier_threshold: f64,
 
     /// Initial estimate of the clock wander of the combination
     /// of our local clock and that of the source. (s/s^2)
-    #[serde(default = "default_initial_wander")]
+    #[cfg_attr(feature = "serde", serde(default = "default_initial_wander"))]
     pub initial_wander: f64,
     /// Initial uncertainty of the frequency difference between
     /// our clock and that of the source. (s/s)
-    #[serde(default = "default_initial_frequency_uncertainty")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_initial_frequency_uncertainty")
+    )]
     pub initial_frequency_uncertainty: f64,
 
     /// Maximum source uncertainty before we start disregarding it
     /// Note that this is combined uncertainty due to noise and
     /// possible asymmetry error (see also weights below). (seconds)
-    #[serde(default = "default_maximum_source_uncertainty")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_maximum_source_uncertainty")
+    )]
     pub maximum_source_uncertainty: f64,
+    /// Maximum root distance of a source, the sum of half the round trip
+    /// delay to the root of its synchronization tree and all dispersion along
+    /// the way, before we start disregarding it. (seconds)
+    #[cfg_attr(feature = "serde", serde(default = "default_maximum_root_distance"))]
+    pub maximum_root_distance: f64,
     /// Weight of statistical uncertainty when constructing
     /// overlap ranges. (standard deviations, 0+)
-    #[serde(default = "default_range_statistical_weight")]
+    #[cfg_attr(feature = "serde", serde(default = "default_range_statistical_weight"))]
     pub range_statistical_weight: f64,
     /// Weight of delay uncertainty when constructing overlap
     /// ranges. (weight, 0-1)
-    #[serde(default = "default_range_delay_weight")]
+    #[cfg_attr(feature = "serde", serde(default = "default_range_delay_weight"))]
     pub range_delay_weight: f64,
+    /// Number of sources below which the cluster algorithm stops pruning
+    /// the sources that survived selection, 0 disables it. (count, 0+)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cluster_minimum_sources: usize,
 
     /// How far from 0 (in multiples of the uncertainty) should
     /// the offset be before we correct. (standard deviations, 0+)
-    #[serde(default = "default_steer_offset_threshold")]
+    #[cfg_attr(feature = "serde", serde(default = "default_steer_offset_threshold"))]
     pub steer_offset_threshold: f64,
     /// How many standard deviations do we leave after offset
     /// correction? (standard deviations, 0+)
-    #[serde(default = "default_steer_offset_leftover")]
+    #[cfg_attr(feature = "serde", serde(default = "default_steer_offset_leftover"))]
     pub steer_offset_leftover: f64,
     /// How far from 0 (in multiples of the uncertainty) should
     /// the frequency estimate be before we correct. (standard deviations, 0+)
-    #[serde(default = "default_steer_frequency_threshold")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_steer_frequency_threshold")
+    )]
     pub steer_frequency_threshold: f64,
     /// How many standard deviations do we leave after frequency
     /// correction? (standard deviations, 0+)
-    #[serde(default = "default_steer_frequency_leftover")]
+    #[cfg_attr(feature = "serde", serde(default = "default_steer_frequency_leftover"))]
     pub steer_frequency_leftover: f64,
     /// From what offset should we step the clock instead of
     /// trying to adjust gradually? (seconds, 0+)
-    #[serde(default = "default_step_threshold")]
+    #[cfg_attr(feature = "serde", serde(default = "default_step_threshold"))]
     pub step_threshold: f64,
     /// What is the maximum frequency offset during a slew (s/s)
-    #[serde(default = "default_slew_maximum_frequency_offset")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_slew_maximum_frequency_offset")
+    )]
     pub slew_maximum_frequency_offset: f64,
     /// What is the minimum duration of a slew (s)
-    #[serde(default = "default_slew_minimum_duration")]
+    #[cfg_attr(feature = "serde", serde(default = "default_slew_minimum_duration"))]
     pub slew_minimum_duration: f64,
 
     /// Absolute maximum frequency correction (s/s)
-    #[serde(default = "default_maximum_frequency_steer")]
+    #[cfg_attr(feature = "serde", serde(default = "default_maximum_frequency_steer"))]
     pub maximum_frequency_steer: f64,
 
     /// Ignore a servers advertised dispersion when synchronizing.
     /// Can improve synchronization quality with servers reporting
     /// overly conservative root dispersion.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub ignore_server_dispersion: bool,
 
+    /// How the sources that survived selection are combined
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub combine_mode: CombineMode,
+
     /// Threshold for detecting external clock meddling
-    #[serde(default = "default_meddling_threshold")]
+    #[cfg_attr(feature = "serde", serde(default = "default_meddling_threshold"))]
     pub meddling_threshold: NtpDuration,
+
+    /// Probability threshold below which a measurement is considered
+    /// inconsistent with the filter state. (probability, 0-1)
+    #[cfg_attr(feature = "serde", serde(default = "default_divergence_probability"))]
+    pub divergence_probability: f64,
+    /// Number of consecutive inconsistent measurements after which the
+    /// filter state of a source is reinitialized, 0 disables this. (count, 0+)
+    #[cfg_attr(feature = "serde", serde(default = "default_divergence_count"))]
+    pub divergence_count: usize,
+
+    /// Threshold for the cumulative sum of measurement errors in one
+    /// direction above which a sudden change in frequency is assumed, 0
+    /// disables this. (standard deviations, 0+)
+    #[cfg_attr(feature = "serde", serde(default = "default_frequency_step_threshold"))]
+    pub frequency_step_threshold: f64,
+    /// Measurement error that is tolerated for every measurement before
+    /// it counts towards the frequency step threshold. (standard deviations, 0+)
+    #[cfg_attr(feature = "serde", serde(default = "default_frequency_step_slack"))]
+    pub frequency_step_slack: f64,
+    /// Frequency uncertainty added to the filter state when a sudden change
+    /// in frequency is detected. (s/s)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_frequency_step_uncertainty")
+    )]
+    pub frequency_step_uncertainty: f64,
+
+    /// Number of clock updates over which the model of frequency against
+    /// temperature is averaged. (count, 1+)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_temperature_model_samples")
+    )]
+    pub temperature_model_samples: usize,
+    /// Minimum standard deviation of the temperature samples before the
+    /// model is used to compensate temperature changes. (degrees Celsius)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_temperature_minimum_spread")
+    )]
+    pub temperature_minimum_spread: f64,
+    /// Largest change in frequency per degree that temperature compensation
+    /// applies. (s/s per degree Celsius)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_temperature_maximum_coefficient")
+    )]
+    pub temperature_maximum_coefficient: f64,
 }
 
 impl Default for AlgorithmConfig {
@@ -122,15 +247,20 @@ impl Default for AlgorithmConfig {
             poll_interval_high_weight: default_poll_interval_high_weight(),
             poll_interval_hysteresis: default_poll_interval_hysteresis(),
             poll_interval_step_threshold: default_poll_interval_step_threshold(),
+            poll_interval_wander_factor: default_poll_interval_wander_factor(),
 
             delay_outlier_threshold: default_delay_outlier_threshold(),
+            outlier_window: 0,
+            outlier_threshold: default_outlier_threshold(),
 
             initial_wander: default_initial_wander(),
             initial_frequency_uncertainty: default_initial_frequency_uncertainty(),
 
             maximum_source_uncertainty: default_maximum_source_uncertainty(),
+            maximum_root_distance: default_maximum_root_distance(),
             range_statistical_weight: default_range_statistical_weight(),
             range_delay_weight: default_range_delay_weight(),
+            cluster_minimum_sources: 0,
 
             steer_offset_threshold: default_steer_offset_threshold(),
             steer_offset_leftover: default_steer_offset_leftover(),
@@ -144,7 +274,20 @@ impl Default for AlgorithmConfig {
 
             ignore_server_dispersion: false,
 
+            combine_mode: CombineMode::default(),
+
             meddling_threshold: default_meddling_threshold(),
+
+            divergence_probability: default_divergence_probability(),
+            divergence_count: default_divergence_count(),
+
+            frequency_step_threshold: default_frequency_step_threshold(),
+            frequency_step_slack: default_frequency_step_slack(),
+            frequency_step_uncertainty: default_frequency_step_uncertainty(),
+
+            temperature_model_samples: default_temperature_model_samples(),
+            temperature_minimum_spread: default_temperature_minimum_spread(),
+            temperature_maximum_coefficient: default_temperature_maximum_coefficient(),
         }
     }
 }
@@ -181,10 +324,18 @@ fn default_poll_interval_step_threshold() -> f64 {
     1e-6
 }
 
+fn default_poll_interval_wander_factor() -> f64 {
+    8.
+}
+
 fn default_delay_outlier_threshold() -> f64 {
     5.
 }
 
+fn default_outlier_threshold() -> f64 {
+    10.
+}
+
 fn default_initial_wander() -> f64 {
     1e-8
 }
@@ -197,6 +348,10 @@ fn default_maximum_source_uncertainty() -> f64 {
     0.250
 }
 
+fn default_maximum_root_distance() -> f64 {
+    1.5
+}
+
 fn default_range_statistical_weight() -> f64 {
     2.
 }
@@ -240,3 +395,35 @@ fn default_slew_minimum_duration() -> f64 {
 fn default_meddling_threshold() -> NtpDuration {
     NtpDuration::from_seconds(5.)
 }
+
+fn default_divergence_probability() -> f64 {
+    1e-4
+}
+
+fn default_divergence_count() -> usize {
+    8
+}
+
+fn default_frequency_step_threshold() -> f64 {
+    12.0
+}
+
+fn default_frequency_step_slack() -> f64 {
+    1.0
+}
+
+fn default_frequency_step_uncertainty() -> f64 {
+    10e-6
+}
+
+fn default_temperature_model_samples() -> usize {
+    4096
+}
+
+fn default_temperature_minimum_spread() -> f64 {
+    1.0
+}
+
+fn default_temperature_maximum_coefficient() -> f64 {
+    1e-6
+}
@@ -1,5 +1,11 @@
-use std::{collections::HashMap, fmt::Debug, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    time::Duration,
+};
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 pub(crate) use source::AveragingBuffer;
 use source::OneWayKalmanSourceController;
 use tracing::{debug, error, info, warn};
@@ -10,21 +16,36 @@ use crate::{
     clock::NtpClock,
     config::{SourceConfig, SynchronizationConfig},
     packet::NtpLeapIndicator,
-    system::TimeSnapshot,
+    system::{SteeringWithheld, TimeSnapshot},
     time_types::{NtpDuration, NtpTimestamp},
 };
 
-use self::{combiner::combine, config::AlgorithmConfig, source::KalmanState};
+use self::{
+    combiner::combine, config::AlgorithmConfig, smear::LeapSmear, source::KalmanState,
+    temperature::TemperatureModel,
+};
 
-use super::{InternalStateUpdate, InternalTimeSyncController, ObservableSourceTimedata};
+use super::{
+    InternalStateUpdate, InternalTimeSyncController, ObservableKalmanState,
+    ObservableSourceTimedata, SavedControllerState, check_holdover, check_offset_steer,
+    clamp_frequency, holdover_remaining, holdover_variance_rate, step_allowed,
+};
 
 mod combiner;
 pub(super) mod config;
 mod matrix;
+mod outlier;
 mod select;
+mod smear;
 mod source;
+mod temperature;
 
-pub use source::{KalmanSourceController, TwoWayKalmanSourceController};
+pub use source::{KalmanSourceController, SavedKalmanSourceState, TwoWayKalmanSourceController};
+
+// Largest difference (in seconds per second) between the frequency of the
+// clock and the one in a saved state for the state to still apply. Anything
+// larger means something else changed the frequency in the meantime.
+const RESTORE_FREQUENCY_TOLERANCE: f64 = 1e-9;
 
 fn sqr(x: f64) -> f64 {
     x * x
@@ -47,6 +68,13 @@ struct SourceSnapshot {
     leap_indicator: NtpLeapIndicator,
 
     last_update: NtpTimestamp,
+
+    // Relative weight in the combined estimate
+    weight: f64,
+    // Whether this source alone should determine the estimate when selected
+    prefer: bool,
+    // Whether this source is assumed to be correct during selection
+    trust: bool,
 }
 
 impl SourceSnapshot {
@@ -58,7 +86,18 @@ impl SourceSnapshot {
         self.state.offset_variance().sqrt()
     }
 
-    fn observe(&self) -> ObservableSourceTimedata {
+    fn root_distance(&self) -> f64 {
+        super::root_distance(
+            self.source_delay.to_seconds(),
+            self.source_uncertainty.to_seconds(),
+            self.delay,
+            self.offset_uncertainty(),
+        )
+    }
+
+    /// Observe the source, `kalman` provides the parts of the filter state
+    /// that are not part of the snapshot
+    fn observe(&self, kalman: ObservableKalmanState) -> ObservableSourceTimedata {
         ObservableSourceTimedata {
             offset: NtpDuration::from_seconds(self.offset()),
             uncertainty: NtpDuration::from_seconds(self.offset_uncertainty()),
@@ -66,6 +105,21 @@ impl SourceSnapshot {
             remote_delay: self.source_delay,
             remote_uncertainty: self.source_uncertainty,
             last_update: self.last_update,
+            kalman: Some(ObservableKalmanState {
+                state: [self.state.state.ventry(0), self.state.state.ventry(1)],
+                covariance: [
+                    [
+                        self.state.uncertainty.entry(0, 0),
+                        self.state.uncertainty.entry(0, 1),
+                    ],
+                    [
+                        self.state.uncertainty.entry(1, 0),
+                        self.state.uncertainty.entry(1, 1),
+                    ],
+                ],
+                wander: self.wander,
+                ..kalman
+            }),
         }
     }
 }
@@ -86,16 +140,42 @@ pub struct KalmanSourceMessage {
     inner: SourceSnapshot,
 }
 
+/// The state of the clock controller, saved when the daemon shuts down
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SavedKalmanState {
+    // Frequency of the clock, including any slewing
+    clock_frequency: f64,
+    desired_freq: f64,
+    slew_end: Option<NtpTimestamp>,
+    smear_freq: f64,
+    in_startup: bool,
+    temperature_model: TemperatureModel,
+}
+
 #[derive(Debug, Clone)]
 pub struct KalmanClockController<C: NtpClock> {
     sources: HashMap<ClockId, (Option<SourceSnapshot>, bool)>,
+    // Sources of which at least one must survive selection to update the clock
+    required_sources: HashSet<ClockId>,
     clock: C,
     synchronization_config: SynchronizationConfig,
     algo_config: AlgorithmConfig,
     freq_offset: f64,
     timedata: TimeSnapshot,
     desired_freq: f64,
+    slew_end: Option<NtpTimestamp>,
+    leap_smear: Option<LeapSmear>,
+    smear_freq: f64,
     in_startup: bool,
+    // Number of clock steps since startup
+    steps: u32,
+    // Whether the configured maximum slew rate and frequency offset applied
+    // to the last slew and frequency change
+    slew_clamped: bool,
+    frequency_clamped: bool,
+    temperature: Option<f64>,
+    temperature_model: TemperatureModel,
 }
 
 impl<C: NtpClock> KalmanClockController<C> {
@@ -135,10 +215,41 @@ impl<C: NtpClock> KalmanClockController<C> {
             )
             .cloned()
             .collect();
-        let selection =
-            select::select(&self.synchronization_config, &self.algo_config, &candidates);
+        // Once synchronized, fewer agreeing sources may suffice to keep
+        // steering than were needed to start
+        let synchronized = !self.in_startup && self.timedata.steering_withheld.is_none();
+        let selection = select::select(
+            self.synchronization_config
+                .required_agreeing_sources(synchronized),
+            &self.algo_config,
+            &candidates,
+        )
+        .and_then(|selection| {
+            if self.required_sources.is_empty()
+                || selection
+                    .iter()
+                    .any(|snapshot| self.required_sources.contains(&snapshot.index))
+            {
+                Ok(selection)
+            } else {
+                Err(SteeringWithheld::NoRequiredSource)
+            }
+        });
+        let selection = match selection {
+            Ok(selection) => selection,
+            Err(reason) => {
+                info!(%reason, "No consensus on current time");
+                self.timedata.steering_withheld = Some(reason);
+                return InternalStateUpdate {
+                    time_snapshot: Some(self.timedata),
+                    ..InternalStateUpdate::default()
+                };
+            }
+        };
 
         if let Some(combined) = combine(&selection, &self.algo_config) {
+            self.timedata.steering_withheld = None;
+
             info!(
                 "Offset: {}+-{}ms, frequency: {}+-{}ppm",
                 combined.estimate.offset() * 1e3,
@@ -153,9 +264,14 @@ impl<C: NtpClock> KalmanClockController<C> {
                     .expect("Cannot update clock");
             }
 
-            let freq_delta = combined.estimate.frequency() - self.desired_freq;
+            // While smearing a leap second we steer towards the smeared time
+            // instead of the time of our sources.
+            let leap_indicator = self.update_leap_smear(combined.leap_indicator, time);
+            let smear_offset = self.leap_smear.map_or(0.0, |smear| smear.offset(time));
+
+            let freq_delta = combined.estimate.frequency() - self.desired_freq - self.smear_freq;
             let freq_uncertainty = combined.estimate.frequency_variance().sqrt();
-            let offset_delta = combined.estimate.offset();
+            let offset_delta = combined.estimate.offset() - smear_offset;
             let offset_uncertainty = combined.estimate.offset_variance().sqrt();
             let next_update = if self.desired_freq == 0.0
                 && offset_delta.abs() > offset_uncertainty * self.algo_config.steer_offset_threshold
@@ -188,11 +304,26 @@ impl<C: NtpClock> KalmanClockController<C> {
                 InternalStateUpdate::default()
             };
 
+            // The frequency correction we settled on, apart from slewing and
+            // smearing, is what the clock needs at its current temperature
+            if let Some(temperature) = self.temperature
+                && !self.in_startup
+            {
+                self.temperature_model.add_sample(
+                    &self.algo_config,
+                    temperature,
+                    self.freq_offset + self.desired_freq + self.smear_freq,
+                );
+            }
+            self.timedata.frequency_correction =
+                Some(self.freq_offset + self.desired_freq + self.smear_freq);
+
             self.timedata.root_delay = combined.delay;
             self.timedata.root_variance_base_time = time;
             self.timedata.root_variance_base = combined.estimate.uncertainty.entry(0, 0);
             self.timedata.root_variance_linear = combined.estimate.uncertainty.entry(0, 1);
-            self.timedata.root_variance_quadratic = combined.estimate.uncertainty.entry(1, 1);
+            self.timedata.root_variance_quadratic = combined.estimate.uncertainty.entry(1, 1)
+                + holdover_variance_rate(&self.synchronization_config);
             self.timedata.root_variance_cubic = selection
                 .iter()
                 .map(|v| v.wander)
@@ -207,10 +338,11 @@ impl<C: NtpClock> KalmanClockController<C> {
                 )
                 .expect("Cannot update clock");
 
-            if let Some(leap) = combined.leap_indicator {
+            if let Some(leap) = leap_indicator {
                 self.clock.status_update(leap).expect("Cannot update clock");
                 self.timedata.leap_indicator = leap;
             }
+            self.timedata.leap_smear = self.leap_smear.map(|smear| smear.status(time));
 
             // After a successful measurement we are out of startup.
             self.in_startup = false;
@@ -218,6 +350,7 @@ impl<C: NtpClock> KalmanClockController<C> {
             InternalStateUpdate {
                 used_sources: Some(combined.sources),
                 time_snapshot: Some(self.timedata),
+                next_update: self.next_update(),
                 ..next_update
             }
         } else {
@@ -229,55 +362,30 @@ impl<C: NtpClock> KalmanClockController<C> {
         }
     }
 
-    fn check_offset_steer(&mut self, change: f64) {
-        let change = NtpDuration::from_seconds(change);
-        if self.in_startup {
-            if !self
-                .synchronization_config
-                .startup_step_panic_threshold
-                .is_within(change)
-            {
-                error!(
-                    "Unusually large clock step suggested, please manually verify system clock and reference clock state and restart if appropriate. If the clock is significantly wrong, you can use `ntp-ctl force-sync` to correct it."
-                );
-                #[cfg(not(test))]
-                std::process::exit(crate::exitcode::SOFTWARE);
-                #[cfg(test)]
-                panic!("Threshold exceeded");
-            }
-        } else {
-            self.timedata.accumulated_steps += change.abs();
-            if !self
-                .synchronization_config
-                .single_step_panic_threshold
-                .is_within(change)
-                || self
-                    .synchronization_config
-                    .accumulated_step_panic_threshold
-                    .is_some_and(|v| self.timedata.accumulated_steps > v)
-            {
-                error!(
-                    "Unusually large clock step suggested, please manually verify system clock and reference clock state and restart if appropriate. If the clock is significantly wrong, you can use `ntp-ctl force-sync` to correct it."
-                );
-                #[cfg(not(test))]
-                std::process::exit(crate::exitcode::SOFTWARE);
-                #[cfg(test)]
-                panic!("Threshold exceeded");
-            }
-        }
-    }
-
     fn steer_offset(
         &mut self,
         change: f64,
         freq_delta: f64,
     ) -> InternalStateUpdate<KalmanControllerMessage> {
-        if change.abs() > self.algo_config.step_threshold {
+        if change.abs() > self.algo_config.step_threshold
+            && step_allowed(
+                &self.synchronization_config,
+                self.in_startup,
+                &mut self.steps,
+                change,
+            )
+        {
             // jump
-            self.check_offset_steer(change);
+            check_offset_steer(
+                &self.synchronization_config,
+                &mut self.timedata,
+                self.in_startup,
+                change,
+            );
             self.clock
                 .step_clock(NtpDuration::from_seconds(change))
                 .expect("Cannot adjust clock");
+            self.timedata.monotonic_offset += NtpDuration::from_seconds(change);
             for (state, _) in self.sources.values_mut() {
                 if let Some(state) = state {
                     state.state = state.state.process_offset_steering(change, state.period);
@@ -299,10 +407,14 @@ impl<C: NtpClock> KalmanClockController<C> {
             }
         } else {
             // start slew
-            let freq = self
-                .algo_config
-                .slew_maximum_frequency_offset
-                .min(change.abs() / self.algo_config.slew_minimum_duration);
+            let freq = clamp_frequency(
+                self.algo_config
+                    .slew_maximum_frequency_offset
+                    .min(change.abs() / self.algo_config.slew_minimum_duration),
+                self.synchronization_config.maximum_slew_rate,
+                &mut self.slew_clamped,
+                "Slew rate",
+            );
             let duration = Duration::from_secs_f64(change.abs() / freq);
             debug!(
                 "Slewing by {}ms over {}s",
@@ -310,10 +422,11 @@ impl<C: NtpClock> KalmanClockController<C> {
                 duration.as_secs_f64(),
             );
             let update = self.change_desired_frequency(-freq * change.signum(), freq_delta);
-            InternalStateUpdate {
-                next_update: Some(duration),
-                ..update
-            }
+            self.slew_end = Some(
+                self.clock.now().expect("Cannot read clock")
+                    + NtpDuration::from_system_duration(duration),
+            );
+            update
         }
     }
 
@@ -322,15 +435,109 @@ impl<C: NtpClock> KalmanClockController<C> {
         new_freq: f64,
         freq_delta: f64,
     ) -> InternalStateUpdate<KalmanControllerMessage> {
-        let change = self.desired_freq - new_freq + freq_delta;
+        self.retarget_frequency(new_freq, self.smear_freq, freq_delta)
+    }
+
+    fn retarget_frequency(
+        &mut self,
+        new_freq: f64,
+        new_smear_freq: f64,
+        freq_delta: f64,
+    ) -> InternalStateUpdate<KalmanControllerMessage> {
+        let change =
+            (self.desired_freq + self.smear_freq) - (new_freq + new_smear_freq) + freq_delta;
         self.desired_freq = new_freq;
+        self.smear_freq = new_smear_freq;
         self.steer_frequency(change)
     }
 
+    /// Keep track of the leap second to smear, if smearing is enabled.
+    /// Returns the leap indicator to pass on to the kernel and our clients.
+    fn update_leap_smear(
+        &mut self,
+        leap: Option<NtpLeapIndicator>,
+        time: NtpTimestamp,
+    ) -> Option<NtpLeapIndicator> {
+        let Some(config) = self.synchronization_config.leap_smear else {
+            return leap;
+        };
+
+        match leap {
+            Some(leap @ (NtpLeapIndicator::Leap61 | NtpLeapIndicator::Leap59)) => {
+                if self.leap_smear.is_none() {
+                    info!(?leap, "Leap second announced, smearing it");
+                    // If we only just started, we may have been restarted
+                    // halfway through the smear.
+                    self.leap_smear = Some(LeapSmear::new(&config, leap, time, self.in_startup));
+                }
+                // Neither the kernel nor our clients should apply the leap
+                // second on top of the smear.
+                Some(NtpLeapIndicator::NoWarning)
+            }
+            Some(NtpLeapIndicator::NoWarning) => {
+                // Once started, we finish the smear even when the sources stop
+                // announcing the leap second, as they do after it has passed.
+                if self
+                    .leap_smear
+                    .is_some_and(|smear| !smear.has_started(time))
+                {
+                    info!("Leap second no longer announced, cancelling smear");
+                    self.leap_smear = None;
+                }
+                leap
+            }
+            _ => leap,
+        }
+    }
+
+    /// Handle the sources going through the leap second while smearing. Our
+    /// clock isn't stepped, but the offsets reported by the sources jump.
+    fn pass_leap_second(&mut self, jump: f64) -> InternalStateUpdate<KalmanControllerMessage> {
+        info!("Sources passed the smeared leap second");
+        let steer = -jump;
+        for (state, _) in self.sources.values_mut() {
+            if let Some(state) = state {
+                state.state = state.state.process_offset_steering(steer, state.period);
+            }
+        }
+        InternalStateUpdate {
+            source_message: Some(KalmanControllerMessage {
+                inner: KalmanControllerMessageInner::Step { steer },
+            }),
+            ..InternalStateUpdate::default()
+        }
+    }
+
+    /// Time until the next non-measurement update is needed, for ending a
+    /// slew, progressing the leap smear or ending the holdover.
+    fn next_update(&self) -> Option<Duration> {
+        let now = self.clock.now().expect("Cannot read clock");
+        let slew = self
+            .slew_end
+            .map(|end| Duration::from_secs_f64((end - now).to_seconds().max(0.0)));
+        let smear = self.leap_smear.map(|smear| {
+            if smear.has_started(now) && self.smear_freq == 0.0 {
+                // Start smearing right away
+                Duration::ZERO
+            } else {
+                smear.next_update(now)
+            }
+        });
+
+        let holdover = holdover_remaining(&self.synchronization_config, &self.timedata, now);
+
+        [slew, smear, holdover].into_iter().flatten().min()
+    }
+
     fn steer_frequency(&mut self, change: f64) -> InternalStateUpdate<KalmanControllerMessage> {
-        let new_freq_offset = ((1.0 + self.freq_offset) * (1.0 + change) - 1.0).clamp(
-            -self.algo_config.maximum_frequency_steer,
-            self.algo_config.maximum_frequency_steer,
+        let new_freq_offset = clamp_frequency(
+            ((1.0 + self.freq_offset) * (1.0 + change) - 1.0).clamp(
+                -self.algo_config.maximum_frequency_steer,
+                self.algo_config.maximum_frequency_steer,
+            ),
+            self.synchronization_config.maximum_frequency_offset,
+            &mut self.frequency_clamped,
+            "Frequency correction",
         );
         let actual_change = (1.0 + new_freq_offset) / (1.0 + self.freq_offset) - 1.0;
         self.freq_offset = new_freq_offset;
@@ -383,17 +590,27 @@ impl<C: NtpClock> InternalTimeSyncController for KalmanClockController<C> {
 
         Ok(KalmanClockController {
             sources: HashMap::new(),
+            required_sources: HashSet::new(),
             clock,
             synchronization_config,
             algo_config,
             freq_offset,
             desired_freq: 0.0,
+            slew_end: None,
+            leap_smear: None,
+            smear_freq: 0.0,
             timedata: TimeSnapshot {
                 accumulated_steps_threshold: synchronization_config
                     .accumulated_step_panic_threshold,
+                monotonic_epoch: rand::random(),
                 ..TimeSnapshot::default()
             },
             in_startup: true,
+            steps: 0,
+            slew_clamped: false,
+            frequency_clamped: false,
+            temperature: None,
+            temperature_model: TemperatureModel::default(),
         })
     }
 
@@ -409,9 +626,12 @@ impl<C: NtpClock> InternalTimeSyncController for KalmanClockController<C> {
         source_config: SourceConfig,
     ) -> Self::NtpSourceController {
         self.sources.insert(id, (None, false));
+        if source_config.require {
+            self.required_sources.insert(id);
+        }
         KalmanSourceController::new(
             id,
-            self.algo_config,
+            &self.algo_config,
             None,
             source_config,
             AveragingBuffer::default(),
@@ -427,9 +647,12 @@ impl<C: NtpClock> InternalTimeSyncController for KalmanClockController<C> {
         period: Option<f64>,
     ) -> Self::OneWaySourceController {
         self.sources.insert(id, (None, false));
+        if source_config.require {
+            self.required_sources.insert(id);
+        }
         KalmanSourceController::new(
             id,
-            self.algo_config,
+            &self.algo_config,
             period,
             source_config,
             FixedMeasurementNoise {
@@ -441,6 +664,7 @@ impl<C: NtpClock> InternalTimeSyncController for KalmanClockController<C> {
 
     fn remove_source(&mut self, id: ClockId) {
         self.sources.remove(&id);
+        self.required_sources.remove(&id);
     }
 
     fn source_update(&mut self, id: ClockId, usable: bool) {
@@ -449,8 +673,112 @@ impl<C: NtpClock> InternalTimeSyncController for KalmanClockController<C> {
         }
     }
     fn time_update(&mut self) -> InternalStateUpdate<Self::ControllerMessage> {
-        // End slew
-        self.change_desired_frequency(0.0, 0.0)
+        let now = self.clock.now().expect("Cannot read clock");
+        check_holdover(
+            &self.synchronization_config,
+            &self.clock,
+            &mut self.timedata,
+            now,
+        );
+
+        let mut update = if let Some(jump) = self
+            .leap_smear
+            .as_mut()
+            .filter(|smear| smear.leap_due(now))
+            .map(LeapSmear::pass_leap)
+        {
+            // Frequency changes are picked up on the next update
+            self.pass_leap_second(jump)
+        } else {
+            if self.slew_end.is_some_and(|end| !now.is_before(end)) {
+                // End slew
+                self.slew_end = None;
+            }
+            if self.leap_smear.is_some_and(|smear| smear.is_finished(now)) {
+                info!("Finished smearing leap second");
+                self.leap_smear = None;
+            }
+
+            let desired_freq = if self.slew_end.is_some() {
+                self.desired_freq
+            } else {
+                0.0
+            };
+            let smear_freq = self.leap_smear.map_or(0.0, |smear| smear.frequency(now));
+            if desired_freq == self.desired_freq && smear_freq == self.smear_freq {
+                InternalStateUpdate::default()
+            } else {
+                self.retarget_frequency(desired_freq, smear_freq, 0.0)
+            }
+        };
+
+        self.timedata.leap_smear = self.leap_smear.map(|smear| smear.status(now));
+        update.time_snapshot = Some(self.timedata);
+        update.next_update = self.next_update();
+        update
+    }
+
+    fn save_state(&self) -> Option<SavedControllerState> {
+        Some(SavedControllerState::Kalman(SavedKalmanState {
+            clock_frequency: self.freq_offset,
+            desired_freq: self.desired_freq,
+            slew_end: self.slew_end,
+            smear_freq: self.smear_freq,
+            in_startup: self.in_startup,
+            temperature_model: self.temperature_model,
+        }))
+    }
+
+    fn restore_state(&mut self, state: &SavedControllerState) -> bool {
+        let SavedControllerState::Kalman(state) = state;
+        if (self.freq_offset - state.clock_frequency).abs() > RESTORE_FREQUENCY_TOLERANCE {
+            warn!(
+                "Clock frequency changed from {}ppm to {}ppm since the state was saved, not restoring it",
+                state.clock_frequency * 1e6,
+                self.freq_offset * 1e6,
+            );
+            return false;
+        }
+
+        // The clock kept running at the frequency of any ongoing slew, so
+        // that continues where it left off
+        self.desired_freq = state.desired_freq;
+        self.slew_end = state.slew_end;
+        self.smear_freq = state.smear_freq;
+        self.in_startup = state.in_startup;
+        self.temperature_model = state.temperature_model;
+        self.timedata.frequency_correction =
+            Some(self.freq_offset + self.desired_freq + self.smear_freq);
+        true
+    }
+
+    fn clock_discontinuity(&mut self) {
+        for (snapshot, _) in self.sources.values_mut() {
+            *snapshot = None;
+        }
+        // Whatever the jump did to the clock is corrected as at startup
+        self.in_startup = true;
+    }
+
+    fn temperature_update(
+        &mut self,
+        temperature: f64,
+    ) -> InternalStateUpdate<Self::ControllerMessage> {
+        let previous = self.temperature.replace(temperature);
+        match (
+            previous,
+            self.temperature_model.coefficient(&self.algo_config),
+        ) {
+            (Some(previous), Some(coefficient)) if !self.in_startup && previous != temperature => {
+                debug!(
+                    "Compensating temperature change of {} degrees at {}ppm per degree",
+                    temperature - previous,
+                    coefficient * 1e6,
+                );
+                self.steer_frequency(coefficient * (temperature - previous))
+            }
+            _ => InternalStateUpdate::default(),
+        }
     }
 
     fn source_message(
@@ -476,7 +804,7 @@ mod tests {
     use matrix::{Matrix, Vector};
 
     use crate::algorithm::{InternalMeasurement, InternalSourceController};
-    use crate::config::StepThreshold;
+    use crate::config::{HoldoverConfig, LeapSmearConfig, StepThreshold};
 
     use super::*;
 
@@ -578,6 +906,71 @@ mod tests {
         assert_eq!(algo.timedata.leap_indicator, NtpLeapIndicator::NoWarning);
         assert_ne!(algo.timedata.root_delay, NtpDuration::ZERO);
         assert_ne!(algo.timedata.root_variance_base, 0.0);
+
+        // After a jump of the clock everything starts over
+        algo.clock_discontinuity();
+        source.clock_discontinuity();
+        assert!(algo.in_startup);
+        assert!(algo.sources[&ClockId(0)].0.is_none());
+        assert_eq!(source.observe().uncertainty, NtpDuration::MAX);
+    }
+
+    #[test]
+    fn test_maintain_agreeing_sources() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            maintain_agreeing_sources: Some(1),
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let mut source = algo.add_source(ClockId(0), SourceConfig::default());
+        algo.source_update(ClockId(0), true);
+
+        let mut measure = |algo: &mut KalmanClockController<TestClock>| {
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            let message = source.handle_measurement(InternalMeasurement {
+                delay: NtpDuration::from_seconds(0.001),
+                offset: NtpDuration::from_seconds(0.001),
+                localtime: algo.clock.current_time,
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            });
+            if let Some(message) = message {
+                let actions = algo.source_message(ClockId(0), message);
+                if let Some(source_message) = actions.source_message {
+                    source.handle_message(source_message);
+                }
+            }
+            algo.timedata.steering_withheld
+        };
+
+        // A single source is not enough to start steering
+        let withheld = SteeringWithheld::NotEnoughAgreeing {
+            agreeing: 1,
+            required: 2,
+        };
+        assert!((0..100).any(|_| measure(&mut algo) == Some(withheld)));
+        assert!(algo.in_startup);
+
+        // but it is enough to keep going once synchronized
+        algo.in_startup = false;
+        algo.timedata.steering_withheld = None;
+        assert_eq!(measure(&mut algo), None);
+
+        // After a jump of the clock, all sources are needed again
+        algo.clock_discontinuity();
+        assert!((0..100).any(|_| measure(&mut algo) == Some(withheld)));
     }
 
     #[test]
@@ -637,6 +1030,126 @@ mod tests {
         algo.steer_offset(-1000.0, 0.0);
     }
 
+    fn step_limit_controller(
+        step_limit: u32,
+        maximum_slew_offset: Option<f64>,
+    ) -> KalmanClockController<TestClock> {
+        let synchronization_config = SynchronizationConfig {
+            single_step_panic_threshold: StepThreshold {
+                forward: None,
+                backward: None,
+            },
+            step_limit: Some(step_limit),
+            maximum_slew_offset: maximum_slew_offset.map(NtpDuration::from_seconds),
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.in_startup = false;
+        algo
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut algo = step_limit_controller(1, None);
+
+        algo.steer_offset(10.0, 0.0);
+        assert_eq!(
+            algo.timedata.accumulated_steps,
+            NtpDuration::from_seconds(10.0)
+        );
+        assert!(algo.slew_end.is_none());
+
+        // Once the steps are used up, offsets are slewed
+        algo.steer_offset(10.0, 0.0);
+        assert_eq!(
+            algo.timedata.accumulated_steps,
+            NtpDuration::from_seconds(10.0)
+        );
+        assert!(algo.slew_end.is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_maximum_slew_offset() {
+        let mut algo = step_limit_controller(0, Some(1.0));
+        algo.steer_offset(0.5, 0.0);
+        algo.steer_offset(10.0, 0.0);
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let new_controller = || {
+            KalmanClockController::new(
+                TestClock {
+                    has_steered: RefCell::new(false),
+                    current_time: NtpTimestamp::from_fixed_int(0),
+                },
+                SynchronizationConfig::default(),
+                AlgorithmConfig::default(),
+            )
+            .unwrap()
+        };
+
+        let mut algo = new_controller();
+        algo.in_startup = false;
+        algo.steer_offset(0.005, 0.0);
+        let state = algo.save_state().unwrap();
+
+        // Slewing changed the frequency of the clock, which a new controller
+        // doesn't know about
+        let mut restored = new_controller();
+        assert!(!restored.restore_state(&state));
+        assert!(restored.in_startup);
+
+        restored.freq_offset = algo.freq_offset;
+        assert!(restored.restore_state(&state));
+        assert!(!restored.in_startup);
+        assert_eq!(restored.desired_freq, algo.desired_freq);
+        assert_eq!(restored.slew_end, algo.slew_end);
+        // Without the slew the clock needs no correction
+        assert!(restored.timedata.frequency_correction.unwrap().abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let synchronization_config = SynchronizationConfig {
+            maximum_slew_rate: Some(100.0),
+            maximum_frequency_offset: Some(50.0),
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        // Slewing 5ms would normally go at 200ppm
+        algo.steer_offset(0.005, 0.0);
+        assert!((algo.desired_freq + 100e-6).abs() < 1e-12);
+        assert!(algo.slew_clamped);
+        let slew_duration = (algo.slew_end.unwrap() - algo.clock.current_time).to_seconds();
+        assert!((slew_duration - 50.0).abs() < 1e-6);
+
+        // Frequency corrections are bounded as a whole
+        assert!((algo.freq_offset - 50e-6).abs() < 1e-12);
+        assert!(algo.frequency_clamped);
+        algo.steer_frequency(-20e-6);
+        assert!((algo.freq_offset - 30e-6).abs() < 1e-9);
+        assert!(!algo.frequency_clamped);
+    }
+
     #[test]
     fn test_jumps_update_state() {
         let synchronization_config = SynchronizationConfig::default();
@@ -668,6 +1181,9 @@ mod tests {
                     source_delay: NtpDuration::ZERO,
                     leap_indicator: NtpLeapIndicator::NoWarning,
                     last_update: NtpTimestamp::from_fixed_int(0),
+                    weight: 1.0,
+                    prefer: false,
+                    trust: false,
                 }),
                 true,
             ),
@@ -690,6 +1206,9 @@ mod tests {
                     source_delay: NtpDuration::ZERO,
                     leap_indicator: NtpLeapIndicator::NoWarning,
                     last_update: NtpTimestamp::from_fixed_int(0),
+                    weight: 1.0,
+                    prefer: false,
+                    trust: false,
                 }),
                 true,
             ),
@@ -753,6 +1272,9 @@ mod tests {
                     source_delay: NtpDuration::ZERO,
                     leap_indicator: NtpLeapIndicator::NoWarning,
                     last_update: NtpTimestamp::from_fixed_int(0),
+                    weight: 1.0,
+                    prefer: false,
+                    trust: false,
                 }),
                 true,
             ),
@@ -875,4 +1397,349 @@ mod tests {
             }
         }
     }
+
+    fn leap_smear_snapshot(
+        leap_indicator: NtpLeapIndicator,
+        offset: f64,
+        frequency: f64,
+        time: NtpTimestamp,
+    ) -> SourceSnapshot {
+        SourceSnapshot {
+            index: ClockId(0),
+            state: KalmanState {
+                state: Vector::new_vector([offset, frequency]),
+                uncertainty: Matrix::new([[1e-18, 0.0], [0.0, 1e-18]]),
+                time,
+            },
+            wander: 0.0,
+            delay: 0.0,
+            period: None,
+            source_uncertainty: NtpDuration::ZERO,
+            source_delay: NtpDuration::ZERO,
+            leap_indicator,
+            last_update: time,
+            weight: 1.0,
+            prefer: false,
+            trust: false,
+        }
+    }
+
+    // Seconds since 2016-12-31T00:00:00Z, the day of the last leap second
+    fn leap_day(seconds: f64) -> NtpTimestamp {
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(3_692_131_200, 0)
+            + NtpDuration::from_seconds(seconds)
+    }
+
+    fn leap_smear_controller(time: NtpTimestamp) -> KalmanClockController<TestClock> {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            leap_smear: Some(LeapSmearConfig::default()),
+            ..SynchronizationConfig::default()
+        };
+        KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: time,
+            },
+            synchronization_config,
+            AlgorithmConfig::default(),
+        )
+        .unwrap()
+    }
+
+    fn check_leap_smear(leap: NtpLeapIndicator, direction: f64) {
+        const DAY: f64 = 86400.0;
+
+        let mut algo = leap_smear_controller(leap_day(3600.0));
+        algo.in_startup = false;
+
+        // The leap second is announced, but only smeared from noon onwards
+        algo.sources.insert(
+            ClockId(0),
+            (
+                Some(leap_smear_snapshot(leap, 0.0, 0.0, leap_day(3600.0))),
+                true,
+            ),
+        );
+        let update = algo.update_clock(leap_day(3600.0));
+        let snapshot = update.time_snapshot.unwrap();
+        assert_eq!(snapshot.leap_indicator, NtpLeapIndicator::NoWarning);
+        assert_eq!(snapshot.leap_smear.unwrap().progress, 0.0);
+        let until_start = update.next_update.unwrap().as_secs_f64();
+        assert!((until_start - 11.0 * 3600.0).abs() < 1e-3);
+        assert_eq!(algo.smear_freq, 0.0);
+
+        // Once started, the clock runs at the smear frequency
+        algo.clock.current_time = leap_day(0.75 * DAY);
+        let update = algo.time_update();
+        assert!((algo.smear_freq - direction / DAY).abs() < 1e-12);
+        assert!(matches!(
+            update.source_message,
+            Some(KalmanControllerMessage {
+                inner: KalmanControllerMessageInner::FreqChange { .. }
+            })
+        ));
+        let status = update.time_snapshot.unwrap().leap_smear.unwrap();
+        assert_eq!(status.leap, leap);
+        assert!((status.progress - 0.25).abs() < 1e-9);
+        assert!((status.offset.to_seconds() - 0.25 * direction).abs() < 1e-6);
+
+        // Sources following the smear are not steered towards
+        algo.sources.insert(
+            ClockId(0),
+            (
+                Some(leap_smear_snapshot(
+                    leap,
+                    0.25 * direction,
+                    algo.smear_freq,
+                    leap_day(0.75 * DAY),
+                )),
+                true,
+            ),
+        );
+        let update = algo.update_clock(leap_day(0.75 * DAY));
+        assert!(update.source_message.is_none());
+        assert_eq!(
+            update.time_snapshot.unwrap().leap_indicator,
+            NtpLeapIndicator::NoWarning
+        );
+
+        // The sources go through the leap second before our clock reaches
+        // midnight, their offset jumps but our clock is left alone
+        algo.clock.current_time = leap_day(DAY - 0.5 * direction + 0.1);
+        let update = algo.time_update();
+        match update.source_message {
+            Some(KalmanControllerMessage {
+                inner: KalmanControllerMessageInner::Step { steer },
+            }) => assert_eq!(steer, direction),
+            other => panic!("Unexpected message {other:?}"),
+        }
+        let offset = algo.sources[&ClockId(0)].0.unwrap().state.offset();
+        assert!((offset + 0.75 * direction).abs() < 1e-9);
+
+        // After the window, we are back to normal
+        algo.clock.current_time = leap_day(1.5 * DAY);
+        let update = algo.time_update();
+        assert!(algo.leap_smear.is_none());
+        assert_eq!(algo.smear_freq, 0.0);
+        assert!(update.time_snapshot.unwrap().leap_smear.is_none());
+        assert_eq!(update.next_update, None);
+    }
+
+    #[test]
+    fn test_leap_smear_insert() {
+        check_leap_smear(NtpLeapIndicator::Leap61, 1.0);
+    }
+
+    #[test]
+    fn test_leap_smear_delete() {
+        check_leap_smear(NtpLeapIndicator::Leap59, -1.0);
+    }
+
+    #[test]
+    fn test_holdover() {
+        let start = leap_day(0.0);
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            holdover: Some(HoldoverConfig {
+                duration: 600.0,
+                dispersion_rate: 1e-4,
+            }),
+            ..SynchronizationConfig::default()
+        };
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: start,
+            },
+            synchronization_config,
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.in_startup = false;
+
+        algo.sources.insert(
+            ClockId(0),
+            (
+                Some(leap_smear_snapshot(
+                    NtpLeapIndicator::NoWarning,
+                    0.0,
+                    0.0,
+                    start,
+                )),
+                true,
+            ),
+        );
+        let update = algo.update_clock(start);
+        let snapshot = update.time_snapshot.unwrap();
+        assert_eq!(snapshot.leap_indicator, NtpLeapIndicator::NoWarning);
+        let until_end = update.next_update.unwrap().as_secs_f64();
+        assert!((until_end - 600.0).abs() < 1e-3);
+
+        // The root dispersion grows at least at the configured rate
+        let dispersion = snapshot.root_dispersion(start + NtpDuration::from_seconds(100.0));
+        assert!(dispersion.to_seconds() >= 0.99e-2);
+
+        // Still within the holdover
+        algo.clock.current_time = start + NtpDuration::from_seconds(300.0);
+        let update = algo.time_update();
+        assert_eq!(
+            update.time_snapshot.unwrap().leap_indicator,
+            NtpLeapIndicator::NoWarning
+        );
+        let until_end = update.next_update.unwrap().as_secs_f64();
+        assert!((until_end - 300.0).abs() < 1e-3);
+
+        // Without further updates, the clock is no longer synchronized
+        algo.clock.current_time = start + NtpDuration::from_seconds(600.0);
+        let update = algo.time_update();
+        assert_eq!(
+            update.time_snapshot.unwrap().leap_indicator,
+            NtpLeapIndicator::Unknown
+        );
+        assert_eq!(update.next_update, None);
+    }
+
+    #[test]
+    fn test_leap_smear_resumes_after_restart() {
+        const DAY: f64 = 86400.0;
+
+        // Started halfway through the smear, the clock is already a quarter
+        // second behind our sources
+        let mut algo = leap_smear_controller(leap_day(0.75 * DAY));
+        algo.sources.insert(
+            ClockId(0),
+            (
+                Some(leap_smear_snapshot(
+                    NtpLeapIndicator::Leap61,
+                    0.25,
+                    0.0,
+                    leap_day(0.75 * DAY),
+                )),
+                true,
+            ),
+        );
+        let update = algo.update_clock(leap_day(0.75 * DAY));
+
+        assert!(!*algo.clock.has_steered.borrow());
+        assert!(update.source_message.is_none());
+        assert_eq!(update.next_update, Some(Duration::ZERO));
+        let status = update.time_snapshot.unwrap().leap_smear.unwrap();
+        assert!((status.progress - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_leap_smear_cancelled() {
+        let mut algo = leap_smear_controller(leap_day(3600.0));
+        algo.in_startup = false;
+
+        algo.sources.insert(
+            ClockId(0),
+            (
+                Some(leap_smear_snapshot(
+                    NtpLeapIndicator::Leap61,
+                    0.0,
+                    0.0,
+                    leap_day(3600.0),
+                )),
+                true,
+            ),
+        );
+        algo.update_clock(leap_day(3600.0));
+        assert!(algo.leap_smear.is_some());
+
+        // The announcement is withdrawn before the smear started
+        algo.sources.insert(
+            ClockId(0),
+            (
+                Some(leap_smear_snapshot(
+                    NtpLeapIndicator::NoWarning,
+                    0.0,
+                    0.0,
+                    leap_day(7200.0),
+                )),
+                true,
+            ),
+        );
+        let update = algo.update_clock(leap_day(7200.0));
+        assert!(algo.leap_smear.is_none());
+        assert!(update.time_snapshot.unwrap().leap_smear.is_none());
+        assert_eq!(update.next_update, None);
+    }
+
+    #[test]
+    fn test_temperature_compensation() {
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        // Nothing is known about the temperature dependence yet
+        algo.in_startup = false;
+        algo.temperature_update(20.0);
+        let update = algo.temperature_update(25.0);
+        assert!(update.source_message.is_none());
+        assert_eq!(algo.freq_offset, 0.0);
+
+        // The clock needed an extra ppm for every degree
+        for i in 0..100 {
+            let temperature = 20.0 + f64::from(i % 10);
+            algo.temperature_model
+                .add_sample(&algo.algo_config, temperature, 1e-6 * temperature);
+        }
+
+        let update = algo.temperature_update(27.0);
+        assert!(matches!(
+            update.source_message,
+            Some(KalmanControllerMessage {
+                inner: KalmanControllerMessageInner::FreqChange { .. }
+            })
+        ));
+        assert!((algo.freq_offset - 2e-6).abs() < 1e-12);
+        assert!(*algo.clock.has_steered.borrow());
+
+        // An unchanged temperature needs no correction
+        let update = algo.temperature_update(27.0);
+        assert!(update.source_message.is_none());
+    }
+
+    #[test]
+    fn test_required_source() {
+        let time = NtpTimestamp::from_fixed_int(0);
+        let mut algo = leap_smear_controller(time);
+        algo.add_source(
+            ClockId(0),
+            SourceConfig {
+                require: true,
+                ..SourceConfig::default()
+            },
+        );
+        algo.add_source(ClockId(1), SourceConfig::default());
+        algo.source_update(ClockId(1), true);
+        algo.sources.get_mut(&ClockId(1)).unwrap().0 = Some(SourceSnapshot {
+            index: ClockId(1),
+            ..leap_smear_snapshot(NtpLeapIndicator::NoWarning, 0.0, 0.0, time)
+        });
+
+        // No updates without the required source
+        assert!(algo.update_clock(time).used_sources.is_none());
+
+        algo.source_update(ClockId(0), true);
+        algo.sources.get_mut(&ClockId(0)).unwrap().0 = Some(leap_smear_snapshot(
+            NtpLeapIndicator::NoWarning,
+            0.0,
+            0.0,
+            time,
+        ));
+        assert_eq!(algo.update_clock(time).used_sources.unwrap().len(), 2);
+
+        // Removing the required source lifts the requirement
+        algo.remove_source(ClockId(0));
+        assert_eq!(algo.update_clock(time).used_sources.unwrap().len(), 1);
+    }
 }
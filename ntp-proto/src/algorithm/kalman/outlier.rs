@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use super::config::AlgorithmConfig;
+
+/// Rejects measurements that deviate grossly from the recent ones before they
+/// reach the Kalman filter.
+///
+/// The filter looks at residuals, the difference between a measured offset
+/// and the offset predicted by the filter state, so steering and frequency
+/// differences don't affect it. A residual is an outlier when it is further
+/// from the median of the window than the configured number of median
+/// absolute deviations (MAD). Rejected residuals are still added to the
+/// window, so a lasting change in offset is accepted once it makes up about
+/// half of the window.
+#[derive(Debug, Clone, Default)]
+pub(super) struct OutlierFilter {
+    residuals: VecDeque<f64>,
+    rejected: u64,
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+impl OutlierFilter {
+    /// Record the residual of a new measurement, returning whether the
+    /// measurement should be discarded. `noise` is the expected standard
+    /// deviation of the measurements, which bounds the MAD from below so
+    /// that a window of near identical measurements doesn't reject
+    /// everything.
+    pub(super) fn is_outlier(
+        &mut self,
+        config: &AlgorithmConfig,
+        residual: f64,
+        noise: f64,
+    ) -> bool {
+        if config.outlier_window == 0 {
+            return false;
+        }
+
+        let outlier = if self.residuals.len() >= config.outlier_window {
+            let center = median(self.residuals.iter().copied().collect());
+            let deviation = median(
+                self.residuals
+                    .iter()
+                    .map(|residual| (residual - center).abs())
+                    .collect(),
+            );
+            (residual - center).abs() > config.outlier_threshold * deviation.max(noise)
+        } else {
+            false
+        };
+
+        while self.residuals.len() >= config.outlier_window {
+            self.residuals.pop_front();
+        }
+        self.residuals.push_back(residual);
+
+        if outlier {
+            self.rejected += 1;
+        }
+        outlier
+    }
+
+    /// Forget the recent residuals, which no longer apply once the filter
+    /// starts over
+    pub(super) fn clear(&mut self) {
+        self.residuals.clear();
+    }
+
+    /// Number of measurements rejected so far
+    pub(super) fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(outlier_window: usize) -> AlgorithmConfig {
+        AlgorithmConfig {
+            outlier_window,
+            outlier_threshold: 5.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(vec![4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut filter = OutlierFilter::default();
+        for _ in 0..16 {
+            assert!(!filter.is_outlier(&config(0), 0.0, 0.0));
+        }
+        assert!(!filter.is_outlier(&config(0), 1.0, 0.0));
+        assert_eq!(filter.rejected(), 0);
+    }
+
+    #[test]
+    fn test_spike() {
+        let config = config(8);
+        let mut filter = OutlierFilter::default();
+
+        // Nothing is rejected until the window is full
+        assert!(!filter.is_outlier(&config, 0.2, 0.0));
+        for i in 0..7 {
+            let residual = if i % 2 == 0 { 1e-3 } else { -1e-3 };
+            assert!(!filter.is_outlier(&config, residual, 0.0));
+        }
+
+        assert!(filter.is_outlier(&config, 0.2, 0.0));
+        assert!(!filter.is_outlier(&config, 2e-3, 0.0));
+        assert_eq!(filter.rejected(), 1);
+    }
+
+    #[test]
+    fn test_noise_floor() {
+        let config = config(8);
+        let mut filter = OutlierFilter::default();
+        for _ in 0..8 {
+            assert!(!filter.is_outlier(&config, 0.0, 1e-3));
+        }
+        assert!(!filter.is_outlier(&config, 4e-3, 1e-3));
+        assert!(filter.is_outlier(&config, 6e-3, 1e-3));
+    }
+
+    #[test]
+    fn test_persistent_change() {
+        let config = config(8);
+        let mut filter = OutlierFilter::default();
+        for _ in 0..8 {
+            filter.is_outlier(&config, 0.0, 1e-3);
+        }
+
+        // Once the new offset makes up half of the window it is accepted again
+        let rejected = (0..8)
+            .filter(|_| filter.is_outlier(&config, 0.1, 1e-3))
+            .count();
+        assert_eq!(rejected, 4);
+        assert!(!filter.is_outlier(&config, 0.1, 1e-3));
+    }
+}
@@ -1,6 +1,6 @@
-use crate::config::SynchronizationConfig;
+use crate::system::SteeringWithheld;
 
-use super::{SourceSnapshot, config::AlgorithmConfig};
+use super::{SourceSnapshot, config::AlgorithmConfig, sqr};
 
 #[derive(Debug)]
 enum BoundType {
@@ -17,11 +17,27 @@ enum BoundType {
 // is also statistically more sound. Any difference (larger set of accepted sources)
 // can be compensated for if desired by setting tighter bounds on the weights
 // determining the confidence interval.
+//
+// When any of the candidates is trusted, only the trusted candidates vote.
+// The other candidates then survive only when they agree with the trusted ones,
+// so they can no longer outvote them.
+//
+// When no set of at least `minimum_agreeing_sources` candidates survives, the
+// reason is returned instead so it can be reported.
 pub(super) fn select(
-    synchronization_config: &SynchronizationConfig,
+    minimum_agreeing_sources: usize,
     algo_config: &AlgorithmConfig,
     candidates: &[SourceSnapshot],
-) -> Vec<SourceSnapshot> {
+) -> Result<Vec<SourceSnapshot>, SteeringWithheld> {
+    let acceptable = |snapshot: &SourceSnapshot| {
+        range_radius(algo_config, snapshot) <= algo_config.maximum_source_uncertainty
+            && snapshot.root_distance() <= algo_config.maximum_root_distance
+            && snapshot.leap_indicator.is_synchronized()
+    };
+    let trusted = candidates
+        .iter()
+        .any(|snapshot| snapshot.trust && snapshot.period.is_none() && acceptable(snapshot));
+
     let mut bounds: Vec<(f64, BoundType)> = Vec::with_capacity(2 * candidates.len());
 
     for snapshot in candidates {
@@ -30,18 +46,20 @@ pub(super) fn select(
             continue;
         }
 
-        let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
-            + snapshot.delay * algo_config.range_delay_weight;
-        if radius > algo_config.maximum_source_uncertainty
-            || !snapshot.leap_indicator.is_synchronized()
-        {
+        if !acceptable(snapshot) || (trusted && !snapshot.trust) {
             continue;
         }
 
+        let radius = range_radius(algo_config, snapshot);
+
         bounds.push((snapshot.offset() - radius, BoundType::Start));
         bounds.push((snapshot.offset() + radius, BoundType::End));
     }
 
+    if bounds.is_empty() {
+        return Err(SteeringWithheld::NoUsableSources);
+    }
+
     bounds.sort_by(|a, b| a.0.total_cmp(&b.0));
 
     // Find the intersection of the confidence intervals of the maximum
@@ -78,21 +96,112 @@ pub(super) fn select(
     assert_eq!(maxlow, maxhigh);
     let max = maxlow;
 
-    if max >= synchronization_config.minimum_agreeing_sources && max * 4 > bounds.len() {
-        candidates
+    if max * 4 <= bounds.len() {
+        return Err(SteeringWithheld::NoMajority {
+            agreeing: max,
+            sources: bounds.len() / 2,
+        });
+    }
+
+    let survivors: Vec<_> = candidates
+        .iter()
+        .filter(|snapshot| {
+            let radius = range_radius(algo_config, snapshot);
+            acceptable(snapshot)
+                && snapshot.offset() - radius <= maxthigh
+                && snapshot.offset() + radius >= maxtlow
+        })
+        .cloned()
+        .collect();
+
+    // With trusted sources, the sources agreeing with them count as well
+    let agreeing = if trusted {
+        survivors
             .iter()
-            .filter(|snapshot| {
-                let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
-                    + snapshot.delay * algo_config.range_delay_weight;
-                radius <= algo_config.maximum_source_uncertainty
-                    && snapshot.offset() - radius <= maxthigh
-                    && snapshot.offset() + radius >= maxtlow
-                    && snapshot.leap_indicator.is_synchronized()
-            })
-            .cloned()
-            .collect()
+            .filter(|snapshot| snapshot.period.is_none())
+            .count()
     } else {
-        vec![]
+        max
+    };
+
+    if agreeing < minimum_agreeing_sources {
+        return Err(SteeringWithheld::NotEnoughAgreeing {
+            agreeing,
+            required: minimum_agreeing_sources,
+        });
+    }
+
+    if algo_config.cluster_minimum_sources == 0 {
+        Ok(survivors)
+    } else {
+        Ok(cluster(
+            survivors,
+            algo_config
+                .cluster_minimum_sources
+                .max(minimum_agreeing_sources),
+        ))
+    }
+}
+
+/// Half the width of the confidence interval of a candidate
+fn range_radius(algo_config: &AlgorithmConfig, snapshot: &SourceSnapshot) -> f64 {
+    snapshot.offset_uncertainty() * algo_config.range_statistical_weight
+        + snapshot.delay * algo_config.range_delay_weight
+}
+
+/// Root mean square distance between the offset of the survivor at `index`
+/// and those of the other non-periodic survivors
+fn selection_jitter(survivors: &[SourceSnapshot], index: usize) -> f64 {
+    let offset = survivors[index].offset();
+    let (sum, count) = survivors
+        .iter()
+        .enumerate()
+        .filter(|(i, snapshot)| *i != index && snapshot.period.is_none())
+        .fold((0.0, 0u32), |(sum, count), (_, snapshot)| {
+            (sum + sqr(snapshot.offset() - offset), count + 1)
+        });
+    if count == 0 {
+        0.0
+    } else {
+        (sum / f64::from(count)).sqrt()
+    }
+}
+
+// Prune survivors of the selection that are statistical outliers, in the
+// spirit of the cluster algorithm of RFC 5905. The survivor whose offset is
+// furthest from the others (its selection jitter) is removed as long as that
+// distance exceeds the uncertainty of the most precise survivor, and more
+// than `minimum` survivors remain. Beyond that point removing sources would
+// not make the remainder agree any better than their own noise allows.
+// Periodic sources don't take part, as their offset is ambiguous, and trusted
+// sources are never removed.
+fn cluster(mut survivors: Vec<SourceSnapshot>, minimum: usize) -> Vec<SourceSnapshot> {
+    loop {
+        let candidates = survivors
+            .iter()
+            .enumerate()
+            .filter(|(_, snapshot)| snapshot.period.is_none());
+        if candidates.clone().count() <= minimum {
+            return survivors;
+        }
+
+        let Some((worst, worst_jitter)) = candidates
+            .clone()
+            .filter(|(_, snapshot)| !snapshot.trust)
+            .map(|(index, _)| (index, selection_jitter(&survivors, index)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            return survivors;
+        };
+        let best_uncertainty = candidates
+            .map(|(_, snapshot)| snapshot.offset_uncertainty())
+            .fold(f64::INFINITY, f64::min);
+
+        if worst_jitter <= best_uncertainty {
+            return survivors;
+        }
+
+        survivors.remove(worst);
     }
 }
 
@@ -101,13 +210,14 @@ mod tests {
     use crate::{
         ClockId,
         algorithm::kalman::source::KalmanState,
+        config::SynchronizationConfig,
         packet::NtpLeapIndicator,
         time_types::{NtpDuration, NtpTimestamp},
     };
 
     use super::super::{
+        combiner::combine,
         matrix::{Matrix, Vector},
-        sqr,
     };
 
     use super::*;
@@ -132,6 +242,9 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
             last_update: NtpTimestamp::from_fixed_int(0),
+            weight: 1.0,
+            prefer: false,
+            trust: false,
         }
     }
 
@@ -157,7 +270,8 @@ mod tests {
             ..Default::default()
         };
 
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -166,7 +280,8 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -175,7 +290,8 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 4);
     }
 
@@ -194,11 +310,13 @@ mod tests {
 
         let algconfig = AlgorithmConfig {
             maximum_source_uncertainty: 3.0,
+            maximum_root_distance: 3.0,
             range_statistical_weight: 1.0,
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 3);
 
         let algconfig = AlgorithmConfig {
@@ -207,7 +325,8 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 2);
 
         let algconfig = AlgorithmConfig {
@@ -216,7 +335,8 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 1);
 
         let algconfig = AlgorithmConfig {
@@ -225,7 +345,8 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 0);
     }
 
@@ -250,14 +371,16 @@ mod tests {
             minimum_agreeing_sources: 3,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 3);
 
         let sysconfig = SynchronizationConfig {
             minimum_agreeing_sources: 4,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 0);
     }
 
@@ -280,10 +403,75 @@ mod tests {
             minimum_agreeing_sources: 1,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 0);
     }
 
+    #[test]
+    fn test_preferred_outvoted() {
+        // A preferred source that disagrees with the majority is still rejected
+        let candidates = vec![
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            SourceSnapshot {
+                prefer: true,
+                weight: 10.0,
+                ..snapshot_for_range(0.5, 0.001, 0.001, None)
+            },
+        ];
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 3.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|snapshot| !snapshot.prefer));
+
+        let combined = combine(&result, &algconfig).unwrap();
+        assert!(combined.estimate.offset().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_root_distance() {
+        let candidates = vec![
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            SourceSnapshot {
+                source_uncertainty: NtpDuration::from_seconds(2.0),
+                ..snapshot_for_range(0.0, 0.01, 0.01, None)
+            },
+        ];
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+
+        let algconfig = AlgorithmConfig::default();
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|snapshot| snapshot.root_distance() < 0.1));
+
+        let algconfig = AlgorithmConfig {
+            maximum_root_distance: 3.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates)
+                .unwrap_or_default()
+                .len(),
+            3
+        );
+    }
+
     #[test]
     fn test_periodic_is_ignored() {
         let candidates = vec![
@@ -300,14 +488,194 @@ mod tests {
             minimum_agreeing_sources: 2,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 3);
         assert_eq!(result[0].offset(), 0.5);
         let sysconfig = SynchronizationConfig {
             minimum_agreeing_sources: 3,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, &candidates);
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
         assert_eq!(result.len(), 0);
     }
+
+    #[test]
+    fn test_cluster() {
+        let survivors = vec![
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            snapshot_for_range(0.001, 0.01, 0.01, None),
+            snapshot_for_range(-0.001, 0.01, 0.01, None),
+            snapshot_for_range(0.02, 0.01, 0.01, None),
+        ];
+
+        // The noisy survivor is pruned, the rest agree within their noise
+        let result = cluster(survivors.clone(), 1);
+        assert_eq!(result.len(), 3);
+        assert!(
+            result
+                .iter()
+                .all(|snapshot| snapshot.offset().abs() < 0.002)
+        );
+
+        // But never below the minimum
+        let result = cluster(survivors.clone(), 4);
+        assert_eq!(result.len(), 4);
+
+        // Periodic sources are left alone
+        let mut survivors = survivors;
+        survivors.push(snapshot_for_range(0.3, 0.01, 0.01, Some(1.0)));
+        let result = cluster(survivors, 1);
+        assert_eq!(result.len(), 4);
+        assert!(result.iter().any(|snapshot| snapshot.period.is_some()));
+    }
+
+    #[test]
+    fn test_cluster_in_select() {
+        let candidates = vec![
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            snapshot_for_range(0.001, 0.01, 0.01, None),
+            snapshot_for_range(-0.001, 0.01, 0.01, None),
+            snapshot_for_range(0.02, 0.01, 0.01, None),
+        ];
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+
+        let algconfig = AlgorithmConfig::default();
+        assert_eq!(
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates)
+                .unwrap_or_default()
+                .len(),
+            4
+        );
+
+        let algconfig = AlgorithmConfig {
+            cluster_minimum_sources: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates)
+                .unwrap_or_default()
+                .len(),
+            3
+        );
+
+        // The minimum number of agreeing sources is kept as well
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates)
+                .unwrap_or_default()
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_trust() {
+        // A trusted source is not outvoted by sources that agree with
+        // each other but not with it
+        let candidates = vec![
+            SourceSnapshot {
+                trust: true,
+                ..snapshot_for_range(0.0, 0.01, 0.01, None)
+            },
+            snapshot_for_range(0.5, 0.01, 0.01, None),
+            snapshot_for_range(0.5, 0.01, 0.01, None),
+            snapshot_for_range(0.001, 0.01, 0.01, None),
+        ];
+        let algconfig = AlgorithmConfig::default();
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..Default::default()
+        };
+
+        let result = select(
+            sysconfig.minimum_agreeing_sources,
+            &algconfig,
+            &candidates[1..],
+        )
+        .unwrap_or_default();
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|snapshot| snapshot.offset() == 0.5));
+
+        let result =
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates).unwrap_or_default();
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|snapshot| snapshot.offset() < 0.01));
+
+        // Sources agreeing with the trusted source count towards the minimum
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates)
+                .unwrap_or_default()
+                .len(),
+            2
+        );
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            select(sysconfig.minimum_agreeing_sources, &algconfig, &candidates)
+                .unwrap_or_default()
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_withheld_reason() {
+        let algconfig = AlgorithmConfig::default();
+        assert_eq!(
+            select(1, &algconfig, &[]).unwrap_err(),
+            SteeringWithheld::NoUsableSources
+        );
+
+        let candidates = vec![
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            snapshot_for_range(0.001, 0.01, 0.01, None),
+            snapshot_for_range(0.5, 0.01, 0.01, None),
+            snapshot_for_range(1.0, 0.01, 0.01, None),
+        ];
+        assert_eq!(
+            select(1, &algconfig, &candidates).unwrap_err(),
+            SteeringWithheld::NoMajority {
+                agreeing: 2,
+                sources: 4
+            }
+        );
+        assert_eq!(
+            select(3, &algconfig, &candidates[..3]).unwrap_err(),
+            SteeringWithheld::NotEnoughAgreeing {
+                agreeing: 2,
+                required: 3
+            }
+        );
+        assert_eq!(select(2, &algconfig, &candidates[..3]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_cluster_keeps_trusted() {
+        let survivors = vec![
+            snapshot_for_range(0.0, 0.01, 0.01, None),
+            snapshot_for_range(0.001, 0.01, 0.01, None),
+            snapshot_for_range(-0.001, 0.01, 0.01, None),
+            SourceSnapshot {
+                trust: true,
+                ..snapshot_for_range(0.02, 0.01, 0.01, None)
+            },
+        ];
+
+        let result = cluster(survivors, 1);
+        assert!(result.iter().any(|snapshot| snapshot.trust));
+    }
 }
@@ -0,0 +1,297 @@
+use std::time::Duration;
+
+use crate::{
+    config::{LeapSmearConfig, LeapSmearCurve},
+    packet::NtpLeapIndicator,
+    system::LeapSmearStatus,
+    time_types::{NtpDuration, NtpTimestamp},
+};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+// How often the smear frequency is updated while smearing
+const UPDATE_INTERVAL: f64 = 60.0;
+
+impl LeapSmearCurve {
+    /// Fraction of the leap second smeared at the given fraction of the window
+    fn value(self, progress: f64) -> f64 {
+        match self {
+            LeapSmearCurve::Linear => progress,
+            LeapSmearCurve::Cosine => (1.0 - (std::f64::consts::PI * progress).cos()) / 2.0,
+        }
+    }
+
+    /// Derivative of `value` with respect to progress
+    fn slope(self, progress: f64) -> f64 {
+        match self {
+            LeapSmearCurve::Linear => 1.0,
+            LeapSmearCurve::Cosine => {
+                std::f64::consts::FRAC_PI_2 * (std::f64::consts::PI * progress).sin()
+            }
+        }
+    }
+}
+
+/// Start of the next day (UTC) after `time`. This is when the kernel would
+/// insert or delete an announced leap second.
+fn next_midnight(time: NtpTimestamp) -> NtpTimestamp {
+    let seconds = u64::from_be_bytes(time.to_bits()) >> 32;
+    let midnight = (seconds / SECONDS_PER_DAY + 1) * SECONDS_PER_DAY;
+    // Wraps around at the era boundary, just like the timestamps themselves
+    NtpTimestamp::from_seconds_nanos_since_ntp_era(midnight as u32, 0)
+}
+
+/// Schedule for smearing out a single leap second.
+///
+/// Offsets and frequencies use the conventions of the kalman filter: they
+/// describe the time of the sources relative to our clock. While smearing an
+/// inserted leap second our clock runs slow, so the sources are ahead of it,
+/// until the sources repeat a second at the leap.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LeapSmear {
+    leap: NtpLeapIndicator,
+    curve: LeapSmearCurve,
+    start: NtpTimestamp,
+    // Local time at which the sources go through the leap second
+    leap_time: NtpTimestamp,
+    end: NtpTimestamp,
+    leap_passed: bool,
+}
+
+impl LeapSmear {
+    /// Plan smearing the leap second announced at `now`.
+    ///
+    /// With `resume` we assume the clock has already been smeared according
+    /// to the full schedule, as is the case when the daemon is restarted in
+    /// the middle of the window. Otherwise a smear that is announced late is
+    /// compressed into the remainder of the window.
+    pub(super) fn new(
+        config: &LeapSmearConfig,
+        leap: NtpLeapIndicator,
+        now: NtpTimestamp,
+        resume: bool,
+    ) -> LeapSmear {
+        debug_assert!(matches!(
+            leap,
+            NtpLeapIndicator::Leap61 | NtpLeapIndicator::Leap59
+        ));
+
+        let midnight = next_midnight(now);
+        let half_window = NtpDuration::from_seconds(config.window / 2.0);
+        let nominal_start = midnight - half_window;
+        let start = if resume || now.is_before(nominal_start) {
+            nominal_start
+        } else {
+            now
+        };
+
+        let mut smear = LeapSmear {
+            leap,
+            curve: config.curve,
+            start,
+            leap_time: midnight,
+            end: midnight + half_window,
+            leap_passed: false,
+        };
+
+        // Our clock is off by the smeared amount by the time the sources
+        // reach midnight.
+        smear.leap_time = midnight - NtpDuration::from_seconds(smear.offset(midnight));
+        smear
+    }
+
+    fn direction(&self) -> f64 {
+        match self.leap {
+            NtpLeapIndicator::Leap59 => -1.0,
+            _ => 1.0,
+        }
+    }
+
+    fn duration(&self) -> f64 {
+        (self.end - self.start).to_seconds()
+    }
+
+    /// Fraction of the window that has passed at `time`
+    fn progress(&self, time: NtpTimestamp) -> f64 {
+        ((time - self.start).to_seconds() / self.duration()).clamp(0.0, 1.0)
+    }
+
+    pub(super) fn has_started(&self, time: NtpTimestamp) -> bool {
+        !time.is_before(self.start)
+    }
+
+    pub(super) fn is_finished(&self, time: NtpTimestamp) -> bool {
+        self.leap_passed && !time.is_before(self.end)
+    }
+
+    /// Intended offset of the sources relative to our clock at `time` (s)
+    pub(super) fn offset(&self, time: NtpTimestamp) -> f64 {
+        let smeared = self.curve.value(self.progress(time));
+        let leaped = if self.leap_passed { 1.0 } else { 0.0 };
+        self.direction() * (smeared - leaped)
+    }
+
+    /// Intended frequency of the sources relative to our clock at `time` (s/s)
+    pub(super) fn frequency(&self, time: NtpTimestamp) -> f64 {
+        if !self.has_started(time) || !time.is_before(self.end) {
+            return 0.0;
+        }
+        self.direction() * self.curve.slope(self.progress(time)) / self.duration()
+    }
+
+    /// Whether the sources have gone through the leap second at `time`,
+    /// without this having been processed yet.
+    pub(super) fn leap_due(&self, time: NtpTimestamp) -> bool {
+        !self.leap_passed && !time.is_before(self.leap_time)
+    }
+
+    /// Mark the leap second as processed, returns by how much the offset of
+    /// the sources jumped.
+    pub(super) fn pass_leap(&mut self) -> f64 {
+        self.leap_passed = true;
+        -self.direction()
+    }
+
+    /// Time after which the smear needs to be updated again
+    pub(super) fn next_update(&self, time: NtpTimestamp) -> Duration {
+        let next_event = if !self.has_started(time) {
+            self.start
+        } else if !self.leap_passed {
+            self.leap_time
+        } else {
+            self.end
+        };
+
+        let mut until = (next_event - time).to_seconds().max(0.0);
+        if self.has_started(time) {
+            until = until.min(UPDATE_INTERVAL);
+        }
+        Duration::from_secs_f64(until)
+    }
+
+    pub(super) fn status(&self, time: NtpTimestamp) -> LeapSmearStatus {
+        let progress = self.progress(time);
+        LeapSmearStatus {
+            leap: self.leap,
+            progress,
+            offset: NtpDuration::from_seconds(self.direction() * self.curve.value(progress)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: f64) -> NtpTimestamp {
+        // 2016-12-31T00:00:00Z, the day of the last leap second
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(3_692_131_200, 0)
+            + NtpDuration::from_seconds(seconds)
+    }
+
+    const DAY: f64 = 86400.0;
+
+    fn config(curve: LeapSmearCurve) -> LeapSmearConfig {
+        LeapSmearConfig { window: DAY, curve }
+    }
+
+    #[test]
+    fn test_next_midnight() {
+        assert_eq!(next_midnight(at(0.0)), at(DAY));
+        assert_eq!(next_midnight(at(1.0)), at(DAY));
+        assert_eq!(next_midnight(at(DAY - 1.0)), at(DAY));
+    }
+
+    #[test]
+    fn test_linear_schedule() {
+        let mut smear = LeapSmear::new(
+            &config(LeapSmearCurve::Linear),
+            NtpLeapIndicator::Leap61,
+            at(3600.0),
+            false,
+        );
+
+        assert!(!smear.has_started(at(3600.0)));
+        assert_eq!(smear.offset(at(3600.0)), 0.0);
+        assert_eq!(smear.frequency(at(3600.0)), 0.0);
+        let until_start = smear.next_update(at(3600.0)).as_secs_f64();
+        assert!((until_start - 11.0 * 3600.0).abs() < 1e-3);
+
+        // Halfway, just before the leap second
+        assert!((smear.offset(at(DAY - 1.0)) - 0.5).abs() < 1e-4);
+        assert!((smear.frequency(at(DAY - 1.0)) - 1.0 / DAY).abs() < 1e-12);
+        assert!(!smear.leap_due(at(DAY - 1.0)));
+
+        // The sources reach midnight while our clock is half a second behind
+        assert!(!smear.leap_due(at(DAY - 0.6)));
+        assert!(smear.leap_due(at(DAY - 0.4)));
+        assert_eq!(smear.pass_leap(), -1.0);
+        assert!(!smear.leap_due(at(DAY)));
+        assert!((smear.offset(at(DAY)) + 0.5).abs() < 1e-4);
+
+        assert!(!smear.is_finished(at(1.5 * DAY - 1.0)));
+        assert!(smear.is_finished(at(1.5 * DAY)));
+        assert!(smear.offset(at(1.5 * DAY)).abs() < 1e-9);
+        assert_eq!(smear.frequency(at(1.5 * DAY)), 0.0);
+    }
+
+    #[test]
+    fn test_cosine_schedule() {
+        let mut smear = LeapSmear::new(
+            &config(LeapSmearCurve::Cosine),
+            NtpLeapIndicator::Leap59,
+            at(3600.0),
+            false,
+        );
+
+        // Gradual start
+        assert!(smear.frequency(at(0.5 * DAY + 1.0)).abs() < 1e-9);
+        assert!(smear.offset(at(0.5 * DAY + 1.0)).abs() < 1e-9);
+
+        // Fastest halfway, clock running ahead
+        let halfway = smear.frequency(at(DAY - 1.0));
+        assert!((halfway + std::f64::consts::FRAC_PI_2 / DAY).abs() < 1e-9);
+        assert!((smear.offset(at(DAY - 1.0)) + 0.5).abs() < 1e-4);
+
+        // Our clock is ahead, so the sources reach the leap second late
+        assert!(!smear.leap_due(at(DAY + 0.4)));
+        assert!(smear.leap_due(at(DAY + 0.6)));
+        assert_eq!(smear.pass_leap(), 1.0);
+        assert!((smear.offset(at(DAY)) - 0.5).abs() < 1e-4);
+
+        let status = smear.status(at(1.25 * DAY));
+        assert_eq!(status.leap, NtpLeapIndicator::Leap59);
+        assert!((status.progress - 0.75).abs() < 1e-9);
+        assert!((status.offset.to_seconds() + 0.5 + 0.5f64.sqrt() / 2.0).abs() < 1e-6);
+
+        // Updated regularly while smearing
+        assert_eq!(
+            smear.next_update(at(1.25 * DAY)),
+            Duration::from_secs_f64(UPDATE_INTERVAL)
+        );
+    }
+
+    #[test]
+    fn test_late_announcement() {
+        // Announced after the window started, compress into the remainder
+        let smear = LeapSmear::new(
+            &config(LeapSmearCurve::Linear),
+            NtpLeapIndicator::Leap61,
+            at(0.75 * DAY),
+            false,
+        );
+        assert!(smear.has_started(at(0.75 * DAY)));
+        assert_eq!(smear.offset(at(0.75 * DAY)), 0.0);
+        assert!((smear.frequency(at(0.75 * DAY)) - 1.0 / (0.75 * DAY)).abs() < 1e-12);
+
+        // After a restart, continue where the previous run left off
+        let smear = LeapSmear::new(
+            &config(LeapSmearCurve::Linear),
+            NtpLeapIndicator::Leap61,
+            at(0.75 * DAY),
+            true,
+        );
+        assert!((smear.offset(at(0.75 * DAY)) - 0.25).abs() < 1e-6);
+        assert!((smear.frequency(at(0.75 * DAY)) - 1.0 / DAY).abs() < 1e-12);
+    }
+}
@@ -73,12 +73,15 @@
 /// If they are often too small, v is quartered, and if they are often too
 /// large, v is quadrupled (note, this corresponds with doubling/halving
 /// the more intuitive standard deviation).
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use tracing::{debug, trace};
 
 use crate::{
-    ClockId, ObservableSourceTimedata,
+    ClockId, NtpLeapIndicator, ObservableKalmanState, ObservableSourceTimedata,
     algorithm::{
-        InternalMeasurement, InternalSourceController, KalmanControllerMessage, KalmanSourceMessage,
+        InternalMeasurement, InternalSourceController, KalmanControllerMessage,
+        KalmanSourceMessage, SavedSourceState,
     },
     config::SourceConfig,
     time_types::{NtpDuration, NtpTimestamp, PollInterval, PollIntervalLimits},
@@ -90,6 +93,7 @@ use super::{
     SourceSnapshot,
     config::AlgorithmConfig,
     matrix::{Matrix, Vector},
+    outlier::OutlierFilter,
     sqr,
 };
 
@@ -106,6 +110,8 @@ pub(super) struct MeasurementStats {
     pub observe_probability: f64,
     // How much the measurement affected the filter state
     pub weight: f64,
+    // Difference between measurement and prediction, in standard deviations
+    pub normalized_difference: f64,
 }
 
 impl KalmanState {
@@ -191,6 +197,8 @@ impl KalmanState {
             MeasurementStats {
                 observe_probability,
                 weight,
+                normalized_difference: difference.ventry(0)
+                    / difference_covariance.entry(0, 0).sqrt(),
             },
         )
     }
@@ -270,6 +278,11 @@ pub struct AveragingBuffer {
 // Large frequency uncertainty as early time essentially gives no reasonable info on frequency.
 const INITIALIZATION_FREQ_UNCERTAINTY: f64 = 100.0;
 
+// Maximum contribution (in standard deviations) of a single measurement to the
+// frequency step detection. A single large error is left to the outlier and
+// divergence detection, it takes a series of them to indicate a frequency step.
+const FREQUENCY_STEP_MAX_CONTRIBUTION: f64 = 3.0;
+
 /// Approximation of 1 - the chi-squared cdf with 1 degree of freedom
 /// source: https://en.wikipedia.org/wiki/Error_function
 fn chi_1(chi: f64) -> f64 {
@@ -314,6 +327,10 @@ pub trait MeasurementNoiseEstimator {
     // for SourceSnapshot
     fn get_max_roundtrip(&self, samples: &i32) -> Option<f64>;
     fn get_delay_mean(&self) -> f64;
+
+    // for keeping the estimate across restarts
+    fn save(&self) -> Vec<f64>;
+    fn restore(&mut self, samples: &[f64]);
 }
 
 impl MeasurementNoiseEstimator for AveragingBuffer {
@@ -357,6 +374,17 @@ impl MeasurementNoiseEstimator for AveragingBuffer {
     fn get_delay_mean(&self) -> f64 {
         self.mean()
     }
+
+    fn save(&self) -> Vec<f64> {
+        self.data.to_vec()
+    }
+
+    fn restore(&mut self, samples: &[f64]) {
+        if samples.len() == self.data.len() {
+            self.data.copy_from_slice(samples);
+            self.next_idx = 0;
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -392,6 +420,13 @@ impl MeasurementNoiseEstimator for FixedMeasurementNoise {
         // Bit of a hack: multiply by 4 to compensate for the low delay weight. This is because accuracy doesn't quite map to delay.
         4.0 * self.accuracy
     }
+
+    // The noise is fixed by the configuration of the source
+    fn save(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    fn restore(&mut self, _samples: &[f64]) {}
 }
 
 #[derive(Debug, Clone)]
@@ -475,10 +510,17 @@ struct SourceFilter<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<Measur
 
     precision_score: i32,
     poll_score: i32,
+    divergence_score: usize,
+    // Cumulative sums of measurement errors above and below the prediction
+    frequency_step_score: [f64; 2],
+    frequency_steps: u64,
     desired_poll_interval: PollInterval,
 
     last_measurement: InternalMeasurement<D>,
     last_monotime: tokio::time::Instant,
+    // Whether the filter was restored from a previous run of the daemon,
+    // and has not seen a measurement since
+    restored: bool,
     prev_was_outlier: bool,
 
     // Last time a packet was processed
@@ -500,7 +542,7 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         &mut self,
         measurement: InternalMeasurement<D>,
         period: Option<f64>,
-    ) -> (f64, f64, f64) {
+    ) -> (f64, f64, f64, f64) {
         // Measurement parameters
         let m_delta_t = (measurement.localtime - self.last_measurement.localtime).to_seconds();
 
@@ -536,7 +578,22 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
             stats.weight, "Measurement absorbed"
         );
 
-        (stats.observe_probability, stats.weight, m_delta_t)
+        (
+            stats.observe_probability,
+            stats.weight,
+            m_delta_t,
+            stats.normalized_difference,
+        )
+    }
+
+    /// Poll interval (in seconds) after which the clock wander has added about
+    /// as much uncertainty to the offset as a single measurement has. Polling
+    /// much faster than this gains little, polling much slower lets the
+    /// estimate drift between measurements.
+    fn wander_poll_interval(&self) -> Option<f64> {
+        // The offset variance due to wander grows as wander * t^3 / 3
+        (self.clock_wander > 0.0)
+            .then(|| (3.0 * self.noise_estimator.get_noise_estimate() / self.clock_wander).cbrt())
     }
 
     /// Ensure we poll often enough to keep the filter well-fed with information, but
@@ -551,18 +608,47 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
     ) {
         // We don't want to speed up when we already want more than we get, and vice versa.
         let reference_measurement_period = self.desired_poll_interval.as_duration().to_seconds();
-        if weight < algo_config.poll_interval_low_weight
-            && measurement_period / reference_measurement_period > 0.75
-        {
+        let may_slow_down = measurement_period / reference_measurement_period > 0.75;
+        let may_speed_up = measurement_period / reference_measurement_period < 1.4;
+
+        // A stable clock can go much longer between polls than the weight of
+        // individual measurements suggests, and an unstable one needs more
+        // than it, so far from the interval the wander calls for it adds to
+        // the score as well.
+        let wander_poll_interval = self.wander_poll_interval();
+        let factor = algo_config.poll_interval_wander_factor;
+        let wander_score = match wander_poll_interval {
+            Some(interval)
+                if factor > 0.0
+                    && may_slow_down
+                    && reference_measurement_period * factor < interval =>
+            {
+                -1
+            }
+            Some(interval)
+                if factor > 0.0
+                    && may_speed_up
+                    && reference_measurement_period > interval * factor =>
+            {
+                1
+            }
+            _ => 0,
+        };
+
+        if weight < algo_config.poll_interval_low_weight && may_slow_down {
             self.poll_score -= 1;
-        } else if weight > algo_config.poll_interval_high_weight
-            && measurement_period / reference_measurement_period < 1.4
-        {
+        } else if weight > algo_config.poll_interval_high_weight && may_speed_up {
             self.poll_score += 1;
-        } else {
+        } else if wander_score == 0 {
             self.poll_score -= self.poll_score.signum();
         }
-        trace!(poll_score = self.poll_score, ?weight, "Poll desire update");
+        self.poll_score += wander_score;
+        trace!(
+            poll_score = self.poll_score,
+            ?weight,
+            ?wander_poll_interval,
+            "Poll desire update"
+        );
         if p <= algo_config.poll_interval_step_threshold {
             self.desired_poll_interval = source_config.poll_interval_limits.min;
             self.poll_score = 0;
@@ -571,13 +657,23 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                 .desired_poll_interval
                 .inc(source_config.poll_interval_limits);
             self.poll_score = 0;
-            debug!(interval = ?self.desired_poll_interval, "Increased poll interval");
+            debug!(
+                interval = ?self.desired_poll_interval,
+                weight,
+                ?wander_poll_interval,
+                "Increased poll interval"
+            );
         } else if self.poll_score >= algo_config.poll_interval_hysteresis {
             self.desired_poll_interval = self
                 .desired_poll_interval
                 .dec(source_config.poll_interval_limits);
             self.poll_score = 0;
-            debug!(interval = ?self.desired_poll_interval, "Decreased poll interval");
+            debug!(
+                interval = ?self.desired_poll_interval,
+                weight,
+                ?wander_poll_interval,
+                "Decreased poll interval"
+            );
         }
     }
 
@@ -617,6 +713,77 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         }
     }
 
+    // When the filter's model no longer matches reality, for example because the
+    // network route to the source changed, measurements will keep falling far
+    // outside the predicted uncertainty. Keep track of how long that has been
+    // going on, so the filter can be restarted instead of slowly converging.
+    fn update_divergence_score(&mut self, algo_config: &AlgorithmConfig, p: f64) {
+        if p < algo_config.divergence_probability {
+            self.divergence_score += 1;
+        } else {
+            self.divergence_score = 0;
+        }
+        trace!(
+            divergence_score = self.divergence_score,
+            p, "Divergence update"
+        );
+    }
+
+    // A sudden change in the frequency of either clock, for example after a
+    // virtual machine migrated, shows up as measurements that keep erring in
+    // the same direction. The filter would take hours to slew to the new
+    // frequency, so when the cumulative sum of the errors in one direction
+    // becomes too large, widen the frequency uncertainty and poll quickly
+    // for the filter to converge on the new frequency.
+    fn update_frequency_step_score(
+        &mut self,
+        source_config: &SourceConfig,
+        algo_config: &AlgorithmConfig,
+        normalized_difference: f64,
+    ) {
+        if algo_config.frequency_step_threshold <= 0.0 {
+            return;
+        }
+
+        let difference = normalized_difference.clamp(
+            -FREQUENCY_STEP_MAX_CONTRIBUTION,
+            FREQUENCY_STEP_MAX_CONTRIBUTION,
+        );
+        let [above, below] = self.frequency_step_score;
+        self.frequency_step_score = [
+            (above + difference - algo_config.frequency_step_slack).max(0.0),
+            (below - difference - algo_config.frequency_step_slack).max(0.0),
+        ];
+        trace!(
+            frequency_step_score = ?self.frequency_step_score,
+            normalized_difference, "Frequency step update"
+        );
+
+        if self
+            .frequency_step_score
+            .iter()
+            .any(|score| *score > algo_config.frequency_step_threshold)
+        {
+            tracing::warn!(
+                frequency = self.state.frequency() * 1e6,
+                "Detected a sudden change in frequency, reconverging"
+            );
+            self.state.uncertainty = self.state.uncertainty
+                + Matrix::new([
+                    [0.0, 0.0],
+                    [0.0, sqr(algo_config.frequency_step_uncertainty)],
+                ]);
+            self.desired_poll_interval = source_config.poll_interval_limits.min;
+            self.poll_score = 0;
+            self.frequency_step_score = [0.0; 2];
+            self.frequency_steps += 1;
+        }
+    }
+
+    fn is_diverged(&self, algo_config: &AlgorithmConfig) -> bool {
+        algo_config.divergence_count > 0 && self.divergence_score >= algo_config.divergence_count
+    }
+
     /// Update our estimates based on a new measurement.
     fn update(
         &mut self,
@@ -653,10 +820,13 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         self.progress_filtertime(measurement.localtime, period);
         self.noise_estimator.update(measurement.delay);
 
-        let (p, weight, measurement_period) = self.absorb_measurement(measurement, period);
+        let (p, weight, measurement_period, normalized_difference) =
+            self.absorb_measurement(measurement, period);
 
         self.update_wander_estimate(algo_config, p, weight);
+        self.update_divergence_score(algo_config, p);
         self.update_desired_poll(source_config, algo_config, p, weight, measurement_period);
+        self.update_frequency_step_score(source_config, algo_config, normalized_difference);
 
         debug!(
             "source offset {}±{}ms, freq {}±{}ppm",
@@ -688,6 +858,27 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
     }
 }
 
+/// The state of a stable source filter, saved when the daemon shuts down so
+/// that the filter doesn't have to start over after a restart
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SavedKalmanSourceState {
+    offset: f64,
+    frequency: f64,
+    uncertainty: [[f64; 2]; 2],
+    time: NtpTimestamp,
+    wander: f64,
+    noise: Vec<f64>,
+    poll_interval: PollInterval,
+    last_offset: NtpDuration,
+    last_localtime: NtpTimestamp,
+    last_iter: NtpTimestamp,
+    root_delay: NtpDuration,
+    root_dispersion: NtpDuration,
+    leap: NtpLeapIndicator,
+    precision: i8,
+}
+
 #[derive(Debug, Clone)]
 enum SourceStateInner<
     D: Debug + Copy + Clone,
@@ -760,9 +951,13 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                         noise_estimator: filter.noise_estimator.clone(),
                         precision_score: 0,
                         poll_score: 0,
+                        divergence_score: 0,
+                        frequency_step_score: [0.0; 2],
+                        frequency_steps: 0,
                         desired_poll_interval: source_config.initial_poll_interval,
                         last_monotime: tokio::time::Instant::now(),
                         last_measurement: measurement,
+                        restored: false,
                         prev_was_outlier: false,
                         last_iter: measurement.localtime,
                     }));
@@ -784,9 +979,14 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                         .unwrap_or(std::time::Duration::ZERO),
                 );
 
-                if localtime_difference.abs_diff(monotime_difference)
-                    > algo_config.meddling_threshold
-                {
+                // The monotonic clock starts over with the daemon, so there is
+                // nothing to compare against right after a restart
+                let meddled = !filter.restored
+                    && localtime_difference.abs_diff(monotime_difference)
+                        > algo_config.meddling_threshold;
+                filter.restored = false;
+
+                if meddled {
                     let msg = "Detected clock meddling. Has another process updated the clock?";
                     tracing::warn!(msg);
 
@@ -798,13 +998,33 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                     }));
 
                     false
+                } else if filter.update(source_config, algo_config, measurement, period) {
+                    if filter.is_diverged(algo_config) {
+                        tracing::warn!(
+                            measurements = filter.divergence_score,
+                            "Source measurements are inconsistent with the filter state, reinitializing"
+                        );
+
+                        let mut initial = InitialSourceFilter {
+                            noise_estimator: filter.noise_estimator.reset(),
+                            init_offset: AveragingBuffer::default(),
+                            last_measurement: None,
+                            samples: 0,
+                        };
+                        initial.update(measurement, period);
+                        *self = SourceState(SourceStateInner::Initial(initial));
+                    }
+
+                    true
                 } else {
-                    filter.update(source_config, algo_config, measurement, period)
+                    false
                 }
             }
         }
     }
 
+    // Weight, preference and trust of the snapshot are left at their defaults,
+    // the controller fills them in from the source configuration.
     fn snapshot(
         &self,
         index: ClockId,
@@ -825,6 +1045,9 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                     source_delay: last_measurement.root_delay,
                     leap_indicator: last_measurement.leap,
                     last_update: last_measurement.localtime,
+                    weight: 1.0,
+                    prefer: false,
+                    trust: false,
                     delay: max_roundtrip,
                     period,
                     state: KalmanState {
@@ -856,10 +1079,71 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                 source_delay: filter.last_measurement.root_delay,
                 leap_indicator: filter.last_measurement.leap,
                 last_update: filter.last_iter,
+                weight: 1.0,
+                prefer: false,
+                trust: false,
             }),
         }
     }
 
+    /// The state of a stable filter, to continue from after a restart
+    fn save(&self) -> Option<SavedKalmanSourceState> {
+        let SourceStateInner::Stable(filter) = &self.0 else {
+            return None;
+        };
+
+        Some(SavedKalmanSourceState {
+            offset: filter.state.offset(),
+            frequency: filter.state.frequency(),
+            uncertainty: [
+                [
+                    filter.state.uncertainty.entry(0, 0),
+                    filter.state.uncertainty.entry(0, 1),
+                ],
+                [
+                    filter.state.uncertainty.entry(1, 0),
+                    filter.state.uncertainty.entry(1, 1),
+                ],
+            ],
+            time: filter.state.time,
+            wander: filter.clock_wander,
+            noise: filter.noise_estimator.save(),
+            poll_interval: filter.desired_poll_interval,
+            last_offset: filter.last_measurement.offset,
+            last_localtime: filter.last_measurement.localtime,
+            last_iter: filter.last_iter,
+            root_delay: filter.last_measurement.root_delay,
+            root_dispersion: filter.last_measurement.root_dispersion,
+            leap: filter.last_measurement.leap,
+            precision: filter.last_measurement.precision,
+        })
+    }
+
+    /// Number of frequency steps detected since the filter was initialized
+    fn frequency_steps(&self) -> u64 {
+        match &self.0 {
+            SourceStateInner::Initial(_) => 0,
+            SourceStateInner::Stable(filter) => filter.frequency_steps,
+        }
+    }
+
+    /// Current estimate of the variance of the offset measurements
+    fn measurement_noise(&self) -> f64 {
+        match &self.0 {
+            SourceStateInner::Initial(filter) => filter.noise_estimator.get_noise_estimate(),
+            SourceStateInner::Stable(filter) => filter.noise_estimator.get_noise_estimate(),
+        }
+    }
+
+    /// Progress of the poll interval controller, and the interval suggested
+    /// by the clock wander
+    fn poll_reasoning(&self) -> (i32, Option<f64>) {
+        match &self.0 {
+            SourceStateInner::Initial(_) => (0, None),
+            SourceStateInner::Stable(filter) => (filter.poll_score, filter.wander_poll_interval()),
+        }
+    }
+
     pub fn get_desired_poll(&self, limits: &PollIntervalLimits) -> PollInterval {
         match &self.0 {
             SourceStateInner::Initial(_) => limits.min,
@@ -878,6 +1162,15 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         }
     }
 
+    /// Start over with the initial filter, forgetting all measurements
+    fn reset(&mut self) {
+        let noise_estimator = match &mut self.0 {
+            SourceStateInner::Initial(filter) => filter.noise_estimator.reset(),
+            SourceStateInner::Stable(filter) => filter.noise_estimator.reset(),
+        };
+        *self = SourceState::new(noise_estimator);
+    }
+
     pub fn process_frequency_steering(
         &mut self,
         time: NtpTimestamp,
@@ -893,6 +1186,49 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
     }
 }
 
+impl<D: Debug + Copy + Clone + Default, N: MeasurementNoiseEstimator<MeasurementDelay = D> + Clone>
+    SourceState<D, N>
+{
+    /// Continue from the state the filter had in a previous run of the daemon
+    fn restore(&mut self, saved: &SavedKalmanSourceState, limits: &PollIntervalLimits) {
+        let mut noise_estimator = match &mut self.0 {
+            SourceStateInner::Initial(filter) => filter.noise_estimator.reset(),
+            SourceStateInner::Stable(filter) => filter.noise_estimator.reset(),
+        };
+        noise_estimator.restore(&saved.noise);
+
+        *self = SourceState(SourceStateInner::Stable(SourceFilter {
+            state: KalmanState {
+                state: Vector::new_vector([saved.offset, saved.frequency]),
+                uncertainty: Matrix::new(saved.uncertainty),
+                time: saved.time,
+            },
+            clock_wander: saved.wander,
+            noise_estimator,
+            precision_score: 0,
+            poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
+            desired_poll_interval: saved.poll_interval.clamp(limits.min, limits.max),
+            last_measurement: InternalMeasurement {
+                delay: D::default(),
+                offset: saved.last_offset,
+                localtime: saved.last_localtime,
+                root_delay: saved.root_delay,
+                root_dispersion: saved.root_dispersion,
+                leap: saved.leap,
+                precision: saved.precision,
+            },
+            last_monotime: tokio::time::Instant::now(),
+            restored: true,
+            prev_was_outlier: false,
+            last_iter: saved.last_iter,
+        }));
+        debug!("Restored source filter from previous run");
+    }
+}
+
 #[derive(Debug)]
 pub struct KalmanSourceController<
     D: Debug + Copy + Clone,
@@ -900,6 +1236,7 @@ pub struct KalmanSourceController<
 > {
     index: ClockId,
     state: SourceState<D, N>,
+    outlier_filter: OutlierFilter,
     period: Option<f64>,
     algo_config: AlgorithmConfig,
     source_config: SourceConfig,
@@ -914,7 +1251,7 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
 {
     pub(super) fn new(
         index: ClockId,
-        algo_config: AlgorithmConfig,
+        algo_config: &AlgorithmConfig,
         period: Option<f64>,
         source_config: SourceConfig,
         noise_estimator: N,
@@ -922,15 +1259,47 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         KalmanSourceController {
             index,
             state: SourceState::new(noise_estimator),
+            outlier_filter: OutlierFilter::default(),
             period,
-            algo_config,
+            algo_config: *algo_config,
             source_config,
         }
     }
+
+    fn snapshot(&self) -> Option<SourceSnapshot> {
+        self.state
+            .snapshot(self.index, &self.algo_config, self.period)
+            .map(|snapshot| SourceSnapshot {
+                weight: self.source_config.weight,
+                prefer: self.source_config.prefer,
+                trust: self.source_config.trust,
+                ..snapshot
+            })
+    }
+
+    fn is_outlier(&mut self, measurement: &InternalMeasurement<D>) -> bool {
+        let Some(snapshot) = self.snapshot() else {
+            return false;
+        };
+
+        let predicted = snapshot.state.offset()
+            + snapshot.state.frequency()
+                * (measurement.localtime - snapshot.state.time).to_seconds();
+        let mut residual = measurement.offset.to_seconds() - predicted;
+        if let Some(period) = self.period {
+            residual -= period * (residual / period).round();
+        }
+
+        self.outlier_filter.is_outlier(
+            &self.algo_config,
+            residual,
+            self.state.measurement_noise().sqrt(),
+        )
+    }
 }
 
 impl<
-    D: Debug + Copy + Clone + Send + 'static,
+    D: Debug + Copy + Clone + Default + Send + 'static,
     N: MeasurementNoiseEstimator<MeasurementDelay = D> + Clone + Send + 'static,
 > InternalSourceController for KalmanSourceController<D, N>
 {
@@ -949,18 +1318,29 @@ impl<
         }
     }
 
+    fn handle_remote_step(&mut self, step: NtpDuration) {
+        // A step of the remote clock shifts the offset just like a step of
+        // our own clock in the opposite direction does
+        self.state
+            .process_offset_steering(-step.to_seconds(), self.period);
+    }
+
     fn handle_measurement(
         &mut self,
         measurement: InternalMeasurement<Self::MeasurementDelay>,
     ) -> Option<Self::SourceMessage> {
+        if self.is_outlier(&measurement) {
+            debug!(offset = ?measurement.offset, "Rejected outlying measurement");
+            return None;
+        }
+
         if self.state.update_self_using_measurement(
             &self.source_config,
             &self.algo_config,
             measurement,
             self.period,
         ) {
-            self.state
-                .snapshot(self.index, &self.algo_config, self.period)
+            self.snapshot()
                 .map(|snapshot| KalmanSourceMessage { inner: snapshot })
         } else {
             None
@@ -972,20 +1352,44 @@ impl<
             .get_desired_poll(&self.source_config.poll_interval_limits)
     }
 
-    fn observe(&self) -> super::super::ObservableSourceTimedata {
+    fn save_state(&self) -> Option<SavedSourceState> {
+        self.state.save().map(SavedSourceState::Kalman)
+    }
+
+    fn restore_state(&mut self, state: &SavedSourceState) {
+        let SavedSourceState::Kalman(state) = state;
         self.state
-            .snapshot(self.index, &self.algo_config, self.period)
-            .map_or(
-                ObservableSourceTimedata {
-                    offset: NtpDuration::ZERO,
-                    uncertainty: NtpDuration::MAX,
-                    delay: NtpDuration::MAX,
-                    remote_delay: NtpDuration::MAX,
-                    remote_uncertainty: NtpDuration::MAX,
-                    last_update: NtpTimestamp::default(),
-                },
-                |snapshot| snapshot.observe(),
-            )
+            .restore(state, &self.source_config.poll_interval_limits);
+    }
+
+    fn clock_discontinuity(&mut self) {
+        self.state.reset();
+        self.outlier_filter.clear();
+    }
+
+    fn observe(&self) -> super::super::ObservableSourceTimedata {
+        self.snapshot().map_or(
+            ObservableSourceTimedata {
+                offset: NtpDuration::ZERO,
+                uncertainty: NtpDuration::MAX,
+                delay: NtpDuration::MAX,
+                remote_delay: NtpDuration::MAX,
+                remote_uncertainty: NtpDuration::MAX,
+                last_update: NtpTimestamp::default(),
+                kalman: None,
+            },
+            |snapshot| {
+                let (poll_score, wander_poll_interval) = self.state.poll_reasoning();
+                snapshot.observe(ObservableKalmanState {
+                    measurement_noise: self.state.measurement_noise(),
+                    poll_score,
+                    wander_poll_interval,
+                    rejected_outliers: self.outlier_filter.rejected(),
+                    frequency_steps: self.state.frequency_steps(),
+                    ..Default::default()
+                })
+            },
+        )
     }
 }
 
@@ -1001,6 +1405,83 @@ mod tests {
 
     use super::*;
 
+    #[tokio::test(start_paused = true)]
+    #[cfg(feature = "serde")]
+    async fn test_save_restore() {
+        let base = NtpTimestamp::from_fixed_int(0);
+
+        let source = SourceState(SourceStateInner::Stable(SourceFilter {
+            state: KalmanState {
+                state: Vector::new_vector([20e-3, 1e-6]),
+                uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+                time: base,
+            },
+            clock_wander: 1e-8,
+            noise_estimator: AveragingBuffer {
+                data: [0.0, 0.0, 0.0, 0.0, 0.875e-6, 0.875e-6, 0.875e-6, 0.875e-6],
+                next_idx: 3,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
+            desired_poll_interval: PollInterval::from_byte(8),
+            last_monotime: Instant::now(),
+            last_measurement: InternalMeasurement {
+                delay: NtpDuration::from_seconds(0.0),
+                offset: NtpDuration::from_seconds(20e-3),
+                localtime: base,
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            },
+            restored: false,
+            prev_was_outlier: false,
+            last_iter: base,
+        }));
+        let saved: SavedKalmanSourceState =
+            serde_json::from_str(&serde_json::to_string(&source.save().unwrap()).unwrap()).unwrap();
+
+        let mut restored = SourceState::new(AveragingBuffer::default());
+        assert!(restored.save().is_none());
+        restored.restore(&saved, &PollIntervalLimits::default());
+        let SourceState(SourceStateInner::Stable(filter)) = &restored else {
+            panic!("Restored filter should be stable");
+        };
+        assert_eq!(filter.state.offset(), 20e-3);
+        assert_eq!(filter.state.frequency(), 1e-6);
+        assert!((filter.noise_estimator.mean() - 0.4375e-6).abs() < 1e-12);
+        assert_eq!(filter.desired_poll_interval, PollInterval::from_byte(8));
+
+        // The daemon was down for a while, which is not clock meddling
+        tokio::time::sleep(std::time::Duration::from_secs(16)).await;
+        restored.update_self_using_measurement(
+            &SourceConfig::default(),
+            &AlgorithmConfig::default(),
+            InternalMeasurement {
+                delay: NtpDuration::from_seconds(0.0),
+                offset: NtpDuration::from_seconds(20e-3 + 1e-3),
+                localtime: base + NtpDuration::from_seconds(1000.0),
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            },
+            None,
+        );
+        assert!(matches!(
+            restored,
+            SourceState(SourceStateInner::Stable(SourceFilter {
+                restored: false,
+                ..
+            }))
+        ));
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_meddling_detection() {
         let base = NtpTimestamp::from_fixed_int(0);
@@ -1018,6 +1499,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1030,6 +1514,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1064,6 +1549,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1076,6 +1564,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1111,6 +1600,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1123,6 +1615,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1146,6 +1639,112 @@ mod tests {
         assert!(matches!(source, SourceState(SourceStateInner::Stable(_))));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_divergence_reset() {
+        let base = NtpTimestamp::from_fixed_int(0);
+
+        let new_source = || {
+            SourceState(SourceStateInner::Stable(SourceFilter {
+                state: KalmanState {
+                    state: Vector::new_vector([0., 0.]),
+                    uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-12]]),
+                    time: base,
+                },
+                clock_wander: 1e-16,
+                noise_estimator: FixedMeasurementNoise {
+                    precision: 1e-6,
+                    accuracy: 0.0,
+                },
+                precision_score: 0,
+                poll_score: 0,
+                divergence_score: 0,
+                frequency_step_score: [0.0; 2],
+                frequency_steps: 0,
+                desired_poll_interval: PollIntervalLimits::default().min,
+                last_monotime: Instant::now(),
+                last_measurement: InternalMeasurement {
+                    delay: (),
+                    offset: NtpDuration::from_seconds(0.0),
+                    localtime: base,
+
+                    root_delay: NtpDuration::default(),
+                    root_dispersion: NtpDuration::default(),
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: 0,
+                },
+                restored: false,
+                prev_was_outlier: false,
+                last_iter: base,
+            }))
+        };
+        let measurement = |i: usize, offset: f64| InternalMeasurement {
+            delay: (),
+            offset: NtpDuration::from_seconds(offset),
+            localtime: base + NtpDuration::from_seconds(16.0 * i as f64),
+
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        // A source consistent with the filter is left alone
+        let mut source = new_source();
+        for i in 1..=20 {
+            tokio::time::sleep(std::time::Duration::from_secs(16)).await;
+            source.update_self_using_measurement(
+                &SourceConfig::default(),
+                &AlgorithmConfig::default(),
+                measurement(i, 0.0),
+                None,
+            );
+            assert!(matches!(source, SourceState(SourceStateInner::Stable(_))));
+        }
+
+        // A persistent jump in offset leads to reinitialization
+        let mut source = new_source();
+        let algo_config = AlgorithmConfig::default();
+        for i in 1..algo_config.divergence_count {
+            tokio::time::sleep(std::time::Duration::from_secs(16)).await;
+            source.update_self_using_measurement(
+                &SourceConfig::default(),
+                &algo_config,
+                measurement(i, 0.1),
+                None,
+            );
+            assert!(matches!(source, SourceState(SourceStateInner::Stable(_))));
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(16)).await;
+        source.update_self_using_measurement(
+            &SourceConfig::default(),
+            &algo_config,
+            measurement(algo_config.divergence_count, 0.1),
+            None,
+        );
+        let SourceState(SourceStateInner::Initial(filter)) = &source else {
+            panic!("Expected source filter to be reinitialized");
+        };
+        assert_eq!(filter.samples, 1);
+        assert!((filter.cur_avg() - 0.1).abs() < 1e-9);
+
+        // Unless the reset is disabled
+        let mut source = new_source();
+        let algo_config = AlgorithmConfig {
+            divergence_count: 0,
+            ..Default::default()
+        };
+        for i in 1..=20 {
+            tokio::time::sleep(std::time::Duration::from_secs(16)).await;
+            source.update_self_using_measurement(
+                &SourceConfig::default(),
+                &algo_config,
+                measurement(i, 0.1),
+                None,
+            );
+            assert!(matches!(source, SourceState(SourceStateInner::Stable(_))));
+        }
+    }
+
     async fn test_offset_steering_and_measurements<
         D: Debug + Clone + Copy,
         N: MeasurementNoiseEstimator<MeasurementDelay = D> + Clone,
@@ -1164,6 +1763,9 @@ mod tests {
             noise_estimator: noise_estimator.clone(),
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1176,6 +1778,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1201,6 +1804,9 @@ mod tests {
             noise_estimator: noise_estimator.clone(),
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1213,6 +1819,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1277,6 +1884,9 @@ mod tests {
             noise_estimator: noise_estimator.clone(),
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1289,6 +1899,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1384,6 +1995,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1396,6 +2010,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1447,6 +2062,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1459,6 +2077,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -1712,6 +2331,9 @@ mod tests {
             noise_estimator: noise_estimator.clone(),
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1724,6 +2346,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         };
@@ -1747,6 +2370,9 @@ mod tests {
             noise_estimator: noise_estimator.clone(),
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -1759,6 +2385,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         }));
@@ -2250,6 +2877,7 @@ mod tests {
         let config = SourceConfig::default();
         let algo_config = AlgorithmConfig {
             poll_interval_hysteresis: 2,
+            poll_interval_wander_factor: 0.0,
             ..Default::default()
         };
 
@@ -2267,6 +2895,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -2279,6 +2910,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         };
@@ -2370,6 +3002,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_poll_wander_interval() {
+        let config = SourceConfig::default();
+        let algo_config = AlgorithmConfig {
+            poll_interval_hysteresis: 2,
+            ..Default::default()
+        };
+
+        let base = NtpTimestamp::from_fixed_int(0);
+        let mut source = SourceFilter {
+            state: KalmanState {
+                state: Vector::new_vector([0.0, 0.]),
+                uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+                time: base,
+            },
+            clock_wander: 1e-8,
+            noise_estimator: AveragingBuffer {
+                data: [0.0, 0.0, 0.0, 0.0, 2e-3, 2e-3, 2e-3, 2e-3],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
+            desired_poll_interval: PollInterval::from_byte(6),
+            last_monotime: Instant::now(),
+            last_measurement: InternalMeasurement {
+                delay: NtpDuration::from_seconds(0.0),
+                offset: NtpDuration::from_seconds(0.0),
+                localtime: base,
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            },
+            restored: false,
+            prev_was_outlier: false,
+            last_iter: base,
+        };
+        let middle_weight =
+            (algo_config.poll_interval_high_weight + algo_config.poll_interval_low_weight) / 2.;
+
+        // The noise estimate of 2.9e-7 s^2 with this wander calls for about
+        // 4.4 s
+        let interval = source.wander_poll_interval().unwrap();
+        assert!((interval - 4.41).abs() < 0.01);
+
+        // 64 s is more than 8 times that, so even without a push from the
+        // weights the poll interval goes down
+        let baseinterval = source.desired_poll_interval.as_duration().to_seconds();
+        source.update_desired_poll(&config, &algo_config, 1.0, middle_weight, baseinterval);
+        assert_eq!(source.poll_score, 1);
+        source.update_desired_poll(&config, &algo_config, 1.0, middle_weight, baseinterval);
+        assert_eq!(source.poll_score, 0);
+        assert_eq!(source.desired_poll_interval, PollInterval::from_byte(5));
+
+        // But not when we already get fewer measurements than we want
+        let baseinterval = source.desired_poll_interval.as_duration().to_seconds();
+        source.update_desired_poll(&config, &algo_config, 1.0, middle_weight, baseinterval * 2.);
+        assert_eq!(source.poll_score, 0);
+
+        // A much more stable clock makes the poll interval go up instead
+        source.clock_wander = 1e-16;
+        assert!((source.wander_poll_interval().unwrap() - 2047.0).abs() < 1.0);
+        source.update_desired_poll(&config, &algo_config, 1.0, middle_weight, baseinterval);
+        assert_eq!(source.poll_score, -1);
+        source.update_desired_poll(&config, &algo_config, 1.0, middle_weight, baseinterval);
+        assert_eq!(source.poll_score, 0);
+        assert_eq!(source.desired_poll_interval, PollInterval::from_byte(6));
+
+        // Within the factor the weights decide alone
+        source.clock_wander = 3e-12;
+        source.update_desired_poll(&config, &algo_config, 1.0, middle_weight, baseinterval * 2.);
+        assert_eq!(source.poll_score, 0);
+        assert_eq!(source.desired_poll_interval, PollInterval::from_byte(6));
+    }
+
     #[test]
     fn test_wander_estimation() {
         let algo_config = AlgorithmConfig {
@@ -2391,6 +3102,9 @@ mod tests {
             },
             precision_score: 0,
             poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
             desired_poll_interval: PollIntervalLimits::default().min,
             last_monotime: Instant::now(),
             last_measurement: InternalMeasurement {
@@ -2403,6 +3117,7 @@ mod tests {
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
             },
+            restored: false,
             prev_was_outlier: false,
             last_iter: base,
         };
@@ -2443,4 +3158,240 @@ mod tests {
         assert_eq!(source.precision_score, 0);
         assert!((source.clock_wander - 1e-8).abs() < 1e-12);
     }
+
+    #[test]
+    fn test_frequency_step_detection() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let new_source = || SourceFilter {
+            state: KalmanState {
+                state: Vector::new_vector([0.0, 0.0]),
+                uncertainty: Matrix::new([[1e-12, 0.0], [0.0, 1e-16]]),
+                time: base,
+            },
+            clock_wander: 1e-16,
+            noise_estimator: FixedMeasurementNoise {
+                precision: 1e-10,
+                accuracy: 0.0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
+            desired_poll_interval: PollIntervalLimits::default().max,
+            last_monotime: Instant::now(),
+            last_measurement: InternalMeasurement {
+                delay: (),
+                offset: NtpDuration::from_seconds(0.0),
+                localtime: base,
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            },
+            restored: false,
+            prev_was_outlier: false,
+            last_iter: base,
+        };
+        let measurement = |i: i32, frequency: f64| {
+            let time = 16.0 * f64::from(i);
+            InternalMeasurement {
+                delay: (),
+                offset: NtpDuration::from_seconds(frequency * time),
+                localtime: base + NtpDuration::from_seconds(time),
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            }
+        };
+        let algo_config = AlgorithmConfig::default();
+
+        // Measurements in line with the filter state don't trigger detection
+        let mut source = new_source();
+        for i in 1..=20 {
+            source.update(
+                &SourceConfig::default(),
+                &algo_config,
+                measurement(i, 0.0),
+                None,
+            );
+        }
+        assert_eq!(source.frequency_steps, 0);
+        assert_eq!(
+            source.desired_poll_interval,
+            PollIntervalLimits::default().max
+        );
+
+        // A sudden 10ppm frequency difference does, after a couple of
+        // measurements
+        let mut source = new_source();
+        for i in 1..=6 {
+            source.update(
+                &SourceConfig::default(),
+                &algo_config,
+                measurement(i, 10e-6),
+                None,
+            );
+            assert_eq!(source.frequency_steps, 0);
+        }
+        source.update(
+            &SourceConfig::default(),
+            &algo_config,
+            measurement(7, 10e-6),
+            None,
+        );
+        assert_eq!(source.frequency_steps, 1);
+        assert_eq!(source.frequency_step_score, [0.0; 2]);
+        assert!(source.state.frequency_variance() >= sqr(algo_config.frequency_step_uncertainty));
+        assert_eq!(
+            source.desired_poll_interval,
+            PollIntervalLimits::default().min
+        );
+
+        // Unless the detection is disabled
+        let algo_config = AlgorithmConfig {
+            frequency_step_threshold: 0.0,
+            ..Default::default()
+        };
+        let mut source = new_source();
+        for i in 1..=20 {
+            source.update(
+                &SourceConfig::default(),
+                &algo_config,
+                measurement(i, 10e-6),
+                None,
+            );
+        }
+        assert_eq!(source.frequency_steps, 0);
+    }
+
+    #[test]
+    fn test_remote_step() {
+        let mut controller = TwoWayKalmanSourceController::new(
+            ClockId(0),
+            &AlgorithmConfig::default(),
+            None,
+            SourceConfig::default(),
+            AveragingBuffer::default(),
+        );
+        controller.handle_measurement(InternalMeasurement {
+            delay: NtpDuration::from_seconds(1e-3),
+            offset: NtpDuration::from_seconds(4e-3),
+            localtime: NtpTimestamp::from_fixed_int(0),
+
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        });
+
+        // A step of the server clock moves the offset along with it
+        controller.handle_remote_step(NtpDuration::from_seconds(2.0));
+        let offset = controller.snapshot().unwrap().state.offset();
+        assert!((offset - 2.004).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_observe_kalman_state() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let noise_estimator = AveragingBuffer {
+            data: [0.0, 0.0, 0.0, 0.0, 1e-3, 1e-3, 1e-3, 1e-3],
+            next_idx: 0,
+        };
+        let mut controller = TwoWayKalmanSourceController::new(
+            ClockId(0),
+            &AlgorithmConfig::default(),
+            None,
+            SourceConfig::default(),
+            AveragingBuffer::default(),
+        );
+        assert!(controller.observe().kalman.is_none());
+
+        controller.state = SourceState(SourceStateInner::Stable(SourceFilter {
+            state: KalmanState {
+                state: Vector::new_vector([20e-3, 1e-6]),
+                uncertainty: Matrix::new([[1e-6, 1e-9], [1e-9, 1e-8]]),
+                time: base,
+            },
+            clock_wander: 1e-8,
+            noise_estimator: noise_estimator.clone(),
+            precision_score: 0,
+            poll_score: 0,
+            divergence_score: 0,
+            frequency_step_score: [0.0; 2],
+            frequency_steps: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_monotime: Instant::now(),
+            last_measurement: InternalMeasurement {
+                delay: NtpDuration::from_seconds(0.0),
+                offset: NtpDuration::from_seconds(20e-3),
+                localtime: base,
+
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+            },
+            restored: false,
+            prev_was_outlier: false,
+            last_iter: base,
+        }));
+
+        let kalman = controller.observe().kalman.unwrap();
+        assert_eq!(kalman.state, [20e-3, 1e-6]);
+        assert_eq!(kalman.covariance, [[1e-6, 1e-9], [1e-9, 1e-8]]);
+        assert_eq!(
+            kalman.measurement_noise,
+            noise_estimator.get_noise_estimate()
+        );
+        assert_eq!(kalman.wander, 1e-8);
+        assert_eq!(kalman.rejected_outliers, 0);
+    }
+
+    #[test]
+    fn test_outlier_rejection() {
+        let mut controller = TwoWayKalmanSourceController::new(
+            ClockId(0),
+            &AlgorithmConfig {
+                outlier_window: 8,
+                ..Default::default()
+            },
+            None,
+            SourceConfig::default(),
+            AveragingBuffer::default(),
+        );
+        let measurement = |i: i32, offset: f64| InternalMeasurement {
+            delay: NtpDuration::from_seconds(if i % 2 == 0 { 1e-3 } else { 3e-3 }),
+            offset: NtpDuration::from_seconds(offset),
+            localtime: NtpTimestamp::from_fixed_int(0) + NtpDuration::from_seconds(f64::from(i)),
+
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        for i in 0..16 {
+            assert!(controller.handle_measurement(measurement(i, 0.0)).is_some());
+        }
+
+        // A single spike doesn't make it to the filter
+        let offset = controller.snapshot().unwrap().state.offset();
+        assert!(
+            controller
+                .handle_measurement(measurement(16, 0.2))
+                .is_none()
+        );
+        assert_eq!(controller.snapshot().unwrap().state.offset(), offset);
+        assert_eq!(controller.observe().kalman.unwrap().rejected_outliers, 1);
+
+        assert!(
+            controller
+                .handle_measurement(measurement(17, 0.0))
+                .is_some()
+        );
+    }
 }
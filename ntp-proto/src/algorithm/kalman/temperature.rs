@@ -0,0 +1,110 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::config::AlgorithmConfig;
+
+/// Linear model of the frequency correction the clock needs as a function of
+/// its temperature.
+///
+/// Every clock update contributes a sample of the temperature at that moment
+/// and the frequency correction the filter settled on. The model is an
+/// exponentially weighted least squares fit through these samples, so that
+/// it follows the slow aging of the oscillator. Its slope is only trusted
+/// once the samples span a reasonable range of temperatures, as the fit is
+/// meaningless without variation in temperature.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(super) struct TemperatureModel {
+    weight: f64,
+    mean_temperature: f64,
+    mean_frequency: f64,
+    temperature_variance: f64,
+    covariance: f64,
+}
+
+impl TemperatureModel {
+    /// Add a sample of the frequency correction in use at `temperature`
+    pub(super) fn add_sample(
+        &mut self,
+        config: &AlgorithmConfig,
+        temperature: f64,
+        frequency: f64,
+    ) {
+        let decay = 1.0 - 1.0 / config.temperature_model_samples.max(1) as f64;
+        self.weight = self.weight * decay + 1.0;
+
+        let temperature_delta = temperature - self.mean_temperature;
+        let frequency_delta = frequency - self.mean_frequency;
+        self.mean_temperature += temperature_delta / self.weight;
+        self.mean_frequency += frequency_delta / self.weight;
+
+        self.temperature_variance = self.temperature_variance * decay
+            + temperature_delta * (temperature - self.mean_temperature);
+        self.covariance =
+            self.covariance * decay + temperature_delta * (frequency - self.mean_frequency);
+    }
+
+    /// Change in frequency correction per degree, if the samples allow
+    /// estimating it
+    pub(super) fn coefficient(&self, config: &AlgorithmConfig) -> Option<f64> {
+        if self.weight < 2.0
+            || self.temperature_variance / self.weight < config.temperature_minimum_spread.powi(2)
+            || self.temperature_variance <= 0.0
+        {
+            return None;
+        }
+
+        Some((self.covariance / self.temperature_variance).clamp(
+            -config.temperature_maximum_coefficient,
+            config.temperature_maximum_coefficient,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear() {
+        let config = AlgorithmConfig::default();
+        let mut model = TemperatureModel::default();
+
+        // Half a ppm per degree around 20 degrees, following a daily cycle
+        for i in 0..1000 {
+            let temperature = 20.0 + 5.0 * (f64::from(i) / 100.0).sin();
+            model.add_sample(&config, temperature, 3e-6 + 0.5e-6 * (temperature - 20.0));
+        }
+
+        let coefficient = model.coefficient(&config).unwrap();
+        assert!((coefficient - 0.5e-6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_insufficient_spread() {
+        let config = AlgorithmConfig::default();
+        let mut model = TemperatureModel::default();
+        assert_eq!(model.coefficient(&config), None);
+
+        for i in 0..1000 {
+            let temperature = 20.0 + 0.1 * (f64::from(i) / 100.0).sin();
+            model.add_sample(&config, temperature, 0.5e-6 * temperature);
+        }
+        assert_eq!(model.coefficient(&config), None);
+    }
+
+    #[test]
+    fn test_clamped() {
+        let config = AlgorithmConfig::default();
+        let mut model = TemperatureModel::default();
+
+        for i in 0..1000 {
+            let temperature = 20.0 + 5.0 * (f64::from(i) / 100.0).sin();
+            model.add_sample(&config, temperature, 10e-6 * temperature);
+        }
+        assert_eq!(
+            model.coefficient(&config),
+            Some(config.temperature_maximum_coefficient)
+        );
+    }
+}
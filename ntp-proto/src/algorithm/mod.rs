@@ -1,11 +1,14 @@
 use std::{
+    collections::VecDeque,
     fmt::Debug,
     pin::Pin,
     sync::{Arc, Mutex, Weak},
     time::Duration,
 };
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{error, info, warn};
 
 use crate::{
     ClockId, NtpLeapIndicator, PollInterval,
@@ -15,7 +18,8 @@ use crate::{
     time_types::{NtpDuration, NtpTimestamp},
 };
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct ObservableSourceTimedata {
     pub offset: NtpDuration,
     pub uncertainty: NtpDuration,
@@ -25,6 +29,134 @@ pub struct ObservableSourceTimedata {
     pub remote_uncertainty: NtpDuration,
 
     pub last_update: NtpTimestamp,
+
+    /// Internals of the Kalman filter tracking the source, if that is the
+    /// algorithm in use
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub kalman: Option<ObservableKalmanState>,
+}
+
+impl ObservableSourceTimedata {
+    /// Estimated maximum error of the time of the source with respect to the
+    /// root of its synchronization tree, as seen from the local clock
+    pub fn root_distance(&self) -> NtpDuration {
+        NtpDuration::from_seconds(root_distance(
+            self.remote_delay.to_seconds(),
+            self.remote_uncertainty.to_seconds(),
+            self.delay.to_seconds(),
+            self.uncertainty.to_seconds(),
+        ))
+    }
+}
+
+/// Root distance (in seconds) of a source, in the spirit of RFC 5905: half of
+/// the total round trip delay to the root plus the total dispersion
+pub(crate) fn root_distance(
+    root_delay: f64,
+    root_dispersion: f64,
+    delay: f64,
+    uncertainty: f64,
+) -> f64 {
+    (root_delay + delay) / 2.0 + root_dispersion + uncertainty
+}
+
+/// The state of the Kalman filter of a source
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ObservableKalmanState {
+    /// Estimated offset (in seconds) and frequency difference (in seconds
+    /// per second) between the source and the local clock
+    pub state: [f64; 2],
+    /// Covariance matrix of the estimated state
+    pub covariance: [[f64; 2]; 2],
+    /// Estimated variance of the offset measurements (in seconds squared)
+    pub measurement_noise: f64,
+    /// Estimated rate at which the variance of the frequency difference
+    /// grows over time (per second)
+    pub wander: f64,
+    /// Number of measurements rejected as outliers before they reached
+    /// the filter
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rejected_outliers: u64,
+    /// Number of sudden changes in frequency detected since the filter was
+    /// initialized
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frequency_steps: u64,
+    /// Progress towards changing the poll interval, positive towards polling
+    /// faster and negative towards polling slower
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub poll_score: i32,
+    /// Poll interval (in seconds) suggested by the wander and measurement
+    /// noise, which the poll interval is pushed towards when far from it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wander_poll_interval: Option<f64>,
+}
+
+/// A single raw measurement of a source, as kept in its measurement history
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ObservableMeasurement {
+    /// Local time at which the request was sent (absent for one way sources)
+    pub local_send: Option<NtpTimestamp>,
+    /// Time at which the source received the request (absent for one way sources)
+    pub remote_receive: Option<NtpTimestamp>,
+    /// Time at which the source sent its response or sample
+    pub remote_transmit: NtpTimestamp,
+    /// Local time at which the response or sample was received
+    pub local_receive: NtpTimestamp,
+
+    pub offset: NtpDuration,
+    /// Round trip delay (absent for one way sources)
+    pub delay: Option<NtpDuration>,
+
+    pub root_delay: NtpDuration,
+    pub root_dispersion: NtpDuration,
+    pub stratum: u8,
+    pub leap: NtpLeapIndicator,
+
+    /// Whether the measurement was used by the source filter
+    pub accepted: bool,
+}
+
+/// Bounded history of the most recent measurements of a source
+#[derive(Debug, Clone)]
+struct MeasurementHistory {
+    capacity: usize,
+    entries: VecDeque<ObservableMeasurement>,
+    // Latest measurement, until taken, whatever the capacity
+    latest: Option<ObservableMeasurement>,
+}
+
+impl MeasurementHistory {
+    fn new(capacity: usize) -> Self {
+        MeasurementHistory {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            latest: None,
+        }
+    }
+
+    fn push(&mut self, measurement: ObservableMeasurement) {
+        self.latest = Some(measurement);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(measurement);
+    }
+
+    fn to_vec(&self) -> Vec<ObservableMeasurement> {
+        self.entries.iter().copied().collect()
+    }
+
+    fn take_latest(&mut self) -> Option<ObservableMeasurement> {
+        self.latest.take()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -53,9 +185,20 @@ impl<ControllerMessage> Default for InternalStateUpdate<ControllerMessage> {
     }
 }
 
+/// A clock algorithm driven by messages from its sources, adapted to
+/// [`TimeSyncController`] by [`TimeSyncControllerWrapper`].
+///
+/// The source controllers run in the tasks of their sources and pass a
+/// `SourceMessage` to the clock controller whenever they have something new.
+/// The clock controller can answer all sources at once with a
+/// `ControllerMessage`, for example after stepping the clock. Like
+/// [`TimeSyncController`], this trait is a stable extension point.
 pub trait InternalTimeSyncController: Sized + Send + 'static {
     type Clock: NtpClock;
+    #[cfg(feature = "serde")]
     type AlgorithmConfig: Debug + Copy + DeserializeOwned + Send;
+    #[cfg(not(feature = "serde"))]
+    type AlgorithmConfig: Debug + Copy + Send;
     type ControllerMessage: Debug + Clone + Send + 'static;
     type SourceMessage: Debug + Clone + Send + 'static;
     type NtpSourceController: InternalSourceController<
@@ -106,8 +249,35 @@ pub trait InternalTimeSyncController: Sized + Send + 'static {
     ) -> InternalStateUpdate<Self::ControllerMessage>;
     /// Non-message driven update (queued via next_update)
     fn time_update(&mut self) -> InternalStateUpdate<Self::ControllerMessage>;
+    /// Notify the controller of a new reading of the temperature of the
+    /// clock, in degrees Celsius. Algorithms that don't compensate for
+    /// temperature can ignore this. The `next_update` of the returned update
+    /// is not used.
+    fn temperature_update(
+        &mut self,
+        _temperature: f64,
+    ) -> InternalStateUpdate<Self::ControllerMessage> {
+        InternalStateUpdate::default()
+    }
+    /// Internal state to keep across a restart of the daemon, if the
+    /// algorithm supports that
+    fn save_state(&self) -> Option<SavedControllerState> {
+        None
+    }
+    /// Continue from the state saved by a previous run of the daemon, before
+    /// any sources are added. Returns whether the state could be used.
+    fn restore_state(&mut self, _state: &SavedControllerState) -> bool {
+        false
+    }
+    /// The clock jumped without the controller stepping it, for example
+    /// because the system was suspended or the time was set by hand. The
+    /// sources are reset separately.
+    fn clock_discontinuity(&mut self) {}
 }
 
+/// The per-source half of an [`InternalTimeSyncController`], which gets the
+/// offset and delay of every measurement of its source and decides what to
+/// pass on to the clock controller.
 pub trait InternalSourceController: Sized + Send + 'static {
     type ControllerMessage: Debug + Clone + Send + 'static;
     type SourceMessage: Debug + Clone + Send + 'static;
@@ -120,16 +290,171 @@ pub trait InternalSourceController: Sized + Send + 'static {
         measurement: InternalMeasurement<Self::MeasurementDelay>,
     ) -> Option<Self::SourceMessage>;
 
+    /// Notify the controller that the remote clock stepped by `step` since
+    /// the previous measurement
+    fn handle_remote_step(&mut self, _step: NtpDuration) {}
+
     fn desired_poll_interval(&self) -> PollInterval;
 
     fn observe(&self) -> ObservableSourceTimedata;
+
+    /// Internal state to keep across a restart of the daemon, if there is
+    /// any worth keeping
+    fn save_state(&self) -> Option<SavedSourceState> {
+        None
+    }
+
+    /// Continue from the state saved by a previous run of the daemon
+    fn restore_state(&mut self, _state: &SavedSourceState) {}
+
+    /// Forget the measurements taken so far, as the clock jumped since
+    fn clock_discontinuity(&mut self) {}
+}
+
+/// Check a clock step of `change` seconds against the panic thresholds in
+/// `config`, and account for it in the accumulated steps. Exits the daemon
+/// when a threshold is exceeded.
+fn check_offset_steer(
+    config: &SynchronizationConfig,
+    timedata: &mut TimeSnapshot,
+    in_startup: bool,
+    change: f64,
+) {
+    let change = NtpDuration::from_seconds(change);
+    let within_thresholds = if in_startup {
+        config.startup_step_panic_threshold.is_within(change)
+    } else {
+        timedata.accumulated_steps += change.abs();
+        config.single_step_panic_threshold.is_within(change)
+            && config
+                .accumulated_step_panic_threshold
+                .is_none_or(|v| timedata.accumulated_steps <= v)
+    };
+
+    if !within_thresholds {
+        error!(
+            "Unusually large clock step suggested, please manually verify system clock and reference clock state and restart if appropriate. If the clock is significantly wrong, you can use `ntp-ctl force-sync` to correct it."
+        );
+        exit_on_panic();
+    }
+}
+
+/// Decide whether an offset of `change` seconds beyond the step threshold is
+/// corrected by stepping the clock, counting the steps after startup in
+/// `steps`. Once the step limit is reached the offset is to be slewed, unless
+/// it exceeds the maximum slew offset, which exits the daemon.
+fn step_allowed(
+    config: &SynchronizationConfig,
+    in_startup: bool,
+    steps: &mut u32,
+    change: f64,
+) -> bool {
+    if in_startup {
+        return true;
+    }
+
+    if config.step_limit.is_none_or(|limit| *steps < limit) {
+        *steps += 1;
+        return true;
+    }
+
+    if config
+        .maximum_slew_offset
+        .is_some_and(|maximum| change.abs() > maximum.to_seconds())
+    {
+        error!(
+            "Clock is off by {}ms, which is too much to slew while no more steps are allowed. Please manually verify system clock and reference clock state and restart if appropriate. If the clock is significantly wrong, you can use `ntp-ctl force-sync` to correct it.",
+            change * 1e3
+        );
+        exit_on_panic();
+    }
+
+    info!("Step limit reached, slewing {}ms instead", change * 1e3);
+    false
+}
+
+/// Clamp a frequency `value` (s/s) to the configured `limit` (ppm), if any,
+/// with a warning when the limit starts to apply. `active` tracks whether the
+/// limit applied to the previous value, so a lasting clamp is reported once.
+fn clamp_frequency(value: f64, limit: Option<f64>, active: &mut bool, description: &str) -> f64 {
+    let Some(limit) = limit.map(|ppm| ppm * 1e-6) else {
+        return value;
+    };
+
+    let clamped = value.clamp(-limit, limit);
+    let hit = clamped != value;
+    if hit && !*active {
+        warn!(
+            requested_ppm = value * 1e6,
+            limit_ppm = limit * 1e6,
+            "{description} limited by the configured maximum"
+        );
+    }
+    *active = hit;
+    clamped
+}
+
+fn exit_on_panic() -> ! {
+    #[cfg(not(test))]
+    std::process::exit(crate::exitcode::PANIC);
+    #[cfg(test)]
+    panic!("Threshold exceeded");
+}
+
+/// Time until the holdover after the last update of the clock, described by
+/// `timedata`, ends. None when holdover is not configured or the clock is not
+/// considered synchronized.
+fn holdover_remaining(
+    config: &SynchronizationConfig,
+    timedata: &TimeSnapshot,
+    now: NtpTimestamp,
+) -> Option<Duration> {
+    let holdover = config.holdover?;
+    if timedata.leap_indicator == NtpLeapIndicator::Unknown {
+        return None;
+    }
+
+    let end = timedata.root_variance_base_time + NtpDuration::from_seconds(holdover.duration);
+    Some(Duration::from_secs_f64((end - now).to_seconds().max(0.0)))
 }
 
+/// Mark the clock unsynchronized when the holdover has ended at `now`
+fn check_holdover<C: NtpClock>(
+    config: &SynchronizationConfig,
+    clock: &C,
+    timedata: &mut TimeSnapshot,
+    now: NtpTimestamp,
+) {
+    if holdover_remaining(config, timedata, now).is_some_and(|remaining| remaining.is_zero()) {
+        warn!("Holdover ended without an update of the clock, marking it unsynchronized");
+        clock
+            .status_update(NtpLeapIndicator::Unknown)
+            .expect("Cannot update clock");
+        timedata.leap_indicator = NtpLeapIndicator::Unknown;
+    }
+}
+
+/// Variance per second squared that is added to the root variance, so the
+/// advertised root dispersion grows at the configured holdover rate
+fn holdover_variance_rate(config: &SynchronizationConfig) -> f64 {
+    config
+        .holdover
+        .map_or(0.0, |holdover| holdover.dispersion_rate.powi(2))
+}
+
+mod asymmetry;
 mod kalman;
+mod pi;
+
+use asymmetry::AsymmetryCorrection;
 
 pub use kalman::{
     KalmanClockController, KalmanControllerMessage, KalmanSourceController, KalmanSourceMessage,
-    TwoWayKalmanSourceController, config::AlgorithmConfig,
+    SavedKalmanSourceState, SavedKalmanState, TwoWayKalmanSourceController,
+    config::{AlgorithmConfig, CombineMode},
+};
+pub use pi::{
+    PiAlgorithmConfig, PiClockController, PiControllerMessage, PiSourceController, PiSourceMessage,
 };
 
 #[derive(Debug, Copy, Clone)]
@@ -153,13 +478,44 @@ pub struct Measurement {
 
     pub root_delay: NtpDuration,
     pub root_dispersion: NtpDuration,
+    pub stratum: u8,
     pub leap: NtpLeapIndicator,
     pub precision: i8,
 }
 
+/// Internal state of a clock algorithm, saved when the daemon shuts down so
+/// that it doesn't have to converge from scratch after a planned restart. The
+/// daemon stores it without looking into it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SavedControllerState {
+    Kalman(SavedKalmanState),
+}
+
+/// The part of the saved state of a clock algorithm that belongs to a single
+/// source
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SavedSourceState {
+    Kalman(SavedKalmanSourceState),
+}
+
+/// The boundary between the daemon and a clock algorithm.
+///
+/// The daemon creates one controller for the system clock, and through it a
+/// [`SourceController`] for every source. The algorithm to use is chosen in
+/// the configuration. This boundary is kept stable so that alternative
+/// algorithms can be developed against it, changes to it are noted in the
+/// changelog. Most algorithms are easier to write as an
+/// [`InternalTimeSyncController`].
 pub trait TimeSyncController: Sized + Send + Sync + 'static {
     type Clock: NtpClock;
+    #[cfg(feature = "serde")]
     type AlgorithmConfig: Debug + Copy + DeserializeOwned + Send;
+    #[cfg(not(feature = "serde"))]
+    type AlgorithmConfig: Debug + Copy + Send;
     type NtpSourceController: SourceController;
     type OneWaySourceController: SourceController;
 
@@ -189,6 +545,24 @@ pub trait TimeSyncController: Sized + Send + Sync + 'static {
     ) -> Self::OneWaySourceController;
     /// Current synchronization state
     fn synchronization_state(&self) -> (TimeSnapshot, Vec<ClockId>);
+    /// Notify the controller of a new reading of the temperature of the
+    /// clock, in degrees Celsius
+    fn temperature_update(&self, _temperature: f64) {}
+    /// Internal state of the algorithm and of its NTP sources to keep across
+    /// a restart of the daemon, if the algorithm supports that
+    fn save_state(&self) -> Option<(SavedControllerState, Vec<(ClockId, SavedSourceState)>)> {
+        None
+    }
+    /// Continue from the state saved by a previous run of the daemon, before
+    /// any sources are added. Returns whether the state could be used.
+    fn restore_state(&self, _state: &SavedControllerState) -> bool {
+        false
+    }
+    /// Notify the controller that the clock jumped without it stepping the
+    /// clock, for example because the system was suspended or the time was
+    /// set by hand. The state of the controller and its sources no longer
+    /// describes the clock, so they start over.
+    fn clock_discontinuity(&self) {}
     /// Run the internal watchdog and messaging.
     fn run(&self) -> impl Future<Output = ()> + Send;
 }
@@ -202,12 +576,53 @@ pub struct TimeSyncControllerWrapper<T: InternalTimeSyncController> {
     messages_for_system_sender:
         tokio::sync::mpsc::UnboundedSender<(ClockId, WrapperMessage<T::SourceMessage>)>,
     oneway_sources: Mutex<Vec<Weak<Mutex<T::OneWaySourceController>>>>,
-    twoway_sources: Mutex<Vec<Weak<Mutex<T::NtpSourceController>>>>,
+    #[expect(clippy::type_complexity)]
+    twoway_sources: Mutex<Vec<(ClockId, Weak<Mutex<T::NtpSourceController>>)>>,
     snapshot: Mutex<TimeSnapshot>,
     used_sources: Mutex<Vec<ClockId>>,
     has_taken_control: Mutex<bool>,
 }
 
+impl<T: InternalTimeSyncController> TimeSyncControllerWrapper<T> {
+    /// Pass the results of an update of the inner controller on to the
+    /// sources and observers, returning when the next update is wanted
+    fn apply_update(&self, update: InternalStateUpdate<T::ControllerMessage>) -> Option<Duration> {
+        if let Some(source_message) = update.source_message {
+            for source in self
+                .oneway_sources
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(Weak::upgrade)
+            {
+                source
+                    .lock()
+                    .unwrap()
+                    .handle_message(source_message.clone());
+            }
+            for source in self
+                .twoway_sources
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(_, source)| source.upgrade())
+            {
+                source
+                    .lock()
+                    .unwrap()
+                    .handle_message(source_message.clone());
+            }
+        }
+        if let Some(time_snapshot) = update.time_snapshot {
+            *self.snapshot.lock().unwrap() = time_snapshot;
+        }
+        if let Some(used_sources) = update.used_sources {
+            *self.used_sources.lock().unwrap() = used_sources;
+        }
+        update.next_update
+    }
+}
+
 impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWrapper<T> {
     type Clock = T::Clock;
     type AlgorithmConfig = T::AlgorithmConfig;
@@ -244,17 +659,22 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
     }
 
     fn add_source(&self, id: ClockId, source_config: SourceConfig) -> Self::NtpSourceController {
+        let history = MeasurementHistory::new(source_config.measurement_history);
         let source_controller = self.inner.lock().unwrap().add_source(id, source_config);
         let wrapper = TwoWaySourceControllerWrapper {
             id,
             inner: Arc::new(Mutex::new(source_controller)),
             last_outgoing_measurement: None,
+            history,
+            asymmetry: AsymmetryCorrection::new(source_config.delay_asymmetry),
+            offset_correction: NtpDuration::from_seconds(source_config.offset_correction),
+            delay_correction: NtpDuration::from_seconds(source_config.delay_correction),
             messages_for_system: self.messages_for_system_sender.clone(),
         };
         self.twoway_sources
             .lock()
             .unwrap()
-            .push(Arc::downgrade(&wrapper.inner));
+            .push((id, Arc::downgrade(&wrapper.inner)));
         wrapper
     }
 
@@ -266,6 +686,7 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
         measurement_accuracy_estimate: f64,
         period: Option<f64>,
     ) -> Self::OneWaySourceController {
+        let history = MeasurementHistory::new(source_config.measurement_history);
         let source_controller = self.inner.lock().unwrap().add_one_way_source(
             id,
             source_config,
@@ -276,6 +697,12 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
         let wrapper = OneWaySourceControllerWrapper {
             id,
             inner: Arc::new(Mutex::new(source_controller)),
+            history,
+            // Without a measured delay, the known delay of the signal can
+            // only be made up for in the offset
+            offset_correction: NtpDuration::from_seconds(
+                source_config.offset_correction + source_config.delay_correction,
+            ),
             messages_for_system: self.messages_for_system_sender.clone(),
         };
         self.oneway_sources
@@ -292,6 +719,42 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
         )
     }
 
+    fn temperature_update(&self, temperature: f64) {
+        let update = self.inner.lock().unwrap().temperature_update(temperature);
+        self.apply_update(update);
+    }
+
+    fn save_state(&self) -> Option<(SavedControllerState, Vec<(ClockId, SavedSourceState)>)> {
+        let state = self.inner.lock().unwrap().save_state()?;
+        let sources = self
+            .twoway_sources
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, source)| Some((*id, source.upgrade()?.lock().unwrap().save_state()?)))
+            .collect();
+        Some((state, sources))
+    }
+
+    fn restore_state(&self, state: &SavedControllerState) -> bool {
+        self.inner.lock().unwrap().restore_state(state)
+    }
+
+    fn clock_discontinuity(&self) {
+        self.inner.lock().unwrap().clock_discontinuity();
+
+        for source in self.oneway_sources.lock().unwrap().iter() {
+            if let Some(source) = source.upgrade() {
+                source.lock().unwrap().clock_discontinuity();
+            }
+        }
+        for (_, source) in self.twoway_sources.lock().unwrap().iter() {
+            if let Some(source) = source.upgrade() {
+                source.lock().unwrap().clock_discontinuity();
+            }
+        }
+    }
+
     async fn run(&self) {
         let mut messages_for_system = self.messages_for_system.lock().unwrap().take().unwrap();
         let mut sleeper = std::pin::pin!(SingleshotSleep::new_disabled());
@@ -301,21 +764,7 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
                     match message {
                         WrapperMessage::SourceMessage(message) => {
                             let update = self.inner.lock().unwrap().source_message(clock_id, message);
-                            if let Some(source_message) = update.source_message {
-                                for source in self.oneway_sources.lock().unwrap().iter().filter_map(Weak::upgrade) {
-                                    source.lock().unwrap().handle_message(source_message.clone());
-                                }
-                                for source in self.twoway_sources.lock().unwrap().iter().filter_map(Weak::upgrade) {
-                                    source.lock().unwrap().handle_message(source_message.clone());
-                                }
-                            }
-                            if let Some(time_snapshot) = update.time_snapshot {
-                                *self.snapshot.lock().unwrap() = time_snapshot;
-                            }
-                            if let Some(used_sources) = update.used_sources {
-                                *self.used_sources.lock().unwrap() = used_sources;
-                            }
-                            if let Some(next_update) = update.next_update {
+                            if let Some(next_update) = self.apply_update(update) {
                                 sleeper.as_mut().reset(tokio::time::Instant::now() + next_update);
                             }
                         },
@@ -329,21 +778,7 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
                 },
                 _ = sleeper.as_mut() => {
                     let update = self.inner.lock().unwrap().time_update();
-                    if let Some(source_message) = update.source_message {
-                        for source in self.oneway_sources.lock().unwrap().iter().filter_map(Weak::upgrade) {
-                            source.lock().unwrap().handle_message(source_message.clone());
-                        }
-                        for source in self.twoway_sources.lock().unwrap().iter().filter_map(Weak::upgrade) {
-                            source.lock().unwrap().handle_message(source_message.clone());
-                        }
-                    }
-                    if let Some(time_snapshot) = update.time_snapshot {
-                        *self.snapshot.lock().unwrap() = time_snapshot;
-                    }
-                    if let Some(used_sources) = update.used_sources {
-                        *self.used_sources.lock().unwrap() = used_sources;
-                    }
-                    if let Some(next_update) = update.next_update {
+                    if let Some(next_update) = self.apply_update(update) {
                         sleeper.as_mut().reset(tokio::time::Instant::now() + next_update);
                     }
                 },
@@ -352,14 +787,34 @@ impl<T: InternalTimeSyncController> TimeSyncController for TimeSyncControllerWra
     }
 }
 
+/// The per-source half of a [`TimeSyncController`], which gets the
+/// timestamps of every exchange with its source.
 pub trait SourceController: Sized + Send + 'static {
     fn handle_measurement(&mut self, measurement: Measurement);
 
     fn set_usable(&mut self, usable: bool);
 
+    /// Notify the controller that the source stepped its clock by `step`
+    /// since the previous measurement, as opposed to its offset changing
+    fn handle_remote_step(&mut self, _step: NtpDuration) {}
+
     fn desired_poll_interval(&self) -> PollInterval;
 
     fn observe(&self) -> ObservableSourceTimedata;
+
+    /// Most recent raw measurements, oldest first
+    fn measurement_history(&self) -> Vec<ObservableMeasurement> {
+        Vec::new()
+    }
+
+    /// The measurement handled last, unless it was taken already
+    fn take_latest_measurement(&mut self) -> Option<ObservableMeasurement> {
+        None
+    }
+
+    /// Continue from the state saved for this source by a previous run of
+    /// the daemon
+    fn restore_state(&mut self, _state: &SavedSourceState) {}
 }
 
 enum WrapperMessage<SourceMessage> {
@@ -371,6 +826,8 @@ enum WrapperMessage<SourceMessage> {
 pub struct OneWaySourceControllerWrapper<T: InternalSourceController<MeasurementDelay = ()>> {
     id: ClockId,
     inner: Arc<Mutex<T>>,
+    history: MeasurementHistory,
+    offset_correction: NtpDuration,
     messages_for_system:
         tokio::sync::mpsc::UnboundedSender<(ClockId, WrapperMessage<T::SourceMessage>)>,
 }
@@ -387,21 +844,37 @@ impl<T: InternalSourceController<MeasurementDelay = ()>> SourceController
     for OneWaySourceControllerWrapper<T>
 {
     fn handle_measurement(&mut self, measurement: Measurement) {
-        if let Some(message) = self
+        // Remote (which is the send timestamp) - local (which is the receive timestamp)
+        let offset = measurement.sender_ts - measurement.receiver_ts + self.offset_correction;
+        let message = self
             .inner
             .lock()
             .unwrap()
             .handle_measurement(InternalMeasurement {
                 delay: (),
-                // Remote (which is the send timestamp) - local (which is the receive timestamp)
-                offset: measurement.sender_ts - measurement.receiver_ts,
+                offset,
                 localtime: measurement.receiver_ts,
                 root_delay: measurement.root_delay,
                 root_dispersion: measurement.root_dispersion,
                 leap: measurement.leap,
                 precision: measurement.precision,
-            })
-        {
+            });
+
+        self.history.push(ObservableMeasurement {
+            local_send: None,
+            remote_receive: None,
+            remote_transmit: measurement.sender_ts,
+            local_receive: measurement.receiver_ts,
+            offset,
+            delay: None,
+            root_delay: measurement.root_delay,
+            root_dispersion: measurement.root_dispersion,
+            stratum: measurement.stratum,
+            leap: measurement.leap,
+            accepted: message.is_some(),
+        });
+
+        if let Some(message) = message {
             self.messages_for_system
                 .send((self.id, WrapperMessage::SourceMessage(message)))
                 .ok();
@@ -421,6 +894,14 @@ impl<T: InternalSourceController<MeasurementDelay = ()>> SourceController
     fn observe(&self) -> ObservableSourceTimedata {
         self.inner.lock().unwrap().observe()
     }
+
+    fn measurement_history(&self) -> Vec<ObservableMeasurement> {
+        self.history.to_vec()
+    }
+
+    fn take_latest_measurement(&mut self) -> Option<ObservableMeasurement> {
+        self.history.take_latest()
+    }
 }
 
 pub struct TwoWaySourceControllerWrapper<
@@ -429,6 +910,10 @@ pub struct TwoWaySourceControllerWrapper<
     id: ClockId,
     inner: Arc<Mutex<T>>,
     last_outgoing_measurement: Option<Measurement>,
+    history: MeasurementHistory,
+    asymmetry: AsymmetryCorrection,
+    offset_correction: NtpDuration,
+    delay_correction: NtpDuration,
     messages_for_system:
         tokio::sync::mpsc::UnboundedSender<(ClockId, WrapperMessage<T::SourceMessage>)>,
 }
@@ -455,23 +940,44 @@ impl<T: InternalSourceController<MeasurementDelay = NtpDuration>> SourceControll
             let Some(last_outgoing) = self.last_outgoing_measurement.take() else {
                 return;
             };
-            if let Some(message) =
-                self.inner
-                    .lock()
-                    .unwrap()
-                    .handle_measurement(InternalMeasurement {
-                        delay: (measurement.receiver_ts - last_outgoing.sender_ts)
-                            - (measurement.sender_ts - last_outgoing.receiver_ts),
-                        offset: ((last_outgoing.receiver_ts - last_outgoing.sender_ts)
-                            + (measurement.sender_ts - measurement.receiver_ts))
-                            / 2,
-                        localtime: measurement.receiver_ts,
-                        root_delay: measurement.root_delay,
-                        root_dispersion: measurement.root_dispersion,
-                        leap: measurement.leap,
-                        precision: measurement.precision,
-                    })
-            {
+            let delay = (measurement.receiver_ts - last_outgoing.sender_ts)
+                - (measurement.sender_ts - last_outgoing.receiver_ts)
+                - self.delay_correction;
+            let offset = self.asymmetry.correct(
+                ((last_outgoing.receiver_ts - last_outgoing.sender_ts)
+                    + (measurement.sender_ts - measurement.receiver_ts))
+                    / 2,
+                delay,
+            ) + self.offset_correction;
+            let message = self
+                .inner
+                .lock()
+                .unwrap()
+                .handle_measurement(InternalMeasurement {
+                    delay,
+                    offset,
+                    localtime: measurement.receiver_ts,
+                    root_delay: measurement.root_delay,
+                    root_dispersion: measurement.root_dispersion,
+                    leap: measurement.leap,
+                    precision: measurement.precision,
+                });
+
+            self.history.push(ObservableMeasurement {
+                local_send: Some(last_outgoing.sender_ts),
+                remote_receive: Some(last_outgoing.receiver_ts),
+                remote_transmit: measurement.sender_ts,
+                local_receive: measurement.receiver_ts,
+                offset,
+                delay: Some(delay),
+                root_delay: measurement.root_delay,
+                root_dispersion: measurement.root_dispersion,
+                stratum: measurement.stratum,
+                leap: measurement.leap,
+                accepted: message.is_some(),
+            });
+
+            if let Some(message) = message {
                 self.messages_for_system
                     .send((self.id, WrapperMessage::SourceMessage(message)))
                     .ok();
@@ -485,6 +991,10 @@ impl<T: InternalSourceController<MeasurementDelay = NtpDuration>> SourceControll
             .ok();
     }
 
+    fn handle_remote_step(&mut self, step: NtpDuration) {
+        self.inner.lock().unwrap().handle_remote_step(step);
+    }
+
     fn desired_poll_interval(&self) -> PollInterval {
         self.inner.lock().unwrap().desired_poll_interval()
     }
@@ -492,6 +1002,18 @@ impl<T: InternalSourceController<MeasurementDelay = NtpDuration>> SourceControll
     fn observe(&self) -> ObservableSourceTimedata {
         self.inner.lock().unwrap().observe()
     }
+
+    fn measurement_history(&self) -> Vec<ObservableMeasurement> {
+        self.history.to_vec()
+    }
+
+    fn take_latest_measurement(&mut self) -> Option<ObservableMeasurement> {
+        self.history.take_latest()
+    }
+
+    fn restore_state(&mut self, state: &SavedSourceState) {
+        self.inner.lock().unwrap().restore_state(state);
+    }
 }
 
 struct SingleshotSleep {
@@ -539,6 +1061,8 @@ impl SingleshotSleep {
 
 #[cfg(test)]
 mod tests {
+    use crate::config::DelayAsymmetry;
+
     use super::*;
 
     struct TestInternalSourceController {
@@ -581,6 +1105,7 @@ mod tests {
             receiver_ts: NtpTimestamp::from_fixed_int(1),
             root_delay: NtpDuration::from_fixed_int(0),
             root_dispersion: NtpDuration::from_fixed_int(0),
+            stratum: 0,
             leap: NtpLeapIndicator::NoWarning,
             precision: 0,
         };
@@ -591,6 +1116,7 @@ mod tests {
             receiver_ts: NtpTimestamp::from_fixed_int(3),
             root_delay: NtpDuration::from_fixed_int(0),
             root_dispersion: NtpDuration::from_fixed_int(0),
+            stratum: 0,
             leap: NtpLeapIndicator::NoWarning,
             precision: 0,
         };
@@ -601,6 +1127,10 @@ mod tests {
                 last_measurement: None,
             })),
             last_outgoing_measurement: None,
+            history: MeasurementHistory::new(0),
+            asymmetry: AsymmetryCorrection::new(DelayAsymmetry::None),
+            offset_correction: NtpDuration::ZERO,
+            delay_correction: NtpDuration::ZERO,
             messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
         };
         measurement_outgoing.sender_ts = NtpTimestamp::from_fixed_int(0);
@@ -637,6 +1167,10 @@ mod tests {
             })),
             messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
             last_outgoing_measurement: None,
+            history: MeasurementHistory::new(0),
+            asymmetry: AsymmetryCorrection::new(DelayAsymmetry::None),
+            offset_correction: NtpDuration::ZERO,
+            delay_correction: NtpDuration::ZERO,
         };
         measurement_outgoing.sender_ts = NtpTimestamp::from_fixed_int(0);
         measurement_outgoing.receiver_ts = NtpTimestamp::from_fixed_int(2);
@@ -672,6 +1206,10 @@ mod tests {
             })),
             messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
             last_outgoing_measurement: None,
+            history: MeasurementHistory::new(0),
+            asymmetry: AsymmetryCorrection::new(DelayAsymmetry::None),
+            offset_correction: NtpDuration::ZERO,
+            delay_correction: NtpDuration::ZERO,
         };
         measurement_outgoing.sender_ts = NtpTimestamp::from_fixed_int(0);
         measurement_outgoing.receiver_ts = NtpTimestamp::from_fixed_int(0);
@@ -700,4 +1238,186 @@ mod tests {
             NtpDuration::from_fixed_int(-2)
         );
     }
+
+    // Accepts every other measurement
+    struct TestOneWaySourceController {
+        accept: bool,
+    }
+
+    impl InternalSourceController for TestOneWaySourceController {
+        type ControllerMessage = ();
+        type SourceMessage = ();
+        type MeasurementDelay = ();
+
+        fn handle_message(&mut self, _message: Self::ControllerMessage) {
+            unimplemented!()
+        }
+
+        fn handle_measurement(
+            &mut self,
+            _measurement: InternalMeasurement<Self::MeasurementDelay>,
+        ) -> Option<Self::SourceMessage> {
+            self.accept = !self.accept;
+            (!self.accept).then_some(())
+        }
+
+        fn desired_poll_interval(&self) -> PollInterval {
+            unimplemented!()
+        }
+
+        fn observe(&self) -> ObservableSourceTimedata {
+            unimplemented!()
+        }
+    }
+
+    fn sample(sender_ts: u64, receiver_ts: u64) -> Measurement {
+        Measurement {
+            sender_id: ClockId(1),
+            receiver_id: ClockId::SYSTEM,
+            sender_ts: NtpTimestamp::from_fixed_int(sender_ts),
+            receiver_ts: NtpTimestamp::from_fixed_int(receiver_ts),
+            root_delay: NtpDuration::from_fixed_int(0),
+            root_dispersion: NtpDuration::from_fixed_int(0),
+            stratum: 1,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        }
+    }
+
+    #[test]
+    fn test_measurement_history_bounded() {
+        let mut history = MeasurementHistory::new(3);
+        let entry = |offset| ObservableMeasurement {
+            local_send: None,
+            remote_receive: None,
+            remote_transmit: NtpTimestamp::default(),
+            local_receive: NtpTimestamp::default(),
+            offset: NtpDuration::from_fixed_int(offset),
+            delay: None,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            stratum: 1,
+            leap: NtpLeapIndicator::NoWarning,
+            accepted: true,
+        };
+
+        for offset in 0..5 {
+            history.push(entry(offset));
+        }
+        let offsets: Vec<_> = history.to_vec().iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, [2, 3, 4].map(NtpDuration::from_fixed_int).to_vec());
+
+        // A history of size zero keeps nothing
+        let mut history = MeasurementHistory::new(0);
+        history.push(entry(0));
+        assert!(history.to_vec().is_empty());
+
+        // But the latest measurement can be taken regardless, once
+        let latest = history.take_latest().unwrap();
+        assert_eq!(latest.offset, NtpDuration::from_fixed_int(0));
+        assert!(history.take_latest().is_none());
+    }
+
+    #[test]
+    fn test_two_way_measurement_history() {
+        let mut controller = TwoWaySourceControllerWrapper {
+            id: ClockId(1),
+            inner: Arc::new(Mutex::new(TestInternalSourceController {
+                last_measurement: None,
+            })),
+            last_outgoing_measurement: None,
+            history: MeasurementHistory::new(2),
+            asymmetry: AsymmetryCorrection::new(DelayAsymmetry::None),
+            offset_correction: NtpDuration::ZERO,
+            delay_correction: NtpDuration::ZERO,
+            messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
+        };
+
+        // Responses without a request are not measurements
+        controller.handle_measurement(sample(2, 3));
+        assert!(controller.measurement_history().is_empty());
+
+        controller.handle_measurement(Measurement {
+            sender_id: ClockId::SYSTEM,
+            receiver_id: ClockId(1),
+            ..sample(0, 1)
+        });
+        controller.handle_measurement(sample(2, 3));
+
+        let history = controller.measurement_history();
+        assert_eq!(history.len(), 1);
+        let entry = history[0];
+        assert_eq!(entry.local_send, Some(NtpTimestamp::from_fixed_int(0)));
+        assert_eq!(entry.remote_receive, Some(NtpTimestamp::from_fixed_int(1)));
+        assert_eq!(entry.remote_transmit, NtpTimestamp::from_fixed_int(2));
+        assert_eq!(entry.local_receive, NtpTimestamp::from_fixed_int(3));
+        assert_eq!(entry.offset, NtpDuration::from_fixed_int(0));
+        assert_eq!(entry.delay, Some(NtpDuration::from_fixed_int(2)));
+        assert_eq!(entry.stratum, 1);
+        // The test controller never produces a message
+        assert!(!entry.accepted);
+    }
+
+    #[test]
+    fn test_measurement_corrections() {
+        let mut controller = TwoWaySourceControllerWrapper {
+            id: ClockId(1),
+            inner: Arc::new(Mutex::new(TestInternalSourceController {
+                last_measurement: None,
+            })),
+            last_outgoing_measurement: None,
+            history: MeasurementHistory::new(0),
+            asymmetry: AsymmetryCorrection::new(DelayAsymmetry::None),
+            offset_correction: NtpDuration::from_fixed_int(3),
+            delay_correction: NtpDuration::from_fixed_int(1),
+            messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
+        };
+        controller.handle_measurement(Measurement {
+            sender_id: ClockId::SYSTEM,
+            receiver_id: ClockId(1),
+            ..sample(0, 1)
+        });
+        controller.handle_measurement(sample(2, 3));
+        let measurement = controller.inner.lock().unwrap().last_measurement.unwrap();
+        assert_eq!(measurement.offset, NtpDuration::from_fixed_int(3));
+        assert_eq!(measurement.delay, NtpDuration::from_fixed_int(1));
+
+        let mut controller = OneWaySourceControllerWrapper {
+            id: ClockId(1),
+            inner: Arc::new(Mutex::new(TestOneWaySourceController { accept: false })),
+            history: MeasurementHistory::new(1),
+            offset_correction: NtpDuration::from_fixed_int(3),
+            messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
+        };
+        controller.handle_measurement(sample(2, 1));
+        let history = controller.measurement_history();
+        assert_eq!(history[0].offset, NtpDuration::from_fixed_int(4));
+    }
+
+    #[test]
+    fn test_one_way_measurement_history() {
+        let mut controller = OneWaySourceControllerWrapper {
+            id: ClockId(1),
+            inner: Arc::new(Mutex::new(TestOneWaySourceController { accept: false })),
+            history: MeasurementHistory::new(2),
+            offset_correction: NtpDuration::ZERO,
+            messages_for_system: tokio::sync::mpsc::unbounded_channel().0,
+        };
+
+        controller.handle_measurement(sample(1, 0));
+        controller.handle_measurement(sample(2, 1));
+        controller.handle_measurement(sample(5, 2));
+
+        let history = controller.measurement_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].offset, NtpDuration::from_fixed_int(1));
+        assert!(history[0].accepted);
+        assert_eq!(history[1].offset, NtpDuration::from_fixed_int(3));
+        assert!(!history[1].accepted);
+        assert!(
+            history
+                .iter()
+                .all(|m| m.delay.is_none() && m.local_send.is_none() && m.remote_receive.is_none())
+        );
+    }
 }
@@ -0,0 +1,853 @@
+//! A proportional-integral clock controller, as a simple alternative to the
+//! Kalman filter for comparing algorithms.
+//!
+//! Every source keeps its most recent measurements and passes on the one with
+//! the lowest delay, like the clock filter of RFC 5905. The clock controller
+//! takes the median offset of the usable sources, steps the clock when that
+//! exceeds the step threshold, and otherwise steers the frequency of the clock
+//! with a proportional-integral loop. Source weights, preference, trust and
+//! requirement are not taken into account.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    marker::PhantomData,
+};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    ClockId,
+    clock::NtpClock,
+    config::{SourceConfig, SynchronizationConfig},
+    packet::NtpLeapIndicator,
+    system::{SteeringWithheld, TimeSnapshot},
+    time_types::{NtpDuration, NtpTimestamp, PollInterval},
+};
+
+use super::{
+    InternalMeasurement, InternalSourceController, InternalStateUpdate, InternalTimeSyncController,
+    ObservableSourceTimedata, check_holdover, check_offset_steer, clamp_frequency,
+    holdover_remaining, step_allowed,
+};
+
+/// Frequency tolerance of the local clock, with which the dispersion grows
+/// between updates (s/s, RFC 5905 PHI)
+const FREQUENCY_TOLERANCE: f64 = 15e-6;
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct PiAlgorithmConfig {
+    /// Number of recent measurements per source, of which the one with the
+    /// lowest delay is used. (count, 1+)
+    #[cfg_attr(feature = "serde", serde(default = "default_filter_length"))]
+    pub filter_length: usize,
+    /// Frequency correction per second of offset. (1/s)
+    #[cfg_attr(feature = "serde", serde(default = "default_proportional_gain"))]
+    pub proportional_gain: f64,
+    /// Change of the frequency estimate per second of offset, for every
+    /// second since the previous update. (1/s^2)
+    #[cfg_attr(feature = "serde", serde(default = "default_integral_gain"))]
+    pub integral_gain: f64,
+    /// Offset from which the clock is stepped instead of steered. (seconds)
+    #[cfg_attr(feature = "serde", serde(default = "default_step_threshold"))]
+    pub step_threshold: f64,
+    /// Absolute maximum frequency correction. (s/s)
+    #[cfg_attr(feature = "serde", serde(default = "default_maximum_frequency_steer"))]
+    pub maximum_frequency_steer: f64,
+}
+
+impl Default for PiAlgorithmConfig {
+    fn default() -> Self {
+        Self {
+            filter_length: default_filter_length(),
+            proportional_gain: default_proportional_gain(),
+            integral_gain: default_integral_gain(),
+            step_threshold: default_step_threshold(),
+            maximum_frequency_steer: default_maximum_frequency_steer(),
+        }
+    }
+}
+
+fn default_filter_length() -> usize {
+    8
+}
+
+// Together with the integral gain this gives a critically damped loop with a
+// time constant of 64 seconds.
+fn default_proportional_gain() -> f64 {
+    1.0 / 64.0
+}
+
+fn default_integral_gain() -> f64 {
+    1.0 / (4.0 * 64.0 * 64.0)
+}
+
+fn default_step_threshold() -> f64 {
+    0.128
+}
+
+fn default_maximum_frequency_steer() -> f64 {
+    495e-6
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    offset: f64,
+    delay: f64,
+    root_delay: NtpDuration,
+    root_dispersion: NtpDuration,
+    leap: NtpLeapIndicator,
+    time: NtpTimestamp,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PiSourceMessage {
+    sample: Sample,
+}
+
+#[derive(Debug, Clone)]
+pub struct PiControllerMessage {
+    step: f64,
+}
+
+/// Delay of a measurement, which one way sources don't have
+pub trait SampleDelay: Debug + Copy + Send + 'static {
+    fn seconds(self) -> f64;
+}
+
+impl SampleDelay for NtpDuration {
+    fn seconds(self) -> f64 {
+        self.to_seconds()
+    }
+}
+
+impl SampleDelay for () {
+    fn seconds(self) -> f64 {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PiSourceController<D> {
+    filter: VecDeque<Sample>,
+    filter_length: usize,
+    last_used: Option<NtpTimestamp>,
+    poll_interval: PollInterval,
+    _delay: PhantomData<D>,
+}
+
+impl<D> PiSourceController<D> {
+    fn new(algo_config: &PiAlgorithmConfig, source_config: &SourceConfig) -> Self {
+        let limits = source_config.poll_interval_limits;
+        let filter_length = algo_config.filter_length.max(1);
+        PiSourceController {
+            filter: VecDeque::with_capacity(filter_length),
+            filter_length,
+            last_used: None,
+            poll_interval: source_config
+                .initial_poll_interval
+                .max(limits.min)
+                .min(limits.max),
+            _delay: PhantomData,
+        }
+    }
+
+    fn best(&self) -> Option<Sample> {
+        self.filter
+            .iter()
+            .min_by(|a, b| a.delay.total_cmp(&b.delay))
+            .copied()
+    }
+}
+
+impl<D: SampleDelay> InternalSourceController for PiSourceController<D> {
+    type ControllerMessage = PiControllerMessage;
+    type SourceMessage = PiSourceMessage;
+    type MeasurementDelay = D;
+
+    fn handle_message(&mut self, message: Self::ControllerMessage) {
+        for sample in &mut self.filter {
+            sample.offset -= message.step;
+        }
+    }
+
+    fn handle_measurement(
+        &mut self,
+        measurement: InternalMeasurement<Self::MeasurementDelay>,
+    ) -> Option<Self::SourceMessage> {
+        if self.filter.len() == self.filter_length {
+            self.filter.pop_front();
+        }
+        self.filter.push_back(Sample {
+            offset: measurement.offset.to_seconds(),
+            delay: measurement.delay.seconds(),
+            root_delay: measurement.root_delay,
+            root_dispersion: measurement.root_dispersion,
+            leap: measurement.leap,
+            time: measurement.localtime,
+        });
+
+        // As in RFC 5905, a sample is only passed on once, and only when it
+        // is newer than the previous one.
+        let best = self.best()?;
+        if self
+            .last_used
+            .is_some_and(|last| !last.is_before(best.time))
+        {
+            return None;
+        }
+        self.last_used = Some(best.time);
+        Some(PiSourceMessage { sample: best })
+    }
+
+    fn desired_poll_interval(&self) -> PollInterval {
+        self.poll_interval
+    }
+
+    fn clock_discontinuity(&mut self) {
+        self.filter.clear();
+        self.last_used = None;
+    }
+
+    fn observe(&self) -> ObservableSourceTimedata {
+        let Some(best) = self.best() else {
+            return ObservableSourceTimedata::default();
+        };
+        let jitter = (self
+            .filter
+            .iter()
+            .map(|sample| (sample.offset - best.offset).powi(2))
+            .sum::<f64>()
+            / self.filter.len() as f64)
+            .sqrt();
+
+        ObservableSourceTimedata {
+            offset: NtpDuration::from_seconds(best.offset),
+            uncertainty: NtpDuration::from_seconds(jitter),
+            delay: NtpDuration::from_seconds(best.delay),
+            remote_delay: best.root_delay,
+            remote_uncertainty: best.root_dispersion,
+            last_update: best.time,
+            kalman: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PiClockController<C: NtpClock> {
+    clock: C,
+    synchronization_config: SynchronizationConfig,
+    algo_config: PiAlgorithmConfig,
+    sources: HashMap<ClockId, (Option<Sample>, bool)>,
+    // Frequency correction built up by the integral term
+    frequency: f64,
+    last_update: Option<NtpTimestamp>,
+    timedata: TimeSnapshot,
+    in_startup: bool,
+    // Number of clock steps since startup
+    steps: u32,
+    // Whether the configured maximum slew rate and frequency offset applied
+    // to the last frequency change
+    slew_clamped: bool,
+    frequency_clamped: bool,
+}
+
+impl<C: NtpClock> PiClockController<C> {
+    fn update_clock(&mut self, time: NtpTimestamp) -> InternalStateUpdate<PiControllerMessage> {
+        let mut candidates: Vec<_> = self
+            .sources
+            .iter()
+            .filter_map(|(id, (sample, usable))| sample.filter(|_| *usable).map(|s| (*id, s)))
+            .collect();
+        let synchronized = !self.in_startup && self.timedata.steering_withheld.is_none();
+        let required = self
+            .synchronization_config
+            .required_agreeing_sources(synchronized);
+        if candidates.is_empty() || candidates.len() < required {
+            info!("Not enough usable sources to steer the clock");
+            self.timedata.steering_withheld = Some(if candidates.is_empty() {
+                SteeringWithheld::NoUsableSources
+            } else {
+                SteeringWithheld::NotEnoughAgreeing {
+                    agreeing: candidates.len(),
+                    required,
+                }
+            });
+            return InternalStateUpdate {
+                time_snapshot: Some(self.timedata),
+                ..InternalStateUpdate::default()
+            };
+        }
+
+        self.timedata.steering_withheld = None;
+        candidates.sort_by(|(_, a), (_, b)| a.offset.total_cmp(&b.offset));
+        let (median_id, median) = candidates[candidates.len() / 2];
+        info!("Offset: {}ms", median.offset * 1e3);
+
+        if self.in_startup {
+            self.clock
+                .disable_ntp_algorithm()
+                .expect("Cannot update clock");
+        }
+
+        let elapsed = self
+            .last_update
+            .map_or(0.0, |last| (time - last).to_seconds().max(0.0));
+        self.last_update = Some(time);
+
+        let source_message = if median.offset.abs() > self.algo_config.step_threshold
+            && step_allowed(
+                &self.synchronization_config,
+                self.in_startup,
+                &mut self.steps,
+                median.offset,
+            ) {
+            Some(self.step(median.offset))
+        } else {
+            self.steer(median.offset, elapsed);
+            None
+        };
+        self.timedata.frequency_correction = Some(self.frequency);
+
+        self.timedata.root_delay = median.root_delay + NtpDuration::from_seconds(median.delay);
+        self.timedata.root_variance_base_time = time;
+        self.timedata.root_variance_base =
+            (median.root_dispersion.to_seconds() + median.delay / 2.0).powi(2);
+        self.timedata.root_variance_linear = 0.0;
+        self.timedata.root_variance_quadratic = self
+            .synchronization_config
+            .holdover
+            .map_or(FREQUENCY_TOLERANCE, |holdover| holdover.dispersion_rate)
+            .powi(2);
+        self.timedata.root_variance_cubic = 0.0;
+        self.clock
+            .error_estimate_update(
+                self.timedata.root_dispersion(time),
+                self.timedata.root_delay,
+            )
+            .expect("Cannot update clock");
+
+        if let Some(leap) = majority_leap(&candidates) {
+            self.clock.status_update(leap).expect("Cannot update clock");
+            self.timedata.leap_indicator = leap;
+        }
+
+        self.in_startup = false;
+
+        // The source that determined the offset goes first
+        let used_sources = std::iter::once(median_id)
+            .chain(
+                candidates
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .filter(|id| *id != median_id),
+            )
+            .collect();
+
+        InternalStateUpdate {
+            source_message,
+            time_snapshot: Some(self.timedata),
+            used_sources: Some(used_sources),
+            next_update: holdover_remaining(&self.synchronization_config, &self.timedata, time),
+        }
+    }
+
+    fn step(&mut self, offset: f64) -> PiControllerMessage {
+        check_offset_steer(
+            &self.synchronization_config,
+            &mut self.timedata,
+            self.in_startup,
+            offset,
+        );
+        self.clock
+            .step_clock(NtpDuration::from_seconds(offset))
+            .expect("Cannot adjust clock");
+        self.timedata.monotonic_offset += NtpDuration::from_seconds(offset);
+        for sample in self
+            .sources
+            .values_mut()
+            .filter_map(|(sample, _)| sample.as_mut())
+        {
+            sample.offset -= offset;
+        }
+
+        if self.synchronization_config.warn_on_jump {
+            warn!(
+                "Jumped offset by {}ms. This may cause problems for other software. If this is not a problem for your system, you can reclassify this warning as an informative message through the `synchronization.warn-on-jump` setting in ntp.toml.",
+                offset * 1e3
+            );
+        } else {
+            info!("Jumped offset by {}ms", offset * 1e3);
+        }
+
+        PiControllerMessage { step: offset }
+    }
+
+    fn steer(&mut self, offset: f64, elapsed: f64) {
+        let limit = self
+            .synchronization_config
+            .maximum_frequency_offset
+            .map_or(self.algo_config.maximum_frequency_steer, |ppm| {
+                self.algo_config.maximum_frequency_steer.min(ppm * 1e-6)
+            });
+        self.frequency = (self.frequency + self.algo_config.integral_gain * offset * elapsed)
+            .clamp(-limit, limit);
+        // The proportional term slews away the offset
+        let slew = clamp_frequency(
+            self.algo_config.proportional_gain * offset,
+            self.synchronization_config.maximum_slew_rate,
+            &mut self.slew_clamped,
+            "Slew rate",
+        );
+        let steer = clamp_frequency(
+            (self.frequency + slew).clamp(
+                -self.algo_config.maximum_frequency_steer,
+                self.algo_config.maximum_frequency_steer,
+            ),
+            self.synchronization_config.maximum_frequency_offset,
+            &mut self.frequency_clamped,
+            "Frequency correction",
+        );
+        self.clock
+            .set_frequency(steer)
+            .expect("Cannot adjust clock");
+        debug!(
+            "Changed frequency, current steer {}ppm, frequency estimate {}ppm",
+            steer * 1e6,
+            self.frequency * 1e6,
+        );
+    }
+}
+
+/// The leap indicator announced by more than half of the sources, if any
+fn majority_leap(candidates: &[(ClockId, Sample)]) -> Option<NtpLeapIndicator> {
+    [
+        NtpLeapIndicator::NoWarning,
+        NtpLeapIndicator::Leap61,
+        NtpLeapIndicator::Leap59,
+    ]
+    .into_iter()
+    .find(|leap| {
+        2 * candidates
+            .iter()
+            .filter(|(_, sample)| sample.leap == *leap)
+            .count()
+            > candidates.len()
+    })
+}
+
+impl<C: NtpClock> InternalTimeSyncController for PiClockController<C> {
+    type Clock = C;
+    type AlgorithmConfig = PiAlgorithmConfig;
+    type ControllerMessage = PiControllerMessage;
+    type SourceMessage = PiSourceMessage;
+    type NtpSourceController = PiSourceController<NtpDuration>;
+    type OneWaySourceController = PiSourceController<()>;
+
+    fn new(
+        clock: C,
+        synchronization_config: SynchronizationConfig,
+        algo_config: Self::AlgorithmConfig,
+    ) -> Result<Self, C::Error> {
+        let frequency = clock.get_frequency()?;
+
+        Ok(PiClockController {
+            clock,
+            synchronization_config,
+            algo_config,
+            sources: HashMap::new(),
+            frequency,
+            last_update: None,
+            timedata: TimeSnapshot {
+                accumulated_steps_threshold: synchronization_config
+                    .accumulated_step_panic_threshold,
+                monotonic_epoch: rand::random(),
+                ..TimeSnapshot::default()
+            },
+            in_startup: true,
+            steps: 0,
+            slew_clamped: false,
+            frequency_clamped: false,
+        })
+    }
+
+    fn take_control(&mut self) -> Result<(), <C as NtpClock>::Error> {
+        self.clock.disable_ntp_algorithm()?;
+        self.clock.status_update(NtpLeapIndicator::Unknown)?;
+        Ok(())
+    }
+
+    fn add_source(
+        &mut self,
+        id: ClockId,
+        source_config: SourceConfig,
+    ) -> Self::NtpSourceController {
+        self.sources.insert(id, (None, false));
+        PiSourceController::new(&self.algo_config, &source_config)
+    }
+
+    fn add_one_way_source(
+        &mut self,
+        id: ClockId,
+        source_config: SourceConfig,
+        _measurement_noise_estimate: f64,
+        _measurement_accuracy_estimate: f64,
+        _period: Option<f64>,
+    ) -> Self::OneWaySourceController {
+        self.sources.insert(id, (None, false));
+        PiSourceController::new(&self.algo_config, &source_config)
+    }
+
+    fn remove_source(&mut self, id: ClockId) {
+        self.sources.remove(&id);
+    }
+
+    fn source_update(&mut self, id: ClockId, usable: bool) {
+        if let Some(state) = self.sources.get_mut(&id) {
+            state.1 = usable;
+        }
+    }
+
+    fn clock_discontinuity(&mut self) {
+        for (sample, _) in self.sources.values_mut() {
+            *sample = None;
+        }
+        // The time since the last update says nothing about how long the
+        // integral term has been steering, and the jump is corrected as at
+        // startup
+        self.last_update = None;
+        self.in_startup = true;
+    }
+
+    fn source_message(
+        &mut self,
+        id: ClockId,
+        message: Self::SourceMessage,
+    ) -> InternalStateUpdate<Self::ControllerMessage> {
+        if let Some(source) = self.sources.get_mut(&id) {
+            source.0 = Some(message.sample);
+            self.update_clock(message.sample.time)
+        } else {
+            error!("Internal error: Update from non-existing source {}", id);
+            InternalStateUpdate::default()
+        }
+    }
+
+    fn time_update(&mut self) -> InternalStateUpdate<Self::ControllerMessage> {
+        // Updates are only requested for ending the holdover
+        let now = self.clock.now().expect("Cannot read clock");
+        check_holdover(
+            &self.synchronization_config,
+            &self.clock,
+            &mut self.timedata,
+            now,
+        );
+
+        InternalStateUpdate {
+            time_snapshot: Some(self.timedata),
+            ..InternalStateUpdate::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {
+        frequency: RefCell<f64>,
+        steps: RefCell<Vec<f64>>,
+    }
+
+    impl NtpClock for TestClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_frequency(&self, freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            *self.frequency.borrow_mut() = freq;
+            Ok(NtpTimestamp::default())
+        }
+
+        fn get_frequency(&self) -> Result<f64, Self::Error> {
+            Ok(*self.frequency.borrow())
+        }
+
+        fn step_clock(&self, offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            self.steps.borrow_mut().push(offset.to_seconds());
+            Ok(NtpTimestamp::default())
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _maximum_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn measurement(offset: f64, delay: f64, time: u64) -> InternalMeasurement<NtpDuration> {
+        InternalMeasurement {
+            delay: NtpDuration::from_seconds(delay),
+            offset: NtpDuration::from_seconds(offset),
+            localtime: NtpTimestamp::from_seconds_nanos_since_ntp_era(time as u32, 0),
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        }
+    }
+
+    // Offsets pick up a small relative error in the conversions to and from
+    // NtpDuration
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() <= 1e-6 * expected.abs(),
+            "{value} is not close to {expected}"
+        );
+    }
+
+    fn controller(minimum_agreeing_sources: usize) -> PiClockController<TestClock> {
+        PiClockController::new(
+            TestClock::default(),
+            SynchronizationConfig {
+                minimum_agreeing_sources,
+                ..SynchronizationConfig::default()
+            },
+            PiAlgorithmConfig::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_filter_lowest_delay() {
+        let mut source: PiSourceController<NtpDuration> = PiSourceController::new(
+            &PiAlgorithmConfig {
+                filter_length: 3,
+                ..PiAlgorithmConfig::default()
+            },
+            &SourceConfig::default(),
+        );
+
+        let message = source
+            .handle_measurement(measurement(1.0, 0.02, 1))
+            .unwrap();
+        assert_close(message.sample.offset, 1.0);
+
+        // The first sample still has the lowest delay, and is not used twice
+        assert!(
+            source
+                .handle_measurement(measurement(2.0, 0.03, 2))
+                .is_none()
+        );
+
+        let message = source
+            .handle_measurement(measurement(3.0, 0.01, 3))
+            .unwrap();
+        assert_close(message.sample.offset, 3.0);
+
+        // Once the best sample is pushed out, the next best one is used as it
+        // is newer than the previous one used
+        assert!(
+            source
+                .handle_measurement(measurement(4.0, 0.02, 4))
+                .is_none()
+        );
+        assert!(
+            source
+                .handle_measurement(measurement(5.0, 0.05, 5))
+                .is_none()
+        );
+        let message = source
+            .handle_measurement(measurement(6.0, 0.05, 6))
+            .unwrap();
+        assert_close(message.sample.offset, 4.0);
+        assert_close(source.observe().offset.to_seconds(), 4.0);
+    }
+
+    #[test]
+    fn test_filter_step() {
+        let mut source: PiSourceController<NtpDuration> =
+            PiSourceController::new(&PiAlgorithmConfig::default(), &SourceConfig::default());
+        source.handle_measurement(measurement(1.0, 0.01, 1));
+        source.handle_message(PiControllerMessage { step: 1.0 });
+        assert_eq!(source.observe().offset, NtpDuration::ZERO);
+    }
+
+    #[test]
+    fn test_step_and_steer() {
+        let mut algo = controller(1);
+        let mut source = algo.add_source(ClockId(1), SourceConfig::default());
+        algo.source_update(ClockId(1), true);
+
+        // A large offset is stepped, and the step is passed to the sources
+        let message = source
+            .handle_measurement(measurement(1.0, 0.01, 1))
+            .unwrap();
+        let update = algo.source_message(ClockId(1), message);
+        assert_eq!(algo.clock.steps.borrow().len(), 1);
+        assert_close(algo.clock.steps.borrow()[0], 1.0);
+        assert_eq!(update.used_sources, Some(vec![ClockId(1)]));
+        source.handle_message(update.source_message.unwrap());
+        assert!(!algo.in_startup);
+
+        // A small offset is steered
+        let offset = 1.0 / 1024.0;
+        let message = source
+            .handle_measurement(measurement(offset, 0.001, 17))
+            .unwrap();
+        let update = algo.source_message(ClockId(1), message);
+        assert!(update.source_message.is_none());
+        assert_eq!(algo.clock.steps.borrow().len(), 1);
+        let integral = offset * 16.0 * algo.algo_config.integral_gain;
+        assert_close(algo.frequency, integral);
+        assert_close(
+            *algo.clock.frequency.borrow(),
+            integral + offset * algo.algo_config.proportional_gain,
+        );
+    }
+
+    #[test]
+    fn test_clock_discontinuity() {
+        let mut algo = controller(1);
+        let mut source = algo.add_source(ClockId(1), SourceConfig::default());
+        algo.source_update(ClockId(1), true);
+
+        let message = source
+            .handle_measurement(measurement(1.0, 0.01, 16))
+            .unwrap();
+        algo.source_message(ClockId(1), message);
+        assert!(!algo.in_startup);
+
+        algo.clock_discontinuity();
+        source.clock_discontinuity();
+        assert!(algo.in_startup);
+        assert!(algo.sources[&ClockId(1)].0.is_none());
+        assert_eq!(source.observe().offset, NtpDuration::ZERO);
+
+        // Measurements from before the jump in local time are used again
+        assert!(
+            source
+                .handle_measurement(measurement(0.5, 0.01, 1))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let mut algo = PiClockController::new(
+            TestClock::default(),
+            SynchronizationConfig {
+                maximum_slew_rate: Some(100.0),
+                maximum_frequency_offset: Some(150.0),
+                ..SynchronizationConfig::default()
+            },
+            PiAlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        // 64ms of offset would be slewed at 1000ppm
+        algo.steer(0.064, 0.0);
+        assert!((*algo.clock.frequency.borrow() - 100e-6).abs() < 1e-12);
+        assert!(algo.slew_clamped);
+        assert!(!algo.frequency_clamped);
+
+        algo.frequency = 140e-6;
+        algo.steer(0.064, 0.0);
+        assert!((*algo.clock.frequency.borrow() - 150e-6).abs() < 1e-12);
+        assert!(algo.frequency_clamped);
+    }
+
+    #[test]
+    fn test_median_and_minimum_sources() {
+        let mut algo = controller(3);
+        let mut sources: Vec<_> = (1..=3)
+            .map(|i| {
+                let source = algo.add_source(ClockId(i), SourceConfig::default());
+                algo.source_update(ClockId(i), true);
+                source
+            })
+            .collect();
+
+        for (i, offset) in [(0, 2.0 / 1024.0), (1, 64.0 / 1024.0)] {
+            let message = sources[i]
+                .handle_measurement(measurement(offset, 0.001, 1))
+                .unwrap();
+            let update = algo.source_message(ClockId(i as u64 + 1), message);
+            assert!(update.used_sources.is_none());
+        }
+        assert_eq!(*algo.clock.frequency.borrow(), 0.0);
+        assert_eq!(
+            algo.timedata.steering_withheld,
+            Some(SteeringWithheld::NotEnoughAgreeing {
+                agreeing: 2,
+                required: 3
+            })
+        );
+
+        let message = sources[2]
+            .handle_measurement(measurement(-1.0 / 1024.0, 0.001, 1))
+            .unwrap();
+        let update = algo.source_message(ClockId(3), message);
+        assert_eq!(update.used_sources.unwrap()[0], ClockId(1));
+        assert!(algo.clock.steps.borrow().is_empty());
+        assert_eq!(algo.timedata.steering_withheld, None);
+        assert_close(
+            *algo.clock.frequency.borrow(),
+            2.0 / 1024.0 * algo.algo_config.proportional_gain,
+        );
+    }
+
+    #[test]
+    fn test_majority_leap() {
+        let sample = |leap| {
+            (
+                ClockId(1),
+                Sample {
+                    offset: 0.0,
+                    delay: 0.0,
+                    root_delay: NtpDuration::ZERO,
+                    root_dispersion: NtpDuration::ZERO,
+                    leap,
+                    time: NtpTimestamp::default(),
+                },
+            )
+        };
+        assert_eq!(
+            majority_leap(&[
+                sample(NtpLeapIndicator::Leap61),
+                sample(NtpLeapIndicator::NoWarning),
+                sample(NtpLeapIndicator::Leap61),
+            ]),
+            Some(NtpLeapIndicator::Leap61)
+        );
+        assert_eq!(
+            majority_leap(&[
+                sample(NtpLeapIndicator::Leap61),
+                sample(NtpLeapIndicator::NoWarning),
+            ]),
+            None
+        );
+    }
+}
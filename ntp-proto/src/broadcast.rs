@@ -0,0 +1,582 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    ClockId,
+    algorithm::{Measurement, ObservableMeasurement, SourceController},
+    identifiers::ReferenceId,
+    packet::{NoCipher, NtpAssociationMode, NtpPacket, RequestIdentifier},
+    source::{NtpSourceSnapshot, ObservableSourceState, ProtocolVersion, Reach},
+    system::NtpSourceInfo,
+    time_types::{NtpDuration, NtpTimestamp, PollInterval},
+};
+
+const MAX_STRATUM: u8 = 16;
+// Number of client/server exchanges used to calibrate the delay to a server
+const CALIBRATION_EXCHANGES: usize = 4;
+// Number of broadcast intervals without broadcasts after which we switch to
+// another server broadcasting on the network
+const SERVER_TIMEOUT_INTERVALS: f64 = 8.0;
+
+/// Broadcast server we are currently listening to
+#[derive(Debug, Clone, Copy)]
+struct BroadcastServer {
+    addr: SocketAddr,
+    // One way delay of packets from the server to us
+    delay: NtpDuration,
+    // Local time at which the last broadcast (or calibration response) arrived
+    last_seen: NtpTimestamp,
+    poll_interval: PollInterval,
+    stratum: u8,
+    reference_id: ReferenceId,
+    version: u8,
+}
+
+impl BroadcastServer {
+    fn timed_out(&self, now: NtpTimestamp) -> bool {
+        (now - self.last_seen).to_seconds()
+            > SERVER_TIMEOUT_INTERVALS * self.poll_interval.as_duration().to_seconds()
+    }
+}
+
+/// Client/server exchanges used to estimate the delay to a new server
+#[derive(Debug, Clone, Copy)]
+struct Calibration {
+    addr: SocketAddr,
+    request: Option<RequestIdentifier>,
+    exchanges_left: usize,
+    delay: Option<NtpDuration>,
+    last_response: Option<NtpTimestamp>,
+}
+
+/// Client of servers broadcasting NTP packets on the local network
+///
+/// Broadcast packets only give the time at which the server sent them, so the
+/// delay from the server to us is first estimated with a few regular
+/// client/server exchanges, unless it is configured. Only one server is
+/// followed at a time, another server is picked when it stops broadcasting.
+#[derive(Debug)]
+pub struct BroadcastSource<Controller: SourceController> {
+    id: ClockId,
+    controller: Controller,
+    fixed_delay: Option<NtpDuration>,
+
+    server: Option<BroadcastServer>,
+    calibration: Option<Calibration>,
+    reach: Reach,
+
+    source_info: Arc<RwLock<NtpSourceInfo>>,
+    source_snapshots: Arc<Mutex<HashMap<ClockId, NtpSourceSnapshot>>>,
+}
+
+impl<Controller: SourceController> BroadcastSource<Controller> {
+    pub(crate) fn new(
+        controller: Controller,
+        fixed_delay: Option<NtpDuration>,
+        id: ClockId,
+        source_info: Arc<RwLock<NtpSourceInfo>>,
+        source_snapshots: Arc<Mutex<HashMap<ClockId, NtpSourceSnapshot>>>,
+    ) -> Self {
+        BroadcastSource {
+            id,
+            controller,
+            fixed_delay,
+            server: None,
+            calibration: None,
+            reach: Reach::never(),
+            source_info,
+            source_snapshots,
+        }
+    }
+
+    /// Handle a packet received on the broadcast socket.
+    ///
+    /// Returns the address of a server whose delay needs to be calibrated
+    /// before its broadcasts can be used. The daemon should then exchange
+    /// packets with it using [`Self::calibration_request`] and
+    /// [`Self::handle_calibration_response`].
+    pub fn handle_broadcast(
+        &mut self,
+        message: &[u8],
+        sender: SocketAddr,
+        recv_time: NtpTimestamp,
+    ) -> Option<SocketAddr> {
+        let message = match NtpPacket::deserialize(message, &NoCipher) {
+            Ok((packet, _)) => packet,
+            Err(e) => {
+                debug!("received invalid packet: {}", e);
+                return None;
+            }
+        };
+
+        if message.mode() != NtpAssociationMode::Broadcast {
+            debug!("Ignoring non-broadcast packet");
+            return None;
+        }
+
+        if message.is_kiss()
+            || message.stratum() >= MAX_STRATUM
+            || !message.leap().is_synchronized()
+        {
+            debug!("Ignoring broadcast from unsynchronized server");
+            return None;
+        }
+
+        match self.server {
+            Some(server) if server.addr == sender => {}
+            Some(server) if !server.timed_out(recv_time) => {
+                debug!(?sender, "Ignoring broadcast from other server");
+                return None;
+            }
+            _ => {
+                if let Some(delay) = self.fixed_delay {
+                    info!(server = ?sender, "Listening to broadcast server");
+                    self.server = Some(BroadcastServer {
+                        addr: sender,
+                        delay,
+                        last_seen: recv_time,
+                        poll_interval: message.poll(),
+                        stratum: message.stratum(),
+                        reference_id: message.reference_id(),
+                        version: message.version().as_u8(),
+                    });
+                } else if self.calibration.is_some_and(|c| c.addr == sender) {
+                    // Still waiting for the calibration to finish
+                    return None;
+                } else {
+                    info!(server = ?sender, "Calibrating delay to broadcast server");
+                    self.server = None;
+                    self.calibration = Some(Calibration {
+                        addr: sender,
+                        request: None,
+                        exchanges_left: CALIBRATION_EXCHANGES,
+                        delay: None,
+                        last_response: None,
+                    });
+                    return Some(sender);
+                }
+            }
+        }
+
+        self.process_broadcast(&message, recv_time);
+        None
+    }
+
+    fn process_broadcast(&mut self, message: &NtpPacket, recv_time: NtpTimestamp) {
+        let Some(server) = self.server.as_mut() else {
+            return;
+        };
+
+        server.last_seen = recv_time;
+        server.poll_interval = message.poll();
+        server.stratum = message.stratum();
+        server.reference_id = message.reference_id();
+        server.version = message.version().as_u8();
+        let server = *server;
+        self.reach.received_packet();
+
+        let snapshot = self.snapshot(&server);
+        let usable = {
+            let source_info = self.source_info.read().unwrap();
+            snapshot
+                .accept_synchronization(
+                    source_info.local_stratum,
+                    source_info.minimum_source_stratum..=source_info.maximum_source_stratum,
+                    &source_info.ip_list,
+                    source_info.server_id,
+                )
+                .is_ok()
+        };
+        self.source_snapshots
+            .lock()
+            .unwrap()
+            .insert(self.id, snapshot);
+        self.controller.set_usable(usable);
+
+        self.controller.handle_measurement(broadcast_measurement(
+            message,
+            self.id,
+            server.delay,
+            recv_time,
+        ));
+    }
+
+    fn snapshot(&self, server: &BroadcastServer) -> NtpSourceSnapshot {
+        NtpSourceSnapshot {
+            source_addr: server.addr,
+            source_id: ReferenceId::from_ip(server.addr.ip()),
+            poll_interval: server.poll_interval,
+            reach: self.reach,
+            stratum: server.stratum,
+            reference_id: server.reference_id,
+            protocol_version: ProtocolVersion::V4,
+            bloom_filter: None,
+        }
+    }
+
+    /// Next request of the ongoing calibration, which should be sent to the
+    /// server returned by [`Self::handle_broadcast`]. Returns `None` once the
+    /// calibration is finished. Any response to an earlier request is no
+    /// longer accepted.
+    pub fn calibration_request(&mut self) -> Option<Vec<u8>> {
+        let calibration = self.calibration.as_mut()?;
+
+        if calibration.exchanges_left == 0 {
+            self.finish_calibration();
+            return None;
+        }
+        calibration.exchanges_left -= 1;
+
+        let (packet, identifier) = NtpPacket::poll_message(PollInterval::default());
+        calibration.request = Some(identifier);
+
+        let mut buffer = [0u8; 1024];
+        let mut cursor = Cursor::new(buffer.as_mut_slice());
+        packet
+            .serialize(&mut cursor, &NoCipher, None)
+            .expect("Internal error: could not serialize packet");
+        let used = cursor.position() as usize;
+        Some(buffer[..used].to_vec())
+    }
+
+    /// Handle a response to a calibration request, sent at `send_time` and
+    /// received at `recv_time`.
+    pub fn handle_calibration_response(
+        &mut self,
+        message: &[u8],
+        send_time: NtpTimestamp,
+        recv_time: NtpTimestamp,
+    ) {
+        let Some(calibration) = self.calibration.as_mut() else {
+            return;
+        };
+        let Some(identifier) = calibration.request else {
+            debug!("Received unexpected calibration response");
+            return;
+        };
+
+        let message = match NtpPacket::deserialize(message, &NoCipher) {
+            Ok((packet, _)) => packet,
+            Err(e) => {
+                debug!("received invalid packet: {}", e);
+                return;
+            }
+        };
+
+        if !message.valid_server_response(identifier, false)
+            || message.mode() != NtpAssociationMode::Server
+            || message.is_kiss()
+        {
+            debug!("Received invalid calibration response");
+            return;
+        }
+        calibration.request = None;
+
+        let delay = exchange_delay(
+            send_time,
+            message.receive_timestamp(),
+            message.transmit_timestamp(),
+            recv_time,
+        );
+        debug!(delay = delay.to_seconds(), "Calibration exchange completed");
+        calibration.delay = Some(calibration.delay.map_or(delay, |d| d.min(delay)));
+        calibration.last_response = Some(recv_time);
+    }
+
+    fn finish_calibration(&mut self) {
+        let Some(calibration) = self.calibration.take() else {
+            return;
+        };
+
+        match (calibration.delay, calibration.last_response) {
+            (Some(delay), Some(last_seen)) => {
+                info!(
+                    server = ?calibration.addr,
+                    delay = delay.to_seconds(),
+                    "Listening to broadcast server"
+                );
+                self.server = Some(BroadcastServer {
+                    addr: calibration.addr,
+                    delay,
+                    last_seen,
+                    poll_interval: PollInterval::default(),
+                    stratum: MAX_STRATUM,
+                    reference_id: ReferenceId::NONE,
+                    version: 4,
+                });
+            }
+            _ => {
+                warn!(server = ?calibration.addr, "Could not calibrate delay to broadcast server");
+            }
+        }
+    }
+
+    /// The measurement handled last, unless it was taken already
+    pub fn take_latest_measurement(&mut self) -> Option<ObservableMeasurement> {
+        self.controller.take_latest_measurement()
+    }
+
+    pub fn observe(&self, name: String, address: String, id: ClockId) -> ObservableSourceState {
+        ObservableSourceState {
+            timedata: self.controller.observe(),
+            unanswered_polls: 0,
+            poll_interval: self
+                .server
+                .map_or(PollInterval::from_byte(0), |server| server.poll_interval),
+            nts_cookies: None,
+            name,
+            // Show the server we listen to rather than where we listen
+            address: self
+                .server
+                .map_or(address, |server| server.addr.to_string()),
+            id,
+            // Broadcast sources can not be weighted
+            weight: crate::config::default_weight(),
+            prefer: false,
+            noselect: false,
+            ntp_version: self.server.map(|server| server.version),
+            stratum: self.server.map(|server| server.stratum),
+            reference_id: self.server.map(|server| server.reference_id),
+            reach: None,
+            target_poll_interval: None,
+            last_kiss_code: None,
+            measurements: self.controller.measurement_history(),
+            nts_key_exchange: None,
+            authentication_failures: 0,
+            nts_degraded: false,
+        }
+    }
+}
+
+/// One way delay estimated from a client/server exchange, assuming the delay
+/// is the same in both directions
+fn exchange_delay(
+    local_send: NtpTimestamp,
+    remote_receive: NtpTimestamp,
+    remote_transmit: NtpTimestamp,
+    local_receive: NtpTimestamp,
+) -> NtpDuration {
+    let roundtrip = (local_receive - local_send) - (remote_transmit - remote_receive);
+    NtpDuration::from_seconds(roundtrip.to_seconds() / 2.0).max(NtpDuration::ZERO)
+}
+
+fn broadcast_measurement(
+    message: &NtpPacket,
+    id: ClockId,
+    delay: NtpDuration,
+    recv_time: NtpTimestamp,
+) -> Measurement {
+    Measurement {
+        sender_id: id,
+        receiver_id: ClockId::SYSTEM,
+        // The time at the server when the broadcast reached us
+        sender_ts: message.transmit_timestamp() + delay,
+        receiver_ts: recv_time,
+        // As if the broadcast was the second half of a regular exchange
+        root_delay: message.root_delay() + delay + delay,
+        root_dispersion: message.root_dispersion(),
+        stratum: message.stratum(),
+        leap: message.leap(),
+        precision: message.precision(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::{
+        ObservableSourceTimedata,
+        packet::{NtpLeapIndicator, NtpPacket},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct RecordingController {
+        measurements: Vec<Measurement>,
+        usable: bool,
+    }
+
+    impl SourceController for RecordingController {
+        fn handle_measurement(&mut self, measurement: Measurement) {
+            self.measurements.push(measurement);
+        }
+
+        fn set_usable(&mut self, usable: bool) {
+            self.usable = usable;
+        }
+
+        fn desired_poll_interval(&self) -> PollInterval {
+            PollInterval::default()
+        }
+
+        fn observe(&self) -> ObservableSourceTimedata {
+            ObservableSourceTimedata::default()
+        }
+    }
+
+    fn source(fixed_delay: Option<NtpDuration>) -> BroadcastSource<RecordingController> {
+        let source_info = NtpSourceInfo {
+            ip_list: Arc::new([]),
+            local_stratum: 16,
+            minimum_source_stratum: 1,
+            maximum_source_stratum: 15,
+            ..Default::default()
+        };
+        BroadcastSource::new(
+            RecordingController::default(),
+            fixed_delay,
+            ClockId::new(),
+            Arc::new(RwLock::new(source_info)),
+            Arc::default(),
+        )
+    }
+
+    fn server(last: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, last)), 123)
+    }
+
+    fn broadcast(transmit: NtpTimestamp) -> Vec<u8> {
+        let mut packet = NtpPacket::test();
+        packet.set_mode(NtpAssociationMode::Broadcast);
+        packet.set_stratum(2);
+        packet.set_leap(NtpLeapIndicator::NoWarning);
+        packet.set_transmit_timestamp(transmit);
+        packet.serialize_without_encryption_vec(None).unwrap()
+    }
+
+    fn seconds(s: f64) -> NtpTimestamp {
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0) + NtpDuration::from_seconds(s)
+    }
+
+    #[test]
+    fn test_exchange_delay() {
+        // 10ms roundtrip, of which the server took 2ms
+        let delay = exchange_delay(seconds(0.0), seconds(0.5), seconds(0.502), seconds(0.010));
+        assert!((delay.to_seconds() - 0.004).abs() < 1e-9);
+
+        // Never negative
+        let delay = exchange_delay(seconds(0.0), seconds(0.5), seconds(0.502), seconds(0.001));
+        assert_eq!(delay, NtpDuration::ZERO);
+    }
+
+    #[test]
+    fn test_fixed_delay() {
+        let mut source = source(Some(NtpDuration::from_seconds(0.001)));
+
+        let calibrate = source.handle_broadcast(&broadcast(seconds(1.0)), server(1), seconds(1.5));
+        assert_eq!(calibrate, None);
+        assert!(source.controller.usable);
+
+        let measurement = source.controller.measurements[0];
+        assert_eq!(measurement.receiver_ts, seconds(1.5));
+        assert!(((measurement.sender_ts - seconds(1.0)).to_seconds() - 0.001).abs() < 1e-9);
+        assert!((measurement.root_delay.to_seconds() - 0.002).abs() < 1e-6);
+        assert_eq!(measurement.stratum, 2);
+
+        let state = source.observe("Broadcast".into(), "0.0.0.0:123".into(), source.id);
+        assert_eq!(state.address, server(1).to_string());
+        assert_eq!(state.ntp_version, Some(4));
+    }
+
+    #[test]
+    fn test_single_server() {
+        let mut source = source(Some(NtpDuration::ZERO));
+
+        source.handle_broadcast(&broadcast(seconds(1.0)), server(1), seconds(1.0));
+        source.handle_broadcast(&broadcast(seconds(2.0)), server(2), seconds(2.0));
+        assert_eq!(source.controller.measurements.len(), 1);
+
+        // Switch over once the first server is silent for a while
+        source.handle_broadcast(&broadcast(seconds(1000.0)), server(2), seconds(1000.0));
+        assert_eq!(source.controller.measurements.len(), 2);
+        source.handle_broadcast(&broadcast(seconds(1001.0)), server(1), seconds(1001.0));
+        assert_eq!(source.controller.measurements.len(), 2);
+    }
+
+    #[test]
+    fn test_ignored_packets() {
+        let mut source = source(Some(NtpDuration::ZERO));
+
+        let mut packet = NtpPacket::test();
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_stratum(2);
+        packet.set_leap(NtpLeapIndicator::NoWarning);
+        let data = packet.serialize_without_encryption_vec(None).unwrap();
+        source.handle_broadcast(&data, server(1), seconds(1.0));
+
+        packet.set_mode(NtpAssociationMode::Broadcast);
+        packet.set_leap(NtpLeapIndicator::Unknown);
+        let data = packet.serialize_without_encryption_vec(None).unwrap();
+        source.handle_broadcast(&data, server(1), seconds(1.0));
+
+        source.handle_broadcast(&[0; 10], server(1), seconds(1.0));
+
+        assert!(source.controller.measurements.is_empty());
+        assert!(source.server.is_none());
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut source = source(None);
+
+        let calibrate = source.handle_broadcast(&broadcast(seconds(1.0)), server(1), seconds(1.0));
+        assert_eq!(calibrate, Some(server(1)));
+        assert!(source.controller.measurements.is_empty());
+
+        // Broadcasts are not used during the calibration
+        let calibrate = source.handle_broadcast(&broadcast(seconds(2.0)), server(1), seconds(2.0));
+        assert_eq!(calibrate, None);
+        assert!(source.controller.measurements.is_empty());
+
+        let mut sent = 0;
+        while let Some(request) = source.calibration_request() {
+            sent += 1;
+            let (request, _) = NtpPacket::deserialize(&request, &NoCipher).unwrap();
+            let mut response = NtpPacket::test();
+            response.set_mode(NtpAssociationMode::Server);
+            response.set_stratum(2);
+            response.set_origin_timestamp(request.transmit_timestamp());
+            response.set_receive_timestamp(seconds(3.0));
+            response.set_transmit_timestamp(seconds(3.0));
+            let response = response.serialize_without_encryption_vec(None).unwrap();
+
+            // Roundtrip of 10ms, 20ms, ...
+            let roundtrip = 0.010 * sent as f64;
+            source.handle_calibration_response(&response, seconds(3.0), seconds(3.0 + roundtrip));
+            // Duplicates are ignored
+            source.handle_calibration_response(&response, seconds(3.0), seconds(3.0));
+        }
+        assert_eq!(sent, CALIBRATION_EXCHANGES);
+
+        // The smallest delay is used
+        source.handle_broadcast(&broadcast(seconds(4.0)), server(1), seconds(4.0));
+        let measurement = source.controller.measurements[0];
+        assert!(((measurement.sender_ts - seconds(4.0)).to_seconds() - 0.005).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_failed_calibration() {
+        let mut source = source(None);
+
+        assert_eq!(
+            source.handle_broadcast(&broadcast(seconds(1.0)), server(1), seconds(1.0)),
+            Some(server(1))
+        );
+        while source.calibration_request().is_some() {}
+
+        // Without responses, we try again on the next broadcast
+        assert_eq!(
+            source.handle_broadcast(&broadcast(seconds(2.0)), server(1), seconds(2.0)),
+            Some(server(1))
+        );
+        assert!(source.controller.measurements.is_empty());
+    }
+}
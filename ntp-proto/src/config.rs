@@ -1,12 +1,18 @@
+#[cfg(feature = "serde")]
 use std::fmt;
+use std::net::SocketAddr;
 
+#[cfg(feature = "serde")]
 use serde::{
     Deserialize, Deserializer,
     de::{self, MapAccess, Unexpected, Visitor},
 };
 
+#[cfg(feature = "serde")]
+use crate::time_types::deserialize_seconds;
 use crate::time_types::{NtpDuration, PollInterval, PollIntervalLimits};
 
+#[cfg(feature = "serde")]
 fn deserialize_option_accumulated_step_panic_threshold<'de, D>(
     deserializer: D,
 ) -> Result<Option<NtpDuration>, D::Error>
@@ -21,6 +27,21 @@ where
     })
 }
 
+#[cfg(feature = "serde")]
+fn deserialize_option_ppm<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let ppm = f64::deserialize(deserializer)?;
+    if !(ppm.is_finite() && ppm > 0.0) {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(ppm),
+            &"a positive rate in ppm",
+        ));
+    }
+    Ok(Some(ppm))
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct ReferenceIdConfig {
     id: u32,
@@ -33,6 +54,7 @@ impl ReferenceIdConfig {
 }
 
 // Deserialize from the string type in config
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for ReferenceIdConfig {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -83,9 +105,11 @@ impl StepThreshold {
     }
 }
 
+#[cfg(feature = "serde")]
 #[derive(Debug, Copy, Clone)]
 struct ThresholdPart(Option<NtpDuration>);
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for ThresholdPart {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -97,7 +121,7 @@ impl<'de> Deserialize<'de> for ThresholdPart {
             type Value = ThresholdPart;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("float or \"inf\"")
+                formatter.write_str("float, duration or \"inf\"")
             }
 
             fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
@@ -125,13 +149,11 @@ impl<'de> Deserialize<'de> for ThresholdPart {
             where
                 E: de::Error,
             {
-                if v != "inf" {
-                    return Err(de::Error::invalid_value(
-                        de::Unexpected::Str(v),
-                        &"float or \"inf\"",
-                    ));
+                if v == "inf" {
+                    return Ok(ThresholdPart(None));
                 }
-                Ok(ThresholdPart(None))
+                let duration: NtpDuration = v.parse().map_err(de::Error::custom)?;
+                Ok(ThresholdPart(Some(duration)))
             }
         }
 
@@ -141,6 +163,7 @@ impl<'de> Deserialize<'de> for ThresholdPart {
 
 // We have a custom deserializer for StepThreshold because we
 // want to deserialize it from either a number or map
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for StepThreshold {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -152,7 +175,7 @@ impl<'de> Deserialize<'de> for StepThreshold {
             type Value = StepThreshold;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("float, map or \"inf\"")
+                formatter.write_str("float, duration, map or \"inf\"")
             }
 
             fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
@@ -192,15 +215,24 @@ impl<'de> Deserialize<'de> for StepThreshold {
             where
                 E: de::Error,
             {
-                if v != "inf" {
+                if v == "inf" {
+                    return Ok(StepThreshold {
+                        forward: None,
+                        backward: None,
+                    });
+                }
+
+                let duration: NtpDuration = v.parse().map_err(de::Error::custom)?;
+                if duration < NtpDuration::ZERO {
                     return Err(de::Error::invalid_value(
-                        de::Unexpected::Str(v),
-                        &"float, map or \"inf\"",
+                        Unexpected::Str(v),
+                        &"a positive duration",
                     ));
                 }
+
                 Ok(StepThreshold {
-                    forward: None,
-                    backward: None,
+                    forward: Some(duration),
+                    backward: Some(duration),
                 })
             }
 
@@ -244,16 +276,112 @@ impl<'de> Deserialize<'de> for StepThreshold {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
-#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "The flags mirror independent per-source options of the configuration file."
+)]
 pub struct SourceConfig {
     /// Minima and maxima for the poll interval of clients
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub poll_interval_limits: PollIntervalLimits,
 
     /// Initial poll interval of the system
-    #[serde(default = "default_initial_poll_interval")]
+    #[cfg_attr(feature = "serde", serde(default = "default_initial_poll_interval"))]
     pub initial_poll_interval: PollInterval,
+
+    /// Burst of polls sent when the source is first mobilized
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub initial_burst: InitialBurst,
+
+    /// Relative weight of the source when combining the sources that
+    /// survive selection. Only configurable per source.
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_deserializing, default = "default_weight")
+    )]
+    pub weight: f64,
+
+    /// When this source survives selection, use it as the sole basis for
+    /// the time estimate. Only configurable per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub prefer: bool,
+
+    /// Measure the source, but never use it for synchronization. Only
+    /// configurable per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub noselect: bool,
+
+    /// Assume this source is correct during selection, so that untrusted
+    /// sources can't outvote it. Only configurable per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub trust: bool,
+
+    /// Only update the clock when this source, or another required one,
+    /// survives selection. Only configurable per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub require: bool,
+
+    /// Number of recent raw measurements kept per source for observation,
+    /// 0 disables the history
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_measurement_history",
+            deserialize_with = "deserialize_measurement_history"
+        )
+    )]
+    pub measurement_history: usize,
+
+    /// Ask servers to respond in interleaved mode, in which they report the
+    /// more accurate transmit timestamp of their previous response
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub interleaved: bool,
+
+    /// Factor by which the poll interval is increased when a server sends a
+    /// RATE kiss-o'-death. Must be a power of two, as poll intervals are.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_rate_kod_backoff",
+            deserialize_with = "deserialize_rate_kod_backoff"
+        )
+    )]
+    pub rate_kod_backoff: u32,
+
+    /// Number of responses in a row that fail NTS or MAC authentication
+    /// after which the source is demobilized, 0 never demobilizes
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_authentication_failures: u32,
+
+    /// Asymmetry of the network path to the source, which is corrected for
+    /// when computing offsets. Only configurable per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub delay_asymmetry: DelayAsymmetry,
+
+    /// Constant in seconds added to the offset of every measurement, to
+    /// compensate for known errors of the source. Only configurable per
+    /// source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub offset_correction: f64,
+
+    /// Known delay in seconds of cables and processing. It is subtracted from
+    /// the measured delay of two-way sources, and as one-way sources can't
+    /// measure their delay it is added to their offset instead. Only
+    /// configurable per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub delay_correction: f64,
+
+    /// Local address the socket of the source is bound to, with a port of 0
+    /// leaving the choice of port to the operating system. Only configurable
+    /// per source.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    pub local_address: Option<SocketAddr>,
 }
 
 impl Default for SourceConfig {
@@ -261,16 +389,170 @@ impl Default for SourceConfig {
         Self {
             poll_interval_limits: PollIntervalLimits::default(),
             initial_poll_interval: default_initial_poll_interval(),
+            initial_burst: InitialBurst::default(),
+            weight: default_weight(),
+            prefer: false,
+            noselect: false,
+            trust: false,
+            require: false,
+            measurement_history: default_measurement_history(),
+            interleaved: false,
+            rate_kod_backoff: default_rate_kod_backoff(),
+            max_authentication_failures: 0,
+            delay_asymmetry: DelayAsymmetry::default(),
+            offset_correction: 0.0,
+            delay_correction: 0.0,
+            local_address: None,
         }
     }
 }
 
+pub(crate) fn default_weight() -> f64 {
+    1.0
+}
+
+// Keeps the observation output of the daemon well within the limits of the
+// observation socket, even with many sources.
+#[cfg(feature = "serde")]
+const MAX_MEASUREMENT_HISTORY: usize = 256;
+
+fn default_measurement_history() -> usize {
+    32
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_measurement_history<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let size: usize = Deserialize::deserialize(deserializer)?;
+    if size > MAX_MEASUREMENT_HISTORY {
+        return Err(de::Error::invalid_value(
+            Unexpected::Unsigned(size as u64),
+            &"a history size of at most 256",
+        ));
+    }
+    Ok(size)
+}
+
+fn default_rate_kod_backoff() -> u32 {
+    2
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_rate_kod_backoff<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let factor: u32 = Deserialize::deserialize(deserializer)?;
+    if !(2..=256).contains(&factor) || !factor.is_power_of_two() {
+        return Err(de::Error::invalid_value(
+            Unexpected::Unsigned(factor.into()),
+            &"a power of two between 2 and 256",
+        ));
+    }
+    Ok(factor)
+}
+
 fn default_initial_poll_interval() -> PollInterval {
     PollIntervalLimits::default().min
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
-#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+/// A short burst of polls sent right after a source is mobilized, which
+/// provides the algorithm with enough measurements to start steering the
+/// clock within seconds instead of after several poll intervals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct InitialBurst {
+    /// Number of polls in the burst, 0 disables the burst
+    #[cfg_attr(feature = "serde", serde(default = "default_initial_burst_count"))]
+    pub count: u32,
+
+    /// Time between the polls of the burst
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_initial_burst_spacing",
+            deserialize_with = "deserialize_initial_burst_spacing"
+        )
+    )]
+    pub spacing: NtpDuration,
+}
+
+impl InitialBurst {
+    pub const DISABLED: InitialBurst = InitialBurst {
+        count: 0,
+        spacing: NtpDuration::ZERO,
+    };
+}
+
+impl Default for InitialBurst {
+    fn default() -> Self {
+        Self {
+            count: default_initial_burst_count(),
+            spacing: default_initial_burst_spacing(),
+        }
+    }
+}
+
+/// Asymmetry of the network path to a source. Offsets are computed assuming
+/// that requests and responses take equally long to arrive, any difference
+/// between the two turns into an error in the offset.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub enum DelayAsymmetry {
+    /// Both directions take equally long
+    #[default]
+    None,
+    /// Fraction of the round trip delay taken by requests on their way to
+    /// the source, 0.5 for a symmetric path
+    Fraction(f64),
+    /// Time (in seconds) by which the path to the source is slower than half
+    /// the round trip delay, negative when it is faster
+    Absolute(f64),
+    /// Estimate the part of the asymmetry that varies with the delay, such
+    /// as queueing on only one direction of the path, from the correlation
+    /// between measured offsets and delays
+    Auto,
+}
+
+fn default_initial_burst_count() -> u32 {
+    4
+}
+
+fn default_initial_burst_spacing() -> NtpDuration {
+    NtpDuration::from_seconds(2.0)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_initial_burst_spacing<'de, D>(deserializer: D) -> Result<NtpDuration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let spacing: NtpDuration = Deserialize::deserialize(deserializer)?;
+    if spacing <= NtpDuration::ZERO {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(spacing.to_seconds()),
+            &"a positive number of seconds",
+        ));
+    }
+    Ok(spacing)
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
 pub struct SynchronizationConfig {
     /// Minimum number of survivors needed to be able to discipline the system clock.
     /// More survivors (so more servers from which to get the time) means a more accurate time.
@@ -280,9 +562,17 @@ pub struct SynchronizationConfig {
     /// > CMIN defines the minimum number of servers consistent with the correctness requirements.
     /// > Suspicious operators would set CMIN to ensure multiple redundant servers are available for the
     /// > algorithms to mitigate properly. However, for historic reasons the default value for CMIN is one.
-    #[serde(default = "default_minimum_agreeing_sources")]
+    #[cfg_attr(feature = "serde", serde(default = "default_minimum_agreeing_sources"))]
     pub minimum_agreeing_sources: usize,
 
+    /// Minimum number of survivors needed to keep disciplining the system
+    /// clock once it is synchronized. Can be lower than
+    /// `minimum_agreeing_sources`, so losing a source doesn't immediately
+    /// stop steering, while still requiring full agreement to start.
+    /// Defaults to `minimum_agreeing_sources`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub maintain_agreeing_sources: Option<usize>,
+
     /// The maximum amount the system clock is allowed to change in a single go
     /// before we conclude something is seriously wrong. This is used to limit
     /// the changes to the clock to reasonable amounts, and stop issues with
@@ -290,29 +580,75 @@ pub struct SynchronizationConfig {
     ///
     /// Note that this is not used during startup. To limit system clock changes
     /// during startup, use startup_panic_threshold
-    #[serde(default = "default_single_step_panic_threshold")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_single_step_panic_threshold")
+    )]
     pub single_step_panic_threshold: StepThreshold,
 
     /// The maximum amount the system clock is allowed to change during startup.
     /// This can be used to limit the impact of bad servers if the system clock
     /// is known to be reasonable on startup
-    #[serde(default = "default_startup_step_panic_threshold")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_startup_step_panic_threshold")
+    )]
     pub startup_step_panic_threshold: StepThreshold,
 
     /// The maximum amount distributed amongst all steps except at startup the
     /// daemon is allowed to step the system clock.
-    #[serde(
-        deserialize_with = "deserialize_option_accumulated_step_panic_threshold",
-        default
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "deserialize_option_accumulated_step_panic_threshold",
+            default
+        )
     )]
     pub accumulated_step_panic_threshold: Option<NtpDuration>,
 
+    /// Number of times the clock may be stepped after startup. Once used up,
+    /// offsets beyond the step threshold are slewed instead. None allows any
+    /// number of steps.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub step_limit: Option<u32>,
+
+    /// Largest offset that is slewed when no more steps are allowed. Larger
+    /// offsets are treated like exceeding a panic threshold.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub maximum_slew_offset: Option<NtpDuration>,
+
+    /// Largest frequency offset used to slew away an offset, on top of the
+    /// algorithm's own limit (ppm)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "deserialize_option_ppm")
+    )]
+    pub maximum_slew_rate: Option<f64>,
+
+    /// Largest total frequency correction applied to the clock, on top of the
+    /// algorithm's own limit (ppm)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "deserialize_option_ppm")
+    )]
+    pub maximum_frequency_offset: Option<f64>,
+
     /// Stratum of the local clock, when not synchronized through ntp. This
     /// can be used in servers to indicate that there are external mechanisms
     /// synchronizing the clock
-    #[serde(default = "default_local_stratum")]
+    #[cfg_attr(feature = "serde", serde(default = "default_local_stratum"))]
     pub local_stratum: u8,
 
+    /// Sources reporting a stratum below this are not used for
+    /// synchronization
+    #[cfg_attr(feature = "serde", serde(default = "default_minimum_source_stratum"))]
+    pub minimum_source_stratum: u8,
+
+    /// Sources reporting a stratum above this are not used for
+    /// synchronization, such as orphaned servers advertising a high stratum
+    #[cfg_attr(feature = "serde", serde(default = "default_maximum_source_stratum"))]
+    pub maximum_source_stratum: u8,
+
     /// Reference ID for clock synchronization. When stratum is 1 this value
     /// is used - the value is left justified, limited to four characters
     /// and zero padded.
@@ -350,27 +686,60 @@ pub struct SynchronizationConfig {
     ///
     /// When the local-stratum not 1 the reference-id is ignored.
     ///
-    #[serde(default = "default_reference_id")]
+    #[cfg_attr(feature = "serde", serde(default = "default_reference_id"))]
     pub reference_id: ReferenceIdConfig,
 
     /// Should a warning be emitted on jumps in the clock
-    #[serde(default = "default_warn_on_jump")]
+    #[cfg_attr(feature = "serde", serde(default = "default_warn_on_jump"))]
     pub warn_on_jump: bool,
+
+    /// Smear leap seconds out over a window around the leap instead of
+    /// inserting or deleting them at once. When enabled, leap seconds are
+    /// never passed on to the kernel or to our clients.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leap_smear: Option<LeapSmearConfig>,
+
+    /// Keep considering the clock synchronized for a limited time after its
+    /// last update, running on its last frequency estimate, before marking
+    /// it unsynchronized
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub holdover: Option<HoldoverConfig>,
+}
+
+impl SynchronizationConfig {
+    /// Number of sources that need to agree before the clock is steered,
+    /// depending on whether the clock is already `synchronized`
+    pub fn required_agreeing_sources(&self, synchronized: bool) -> usize {
+        match self.maintain_agreeing_sources {
+            Some(maintain) if synchronized => maintain.min(self.minimum_agreeing_sources),
+            _ => self.minimum_agreeing_sources,
+        }
+    }
 }
 
 impl Default for SynchronizationConfig {
     fn default() -> Self {
         Self {
             minimum_agreeing_sources: default_minimum_agreeing_sources(),
+            maintain_agreeing_sources: None,
 
             single_step_panic_threshold: default_single_step_panic_threshold(),
             startup_step_panic_threshold: default_startup_step_panic_threshold(),
             accumulated_step_panic_threshold: None,
+            step_limit: None,
+            maximum_slew_offset: None,
+            maximum_slew_rate: None,
+            maximum_frequency_offset: None,
 
             local_stratum: default_local_stratum(),
+            minimum_source_stratum: default_minimum_source_stratum(),
+            maximum_source_stratum: default_maximum_source_stratum(),
             reference_id: default_reference_id(),
 
             warn_on_jump: default_warn_on_jump(),
+
+            leap_smear: None,
+            holdover: None,
         }
     }
 }
@@ -407,6 +776,155 @@ fn default_local_stratum() -> u8 {
     16
 }
 
+fn default_minimum_source_stratum() -> u8 {
+    1
+}
+
+fn default_maximum_source_stratum() -> u8 {
+    15
+}
+
 fn default_warn_on_jump() -> bool {
     true
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct LeapSmearConfig {
+    /// Length of the window, centered on the leap second, over which the
+    /// leap second is smeared (seconds)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_leap_smear_window",
+            deserialize_with = "deserialize_leap_smear_window"
+        )
+    )]
+    pub window: f64,
+    /// Shape of the smear over the window
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub curve: LeapSmearCurve,
+}
+
+impl Default for LeapSmearConfig {
+    fn default() -> Self {
+        Self {
+            window: default_leap_smear_window(),
+            curve: LeapSmearCurve::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum LeapSmearCurve {
+    /// Constant frequency offset over the entire window
+    #[default]
+    Linear,
+    /// Frequency offset following a cosine, gradually starting and ending
+    Cosine,
+}
+
+fn default_leap_smear_window() -> f64 {
+    // Noon to noon around the leap second
+    86400.0
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_leap_smear_window<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let window = deserialize_seconds(deserializer)?;
+    // The window is centered on the leap second, which is at most a day away
+    // when it is announced.
+    if !(window > 0.0 && window <= 2.0 * 86400.0) {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(window),
+            &"a window between 0 and 172800 seconds",
+        ));
+    }
+    Ok(window)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct HoldoverConfig {
+    /// Time after the last update of the clock during which it is still
+    /// considered synchronized (seconds)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_holdover_duration",
+            deserialize_with = "deserialize_holdover_duration"
+        )
+    )]
+    pub duration: f64,
+    /// Rate at which the advertised root dispersion grows after the last
+    /// update of the clock (seconds per second)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_holdover_dispersion_rate",
+            deserialize_with = "deserialize_holdover_dispersion_rate"
+        )
+    )]
+    pub dispersion_rate: f64,
+}
+
+impl Default for HoldoverConfig {
+    fn default() -> Self {
+        Self {
+            duration: default_holdover_duration(),
+            dispersion_rate: default_holdover_dispersion_rate(),
+        }
+    }
+}
+
+fn default_holdover_duration() -> f64 {
+    3600.0
+}
+
+fn default_holdover_dispersion_rate() -> f64 {
+    // The frequency tolerance of RFC 5905 (PHI)
+    15e-6
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_holdover_duration<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = deserialize_seconds(deserializer)?;
+    if duration <= 0.0 {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(duration),
+            &"a positive duration",
+        ));
+    }
+    Ok(duration)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_holdover_dispersion_rate<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rate = f64::deserialize(deserializer)?;
+    if !(rate.is_finite() && rate >= 0.0) {
+        return Err(de::Error::invalid_value(
+            Unexpected::Float(rate),
+            &"a non-negative rate",
+        ));
+    }
+    Ok(rate)
+}
@@ -1,4 +1,4 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
 #[cfg(feature = "rustcrypto")]
 use md5::{Digest, Md5};
@@ -6,9 +6,16 @@ use md5::{Digest, Md5};
 #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
 use openssl::hash::{MessageDigest, hash};
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Reference identifier of an NTP packet, which identifies the source a
+/// server synchronizes to or carries a kiss code.
+///
+/// With serde it is represented as a 32-bit number, with the first byte of
+/// the identifier as the most significant byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ReferenceId(u32);
 
 impl ReferenceId {
@@ -19,6 +26,11 @@ impl ReferenceId {
     pub const NONE: ReferenceId = ReferenceId(u32::from_be_bytes(*b"XNON"));
     pub const SOCK: ReferenceId = ReferenceId(u32::from_be_bytes(*b"SOCK"));
     pub const PPS: ReferenceId = ReferenceId(u32::from_be_bytes(*b"PPS\0"));
+    pub const ROUGHTIME: ReferenceId = ReferenceId(u32::from_be_bytes(*b"ROUG"));
+    pub const GPS: ReferenceId = ReferenceId(u32::from_be_bytes(*b"GPS\0"));
+    pub const PHC: ReferenceId = ReferenceId(u32::from_be_bytes(*b"PHC\0"));
+    pub const PTP: ReferenceId = ReferenceId(u32::from_be_bytes(*b"PTP\0"));
+    pub const SHM: ReferenceId = ReferenceId(u32::from_be_bytes(*b"SHM\0"));
 
     // Network Time Security (NTS) negative-acknowledgment (NAK), from rfc8915
     pub const KISS_NTSN: ReferenceId = ReferenceId(u32::from_be_bytes(*b"NTSN"));
@@ -57,6 +69,42 @@ impl ReferenceId {
         *self == Self::KISS_NTSN
     }
 
+    /// The ASCII code carried by kiss packets and stratum 1 servers, like
+    /// "RATE" or "GPS". Returns `None` when the id is not printable.
+    pub fn as_code(self) -> Option<String> {
+        let bytes = self.to_bytes();
+        // Codes shorter than four characters are padded with zeros (or
+        // spaces, by some implementations)
+        let length = bytes.iter().rposition(|&b| b != 0)? + 1;
+        let code = &bytes[..length];
+        if !code.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(code).trim_end().to_owned()).filter(|code| !code.is_empty())
+    }
+
+    /// The id with an ASCII code of one to four characters, padded with
+    /// zeros. The inverse of [`ReferenceId::as_code`].
+    pub fn from_code(code: &str) -> Option<ReferenceId> {
+        if code.is_empty() || code.len() > 4 || !code.bytes().all(|b| b.is_ascii_graphic()) {
+            return None;
+        }
+
+        let mut bytes = [0; 4];
+        bytes[..code.len()].copy_from_slice(code.as_bytes());
+        Some(ReferenceId::from_bytes(bytes))
+    }
+
+    /// Human readable form of the id, which depends on the stratum of the
+    /// packet or system it belongs to. For strata 2 to 15 the id identifies
+    /// the upstream server and is shown as an IPv4 address. Note that for
+    /// IPv6 servers this is the first four bytes of the MD5 hash of the
+    /// address instead, see [`ReferenceId::from_ip`].
+    pub fn display(self, stratum: u8) -> ReferenceIdDisplay {
+        ReferenceIdDisplay { id: self, stratum }
+    }
+
     pub(crate) fn to_bytes(self) -> [u8; 4] {
         self.0.to_be_bytes()
     }
@@ -66,6 +114,23 @@ impl ReferenceId {
     }
 }
 
+/// Helper for showing a [`ReferenceId`], created by [`ReferenceId::display`]
+#[derive(Debug, Clone, Copy)]
+pub struct ReferenceIdDisplay {
+    id: ReferenceId,
+    stratum: u8,
+}
+
+impl std::fmt::Display for ReferenceIdDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.stratum, self.id.as_code()) {
+            (2..=15, _) => write!(f, "{}", Ipv4Addr::from(self.id.0)),
+            (_, Some(code)) => f.write_str(&code),
+            (_, None) => write!(f, "{:#010x}", self.id.0),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -80,6 +145,58 @@ mod tests {
         assert_eq!(b, d);
     }
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn referenceid_serde_representation() {
+        let json = serde_json::to_string(&ReferenceId::KISS_RATE).unwrap();
+        assert_eq!(json, "1380013125");
+        assert_eq!(
+            serde_json::from_str::<ReferenceId>(&json).unwrap(),
+            ReferenceId::KISS_RATE
+        );
+    }
+
+    #[test]
+    fn referenceid_from_code() {
+        assert_eq!(ReferenceId::from_code("SOCK"), Some(ReferenceId::SOCK));
+        assert_eq!(ReferenceId::from_code("PPS"), Some(ReferenceId::PPS));
+        assert_eq!(
+            ReferenceId::from_code("GPS1").and_then(ReferenceId::as_code),
+            Some("GPS1".to_owned())
+        );
+        assert_eq!(ReferenceId::from_code(""), None);
+        assert_eq!(ReferenceId::from_code("GNSS1"), None);
+        assert_eq!(ReferenceId::from_code("A B"), None);
+    }
+
+    #[test]
+    fn referenceid_display() {
+        assert_eq!(ReferenceId::KISS_RATE.display(0).to_string(), "RATE");
+        assert_eq!(ReferenceId::PPS.display(1).to_string(), "PPS");
+        assert_eq!(ReferenceId::NONE.display(16).to_string(), "XNON");
+        assert_eq!(
+            ReferenceId::from_bytes(*b"GPS ").display(1).to_string(),
+            "GPS"
+        );
+        assert_eq!(
+            ReferenceId::from_bytes([0x01, 0x02, 0x03, 0x04])
+                .display(1)
+                .to_string(),
+            "0x01020304"
+        );
+        assert_eq!(
+            ReferenceId::from_int(0).display(1).to_string(),
+            "0x00000000"
+        );
+
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(ReferenceId::from_ip(ip).display(2).to_string(), "192.0.2.1");
+
+        assert_eq!(ReferenceId::from_bytes(*b"ATOM").as_code().unwrap(), "ATOM");
+        assert_eq!(ReferenceId::from_bytes(*b"A\0OM").as_code(), None);
+        assert_eq!(ReferenceId::from_bytes(*b"\xffPS\0").as_code(), None);
+    }
+
     #[test]
     fn referenceid_kiss_codes() {
         let a = [b'R', b'A', b'T', b'E'];
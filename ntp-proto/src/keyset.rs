@@ -3,14 +3,34 @@ use std::{
     sync::Arc,
 };
 
+use rand::Rng;
+
 use crate::{
     nts::AeadAlgorithm,
     packet::{
-        AesSivCmac256, AesSivCmac512, Cipher, CipherHolder, CipherProvider, DecryptError,
-        EncryptResult, ExtensionField,
+        Aes128GcmSiv, AesSivCmac256, AesSivCmac512, Cipher, CipherHolder, CipherProvider,
+        DecryptError, EncryptResult, EncryptWithNonce, ExtensionField,
     },
 };
 
+/// Layout of the encrypted part of a cookie, given by its first byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CookieVersion {
+    /// The algorithm (u16) followed by both keys. Cookies from before there
+    /// was a version byte: all algorithm identifiers fit in one byte, so
+    /// these start with a zero.
+    Legacy = 0,
+    /// The version, algorithm (u16) and key length (u16), followed by both
+    /// keys. Anything after the keys is ignored, so fields can be added
+    /// without a new version that older servers would reject.
+    V1 = 1,
+}
+
+impl CookieVersion {
+    /// The version of newly issued cookies
+    pub(crate) const CURRENT: Self = CookieVersion::V1;
+}
+
 pub struct DecodedServerCookie {
     pub(crate) algorithm: AeadAlgorithm,
     pub s2c: Box<dyn Cipher>,
@@ -18,16 +38,81 @@ pub struct DecodedServerCookie {
 }
 
 impl DecodedServerCookie {
-    fn plaintext(&self) -> Vec<u8> {
+    pub(crate) fn plaintext(&self, version: CookieVersion) -> Vec<u8> {
         let mut plaintext = Vec::new();
 
         let algorithm_bytes = u16::from(self.algorithm).to_be_bytes();
-        plaintext.extend_from_slice(&algorithm_bytes);
+        match version {
+            CookieVersion::Legacy => {
+                debug_assert_eq!(algorithm_bytes[0], 0);
+                plaintext.extend_from_slice(&algorithm_bytes);
+            }
+            CookieVersion::V1 => {
+                let key_length = self.s2c.key_bytes().len() as u16;
+                plaintext.push(CookieVersion::V1 as u8);
+                plaintext.extend_from_slice(&algorithm_bytes);
+                plaintext.extend_from_slice(&key_length.to_be_bytes());
+            }
+        }
         plaintext.extend_from_slice(self.s2c.key_bytes());
         plaintext.extend_from_slice(self.c2s.key_bytes());
 
         plaintext
     }
+
+    pub(crate) fn from_key_bytes(
+        algorithm: AeadAlgorithm,
+        key_bytes: &[u8],
+    ) -> Result<Self, DecryptError> {
+        Ok(match algorithm {
+            AeadAlgorithm::AeadAesSivCmac256 => {
+                const KEY_WIDTH: usize = 32;
+
+                if key_bytes.len() != 2 * KEY_WIDTH {
+                    return Err(DecryptError);
+                }
+
+                let (s2c, c2s) = key_bytes.split_at(KEY_WIDTH);
+
+                DecodedServerCookie {
+                    algorithm,
+                    s2c: Box::new(AesSivCmac256::try_from(s2c).unwrap()),
+                    c2s: Box::new(AesSivCmac256::try_from(c2s).unwrap()),
+                }
+            }
+            AeadAlgorithm::AeadAesSivCmac512 => {
+                const KEY_WIDTH: usize = 64;
+
+                if key_bytes.len() != 2 * KEY_WIDTH {
+                    return Err(DecryptError);
+                }
+
+                let (s2c, c2s) = key_bytes.split_at(KEY_WIDTH);
+
+                DecodedServerCookie {
+                    algorithm,
+                    s2c: Box::new(AesSivCmac512::try_from(s2c).unwrap()),
+                    c2s: Box::new(AesSivCmac512::try_from(c2s).unwrap()),
+                }
+            }
+            AeadAlgorithm::AeadAes128GcmSiv => {
+                const KEY_WIDTH: usize = 16;
+
+                if key_bytes.len() != 2 * KEY_WIDTH {
+                    return Err(DecryptError);
+                }
+
+                let (s2c, c2s) = key_bytes.split_at(KEY_WIDTH);
+
+                DecodedServerCookie {
+                    algorithm,
+                    s2c: Box::new(Aes128GcmSiv::try_from(s2c).unwrap()),
+                    c2s: Box::new(Aes128GcmSiv::try_from(c2s).unwrap()),
+                }
+            }
+            AeadAlgorithm::Unknown(_) => return Err(DecryptError),
+        })
+    }
 }
 
 impl std::fmt::Debug for DecodedServerCookie {
@@ -54,6 +139,7 @@ impl KeySetProvider {
                 keys: vec![AesSivCmac512::new_random()],
                 id_offset: 0,
                 primary: 0,
+                created: std::time::SystemTime::now(),
             }),
             history,
         }
@@ -66,6 +152,7 @@ impl KeySetProvider {
                 keys: vec![AesSivCmac512::new(std::array::from_fn(|i| i as u8).into())],
                 id_offset: 0,
                 primary: 0,
+                created: std::time::SystemTime::now(),
             }),
             history,
         }
@@ -89,6 +176,7 @@ impl KeySetProvider {
                 .wrapping_add(self.current.keys.len().saturating_sub(self.history) as u32),
             primary: keys.len() as u32 - 1,
             keys,
+            created: std::time::SystemTime::now(),
         });
     }
 
@@ -103,20 +191,27 @@ impl KeySetProvider {
         let id_offset = u32::from_be_bytes(buf[8..12].try_into().unwrap());
         let primary = u32::from_be_bytes(buf[12..16].try_into().unwrap());
         let len = u32::from_be_bytes(buf[16..20].try_into().unwrap());
-        if primary > len {
-            return Err(std::io::ErrorKind::Other.into());
+        if primary >= len {
+            return Err(std::io::ErrorKind::InvalidData.into());
         }
         let mut keys = vec![];
         for _ in 0..len {
             reader.read_exact(&mut buf[0..64])?;
             keys.push(AesSivCmac512::try_from(buf).unwrap());
         }
+
+        // The history may have been shortened since the keys were stored,
+        // forget the keys that are no longer retained.
+        let stale = (primary as usize).saturating_sub(history);
+        keys.drain(..stale);
+
         Ok((
             KeySetProvider {
                 current: Arc::new(KeySet {
                     keys,
-                    id_offset,
-                    primary,
+                    id_offset: id_offset.wrapping_add(stale as u32),
+                    primary: primary - stale as u32,
+                    created: time,
                 }),
                 history,
             },
@@ -148,16 +243,56 @@ pub struct KeySet {
     keys: Vec<AesSivCmac512>,
     id_offset: u32,
     primary: u32,
+    created: std::time::SystemTime,
 }
 
 impl KeySet {
+    /// When the primary key came into use. For a loaded key set this is when
+    /// it was stored.
+    pub fn created(&self) -> std::time::SystemTime {
+        self.created
+    }
+
     #[cfg(feature = "__internal-fuzz")]
     pub fn encode_cookie_pub(&self, cookie: &DecodedServerCookie) -> Vec<u8> {
         self.encode_cookie(cookie)
     }
 
     pub(crate) fn encode_cookie(&self, cookie: &DecodedServerCookie) -> Vec<u8> {
-        let mut output = cookie.plaintext();
+        self.seal(cookie.plaintext(CookieVersion::CURRENT))
+    }
+
+    /// Encode `cookie` in the newest format that takes at most `max_length`
+    /// bytes. Clients that got their cookies from an older version only
+    /// leave room for cookies in the older format, so they keep getting those
+    /// until their next key exchange.
+    pub(crate) fn encode_cookie_within(
+        &self,
+        cookie: &DecodedServerCookie,
+        max_length: usize,
+    ) -> Option<Vec<u8>> {
+        [CookieVersion::CURRENT, CookieVersion::Legacy]
+            .into_iter()
+            .map(|version| cookie.plaintext(version))
+            .find(|plaintext| Self::cookie_length(plaintext.len()) <= max_length)
+            .map(|plaintext| self.seal(plaintext))
+    }
+
+    /// Length of an encrypted cookie for a plaintext of the given length:
+    /// header (4 + 2 bytes), nonce (16 bytes), the plaintext and the
+    /// additional ciphertext data from the cmac (16 bytes)
+    fn cookie_length(plaintext_length: usize) -> usize {
+        4 + 2 + 16 + plaintext_length + 16
+    }
+
+    /// Encrypt the plaintext of a cookie with the primary key
+    fn seal(&self, output: Vec<u8>) -> Vec<u8> {
+        let nonce: [u8; 16] = rand::thread_rng().r#gen();
+        self.seal_with_nonce(output, &nonce)
+    }
+
+    /// Encrypt the plaintext of a cookie with the primary key and `nonce`
+    pub(crate) fn seal_with_nonce(&self, mut output: Vec<u8>, nonce: &[u8; 16]) -> Vec<u8> {
         let plaintext_length = output.as_slice().len();
 
         // Add space for header (4 + 2 bytes), additional ciphertext
@@ -170,7 +305,7 @@ impl KeySet {
             nonce_length,
             ciphertext_length,
         } = self.keys[self.primary as usize]
-            .encrypt(&mut output[6..], plaintext_length, &[])
+            .encrypt_with_nonce(&mut output[6..], plaintext_length, &[], nonce)
             .expect("Failed to encrypt cookie");
 
         debug_assert_eq!(nonce_length, 16);
@@ -179,6 +314,7 @@ impl KeySet {
         output[0..4].copy_from_slice(&(self.primary.wrapping_add(self.id_offset)).to_be_bytes());
         output[4..6].copy_from_slice(&(ciphertext_length as u16).to_be_bytes());
         debug_assert_eq!(output.len(), 6 + nonce_length + ciphertext_length);
+        debug_assert_eq!(output.len(), Self::cookie_length(plaintext_length));
         output
     }
 
@@ -203,45 +339,37 @@ impl KeySet {
         let ciphertext = cookie[22..].get(..cipher_text_length).ok_or(DecryptError)?;
         let plaintext = key.decrypt(nonce, ciphertext, &[])?;
 
-        let [b0, b1, ref key_bytes @ ..] = plaintext[..] else {
-            return Err(DecryptError);
-        };
-
-        let algorithm = AeadAlgorithm::from(u16::from_be_bytes([b0, b1]));
-
-        Ok(match algorithm {
-            AeadAlgorithm::AeadAesSivCmac256 => {
-                const KEY_WIDTH: usize = 32;
-
-                if key_bytes.len() != 2 * KEY_WIDTH {
+        match plaintext.first().copied() {
+            Some(version) if version == CookieVersion::Legacy as u8 => {
+                let [b0, b1, ref key_bytes @ ..] = plaintext[..] else {
                     return Err(DecryptError);
-                }
-
-                let (s2c, c2s) = key_bytes.split_at(KEY_WIDTH);
-
-                DecodedServerCookie {
-                    algorithm,
-                    s2c: Box::new(AesSivCmac256::try_from(s2c).unwrap()),
-                    c2s: Box::new(AesSivCmac256::try_from(c2s).unwrap()),
-                }
+                };
+                let algorithm = AeadAlgorithm::from(u16::from_be_bytes([b0, b1]));
+                DecodedServerCookie::from_key_bytes(algorithm, key_bytes)
             }
-            AeadAlgorithm::AeadAesSivCmac512 => {
-                const KEY_WIDTH: usize = 64;
-
-                if key_bytes.len() != 2 * KEY_WIDTH {
+            Some(version) if version == CookieVersion::V1 as u8 => {
+                let [_, a0, a1, l0, l1, ref rest @ ..] = plaintext[..] else {
                     return Err(DecryptError);
-                }
-
-                let (s2c, c2s) = key_bytes.split_at(KEY_WIDTH);
-
-                DecodedServerCookie {
-                    algorithm,
-                    s2c: Box::new(AesSivCmac512::try_from(s2c).unwrap()),
-                    c2s: Box::new(AesSivCmac512::try_from(c2s).unwrap()),
-                }
+                };
+                let algorithm = AeadAlgorithm::from(u16::from_be_bytes([a0, a1]));
+                let key_length = u16::from_be_bytes([l0, l1]) as usize;
+                let key_bytes = rest.get(..2 * key_length).ok_or(DecryptError)?;
+                DecodedServerCookie::from_key_bytes(algorithm, key_bytes)
             }
-            AeadAlgorithm::Unknown(_) => return Err(DecryptError),
-        })
+            // Made by a newer version of the server
+            _ => Err(DecryptError),
+        }
+    }
+
+    /// A key set with just the given key, which has identifier `key_id`
+    #[cfg(feature = "nts-test-vectors")]
+    pub(crate) fn with_key(key: AesSivCmac512, key_id: u32) -> Self {
+        Self {
+            keys: vec![key],
+            id_offset: key_id,
+            primary: 0,
+            created: std::time::SystemTime::UNIX_EPOCH,
+        }
     }
 
     #[cfg(test)]
@@ -250,6 +378,7 @@ impl KeySet {
             keys: vec![AesSivCmac512::try_from(std::iter::repeat_n(0, 64)).unwrap()],
             id_offset: 1,
             primary: 0,
+            created: std::time::SystemTime::now(),
         }
     }
 }
@@ -310,6 +439,7 @@ mod tests {
             keys: vec![AesSivCmac512::try_from(std::iter::repeat_n(0, 64)).unwrap()],
             id_offset: 1,
             primary: 0,
+            created: std::time::SystemTime::now(),
         };
 
         let encoded = keyset.encode_cookie(&decoded);
@@ -350,6 +480,7 @@ mod tests {
             keys: vec![AesSivCmac512::try_from(std::iter::repeat_n(0, 64)).unwrap()],
             id_offset: 1,
             primary: 0,
+            created: std::time::SystemTime::now(),
         };
 
         let mut encoded = keyset.encode_cookie(&decoded);
@@ -373,6 +504,29 @@ mod tests {
             keys: vec![AesSivCmac512::try_from(std::iter::repeat_n(0, 64)).unwrap()],
             id_offset: 1,
             primary: 0,
+            created: std::time::SystemTime::now(),
+        };
+
+        let encoded = keyset.encode_cookie(&decoded);
+        let round = keyset.decode_cookie(&encoded).unwrap();
+        assert_eq!(decoded.algorithm, round.algorithm);
+        assert_eq!(decoded.s2c.key_bytes(), round.s2c.key_bytes());
+        assert_eq!(decoded.c2s.key_bytes(), round.c2s.key_bytes());
+    }
+
+    #[test]
+    fn roundtrip_aes_128_gcm_siv() {
+        let decoded = DecodedServerCookie {
+            algorithm: AeadAlgorithm::AeadAes128GcmSiv,
+            s2c: Box::new(Aes128GcmSiv::try_from(&[1; 16]).unwrap()),
+            c2s: Box::new(Aes128GcmSiv::try_from(&[2; 16]).unwrap()),
+        };
+
+        let keyset = KeySet {
+            keys: vec![AesSivCmac512::try_from(std::iter::repeat_n(0, 64)).unwrap()],
+            id_offset: 1,
+            primary: 0,
+            created: std::time::SystemTime::now(),
         };
 
         let encoded = keyset.encode_cookie(&decoded);
@@ -398,6 +552,7 @@ mod tests {
                 .as_secs()
                 < 2
         );
+        assert_eq!(copy.get().created(), time);
         assert_eq!(provider.get().primary, copy.get().primary);
         assert_eq!(provider.get().id_offset, copy.get().id_offset);
         for i in 0..provider.get().keys.len() {
@@ -408,6 +563,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_restore_shorter_history() {
+        let decoded = test_cookie();
+
+        let mut provider = KeySetProvider::new(8);
+        let old = provider.get().encode_cookie(&decoded);
+        for _ in 0..4 {
+            provider.rotate();
+        }
+        let recent = provider.get().encode_cookie(&decoded);
+
+        let mut output = Cursor::new(vec![]);
+        provider.store(&mut output).unwrap();
+        let data = output.into_inner();
+
+        let (copy, _) = KeySetProvider::load(&mut Cursor::new(&data), 2).unwrap();
+        assert_eq!(copy.get().keys.len(), 3);
+        assert!(copy.get().decode_cookie(&old).is_err());
+        assert!(copy.get().decode_cookie(&recent).is_ok());
+
+        // A primary key outside of the stored keys is rejected
+        let mut corrupted = data.clone();
+        corrupted[12..16].copy_from_slice(&5u32.to_be_bytes());
+        assert!(KeySetProvider::load(&mut Cursor::new(&corrupted), 8).is_err());
+    }
+
     #[test]
     fn old_cookie_still_valid() {
         let decoded = DecodedServerCookie {
@@ -436,6 +617,114 @@ mod tests {
         assert!(provider.get().decode_cookie(&encoded).is_err());
     }
 
+    fn legacy_plaintext(cookie: &DecodedServerCookie) -> Vec<u8> {
+        // As written before cookies had a version
+        let mut plaintext = u16::from(cookie.algorithm).to_be_bytes().to_vec();
+        plaintext.extend_from_slice(cookie.s2c.key_bytes());
+        plaintext.extend_from_slice(cookie.c2s.key_bytes());
+        plaintext
+    }
+
+    #[test]
+    fn legacy_cookie_still_valid() {
+        let mut provider = KeySetProvider::new(1);
+
+        for decoded in [
+            test_cookie(),
+            DecodedServerCookie {
+                algorithm: AeadAlgorithm::AeadAesSivCmac512,
+                s2c: Box::new(AesSivCmac512::try_from(0..64_u8).unwrap()),
+                c2s: Box::new(AesSivCmac512::try_from(64..128_u8).unwrap()),
+            },
+            DecodedServerCookie {
+                algorithm: AeadAlgorithm::AeadAes128GcmSiv,
+                s2c: Box::new(Aes128GcmSiv::try_from(&[1; 16]).unwrap()),
+                c2s: Box::new(Aes128GcmSiv::try_from(&[2; 16]).unwrap()),
+            },
+        ] {
+            assert_eq!(
+                decoded.plaintext(CookieVersion::Legacy),
+                legacy_plaintext(&decoded)
+            );
+            let legacy = provider.get().seal(legacy_plaintext(&decoded));
+
+            // Stored by the old daemon and loaded by the new one
+            let mut output = Cursor::new(vec![]);
+            provider.store(&mut output).unwrap();
+            let (loaded, _) =
+                KeySetProvider::load(&mut Cursor::new(output.into_inner()), 1).unwrap();
+
+            let round = loaded.get().decode_cookie(&legacy).unwrap();
+            assert_eq!(decoded.algorithm, round.algorithm);
+            assert_eq!(decoded.s2c.key_bytes(), round.s2c.key_bytes());
+            assert_eq!(decoded.c2s.key_bytes(), round.c2s.key_bytes());
+
+            // Also after a key rotation, like other cookies
+            provider.rotate();
+            assert!(provider.get().decode_cookie(&legacy).is_ok());
+        }
+    }
+
+    #[test]
+    fn cookie_version() {
+        let keyset = KeySet::new();
+        let decoded = test_cookie();
+
+        let plaintext = decoded.plaintext(CookieVersion::V1);
+        assert_eq!(plaintext[..5], [1, 0, 15, 0, 32]);
+        assert_eq!(plaintext.len(), 5 + 64);
+
+        // Fields added after the keys are ignored
+        let mut extended = plaintext.clone();
+        extended.extend_from_slice(&[0xff; 8]);
+        let round = keyset.decode_cookie(&keyset.seal(extended)).unwrap();
+        assert_eq!(decoded.s2c.key_bytes(), round.s2c.key_bytes());
+        assert_eq!(decoded.c2s.key_bytes(), round.c2s.key_bytes());
+
+        // Versions from the future are rejected
+        let mut future = plaintext.clone();
+        future[0] = 2;
+        assert!(keyset.decode_cookie(&keyset.seal(future)).is_err());
+
+        // As are keys shorter than the algorithm needs
+        let mut short = plaintext.clone();
+        short[4] = 16;
+        assert!(keyset.decode_cookie(&keyset.seal(short)).is_err());
+        assert!(
+            keyset
+                .decode_cookie(&keyset.seal(plaintext[..40].to_vec()))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn encode_cookie_within() {
+        let keyset = KeySet::new();
+        let decoded = test_cookie();
+
+        let current = keyset.encode_cookie(&decoded);
+        let legacy = keyset.seal(legacy_plaintext(&decoded));
+        assert!(legacy.len() < current.len());
+
+        let fitting = keyset
+            .encode_cookie_within(&decoded, current.len())
+            .unwrap();
+        assert_eq!(fitting.len(), current.len());
+
+        // Clients with a legacy cookie keep getting legacy cookies
+        for max_length in [legacy.len(), current.len() - 1] {
+            let fitting = keyset.encode_cookie_within(&decoded, max_length).unwrap();
+            assert_eq!(fitting.len(), legacy.len());
+            assert!(keyset.decode_cookie(&fitting).is_ok());
+        }
+
+        assert!(
+            keyset
+                .encode_cookie_within(&decoded, legacy.len() - 1)
+                .is_none()
+        );
+    }
+
     #[test]
     fn invalid_cookie_length() {
         // this cookie data lies about its length, pretending to be longer than it actually is.
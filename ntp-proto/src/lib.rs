@@ -144,6 +144,7 @@
 compile_error!("A crypto provider is needed, use '--features rustcrypto' or '--features openssl'");
 
 mod algorithm;
+mod broadcast;
 mod clock;
 mod config;
 mod cookiestash;
@@ -158,14 +159,19 @@ mod source;
 mod system;
 mod time_types;
 
+#[cfg(feature = "nts-test-vectors")]
+pub mod nts_test_vectors;
+pub mod roughtime;
+pub mod sntp;
 pub mod tls_utils;
+pub mod x509;
 
+// Must be kept in sync with the documented exit codes of ntpd (ntpd::exitcode)
 pub(crate) mod exitcode {
-    /// An internal software error has been detected.  This
-    /// should be limited to non-operating system related
-    /// errors as possible.
+    /// The clock is further off than the configured panic thresholds allow,
+    /// and the daemon refuses to correct it.
     #[cfg(not(test))]
-    pub const SOFTWARE: i32 = 70;
+    pub const PANIC: i32 = 79;
 }
 
 const NTP_DEFAULT_PORT: u16 = 123;
@@ -225,7 +231,8 @@ mod generic {
 /// Unique identifier for a source.
 /// This source id makes sure that even if the network address is the same
 /// that we always know which specific spawned source we are talking about.
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ClockId(u64);
 
 impl ClockId {
@@ -249,29 +256,47 @@ impl std::fmt::Display for ClockId {
     }
 }
 
+impl std::str::FromStr for ClockId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ClockId)
+    }
+}
+
 mod exports {
     pub use super::algorithm::{
-        AlgorithmConfig, KalmanClockController, KalmanControllerMessage, KalmanSourceController,
-        KalmanSourceMessage, Measurement, ObservableSourceTimedata, OneWaySourceControllerWrapper,
+        AlgorithmConfig, CombineMode, InternalMeasurement, InternalSourceController,
+        InternalStateUpdate, InternalTimeSyncController, KalmanClockController,
+        KalmanControllerMessage, KalmanSourceController, KalmanSourceMessage, Measurement,
+        ObservableKalmanState, ObservableMeasurement, ObservableSourceTimedata,
+        OneWaySourceControllerWrapper, PiAlgorithmConfig, PiClockController, PiControllerMessage,
+        PiSourceController, PiSourceMessage, SavedControllerState, SavedSourceState,
         SourceController, TimeSyncController, TimeSyncControllerWrapper,
         TwoWayKalmanSourceController, TwoWaySourceControllerWrapper,
     };
+    pub use super::broadcast::BroadcastSource;
     pub use super::clock::NtpClock;
-    pub use super::config::{SourceConfig, StepThreshold, SynchronizationConfig};
-    pub use super::identifiers::ReferenceId;
+    pub use super::config::{
+        DelayAsymmetry, HoldoverConfig, InitialBurst, LeapSmearConfig, LeapSmearCurve,
+        SourceConfig, StepThreshold, SynchronizationConfig,
+    };
+    pub use super::identifiers::{ReferenceId, ReferenceIdDisplay};
     #[cfg(feature = "__internal-fuzz")]
     pub use super::ipfilter::fuzz::fuzz_ipfilter;
     pub use super::keyset::{DecodedServerCookie, KeySet, KeySetProvider};
 
     #[cfg(feature = "__internal-fuzz")]
     pub use super::keyset::test_cookie;
-    #[cfg(feature = "__internal-fuzz")]
-    pub use super::packet::ExtensionField;
     pub use super::packet::{
-        Cipher, CipherProvider, EncryptResult, ExtensionHeaderVersion, NoCipher,
-        NtpAssociationMode, NtpLeapIndicator, NtpPacket, PacketParsingError,
+        Cipher, CipherProvider, EncryptResult, ExtensionField, ExtensionFieldHandler,
+        ExtensionFieldHandlers, ExtensionFieldRegistrationError, ExtensionHeaderVersion, KeyError,
+        MacAlgorithm, NoCipher, NtpAssociationMode, NtpLeapIndicator, NtpPacket, NtpPacketBuilder,
+        PacketParsingError, SymmetricKey,
     };
     #[cfg(feature = "__internal-fuzz")]
+    pub use super::packet::{fuzz_encrypted_field, fuzz_extension_fields, v5::fuzz_v5_header};
+    #[cfg(feature = "__internal-fuzz")]
     pub use super::server::HandleInnerData;
     pub use super::server::{
         FilterAction, FilterList, IpSubnet, Server, ServerAction, ServerConfig, ServerReason,
@@ -281,24 +306,27 @@ mod exports {
     pub use super::source::source_snapshot;
     pub use super::source::{
         AcceptSynchronizationError, NtpSource, NtpSourceAction, NtpSourceActionIterator,
-        NtpSourceSnapshot, ObservableSourceState, OneWaySource, ProtocolVersion, Reach,
-        SourceNtsData,
+        NtpSourceSnapshot, ObservableKeyExchange, ObservableSourceState, OneWaySource,
+        ProtocolVersion, Reach, SourceNtsData, SourceSecurityEvent,
     };
     pub use super::system::{
-        NtpManager, NtpServerInfo, NtpSnapshot, SourceType, SystemSnapshot, TimeSnapshot,
+        LeapSmearStatus, NtpManager, NtpServerInfo, NtpSnapshot, SourceType, SteeringWithheld,
+        SystemSnapshot, TimeSnapshot,
     };
 
     #[cfg(feature = "__internal-fuzz")]
     pub use super::time_types::fuzz_duration_from_seconds;
     pub use super::time_types::{
-        FrequencyTolerance, NtpDuration, NtpTimestamp, PollInterval, PollIntervalLimits,
+        FrequencyTolerance, NtpDuration, NtpTimestamp, ParseTimeError, PollInterval,
+        PollIntervalLimits,
     };
 
     #[cfg(feature = "__internal-fuzz")]
     pub use super::nts::Request as KeyExchangeRequest;
     pub use super::nts::{
-        KeyExchangeClient, KeyExchangeResult, KeyExchangeServer, NtsClientConfig, NtsError,
-        NtsServerConfig,
+        AeadAlgorithm, KeyExchangeClient, KeyExchangeResult, KeyExchangeServer,
+        KeyExchangeStatHandler, NtsCertificatePins, NtsClientConfig, NtsClientIdentity, NtsError,
+        NtsServerCertificate, NtsServerConfig, NtsServerKey,
     };
     #[cfg(feature = "__internal-fuzz")]
     pub use super::nts::{KeyExchangeResponse, NtsRecord};
@@ -319,4 +347,5 @@ pub use exports::*;
 
 #[cfg(not(feature = "__internal-api"))]
 pub(crate) use exports::*;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::tls_utils::{
+    Certificate, CertifiedKey, ClientHello, CryptoProvider, Error, InconsistentKeys, PrivateKey,
+    ResolvesServerCert, SigningKey,
+};
+
+use super::NtsError;
+
+/// Private key of a key exchange server certificate
+#[derive(Debug)]
+pub enum NtsServerKey {
+    /// A key loaded into memory, typically read from a PEM file
+    Der(PrivateKey),
+    /// A key that never leaves the device holding it, such as a PKCS#11
+    /// token, and that is only asked to sign handshakes
+    External(Arc<dyn SigningKey>),
+}
+
+impl From<PrivateKey> for NtsServerKey {
+    fn from(value: PrivateKey) -> Self {
+        NtsServerKey::Der(value)
+    }
+}
+
+impl NtsServerKey {
+    fn certify(
+        self,
+        certificate_chain: Vec<Certificate>,
+        provider: &CryptoProvider,
+    ) -> Result<CertifiedKey, NtsError> {
+        match self {
+            NtsServerKey::Der(key) => Ok(CertifiedKey::from_der(certificate_chain, key, provider)?),
+            NtsServerKey::External(key) => {
+                let certified = CertifiedKey::new(certificate_chain, key);
+                // Like for keys in memory, only reject keys that are known
+                // not to match the certificate
+                match certified.keys_match() {
+                    Ok(()) | Err(Error::InconsistentKeys(InconsistentKeys::Unknown)) => {
+                        Ok(certified)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+/// Certificate the key exchange server presents to clients that ask for
+/// `server_name` through SNI
+#[derive(Debug)]
+pub struct NtsServerCertificate {
+    pub server_name: String,
+    pub certificate_chain: Vec<Certificate>,
+    pub private_key: NtsServerKey,
+}
+
+#[derive(Debug)]
+struct Certificates {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl Certificates {
+    fn load(
+        provider: &CryptoProvider,
+        certificate_chain: Vec<Certificate>,
+        private_key: NtsServerKey,
+        sni_certificates: Vec<NtsServerCertificate>,
+    ) -> Result<Self, NtsError> {
+        let default = Arc::new(private_key.certify(certificate_chain, provider)?);
+
+        let by_name = sni_certificates
+            .into_iter()
+            .map(|certificate| {
+                let key = certificate
+                    .private_key
+                    .certify(certificate.certificate_chain, provider)?;
+                Ok((certificate.server_name.to_ascii_lowercase(), Arc::new(key)))
+            })
+            .collect::<Result<_, NtsError>>()?;
+
+        Ok(Certificates { default, by_name })
+    }
+}
+
+/// Selects the certificate for a connection by the server name the client
+/// asked for, falling back to the default certificate. The certificates can
+/// be replaced while the server is running.
+#[derive(Debug)]
+pub(super) struct CertificateResolver {
+    provider: Arc<CryptoProvider>,
+    certificates: RwLock<Certificates>,
+}
+
+impl CertificateResolver {
+    pub(super) fn new(
+        provider: Arc<CryptoProvider>,
+        certificate_chain: Vec<Certificate>,
+        private_key: NtsServerKey,
+        sni_certificates: Vec<NtsServerCertificate>,
+    ) -> Result<Self, NtsError> {
+        let certificates =
+            Certificates::load(&provider, certificate_chain, private_key, sni_certificates)?;
+        Ok(CertificateResolver {
+            provider,
+            certificates: RwLock::new(certificates),
+        })
+    }
+
+    /// Replace all certificates. When any of them is invalid, the current
+    /// certificates are kept.
+    pub(super) fn replace(
+        &self,
+        certificate_chain: Vec<Certificate>,
+        private_key: NtsServerKey,
+        sni_certificates: Vec<NtsServerCertificate>,
+    ) -> Result<(), NtsError> {
+        let certificates = Certificates::load(
+            &self.provider,
+            certificate_chain,
+            private_key,
+            sni_certificates,
+        )?;
+        *self.certificates.write().unwrap() = certificates;
+        Ok(())
+    }
+
+    fn select(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let certificates = self.certificates.read().unwrap();
+        server_name
+            .and_then(|name| certificates.by_name.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&certificates.default)
+            .clone()
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.select(client_hello.server_name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tls_utils;
+
+    use super::*;
+
+    fn certificate_chain() -> Vec<Certificate> {
+        tls_utils::pemfile::certs(
+            &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    }
+
+    fn private_key() -> NtsServerKey {
+        tls_utils::pemfile::private_key(&mut include_bytes!("../../test-keys/end.key").as_slice())
+            .unwrap()
+            .into()
+    }
+
+    fn provider() -> Arc<CryptoProvider> {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        tls_utils::server_config_builder().crypto_provider().clone()
+    }
+
+    #[test]
+    fn test_select_by_server_name() {
+        let resolver = CertificateResolver::new(
+            provider(),
+            certificate_chain(),
+            private_key(),
+            vec![NtsServerCertificate {
+                server_name: "Time.Example.com".into(),
+                certificate_chain: certificate_chain(),
+                private_key: private_key(),
+            }],
+        )
+        .unwrap();
+
+        let default = resolver.select(None);
+        let named = resolver.select(Some("time.example.com"));
+        assert!(!Arc::ptr_eq(&default, &named));
+        assert!(Arc::ptr_eq(
+            &named,
+            &resolver.select(Some("TIME.example.com"))
+        ));
+        assert!(Arc::ptr_eq(
+            &default,
+            &resolver.select(Some("other.example.com"))
+        ));
+
+        // Replacing the certificates drops the old ones
+        resolver
+            .replace(certificate_chain(), private_key(), vec![])
+            .unwrap();
+        assert!(!Arc::ptr_eq(&default, &resolver.select(None)));
+        assert!(!Arc::ptr_eq(
+            &named,
+            &resolver.select(Some("time.example.com"))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let invalid_key = PrivateKey::Pkcs8(vec![0; 16].into()).into();
+
+        let resolver =
+            CertificateResolver::new(provider(), certificate_chain(), private_key(), vec![])
+                .unwrap();
+        let current = resolver.select(None);
+
+        assert!(
+            resolver
+                .replace(certificate_chain(), invalid_key, vec![])
+                .is_err()
+        );
+        assert!(Arc::ptr_eq(&current, &resolver.select(None)));
+    }
+}
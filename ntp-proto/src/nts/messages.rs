@@ -134,7 +134,7 @@ impl Request<'_> {
             return if let (Some(authentication), Some(protocols), Some(algorithms)) =
                 (authentication, protocols, algorithms)
             {
-                use crate::packet::{AesSivCmac256, AesSivCmac512};
+                use crate::packet::{Aes128GcmSiv, AesSivCmac256, AesSivCmac512};
 
                 if protocols.len() != 1 || algorithms.len() != 1 {
                     return Err(NtsError::Invalid);
@@ -155,6 +155,13 @@ impl Request<'_> {
                         (Ok(c2s), Ok(s2c)) => (Box::new(c2s), Box::new(s2c)),
                         _ => return Err(NtsError::IncorrectSizedKey),
                     },
+                    AeadAlgorithm::AeadAes128GcmSiv => match (
+                        Aes128GcmSiv::try_from(key_bytes.0.as_ref()),
+                        Aes128GcmSiv::try_from(key_bytes.1.as_ref()),
+                    ) {
+                        (Ok(c2s), Ok(s2c)) => (Box::new(c2s), Box::new(s2c)),
+                        _ => return Err(NtsError::IncorrectSizedKey),
+                    },
                     AeadAlgorithm::Unknown(v) => return Err(NtsError::AeadNotSupported(v)),
                 };
 
@@ -337,7 +344,10 @@ impl KeyExchangeResponse<'_> {
                     }
                 }
                 NtsRecord::Server { name } => {
-                    if server.is_some() {
+                    // A server record without a name leaves us nothing to
+                    // associate with, so don't silently fall back to the
+                    // key exchange server.
+                    if server.is_some() || name.is_empty() {
                         return Err(NtsError::Invalid);
                     }
                     server = Some(name);
@@ -2005,6 +2015,41 @@ mod tests {
             )
             .is_err()
         );
+
+        assert!(
+            pwrap(
+                KeyExchangeResponse::parse,
+                &[
+                    0x80, 1, 0, 2, 0, 0, 0x80, 4, 0, 2, 0, 15, 0x80, 6, 0, 2, b'h', b'i', 0x80, 6,
+                    0, 2, b'h', b'o', 0x80, 0, 0, 0
+                ]
+            )
+            .is_err()
+        );
+
+        assert!(
+            pwrap(
+                KeyExchangeResponse::parse,
+                &[
+                    0x80, 1, 0, 2, 0, 0, 0x80, 4, 0, 2, 0, 15, 0x80, 7, 0, 2, 0, 5, 0x80, 7, 0, 2,
+                    0, 6, 0x80, 0, 0, 0
+                ]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_key_exchange_response_reject_empty_server() {
+        assert!(
+            pwrap(
+                KeyExchangeResponse::parse,
+                &[
+                    0x80, 1, 0, 2, 0, 0, 0x80, 4, 0, 2, 0, 15, 0x80, 6, 0, 0, 0x80, 0, 0, 0
+                ]
+            )
+            .is_err()
+        );
     }
 
     #[test]
@@ -9,9 +9,9 @@ use crate::{
     generic::NtpVersion,
     keyset::KeySet,
     nts::messages::{ErrorResponse, NoOverlapResponse},
-    packet::{AesSivCmac256, AesSivCmac512, Cipher},
-    source::{ProtocolVersion, SourceNtsData},
-    tls_utils::{self, Certificate, PrivateKey, ServerName, TLS13},
+    packet::{Aes128GcmSiv, AesSivCmac256, AesSivCmac512, Cipher},
+    source::{ObservableKeyExchange, ProtocolVersion, SourceNtsData},
+    tls_utils::{self, Certificate, HandshakeKind, PrivateKey, Resumption, ServerName, TLS13},
 };
 
 #[cfg(feature = "__internal-fuzz")]
@@ -21,9 +21,15 @@ use messages::{KeyExchangeResponse, Request};
 #[cfg(feature = "__internal-fuzz")]
 pub use record::NtsRecord;
 
+mod certificates;
 mod messages;
+mod pinning;
 mod record;
 
+use certificates::CertificateResolver;
+pub use certificates::{NtsServerCertificate, NtsServerKey};
+pub use pinning::NtsCertificatePins;
+
 const DEFAULT_NUMBER_OF_COOKIES: usize = 8;
 
 /// From https://www.iana.org/assignments/aead-parameters/aead-parameters.xhtml
@@ -31,6 +37,7 @@ const DEFAULT_NUMBER_OF_COOKIES: usize = 8;
 pub enum AeadAlgorithm {
     AeadAesSivCmac256,
     AeadAesSivCmac512,
+    AeadAes128GcmSiv,
     Unknown(u16),
 }
 
@@ -39,6 +46,7 @@ impl From<u16> for AeadAlgorithm {
         match value {
             15 => Self::AeadAesSivCmac256,
             17 => Self::AeadAesSivCmac512,
+            30 => Self::AeadAes128GcmSiv,
             v => Self::Unknown(v),
         }
     }
@@ -49,13 +57,53 @@ impl From<AeadAlgorithm> for u16 {
         match value {
             AeadAlgorithm::AeadAesSivCmac256 => 15,
             AeadAlgorithm::AeadAesSivCmac512 => 17,
+            AeadAlgorithm::AeadAes128GcmSiv => 30,
             AeadAlgorithm::Unknown(v) => v,
         }
     }
 }
 
+impl std::fmt::Display for AeadAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AeadAlgorithm::AeadAesSivCmac256 => f.write_str("aes-siv-cmac-256"),
+            AeadAlgorithm::AeadAesSivCmac512 => f.write_str("aes-siv-cmac-512"),
+            AeadAlgorithm::AeadAes128GcmSiv => f.write_str("aes-128-gcm-siv"),
+            AeadAlgorithm::Unknown(v) => write!(f, "unknown AEAD algorithm {v}"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AeadAlgorithm {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AeadAlgorithm {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        AeadAlgorithm::SUPPORTED
+            .into_iter()
+            .find(|algorithm| algorithm.to_string() == s)
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "unknown AEAD algorithm {s}, expected aes-siv-cmac-512, aes-siv-cmac-256 or aes-128-gcm-siv"
+                ))
+            })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
-enum NextProtocol {
+pub(crate) enum NextProtocol {
     NTPv4,
     DraftNTPv5,
     Unknown(u16),
@@ -81,16 +129,27 @@ impl From<NextProtocol> for u16 {
     }
 }
 
+/// Label for exporting the NTP keys from the TLS session (RFC 8915, section 5.1)
+pub const KEY_EXPORTER_LABEL: &[u8] = b"EXPORTER-network-time-security";
+
+/// Context for exporting the client-to-server (`direction` 0) or
+/// server-to-client (`direction` 1) key from the TLS session
+pub(crate) fn key_exporter_context(
+    protocol: NextProtocol,
+    algorithm: AeadAlgorithm,
+    direction: u8,
+) -> [u8; 5] {
+    let [p0, p1] = u16::from(protocol).to_be_bytes();
+    let [a0, a1] = u16::from(algorithm).to_be_bytes();
+    [p0, p1, a0, a1, direction]
+}
+
 fn extract_key_bytes<T: Default + AsMut<[u8]>, ConnectionData>(
     tls_connection: &tls_utils::ConnectionCommon<ConnectionData>,
     context: &[u8],
 ) -> Result<T, tls_utils::Error> {
     let mut key = T::default();
-    tls_connection.export_keying_material(
-        &mut key,
-        b"EXPORTER-network-time-security",
-        Some(context),
-    )?;
+    tls_connection.export_keying_material(&mut key, KEY_EXPORTER_LABEL, Some(context))?;
 
     Ok(key)
 }
@@ -106,23 +165,8 @@ impl NtsKeys {
         protocol: NextProtocol,
         algorithm: AeadAlgorithm,
     ) -> Result<Self, NtsError> {
-        let protocol_id: u16 = protocol.into();
-        let algorithm_id: u16 = algorithm.into();
-
-        let c2s_context = &[
-            (protocol_id >> 8) as u8,
-            protocol_id as u8,
-            (algorithm_id >> 8) as u8,
-            algorithm_id as u8,
-            0,
-        ];
-        let s2c_context = &[
-            (protocol_id >> 8) as u8,
-            protocol_id as u8,
-            (algorithm_id >> 8) as u8,
-            algorithm_id as u8,
-            1,
-        ];
+        let c2s_context = &key_exporter_context(protocol, algorithm, 0);
+        let s2c_context = &key_exporter_context(protocol, algorithm, 1);
 
         match algorithm {
             AeadAlgorithm::AeadAesSivCmac256 => Ok(NtsKeys {
@@ -145,6 +189,16 @@ impl NtsKeys {
                     s2c_context,
                 )?)),
             }),
+            AeadAlgorithm::AeadAes128GcmSiv => Ok(NtsKeys {
+                c2s: Box::new(Aes128GcmSiv::new(extract_key_bytes(
+                    tls_connection,
+                    c2s_context,
+                )?)),
+                s2c: Box::new(Aes128GcmSiv::new(extract_key_bytes(
+                    tls_connection,
+                    s2c_context,
+                )?)),
+            }),
             AeadAlgorithm::Unknown(_) => Err(NtsError::Invalid),
         }
     }
@@ -157,8 +211,15 @@ struct AlgorithmDescription {
 }
 
 impl AeadAlgorithm {
+    /// The algorithms we support, in our default order of preference
+    pub const SUPPORTED: [AeadAlgorithm; 3] = [
+        AeadAlgorithm::AeadAesSivCmac512,
+        AeadAlgorithm::AeadAesSivCmac256,
+        AeadAlgorithm::AeadAes128GcmSiv,
+    ];
+
     fn description(self) -> Option<AlgorithmDescription> {
-        use crate::packet::{AesSivCmac256, AesSivCmac512};
+        use crate::packet::{Aes128GcmSiv, AesSivCmac256, AesSivCmac512};
 
         match self {
             AeadAlgorithm::AeadAesSivCmac256 => Some(AlgorithmDescription {
@@ -173,6 +234,14 @@ impl AeadAlgorithm {
                     .try_into()
                     .expect("Aead algorithm has oversized keys"),
             }),
+            AeadAlgorithm::AeadAes128GcmSiv => {
+                Aes128GcmSiv::is_supported().then(|| AlgorithmDescription {
+                    id: self,
+                    keysize: Aes128GcmSiv::key_size()
+                        .try_into()
+                        .expect("Aead algorithm has oversized keys"),
+                })
+            }
             AeadAlgorithm::Unknown(_) => None,
         }
     }
@@ -308,14 +377,34 @@ impl std::error::Error for NtsError {}
 pub struct KeyExchangeResult {
     pub remote: String,
     pub port: u16,
+    /// Whether the key exchange server told us which NTP server to use. If
+    /// not, `remote` is the name of the key exchange server, and the NTP
+    /// server is at the same IP address as the key exchange server.
+    pub remote_negotiated: bool,
     pub nts: Box<SourceNtsData>,
     pub protocol_version: ProtocolVersion,
 }
 
+/// Certificate a client presents to key exchange servers that require
+/// clients to authenticate themselves
+#[derive(Debug)]
+pub struct NtsClientIdentity {
+    pub certificate_chain: Vec<Certificate>,
+    pub private_key: PrivateKey,
+}
+
 #[derive(Debug, Clone)]
 pub struct NtsClientConfig {
     pub certificates: Arc<[Certificate]>,
     pub protocol_version: ProtocolVersion,
+    pub identity: Option<Arc<NtsClientIdentity>>,
+    /// AEAD algorithms to offer, in order of preference
+    pub algorithms: Vec<AeadAlgorithm>,
+    pub pins: NtsCertificatePins,
+    /// Resume earlier TLS sessions with a server, which saves a round trip
+    /// and the certificate validation. Key exchange requests are never sent
+    /// as early (0-RTT) data, as they could be replayed.
+    pub session_resumption: bool,
 }
 
 impl Default for NtsClientConfig {
@@ -323,6 +412,10 @@ impl Default for NtsClientConfig {
         Self {
             certificates: Arc::new([]),
             protocol_version: ProtocolVersion::V4,
+            identity: None,
+            algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+            pins: NtsCertificatePins::default(),
+            session_resumption: true,
         }
     }
 }
@@ -336,14 +429,26 @@ pub struct KeyExchangeClient {
 impl KeyExchangeClient {
     pub fn new(config: &NtsClientConfig) -> Result<Self, NtsError> {
         let builder = tls_utils::client_config_builder_with_protocol_versions(&[&TLS13]);
-        let verifier =
-            tls_utils::PlatformVerifier::new_with_extra_roots(config.certificates.iter().cloned())?
-                .with_provider(builder.crypto_provider().clone());
-        let mut tls_config = builder
+        let verifier = pinning::server_verifier(
+            builder.crypto_provider(),
+            &config.certificates,
+            &config.pins,
+        )?;
+        let builder = builder
             .dangerous()
-            .with_custom_certificate_verifier(Arc::new(verifier))
-            .with_no_client_auth();
+            .with_custom_certificate_verifier(verifier);
+        let mut tls_config = match &config.identity {
+            Some(identity) => builder.with_client_auth_cert(
+                identity.certificate_chain.clone(),
+                identity.private_key.clone_key(),
+            )?,
+            None => builder.with_no_client_auth(),
+        };
         tls_config.alpn_protocols = vec![b"ntske/1".to_vec()];
+        tls_config.enable_early_data = false;
+        if !config.session_resumption {
+            tls_config.resumption = Resumption::disabled();
+        }
 
         Ok(KeyExchangeClient {
             connector: TlsConnector::from(Arc::new(tls_config)),
@@ -352,11 +457,13 @@ impl KeyExchangeClient {
                 ProtocolVersion::V5 => [NextProtocol::DraftNTPv5].into(),
                 _ => [NextProtocol::DraftNTPv5, NextProtocol::NTPv4].into(),
             },
-            algorithms: [
-                AeadAlgorithm::AeadAesSivCmac512,
-                AeadAlgorithm::AeadAesSivCmac256,
-            ]
-            .into(),
+            // Don't offer algorithms the crypto backend can't provide
+            algorithms: config
+                .algorithms
+                .iter()
+                .copied()
+                .filter(|algorithm| algorithm.description().is_some())
+                .collect(),
         })
     }
 
@@ -393,6 +500,19 @@ impl KeyExchangeClient {
             response.algorithm,
         )?;
 
+        let key_exchange = ObservableKeyExchange {
+            server: server_name.clone(),
+            time: std::time::SystemTime::now(),
+            algorithm: response.algorithm,
+            certificate_expiry: io
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|certificate| crate::x509::not_after(certificate)),
+            resumed: io.get_ref().1.handshake_kind() == Some(HandshakeKind::Resumed),
+        };
+
         let mut cookies = CookieStash::default();
         for cookie in response.cookies.into_owned().into_iter() {
             cookies.store(cookie.into_owned());
@@ -403,6 +523,7 @@ impl KeyExchangeClient {
         }
 
         Ok(KeyExchangeResult {
+            remote_negotiated: response.server.is_some(),
             remote: response
                 .server
                 .unwrap_or(Cow::Owned(server_name))
@@ -412,6 +533,7 @@ impl KeyExchangeClient {
                 cookies,
                 c2s: keys.c2s,
                 s2c: keys.s2c,
+                key_exchange,
             }),
             protocol_version: match response.protocol {
                 NextProtocol::NTPv4 => ProtocolVersion::V4,
@@ -425,15 +547,33 @@ impl KeyExchangeClient {
 #[derive(Debug)]
 pub struct NtsServerConfig {
     pub certificate_chain: Vec<Certificate>,
-    pub private_key: PrivateKey,
+    pub private_key: NtsServerKey,
+    /// Certificates for specific server names, presented instead of the
+    /// default certificate when clients ask for that name
+    pub sni_certificates: Vec<NtsServerCertificate>,
     pub accepted_versions: Vec<NtpVersion>,
     pub server: Option<String>,
     pub port: Option<u16>,
     pub pool_authentication_tokens: Vec<String>,
+    /// AEAD algorithms clients may pick from. Clients choose according to
+    /// their own preference, this order is only used when advertising them.
+    pub accepted_algorithms: Vec<AeadAlgorithm>,
+}
+
+/// Receives statistics on the key exchanges handled by a [`KeyExchangeServer`]
+pub trait KeyExchangeStatHandler {
+    /// Called for every response with fresh cookies, with the AEAD algorithm
+    /// the client's keys are for
+    fn register_cookies(&self, algorithm: AeadAlgorithm, count: usize);
+}
+
+impl KeyExchangeStatHandler for () {
+    fn register_cookies(&self, _algorithm: AeadAlgorithm, _count: usize) {}
 }
 
 pub struct KeyExchangeServer {
     acceptor: TlsAcceptor,
+    certificates: Arc<CertificateResolver>,
     protocols: Box<[NextProtocol]>,
     algorithms: Box<[AlgorithmDescription]>,
     pool_authentication_tokens: Box<[String]>,
@@ -443,9 +583,16 @@ pub struct KeyExchangeServer {
 
 impl KeyExchangeServer {
     pub fn new(config: NtsServerConfig) -> Result<Self, NtsError> {
-        let mut server_config = tls_utils::server_config_builder_with_protocol_versions(&[&TLS13])
+        let builder = tls_utils::server_config_builder_with_protocol_versions(&[&TLS13]);
+        let certificates = Arc::new(CertificateResolver::new(
+            builder.crypto_provider().clone(),
+            config.certificate_chain,
+            config.private_key,
+            config.sni_certificates,
+        )?);
+        let mut server_config = builder
             .with_no_client_auth()
-            .with_single_cert(config.certificate_chain, config.private_key)?;
+            .with_cert_resolver(certificates.clone());
         server_config.alpn_protocols = vec![b"ntske/1".to_vec()];
 
         let protocols = config
@@ -460,27 +607,52 @@ impl KeyExchangeServer {
 
         Ok(KeyExchangeServer {
             acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            certificates,
             protocols,
-            algorithms: Box::new([
-                AeadAlgorithm::AeadAesSivCmac256
-                    .description()
-                    .expect("Missing description for AEAD algorithm"),
-                AeadAlgorithm::AeadAesSivCmac512
-                    .description()
-                    .expect("Missing description for AEAD algorithm"),
-            ]),
+            algorithms: config
+                .accepted_algorithms
+                .into_iter()
+                .filter_map(AeadAlgorithm::description)
+                .collect(),
             pool_authentication_tokens: config.pool_authentication_tokens.into(),
             server: config.server,
             port: config.port,
         })
     }
 
+    /// Replace the certificates presented to clients, for instance after they
+    /// were renewed. Established connections are not affected.
+    pub fn update_certificates(
+        &self,
+        certificate_chain: Vec<Certificate>,
+        private_key: NtsServerKey,
+        sni_certificates: Vec<NtsServerCertificate>,
+    ) -> Result<(), NtsError> {
+        self.certificates
+            .replace(certificate_chain, private_key, sni_certificates)
+    }
+
+    pub async fn handle_longterm<T: AsyncRead + AsyncWrite + Unpin, U: AsRef<KeySet>>(
+        &self,
+        io: tokio_rustls::server::TlsStream<T>,
+        get_keyset: impl FnMut() -> U,
+    ) -> Result<(), NtsError> {
+        self.handle_longterm_for_server(io, get_keyset, self.server.as_deref(), self.port, &())
+            .await
+    }
+
+    /// Like [`Self::handle_longterm`], but sends clients to the NTP server
+    /// at `server` and `port` instead of the configured one, and reports the
+    /// cookies it hands out to `stats`.
     // FIXME: Figure out a way to simplify and/or split this function.
     #[expect(clippy::too_many_lines)]
-    pub async fn handle_longterm<T: AsyncRead + AsyncWrite + Unpin, U: AsRef<KeySet>>(
+    pub async fn handle_longterm_for_server<T: AsyncRead + AsyncWrite + Unpin, U: AsRef<KeySet>>(
         &self,
         mut io: tokio_rustls::server::TlsStream<T>,
         mut get_keyset: impl FnMut() -> U,
+        server: Option<&str>,
+        port: Option<u16>,
+        stats: &impl KeyExchangeStatHandler,
     ) -> Result<(), NtsError> {
         tracing::debug!("Longterm handling started for connection");
 
@@ -546,6 +718,7 @@ impl KeyExchangeServer {
                     for _ in 0..DEFAULT_NUMBER_OF_COOKIES {
                         cookies.push(keyset.as_ref().encode_cookie(&cookie).into());
                     }
+                    stats.register_cookies(algorithm, cookies.len());
 
                     drop(keyset);
 
@@ -553,8 +726,8 @@ impl KeyExchangeServer {
                         protocol,
                         algorithm,
                         cookies: cookies.into(),
-                        server: self.server.as_deref().map(Into::into),
-                        port: self.port,
+                        server: server.map(Into::into),
+                        port,
                         keep_alive,
                     };
 
@@ -614,12 +787,36 @@ impl KeyExchangeServer {
         }
     }
 
+    pub async fn handle_connection<IO: AsyncRead + AsyncWrite + Unpin, P>(
+        &self,
+        io: IO,
+        keyset: &KeySet,
+        get_keepalive_permit: impl FnOnce() -> Option<P>,
+    ) -> Result<Option<(P, tokio_rustls::server::TlsStream<IO>)>, NtsError> {
+        self.handle_connection_for_server(
+            io,
+            keyset,
+            self.server.as_deref(),
+            self.port,
+            &(),
+            get_keepalive_permit,
+        )
+        .await
+    }
+
+    /// Like [`Self::handle_connection`], but sends clients to the NTP server
+    /// at `server` and `port` instead of the configured one. The cookies are
+    /// made with `keyset`, so that server must know its keys, and reported to
+    /// `stats`.
     // FIXME: Figure out a way to simplify or split this function
     #[expect(clippy::too_many_lines)]
-    pub async fn handle_connection<IO: AsyncRead + AsyncWrite + Unpin, P>(
+    pub async fn handle_connection_for_server<IO: AsyncRead + AsyncWrite + Unpin, P>(
         &self,
         io: IO,
         keyset: &KeySet,
+        server: Option<&str>,
+        port: Option<u16>,
+        stats: &impl KeyExchangeStatHandler,
         get_keepalive_permit: impl FnOnce() -> Option<P>,
     ) -> Result<Option<(P, tokio_rustls::server::TlsStream<IO>)>, NtsError> {
         let mut io = self.acceptor.accept(io).await?;
@@ -668,7 +865,7 @@ impl KeyExchangeServer {
                     .cloned();
                 let algorithm = algorithms
                     .iter()
-                    .find(|v| !matches!(v, AeadAlgorithm::Unknown(_)))
+                    .find(|v| self.algorithms.iter().any(|known| known.id == **v))
                     .cloned();
 
                 let result = match (protocol, algorithm) {
@@ -714,13 +911,14 @@ impl KeyExchangeServer {
                         for _ in 0..DEFAULT_NUMBER_OF_COOKIES {
                             cookies.push(keyset.encode_cookie(&cookie).into());
                         }
+                        stats.register_cookies(algorithm, cookies.len());
 
                         let response = KeyExchangeResponse {
                             protocol,
                             algorithm,
                             cookies: cookies.into(),
-                            server: self.server.as_deref().map(Into::into),
-                            port: self.port,
+                            server: server.map(Into::into),
+                            port,
                             keep_alive: false,
                         };
 
@@ -760,6 +958,7 @@ impl KeyExchangeServer {
                 for _ in 0..DEFAULT_NUMBER_OF_COOKIES {
                     cookies.push(keyset.encode_cookie(&cookie).into());
                 }
+                stats.register_cookies(algorithm, cookies.len());
 
                 let permit = if keep_alive {
                     get_keepalive_permit()
@@ -771,8 +970,8 @@ impl KeyExchangeServer {
                     protocol,
                     algorithm,
                     cookies: cookies.into(),
-                    server: self.server.as_deref().map(Into::into),
-                    port: self.port,
+                    server: server.map(Into::into),
+                    port,
                     keep_alive: permit.is_some(),
                 };
 
@@ -874,6 +1073,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_client_with_identity() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let certificate_chain = tls_utils::pemfile::certs(
+            &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        let private_key = tls_utils::pemfile::private_key(
+            &mut include_bytes!("../../test-keys/end.key").as_slice(),
+        )
+        .unwrap();
+
+        assert!(
+            KeyExchangeClient::new(&NtsClientConfig {
+                identity: Some(Arc::new(NtsClientIdentity {
+                    certificate_chain,
+                    private_key,
+                })),
+                ..NtsClientConfig::default()
+            })
+            .is_ok()
+        );
+    }
+
     #[tokio::test]
     async fn test_keyexchange_roundtrip_v4() {
         #[cfg(feature = "openssl")]
@@ -890,6 +1116,10 @@ mod tests {
             let kex = KeyExchangeClient::new(&NtsClientConfig {
                 certificates,
                 protocol_version: ProtocolVersion::V4,
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             kex.exchange_keys(client, "localhost".into(), [])
@@ -909,11 +1139,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -927,6 +1159,15 @@ mod tests {
 
         let (mut kexresult, keyset) = tokio::join!(client, server);
         assert_eq!(kexresult.protocol_version, ProtocolVersion::V4);
+        assert_eq!(kexresult.nts.key_exchange.server, "localhost");
+        assert_eq!(
+            kexresult.nts.key_exchange.algorithm,
+            AeadAlgorithm::AeadAesSivCmac512
+        );
+        assert_eq!(
+            kexresult.nts.key_exchange.certificate_expiry,
+            Some(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_803_723_092))
+        );
 
         let mut count = 0;
         while let Some(cookie) = kexresult.nts.get_cookie() {
@@ -939,6 +1180,296 @@ mod tests {
         assert_eq!(count, 8);
     }
 
+    #[tokio::test]
+    async fn test_keyexchange_session_resumption() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let certificate_chain = tls_utils::pemfile::certs(
+            &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        let private_key = tls_utils::pemfile::private_key(
+            &mut include_bytes!("../../test-keys/end.key").as_slice(),
+        )
+        .unwrap();
+        let server = KeyExchangeServer::new(NtsServerConfig {
+            certificate_chain,
+            private_key: private_key.into(),
+            sni_certificates: vec![],
+            accepted_versions: vec![NtpVersion::V4],
+            server: None,
+            port: None,
+            pool_authentication_tokens: vec![],
+            accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+        })
+        .unwrap();
+        let keyset = KeySet::new();
+
+        let certificates =
+            tls_utils::pemfile::certs(&mut include_bytes!("../../test-keys/testca.pem").as_slice())
+                .collect::<Result<Arc<_>, _>>()
+                .unwrap();
+
+        for session_resumption in [true, false] {
+            let client = KeyExchangeClient::new(&NtsClientConfig {
+                certificates: certificates.clone(),
+                session_resumption,
+                ..NtsClientConfig::default()
+            })
+            .unwrap();
+
+            let mut resumed = vec![];
+            for _ in 0..2 {
+                let (client_io, server_io) = tokio::io::duplex(4096);
+                let (result, _) = tokio::join!(
+                    client.exchange_keys(client_io, "localhost".into(), []),
+                    server.handle_connection(server_io, &keyset, || None::<()>)
+                );
+                let key_exchange = result.unwrap().nts.key_exchange;
+                // The certificate is remembered with the session
+                assert!(key_exchange.certificate_expiry.is_some());
+                resumed.push(key_exchange.resumed);
+            }
+            assert_eq!(resumed, [false, session_resumption]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyexchange_algorithm_preference() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let (client, server) = tokio::io::duplex(2048);
+
+        let client = async move {
+            let certificates = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/testca.pem").as_slice(),
+            )
+            .collect::<Result<Arc<_>, _>>()
+            .unwrap();
+            let kex = KeyExchangeClient::new(&NtsClientConfig {
+                certificates,
+                protocol_version: ProtocolVersion::V4,
+                identity: None,
+                algorithms: vec![
+                    AeadAlgorithm::AeadAes128GcmSiv,
+                    AeadAlgorithm::AeadAesSivCmac256,
+                ],
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
+            })
+            .unwrap();
+            kex.exchange_keys(client, "localhost".into(), [])
+                .await
+                .unwrap()
+        };
+
+        let server = async move {
+            let certificate_chain = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+            )
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+            let private_key = tls_utils::pemfile::private_key(
+                &mut include_bytes!("../../test-keys/end.key").as_slice(),
+            )
+            .unwrap();
+            let kex = KeyExchangeServer::new(NtsServerConfig {
+                certificate_chain,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
+                accepted_versions: vec![NtpVersion::V4],
+                server: None,
+                port: None,
+                pool_authentication_tokens: vec![],
+                accepted_algorithms: vec![
+                    AeadAlgorithm::AeadAesSivCmac256,
+                    AeadAlgorithm::AeadAes128GcmSiv,
+                ],
+            })
+            .unwrap();
+            let keyset = KeySet::new();
+            assert!(
+                kex.handle_connection(server, &keyset, || None::<()>)
+                    .await
+                    .is_ok()
+            );
+            keyset
+        };
+
+        // The client's preference wins over the order the server lists them in
+        let (mut kexresult, keyset) = tokio::join!(client, server);
+        assert_eq!(kexresult.nts.c2s.key_bytes().len(), 16);
+        let cookie = kexresult.nts.get_cookie().unwrap();
+        let decoded = keyset.decode_cookie(&cookie).unwrap();
+        assert_eq!(decoded.algorithm, AeadAlgorithm::AeadAes128GcmSiv);
+        assert_eq!(decoded.c2s.key_bytes(), kexresult.nts.c2s.key_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_keyexchange_no_overlapping_algorithm() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let (client, server) = tokio::io::duplex(2048);
+
+        let client = async move {
+            let certificates = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/testca.pem").as_slice(),
+            )
+            .collect::<Result<Arc<_>, _>>()
+            .unwrap();
+            let kex = KeyExchangeClient::new(&NtsClientConfig {
+                certificates,
+                protocol_version: ProtocolVersion::V4,
+                identity: None,
+                algorithms: vec![AeadAlgorithm::AeadAes128GcmSiv],
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
+            })
+            .unwrap();
+            kex.exchange_keys(client, "localhost".into(), []).await
+        };
+
+        let server = async move {
+            let certificate_chain = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+            )
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+            let private_key = tls_utils::pemfile::private_key(
+                &mut include_bytes!("../../test-keys/end.key").as_slice(),
+            )
+            .unwrap();
+            let kex = KeyExchangeServer::new(NtsServerConfig {
+                certificate_chain,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
+                accepted_versions: vec![NtpVersion::V4],
+                server: None,
+                port: None,
+                pool_authentication_tokens: vec![],
+                accepted_algorithms: vec![AeadAlgorithm::AeadAesSivCmac512],
+            })
+            .unwrap();
+            let keyset = KeySet::new();
+            kex.handle_connection(server, &keyset, || None::<()>).await
+        };
+
+        let (client_result, server_result) = tokio::join!(client, server);
+        assert!(matches!(
+            server_result,
+            Err(NtsError::NoOverlappingAlgorithm)
+        ));
+        assert!(client_result.is_err());
+    }
+
+    async fn keyexchange_with_pins(
+        certificates: Arc<[Certificate]>,
+        pins: NtsCertificatePins,
+    ) -> Result<KeyExchangeResult, NtsError> {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let (client, server) = tokio::io::duplex(2048);
+
+        let client = async move {
+            let kex = KeyExchangeClient::new(&NtsClientConfig {
+                certificates,
+                protocol_version: ProtocolVersion::V4,
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins,
+                session_resumption: true,
+            })
+            .unwrap();
+            kex.exchange_keys(client, "localhost".into(), []).await
+        };
+
+        let server = async move {
+            let certificate_chain = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+            )
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+            let private_key = tls_utils::pemfile::private_key(
+                &mut include_bytes!("../../test-keys/end.key").as_slice(),
+            )
+            .unwrap();
+            let kex = KeyExchangeServer::new(NtsServerConfig {
+                certificate_chain,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
+                accepted_versions: vec![NtpVersion::V4],
+                server: None,
+                port: None,
+                pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+            })
+            .unwrap();
+            let keyset = KeySet::new();
+            kex.handle_connection(server, &keyset, || None::<()>)
+                .await
+                .ok();
+        };
+
+        tokio::join!(client, server).0
+    }
+
+    #[tokio::test]
+    async fn test_keyexchange_pinning() {
+        let testca: Arc<[Certificate]> =
+            tls_utils::pemfile::certs(&mut include_bytes!("../../test-keys/testca.pem").as_slice())
+                .collect::<Result<_, _>>()
+                .unwrap();
+        let end: Vec<Certificate> =
+            tls_utils::pemfile::certs(&mut include_bytes!("../../test-keys/end.pem").as_slice())
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+        // SHA-256 of the public keys of the end entity and CA certificates
+        let end_spki = [
+            0x7c, 0x51, 0x0c, 0xf4, 0xdb, 0x3a, 0x49, 0x2a, 0x94, 0x3c, 0x78, 0x78, 0x95, 0x9f,
+            0x48, 0xef, 0x73, 0x80, 0xb0, 0x6f, 0xb0, 0x51, 0x2b, 0xda, 0x78, 0x4a, 0x01, 0x21,
+            0x80, 0x6f, 0x2d, 0x41,
+        ];
+        let ca_spki = [
+            0x3d, 0x4e, 0x9a, 0x4a, 0x6f, 0x82, 0x16, 0x48, 0xdf, 0xbd, 0x3a, 0x64, 0x6e, 0x8c,
+            0x9d, 0x97, 0xad, 0xaf, 0xe4, 0x38, 0x0a, 0xd5, 0x99, 0x70, 0xc8, 0x5b, 0x5e, 0x6f,
+            0xfc, 0xdb, 0x36, 0x5c,
+        ];
+
+        for spki_sha256 in [vec![end_spki], vec![[0; 32], ca_spki]] {
+            let pins = NtsCertificatePins {
+                certificate_authorities: vec![],
+                spki_sha256,
+            };
+            assert!(keyexchange_with_pins(testca.clone(), pins).await.is_ok());
+        }
+
+        let pins = NtsCertificatePins {
+            certificate_authorities: vec![],
+            spki_sha256: vec![[0; 32]],
+        };
+        assert!(keyexchange_with_pins(testca.clone(), pins).await.is_err());
+
+        // A pinned certificate authority does not need to be added separately
+        let pins = NtsCertificatePins {
+            certificate_authorities: testca.to_vec(),
+            spki_sha256: vec![],
+        };
+        assert!(keyexchange_with_pins(Arc::from([]), pins).await.is_ok());
+
+        // Only the pinned certificate authorities are trusted
+        let pins = NtsCertificatePins {
+            certificate_authorities: end,
+            spki_sha256: vec![],
+        };
+        assert!(keyexchange_with_pins(testca, pins).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_keyexchange_roundtrip_v5() {
         #[cfg(feature = "openssl")]
@@ -955,6 +1486,10 @@ mod tests {
             let kex = KeyExchangeClient::new(&NtsClientConfig {
                 certificates,
                 protocol_version: ProtocolVersion::V5,
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             kex.exchange_keys(client, "localhost".into(), [])
@@ -974,11 +1509,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V5],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1020,6 +1557,10 @@ mod tests {
             let kex = KeyExchangeClient::new(&NtsClientConfig {
                 certificates,
                 protocol_version: ProtocolVersion::V4UpgradingToV5 { tries_left: 8 },
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             kex.exchange_keys(client, "localhost".into(), [])
@@ -1039,11 +1580,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4, NtpVersion::V5],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1085,6 +1628,10 @@ mod tests {
             let kex = KeyExchangeClient::new(&NtsClientConfig {
                 certificates,
                 protocol_version: ProtocolVersion::V4UpgradingToV5 { tries_left: 8 },
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             kex.exchange_keys(client, "localhost".into(), [])
@@ -1104,11 +1651,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1150,6 +1699,10 @@ mod tests {
             let kex = KeyExchangeClient::new(&NtsClientConfig {
                 certificates,
                 protocol_version: ProtocolVersion::V5,
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             kex.exchange_keys(client, "localhost".into(), []).await
@@ -1167,11 +1720,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1199,6 +1754,10 @@ mod tests {
             let kex = KeyExchangeClient::new(&NtsClientConfig {
                 certificates,
                 protocol_version: ProtocolVersion::V4,
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             kex.exchange_keys(client, "localhost".into(), []).await
@@ -1216,11 +1775,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec![],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let mut server = kex.acceptor.accept(server).await.unwrap();
@@ -1310,11 +1871,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec!["hi".into()],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1433,11 +1996,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec!["hi".into()],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = Arc::new(KeySet::new());
@@ -1541,11 +2106,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec!["hi".into()],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1637,11 +2204,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec!["hi".into()],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1721,11 +2290,17 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec!["hi".into()],
+                // The supported algorithms are listed in order of preference
+                accepted_algorithms: vec![
+                    AeadAlgorithm::AeadAesSivCmac256,
+                    AeadAlgorithm::AeadAesSivCmac512,
+                ],
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -1801,11 +2376,13 @@ mod tests {
             .unwrap();
             let kex = KeyExchangeServer::new(NtsServerConfig {
                 certificate_chain,
-                private_key,
+                private_key: private_key.into(),
+                sni_certificates: vec![],
                 accepted_versions: vec![NtpVersion::V4],
                 server: None,
                 port: None,
                 pool_authentication_tokens: vec!["hi".into()],
+                accepted_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
             })
             .unwrap();
             let keyset = KeySet::new();
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use crate::tls_utils::{
+    Certificate, CertificateDer, CertificateError, CryptoProvider, DigitallySignedStruct, Error,
+    HandshakeSignatureValid, Hash, HashAlgorithm, PlatformVerifier, RootCertStore,
+    ServerCertVerified, ServerCertVerifier, ServerName, SignatureScheme, SupportedCipherSuite,
+    UnixTime, WebPkiServerVerifier,
+};
+use crate::x509::subject_public_key_info;
+
+use super::NtsError;
+
+/// Restrictions on the certificates a key exchange server may present, on
+/// top of the regular certificate validation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NtsCertificatePins {
+    /// When not empty, only these certificate authorities are trusted,
+    /// instead of those of the system and any additional ones
+    pub certificate_authorities: Vec<Certificate>,
+    /// When not empty, one of the certificates the server sends must have a
+    /// public key (SubjectPublicKeyInfo) with one of these SHA-256 hashes
+    pub spki_sha256: Vec<[u8; 32]>,
+}
+
+/// Build the verifier for key exchange server certificates
+pub(super) fn server_verifier(
+    provider: &Arc<CryptoProvider>,
+    extra_roots: &[Certificate],
+    pins: &NtsCertificatePins,
+) -> Result<Arc<dyn ServerCertVerifier>, NtsError> {
+    let verifier: Arc<dyn ServerCertVerifier> = if pins.certificate_authorities.is_empty() {
+        Arc::new(
+            PlatformVerifier::new_with_extra_roots(extra_roots.iter().cloned())?
+                .with_provider(provider.clone()),
+        )
+    } else {
+        let mut roots = RootCertStore::empty();
+        for certificate in &pins.certificate_authorities {
+            roots.add(certificate.clone())?;
+        }
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(std::io::Error::other)?
+    };
+
+    if pins.spki_sha256.is_empty() {
+        return Ok(verifier);
+    }
+
+    let sha256 = provider
+        .cipher_suites
+        .iter()
+        .filter_map(SupportedCipherSuite::tls13)
+        .map(|suite| suite.common.hash_provider)
+        .find(|hash| hash.algorithm() == HashAlgorithm::SHA256)
+        .ok_or_else(|| std::io::Error::other("crypto provider does not support SHA-256"))?;
+
+    Ok(Arc::new(SpkiPinningVerifier {
+        inner: verifier,
+        spki_sha256: pins.spki_sha256.clone(),
+        sha256,
+    }))
+}
+
+/// Accepts only chains that pass `inner` and contain a certificate with a
+/// pinned public key
+struct SpkiPinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    spki_sha256: Vec<[u8; 32]>,
+    sha256: &'static dyn Hash,
+}
+
+impl std::fmt::Debug for SpkiPinningVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpkiPinningVerifier")
+            .field("inner", &self.inner)
+            .field("spki_sha256", &self.spki_sha256.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerCertVerifier for SpkiPinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|certificate| subject_public_key_info(certificate))
+            .any(|spki| {
+                let hash = self.sha256.hash(spki);
+                self.spki_sha256
+                    .iter()
+                    .any(|pin| pin.as_slice() == hash.as_ref())
+            });
+
+        if pinned {
+            Ok(verified)
+        } else {
+            tracing::debug!(?server_name, "No pinned public key in certificate chain");
+            Err(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,427 @@
+//! Deterministic NTS messages, for testing other implementations against
+//! ntpd-rs.
+//!
+//! NTS (RFC 8915) uses a fresh random nonce for every cookie and every
+//! authenticated packet, and a random unique identifier for every request,
+//! so no two runs produce the same bytes. The functions here take all of
+//! these as inputs instead, so that another implementation, for example a
+//! client on a microcontroller, can be checked against fixed expected output
+//! and can exchange packets with ntpd-rs without a key exchange. RFC 8915 does
+//! not come with test vectors; the output of this module is what ntpd-rs
+//! sends and accepts.
+//!
+//! Never use this outside of tests: reusing a nonce with the same key breaks
+//! the security of NTS.
+
+use std::io::{Cursor, ErrorKind};
+
+use crate::{
+    keyset::{CookieVersion, DecodedServerCookie, KeySet},
+    nts::{NextProtocol, key_exporter_context},
+    packet::{
+        Aes128GcmSiv, AesSivCmac256, AesSivCmac512, Cipher, ExtensionField, FixedNonce, NtpPacket,
+    },
+    time_types::NtpTimestamp,
+};
+
+pub use crate::nts::{AeadAlgorithm, KEY_EXPORTER_LABEL};
+
+/// Direction of the traffic a key protects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDirection {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// Context for exporting the NTPv4 key for `direction` from the TLS session,
+/// used together with [`KEY_EXPORTER_LABEL`]
+pub fn ntpv4_key_exporter_context(algorithm: AeadAlgorithm, direction: KeyDirection) -> [u8; 5] {
+    let direction = match direction {
+        KeyDirection::ClientToServer => 0,
+        KeyDirection::ServerToClient => 1,
+    };
+    key_exporter_context(NextProtocol::NTPv4, algorithm, direction)
+}
+
+/// The keys of an NTS association, as exported from the TLS session of the
+/// key exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionKeys {
+    pub algorithm: AeadAlgorithm,
+    pub c2s: Vec<u8>,
+    pub s2c: Vec<u8>,
+}
+
+/// The authenticated NTS contents of a server response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtsResponse {
+    pub unique_id: Vec<u8>,
+    pub cookies: Vec<Vec<u8>>,
+}
+
+impl SessionKeys {
+    fn decoded(&self) -> std::io::Result<DecodedServerCookie> {
+        DecodedServerCookie::from_key_bytes(
+            self.algorithm,
+            &[self.s2c.as_slice(), self.c2s.as_slice()].concat(),
+        )
+        .map_err(|_| invalid_input("keys do not match the algorithm"))
+    }
+
+    /// An NTPv4 request with the unique identifier, `cookie` and
+    /// `placeholders` cookie placeholders, authenticated with the c2s key and
+    /// `nonce`. Timestamps are in the 64-bit NTP format.
+    pub fn client_request(
+        &self,
+        cookie: &[u8],
+        placeholders: u8,
+        unique_id: [u8; 32],
+        transmit_timestamp: u64,
+        nonce: &[u8],
+    ) -> std::io::Result<Vec<u8>> {
+        let packet = NtpPacket::nts_test_vector_request(
+            cookie,
+            placeholders.saturating_add(1),
+            unique_id,
+            NtpTimestamp::from_fixed_int(transmit_timestamp),
+        );
+        serialize(&packet, &*fixed_nonce(self.algorithm, &self.c2s, nonce)?)
+    }
+
+    /// Check the authentication of a server response, and return its unique
+    /// identifier and new cookies
+    pub fn decode_response(&self, response: &[u8]) -> std::io::Result<NtsResponse> {
+        let keys = self.decoded()?;
+        let (packet, _) = NtpPacket::deserialize(response, keys.s2c.as_ref())
+            .map_err(|_| invalid_data("invalid response"))?;
+
+        let unique_id = packet
+            .authenticated_extension_fields()
+            .find_map(|ef| match ef {
+                ExtensionField::UniqueIdentifier(id) => Some(id.to_vec()),
+                _ => None,
+            })
+            .ok_or_else(|| invalid_data("response without authenticated unique identifier"))?;
+
+        Ok(NtsResponse {
+            unique_id,
+            cookies: packet.new_cookies().collect(),
+        })
+    }
+}
+
+/// The master key a server encrypts its cookies with, and the identifier
+/// that cookies carry for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieKey {
+    pub key: [u8; 64],
+    pub id: u32,
+}
+
+impl CookieKey {
+    fn key_set(&self) -> KeySet {
+        let key =
+            AesSivCmac512::try_from(self.key.iter()).expect("AES-SIV-CMAC-512 keys are 64 bytes");
+        KeySet::with_key(key, self.id)
+    }
+
+    /// Encrypt `keys` into a cookie, with `nonce`
+    pub fn encode_cookie(&self, keys: &SessionKeys, nonce: [u8; 16]) -> std::io::Result<Vec<u8>> {
+        let plaintext = keys.decoded()?.plaintext(CookieVersion::CURRENT);
+        Ok(self.key_set().seal_with_nonce(plaintext, &nonce))
+    }
+
+    /// Decrypt a cookie back into the keys it holds
+    pub fn decode_cookie(&self, cookie: &[u8]) -> std::io::Result<SessionKeys> {
+        let decoded = self
+            .key_set()
+            .decode_cookie(cookie)
+            .map_err(|_| invalid_data("cookie could not be decrypted"))?;
+
+        Ok(SessionKeys {
+            algorithm: decoded.algorithm,
+            c2s: decoded.c2s.key_bytes().to_vec(),
+            s2c: decoded.s2c.key_bytes().to_vec(),
+        })
+    }
+
+    /// The response to an NTPv4 NTS request, with one new cookie for each
+    /// of `cookie_nonces`, authenticated with the s2c key from the cookie in
+    /// the request and `nonce`. A real server sends one cookie for the
+    /// cookie and each placeholder in the request. Timestamps are in the
+    /// 64-bit NTP format.
+    pub fn server_response(
+        &self,
+        request: &[u8],
+        receive_timestamp: u64,
+        transmit_timestamp: u64,
+        cookie_nonces: &[[u8; 16]],
+        nonce: &[u8],
+    ) -> std::io::Result<Vec<u8>> {
+        let key_set = self.key_set();
+        let (request, cookie) = NtpPacket::deserialize(request, &key_set)
+            .map_err(|_| invalid_data("invalid or unauthenticated request"))?;
+        let cookie = cookie.ok_or_else(|| invalid_data("request without a valid cookie"))?;
+
+        let plaintext = cookie.plaintext(CookieVersion::CURRENT);
+        let cookies = cookie_nonces
+            .iter()
+            .map(|cookie_nonce| key_set.seal_with_nonce(plaintext.clone(), cookie_nonce))
+            .collect();
+
+        let response = NtpPacket::nts_test_vector_response(
+            request,
+            NtpTimestamp::from_fixed_int(receive_timestamp),
+            NtpTimestamp::from_fixed_int(transmit_timestamp),
+            cookies,
+        )
+        .ok_or_else(|| invalid_data("request is not NTPv4"))?;
+
+        serialize(
+            &response,
+            &*fixed_nonce(cookie.algorithm, cookie.s2c.key_bytes(), nonce)?,
+        )
+    }
+}
+
+fn fixed_nonce(
+    algorithm: AeadAlgorithm,
+    key: &[u8],
+    nonce: &[u8],
+) -> std::io::Result<Box<dyn Cipher>> {
+    let invalid_key = |_| invalid_input("key does not match the algorithm");
+    let nonce = nonce.to_vec();
+
+    let cipher: Box<dyn Cipher> = match algorithm {
+        AeadAlgorithm::AeadAesSivCmac256 => Box::new(FixedNonce {
+            cipher: AesSivCmac256::try_from(key).map_err(invalid_key)?,
+            nonce,
+        }),
+        AeadAlgorithm::AeadAesSivCmac512 => Box::new(FixedNonce {
+            cipher: AesSivCmac512::try_from(key).map_err(invalid_key)?,
+            nonce,
+        }),
+        AeadAlgorithm::AeadAes128GcmSiv => Box::new(FixedNonce {
+            cipher: Aes128GcmSiv::try_from(key).map_err(invalid_key)?,
+            nonce,
+        }),
+        AeadAlgorithm::Unknown(_) => return Err(invalid_input("unknown algorithm")),
+    };
+    Ok(cipher)
+}
+
+fn serialize(packet: &NtpPacket<'_>, cipher: &dyn Cipher) -> std::io::Result<Vec<u8>> {
+    let mut buffer = vec![0; 4096];
+    let mut cursor = Cursor::new(buffer.as_mut_slice());
+    packet.serialize(&mut cursor, cipher, None)?;
+    let length = cursor.position() as usize;
+    buffer.truncate(length);
+    Ok(buffer)
+}
+
+fn invalid_input(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookie_key() -> CookieKey {
+        CookieKey {
+            key: std::array::from_fn(|i| i as u8),
+            id: 0x0102_0304,
+        }
+    }
+
+    fn session_keys(algorithm: AeadAlgorithm, key_length: u8) -> SessionKeys {
+        SessionKeys {
+            algorithm,
+            s2c: (0x80..0x80 + key_length).collect(),
+            c2s: (0xa0..0xa0 + key_length).collect(),
+        }
+    }
+
+    fn nonce(length: u8) -> Vec<u8> {
+        (0x40..0x40 + length).collect()
+    }
+
+    #[test]
+    fn test_key_exporter_context() {
+        assert_eq!(
+            ntpv4_key_exporter_context(
+                AeadAlgorithm::AeadAesSivCmac256,
+                KeyDirection::ClientToServer
+            ),
+            [0, 0, 0, 15, 0]
+        );
+        assert_eq!(
+            ntpv4_key_exporter_context(
+                AeadAlgorithm::AeadAes128GcmSiv,
+                KeyDirection::ServerToClient
+            ),
+            [0, 0, 0, 30, 1]
+        );
+    }
+
+    #[test]
+    fn test_cookie_vector() {
+        let keys = session_keys(AeadAlgorithm::AeadAesSivCmac256, 32);
+        let cookie = cookie_key()
+            .encode_cookie(&keys, nonce(16).try_into().unwrap())
+            .unwrap();
+
+        // Key id, ciphertext length, nonce and the AES-SIV-CMAC-512
+        // encryption of version 1, algorithm 15, key length 32 and the keys
+        let expected = [
+            "0102030400554041424344454647",
+            "48494a4b4c4d4e4f191c8ca6ee35",
+            "79422585e8de8e0467a4b8c0c194",
+            "74f2d73519e86dc0adcdbb1b1fe6",
+            "b7a71b129926e9bff8f0b484c8b6",
+            "21cfff450ebe2ca9857a89ca4dcd",
+            "10975e5e3d52f59ee37c498c7c4d",
+            "b3bab3babab97ee503",
+        ]
+        .concat();
+        let expected: Vec<u8> = (0..expected.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&expected[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(cookie, expected);
+
+        assert_eq!(cookie_key().decode_cookie(&cookie).unwrap(), keys);
+    }
+
+    #[test]
+    fn test_cookie_wrong_key() {
+        let keys = session_keys(AeadAlgorithm::AeadAesSivCmac512, 64);
+        let cookie = cookie_key().encode_cookie(&keys, [0; 16]).unwrap();
+
+        let other = CookieKey {
+            key: [0; 64],
+            ..cookie_key()
+        };
+        assert!(other.decode_cookie(&cookie).is_err());
+
+        let other = CookieKey {
+            id: 7,
+            ..cookie_key()
+        };
+        assert!(other.decode_cookie(&cookie).is_err());
+    }
+
+    #[test]
+    fn test_exchange() {
+        for (algorithm, key_length, nonce_length) in [
+            (AeadAlgorithm::AeadAesSivCmac256, 32, 16),
+            (AeadAlgorithm::AeadAesSivCmac512, 64, 16),
+            (AeadAlgorithm::AeadAes128GcmSiv, 16, 12),
+        ] {
+            let keys = session_keys(algorithm, key_length);
+            let cookie = cookie_key().encode_cookie(&keys, [1; 16]).unwrap();
+
+            let request = keys
+                .client_request(&cookie, 2, [7; 32], 0x1234, &nonce(nonce_length))
+                .unwrap();
+            // Nothing is random
+            assert_eq!(
+                request,
+                keys.client_request(&cookie, 2, [7; 32], 0x1234, &nonce(nonce_length))
+                    .unwrap()
+            );
+
+            let response = cookie_key()
+                .server_response(
+                    &request,
+                    0x5678,
+                    0x9abc,
+                    &[[2; 16], [3; 16], [4; 16]],
+                    &nonce(nonce_length),
+                )
+                .unwrap();
+            assert_eq!(
+                response,
+                cookie_key()
+                    .server_response(
+                        &request,
+                        0x5678,
+                        0x9abc,
+                        &[[2; 16], [3; 16], [4; 16]],
+                        &nonce(nonce_length),
+                    )
+                    .unwrap()
+            );
+
+            let decoded = keys.decode_response(&response).unwrap();
+            assert_eq!(decoded.unique_id, [7; 32]);
+            assert_eq!(
+                decoded.cookies,
+                [
+                    cookie_key().encode_cookie(&keys, [2; 16]).unwrap(),
+                    cookie_key().encode_cookie(&keys, [3; 16]).unwrap(),
+                    cookie_key().encode_cookie(&keys, [4; 16]).unwrap(),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_exchange_wrong_keys() {
+        let keys = session_keys(AeadAlgorithm::AeadAesSivCmac256, 32);
+        let cookie = cookie_key().encode_cookie(&keys, [1; 16]).unwrap();
+
+        // The client authenticates with a key that is not in the cookie
+        let other = SessionKeys {
+            c2s: vec![0; 32],
+            ..keys.clone()
+        };
+        let request = other
+            .client_request(&cookie, 0, [7; 32], 0, &nonce(16))
+            .unwrap();
+        assert!(
+            cookie_key()
+                .server_response(&request, 0, 0, &[], &nonce(16))
+                .is_err()
+        );
+
+        let request = keys
+            .client_request(&cookie, 0, [7; 32], 0, &nonce(16))
+            .unwrap();
+        let response = cookie_key()
+            .server_response(&request, 0, 0, &[[2; 16]], &nonce(16))
+            .unwrap();
+        let other = SessionKeys {
+            s2c: vec![0; 32],
+            ..keys
+        };
+        assert!(other.decode_response(&response).is_err());
+    }
+
+    #[test]
+    fn test_invalid_nonce_length() {
+        let keys = session_keys(AeadAlgorithm::AeadAes128GcmSiv, 16);
+        let cookie = cookie_key().encode_cookie(&keys, [1; 16]).unwrap();
+        assert!(
+            keys.client_request(&cookie, 0, [7; 32], 0, &nonce(16))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let keys = SessionKeys {
+            c2s: vec![0; 16],
+            ..session_keys(AeadAlgorithm::AeadAesSivCmac256, 32)
+        };
+        assert!(cookie_key().encode_cookie(&keys, [1; 16]).is_err());
+        assert!(
+            keys.client_request(&[0; 100], 0, [7; 32], 0, &nonce(16))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::borrow::Cow;
+
+use crate::{NtpDuration, NtpTimestamp, NtpVersion, PollInterval, ReferenceId};
+
+use super::{
+    ExtensionField, ExtensionFieldData, Mac, NtpAssociationMode, NtpHeader, NtpHeaderV3V4,
+    NtpLeapIndicator, NtpPacket,
+};
+
+/// Builder for NTPv3 and NTPv4 packets with arbitrary contents.
+///
+/// Unlike the constructors on [`NtpPacket`], the builder does not check the
+/// fields for consistency, so it can be used to produce unusual or malformed
+/// packets for testing other implementations.
+#[derive(Debug, Clone)]
+pub struct NtpPacketBuilder<'a> {
+    version: NtpVersion,
+    header: NtpHeaderV3V4,
+    efdata: ExtensionFieldData<'a>,
+    mac: Option<Mac<'a>>,
+}
+
+impl Default for NtpPacketBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> NtpPacketBuilder<'a> {
+    /// Start from an NTPv4 client packet with all other fields zero
+    pub fn new() -> Self {
+        NtpPacketBuilder {
+            version: NtpVersion::V4,
+            header: NtpHeaderV3V4::new(),
+            efdata: ExtensionFieldData::default(),
+            mac: None,
+        }
+    }
+
+    /// Set the protocol version. NTPv3 packets can't contain extension
+    /// fields, those are left out when serializing them.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called with [`NtpVersion::V5`], which has a different
+    /// header layout.
+    pub fn version(mut self, version: NtpVersion) -> Self {
+        assert!(
+            version != NtpVersion::V5,
+            "The packet builder does not support NTPv5"
+        );
+        self.version = version;
+        self
+    }
+
+    pub fn leap(mut self, leap: NtpLeapIndicator) -> Self {
+        self.header.leap = leap;
+        self
+    }
+
+    pub fn mode(mut self, mode: NtpAssociationMode) -> Self {
+        self.header.mode = mode;
+        self
+    }
+
+    pub fn stratum(mut self, stratum: u8) -> Self {
+        self.header.stratum = stratum;
+        self
+    }
+
+    pub fn poll(mut self, poll: PollInterval) -> Self {
+        self.header.poll = poll;
+        self
+    }
+
+    pub fn precision(mut self, precision: i8) -> Self {
+        self.header.precision = precision;
+        self
+    }
+
+    pub fn root_delay(mut self, root_delay: NtpDuration) -> Self {
+        self.header.root_delay = root_delay;
+        self
+    }
+
+    pub fn root_dispersion(mut self, root_dispersion: NtpDuration) -> Self {
+        self.header.root_dispersion = root_dispersion;
+        self
+    }
+
+    pub fn reference_id(mut self, reference_id: ReferenceId) -> Self {
+        self.header.reference_id = reference_id;
+        self
+    }
+
+    pub fn reference_timestamp(mut self, timestamp: NtpTimestamp) -> Self {
+        self.header.reference_timestamp = timestamp;
+        self
+    }
+
+    pub fn origin_timestamp(mut self, timestamp: NtpTimestamp) -> Self {
+        self.header.origin_timestamp = timestamp;
+        self
+    }
+
+    pub fn receive_timestamp(mut self, timestamp: NtpTimestamp) -> Self {
+        self.header.receive_timestamp = timestamp;
+        self
+    }
+
+    pub fn transmit_timestamp(mut self, timestamp: NtpTimestamp) -> Self {
+        self.header.transmit_timestamp = timestamp;
+        self
+    }
+
+    /// Add an extension field that is not covered by NTS authentication
+    pub fn extension_field(mut self, field: ExtensionField<'a>) -> Self {
+        self.efdata.untrusted.push(field);
+        self
+    }
+
+    /// Add an extension field that is authenticated, but not encrypted, by
+    /// NTS. Serializing the packet requires a cipher when such fields are
+    /// present.
+    pub fn authenticated_extension_field(mut self, field: ExtensionField<'a>) -> Self {
+        self.efdata.authenticated.push(field);
+        self
+    }
+
+    /// Add an extension field to the NTS encrypted field. Serializing the
+    /// packet requires a cipher when such fields are present.
+    pub fn encrypted_extension_field(mut self, field: ExtensionField<'a>) -> Self {
+        self.efdata.encrypted.push(field);
+        self
+    }
+
+    /// Append a MAC with the given key id and contents. Neither is checked,
+    /// so this can also produce crypto-NAKs and MACs of invalid length.
+    pub fn mac(mut self, key_id: u32, mac: impl Into<Cow<'a, [u8]>>) -> Self {
+        self.mac = Some(Mac::new(key_id, mac.into()));
+        self
+    }
+
+    pub fn build(self) -> NtpPacket<'a> {
+        let header = match self.version {
+            NtpVersion::V3 => NtpHeader::V3(self.header),
+            NtpVersion::V4 => NtpHeader::V4(self.header),
+            NtpVersion::V5 => unreachable!("Checked when setting the version"),
+        };
+
+        NtpPacket {
+            header,
+            efdata: self.efdata,
+            mac: self.mac,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::packet::{NoCipher, PacketParsingError};
+
+    use super::*;
+
+    fn serialize(packet: &NtpPacket) -> Vec<u8> {
+        let mut buffer = [0u8; 1024];
+        let mut cursor = Cursor::new(buffer.as_mut_slice());
+        packet.serialize(&mut cursor, &NoCipher, None).unwrap();
+        let used = cursor.position() as usize;
+        buffer[..used].to_vec()
+    }
+
+    #[test]
+    fn test_builder_header_fields() {
+        let packet = NtpPacketBuilder::new()
+            .version(NtpVersion::V3)
+            .leap(NtpLeapIndicator::Leap61)
+            .mode(NtpAssociationMode::SymmetricActive)
+            .stratum(17)
+            .precision(-20)
+            .reference_id(ReferenceId::KISS_DENY)
+            .origin_timestamp(NtpTimestamp::from_fixed_int(1))
+            .receive_timestamp(NtpTimestamp::from_fixed_int(2))
+            .transmit_timestamp(NtpTimestamp::from_fixed_int(3))
+            .build();
+
+        let data = serialize(&packet);
+        assert_eq!(data.len(), 48);
+
+        let (parsed, _) = NtpPacket::deserialize(&data, &NoCipher).unwrap();
+        assert_eq!(parsed, packet);
+        assert_eq!(parsed.version(), NtpVersion::V3);
+        assert_eq!(parsed.mode(), NtpAssociationMode::SymmetricActive);
+        assert_eq!(parsed.stratum(), 17);
+        assert_eq!(parsed.precision(), -20);
+        assert_eq!(parsed.reference_id(), ReferenceId::KISS_DENY);
+        assert_eq!(parsed.transmit_timestamp(), NtpTimestamp::from_fixed_int(3));
+    }
+
+    #[test]
+    fn test_builder_extension_fields() {
+        let packet = NtpPacketBuilder::new()
+            .extension_field(ExtensionField::Unknown {
+                type_id: 0x7777,
+                data: Cow::Borrowed(&[1; 24]),
+            })
+            .build();
+
+        let data = serialize(&packet);
+        let (parsed, _) = NtpPacket::deserialize(&data, &NoCipher).unwrap();
+        assert_eq!(parsed, packet);
+
+        // Authenticated fields can't be serialized without a cipher
+        let packet = NtpPacketBuilder::new()
+            .authenticated_extension_field(ExtensionField::UniqueIdentifier(Cow::Borrowed(
+                &[0; 32],
+            )))
+            .build();
+        let mut buffer = [0u8; 1024];
+        assert!(
+            packet
+                .serialize(&mut Cursor::new(buffer.as_mut_slice()), &NoCipher, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_builder_mac() {
+        let packet = NtpPacketBuilder::new().mac(5, vec![0xaa; 16]).build();
+        let data = serialize(&packet);
+        assert_eq!(data.len(), 48 + 4 + 16);
+        let (parsed, _) = NtpPacket::deserialize(&data, &NoCipher).unwrap();
+        assert_eq!(parsed.mac_key_id(), Some(5));
+
+        // Crypto-NAK
+        let packet = NtpPacketBuilder::new().mac(0, Vec::new()).build();
+        assert_eq!(serialize(&packet).len(), 48 + 4);
+
+        // MACs longer than allowed by RFC 7822 are rejected by the parser
+        let packet = NtpPacketBuilder::new().mac(5, vec![0xaa; 32]).build();
+        assert!(matches!(
+            NtpPacket::deserialize(&serialize(&packet), &NoCipher),
+            Err(PacketParsingError::IncorrectLength)
+        ));
+    }
+}
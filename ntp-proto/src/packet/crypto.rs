@@ -1,6 +1,8 @@
 use std::borrow::Borrow;
 use std::fmt::Display;
 
+#[cfg(feature = "rustcrypto")]
+use aes_gcm_siv::aead::{Aead, AeadInPlace, Payload};
 #[cfg(feature = "rustcrypto")]
 use aes_siv::{Key, KeyInit, siv::Aes128Siv, siv::Aes256Siv};
 use rand::Rng;
@@ -61,7 +63,8 @@ pub trait Cipher: Sync + Send + ZeroizeOnDrop + 'static {
         associated_data: &[u8],
     ) -> std::io::Result<EncryptResult>;
 
-    // MUST support arbitrary length nonces
+    // MUST accept nonces of arbitrary length, and reject those the algorithm
+    // cannot use with a DecryptError
     fn decrypt(
         &self,
         nonce: &[u8],
@@ -72,6 +75,18 @@ pub trait Cipher: Sync + Send + ZeroizeOnDrop + 'static {
     fn key_bytes(&self) -> &[u8];
 }
 
+/// A cipher that can encrypt with a nonce chosen by the caller. Regular
+/// encryption picks a random nonce and hands it to this.
+pub(crate) trait EncryptWithNonce: Cipher {
+    fn encrypt_with_nonce(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+        nonce: &[u8],
+    ) -> std::io::Result<EncryptResult>;
+}
+
 pub enum CipherHolder<'a> {
     DecodedServerCookie(DecodedServerCookie),
     Other(&'a dyn Cipher),
@@ -176,23 +191,23 @@ fn prepend_slice<'a>(
     Ok(&mut buffer[nonce.len()..])
 }
 
-impl Cipher for AesSivCmac256 {
+impl EncryptWithNonce for AesSivCmac256 {
     #[cfg(feature = "rustcrypto")]
-    fn encrypt(
+    fn encrypt_with_nonce(
         &self,
         buffer: &mut [u8],
         plaintext_length: usize,
         associated_data: &[u8],
+        nonce: &[u8],
     ) -> std::io::Result<EncryptResult> {
         let mut siv = Aes128Siv::new(&self.key);
-        let nonce: [u8; 16] = rand::thread_rng().r#gen();
 
-        let buffer = prepend_slice(buffer, plaintext_length, &nonce)?;
+        let buffer = prepend_slice(buffer, plaintext_length, nonce)?;
 
         // Create a wrapper around the plaintext portion of the buffer that has
         // the methods aes_siv needs to do encryption in-place.
         let mut buffer_wrap = Buffer::new(buffer, plaintext_length);
-        siv.encrypt_in_place([associated_data, &nonce], &mut buffer_wrap)
+        siv.encrypt_in_place([associated_data, nonce], &mut buffer_wrap)
             .map_err(|_| std::io::ErrorKind::Other)?;
 
         Ok(EncryptResult {
@@ -201,34 +216,21 @@ impl Cipher for AesSivCmac256 {
         })
     }
 
-    #[cfg(feature = "rustcrypto")]
-    fn decrypt(
-        &self,
-        nonce: &[u8],
-        ciphertext: &[u8],
-        associated_data: &[u8],
-    ) -> Result<Vec<u8>, DecryptError> {
-        let mut siv = Aes128Siv::new(&self.key);
-        siv.decrypt([associated_data, nonce], ciphertext)
-            .map_err(|_| DecryptError)
-    }
-
     #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
-    fn encrypt(
+    fn encrypt_with_nonce(
         &self,
         buffer: &mut [u8],
         plaintext_length: usize,
         associated_data: &[u8],
+        nonce: &[u8],
     ) -> std::io::Result<EncryptResult> {
-        let nonce: [u8; 16] = rand::thread_rng().r#gen();
-
-        let buffer = prepend_slice(buffer, plaintext_length, &nonce)?;
+        let buffer = prepend_slice(buffer, plaintext_length, nonce)?;
 
         let ciphertext_length = openssl_defs::encrypt_in_place(
             &self.key,
             buffer,
             plaintext_length,
-            [associated_data, nonce.as_slice()],
+            [associated_data, nonce],
         )?;
 
         Ok(EncryptResult {
@@ -236,6 +238,30 @@ impl Cipher for AesSivCmac256 {
             ciphertext_length,
         })
     }
+}
+
+impl Cipher for AesSivCmac256 {
+    fn encrypt(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+    ) -> std::io::Result<EncryptResult> {
+        let nonce: [u8; 16] = rand::thread_rng().r#gen();
+        self.encrypt_with_nonce(buffer, plaintext_length, associated_data, &nonce)
+    }
+
+    #[cfg(feature = "rustcrypto")]
+    fn decrypt(
+        &self,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
+        let mut siv = Aes128Siv::new(&self.key);
+        siv.decrypt([associated_data, nonce], ciphertext)
+            .map_err(|_| DecryptError)
+    }
 
     #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
     fn decrypt(
@@ -316,23 +342,23 @@ impl Drop for AesSivCmac512 {
     }
 }
 
-impl Cipher for AesSivCmac512 {
+impl EncryptWithNonce for AesSivCmac512 {
     #[cfg(feature = "rustcrypto")]
-    fn encrypt(
+    fn encrypt_with_nonce(
         &self,
         buffer: &mut [u8],
         plaintext_length: usize,
         associated_data: &[u8],
+        nonce: &[u8],
     ) -> std::io::Result<EncryptResult> {
         let mut siv = Aes256Siv::new(&self.key);
-        let nonce: [u8; 16] = rand::thread_rng().r#gen();
 
-        let buffer = prepend_slice(buffer, plaintext_length, &nonce)?;
+        let buffer = prepend_slice(buffer, plaintext_length, nonce)?;
 
         // Create a wrapper around the plaintext portion of the buffer that has
         // the methods aes_siv needs to do encryption in-place.
         let mut buffer_wrap = Buffer::new(buffer, plaintext_length);
-        siv.encrypt_in_place([associated_data, &nonce], &mut buffer_wrap)
+        siv.encrypt_in_place([associated_data, nonce], &mut buffer_wrap)
             .map_err(|_| std::io::ErrorKind::Other)?;
 
         Ok(EncryptResult {
@@ -341,6 +367,41 @@ impl Cipher for AesSivCmac512 {
         })
     }
 
+    #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
+    fn encrypt_with_nonce(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+        nonce: &[u8],
+    ) -> std::io::Result<EncryptResult> {
+        let buffer = prepend_slice(buffer, plaintext_length, nonce)?;
+
+        let ciphertext_length = openssl_defs::encrypt_in_place(
+            &self.key,
+            buffer,
+            plaintext_length,
+            [associated_data, nonce],
+        )?;
+
+        Ok(EncryptResult {
+            nonce_length: nonce.len(),
+            ciphertext_length,
+        })
+    }
+}
+
+impl Cipher for AesSivCmac512 {
+    fn encrypt(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+    ) -> std::io::Result<EncryptResult> {
+        let nonce: [u8; 16] = rand::thread_rng().r#gen();
+        self.encrypt_with_nonce(buffer, plaintext_length, associated_data, &nonce)
+    }
+
     #[cfg(feature = "rustcrypto")]
     fn decrypt(
         &self,
@@ -354,21 +415,115 @@ impl Cipher for AesSivCmac512 {
     }
 
     #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
-    fn encrypt(
+    fn decrypt(
+        &self,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
+        openssl_defs::decrypt_vec(&self.key, ciphertext, [associated_data, nonce])
+            .map_err(|_| DecryptError)
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        self.key.as_ref()
+    }
+}
+
+// Ensure siv is not shown in debug output
+impl std::fmt::Debug for AesSivCmac512 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AesSivCmac512").finish()
+    }
+}
+
+pub struct Aes128GcmSiv {
+    key: [u8; 16],
+}
+
+impl Aes128GcmSiv {
+    pub fn new(key: [u8; 16]) -> Self {
+        Self { key }
+    }
+
+    pub fn key_size() -> usize {
+        16
+    }
+
+    pub fn try_from(key_bytes: &[u8]) -> Result<Self, KeyError> {
+        key_bytes.try_into().map(Self::new).map_err(|_| KeyError)
+    }
+
+    /// Whether the crypto backend implements AES-GCM-SIV
+    #[cfg(feature = "rustcrypto")]
+    pub fn is_supported() -> bool {
+        true
+    }
+
+    /// Whether the crypto backend implements AES-GCM-SIV
+    #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
+    pub fn is_supported() -> bool {
+        openssl_defs::gcm_siv_supported()
+    }
+}
+
+impl ZeroizeOnDrop for Aes128GcmSiv {}
+
+impl Drop for Aes128GcmSiv {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl EncryptWithNonce for Aes128GcmSiv {
+    #[cfg(feature = "rustcrypto")]
+    fn encrypt_with_nonce(
         &self,
         buffer: &mut [u8],
         plaintext_length: usize,
         associated_data: &[u8],
+        nonce: &[u8],
     ) -> std::io::Result<EncryptResult> {
-        let nonce: [u8; 16] = rand::thread_rng().r#gen();
+        // Unlike AES-SIV, AES-GCM-SIV only takes nonces of exactly 12 bytes
+        let nonce: &[u8; 12] = nonce
+            .try_into()
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+        let cipher = aes_gcm_siv::Aes128GcmSiv::new(&self.key.into());
 
-        let buffer = prepend_slice(buffer, plaintext_length, &nonce)?;
+        let buffer = prepend_slice(buffer, plaintext_length, nonce)?;
 
-        let ciphertext_length = openssl_defs::encrypt_in_place(
+        let mut buffer_wrap = Buffer::new(buffer, plaintext_length);
+        cipher
+            .encrypt_in_place(nonce.into(), associated_data, &mut buffer_wrap)
+            .map_err(|_| std::io::ErrorKind::Other)?;
+
+        Ok(EncryptResult {
+            nonce_length: nonce.len(),
+            ciphertext_length: buffer_wrap.valid(),
+        })
+    }
+
+    #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
+    fn encrypt_with_nonce(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+        nonce: &[u8],
+    ) -> std::io::Result<EncryptResult> {
+        // Unlike AES-SIV, AES-GCM-SIV only takes nonces of exactly 12 bytes
+        let nonce: &[u8; 12] = nonce
+            .try_into()
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+
+        let buffer = prepend_slice(buffer, plaintext_length, nonce)?;
+
+        let ciphertext_length = openssl_defs::gcm_siv_encrypt_in_place(
             &self.key,
+            nonce,
             buffer,
             plaintext_length,
-            [associated_data, nonce.as_slice()],
+            associated_data,
         )?;
 
         Ok(EncryptResult {
@@ -376,6 +531,35 @@ impl Cipher for AesSivCmac512 {
             ciphertext_length,
         })
     }
+}
+
+impl Cipher for Aes128GcmSiv {
+    fn encrypt(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+    ) -> std::io::Result<EncryptResult> {
+        let nonce: [u8; 12] = rand::thread_rng().r#gen();
+        self.encrypt_with_nonce(buffer, plaintext_length, associated_data, &nonce)
+    }
+
+    #[cfg(feature = "rustcrypto")]
+    fn decrypt(
+        &self,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
+        let nonce: &[u8; 12] = nonce.try_into().map_err(|_| DecryptError)?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: associated_data,
+        };
+        aes_gcm_siv::Aes128GcmSiv::new(&self.key.into())
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| DecryptError)
+    }
 
     #[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
     fn decrypt(
@@ -384,19 +568,57 @@ impl Cipher for AesSivCmac512 {
         ciphertext: &[u8],
         associated_data: &[u8],
     ) -> Result<Vec<u8>, DecryptError> {
-        openssl_defs::decrypt_vec(&self.key, ciphertext, [associated_data, nonce])
+        let nonce: &[u8; 12] = nonce.try_into().map_err(|_| DecryptError)?;
+        openssl_defs::gcm_siv_decrypt_vec(&self.key, nonce, ciphertext, associated_data)
             .map_err(|_| DecryptError)
     }
 
     fn key_bytes(&self) -> &[u8] {
-        self.key.as_ref()
+        &self.key
     }
 }
 
-// Ensure siv is not shown in debug output
-impl std::fmt::Debug for AesSivCmac512 {
+// Ensure the key is not shown in debug output
+impl std::fmt::Debug for Aes128GcmSiv {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("AesSivCmac512").finish()
+        f.debug_struct("Aes128GcmSiv").finish()
+    }
+}
+
+/// Encrypts with the same nonce every time, to produce reproducible test
+/// vectors. Reusing a nonce with the same key breaks the security of NTS.
+#[cfg(feature = "nts-test-vectors")]
+pub(crate) struct FixedNonce<C> {
+    pub(crate) cipher: C,
+    pub(crate) nonce: Vec<u8>,
+}
+
+#[cfg(feature = "nts-test-vectors")]
+impl<C: EncryptWithNonce> ZeroizeOnDrop for FixedNonce<C> {}
+
+#[cfg(feature = "nts-test-vectors")]
+impl<C: EncryptWithNonce> Cipher for FixedNonce<C> {
+    fn encrypt(
+        &self,
+        buffer: &mut [u8],
+        plaintext_length: usize,
+        associated_data: &[u8],
+    ) -> std::io::Result<EncryptResult> {
+        self.cipher
+            .encrypt_with_nonce(buffer, plaintext_length, associated_data, &self.nonce)
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
+        self.cipher.decrypt(nonce, ciphertext, associated_data)
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        self.cipher.key_bytes()
     }
 }
 
@@ -556,6 +778,106 @@ mod tests {
         assert_eq!(result, (0..16).collect::<Vec<u8>>());
     }
 
+    #[test]
+    fn test_aes_128_gcm_siv_with_assoc_data() {
+        let mut testvec: Vec<u8> = (0..16).collect();
+        testvec.resize(testvec.len() + 32, 0);
+        let key = Aes128GcmSiv::new([0u8; 16]);
+        let EncryptResult {
+            nonce_length,
+            ciphertext_length,
+        } = key.encrypt(&mut testvec, 16, &[1]).unwrap();
+        assert_eq!(nonce_length, 12);
+        assert!(
+            key.decrypt(
+                &testvec[..nonce_length],
+                &testvec[nonce_length..(nonce_length + ciphertext_length)],
+                &[2]
+            )
+            .is_err()
+        );
+        assert!(
+            key.decrypt(
+                &testvec[1..nonce_length],
+                &testvec[nonce_length..(nonce_length + ciphertext_length)],
+                &[1]
+            )
+            .is_err()
+        );
+        let result = key
+            .decrypt(
+                &testvec[..nonce_length],
+                &testvec[nonce_length..(nonce_length + ciphertext_length)],
+                &[1],
+            )
+            .unwrap();
+        assert_eq!(result, (0..16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_aes_128_gcm_siv_vectors() {
+        fn hex(data: &str) -> Vec<u8> {
+            (0..data.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&data[i..i + 2], 16).unwrap())
+                .collect()
+        }
+
+        // RFC 8452, appendix C.1
+        let key = Aes128GcmSiv::try_from(&hex("01000000000000000000000000000000")).unwrap();
+        let nonce = hex("030000000000000000000000");
+
+        for (plaintext, associated_data, expected) in [
+            ("", "", "dc20e2d83f25705bb49e439eca56de25"),
+            (
+                "0100000000000000",
+                "",
+                "b5d839330ac7b786578782fff6013b815b287c22493a364c",
+            ),
+            (
+                "0100000000000000000000000000000002000000000000000000000000000000",
+                "",
+                "84e07e62ba83a6585417245d7ec413a9fe427d6315c09b57ce45f2e3936a94451a8e45dcd4578c667cd86847bf6155ff",
+            ),
+            (
+                "0200000000000000",
+                "01",
+                "1e6daba35669f4273b0a1a2560969cdf790d99759abd1508",
+            ),
+        ] {
+            let plaintext = hex(plaintext);
+            let associated_data = hex(associated_data);
+
+            let mut buffer = plaintext.clone();
+            buffer.resize(nonce.len() + plaintext.len() + 16, 0);
+            let EncryptResult {
+                nonce_length,
+                ciphertext_length,
+            } = key
+                .encrypt_with_nonce(&mut buffer, plaintext.len(), &associated_data, &nonce)
+                .unwrap();
+            assert_eq!(buffer[..nonce_length], nonce);
+            let ciphertext = &mut buffer[nonce_length..][..ciphertext_length];
+            assert_eq!(ciphertext, hex(expected));
+
+            assert_eq!(
+                key.decrypt(&nonce, ciphertext, &associated_data).unwrap(),
+                plaintext
+            );
+
+            ciphertext[0] ^= 1;
+            assert!(key.decrypt(&nonce, ciphertext, &associated_data).is_err());
+        }
+
+        // Nonces of other lengths are rejected rather than misused
+        let mut buffer = [0; 64];
+        assert!(
+            key.encrypt_with_nonce(&mut buffer, 8, &[], &[0; 16])
+                .is_err()
+        );
+        assert!(key.decrypt(&[0; 16], &buffer[..24], &[]).is_err());
+    }
+
     #[test]
     fn key_functions_correctness() {
         assert_eq!(
@@ -575,5 +897,9 @@ mod tests {
 
         let slice = &key_bytes[..AesSivCmac512::key_size()];
         assert_eq!(AesSivCmac512::try_from(slice).unwrap().key_bytes(), slice);
+
+        assert!(Aes128GcmSiv::try_from(&key_bytes).is_err());
+        let slice = &key_bytes[..Aes128GcmSiv::key_size()];
+        assert_eq!(Aes128GcmSiv::try_from(slice).unwrap().key_bytes(), slice);
     }
 }
@@ -77,10 +77,6 @@ impl<const N: usize, T: EVPCipher<Key = [u8; N]>> From<[u8; N]> for Key<T> {
     }
 }
 
-// NOTE for future modifications: if AES-GCM-SIV is to be added, keep in mind lessons learned the hard way:
-// - the tag is at the end of the ciphertext, whereas with AES-SIV it sits at the beginning
-// - the nonce needs to be set using crypt_init as the IV and is not provided as associated data
-
 pub fn decrypt_vec<T: EVPCipher>(
     key: &Key<T>,
     ciphertext: &[u8],
@@ -141,6 +137,68 @@ where
     Ok(ciphertext_length + tag.len())
 }
 
+// Unlike with AES-SIV, for AES-GCM-SIV:
+// - the tag is at the end of the ciphertext, whereas with AES-SIV it sits at the beginning
+// - the nonce needs to be set using crypt_init as the IV and is not provided as associated data
+
+/// OpenSSL only provides AES-GCM-SIV from version 3.2 onwards
+pub fn gcm_siv_supported() -> bool {
+    openssl::cipher::Cipher::fetch(None, "AES-128-GCM-SIV", None).is_ok()
+}
+
+pub fn gcm_siv_decrypt_vec(
+    key: &[u8; 16],
+    nonce: &[u8; 12],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> io::Result<Vec<u8>> {
+    let cipher = &openssl::cipher::Cipher::fetch(None, "AES-128-GCM-SIV", None)?;
+    let mut ctx = openssl::cipher_ctx::CipherCtx::new()?;
+
+    ctx.decrypt_init(Some(cipher), Some(key), Some(nonce))?;
+
+    let (ciphertext, tag) = ciphertext
+        .split_at_checked(ciphertext.len().saturating_sub(ctx.tag_length()))
+        .filter(|(_, tag)| tag.len() == ctx.tag_length())
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+    let mut output = Vec::new();
+    ctx.set_tag(tag)?;
+    ctx.cipher_update(aad, None)?;
+    ctx.cipher_update_vec(ciphertext, &mut output)?;
+    ctx.cipher_final_vec(&mut output)?;
+
+    Ok(output)
+}
+
+pub fn gcm_siv_encrypt_in_place(
+    key: &[u8; 16],
+    nonce: &[u8; 12],
+    buffer: &mut [u8],
+    plaintext_length: usize,
+    aad: &[u8],
+) -> io::Result<usize> {
+    let cipher = &openssl::cipher::Cipher::fetch(None, "AES-128-GCM-SIV", None)?;
+    let mut ctx = openssl::cipher_ctx::CipherCtx::new()?;
+
+    ctx.encrypt_init(Some(cipher), Some(key), Some(nonce))?;
+
+    if buffer.len() < plaintext_length + ctx.tag_length() {
+        return Err(std::io::ErrorKind::WriteZero.into());
+    }
+
+    let (ciphertext, rest) = buffer.split_at_mut(plaintext_length);
+
+    ctx.cipher_update(aad, None)?;
+    let mut ciphertext_length = ctx.cipher_update_inplace(ciphertext, plaintext_length)?;
+    ciphertext_length += ctx.cipher_final(&mut ciphertext[ciphertext_length..])?;
+
+    let tag = &mut rest[..ctx.tag_length()];
+    ctx.tag(tag)?;
+
+    Ok(ciphertext_length + tag.len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -164,4 +222,18 @@ mod tests {
         let plaintext = decrypt_vec(&key, ciphertext, [&[1], &[2]]).unwrap();
         assert_eq!(plaintext, payload);
     }
+
+    #[test]
+    fn gcm_siv_roundtrip() {
+        let payload: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let mut buf = [0; 32];
+        buf[..16].copy_from_slice(&payload);
+        let size = gcm_siv_encrypt_in_place(&[0; 16], &[1; 12], &mut buf, 16, &[2]).unwrap();
+        let ciphertext = &buf[..size];
+        let plaintext = gcm_siv_decrypt_vec(&[0; 16], &[1; 12], ciphertext, &[2]).unwrap();
+        assert_eq!(plaintext, payload);
+
+        assert!(gcm_siv_decrypt_vec(&[0; 16], &[1; 12], ciphertext, &[3]).is_err());
+        assert!(gcm_siv_decrypt_vec(&[0; 16], &[1; 12], &ciphertext[..15], &[2]).is_err());
+    }
 }
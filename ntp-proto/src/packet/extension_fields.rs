@@ -1,11 +1,15 @@
 use std::{
     borrow::Cow,
+    collections::HashMap,
     io::{Cursor, Write},
+    sync::Arc,
 };
 
 use crate::{io::NonBlockingWrite, keyset::DecodedServerCookie};
 
-use crate::packet::v5::extension_fields::{ReferenceIdRequest, ReferenceIdResponse};
+use crate::packet::v5::extension_fields::{
+    MonotonicReceiveTimestamp, ReferenceIdRequest, ReferenceIdResponse,
+};
 
 use super::{Cipher, CipherProvider, Mac, crypto::EncryptResult, error::ParsingError};
 
@@ -20,6 +24,7 @@ pub(super) enum ExtensionFieldTypeId {
     Padding,
     ReferenceIdRequest,
     ReferenceIdResponse,
+    MonotonicReceiveTimestamp,
 }
 
 impl ExtensionFieldTypeId {
@@ -33,6 +38,7 @@ impl ExtensionFieldTypeId {
             0xF501 => Self::Padding,
             0xF503 => Self::ReferenceIdRequest,
             0xF504 => Self::ReferenceIdResponse,
+            0xF508 => Self::MonotonicReceiveTimestamp,
             _ => Self::Unknown { type_id },
         }
     }
@@ -47,6 +53,7 @@ impl ExtensionFieldTypeId {
             ExtensionFieldTypeId::Padding => 0xF501,
             ExtensionFieldTypeId::ReferenceIdRequest => 0xF503,
             ExtensionFieldTypeId::ReferenceIdResponse => 0xF504,
+            ExtensionFieldTypeId::MonotonicReceiveTimestamp => 0xF508,
             ExtensionFieldTypeId::Unknown { type_id } => type_id,
         }
     }
@@ -62,6 +69,7 @@ pub enum ExtensionField<'a> {
     Padding(usize),
     ReferenceIdRequest(super::v5::extension_fields::ReferenceIdRequest),
     ReferenceIdResponse(super::v5::extension_fields::ReferenceIdResponse<'a>),
+    MonotonicReceiveTimestamp(super::v5::extension_fields::MonotonicReceiveTimestamp),
     Unknown { type_id: u16, data: Cow<'a, [u8]> },
 }
 
@@ -83,6 +91,9 @@ impl std::fmt::Debug for ExtensionField<'_> {
             Self::Padding(len) => f.debug_struct("Padding").field("length", &len).finish(),
             Self::ReferenceIdRequest(r) => f.debug_tuple("ReferenceIdRequest").field(r).finish(),
             Self::ReferenceIdResponse(r) => f.debug_tuple("ReferenceIdResponse").field(r).finish(),
+            Self::MonotonicReceiveTimestamp(m) => {
+                f.debug_tuple("MonotonicReceiveTimestamp").field(m).finish()
+            }
             Self::Unknown {
                 type_id: typeid,
                 data,
@@ -122,9 +133,30 @@ impl<'a> ExtensionField<'a> {
             Padding(len) => Padding(len),
             ReferenceIdRequest(req) => ReferenceIdRequest(req),
             ReferenceIdResponse(res) => ReferenceIdResponse(res.into_owned()),
+            MonotonicReceiveTimestamp(m) => MonotonicReceiveTimestamp(m),
         }
     }
 
+    /// The type id with which this field is encoded on the wire
+    pub fn type_id(&self) -> u16 {
+        use ExtensionField::*;
+
+        let ef_id = match self {
+            Unknown { type_id, .. } => return *type_id,
+            UniqueIdentifier(_) => ExtensionFieldTypeId::UniqueIdentifier,
+            NtsCookie(_) => ExtensionFieldTypeId::NtsCookie,
+            NtsCookiePlaceholder { .. } => ExtensionFieldTypeId::NtsCookiePlaceholder,
+            InvalidNtsEncryptedField => ExtensionFieldTypeId::NtsEncryptedField,
+            DraftIdentification(_) => ExtensionFieldTypeId::DraftIdentification,
+            Padding(_) => ExtensionFieldTypeId::Padding,
+            ReferenceIdRequest(_) => ExtensionFieldTypeId::ReferenceIdRequest,
+            ReferenceIdResponse(_) => ExtensionFieldTypeId::ReferenceIdResponse,
+            MonotonicReceiveTimestamp(_) => ExtensionFieldTypeId::MonotonicReceiveTimestamp,
+        };
+
+        ef_id.to_type_id()
+    }
+
     pub(crate) fn serialize(
         &self,
         w: impl NonBlockingWrite,
@@ -151,6 +183,7 @@ impl<'a> ExtensionField<'a> {
             Padding(len) => Self::encode_padding_field(w, *len, minimum_size, version),
             ReferenceIdRequest(req) => req.serialize(w),
             ReferenceIdResponse(res) => res.serialize(w),
+            MonotonicReceiveTimestamp(m) => m.serialize(w),
         }
     }
 
@@ -508,11 +541,113 @@ impl<'a> ExtensionField<'a> {
             {
                 Ok(ReferenceIdResponse::decode(message).into())
             }
+            TypeId::MonotonicReceiveTimestamp
+                if extension_header_version == ExtensionHeaderVersion::V5 =>
+            {
+                Ok(MonotonicReceiveTimestamp::decode(message)?.into())
+            }
             type_id => EF::decode_unknown(type_id.to_type_id(), message),
         }
     }
 }
 
+/// Handler for an extension field type that is not natively supported.
+///
+/// Fields of such a type end up in [`ExtensionField::Unknown`]. Registering
+/// a handler for the type with a [`Server`](crate::Server) allows it to
+/// respond to those fields, which makes it possible to experiment with new
+/// (draft) extensions without changes to the packet parser.
+pub trait ExtensionFieldHandler: Send + Sync {
+    /// Produce the response to a field of the registered type in a client
+    /// request. Returning `None` leaves the field unanswered.
+    ///
+    /// `authenticated` indicates whether the field was covered by NTS
+    /// authentication.
+    fn respond(&self, data: &[u8], authenticated: bool) -> Option<ExtensionField<'static>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionFieldRegistrationError {
+    /// The type id is already handled by the packet parser itself
+    Builtin(u16),
+    /// A handler was already registered for this type id
+    AlreadyRegistered(u16),
+}
+
+impl std::fmt::Display for ExtensionFieldRegistrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Builtin(type_id) => {
+                write!(f, "extension field type {type_id:#06x} is handled natively")
+            }
+            Self::AlreadyRegistered(type_id) => write!(
+                f,
+                "a handler for extension field type {type_id:#06x} is already registered"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExtensionFieldRegistrationError {}
+
+/// Collection of [`ExtensionFieldHandler`]s, indexed by extension field type id
+#[derive(Clone, Default)]
+pub struct ExtensionFieldHandlers {
+    handlers: HashMap<u16, Arc<dyn ExtensionFieldHandler>>,
+}
+
+impl std::fmt::Debug for ExtensionFieldHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+impl ExtensionFieldHandlers {
+    pub fn register(
+        &mut self,
+        type_id: u16,
+        handler: Arc<dyn ExtensionFieldHandler>,
+    ) -> Result<(), ExtensionFieldRegistrationError> {
+        if !matches!(
+            ExtensionFieldTypeId::from_type_id(type_id),
+            ExtensionFieldTypeId::Unknown { .. }
+        ) {
+            return Err(ExtensionFieldRegistrationError::Builtin(type_id));
+        }
+
+        match self.handlers.entry(type_id) {
+            std::collections::hash_map::Entry::Occupied(_) => {
+                Err(ExtensionFieldRegistrationError::AlreadyRegistered(type_id))
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(handler);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Responses of the registered handlers to the unknown fields in `fields`
+    pub(crate) fn respond<'a>(
+        &self,
+        fields: impl IntoIterator<Item = &'a ExtensionField<'a>>,
+        authenticated: bool,
+    ) -> Vec<ExtensionField<'static>> {
+        fields
+            .into_iter()
+            .filter_map(|ef| match ef {
+                ExtensionField::Unknown { type_id, data } => {
+                    self.handlers.get(type_id)?.respond(data, authenticated)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub(super) struct ExtensionFieldData<'a> {
     pub(super) authenticated: Vec<ExtensionField<'a>>,
@@ -636,9 +771,9 @@ impl<'a> ExtensionFieldData<'a> {
                     }
                 };
 
-                // for the current ciphers we allow in non-test code,
-                // the nonce should always be 16 bytes
-                debug_assert_eq!(encrypted.nonce.len(), 16);
+                // for the current ciphers we allow in non-test code, the nonce
+                // should be 16 bytes (AES-SIV) or 12 bytes (AES-GCM-SIV)
+                debug_assert!(matches!(encrypted.nonce.len(), 12 | 16));
 
                 efdata.encrypted.extend(encrypted_fields);
                 cookie = match cipher {
@@ -730,6 +865,8 @@ impl<'a> RawEncryptedField<'a> {
                 // TODO: Discuss whether we want this check
                 Err(ParsingError::MalformedNtsExtensionFields)
             } else {
+                // The plaintext does not outlive this function, unlike the
+                // receive buffer the other fields borrow from
                 Ok(ExtensionField::decode(&encrypted_field, version)
                     .map_err(ParsingError::generalize)?
                     .into_owned())
@@ -884,6 +1021,79 @@ const fn next_multiple_of_usize(lhs: usize, rhs: usize) -> usize {
     }
 }
 
+/// Parse a sequence of extension fields. In NTPv4 every field is padded, so
+/// fields that parse should survive a round trip through serialization.
+#[cfg(feature = "__internal-fuzz")]
+pub fn fuzz_extension_fields(data: &[u8], version: ExtensionHeaderVersion) {
+    for field in RawExtensionField::deserialize_sequence(
+        data,
+        0,
+        RawExtensionField::BARE_MINIMUM_SIZE,
+        version,
+    ) {
+        let Ok((_, raw)) = field else {
+            return;
+        };
+        if raw.type_id == ExtensionFieldTypeId::NtsEncryptedField {
+            continue;
+        }
+        let Ok(field) = ExtensionField::decode(&raw, version) else {
+            continue;
+        };
+
+        let mut buffer = Vec::new();
+        field.serialize(&mut buffer, 4, version).unwrap();
+
+        let (_, reraw) = RawExtensionField::deserialize_sequence(
+            &buffer,
+            0,
+            RawExtensionField::BARE_MINIMUM_SIZE,
+            version,
+        )
+        .next()
+        .unwrap()
+        .unwrap();
+        let reparsed = ExtensionField::decode(&reraw, version).unwrap();
+        if version == ExtensionHeaderVersion::V4 {
+            assert_eq!(field, reparsed);
+        }
+    }
+}
+
+/// Encrypt `plaintext` into an NTS encrypted extension field and parse it
+/// again. Decryption must succeed, any error should come from parsing the
+/// fields inside.
+#[cfg(feature = "__internal-fuzz")]
+pub fn fuzz_encrypted_field(plaintext: &[u8], aad: &[u8], version: ExtensionHeaderVersion) {
+    if plaintext.len() > u16::MAX as usize / 2 {
+        return;
+    }
+    let cipher = super::AesSivCmac256::try_from(&[0; 32]).unwrap();
+
+    let mut buffer = vec![0u8; plaintext.len() + 64];
+    buffer[..plaintext.len()].copy_from_slice(plaintext);
+    let Ok(EncryptResult {
+        nonce_length,
+        ciphertext_length,
+    }) = cipher.encrypt(&mut buffer, plaintext.len(), aad)
+    else {
+        return;
+    };
+
+    let mut message = Vec::new();
+    message.extend_from_slice(&(nonce_length as u16).to_be_bytes());
+    message.extend_from_slice(&(ciphertext_length as u16).to_be_bytes());
+    message.extend_from_slice(&buffer[..nonce_length]);
+    message.resize(4 + next_multiple_of_usize(nonce_length, 4), 0);
+    message.extend_from_slice(&buffer[nonce_length..nonce_length + ciphertext_length]);
+
+    let encrypted = RawEncryptedField::from_message_bytes(&message).unwrap();
+    assert!(!matches!(
+        encrypted.decrypt(&cipher, aad, version),
+        Err(ParsingError::DecryptError(_))
+    ));
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{keyset::KeySet, packet::AesSivCmac256};
@@ -898,6 +1108,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn extension_field_type_id() {
+        assert_eq!(
+            ExtensionField::UniqueIdentifier(Cow::Borrowed(&[])).type_id(),
+            0x104
+        );
+        assert_eq!(
+            ExtensionField::NtsCookie(Cow::Borrowed(&[])).type_id(),
+            0x204
+        );
+        assert_eq!(ExtensionField::Padding(4).type_id(), 0xF501);
+        assert_eq!(
+            ExtensionField::Unknown {
+                type_id: 0x2005,
+                data: Cow::Borrowed(&[])
+            }
+            .type_id(),
+            0x2005
+        );
+    }
+
     #[test]
     fn test_unique_identifier() {
         let identifier: Vec<_> = (0..16).collect();
@@ -1379,7 +1610,7 @@ mod tests {
             untrusted: vec![],
         };
 
-        let mut w = [0u8; 256];
+        let mut w = [0u8; 512];
         let mut cursor = Cursor::new(w.as_mut_slice());
         data.serialize(&mut cursor, &keyset, ExtensionHeaderVersion::V4)
             .unwrap();
@@ -1,8 +1,12 @@
 use std::borrow::Cow;
 
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
 use crate::io::NonBlockingWrite;
 
-use super::error::ParsingError;
+use super::{crypto::KeyError, error::ParsingError};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(super) struct Mac<'a> {
@@ -17,6 +21,10 @@ impl<'a> Mac<'a> {
     // crypto-NAK.
     pub(super) const MAXIMUM_SIZE: usize = 24;
 
+    pub(super) fn new(keyid: u32, mac: Cow<'a, [u8]>) -> Self {
+        Mac { keyid, mac }
+    }
+
     pub(super) fn into_owned(self) -> Mac<'static> {
         Mac {
             keyid: self.keyid,
@@ -24,6 +32,10 @@ impl<'a> Mac<'a> {
         }
     }
 
+    pub(super) fn keyid(&self) -> u32 {
+        self.keyid
+    }
+
     pub(super) fn serialize(&self, mut w: impl NonBlockingWrite) -> std::io::Result<()> {
         w.write_all(&self.keyid.to_be_bytes())?;
         w.write_all(&self.mac)
@@ -43,6 +55,207 @@ impl<'a> Mac<'a> {
     }
 }
 
+/// Algorithm used to compute the MAC of a symmetric key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub enum MacAlgorithm {
+    /// AES-128-CMAC, as specified in RFC 8573
+    #[cfg_attr(feature = "serde", serde(rename = "AES128CMAC"))]
+    Aes128Cmac,
+    /// MD5 digest of the key followed by the packet, as used by older ntpd
+    /// versions. This is not secure, see RFC 8573.
+    #[cfg_attr(feature = "serde", serde(rename = "MD5"))]
+    Md5,
+    /// SHA-1 digest of the key followed by the packet. This is not secure.
+    #[cfg_attr(feature = "serde", serde(rename = "SHA1"))]
+    Sha1,
+    /// SHA-256 digest of the key followed by the packet, truncated to 20
+    /// bytes to fit the MAC field. This is not secure.
+    #[cfg_attr(feature = "serde", serde(rename = "SHA256"))]
+    Sha256,
+}
+
+impl MacAlgorithm {
+    /// Whether this is one of the digest based algorithms from older ntpd
+    /// versions, which should only be used for compatibility with devices
+    /// that don't support anything better.
+    pub fn is_legacy(self) -> bool {
+        match self {
+            MacAlgorithm::Aes128Cmac => false,
+            MacAlgorithm::Md5 | MacAlgorithm::Sha1 | MacAlgorithm::Sha256 => true,
+        }
+    }
+
+    fn valid_key_length(self, length: usize) -> bool {
+        match self {
+            MacAlgorithm::Aes128Cmac => length == 16,
+            // ntpd accepts keys of arbitrary length for the digest algorithms
+            MacAlgorithm::Md5 | MacAlgorithm::Sha1 | MacAlgorithm::Sha256 => {
+                (1..=LEGACY_MAXIMUM_KEY_LENGTH).contains(&length)
+            }
+        }
+    }
+
+    fn digest_length(self) -> usize {
+        match self {
+            MacAlgorithm::Aes128Cmac | MacAlgorithm::Md5 => 16,
+            MacAlgorithm::Sha1 | MacAlgorithm::Sha256 => 20,
+        }
+    }
+}
+
+const LEGACY_MAXIMUM_KEY_LENGTH: usize = 64;
+
+/// A pre-shared key for authenticating packets with a MAC (RFC 5905 section 7.3)
+#[derive(Clone)]
+pub struct SymmetricKey {
+    id: u32,
+    algorithm: MacAlgorithm,
+    key: Vec<u8>,
+}
+
+impl Drop for SymmetricKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SymmetricKey {}
+
+impl std::fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymmetricKey")
+            .field("id", &self.id)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SymmetricKey {
+    /// Create a new key. Key id 0 is reserved for crypto-NAKs and can't be used.
+    pub fn new(id: u32, algorithm: MacAlgorithm, key: &[u8]) -> Result<Self, KeyError> {
+        if id == 0 || !algorithm.valid_key_length(key.len()) {
+            return Err(KeyError);
+        }
+
+        Ok(SymmetricKey {
+            id,
+            algorithm,
+            key: key.to_vec(),
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn algorithm(&self) -> MacAlgorithm {
+        self.algorithm
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut digest = match self.algorithm {
+            MacAlgorithm::Aes128Cmac => aes_128_cmac(&self.key, data),
+            MacAlgorithm::Md5 | MacAlgorithm::Sha1 | MacAlgorithm::Sha256 => {
+                legacy_digest(self.algorithm, &self.key, data)
+            }
+        };
+        digest.truncate(self.algorithm.digest_length());
+        digest
+    }
+
+    pub(super) fn sign(&self, data: &[u8]) -> Mac<'static> {
+        Mac {
+            keyid: self.id,
+            mac: Cow::Owned(self.digest(data)),
+        }
+    }
+
+    /// Check the MAC at the end of `message` against this key
+    pub fn verify(&self, message: &[u8]) -> bool {
+        let Some(split) = message
+            .len()
+            .checked_sub(4 + self.algorithm.digest_length())
+        else {
+            return false;
+        };
+        let (data, mac) = message.split_at(split);
+
+        mac[..4] == self.id.to_be_bytes() && constant_time_eq(&mac[4..], &self.digest(data))
+    }
+}
+
+#[cfg(feature = "rustcrypto")]
+fn aes_128_cmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use cmac::{Cmac, Mac as _};
+
+    let mut mac =
+        Cmac::<aes::Aes128>::new_from_slice(key).expect("key length is checked on creation");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
+fn aes_128_cmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use openssl::{pkey::PKey, sign::Signer, symm::Cipher};
+
+    let key = PKey::cmac(&Cipher::aes_128_cbc(), key).expect("OpenSSL could not load CMAC key");
+    let mut signer = Signer::new_without_digest(&key).expect("OpenSSL could not create signer");
+    signer.update(data).expect("OpenSSL could not compute CMAC");
+    signer
+        .sign_to_vec()
+        .expect("OpenSSL could not compute CMAC")
+}
+
+#[cfg(feature = "rustcrypto")]
+fn legacy_digest(algorithm: MacAlgorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    use md5::{Digest, Md5};
+    use sha1::Sha1;
+    use sha2::Sha256;
+
+    fn digest<D: Digest>(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut hasher = D::new();
+        hasher.update(key);
+        hasher.update(data);
+        hasher.finalize().to_vec()
+    }
+
+    match algorithm {
+        MacAlgorithm::Md5 => digest::<Md5>(key, data),
+        MacAlgorithm::Sha1 => digest::<Sha1>(key, data),
+        MacAlgorithm::Sha256 => digest::<Sha256>(key, data),
+        MacAlgorithm::Aes128Cmac => unreachable!("CMAC is not a digest"),
+    }
+}
+
+#[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
+fn legacy_digest(algorithm: MacAlgorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    use openssl::hash::{Hasher, MessageDigest};
+
+    let digest = match algorithm {
+        MacAlgorithm::Md5 => MessageDigest::md5(),
+        MacAlgorithm::Sha1 => MessageDigest::sha1(),
+        MacAlgorithm::Sha256 => MessageDigest::sha256(),
+        MacAlgorithm::Aes128Cmac => unreachable!("CMAC is not a digest"),
+    };
+
+    let mut hasher = Hasher::new(digest).expect("OpenSSL could not create hasher");
+    hasher
+        .update(key)
+        .expect("OpenSSL could not compute digest");
+    hasher
+        .update(data)
+        .expect("OpenSSL could not compute digest");
+    hasher
+        .finish()
+        .expect("OpenSSL could not compute digest")
+        .to_vec()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,6 +289,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn aes_128_cmac_test_vectors() {
+        // RFC 4493 section 4
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        assert_eq!(
+            aes_128_cmac(&key, &[]),
+            [
+                0xbb, 0x1d, 0x69, 0x29, 0xe9, 0x59, 0x37, 0x28, 0x7f, 0xa3, 0x7d, 0x12, 0x9b, 0x75,
+                0x67, 0x46
+            ]
+        );
+        let message = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        assert_eq!(
+            aes_128_cmac(&key, &message),
+            [
+                0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a,
+                0x28, 0x7c
+            ]
+        );
+    }
+
+    #[test]
+    fn symmetric_key_sign_verify() {
+        let key = SymmetricKey::new(7, MacAlgorithm::Aes128Cmac, &[0x42; 16]).unwrap();
+        let data = [1u8; 48];
+
+        let mut message = data.to_vec();
+        key.sign(&data).serialize(&mut message).unwrap();
+        assert_eq!(message.len(), 48 + 20);
+        assert!(key.verify(&message));
+
+        let other_id = SymmetricKey::new(8, MacAlgorithm::Aes128Cmac, &[0x42; 16]).unwrap();
+        assert!(!other_id.verify(&message));
+        let other_key = SymmetricKey::new(7, MacAlgorithm::Aes128Cmac, &[0x43; 16]).unwrap();
+        assert!(!other_key.verify(&message));
+
+        message[3] ^= 1;
+        assert!(!key.verify(&message));
+        assert!(!key.verify(&message[..10]));
+    }
+
+    #[test]
+    fn symmetric_key_rejects_invalid() {
+        assert!(SymmetricKey::new(0, MacAlgorithm::Aes128Cmac, &[0; 16]).is_err());
+        assert!(SymmetricKey::new(1, MacAlgorithm::Aes128Cmac, &[0; 15]).is_err());
+        assert!(SymmetricKey::new(1, MacAlgorithm::Aes128Cmac, &[0; 32]).is_err());
+        assert!(SymmetricKey::new(1, MacAlgorithm::Md5, &[]).is_err());
+        assert!(SymmetricKey::new(1, MacAlgorithm::Sha1, &[0; 65]).is_err());
+        assert!(SymmetricKey::new(1, MacAlgorithm::Sha256, &[0; 20]).is_ok());
+    }
+
+    #[test]
+    fn legacy_digest_lengths() {
+        let data = [1u8; 48];
+
+        for (algorithm, digest_length) in [
+            (MacAlgorithm::Md5, 16),
+            (MacAlgorithm::Sha1, 20),
+            (MacAlgorithm::Sha256, 20),
+        ] {
+            assert!(algorithm.is_legacy());
+            let key = SymmetricKey::new(3, algorithm, b"secret").unwrap();
+
+            let mut message = data.to_vec();
+            key.sign(&data).serialize(&mut message).unwrap();
+            assert_eq!(message.len(), 48 + 4 + digest_length);
+            assert!(key.verify(&message));
+
+            message[0] ^= 1;
+            assert!(!key.verify(&message));
+        }
+
+        assert!(!MacAlgorithm::Aes128Cmac.is_legacy());
+    }
+
+    #[test]
+    fn legacy_digest_known_values() {
+        // The digests of "abc", split between the key and the data
+        let md5 = SymmetricKey::new(1, MacAlgorithm::Md5, b"a").unwrap();
+        assert_eq!(
+            md5.digest(b"bc"),
+            [
+                0x90, 0x01, 0x50, 0x98, 0x3c, 0xd2, 0x4f, 0xb0, 0xd6, 0x96, 0x3f, 0x7d, 0x28, 0xe1,
+                0x7f, 0x72
+            ]
+        );
+
+        let sha1 = SymmetricKey::new(1, MacAlgorithm::Sha1, b"abc").unwrap();
+        assert_eq!(
+            sha1.digest(b""),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+                0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+            ]
+        );
+
+        // SHA-256 is truncated to the first 20 bytes
+        let sha256 = SymmetricKey::new(1, MacAlgorithm::Sha256, b"ab").unwrap();
+        assert_eq!(
+            sha256.digest(b"c"),
+            [
+                0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+                0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3
+            ]
+        );
+    }
+
     #[test]
     fn rejects_too_long() {
         let mut data = Vec::with_capacity(4 + Mac::MAXIMUM_SIZE + 1);
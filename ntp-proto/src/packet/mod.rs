@@ -1,6 +1,7 @@
 use std::{borrow::Cow, io::Cursor};
 
 use rand::{Rng, thread_rng};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -15,6 +16,7 @@ use crate::{
 
 use self::{error::ParsingError, extension_fields::ExtensionFieldData, mac::Mac};
 
+mod builder;
 mod crypto;
 mod error;
 mod extension_fields;
@@ -22,14 +24,25 @@ mod mac;
 
 pub mod v5;
 
+pub use builder::NtpPacketBuilder;
+pub(crate) use crypto::EncryptWithNonce;
+#[cfg(feature = "nts-test-vectors")]
+pub(crate) use crypto::FixedNonce;
 pub use crypto::{
-    AesSivCmac256, AesSivCmac512, Cipher, CipherHolder, CipherProvider, DecryptError,
-    EncryptResult, NoCipher,
+    Aes128GcmSiv, AesSivCmac256, AesSivCmac512, Cipher, CipherHolder, CipherProvider, DecryptError,
+    EncryptResult, KeyError, NoCipher,
 };
 pub use error::PacketParsingError;
-pub use extension_fields::{ExtensionField, ExtensionHeaderVersion};
+pub use extension_fields::{
+    ExtensionField, ExtensionFieldHandler, ExtensionFieldHandlers, ExtensionFieldRegistrationError,
+    ExtensionHeaderVersion,
+};
+#[cfg(feature = "__internal-fuzz")]
+pub use extension_fields::{fuzz_encrypted_field, fuzz_extension_fields};
+pub use mac::{MacAlgorithm, SymmetricKey};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum NtpLeapIndicator {
     NoWarning,
     Leap61,
@@ -151,6 +164,8 @@ pub struct NtpHeaderV3V4 {
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct RequestIdentifier {
     expected_origin_timestamp: NtpTimestamp,
+    // Origin timestamp of a response in interleaved mode, if requested
+    interleaved_origin_timestamp: Option<NtpTimestamp>,
     uid: Option<[u8; 32]>,
 }
 
@@ -228,6 +243,7 @@ impl NtpHeaderV3V4 {
             packet,
             RequestIdentifier {
                 expected_origin_timestamp: transmit_timestamp,
+                interleaved_origin_timestamp: None,
                 uid: None,
             },
         )
@@ -256,6 +272,28 @@ impl NtpHeaderV3V4 {
         }
     }
 
+    fn broadcast<C: NtpClock>(
+        server_info: &NtpServerInfo,
+        poll_interval: PollInterval,
+        clock: &C,
+    ) -> Self {
+        let now = clock.now().expect("Failed to read time");
+        Self {
+            mode: NtpAssociationMode::Broadcast,
+            stratum: server_info.ntp_snapshot.stratum,
+            reference_id: server_info.ntp_snapshot.reference_id,
+            poll: poll_interval,
+            precision: server_info.time_snapshot.precision.log2(),
+            root_delay: server_info.time_snapshot.root_delay,
+            root_dispersion: server_info.time_snapshot.root_dispersion(now),
+            leap: server_info.time_snapshot.leap_indicator,
+            reference_timestamp: now.truncated_second_bits(7),
+            // Timestamp must be last to make it as accurate as possible.
+            transmit_timestamp: clock.now().expect("Failed to read time"),
+            ..Self::new()
+        }
+    }
+
     fn rate_limit_response(packet_from_client: Self) -> Self {
         Self {
             mode: NtpAssociationMode::Server,
@@ -296,6 +334,11 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    /// Parse a packet from `data`.
+    ///
+    /// The extension fields and MAC of the result borrow from `data`, only the
+    /// contents of the NTS encrypted field are owned as they are the output of
+    /// decryption. Use [`Self::into_owned`] to keep the packet around longer.
     #[expect(clippy::result_large_err)]
     // FIXME: Figure out a way to simplify and/or split this function.
     #[expect(clippy::too_many_lines)]
@@ -447,6 +490,12 @@ impl<'a> NtpPacket<'a> {
         Ok(buffer)
     }
 
+    /// Serialize the packet into `w`.
+    ///
+    /// When responding to a request, `desired_size` should be the size of
+    /// that request. NTPv5 responses are padded up to that size, and the
+    /// serialization of any response larger than it fails, so the server
+    /// can't be used to amplify traffic towards a spoofed address.
     pub fn serialize(
         &self,
         w: &mut Cursor<&mut [u8]>,
@@ -455,6 +504,55 @@ impl<'a> NtpPacket<'a> {
     ) -> std::io::Result<()> {
         let start = w.position();
 
+        self.serialize_header_and_extension_fields(w, cipher)?;
+
+        if let Some(ref mac) = self.mac {
+            mac.serialize(&mut *w)?;
+        }
+
+        if matches!(self.header, NtpHeader::V5(_))
+            && let Some(desired_size) = desired_size
+        {
+            let written = (w.position() - start) as usize;
+            if desired_size > written {
+                ExtensionField::Padding(desired_size - written).serialize(
+                    &mut *w,
+                    4,
+                    ExtensionHeaderVersion::V5,
+                )?;
+            }
+        }
+
+        check_response_size((w.position() - start) as usize, desired_size)
+    }
+
+    /// Serialize the packet, authenticated with a MAC computed using `key`
+    /// (RFC 8573). Any MAC already present on the packet is replaced.
+    ///
+    /// Like for [`NtpPacket::serialize`], serialization fails when the result
+    /// is larger than `desired_size`.
+    pub fn serialize_with_mac(
+        &self,
+        w: &mut Cursor<&mut [u8]>,
+        key: &SymmetricKey,
+        desired_size: Option<usize>,
+    ) -> std::io::Result<()> {
+        let start = w.position() as usize;
+
+        self.serialize_header_and_extension_fields(w, &NoCipher)?;
+
+        let end = w.position() as usize;
+        let mac = key.sign(&w.get_ref()[start..end]);
+        mac.serialize(&mut *w)?;
+
+        check_response_size(w.position() as usize - start, desired_size)
+    }
+
+    fn serialize_header_and_extension_fields(
+        &self,
+        w: &mut Cursor<&mut [u8]>,
+        cipher: &(impl CipherProvider + ?Sized),
+    ) -> std::io::Result<()> {
         match self.header {
             NtpHeader::V3(header) => header.serialize(&mut *w, 3)?,
             NtpHeader::V4(header) => header.serialize(&mut *w, 4)?,
@@ -473,23 +571,6 @@ impl<'a> NtpPacket<'a> {
             }
         }
 
-        if let Some(ref mac) = self.mac {
-            mac.serialize(&mut *w)?;
-        }
-
-        if matches!(self.header, NtpHeader::V5(_))
-            && let Some(desired_size) = desired_size
-        {
-            let written = (w.position() - start) as usize;
-            if desired_size > written {
-                ExtensionField::Padding(desired_size - written).serialize(
-                    w,
-                    4,
-                    ExtensionHeaderVersion::V5,
-                )?;
-            }
-        }
-
         Ok(())
     }
 
@@ -497,14 +578,14 @@ impl<'a> NtpPacket<'a> {
         cookie: &'a [u8],
         new_cookies: u8,
         poll_interval: PollInterval,
-    ) -> (NtpPacket<'static>, RequestIdentifier) {
+    ) -> (NtpPacket<'a>, RequestIdentifier) {
         let (header, id) = NtpHeaderV3V4::poll_message(poll_interval);
 
         let identifier: [u8; 32] = rand::thread_rng().r#gen();
 
         let mut authenticated = vec![
             ExtensionField::UniqueIdentifier(identifier.to_vec().into()),
-            ExtensionField::NtsCookie(cookie.to_vec().into()),
+            ExtensionField::NtsCookie(Cow::Borrowed(cookie)),
         ];
 
         for _ in 1..new_cookies {
@@ -534,14 +615,14 @@ impl<'a> NtpPacket<'a> {
         cookie: &'a [u8],
         new_cookies: u8,
         poll_interval: PollInterval,
-    ) -> (NtpPacket<'static>, RequestIdentifier) {
+    ) -> (NtpPacket<'a>, RequestIdentifier) {
         let (header, id) = v5::NtpHeaderV5::poll_message(poll_interval);
 
         let identifier: [u8; 32] = rand::thread_rng().r#gen();
 
         let mut authenticated = vec![
             ExtensionField::UniqueIdentifier(identifier.to_vec().into()),
-            ExtensionField::NtsCookie(cookie.to_vec().into()),
+            ExtensionField::NtsCookie(Cow::Borrowed(cookie)),
         ];
 
         for _ in 1..new_cookies {
@@ -582,6 +663,50 @@ impl<'a> NtpPacket<'a> {
         )
     }
 
+    /// Poll message asking the server to respond in interleaved mode
+    ///
+    /// `remote_receive` and `local_receive` are the receive timestamps of the
+    /// previous exchange with the server, as reported by the server and as
+    /// measured locally. A server supporting interleaved mode responds with
+    /// the transmit timestamp of its previous response, which it can measure
+    /// more accurately after the fact. Other servers respond in basic mode.
+    pub fn poll_message_interleaved(
+        poll_interval: PollInterval,
+        remote_receive: NtpTimestamp,
+        local_receive: NtpTimestamp,
+    ) -> (Self, RequestIdentifier) {
+        let (mut header, id) = NtpHeaderV3V4::poll_message(poll_interval);
+
+        header.origin_timestamp = remote_receive;
+        header.receive_timestamp = local_receive;
+
+        (
+            NtpPacket {
+                header: NtpHeader::V4(header),
+                efdata: ExtensionFieldData::default(),
+                mac: None,
+            },
+            RequestIdentifier {
+                interleaved_origin_timestamp: Some(local_receive),
+                ..id
+            },
+        )
+    }
+
+    /// Unsolicited message for broadcast or multicast clients, announcing
+    /// that the next one follows after `poll_interval`
+    pub fn broadcast<C: NtpClock>(
+        server_info: &NtpServerInfo,
+        poll_interval: PollInterval,
+        clock: &C,
+    ) -> Self {
+        NtpPacket {
+            header: NtpHeader::V4(NtpHeaderV3V4::broadcast(server_info, poll_interval, clock)),
+            efdata: ExtensionFieldData::default(),
+            mac: None,
+        }
+    }
+
     pub fn poll_message_upgrade_request(poll_interval: PollInterval) -> (Self, RequestIdentifier) {
         let (mut header, id) = NtpHeaderV3V4::poll_message(poll_interval);
 
@@ -621,7 +746,7 @@ impl<'a> NtpPacket<'a> {
     }
 
     pub fn timestamp_response<C: NtpClock>(
-        server_info: NtpServerInfo,
+        server_info: &NtpServerInfo,
         input: Self,
         recv_timestamp: NtpTimestamp,
         clock: &C,
@@ -629,7 +754,7 @@ impl<'a> NtpPacket<'a> {
         match &input.header {
             NtpHeader::V3(header) => NtpPacket {
                 header: NtpHeader::V3(NtpHeaderV3V4::timestamp_response(
-                    &server_info,
+                    server_info,
                     *header,
                     recv_timestamp,
                     clock,
@@ -639,7 +764,7 @@ impl<'a> NtpPacket<'a> {
             },
             NtpHeader::V4(header) => {
                 let mut response_header =
-                    NtpHeaderV3V4::timestamp_response(&server_info, *header, recv_timestamp, clock);
+                    NtpHeaderV3V4::timestamp_response(server_info, *header, recv_timestamp, clock);
 
                 // Respond with the upgrade timestamp (NTP5NTP5) iff the input had it and the packet
                 // had the correct draft identification
@@ -666,7 +791,7 @@ impl<'a> NtpPacket<'a> {
             }
             NtpHeader::V5(header) => NtpPacket {
                 header: NtpHeader::V5(v5::NtpHeaderV5::timestamp_response(
-                    &server_info,
+                    server_info,
                     *header,
                     recv_timestamp,
                     clock,
@@ -687,6 +812,9 @@ impl<'a> NtpPacket<'a> {
                                     req.to_response(&server_info.ntp_snapshot.bloom_filter)?;
                                 Some(ExtensionField::ReferenceIdResponse(response).into_owned())
                             }
+                            ExtensionField::MonotonicReceiveTimestamp(_) => Some(
+                                v5::monotonic_receive_timestamp(server_info, recv_timestamp).into(),
+                            ),
                             _ => None,
                         })
                         .chain(std::iter::once(ExtensionField::DraftIdentification(
@@ -704,6 +832,7 @@ impl<'a> NtpPacket<'a> {
             .untrusted
             .iter()
             .chain(self.efdata.authenticated.iter())
+            .chain(self.efdata.encrypted.iter())
             .find_map(|ef| match ef {
                 ExtensionField::DraftIdentification(id) => Some(&**id),
                 _ => None,
@@ -712,7 +841,7 @@ impl<'a> NtpPacket<'a> {
 
     #[allow(clippy::too_many_lines)]
     pub fn nts_timestamp_response<C: NtpClock>(
-        server_info: NtpServerInfo,
+        server_info: &NtpServerInfo,
         input: Self,
         recv_timestamp: NtpTimestamp,
         clock: &C,
@@ -723,7 +852,7 @@ impl<'a> NtpPacket<'a> {
             NtpHeader::V3(_) => unreachable!("NTS shouldn't work with NTPv3"),
             NtpHeader::V4(header) => NtpPacket {
                 header: NtpHeader::V4(NtpHeaderV3V4::timestamp_response(
-                    &server_info,
+                    server_info,
                     header,
                     recv_timestamp,
                     clock,
@@ -736,22 +865,14 @@ impl<'a> NtpPacket<'a> {
                         .chain(input.efdata.encrypted.iter())
                         .take(MAX_COOKIES)
                         .filter_map(|f| match f {
-                            ExtensionField::NtsCookiePlaceholder { cookie_length } => {
-                                let new_cookie = keyset.encode_cookie(cookie);
-                                if new_cookie.len() > *cookie_length as usize {
-                                    None
-                                } else {
-                                    Some(ExtensionField::NtsCookie(Cow::Owned(new_cookie)))
-                                }
-                            }
-                            ExtensionField::NtsCookie(old_cookie) => {
-                                let new_cookie = keyset.encode_cookie(cookie);
-                                if new_cookie.len() > old_cookie.len() {
-                                    None
-                                } else {
-                                    Some(ExtensionField::NtsCookie(Cow::Owned(new_cookie)))
-                                }
-                            }
+                            ExtensionField::NtsCookiePlaceholder { cookie_length } => keyset
+                                .encode_cookie_within(cookie, *cookie_length as usize)
+                                .map(Cow::Owned)
+                                .map(ExtensionField::NtsCookie),
+                            ExtensionField::NtsCookie(old_cookie) => keyset
+                                .encode_cookie_within(cookie, old_cookie.len())
+                                .map(Cow::Owned)
+                                .map(ExtensionField::NtsCookie),
                             _ => None,
                         })
                         .collect(),
@@ -768,7 +889,7 @@ impl<'a> NtpPacket<'a> {
             },
             NtpHeader::V5(header) => NtpPacket {
                 header: NtpHeader::V5(v5::NtpHeaderV5::timestamp_response(
-                    &server_info,
+                    server_info,
                     header,
                     recv_timestamp,
                     clock,
@@ -781,22 +902,14 @@ impl<'a> NtpPacket<'a> {
                         .chain(input.efdata.encrypted.iter())
                         .take(MAX_COOKIES)
                         .filter_map(|f| match f {
-                            ExtensionField::NtsCookiePlaceholder { cookie_length } => {
-                                let new_cookie = keyset.encode_cookie(cookie);
-                                if new_cookie.len() > *cookie_length as usize {
-                                    None
-                                } else {
-                                    Some(ExtensionField::NtsCookie(Cow::Owned(new_cookie)))
-                                }
-                            }
-                            ExtensionField::NtsCookie(old_cookie) => {
-                                let new_cookie = keyset.encode_cookie(cookie);
-                                if new_cookie.len() > old_cookie.len() {
-                                    None
-                                } else {
-                                    Some(ExtensionField::NtsCookie(Cow::Owned(new_cookie)))
-                                }
-                            }
+                            ExtensionField::NtsCookiePlaceholder { cookie_length } => keyset
+                                .encode_cookie_within(cookie, *cookie_length as usize)
+                                .map(Cow::Owned)
+                                .map(ExtensionField::NtsCookie),
+                            ExtensionField::NtsCookie(old_cookie) => keyset
+                                .encode_cookie_within(cookie, old_cookie.len())
+                                .map(Cow::Owned)
+                                .map(ExtensionField::NtsCookie),
                             _ => None,
                         })
                         .collect(),
@@ -811,6 +924,9 @@ impl<'a> NtpPacket<'a> {
                                     req.to_response(&server_info.ntp_snapshot.bloom_filter)?;
                                 Some(ExtensionField::ReferenceIdResponse(response).into_owned())
                             }
+                            ExtensionField::MonotonicReceiveTimestamp(_) => Some(
+                                v5::monotonic_receive_timestamp(server_info, recv_timestamp).into(),
+                            ),
                             _ => None,
                         })
                         .chain(std::iter::once(ExtensionField::DraftIdentification(
@@ -1124,7 +1240,7 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
-    fn kiss_code(&self) -> ReferenceId {
+    pub(crate) fn kiss_code(&self) -> ReferenceId {
         match self.header {
             NtpHeader::V3(header) | NtpHeader::V4(header) => header.reference_id,
             // Kiss code in ntpv5 is the first four bytes of the server cookie
@@ -1207,6 +1323,7 @@ impl<'a> NtpPacket<'a> {
         match self.header {
             NtpHeader::V3(header) | NtpHeader::V4(header) => {
                 header.origin_timestamp == identifier.expected_origin_timestamp
+                    || Some(header.origin_timestamp) == identifier.interleaved_origin_timestamp
             }
             NtpHeader::V5(header) => {
                 header.client_cookie
@@ -1215,6 +1332,24 @@ impl<'a> NtpPacket<'a> {
         }
     }
 
+    /// Whether this is a response in interleaved mode to the request with the
+    /// given identifier. The transmit timestamp of such a response belongs to
+    /// the previous response of the server.
+    pub fn is_interleaved_response(&self, identifier: RequestIdentifier) -> bool {
+        match self.header {
+            NtpHeader::V4(header) => {
+                Some(header.origin_timestamp) == identifier.interleaved_origin_timestamp
+                    && header.origin_timestamp != identifier.expected_origin_timestamp
+            }
+            NtpHeader::V3(_) | NtpHeader::V5(_) => false,
+        }
+    }
+
+    /// Key id of the MAC on the packet, if there is one
+    pub fn mac_key_id(&self) -> Option<u32> {
+        self.mac.as_ref().map(Mac::keyid)
+    }
+
     pub fn untrusted_extension_fields(&self) -> impl Iterator<Item = &ExtensionField<'_>> {
         self.efdata.untrusted.iter()
     }
@@ -1232,6 +1367,17 @@ impl<'a> NtpPacket<'a> {
     }
 }
 
+// Responses must never be larger than the request they answer, so the server
+// can't be used to amplify traffic towards a spoofed address.
+fn check_response_size(written: usize, desired_size: Option<usize>) -> std::io::Result<()> {
+    match desired_size {
+        Some(desired_size) if written > desired_size => Err(std::io::Error::other(format!(
+            "response of {written} bytes is larger than the request of {desired_size} bytes"
+        ))),
+        _ => Ok(()),
+    }
+}
+
 // Returns whether all uid extension fields found match the given uid, or
 // None if there were none.
 fn check_uid_extensionfield<'a, I: IntoIterator<Item = &'a ExtensionField<'a>>>(
@@ -1250,6 +1396,66 @@ fn check_uid_extensionfield<'a, I: IntoIterator<Item = &'a ExtensionField<'a>>>(
     if found_uid { Some(true) } else { None }
 }
 
+#[cfg(feature = "nts-test-vectors")]
+impl<'a> NtpPacket<'a> {
+    /// An NTPv4 NTS request like [`Self::nts_poll_message`], but with the
+    /// unique identifier and transmit timestamp given instead of random
+    pub(crate) fn nts_test_vector_request(
+        cookie: &'a [u8],
+        new_cookies: u8,
+        unique_id: [u8; 32],
+        transmit_timestamp: NtpTimestamp,
+    ) -> Self {
+        let (mut packet, _) = Self::nts_poll_message(cookie, new_cookies, PollInterval::default());
+        if let NtpHeader::V4(header) = &mut packet.header {
+            header.transmit_timestamp = transmit_timestamp;
+        }
+        packet.efdata.authenticated[0] =
+            ExtensionField::UniqueIdentifier(unique_id.to_vec().into());
+        packet
+    }
+
+    /// A stratum 1 response to an NTPv4 NTS request, with the given
+    /// timestamps and cookies. Header fields that depend on the state of
+    /// the server are left zero. Returns `None` for other NTP versions.
+    pub(crate) fn nts_test_vector_response(
+        request: Self,
+        receive_timestamp: NtpTimestamp,
+        transmit_timestamp: NtpTimestamp,
+        cookies: Vec<Vec<u8>>,
+    ) -> Option<Self> {
+        let NtpHeader::V4(header) = request.header else {
+            return None;
+        };
+
+        Some(NtpPacket {
+            header: NtpHeader::V4(NtpHeaderV3V4 {
+                mode: NtpAssociationMode::Server,
+                stratum: 1,
+                poll: header.poll,
+                origin_timestamp: header.transmit_timestamp,
+                receive_timestamp,
+                transmit_timestamp,
+                ..NtpHeaderV3V4::new()
+            }),
+            efdata: ExtensionFieldData {
+                authenticated: request
+                    .efdata
+                    .authenticated
+                    .into_iter()
+                    .filter(|ef| matches!(ef, ExtensionField::UniqueIdentifier(_)))
+                    .collect(),
+                encrypted: cookies
+                    .into_iter()
+                    .map(|cookie| ExtensionField::NtsCookie(Cow::Owned(cookie)))
+                    .collect(),
+                untrusted: vec![],
+            },
+            mac: None,
+        })
+    }
+}
+
 #[cfg(any(test, feature = "__internal-fuzz", feature = "__internal-test"))]
 impl NtpPacket<'_> {
     pub fn test() -> Self {
@@ -1574,6 +1780,58 @@ mod tests {
         assert_eq!(packet1, packet2);
     }
 
+    #[test]
+    fn test_nts_roundtrip_v5() {
+        let cookie = [0; 16];
+        let (mut packet1, _) =
+            NtpPacket::nts_poll_message_v5(&cookie, 2, PollIntervalLimits::default().min);
+        // Requests don't claim to be synchronized, which NTPv5 reads back as
+        // an unsynchronized leap indicator
+        packet1.set_leap(NtpLeapIndicator::Unsynchronized);
+        let cipher = AesSivCmac512::new(std::array::from_fn::<_, 64, _>(|i| i as u8).into());
+
+        let mut buffer = [0u8; 2048];
+        let mut cursor = Cursor::new(buffer.as_mut());
+        packet1.serialize(&mut cursor, &cipher, None).unwrap();
+        let data = &cursor.get_ref()[..cursor.position() as usize];
+        let (packet2, _) = NtpPacket::deserialize(data, &cipher).unwrap();
+        assert_eq!(packet1, packet2);
+        assert_eq!(packet2.version(), NtpVersion::V5);
+
+        // Tampering with the header must be detected
+        let mut tampered = data.to_vec();
+        tampered[3] ^= 1;
+        assert!(matches!(
+            NtpPacket::deserialize(&tampered, &cipher),
+            Err(ParsingError::DecryptError(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_borrows_extension_fields() {
+        let cookie = [1; 16];
+        let (packet, _) =
+            NtpPacket::nts_poll_message(&cookie, 2, PollIntervalLimits::default().min);
+        let cipher = AesSivCmac512::new(std::array::from_fn::<_, 64, _>(|i| i as u8).into());
+
+        let mut buffer = [0u8; 2048];
+        let mut cursor = Cursor::new(buffer.as_mut());
+        packet.serialize(&mut cursor, &cipher, None).unwrap();
+        let data = &cursor.get_ref()[..cursor.position() as usize];
+        let (packet, _) = NtpPacket::deserialize(data, &cipher).unwrap();
+
+        let in_buffer = |field: &[u8]| data.as_ptr_range().contains(&field.as_ptr());
+        let mut fields = packet.authenticated_extension_fields();
+        assert!(matches!(
+            fields.next(),
+            Some(ExtensionField::UniqueIdentifier(Cow::Borrowed(uid))) if in_buffer(uid)
+        ));
+        assert!(matches!(
+            fields.next(),
+            Some(ExtensionField::NtsCookie(Cow::Borrowed(cookie))) if in_buffer(cookie)
+        ));
+    }
+
     #[test]
     fn test_nts_captured_server() {
         let packet = b"\x24\x01\x04\xe8\x00\x00\x00\x00\x00\x00\x00\x60\x54\x4d\x4e\x4c\xe8\x49\x48\x92\xf9\x29\x57\x9e\x62\x87\xdb\x47\x3f\xf7\x5f\x58\xe8\x49\x48\xb2\xb6\x40\xd7\x01\xe8\x49\x48\xb2\xb6\x44\xbf\xf8\x01\x04\x00\x24\xe4\x83\x3a\x8d\x60\x0e\x13\x42\x43\x5c\xb2\x9d\xe5\x50\xac\xc0\xf8\xd8\xfa\x16\xe5\xc5\x37\x0a\x62\x0b\x15\x5f\x58\x6a\xda\xd6\x04\x04\x00\xd4\x00\x10\x00\xbc\x6a\x1d\xe3\xc2\x6e\x13\xeb\x10\xc7\x39\xd7\x0b\x84\x1f\xad\x1b\x86\xe2\x30\xc6\x3e\x9e\xa5\xf7\x1b\x62\xa8\xa7\x98\x81\xce\x7c\x6b\x17\xcb\x31\x32\x49\x0f\xde\xcf\x21\x10\x56\x4e\x36\x88\x92\xdd\xee\xf1\xf4\x23\xf6\x55\x53\x41\xc2\xc9\x17\x61\x20\xa5\x18\xdc\x1a\x7e\xdc\x5e\xe3\xc8\x3b\x05\x08\x7b\x73\x03\xf7\xab\x86\xd5\x2c\xc7\x49\x0c\xe8\x29\x39\x72\x23\xdc\xef\x2d\x94\xfa\xf8\xd7\x1d\x12\x80\xda\x03\x2d\xd7\x04\x69\xe9\xac\x5f\x82\xef\x57\x81\xd2\x07\xfb\xac\xb4\xa8\xb6\x31\x91\x14\xd5\xf5\x6f\xb2\x2a\x0c\xb6\xd7\xdc\xf7\x7d\xf0\x21\x46\xf6\x7e\x46\x01\xb5\x3b\x21\x7c\xa8\xac\x1a\x4d\x97\xd5\x9b\xce\xeb\x98\x33\x99\x7f\x10\x0e\xd4\x69\x85\x8b\xcd\x73\x52\x01\xad\xec\x38\xcf\x8c\xb2\xc6\xd0\x54\x1a\x97\x67\xdd\xb3\xea\x09\x1d\x63\xd9\x8d\x03\xdd\x6e\x48\x15\x3d\xc9\xb6\x1f\xe5\xd9\x1d\x74\xae\x35\x48";
@@ -1682,7 +1940,7 @@ mod tests {
         let (packet, id) =
             NtpPacket::nts_poll_message(&cookie, 0, PollIntervalLimits::default().min);
         let mut response = NtpPacket::timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1732,12 +1990,63 @@ mod tests {
         assert!(!response.valid_server_response(id, true));
     }
 
+    #[test]
+    fn test_interleaved_response_validation() {
+        let remote_receive = NtpTimestamp::from_fixed_int(100);
+        let local_receive = NtpTimestamp::from_fixed_int(200);
+        let (packet, id) = NtpPacket::poll_message_interleaved(
+            PollIntervalLimits::default().min,
+            remote_receive,
+            local_receive,
+        );
+
+        let NtpHeader::V4(header) = packet.header else {
+            panic!("wrong version");
+        };
+        assert_eq!(header.origin_timestamp, remote_receive);
+        assert_eq!(header.receive_timestamp, local_receive);
+
+        // Servers without interleaved mode respond in basic mode
+        let mut response = NtpPacket::timestamp_response(
+            &NtpServerInfo::default(),
+            packet,
+            NtpTimestamp::from_fixed_int(300),
+            &TestClock {
+                now: NtpTimestamp::from_fixed_int(400),
+            },
+        );
+        assert!(response.valid_server_response(id, false));
+        assert!(!response.is_interleaved_response(id));
+
+        response.set_origin_timestamp(local_receive);
+        assert!(response.valid_server_response(id, false));
+        assert!(response.is_interleaved_response(id));
+
+        response.set_origin_timestamp(remote_receive);
+        assert!(!response.valid_server_response(id, false));
+        assert!(!response.is_interleaved_response(id));
+
+        // Basic requests never get interleaved responses
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let mut response = NtpPacket::timestamp_response(
+            &NtpServerInfo::default(),
+            packet,
+            NtpTimestamp::from_fixed_int(300),
+            &TestClock {
+                now: NtpTimestamp::from_fixed_int(400),
+            },
+        );
+        assert!(!response.is_interleaved_response(id));
+        response.set_origin_timestamp(local_receive);
+        assert!(!response.valid_server_response(id, false));
+    }
+
     #[test]
     fn v5_upgrade_packet() {
         let (packet, _) = NtpPacket::poll_message_upgrade_request(PollInterval::default());
 
         let response = NtpPacket::timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1780,7 +2089,7 @@ mod tests {
             })
             .unwrap();
         let response = NtpPacket::timestamp_response(
-            NtpServerInfo {
+            &NtpServerInfo {
                 time_snapshot: TimeSnapshot {
                     leap_indicator: NtpLeapIndicator::Leap59,
                     ..Default::default()
@@ -1835,7 +2144,7 @@ mod tests {
             })
             .unwrap();
         let response = NtpPacket::timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1879,7 +2188,7 @@ mod tests {
             })
             .unwrap();
         let response = NtpPacket::nts_timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1917,7 +2226,7 @@ mod tests {
             &mut packet.efdata.untrusted,
         );
         let response = NtpPacket::nts_timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1963,7 +2272,7 @@ mod tests {
         let (packet, _) =
             NtpPacket::nts_poll_message(&cookie, 1, PollIntervalLimits::default().min);
         let response = NtpPacket::nts_timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1977,7 +2286,7 @@ mod tests {
         let (packet, _) =
             NtpPacket::nts_poll_message(&cookie, 2, PollIntervalLimits::default().min);
         let response = NtpPacket::nts_timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -1991,7 +2300,7 @@ mod tests {
         let (packet, _) =
             NtpPacket::nts_poll_message(&cookie, 3, PollIntervalLimits::default().min);
         let response = NtpPacket::nts_timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -2005,7 +2314,7 @@ mod tests {
         let (packet, _) =
             NtpPacket::nts_poll_message(&cookie, 4, PollIntervalLimits::default().min);
         let response = NtpPacket::nts_timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             packet,
             NtpTimestamp::from_fixed_int(0),
             &TestClock {
@@ -2393,13 +2702,88 @@ mod tests {
         for i in 10..40 {
             let packet = NtpPacket::poll_message_v5(PollInterval::default()).0;
 
-            let data = packet
-                .serialize_without_encryption_vec(Some(4 * i))
-                .unwrap();
+            let result = packet.serialize_without_encryption_vec(Some(4 * i));
 
-            assert_eq!(data.len(), 76.max(i * 4));
+            // Never larger than the desired size
+            if i * 4 < 76 {
+                assert!(result.is_err());
+                continue;
+            }
+
+            let data = result.unwrap();
+            assert_eq!(data.len(), i * 4);
 
             assert!(NtpPacket::deserialize(&data, &NoCipher).is_ok());
         }
     }
+
+    #[test]
+    fn response_not_larger_than_request() {
+        for version in [NtpVersion::V4, NtpVersion::V5] {
+            let packet = match version {
+                NtpVersion::V5 => NtpPacket::poll_message_v5(PollInterval::default()).0,
+                _ => NtpPacket::poll_message(PollInterval::default()).0,
+            };
+            let size = packet.serialize_without_encryption_vec(None).unwrap().len();
+
+            assert_eq!(
+                packet
+                    .serialize_without_encryption_vec(Some(size))
+                    .unwrap()
+                    .len(),
+                size
+            );
+            assert!(
+                packet
+                    .serialize_without_encryption_vec(Some(size - 4))
+                    .is_err()
+            );
+        }
+
+        // Also when authenticated with a MAC
+        let packet = NtpPacket::poll_message(PollInterval::default()).0;
+        let key = SymmetricKey::new(1, MacAlgorithm::Aes128Cmac, &[1; 16]).unwrap();
+        let mut buffer = [0; 1024];
+        let mut cursor = Cursor::new(buffer.as_mut_slice());
+        packet.serialize_with_mac(&mut cursor, &key, None).unwrap();
+        let size = cursor.position() as usize;
+
+        let mut cursor = Cursor::new(buffer.as_mut_slice());
+        assert!(
+            packet
+                .serialize_with_mac(&mut cursor, &key, Some(size))
+                .is_ok()
+        );
+        let mut cursor = Cursor::new(buffer.as_mut_slice());
+        assert!(
+            packet
+                .serialize_with_mac(&mut cursor, &key, Some(size - 4))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn extension_field_minimum_size_v4() {
+        let mut packet = NtpPacket::poll_message(PollInterval::default()).0;
+        packet.efdata.untrusted = vec![
+            ExtensionField::Unknown {
+                type_id: 0x7777,
+                data: Cow::Borrowed(&[1; 2]),
+            },
+            ExtensionField::Unknown {
+                type_id: 0x7778,
+                data: Cow::Borrowed(&[2; 2]),
+            },
+        ];
+
+        // RFC 7822: fields are at least 16 bytes, the last one at least 28
+        // bytes as there is no MAC
+        let data = packet.serialize_without_encryption_vec(None).unwrap();
+        assert_eq!(data.len(), 48 + 16 + 28);
+        assert_eq!(&data[48..52], &[0x77, 0x77, 0, 16]);
+        assert_eq!(&data[64..68], &[0x77, 0x78, 0, 28]);
+
+        let (parsed, _) = NtpPacket::deserialize(&data, &NoCipher).unwrap();
+        assert_eq!(parsed.efdata.untrusted.len(), 2);
+    }
 }
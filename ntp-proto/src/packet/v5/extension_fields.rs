@@ -1,3 +1,4 @@
+use crate::NtpTimestamp;
 use crate::io::NonBlockingWrite;
 use crate::packet::ExtensionField;
 use crate::packet::error::ParsingError;
@@ -151,10 +152,80 @@ impl<'a> From<ReferenceIdResponse<'a>> for ExtensionField<'a> {
     }
 }
 
+/// Receive timestamp of the server on a timescale that is not affected by
+/// steps of its clock.
+///
+/// Clients include the field with arbitrary contents in their request, the
+/// server fills it in. As long as the epoch stays the same, the difference
+/// between two monotonic receive timestamps matches the difference between
+/// the regular receive timestamps, unless the server stepped its clock in
+/// between. A new epoch means the timescale was restarted, for example
+/// because the server restarted, so timestamps can't be compared.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct MonotonicReceiveTimestamp {
+    pub epoch: u32,
+    pub timestamp: NtpTimestamp,
+}
+
+impl MonotonicReceiveTimestamp {
+    const BODY_LENGTH: u16 = 12;
+
+    pub fn serialize(&self, mut writer: impl NonBlockingWrite) -> std::io::Result<()> {
+        writer.write_all(
+            &ExtensionFieldTypeId::MonotonicReceiveTimestamp
+                .to_type_id()
+                .to_be_bytes(),
+        )?;
+        writer.write_all(&(Self::BODY_LENGTH + 4).to_be_bytes())?;
+        writer.write_all(&self.epoch.to_be_bytes())?;
+        writer.write_all(&self.timestamp.to_bits())
+    }
+
+    pub fn decode(msg: &[u8]) -> Result<Self, ParsingError<Infallible>> {
+        if msg.len() < Self::BODY_LENGTH as usize {
+            return Err(ParsingError::IncorrectLength);
+        }
+
+        Ok(Self {
+            epoch: u32::from_be_bytes(msg[0..4].try_into().unwrap()),
+            timestamp: NtpTimestamp::from_bits(msg[4..12].try_into().unwrap()),
+        })
+    }
+}
+
+impl From<MonotonicReceiveTimestamp> for ExtensionField<'static> {
+    fn from(value: MonotonicReceiveTimestamp) -> Self {
+        Self::MonotonicReceiveTimestamp(value)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_monotonic_receive_timestamp() {
+        let field = MonotonicReceiveTimestamp {
+            epoch: 0x0102_0304,
+            timestamp: NtpTimestamp::from_fixed_int(0x1112_1314_1516_1718),
+        };
+
+        let mut out = vec![];
+        field.serialize(&mut out).unwrap();
+        assert_eq!(
+            out,
+            [
+                0xF5, 0x08, 0, 16, 1, 2, 3, 4, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18
+            ]
+        );
+
+        assert_eq!(MonotonicReceiveTimestamp::decode(&out[4..]).unwrap(), field);
+        assert!(matches!(
+            MonotonicReceiveTimestamp::decode(&out[4..12]),
+            Err(ParsingError::IncorrectLength)
+        ));
+    }
+
     #[test]
     fn test_reference_id_request_too_short() {
         assert!(matches!(
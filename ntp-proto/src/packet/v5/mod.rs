@@ -17,6 +17,19 @@ use super::RequestIdentifier;
 pub(crate) const DRAFT_VERSION: &str = "draft-ietf-ntp-ntpv5-08";
 pub(crate) const UPGRADE_TIMESTAMP: NtpTimestamp = NtpTimestamp::from_bits(*b"NTP5DRFT");
 
+/// Our receive timestamp on the monotonic timescale, for responding to a
+/// [`MonotonicReceiveTimestamp`](extension_fields::MonotonicReceiveTimestamp)
+/// request
+pub(crate) fn monotonic_receive_timestamp(
+    server_info: &NtpServerInfo,
+    recv_timestamp: NtpTimestamp,
+) -> extension_fields::MonotonicReceiveTimestamp {
+    extension_fields::MonotonicReceiveTimestamp {
+        epoch: server_info.time_snapshot.monotonic_epoch,
+        timestamp: recv_timestamp - server_info.time_snapshot.monotonic_offset,
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum NtpMode {
@@ -326,12 +339,30 @@ impl NtpHeaderV5 {
             packet,
             RequestIdentifier {
                 expected_origin_timestamp: client_cookie.into_ntp_timestamp(),
+                interleaved_origin_timestamp: None,
                 uid: None,
             },
         )
     }
 }
 
+/// A header that parses should serialize to the same bytes after a second
+/// round through the parser
+#[cfg(feature = "__internal-fuzz")]
+pub fn fuzz_v5_header(data: &[u8]) {
+    let Ok((header, _)) = NtpHeaderV5::deserialize(data) else {
+        return;
+    };
+
+    let mut first = [0u8; NtpHeaderV5::WIRE_LENGTH];
+    header.serialize(first.as_mut_slice()).unwrap();
+    let (reparsed, _) = NtpHeaderV5::deserialize(&first).unwrap();
+
+    let mut second = [0u8; NtpHeaderV5::WIRE_LENGTH];
+    reparsed.serialize(second.as_mut_slice()).unwrap();
+    assert_eq!(first, second);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -156,6 +156,10 @@ pub struct RemoteBloomFilter {
     last_requested: Option<(u16, NtpClientCookie)>,
     next_to_request: u16,
     is_filled: bool,
+    /// Bit mask of the chunks received so far
+    received: u128,
+    /// Offsets of chunks to request before continuing in order
+    priority: Vec<u16>,
 }
 
 impl RemoteBloomFilter {
@@ -184,16 +188,46 @@ impl RemoteBloomFilter {
             last_requested: None,
             next_to_request: 0,
             is_filled: false,
+            received: 0,
+            priority: Vec::new(),
         })
     }
 
+    /// Request the chunks that contain the bits of `id` first, so a loop
+    /// through the remote can be detected before the whole filter is in.
+    pub fn prioritize(&mut self, id: &ServerId) {
+        let mut priority: Vec<u16> =
+            id.0.iter()
+                .map(|idx| {
+                    let (byte, _) = idx.byte_and_mask();
+                    byte as u16 / self.chunk_size * self.chunk_size
+                })
+                .filter(|&offset| !self.is_received(offset))
+                .collect();
+        // The indices of a server id are sorted, so duplicates are adjacent
+        priority.dedup();
+        self.priority = priority;
+    }
+
     /// Returns the fully fetched filter or None if not all chunks were received yet
+    #[cfg(test)]
     pub fn full_filter(&self) -> Option<&BloomFilter> {
         self.is_filled.then_some(&self.filter)
     }
 
-    pub const fn next_request(&mut self, cookie: NtpClientCookie) -> ReferenceIdRequest {
-        let offset = self.next_to_request;
+    /// Returns the filter as far as it is known, or None if no chunks were
+    /// received yet. Bits in chunks that were not received yet are unset, so
+    /// only a positive result of [`BloomFilter::contains_id`] is conclusive.
+    pub fn known_filter(&self) -> Option<&BloomFilter> {
+        (self.received != 0).then_some(&self.filter)
+    }
+
+    pub fn next_request(&mut self, cookie: NtpClientCookie) -> ReferenceIdRequest {
+        let offset = self
+            .priority
+            .first()
+            .copied()
+            .unwrap_or(self.next_to_request);
         self.last_requested = Some((offset, cookie));
 
         ReferenceIdRequest::new(self.chunk_size, offset)
@@ -219,18 +253,40 @@ impl RemoteBloomFilter {
 
         self.filter.0[(offset as usize)..][..(self.chunk_size as usize)]
             .copy_from_slice(response.bytes());
-        self.advance_next_to_request();
+        self.received |= 1u128 << (offset / self.chunk_size);
+        self.is_filled = self.received == self.all_chunks();
+        if self.priority.first() == Some(&offset) {
+            self.priority.remove(0);
+            if !self.is_filled && self.is_received(self.next_to_request) {
+                self.advance_next_to_request();
+            }
+        } else {
+            self.advance_next_to_request();
+        }
         self.last_requested = None;
 
         Ok(())
     }
 
+    const fn all_chunks(&self) -> u128 {
+        let chunks = BloomFilter::BYTES as u32 / self.chunk_size as u32;
+        u128::MAX >> (u128::BITS - chunks)
+    }
+
+    const fn is_received(&self, offset: u16) -> bool {
+        self.received & (1u128 << (offset / self.chunk_size)) != 0
+    }
+
     const fn advance_next_to_request(&mut self) {
-        self.next_to_request = (self.next_to_request + self.chunk_size) % BloomFilter::BYTES as u16;
+        // Until the filter is complete, skip chunks that were already
+        // received out of order
+        loop {
+            self.next_to_request =
+                (self.next_to_request + self.chunk_size) % BloomFilter::BYTES as u16;
 
-        if self.next_to_request == 0 {
-            // We made the round at least once... so we must be fully filled
-            self.is_filled = true;
+            if self.is_filled || !self.is_received(self.next_to_request) {
+                break;
+            }
         }
     }
 }
@@ -242,6 +298,7 @@ impl Debug for RemoteBloomFilter {
             .field("last_requested", &self.last_requested)
             .field("next_to_request", &self.next_to_request)
             .field("is_filled", &self.is_filled)
+            .field("priority", &self.priority)
             .finish()
     }
 }
@@ -397,4 +454,46 @@ mod tests {
             assert_eq!(&target_filter, result_filter);
         }
     }
+
+    #[test]
+    fn prioritized_chunks_detect_loops_early() {
+        let own_id = ServerId::default();
+        let mut target_filter = BloomFilter::new();
+        target_filter.add_id(&own_id);
+        target_filter.add_id(&ServerId::default());
+
+        let chunk_size = 16;
+        let mut bf = RemoteBloomFilter::new(chunk_size).unwrap();
+        assert!(bf.known_filter().is_none());
+        bf.prioritize(&own_id);
+        let prioritized = bf.priority.clone();
+        assert!(!prioritized.is_empty());
+
+        for offset in &prioritized {
+            let cookie = NtpClientCookie::new_random();
+            let request = bf.next_request(cookie);
+            assert_eq!(request.offset(), *offset);
+            let response = request.to_response(&target_filter).unwrap();
+            bf.handle_response(cookie, &response).unwrap();
+        }
+
+        // All bits of our own id are known, the rest of the filter is not
+        assert!(bf.priority.is_empty());
+        assert!(bf.full_filter().is_none());
+        assert!(bf.known_filter().unwrap().contains_id(&own_id));
+
+        // The remaining chunks are fetched in order, skipping those we have
+        let mut requests = prioritized.len();
+        while bf.full_filter().is_none() {
+            let cookie = NtpClientCookie::new_random();
+            let request = bf.next_request(cookie);
+            assert!(!bf.is_received(request.offset()));
+            let response = request.to_response(&target_filter).unwrap();
+            bf.handle_response(cookie, &response).unwrap();
+            requests += 1;
+        }
+
+        assert_eq!(requests, 512 / chunk_size as usize);
+        assert_eq!(bf.full_filter(), Some(&target_filter));
+    }
 }
@@ -0,0 +1,684 @@
+//! A client for the Roughtime protocol (draft-ietf-ntp-roughtime).
+//!
+//! Roughtime servers sign every answer with a key that is certified by a
+//! long-term public key, so a response proves that the server believed the
+//! time to be within a radius of its midpoint when it answered our request.
+//! The time is only accurate to a few seconds, but unlike NTS it does not
+//! need a roughly correct clock to validate certificates, which makes it
+//! useful for bounding the system clock before other sources converge.
+//!
+//! Like [`sntp`](crate::sntp), this module does no IO itself: send the bytes
+//! of a [`Request`] to the server and pass its answer to [`query`] together
+//! with the public key of the server.
+
+use rustls23::pki_types::alg_id;
+
+use crate::tls_utils;
+
+pub use crate::time_types::{NtpDuration, NtpTimestamp};
+
+const MAGIC: &[u8; 8] = b"ROUGHTIM";
+const FRAME_HEADER_SIZE: usize = 12;
+
+// Requests are padded to this size, to prevent amplification attacks
+const REQUEST_SIZE: usize = 1024;
+const VERSION: u32 = 0x8000_000c;
+
+const NONCE_SIZE: usize = 32;
+const HASH_SIZE: usize = 32;
+
+/// Size of an Ed25519 public key
+pub const PUBLIC_KEY_SIZE: usize = 32;
+
+const DELEGATION_CONTEXT: &[u8] = b"RoughTime v1 delegation signature\0";
+const RESPONSE_CONTEXT: &[u8] = b"RoughTime v1 response signature\0";
+
+// Seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Tag(u32);
+
+impl Tag {
+    const SIG: Tag = Tag::new(b"SIG\0");
+    const VER: Tag = Tag::new(b"VER\0");
+    const NONC: Tag = Tag::new(b"NONC");
+    const TYPE: Tag = Tag::new(b"TYPE");
+    const PATH: Tag = Tag::new(b"PATH");
+    const SREP: Tag = Tag::new(b"SREP");
+    const CERT: Tag = Tag::new(b"CERT");
+    const INDX: Tag = Tag::new(b"INDX");
+    const ROOT: Tag = Tag::new(b"ROOT");
+    const MIDP: Tag = Tag::new(b"MIDP");
+    const RADI: Tag = Tag::new(b"RADI");
+    const DELE: Tag = Tag::new(b"DELE");
+    const PUBK: Tag = Tag::new(b"PUBK");
+    const MINT: Tag = Tag::new(b"MINT");
+    const MAXT: Tag = Tag::new(b"MAXT");
+    const ZZZZ: Tag = Tag::new(b"ZZZZ");
+
+    // Tags are compared as little endian integers
+    const fn new(name: &[u8; 4]) -> Tag {
+        Tag(u32::from_le_bytes(*name))
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, QueryError> {
+    data.get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or(QueryError::InvalidPacket)
+}
+
+/// A message is a map from tags to values, with the tags in increasing order
+#[derive(Debug)]
+struct Message<'a> {
+    fields: Vec<(Tag, &'a [u8])>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, QueryError> {
+        let count = read_u32(data, 0)? as usize;
+        if count == 0 {
+            return Ok(Message { fields: vec![] });
+        }
+
+        // The count is followed by count - 1 offsets and count tags
+        let header_size = count.checked_mul(8).ok_or(QueryError::InvalidPacket)?;
+        let values = data.get(header_size..).ok_or(QueryError::InvalidPacket)?;
+
+        let mut fields: Vec<(Tag, &[u8])> = Vec::with_capacity(count);
+        let mut start = 0;
+        for i in 0..count {
+            let tag = Tag(read_u32(data, 4 * count + 4 * i)?);
+            let end = if i + 1 < count {
+                read_u32(data, 4 + 4 * i)? as usize
+            } else {
+                values.len()
+            };
+
+            if end < start || end > values.len() || !end.is_multiple_of(4) {
+                return Err(QueryError::InvalidPacket);
+            }
+            if fields.last().is_some_and(|(previous, _)| *previous >= tag) {
+                return Err(QueryError::InvalidPacket);
+            }
+
+            fields.push((tag, &values[start..end]));
+            start = end;
+        }
+
+        Ok(Message { fields })
+    }
+
+    fn get(&self, tag: Tag) -> Option<&'a [u8]> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| *value)
+    }
+
+    fn require(&self, tag: Tag) -> Result<&'a [u8], QueryError> {
+        self.get(tag).ok_or(QueryError::InvalidPacket)
+    }
+
+    fn require_u32(&self, tag: Tag) -> Result<u32, QueryError> {
+        let value = self.require(tag)?;
+        value
+            .try_into()
+            .map(u32::from_le_bytes)
+            .map_err(|_| QueryError::InvalidPacket)
+    }
+
+    fn require_u64(&self, tag: Tag) -> Result<u64, QueryError> {
+        let value = self.require(tag)?;
+        value
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| QueryError::InvalidPacket)
+    }
+
+    fn require_message(&self, tag: Tag) -> Result<Message<'a>, QueryError> {
+        Message::parse(self.require(tag)?)
+    }
+}
+
+fn encode_message(fields: &[(Tag, &[u8])]) -> Vec<u8> {
+    let mut fields = fields.to_vec();
+    fields.sort_by_key(|(tag, _)| *tag);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    let mut offset = 0;
+    for (_, value) in &fields[..fields.len().saturating_sub(1)] {
+        offset += value.len();
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+    }
+    for (tag, _) in &fields {
+        data.extend_from_slice(&tag.0.to_le_bytes());
+    }
+    for (_, value) in &fields {
+        debug_assert!(value.len().is_multiple_of(4));
+        data.extend_from_slice(value);
+    }
+    data
+}
+
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(FRAME_HEADER_SIZE + message.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+fn unframe(packet: &[u8]) -> Result<&[u8], QueryError> {
+    let (header, message) = packet
+        .split_at_checked(FRAME_HEADER_SIZE)
+        .ok_or(QueryError::InvalidPacket)?;
+    if header[..MAGIC.len()] != MAGIC[..]
+        || read_u32(header, MAGIC.len())? as usize != message.len()
+    {
+        return Err(QueryError::InvalidPacket);
+    }
+    Ok(message)
+}
+
+#[cfg(feature = "rustcrypto")]
+fn tree_hash(parts: &[&[u8]]) -> [u8; HASH_SIZE] {
+    use sha2::{Digest, Sha512};
+
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut hash = [0; HASH_SIZE];
+    hash.copy_from_slice(&hasher.finalize()[..HASH_SIZE]);
+    hash
+}
+
+#[cfg(all(feature = "openssl", not(feature = "rustcrypto")))]
+fn tree_hash(parts: &[&[u8]]) -> [u8; HASH_SIZE] {
+    use openssl::hash::{Hasher, MessageDigest};
+
+    let mut hasher = Hasher::new(MessageDigest::sha512()).expect("OpenSSL could not create hasher");
+    for part in parts {
+        hasher
+            .update(part)
+            .expect("OpenSSL could not compute digest");
+    }
+    let digest = hasher.finish().expect("OpenSSL could not compute digest");
+    let mut hash = [0; HASH_SIZE];
+    hash.copy_from_slice(&digest[..HASH_SIZE]);
+    hash
+}
+
+/// Compute the root of the Merkle tree of requests the server answered
+/// together, from the path from our request to the root.
+fn merkle_root(request: &[u8], path: &[u8], mut index: u32) -> Result<[u8; HASH_SIZE], QueryError> {
+    if !path.len().is_multiple_of(HASH_SIZE) {
+        return Err(QueryError::InvalidPacket);
+    }
+
+    let mut hash = tree_hash(&[&[0], request]);
+    for node in path.chunks_exact(HASH_SIZE) {
+        hash = if index & 1 == 0 {
+            tree_hash(&[&[1], &hash, node])
+        } else {
+            tree_hash(&[&[1], node, &hash])
+        };
+        index >>= 1;
+    }
+
+    // Bits left in the index mean that the path does not lead to our request
+    if index != 0 {
+        return Err(QueryError::UnexpectedResponse);
+    }
+
+    Ok(hash)
+}
+
+fn verify_signature(
+    public_key: &[u8],
+    context: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<(), QueryError> {
+    let builder = tls_utils::client_config_builder();
+    let algorithm = builder
+        .crypto_provider()
+        .signature_verification_algorithms
+        .all
+        .iter()
+        .find(|algorithm| {
+            algorithm.public_key_alg_id() == alg_id::ED25519
+                && algorithm.signature_alg_id() == alg_id::ED25519
+        })
+        .ok_or(QueryError::InvalidSignature)?;
+
+    algorithm
+        .verify_signature(public_key, &[context, data].concat(), signature)
+        .map_err(|_| QueryError::InvalidSignature)
+}
+
+/// A request to send to a Roughtime server
+#[derive(Debug, Clone)]
+pub struct Request {
+    nonce: [u8; NONCE_SIZE],
+    bytes: Vec<u8>,
+}
+
+impl Request {
+    /// The bytes to send to the server
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Create a new request with a random nonce. A request should not be sent
+/// more than once.
+pub fn request() -> Request {
+    let nonce: [u8; NONCE_SIZE] = rand::random();
+    let version = VERSION.to_le_bytes();
+    let request_type = 0u32.to_le_bytes();
+
+    // Four tags take 32 bytes of message header
+    let unpadded = FRAME_HEADER_SIZE + 32 + version.len() + NONCE_SIZE + request_type.len();
+    let padding = vec![0; REQUEST_SIZE - unpadded];
+
+    let message = encode_message(&[
+        (Tag::VER, &version),
+        (Tag::NONC, &nonce),
+        (Tag::TYPE, &request_type),
+        (Tag::ZZZZ, &padding),
+    ]);
+
+    Request {
+        nonce,
+        bytes: frame(&message),
+    }
+}
+
+/// The time according to a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryResult {
+    /// Time of the server at some moment between sending the request and
+    /// receiving the response
+    pub midpoint: NtpTimestamp,
+    /// The server claims the true time is within this distance of the
+    /// midpoint
+    pub radius: NtpDuration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// The response is not a valid Roughtime packet
+    InvalidPacket,
+    /// The response is not an answer to the request
+    UnexpectedResponse,
+    /// A signature in the response could not be verified
+    InvalidSignature,
+    /// The midpoint lies outside the validity period of the key that signed
+    /// the response
+    ExpiredKey,
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPacket => f.write_str("response is not a valid Roughtime packet"),
+            Self::UnexpectedResponse => f.write_str("response does not match the request"),
+            Self::InvalidSignature => f.write_str("response has an invalid signature"),
+            Self::ExpiredKey => f.write_str("response was signed with an expired key"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Verify the response of a server to `request`, which should be signed
+/// with the long-term `public_key` of the server.
+pub fn query(
+    request: &Request,
+    response: &[u8],
+    public_key: &[u8; PUBLIC_KEY_SIZE],
+) -> Result<QueryResult, QueryError> {
+    let response = Message::parse(unframe(response)?)?;
+
+    if response
+        .get(Tag::TYPE)
+        .is_some_and(|response_type| response_type != 1u32.to_le_bytes())
+        || response
+            .get(Tag::NONC)
+            .is_some_and(|nonce| nonce != request.nonce)
+    {
+        return Err(QueryError::UnexpectedResponse);
+    }
+
+    // The long-term key delegates to an online key for a limited time
+    let certificate = response.require_message(Tag::CERT)?;
+    let delegation = certificate.require(Tag::DELE)?;
+    verify_signature(
+        public_key,
+        DELEGATION_CONTEXT,
+        delegation,
+        certificate.require(Tag::SIG)?,
+    )?;
+    let delegation = Message::parse(delegation)?;
+
+    let signed_response = response.require(Tag::SREP)?;
+    verify_signature(
+        delegation.require(Tag::PUBK)?,
+        RESPONSE_CONTEXT,
+        signed_response,
+        response.require(Tag::SIG)?,
+    )?;
+    let signed_response = Message::parse(signed_response)?;
+
+    // The server signs a batch of requests at once, ours must be among them
+    let root = merkle_root(
+        request.as_bytes(),
+        response.require(Tag::PATH)?,
+        response.require_u32(Tag::INDX)?,
+    )?;
+    if signed_response.require(Tag::ROOT)? != root {
+        return Err(QueryError::UnexpectedResponse);
+    }
+
+    let midpoint = signed_response.require_u64(Tag::MIDP)?;
+    let radius = signed_response.require_u32(Tag::RADI)?;
+    if midpoint < delegation.require_u64(Tag::MINT)?
+        || midpoint > delegation.require_u64(Tag::MAXT)?
+    {
+        return Err(QueryError::ExpiredKey);
+    }
+    let seconds = midpoint
+        .checked_add(NTP_UNIX_OFFSET)
+        .ok_or(QueryError::InvalidPacket)?;
+
+    Ok(QueryResult {
+        // NTP timestamps carry no era, so wrapping is intended here
+        midpoint: NtpTimestamp::from_seconds_nanos_since_ntp_era(seconds as u32, 0),
+        radius: NtpDuration::from_seconds(f64::from(radius)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls23::{
+        SignatureScheme,
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    };
+
+    use super::*;
+
+    const LONG_TERM_SEED: [u8; 32] = [1; 32];
+    const LONG_TERM_KEY: [u8; PUBLIC_KEY_SIZE] = [
+        0x8a, 0x88, 0xe3, 0xdd, 0x74, 0x09, 0xf1, 0x95, 0xfd, 0x52, 0xdb, 0x2d, 0x3c, 0xba, 0x5d,
+        0x72, 0xca, 0x67, 0x09, 0xbf, 0x1d, 0x94, 0x12, 0x1b, 0xf3, 0x74, 0x88, 0x01, 0xb4, 0x0f,
+        0x6f, 0x5c,
+    ];
+    const ONLINE_SEED: [u8; 32] = [2; 32];
+    const ONLINE_KEY: [u8; PUBLIC_KEY_SIZE] = [
+        0x81, 0x39, 0x77, 0x0e, 0xa8, 0x7d, 0x17, 0x5f, 0x56, 0xa3, 0x54, 0x66, 0xc3, 0x4c, 0x7e,
+        0xcc, 0xcb, 0x8d, 0x8a, 0x91, 0xb4, 0xee, 0x37, 0xa2, 0x5d, 0xf6, 0x0f, 0x5b, 0x8f, 0xc9,
+        0xb3, 0x94,
+    ];
+
+    // PKCS#8 encoding of an Ed25519 private key, followed by the seed
+    const PKCS8_PREFIX: [u8; 16] = [
+        0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+        0x20,
+    ];
+
+    fn sign(seed: &[u8; 32], context: &[u8], data: &[u8]) -> Vec<u8> {
+        let builder = tls_utils::client_config_builder();
+        let key = builder
+            .crypto_provider()
+            .key_provider
+            .load_private_key(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                [&PKCS8_PREFIX[..], seed].concat(),
+            )))
+            .unwrap();
+        let signer = key.choose_scheme(&[SignatureScheme::ED25519]).unwrap();
+        signer.sign(&[context, data].concat()).unwrap()
+    }
+
+    struct TestResponse {
+        midpoint: u64,
+        radius: u32,
+        min_time: u64,
+        max_time: u64,
+        long_term_seed: [u8; 32],
+        nonce: Option<[u8; NONCE_SIZE]>,
+        index: u32,
+    }
+
+    impl TestResponse {
+        fn new() -> Self {
+            TestResponse {
+                midpoint: 1_700_000_000,
+                radius: 10,
+                min_time: 1_600_000_000,
+                max_time: 1_800_000_000,
+                long_term_seed: LONG_TERM_SEED,
+                nonce: None,
+                index: 0,
+            }
+        }
+
+        /// Answer the request together with one other request
+        fn build(&self, request: &Request) -> Vec<u8> {
+            let ours = tree_hash(&[&[0], request.as_bytes()]);
+            let other = tree_hash(&[&[0], b"other request"]);
+            let root = tree_hash(&[&[1], &ours, &other]);
+
+            let midpoint = self.midpoint.to_le_bytes();
+            let radius = self.radius.to_le_bytes();
+            let signed_response = encode_message(&[
+                (Tag::ROOT, &root),
+                (Tag::MIDP, &midpoint),
+                (Tag::RADI, &radius),
+            ]);
+
+            let min_time = self.min_time.to_le_bytes();
+            let max_time = self.max_time.to_le_bytes();
+            let delegation = encode_message(&[
+                (Tag::PUBK, &ONLINE_KEY),
+                (Tag::MINT, &min_time),
+                (Tag::MAXT, &max_time),
+            ]);
+            let delegation_signature = sign(&self.long_term_seed, DELEGATION_CONTEXT, &delegation);
+            let certificate =
+                encode_message(&[(Tag::SIG, &delegation_signature), (Tag::DELE, &delegation)]);
+
+            let signature = sign(&ONLINE_SEED, RESPONSE_CONTEXT, &signed_response);
+            let nonce = self.nonce.unwrap_or(request.nonce);
+            let response_type = 1u32.to_le_bytes();
+            let index = self.index.to_le_bytes();
+            frame(&encode_message(&[
+                (Tag::SIG, &signature),
+                (Tag::NONC, &nonce),
+                (Tag::TYPE, &response_type),
+                (Tag::PATH, &other),
+                (Tag::SREP, &signed_response),
+                (Tag::CERT, &certificate),
+                (Tag::INDX, &index),
+            ]))
+        }
+    }
+
+    #[test]
+    fn test_request() {
+        let request = request();
+        assert_eq!(request.as_bytes().len(), REQUEST_SIZE);
+
+        let message = Message::parse(unframe(request.as_bytes()).unwrap()).unwrap();
+        assert_eq!(message.require_u32(Tag::VER), Ok(VERSION));
+        assert_eq!(message.require(Tag::NONC), Ok(&request.nonce[..]));
+        assert_eq!(message.require_u32(Tag::TYPE), Ok(0));
+
+        // Every request has a fresh nonce
+        assert_ne!(super::request().nonce, request.nonce);
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        let message = encode_message(&[(Tag::ZZZZ, &[0; 8]), (Tag::SIG, &[1; 4]), (Tag::VER, &[])]);
+        assert_eq!(
+            message,
+            [
+                3, 0, 0, 0, // three tags
+                4, 0, 0, 0, // offset of the second value
+                4, 0, 0, 0, // offset of the third value
+                b'S', b'I', b'G', 0, b'V', b'E', b'R', 0, b'Z', b'Z', b'Z', b'Z', // tags
+                1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, // values
+            ]
+        );
+
+        let parsed = Message::parse(&message).unwrap();
+        assert_eq!(parsed.get(Tag::SIG), Some(&[1u8; 4][..]));
+        assert_eq!(parsed.get(Tag::VER), Some(&[][..]));
+        assert_eq!(parsed.get(Tag::ZZZZ), Some(&[0u8; 8][..]));
+        assert_eq!(parsed.get(Tag::NONC), None);
+
+        assert!(Message::parse(&[0, 0, 0, 0]).unwrap().fields.is_empty());
+        assert!(Message::parse(&[]).is_err());
+
+        // Tags out of order
+        let mut unsorted = message.clone();
+        unsorted[12..16].copy_from_slice(b"ZZZZ");
+        assert!(Message::parse(&unsorted).is_err());
+
+        // Offsets must be aligned and in range
+        let mut misaligned = message.clone();
+        misaligned[4] = 2;
+        assert!(Message::parse(&misaligned).is_err());
+        let mut out_of_range = message.clone();
+        out_of_range[8] = 16;
+        assert!(Message::parse(&out_of_range).is_err());
+
+        // Too many tags for the size of the message
+        assert!(Message::parse(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_query() {
+        let request = request();
+        let response = TestResponse::new().build(&request);
+
+        let result = query(&request, &response, &LONG_TERM_KEY).unwrap();
+        assert_eq!(
+            result.midpoint,
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(3_908_988_800, 0)
+        );
+        assert_eq!(result.radius, NtpDuration::from_seconds(10.0));
+    }
+
+    #[test]
+    fn test_query_rejects() {
+        let request = request();
+
+        assert_eq!(
+            query(&request, &[0; 12], &LONG_TERM_KEY),
+            Err(QueryError::InvalidPacket)
+        );
+
+        let mut truncated = TestResponse::new().build(&request);
+        truncated.pop();
+        assert_eq!(
+            query(&request, &truncated, &LONG_TERM_KEY),
+            Err(QueryError::InvalidPacket)
+        );
+
+        // A response to another request
+        let other = TestResponse::new().build(&super::request());
+        assert_eq!(
+            query(&request, &other, &LONG_TERM_KEY),
+            Err(QueryError::UnexpectedResponse)
+        );
+
+        let wrong_nonce = TestResponse {
+            nonce: Some([0; NONCE_SIZE]),
+            ..TestResponse::new()
+        };
+        assert_eq!(
+            query(&request, &wrong_nonce.build(&request), &LONG_TERM_KEY),
+            Err(QueryError::UnexpectedResponse)
+        );
+
+        // Our request is the left leaf of the tree
+        let wrong_index = TestResponse {
+            index: 1,
+            ..TestResponse::new()
+        };
+        assert_eq!(
+            query(&request, &wrong_index.build(&request), &LONG_TERM_KEY),
+            Err(QueryError::UnexpectedResponse)
+        );
+        let too_large_index = TestResponse {
+            index: 2,
+            ..TestResponse::new()
+        };
+        assert_eq!(
+            query(&request, &too_large_index.build(&request), &LONG_TERM_KEY),
+            Err(QueryError::UnexpectedResponse)
+        );
+
+        // Delegation signed by some other key
+        let wrong_key = TestResponse {
+            long_term_seed: ONLINE_SEED,
+            ..TestResponse::new()
+        };
+        assert_eq!(
+            query(&request, &wrong_key.build(&request), &LONG_TERM_KEY),
+            Err(QueryError::InvalidSignature)
+        );
+
+        // Signatures cover the signed response
+        let mut tampered = TestResponse::new().build(&request);
+        let position = tampered
+            .windows(8)
+            .position(|window| window == 1_700_000_000u64.to_le_bytes())
+            .unwrap();
+        tampered[position] ^= 1;
+        assert_eq!(
+            query(&request, &tampered, &LONG_TERM_KEY),
+            Err(QueryError::InvalidSignature)
+        );
+
+        let expired = TestResponse {
+            max_time: 1_650_000_000,
+            ..TestResponse::new()
+        };
+        assert_eq!(
+            query(&request, &expired.build(&request), &LONG_TERM_KEY),
+            Err(QueryError::ExpiredKey)
+        );
+
+        let overflowing = TestResponse {
+            midpoint: u64::MAX - 1,
+            max_time: u64::MAX,
+            ..TestResponse::new()
+        };
+        assert_eq!(
+            query(&request, &overflowing.build(&request), &LONG_TERM_KEY),
+            Err(QueryError::InvalidPacket)
+        );
+    }
+
+    #[test]
+    fn test_query_next_era() {
+        let request = request();
+        let response = TestResponse {
+            midpoint: 2_100_000_000 + (1 << 32),
+            max_time: u64::MAX,
+            ..TestResponse::new()
+        };
+        let result = query(&request, &response.build(&request), &LONG_TERM_KEY).unwrap();
+        assert_eq!(
+            result.midpoint,
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(14_021_504, 0)
+        );
+    }
+}
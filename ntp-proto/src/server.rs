@@ -7,11 +7,13 @@ use std::{
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Deserializer, de};
 
 use crate::{
-    Cipher, KeySet, NtpClock, NtpPacket, NtpTimestamp, NtpVersion, PacketParsingError,
-    ipfilter::IpFilter, system::NtpServerInfo,
+    Cipher, ExtensionFieldHandler, ExtensionFieldHandlers, ExtensionFieldRegistrationError, KeySet,
+    NoCipher, NtpClock, NtpLeapIndicator, NtpPacket, NtpTimestamp, NtpVersion, PacketParsingError,
+    PollInterval, SymmetricKey, ipfilter::IpFilter, system::NtpServerInfo,
 };
 
 pub enum ServerAction<'a> {
@@ -50,8 +52,9 @@ pub trait ServerStatHandler {
     fn register(&mut self, version: u8, nts: bool, reason: ServerReason, response: ServerResponse);
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum FilterAction {
     Ignore,
     Deny,
@@ -66,7 +69,8 @@ impl From<FilterAction> for ServerResponse {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct FilterList {
     pub filter: Vec<IpSubnet>,
     pub action: FilterAction,
@@ -90,6 +94,8 @@ pub struct Server<C> {
     client_cache: TimestampedCache<IpAddr>,
     server_info: Arc<RwLock<NtpServerInfo>>,
     keyset: Arc<KeySet>,
+    symmetric_keys: Arc<[SymmetricKey]>,
+    extension_handlers: ExtensionFieldHandlers,
 }
 
 // Quick estimation of ntp packet message version without doing full parsing
@@ -116,6 +122,8 @@ impl<C> Server<C> {
             client_cache,
             server_info,
             keyset,
+            symmetric_keys: Arc::new([]),
+            extension_handlers: ExtensionFieldHandlers::default(),
         }
     }
 
@@ -124,6 +132,20 @@ impl<C> Server<C> {
         self.keyset = keyset;
     }
 
+    /// Provide the server with the pre-shared keys clients may authenticate with
+    pub fn update_symmetric_keys(&mut self, keys: Arc<[SymmetricKey]>) {
+        self.symmetric_keys = keys;
+    }
+
+    /// Respond to client extension fields of the given type using `handler`
+    pub fn register_extension_handler(
+        &mut self,
+        type_id: u16,
+        handler: Arc<dyn ExtensionFieldHandler>,
+    ) -> Result<(), ExtensionFieldRegistrationError> {
+        self.extension_handlers.register(type_id, handler)
+    }
+
     fn intended_action(&mut self, client_ip: IpAddr) -> (ServerResponse, ServerReason) {
         if self.denyfilter.is_in(&client_ip) {
             // First apply denylist
@@ -152,6 +174,7 @@ pub struct HandleInnerData<'a> {
     pub nts: bool,
     pub packet: NtpPacket<'a>,
     pub cipher: Option<Box<dyn Cipher>>,
+    pub symmetric_key: Option<SymmetricKey>,
     pub desired_size: Option<usize>,
 }
 
@@ -176,6 +199,7 @@ impl<C: NtpClock> Server<C> {
             nts,
             packet,
             cipher,
+            symmetric_key,
             desired_size,
         } = match self.handle_inner(client_ip, recv_timestamp, message, stats_handler) {
             Ok(value) => value,
@@ -183,7 +207,11 @@ impl<C: NtpClock> Server<C> {
         };
 
         let mut cursor = Cursor::new(buffer);
-        match packet.serialize(&mut cursor, &cipher.as_deref(), desired_size) {
+        let result = match symmetric_key {
+            Some(key) => packet.serialize_with_mac(&mut cursor, &key, desired_size),
+            None => packet.serialize(&mut cursor, &cipher.as_deref(), desired_size),
+        };
+        match result {
             Ok(_) => {
                 stats_handler.register(version.into(), nts, reason, action);
                 let length = cursor.position();
@@ -204,6 +232,37 @@ impl<C: NtpClock> Server<C> {
         }
     }
 
+    /// Create a message for broadcast and multicast clients, announcing that
+    /// the next one follows after `poll_interval`
+    ///
+    /// Returns `None` while we are not synchronized ourselves, as clients
+    /// would ignore the message anyway.
+    pub fn broadcast<'a>(
+        &self,
+        poll_interval: PollInterval,
+        buffer: &'a mut [u8],
+    ) -> Option<&'a [u8]> {
+        let server_info = *self.server_info.read().unwrap();
+        if server_info.ntp_snapshot.stratum >= 16
+            || server_info.time_snapshot.leap_indicator == NtpLeapIndicator::Unknown
+        {
+            return None;
+        }
+
+        let packet = NtpPacket::broadcast(&server_info, poll_interval, &self.clock);
+        let mut cursor = Cursor::new(buffer);
+        match packet.serialize(&mut cursor, &NoCipher, None) {
+            Ok(()) => {
+                let length = cursor.position();
+                Some(&cursor.into_inner()[..length as _])
+            }
+            Err(e) => {
+                tracing::debug!("Could not serialize broadcast: {}", e);
+                None
+            }
+        }
+    }
+
     // FIXME: Figure out a way to split this
     #[expect(clippy::too_many_lines)]
     fn handle_inner<'a>(
@@ -268,6 +327,24 @@ impl<C: NtpClock> Server<C> {
             return Err(ServerAction::Ignore);
         }
 
+        // Requests authenticated with a pre-shared key get a response
+        // authenticated with that same key
+        let symmetric_key = match packet.mac_key_id() {
+            None => None,
+            Some(key_id) => match self.symmetric_keys.iter().find(|key| key.id() == key_id) {
+                Some(key) if key.verify(message) => Some(key.clone()),
+                _ => {
+                    stats_handler.register(
+                        version.into(),
+                        false,
+                        ServerReason::InvalidCrypto,
+                        ServerResponse::Ignore,
+                    );
+                    return Err(ServerAction::Ignore);
+                }
+            },
+        };
+
         let nts = cookie.is_some() || action == ServerResponse::NTSNak;
 
         // ignore non-NTS packets when configured to require NTS
@@ -288,39 +365,58 @@ impl<C: NtpClock> Server<C> {
         let server_info = *self.server_info.read().unwrap();
 
         let (packet, cipher, desired_size) = match action {
-            ServerResponse::NTSNak => (NtpPacket::nts_nak_response(packet), None, None),
+            ServerResponse::NTSNak => (
+                NtpPacket::nts_nak_response(packet),
+                None,
+                Some(message.len()),
+            ),
             ServerResponse::Deny => {
                 if let Some(cookie) = cookie {
-                    (NtpPacket::nts_deny_response(packet), Some(cookie.s2c), None)
+                    (
+                        NtpPacket::nts_deny_response(packet),
+                        Some(cookie.s2c),
+                        Some(message.len()),
+                    )
                 } else {
-                    (NtpPacket::deny_response(packet), None, None)
+                    (NtpPacket::deny_response(packet), None, Some(message.len()))
                 }
             }
             ServerResponse::ProvideTime => {
                 if let Some(cookie) = cookie {
-                    (
-                        NtpPacket::nts_timestamp_response(
-                            server_info,
-                            packet,
-                            recv_timestamp,
-                            &self.clock,
-                            &cookie,
-                            &self.keyset,
-                        ),
-                        Some(cookie.s2c),
-                        Some(message.len()),
-                    )
+                    // Only authenticated fields get a response, so an attacker
+                    // can't get them into an authenticated response
+                    let additional = self
+                        .extension_handlers
+                        .respond(packet.authenticated_extension_fields(), true);
+                    let mut response = NtpPacket::nts_timestamp_response(
+                        &server_info,
+                        packet,
+                        recv_timestamp,
+                        &self.clock,
+                        &cookie,
+                        &self.keyset,
+                    );
+                    for ef in additional {
+                        response.push_additional(ef);
+                    }
+                    (response, Some(cookie.s2c), Some(message.len()))
                 } else {
-                    (
-                        NtpPacket::timestamp_response(
-                            server_info,
-                            packet,
-                            recv_timestamp,
-                            &self.clock,
-                        ),
-                        None,
-                        Some(message.len()),
-                    )
+                    let additional = self.extension_handlers.respond(
+                        packet
+                            .untrusted_extension_fields()
+                            .chain(packet.authenticated_extension_fields()),
+                        false,
+                    );
+                    let mut response = NtpPacket::timestamp_response(
+                        &server_info,
+                        packet,
+                        recv_timestamp,
+                        &self.clock,
+                    );
+                    for ef in additional {
+                        response.push_additional(ef);
+                    }
+                    (response, None, Some(message.len()))
                 }
             }
             ServerResponse::Ignore => unreachable!(),
@@ -333,6 +429,7 @@ impl<C: NtpClock> Server<C> {
             nts,
             packet,
             cipher,
+            symmetric_key,
             desired_size,
         })
     }
@@ -460,6 +557,7 @@ impl std::str::FromStr for IpSubnet {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for IpSubnet {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -478,9 +576,12 @@ impl<'de> Deserialize<'de> for IpSubnet {
 mod tests {
     use std::net::{Ipv4Addr, Ipv6Addr};
 
+    use std::borrow::Cow;
+
     use crate::{
-        Cipher, DecodedServerCookie, KeySetProvider, NoCipher, NtpDuration, NtpLeapIndicator,
-        PollIntervalLimits, nts::AeadAlgorithm, packet::AesSivCmac256,
+        Cipher, DecodedServerCookie, ExtensionField, KeySetProvider, MacAlgorithm,
+        NtpAssociationMode, NtpDuration, PollIntervalLimits, nts::AeadAlgorithm,
+        packet::AesSivCmac256,
     };
 
     use super::*;
@@ -1302,6 +1403,95 @@ mod tests {
         assert!(packet.is_kiss_ntsn());
     }
 
+    #[test]
+    fn test_server_nts_v5() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_millis(100),
+            rate_limiting_cache_size: 0,
+            require_nts: Some(FilterAction::Ignore),
+            accepted_versions: vec![NtpVersion::V5],
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+        let keyset = KeySetProvider::new(1).get();
+
+        let mut server = Server::new_internal(config, clock, Arc::default(), keyset.clone());
+
+        let decodedcookie = DecodedServerCookie {
+            algorithm: AeadAlgorithm::AeadAesSivCmac256,
+            s2c: Box::new(AesSivCmac256::new([0; 32].into())),
+            c2s: Box::new(AesSivCmac256::new([0; 32].into())),
+        };
+        let cookie = keyset.encode_cookie(&decodedcookie);
+        let (packet, id) =
+            NtpPacket::nts_poll_message_v5(&cookie, 2, PollIntervalLimits::default().min);
+        let serialized = serialize_packet_encrypted(&packet, decodedcookie.c2s.as_ref());
+
+        let mut buf = [0; 1024];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((5, true, ServerReason::Policy, ServerResponse::ProvideTime))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, decodedcookie.s2c.as_ref())
+            .unwrap()
+            .0;
+        assert_eq!(packet.version(), NtpVersion::V5);
+        assert!(packet.valid_server_response(id, true));
+        assert_eq!(
+            packet.receive_timestamp(),
+            NtpTimestamp::from_fixed_int(100)
+        );
+        assert_eq!(packet.new_cookies().count(), 2);
+
+        let cookie_invalid = KeySetProvider::new(1).get().encode_cookie(&decodedcookie);
+        let (packet_invalid, id) =
+            NtpPacket::nts_poll_message_v5(&cookie_invalid, 0, PollIntervalLimits::default().min);
+        let serialized = serialize_packet_encrypted(&packet_invalid, decodedcookie.c2s.as_ref());
+
+        let mut buf = [0; 1024];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((5, true, ServerReason::InvalidCrypto, ServerResponse::NTSNak))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, decodedcookie.s2c.as_ref())
+            .unwrap()
+            .0;
+        assert!(packet.is_kiss_ntsn());
+        assert!(packet.valid_server_response(id, true));
+    }
+
     #[test]
     fn test_server_require_nts() {
         let mut config = ServerConfig {
@@ -1594,7 +1784,242 @@ mod tests {
         assert!(cache.is_allowed(0, instant, second));
     }
 
+    #[test]
+    fn test_server_symmetric_key() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            rate_limiting_cache_size: 0,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+        let mut server =
+            Server::new_internal(config, clock, Arc::default(), KeySetProvider::new(1).get());
+        let key = SymmetricKey::new(5, MacAlgorithm::Aes128Cmac, &[1; 16]).unwrap();
+        server.update_symmetric_keys(Arc::new([key.clone()]));
+
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let mut request = [0; 1024];
+        let mut cursor = Cursor::new(request.as_mut_slice());
+        packet.serialize_with_mac(&mut cursor, &key, None).unwrap();
+        let used = cursor.position() as usize;
+
+        let mut buf = [0; 1024];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &request[..used],
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::Policy, ServerResponse::ProvideTime))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        assert!(key.verify(data));
+        let response = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert!(response.valid_server_response(id, false));
+        assert_eq!(response.mac_key_id(), Some(5));
+
+        // A key unknown to the server is ignored
+        let unknown = SymmetricKey::new(6, MacAlgorithm::Aes128Cmac, &[1; 16]).unwrap();
+        let mut cursor = Cursor::new(request.as_mut_slice());
+        packet
+            .serialize_with_mac(&mut cursor, &unknown, None)
+            .unwrap();
+        let used = cursor.position() as usize;
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &request[..used],
+            &mut buf,
+            &mut stats,
+        );
+        assert!(matches!(response, ServerAction::Ignore));
+        assert_eq!(
+            stats.last_register.take(),
+            Some((
+                4,
+                false,
+                ServerReason::InvalidCrypto,
+                ServerResponse::Ignore
+            ))
+        );
+
+        // As is a MAC that does not match the key
+        let mut cursor = Cursor::new(request.as_mut_slice());
+        packet.serialize_with_mac(&mut cursor, &key, None).unwrap();
+        let used = cursor.position() as usize;
+        request[used - 1] ^= 1;
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &request[..used],
+            &mut buf,
+            &mut stats,
+        );
+        assert!(matches!(response, ServerAction::Ignore));
+        assert_eq!(
+            stats.last_register.take(),
+            Some((
+                4,
+                false,
+                ServerReason::InvalidCrypto,
+                ServerResponse::Ignore
+            ))
+        );
+    }
+
+    struct EchoReversed;
+
+    impl ExtensionFieldHandler for EchoReversed {
+        fn respond(&self, data: &[u8], authenticated: bool) -> Option<ExtensionField<'static>> {
+            assert!(!authenticated);
+            Some(ExtensionField::Unknown {
+                type_id: 0x2006,
+                data: Cow::Owned(data.iter().rev().copied().collect()),
+            })
+        }
+    }
+
+    #[test]
+    fn test_server_extension_handler() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            rate_limiting_cache_size: 0,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+        let mut server =
+            Server::new_internal(config, clock, Arc::default(), KeySetProvider::new(1).get());
+
+        assert_eq!(
+            server.register_extension_handler(0x104, Arc::new(EchoReversed)),
+            Err(ExtensionFieldRegistrationError::Builtin(0x104))
+        );
+        server
+            .register_extension_handler(0x2005, Arc::new(EchoReversed))
+            .unwrap();
+        assert_eq!(
+            server.register_extension_handler(0x2005, Arc::new(EchoReversed)),
+            Err(ExtensionFieldRegistrationError::AlreadyRegistered(0x2005))
+        );
+
+        // NTPv4 extension fields are padded, the last one to at least 28
+        // bytes, so use data that fills it exactly to echo it unchanged
+        let echoed: Vec<u8> = (1..=24).collect();
+        let (mut packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        packet.push_additional(ExtensionField::Unknown {
+            type_id: 0x2005,
+            data: Cow::Owned(echoed.clone()),
+        });
+        packet.push_additional(ExtensionField::Unknown {
+            type_id: 0x2007,
+            data: Cow::Borrowed(&[5, 6, 7, 8]),
+        });
+        let serialized = serialize_packet_unencrypted(&packet);
+
+        let mut buf = [0; 1024];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert_eq!(
+            packet.untrusted_extension_fields().collect::<Vec<_>>(),
+            [&ExtensionField::Unknown {
+                type_id: 0x2006,
+                data: Cow::Owned(echoed.iter().rev().copied().collect()),
+            }]
+        );
+    }
+
     // IpSubnet parsing tests
+    #[test]
+    fn test_server_broadcast() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            rate_limiting_cache_size: 0,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let server_info: Arc<RwLock<NtpServerInfo>> = Arc::default();
+        let server = Server::new_internal(
+            config,
+            clock,
+            server_info.clone(),
+            KeySetProvider::new(1).get(),
+        );
+        let poll_interval = PollIntervalLimits::default().min;
+
+        // Not synchronized yet
+        let mut buf = [0; 48];
+        assert!(server.broadcast(poll_interval, &mut buf).is_none());
+
+        {
+            let mut server_info = server_info.write().unwrap();
+            server_info.ntp_snapshot.stratum = 2;
+            server_info.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+        }
+
+        let mut buf = [0; 48];
+        let data = server.broadcast(poll_interval, &mut buf).unwrap();
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert_eq!(packet.mode(), NtpAssociationMode::Broadcast);
+        assert_eq!(packet.stratum(), 2);
+        assert_eq!(packet.poll(), poll_interval);
+        assert_eq!(
+            packet.transmit_timestamp(),
+            NtpTimestamp::from_fixed_int(200)
+        );
+    }
+
     #[test]
     fn test_ipv4_subnet_parse() {
         use std::str::FromStr;
@@ -1624,6 +2049,7 @@ mod tests {
             })
         );
 
+        #[cfg(feature = "serde")]
         assert_eq!(
             serde_json::from_str::<IpSubnet>(r#""0.0.0.0/0""#).unwrap(),
             IpSubnet {
@@ -1632,6 +2058,7 @@ mod tests {
             }
         );
 
+        #[cfg(feature = "serde")]
         assert_eq!(
             serde_json::from_str::<IpSubnet>(r#""127.0.0.1/32""#).unwrap(),
             IpSubnet {
@@ -1667,6 +2094,7 @@ mod tests {
             })
         );
 
+        #[cfg(feature = "serde")]
         assert_eq!(
             serde_json::from_str::<IpSubnet>(r#""::/0""#).unwrap(),
             IpSubnet {
@@ -1675,6 +2103,7 @@ mod tests {
             }
         );
 
+        #[cfg(feature = "serde")]
         assert_eq!(
             serde_json::from_str::<IpSubnet>(r#""::1/128""#).unwrap(),
             IpSubnet {
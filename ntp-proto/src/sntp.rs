@@ -0,0 +1,255 @@
+//! A minimal SNTP client (RFC 4330) for taking a single measurement of the
+//! offset to an NTP server.
+//!
+//! This is meant for applications that only occasionally want to know the
+//! time according to a server, without running the full synchronization
+//! algorithm of the daemon. Like the rest of this crate, it does no IO
+//! itself: send the bytes of a [`Request`] to the server, and pass its
+//! answer to [`query`] together with the local send and receive timestamps.
+//!
+//! ```no_run
+//! use std::{net::UdpSocket, time::SystemTime};
+//!
+//! use ntp_proto::sntp::{self, NtpTimestamp};
+//!
+//! fn now() -> NtpTimestamp {
+//!     // Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+//!     const EPOCH_OFFSET: u64 = 2_208_988_800;
+//!     let since_epoch = SystemTime::now()
+//!         .duration_since(SystemTime::UNIX_EPOCH)
+//!         .unwrap();
+//!     NtpTimestamp::from_seconds_nanos_since_ntp_era(
+//!         (since_epoch.as_secs() + EPOCH_OFFSET) as u32,
+//!         since_epoch.subsec_nanos(),
+//!     )
+//! }
+//!
+//! let socket = UdpSocket::bind("0.0.0.0:0")?;
+//! socket.connect("pool.ntp.org:123")?;
+//!
+//! let request = sntp::request();
+//! let send_time = now();
+//! socket.send(request.as_bytes())?;
+//!
+//! let mut buf = [0; 1024];
+//! let size = socket.recv(&mut buf)?;
+//! let recv_time = now();
+//!
+//! match sntp::query(&request, &buf[..size], send_time, recv_time) {
+//!     Ok(result) => println!("offset {}, delay {}", result.offset, result.delay),
+//!     Err(e) => eprintln!("{e}"),
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::Cursor;
+
+use crate::{
+    packet::{NoCipher, NtpAssociationMode, NtpPacket, RequestIdentifier},
+    time_types::PollIntervalLimits,
+};
+
+pub use crate::packet::NtpLeapIndicator;
+pub use crate::time_types::{NtpDuration, NtpTimestamp};
+
+/// A request to send to an NTP server
+#[derive(Debug, Clone)]
+pub struct Request {
+    identifier: RequestIdentifier,
+    bytes: Vec<u8>,
+}
+
+impl Request {
+    /// The bytes to send to the server
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Create a new request. Every request has a random transmit timestamp, so a
+/// request should not be sent more than once.
+pub fn request() -> Request {
+    let (packet, identifier) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+
+    let mut bytes = vec![0; 1024];
+    let mut cursor = Cursor::new(bytes.as_mut_slice());
+    packet
+        .serialize(&mut cursor, &NoCipher, None)
+        .expect("Internal error: could not serialize request");
+    let length = cursor.position() as usize;
+    bytes.truncate(length);
+
+    Request { identifier, bytes }
+}
+
+/// The outcome of a single request/response exchange with a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryResult {
+    /// Offset of the server clock relative to the local clock. A positive
+    /// offset means the local clock is behind.
+    pub offset: NtpDuration,
+    /// Round trip delay of the exchange, excluding the processing time of the
+    /// server
+    pub delay: NtpDuration,
+    pub stratum: u8,
+    pub leap: NtpLeapIndicator,
+    pub root_delay: NtpDuration,
+    pub root_dispersion: NtpDuration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// The response is not a valid NTP packet
+    InvalidPacket,
+    /// The response is not an answer to the request
+    UnexpectedResponse,
+    /// The server sent a kiss-o'-death packet with the given code, and should
+    /// not be queried again for a while (RATE) or at all (DENY, RSTR)
+    KissOfDeath(String),
+    /// The server is not synchronized itself
+    Unsynchronized,
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPacket => f.write_str("response is not a valid NTP packet"),
+            Self::UnexpectedResponse => f.write_str("response does not match the request"),
+            Self::KissOfDeath(code) => write!(f, "server sent kiss code {code}"),
+            Self::Unsynchronized => f.write_str("server is not synchronized"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Process the response of a server to `request`. `send_time` and
+/// `recv_time` are the local times at which the request was sent and the
+/// response was received.
+pub fn query(
+    request: &Request,
+    response: &[u8],
+    send_time: NtpTimestamp,
+    recv_time: NtpTimestamp,
+) -> Result<QueryResult, QueryError> {
+    let (packet, _) =
+        NtpPacket::deserialize(response, &NoCipher).map_err(|_| QueryError::InvalidPacket)?;
+
+    if packet.mode() != NtpAssociationMode::Server
+        || !packet.valid_server_response(request.identifier, false)
+    {
+        return Err(QueryError::UnexpectedResponse);
+    }
+
+    if packet.is_kiss() {
+        let code = packet.reference_id().as_code().unwrap_or_default();
+        return Err(QueryError::KissOfDeath(code));
+    }
+
+    // RFC 4330 section 5: a server without leap indicator or transmit time
+    // can't be used for synchronization
+    if !packet.leap().is_synchronized()
+        || packet.stratum() >= 16
+        || packet.transmit_timestamp() == NtpTimestamp::default()
+    {
+        return Err(QueryError::Unsynchronized);
+    }
+
+    let remote_receive = packet.receive_timestamp();
+    let remote_transmit = packet.transmit_timestamp();
+
+    Ok(QueryResult {
+        offset: ((remote_receive - send_time) + (remote_transmit - recv_time)) / 2,
+        delay: (recv_time - send_time) - (remote_transmit - remote_receive),
+        stratum: packet.stratum(),
+        leap: packet.leap(),
+        root_delay: packet.root_delay(),
+        root_dispersion: packet.root_dispersion(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ReferenceId;
+
+    use super::*;
+
+    fn response(request: &Request, modify: impl FnOnce(&mut NtpPacket)) -> Vec<u8> {
+        let (request, _) = NtpPacket::deserialize(request.as_bytes(), &NoCipher).unwrap();
+
+        let mut packet = NtpPacket::test();
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_stratum(2);
+        packet.set_leap(NtpLeapIndicator::NoWarning);
+        packet.set_origin_timestamp(request.transmit_timestamp());
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(1500));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(1700));
+        modify(&mut packet);
+        packet.serialize_without_encryption_vec(None).unwrap()
+    }
+
+    #[test]
+    fn test_query() {
+        let request = request();
+        let (packet, _) = NtpPacket::deserialize(request.as_bytes(), &NoCipher).unwrap();
+        assert_eq!(packet.mode(), NtpAssociationMode::Client);
+
+        let result = query(
+            &request,
+            &response(&request, |_| {}),
+            NtpTimestamp::from_fixed_int(1000),
+            NtpTimestamp::from_fixed_int(1400),
+        )
+        .unwrap();
+
+        assert_eq!(result.offset, NtpDuration::from_fixed_int(400));
+        assert_eq!(result.delay, NtpDuration::from_fixed_int(200));
+        assert_eq!(result.stratum, 2);
+        assert_eq!(result.leap, NtpLeapIndicator::NoWarning);
+    }
+
+    #[test]
+    fn test_query_rejects() {
+        let request = request();
+        let send_time = NtpTimestamp::from_fixed_int(1000);
+        let recv_time = NtpTimestamp::from_fixed_int(1400);
+
+        assert_eq!(
+            query(&request, &[0; 12], send_time, recv_time),
+            Err(QueryError::InvalidPacket)
+        );
+
+        let other = response(&request, |packet| {
+            packet.set_origin_timestamp(NtpTimestamp::from_fixed_int(1));
+        });
+        assert_eq!(
+            query(&request, &other, send_time, recv_time),
+            Err(QueryError::UnexpectedResponse)
+        );
+
+        let broadcast = response(&request, |packet| {
+            packet.set_mode(NtpAssociationMode::Broadcast);
+        });
+        assert_eq!(
+            query(&request, &broadcast, send_time, recv_time),
+            Err(QueryError::UnexpectedResponse)
+        );
+
+        let kiss = response(&request, |packet| {
+            packet.set_stratum(0);
+            packet.set_reference_id(ReferenceId::KISS_RATE);
+        });
+        assert_eq!(
+            query(&request, &kiss, send_time, recv_time),
+            Err(QueryError::KissOfDeath("RATE".into()))
+        );
+
+        let unsynchronized = response(&request, |packet| {
+            packet.set_leap(NtpLeapIndicator::Unknown);
+        });
+        assert_eq!(
+            query(&request, &unsynchronized, send_time, recv_time),
+            Err(QueryError::Unsynchronized)
+        );
+    }
+}
@@ -3,35 +3,50 @@ use crate::{
     algorithm::Measurement,
     packet::{
         ExtensionField, NtpHeader,
-        v5::server_reference_id::{BloomFilter, RemoteBloomFilter},
+        v5::{
+            extension_fields::MonotonicReceiveTimestamp,
+            server_reference_id::{BloomFilter, RemoteBloomFilter},
+        },
     },
     system::NtpSourceInfo,
     v5::ServerId,
 };
 use crate::{
-    algorithm::{ObservableSourceTimedata, SourceController},
-    config::SourceConfig,
+    algorithm::{ObservableMeasurement, ObservableSourceTimedata, SourceController},
+    config::{InitialBurst, SourceConfig},
     cookiestash::CookieStash,
     identifiers::ReferenceId,
-    packet::{Cipher, NtpAssociationMode, NtpPacket, RequestIdentifier},
-    time_types::{NtpTimestamp, PollInterval},
+    nts::AeadAlgorithm,
+    packet::{
+        Cipher, NtpAssociationMode, NtpPacket, PacketParsingError, RequestIdentifier, SymmetricKey,
+    },
+    time_types::{NtpDuration, NtpTimestamp, PollInterval},
 };
 use rand::{Rng, thread_rng};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     fmt::Debug,
     io::Cursor,
     net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
     sync::{Arc, Mutex, RwLock},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use tracing::{debug, trace, warn};
+use tracing::{debug, info, trace, warn};
 
 const MAX_STRATUM: u8 = 16;
 const POLL_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
 const STARTUP_TRIES_THRESHOLD: usize = 3;
 const AFTER_UPGRADE_TRIES_THRESHOLD: u32 = 2;
+// Number of basic responses to interleaved requests after which we conclude
+// the server does not support interleaved mode
+const INTERLEAVED_TRIES_THRESHOLD: u8 = 8;
+// Differences between the monotonic and regular receive timestamps of a
+// server below this are attributed to the server's clocks running at slightly
+// different rates, not to a step
+const REMOTE_STEP_THRESHOLD: NtpDuration = NtpDuration::from_exponent(-10);
 
 pub struct SourceNtsData {
     pub(crate) cookies: CookieStash,
@@ -40,6 +55,7 @@ pub struct SourceNtsData {
     // in the key information they need to keep.
     pub(crate) c2s: Box<dyn Cipher>,
     pub(crate) s2c: Box<dyn Cipher>,
+    pub(crate) key_exchange: ObservableKeyExchange,
 }
 
 #[cfg(any(test, feature = "__internal-test"))]
@@ -57,13 +73,25 @@ impl std::fmt::Debug for SourceNtsData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SourceNtsData")
             .field("cookies", &self.cookies)
+            .field("key_exchange", &self.key_exchange)
             .finish()
     }
 }
 
+/// Timestamps of a single exchange with the server
+#[derive(Debug, Clone, Copy)]
+struct Exchange {
+    local_send: NtpTimestamp,
+    remote_receive: NtpTimestamp,
+    local_receive: NtpTimestamp,
+}
+
 #[derive(Debug)]
 pub struct NtpSource<Controller: SourceController> {
     nts: Option<Box<SourceNtsData>>,
+    // Pre-shared key authenticating our exchanges with the source, unused
+    // when NTS is active
+    symmetric_key: Option<SymmetricKey>,
 
     // Poll interval used when sending last poll message.
     last_poll_interval: PollInterval,
@@ -79,6 +107,16 @@ pub struct NtpSource<Controller: SourceController> {
     // Whether we have seen a DENY/RSTR KISS response since the last succesfull
     // interaction
     have_deny_rstr_response: bool,
+    // Code of the most recent kiss-o'-death packet, for observability
+    last_kiss_code: Option<String>,
+
+    // Responses that failed NTS or MAC authentication, in total and since
+    // the last valid response
+    authentication_failures: u64,
+    consecutive_authentication_failures: u32,
+
+    // Security relevant events not yet taken by the daemon
+    security_events: Vec<SourceSecurityEvent>,
 
     stratum: u8,
     reference_id: ReferenceId,
@@ -88,6 +126,19 @@ pub struct NtpSource<Controller: SourceController> {
     reach: Reach,
     tries: usize,
 
+    // Number of polls of the initial burst that still need to be sent
+    burst_remaining: u32,
+
+    // Last completed exchange, which the next request can ask the server to
+    // complete in interleaved mode.
+    last_exchange: Option<Exchange>,
+    // Exchange completed by a response in interleaved mode to the current
+    // request, if one was requested.
+    interleaved_exchange: Option<Exchange>,
+    // Basic responses to interleaved requests we still accept before falling
+    // back to basic mode for good.
+    interleaved_tries_left: u8,
+
     controller: Controller,
 
     source_config: SourceConfig,
@@ -99,6 +150,10 @@ pub struct NtpSource<Controller: SourceController> {
     // TODO we only need this if we run as a server
     bloom_filter: RemoteBloomFilter,
 
+    // Monotonic and regular receive timestamp of the last response, used to
+    // detect steps of the server clock
+    last_monotonic_receive: Option<(MonotonicReceiveTimestamp, NtpTimestamp)>,
+
     id: ClockId,
 
     source_info: Arc<RwLock<NtpSourceInfo>>,
@@ -120,6 +175,11 @@ impl<Controller: SourceController> OneWaySource<Controller> {
         self.controller.handle_measurement(measurement);
     }
 
+    /// The measurement handled last, unless it was taken already
+    pub fn take_latest_measurement(&mut self) -> Option<ObservableMeasurement> {
+        self.controller.take_latest_measurement()
+    }
+
     pub fn observe(&self, name: String, address: String, id: ClockId) -> ObservableSourceState {
         ObservableSourceState {
             timedata: self.controller.observe(),
@@ -129,6 +189,20 @@ impl<Controller: SourceController> OneWaySource<Controller> {
             name,
             address,
             id,
+            // One way sources can not be weighted
+            weight: crate::config::default_weight(),
+            prefer: false,
+            noselect: false,
+            ntp_version: None,
+            stratum: None,
+            reference_id: None,
+            reach: None,
+            target_poll_interval: None,
+            last_kiss_code: None,
+            measurements: self.controller.measurement_history(),
+            nts_key_exchange: None,
+            authentication_failures: 0,
+            nts_degraded: false,
         }
     }
 }
@@ -140,7 +214,8 @@ impl<Controller: SourceController> OneWaySource<Controller> {
 /// As valid packets arrive, the rightmost bit is set to one.
 /// If the register contains any nonzero bits, the server is considered reachable;
 /// otherwise, it is unreachable.
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Reach(u8);
 
 impl std::fmt::Debug for Reach {
@@ -182,6 +257,11 @@ impl Reach {
     pub fn unanswered_polls(&self) -> u32 {
         self.0.trailing_zeros()
     }
+
+    /// The raw register, the lowest bit corresponds to the most recent poll
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -212,6 +292,7 @@ impl NtpSourceSnapshot {
     pub fn accept_synchronization(
         &self,
         local_stratum: u8,
+        accepted_strata: RangeInclusive<u8>,
         local_ips: &[IpAddr],
         server_id: ServerId,
     ) -> Result<(), AcceptSynchronizationError> {
@@ -226,6 +307,16 @@ impl NtpSourceSnapshot {
             return Err(Stratum);
         }
 
+        if !accepted_strata.contains(&self.stratum) {
+            debug!(
+                source_stratum = self.stratum,
+                minimum_stratum = accepted_strata.start(),
+                maximum_stratum = accepted_strata.end(),
+                "Source rejected because its stratum is outside of the configured range",
+            );
+            return Err(Stratum);
+        }
+
         // Detect whether the remote uses us as their main time reference.
         // if so, we shouldn't sync to them as that would create a loop.
         // Note, this can only ever be an issue if the source is not using
@@ -266,7 +357,7 @@ impl NtpSourceSnapshot {
             reach: source.reach,
             poll_interval: source.last_poll_interval,
             protocol_version: source.protocol_version,
-            bloom_filter: source.bloom_filter.full_filter().copied(),
+            bloom_filter: source.bloom_filter.known_filter().copied(),
         }
     }
 }
@@ -323,6 +414,15 @@ impl ProtocolVersion {
         }
     }
 
+    /// Version of the NTP packets currently exchanged with the source
+    pub fn current_version(&self) -> NtpVersion {
+        match self {
+            // Upgrade requests are regular NTPv4 packets
+            ProtocolVersion::V4 | ProtocolVersion::V4UpgradingToV5 { .. } => NtpVersion::V4,
+            ProtocolVersion::UpgradedToV5 | ProtocolVersion::V5 => NtpVersion::V5,
+        }
+    }
+
     pub fn v4_upgrading_to_v5_with_default_tries() -> ProtocolVersion {
         ProtocolVersion::V4UpgradingToV5 {
             tries_left: Self::DEFAULT_UPGRADE_TRIES,
@@ -342,6 +442,18 @@ pub enum NtpSourceAction {
     Demobilize,
 }
 
+/// Security relevant events of a source, for the audit log of the daemon
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceSecurityEvent {
+    /// A response failed NTS or MAC authentication
+    AuthenticationFailure,
+    /// The source sent a kiss-o'-death packet
+    KissOfDeath { code: String },
+}
+
+// Events are dropped beyond this many, for when they are never taken
+const MAX_SECURITY_EVENTS: usize = 64;
+
 #[derive(Debug, Default)]
 pub struct NtpSourceActionIterator {
     iter: <Vec<NtpSourceAction> as IntoIterator>::IntoIter,
@@ -371,9 +483,28 @@ macro_rules! actions {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// The key exchange an NTS source got its keys and first cookies from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObservableKeyExchange {
+    /// Name of the key exchange server
+    pub server: String,
+    pub time: SystemTime,
+    /// Algorithm protecting the NTP packets
+    pub algorithm: AeadAlgorithm,
+    /// End of the validity period of the certificate of the key exchange
+    /// server, if it could be read
+    pub certificate_expiry: Option<SystemTime>,
+    /// Whether an earlier TLS session was resumed instead of doing a full
+    /// handshake
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub resumed: bool,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ObservableSourceState {
-    #[serde(flatten)]
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub timedata: ObservableSourceTimedata,
     pub unanswered_polls: u32,
     pub poll_interval: PollInterval,
@@ -381,6 +512,72 @@ pub struct ObservableSourceState {
     pub name: String,
     pub address: String,
     pub id: ClockId,
+    /// Configured weight of the source in the combined estimate
+    #[cfg_attr(feature = "serde", serde(default = "crate::config::default_weight"))]
+    pub weight: f64,
+    /// Whether the source is configured as preferred
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefer: bool,
+    /// Whether the source is only monitored, and never used for
+    /// synchronization
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub noselect: bool,
+    /// NTP version currently used with the source (absent for one way sources)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub ntp_version: Option<u8>,
+    /// Stratum reported by the source (absent for one way sources)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub stratum: Option<u8>,
+    /// Reference id reported by the source (absent for one way sources)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub reference_id: Option<ReferenceId>,
+    /// Reachability register of the source (absent for one way sources)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub reach: Option<u8>,
+    /// Poll interval the next request will be sent with, which differs from
+    /// `poll_interval` while the interval is being adjusted
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub target_poll_interval: Option<PollInterval>,
+    /// Code of the last kiss-o'-death packet received from the source
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub last_kiss_code: Option<String>,
+    /// Most recent raw measurements of the source, oldest first
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub measurements: Vec<ObservableMeasurement>,
+    /// Key exchange of NTS sources
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub nts_key_exchange: Option<ObservableKeyExchange>,
+    /// Number of responses that failed NTS or MAC authentication
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub authentication_failures: u64,
+    /// Whether the source stands in, without authentication, for an NTS
+    /// source whose key exchange failed
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub nts_degraded: bool,
 }
 
 impl<Controller: SourceController> NtpSource<Controller> {
@@ -394,18 +591,67 @@ impl<Controller: SourceController> NtpSource<Controller> {
         protocol_version: ProtocolVersion,
         controller: Controller,
         nts: Option<Box<SourceNtsData>>,
+        symmetric_key: Option<SymmetricKey>,
         id: ClockId,
         source_info: Arc<RwLock<NtpSourceInfo>>,
         source_snapshots: Arc<Mutex<HashMap<ClockId, NtpSourceSnapshot>>>,
     ) -> (Self, NtpSourceActionIterator) {
+        // Keep respecting rate limits the server imposed on a previous
+        // instance of this source
+        let rate_limit = source_info
+            .read()
+            .unwrap()
+            .rate_limits
+            .get(&source_addr.ip())
+            .copied();
+        let remote_min_poll_interval = rate_limit
+            .map_or(source_config.poll_interval_limits.min, |interval| {
+                interval.max(source_config.poll_interval_limits.min)
+            });
+
+        // A server that asked us to poll less often won't appreciate a burst
+        let burst_remaining = if rate_limit.is_some() {
+            debug!("Source imposed a rate limit before, skipping initial burst");
+            0
+        } else {
+            source_config.initial_burst.count
+        };
+
+        // Don't waste polls on upgrade requests to a server that already
+        // ignored them before
+        let v5_unsupported = source_info
+            .read()
+            .unwrap()
+            .v5_unsupported
+            .contains(&source_addr.ip());
+        let protocol_version = match protocol_version {
+            ProtocolVersion::V4UpgradingToV5 { .. } if v5_unsupported => {
+                debug!("Source did not support NTPv5 before, using NTPv4");
+                ProtocolVersion::V4
+            }
+            protocol_version => protocol_version,
+        };
+
+        // Fetch the parts of the remote filter that could contain our own id
+        // first, so loops are detected without waiting for the full filter
+        let mut bloom_filter = RemoteBloomFilter::new(16).expect("16 is a valid chunk size");
+        bloom_filter.prioritize(&source_info.read().unwrap().server_id);
+
         (
             Self {
                 nts,
+                symmetric_key,
 
                 last_poll_interval: source_config.poll_interval_limits.min,
-                remote_min_poll_interval: source_config.poll_interval_limits.min,
+                remote_min_poll_interval,
 
                 have_deny_rstr_response: false,
+                last_kiss_code: None,
+
+                authentication_failures: 0,
+                consecutive_authentication_failures: 0,
+
+                security_events: vec![],
 
                 current_request_identifier: None,
                 source_id: ReferenceId::from_ip(source_addr.ip()),
@@ -413,6 +659,12 @@ impl<Controller: SourceController> NtpSource<Controller> {
                 reach: Reach::never(),
                 tries: 0,
 
+                burst_remaining,
+
+                last_exchange: None,
+                interleaved_exchange: None,
+                interleaved_tries_left: INTERLEAVED_TRIES_THRESHOLD,
+
                 stratum: 16,
                 reference_id: ReferenceId::NONE,
 
@@ -423,7 +675,9 @@ impl<Controller: SourceController> NtpSource<Controller> {
 
                 protocol_version, // TODO make this configurable
 
-                bloom_filter: RemoteBloomFilter::new(16).expect("16 is a valid chunk size"),
+                bloom_filter,
+
+                last_monotonic_receive: None,
 
                 id,
 
@@ -435,6 +689,24 @@ impl<Controller: SourceController> NtpSource<Controller> {
         )
     }
 
+    /// Whether the clock controller may use this source, given its state in
+    /// `snapshot`
+    fn usable(&self, snapshot: &NtpSourceSnapshot) -> bool {
+        if self.source_config.noselect {
+            return false;
+        }
+
+        let source_info = self.source_info.read().unwrap();
+        snapshot
+            .accept_synchronization(
+                source_info.local_stratum,
+                source_info.minimum_source_stratum..=source_info.maximum_source_stratum,
+                &source_info.ip_list,
+                source_info.server_id,
+            )
+            .is_ok()
+    }
+
     pub fn observe(&self, name: String, id: ClockId) -> ObservableSourceState {
         ObservableSourceState {
             timedata: self.controller.observe(),
@@ -444,6 +716,19 @@ impl<Controller: SourceController> NtpSource<Controller> {
             name,
             address: self.source_addr.to_string(),
             id,
+            weight: self.source_config.weight,
+            prefer: self.source_config.prefer,
+            noselect: self.source_config.noselect,
+            ntp_version: Some(self.protocol_version.current_version().as_u8()),
+            stratum: Some(self.stratum),
+            reference_id: Some(self.reference_id),
+            reach: Some(self.reach.bits()),
+            target_poll_interval: Some(self.current_poll_interval()),
+            last_kiss_code: self.last_kiss_code.clone(),
+            measurements: self.controller.measurement_history(),
+            nts_key_exchange: self.nts.as_ref().map(|nts| nts.key_exchange.clone()),
+            authentication_failures: self.authentication_failures,
+            nts_degraded: false,
         }
     }
 
@@ -453,6 +738,29 @@ impl<Controller: SourceController> NtpSource<Controller> {
             .max(self.remote_min_poll_interval)
     }
 
+    /// Poll message for a source without NTS, which is possibly interleaved
+    fn unauthenticated_poll_message(
+        &self,
+        poll_interval: PollInterval,
+    ) -> (NtpPacket<'static>, RequestIdentifier) {
+        match self.protocol_version {
+            ProtocolVersion::V4 => match self.interleaved_exchange {
+                Some(exchange) => NtpPacket::poll_message_interleaved(
+                    poll_interval,
+                    exchange.remote_receive,
+                    exchange.local_receive,
+                ),
+                None => NtpPacket::poll_message(poll_interval),
+            },
+            ProtocolVersion::V4UpgradingToV5 { .. } => {
+                NtpPacket::poll_message_upgrade_request(poll_interval)
+            }
+            ProtocolVersion::UpgradedToV5 | ProtocolVersion::V5 => {
+                NtpPacket::poll_message_v5(poll_interval)
+            }
+        }
+    }
+
     pub fn handle_timer(&mut self) -> NtpSourceActionIterator {
         if !self.reach.is_reachable() && self.tries >= STARTUP_TRIES_THRESHOLD {
             return if self.have_deny_rstr_response {
@@ -469,16 +777,38 @@ impl<Controller: SourceController> NtpSource<Controller> {
         {
             // For some reason V5 communication isn't working, even though we and the server support it. Fall back.
             self.protocol_version = ProtocolVersion::V4;
+            self.remember_v5_unsupported();
         }
 
         self.reach.poll();
-        self.tries = self.tries.saturating_add(1);
+        if self.burst_remaining > 0 {
+            // Polls of the burst are sent in quick succession, they should not
+            // cause an unresponsive source to be given up on sooner.
+            self.burst_remaining -= 1;
+        } else {
+            self.tries = self.tries.saturating_add(1);
+        }
 
         let poll_interval = self.current_poll_interval();
+
+        // Interleaved mode is only available for unauthenticated NTPv4, and
+        // needs the timestamps of an exchange that directly preceded this one.
+        let last_exchange = self.last_exchange.take();
+        self.interleaved_exchange = last_exchange.filter(|_| {
+            self.source_config.interleaved
+                && self.interleaved_tries_left > 0
+                && self.nts.is_none()
+                && self.symmetric_key.is_none()
+                && matches!(self.protocol_version, ProtocolVersion::V4)
+        });
+
+        // Declared out here as the poll message borrows the cookie
+        let cookie;
         let (mut packet, identifier) = match &mut self.nts {
             Some(nts) => {
-                let Some(cookie) = nts.cookies.get() else {
-                    return actions!(NtpSourceAction::Reset);
+                cookie = match nts.cookies.get() {
+                    Some(cookie) => cookie,
+                    None => return actions!(NtpSourceAction::Reset),
                 };
                 // Do ensure we don't exceed the buffer size
                 // when requesting new cookies. We keep 350
@@ -505,15 +835,7 @@ impl<Controller: SourceController> NtpSource<Controller> {
                     }
                 }
             }
-            None => match self.protocol_version {
-                ProtocolVersion::V4 => NtpPacket::poll_message(poll_interval),
-                ProtocolVersion::V4UpgradingToV5 { .. } => {
-                    NtpPacket::poll_message_upgrade_request(poll_interval)
-                }
-                ProtocolVersion::UpgradedToV5 | ProtocolVersion::V5 => {
-                    NtpPacket::poll_message_v5(poll_interval)
-                }
-            },
+            None => self.unauthenticated_poll_message(poll_interval),
         };
         self.current_request_identifier =
             Some((identifier, tokio::time::Instant::now() + POLL_WINDOW));
@@ -521,35 +843,35 @@ impl<Controller: SourceController> NtpSource<Controller> {
         if let NtpHeader::V5(header) = packet.header() {
             let req_ef = self.bloom_filter.next_request(header.client_cookie);
             packet.push_additional(ExtensionField::ReferenceIdRequest(req_ef));
+            packet.push_additional(ExtensionField::MonotonicReceiveTimestamp(
+                MonotonicReceiveTimestamp::default(),
+            ));
         }
 
         // update the poll interval
         self.last_poll_interval = poll_interval;
 
         let snapshot = NtpSourceSnapshot::from_source(self);
+        let usable = self.usable(&snapshot);
+
+        let timeout = if self.burst_remaining > 0 {
+            self.burst_spacing()
+        } else {
+            Self::poll_timeout(poll_interval)
+        };
 
         // Write packet to buffer
         let mut cursor: Cursor<&mut [u8]> = Cursor::new(&mut self.buffer);
-        packet
-            .serialize(
-                &mut cursor,
-                &self.nts.as_ref().map(|nts| nts.c2s.as_ref()),
-                None,
-            )
-            .expect("Internal error: could not serialize packet");
+        match (&self.nts, &self.symmetric_key) {
+            (None, Some(key)) => packet.serialize_with_mac(&mut cursor, key, None),
+            (nts, _) => {
+                packet.serialize(&mut cursor, &nts.as_ref().map(|nts| nts.c2s.as_ref()), None)
+            }
+        }
+        .expect("Internal error: could not serialize packet");
         let used = cursor.position();
         let result = &cursor.into_inner()[..used as usize];
 
-        let usable = {
-            let source_info = self.source_info.read().unwrap();
-            snapshot
-                .accept_synchronization(
-                    source_info.local_stratum,
-                    &source_info.ip_list,
-                    source_info.server_id,
-                )
-                .is_ok()
-        };
         self.source_snapshots
             .lock()
             .unwrap()
@@ -558,24 +880,184 @@ impl<Controller: SourceController> NtpSource<Controller> {
 
         actions!(
             NtpSourceAction::Send(result.into()),
-            // randomize the poll interval a little to make it harder to predict poll requests
-            NtpSourceAction::SetTimer(
-                poll_interval
-                    .as_system_duration()
-                    .mul_f64(thread_rng().gen_range(1.01..=1.05))
-            )
+            NtpSourceAction::SetTimer(timeout)
         )
     }
 
+    fn burst_spacing(&self) -> Duration {
+        Duration::from_secs_f64(self.source_config.initial_burst.spacing.to_seconds())
+    }
+
+    fn poll_timeout(poll_interval: PollInterval) -> Duration {
+        // randomize the poll interval a little to make it harder to predict poll requests
+        poll_interval
+            .as_system_duration()
+            .mul_f64(thread_rng().gen_range(1.01..=1.05))
+    }
+
+    fn remember_v5_unsupported(&self) {
+        self.source_info
+            .write()
+            .unwrap()
+            .v5_unsupported
+            .insert(self.source_addr.ip());
+    }
+
+    fn record_kiss(&mut self, code: ReferenceId) {
+        let code = code
+            .as_code()
+            .unwrap_or_else(|| format!("{:08x}", u32::from_be_bytes(code.to_bytes())));
+        self.record_security_event(SourceSecurityEvent::KissOfDeath { code: code.clone() });
+        self.last_kiss_code = Some(code);
+    }
+
+    fn record_security_event(&mut self, event: SourceSecurityEvent) {
+        if self.security_events.len() < MAX_SECURITY_EVENTS {
+            self.security_events.push(event);
+        }
+    }
+
+    /// Security relevant events since the previous call, oldest first
+    pub fn take_security_events(&mut self) -> Vec<SourceSecurityEvent> {
+        std::mem::take(&mut self.security_events)
+    }
+
+    /// The measurement handled last, unless it was taken already
+    pub fn take_latest_measurement(&mut self) -> Option<ObservableMeasurement> {
+        self.controller.take_latest_measurement()
+    }
+
+    /// Count a response that failed authentication, and give up on the source
+    /// when too many of them arrive in a row. Forged responses are dropped
+    /// either way, but a steady stream of them can point at an attack.
+    fn record_authentication_failure(&mut self) -> NtpSourceActionIterator {
+        self.record_security_event(SourceSecurityEvent::AuthenticationFailure);
+        self.authentication_failures += 1;
+        self.consecutive_authentication_failures =
+            self.consecutive_authentication_failures.saturating_add(1);
+
+        let limit = self.source_config.max_authentication_failures;
+        if limit > 0 && self.consecutive_authentication_failures >= limit {
+            warn!(
+                failures = self.consecutive_authentication_failures,
+                "Too many responses failed authentication, demobilizing source"
+            );
+            actions!(NtpSourceAction::Demobilize)
+        } else {
+            actions!()
+        }
+    }
+
+    /// Poll the source in a burst right away, as on startup, for example to
+    /// speed up convergence after a known disruption. Sources that asked us
+    /// to poll less often are left alone. Uses the configured burst, or the
+    /// default one when the initial burst is disabled.
+    pub fn start_burst(&mut self) -> NtpSourceActionIterator {
+        if self.have_deny_rstr_response
+            || self.remote_min_poll_interval > self.source_config.poll_interval_limits.min
+        {
+            info!("Source imposed a rate limit, not starting a burst");
+            return actions!();
+        }
+
+        if self.source_config.initial_burst.count == 0 {
+            self.source_config.initial_burst = InitialBurst::default();
+        }
+        debug!(
+            count = self.source_config.initial_burst.count,
+            "Starting burst"
+        );
+        // The timer fires immediately, which sends the first poll of the burst
+        self.burst_remaining = self.source_config.initial_burst.count;
+        actions!(NtpSourceAction::SetTimer(Duration::ZERO))
+    }
+
+    /// Continue with the server at `source_addr`, after the name of the
+    /// source started resolving to it. The controller keeps its state, but
+    /// anything learned about the previous server is forgotten.
+    pub fn change_address(&mut self, source_addr: SocketAddr) {
+        info!(old = %self.source_addr, new = %source_addr, "Source moved to a new address");
+
+        let source_info = self.source_info.read().unwrap();
+        self.remote_min_poll_interval = source_info
+            .rate_limits
+            .get(&source_addr.ip())
+            .map_or(self.source_config.poll_interval_limits.min, |interval| {
+                (*interval).max(self.source_config.poll_interval_limits.min)
+            });
+        self.bloom_filter = RemoteBloomFilter::new(16).expect("16 is a valid chunk size");
+        self.bloom_filter.prioritize(&source_info.server_id);
+        drop(source_info);
+
+        self.source_addr = source_addr;
+        self.source_id = ReferenceId::from_ip(source_addr.ip());
+        self.stratum = 16;
+        self.reference_id = ReferenceId::NONE;
+        self.have_deny_rstr_response = false;
+        self.last_kiss_code = None;
+
+        // Responses to requests sent to the previous server are of no use
+        self.current_request_identifier = None;
+        self.last_exchange = None;
+        self.interleaved_exchange = None;
+        self.interleaved_tries_left = INTERLEAVED_TRIES_THRESHOLD;
+        self.last_monotonic_receive = None;
+    }
+
+    /// Abort the initial burst, if any, after the source indicated it does not
+    /// want to be polled this often. Returns the actions needed to fall back
+    /// to the regular poll schedule.
+    fn stop_burst(&mut self) -> NtpSourceActionIterator {
+        if self.burst_remaining == 0 {
+            return actions!();
+        }
+
+        debug!(
+            burst_remaining = self.burst_remaining,
+            "Stopping initial burst"
+        );
+        self.burst_remaining = 0;
+        let timeout = Self::poll_timeout(self.current_poll_interval());
+        actions!(NtpSourceAction::SetTimer(timeout))
+    }
+
+    fn handle_kiss_rate(&mut self) -> NtpSourceActionIterator {
+        self.record_kiss(ReferenceId::KISS_RATE);
+        // KISS packets may not have correct timestamps at all, handle them anyway
+        let current = Ord::max(self.remote_min_poll_interval, self.last_poll_interval);
+        self.remote_min_poll_interval = (0..self.source_config.rate_kod_backoff.max(1).ilog2())
+            .fold(current, |interval, _| {
+                interval.inc(self.source_config.poll_interval_limits)
+            });
+        self.source_info
+            .write()
+            .unwrap()
+            .rate_limits
+            .insert(self.source_addr.ip(), self.remote_min_poll_interval);
+        warn!(?self.remote_min_poll_interval, "Source requested rate limit");
+        self.stop_burst()
+    }
+
     pub fn handle_incoming(
         &mut self,
         message: &[u8],
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
     ) -> NtpSourceActionIterator {
+        if let (None, Some(key)) = (&self.nts, &self.symmetric_key)
+            && !key.verify(message)
+        {
+            warn!("received packet without a valid MAC");
+            return self.record_authentication_failure();
+        }
+
         let message =
             match NtpPacket::deserialize(message, &self.nts.as_ref().map(|nts| nts.s2c.as_ref())) {
                 Ok((packet, _)) => packet,
+                Err(PacketParsingError::DecryptError(_)) => {
+                    warn!("received packet that failed NTS authentication");
+                    return self.record_authentication_failure();
+                }
                 Err(e) => {
                     warn!("received invalid packet: {}", e);
                     return actions!();
@@ -613,6 +1095,7 @@ impl<Controller: SourceController> NtpSource<Controller> {
                 } else if tries_left == 0 {
                     debug!("Server does not support NTPv5, stopping the upgrade process");
                     self.protocol_version = ProtocolVersion::V4;
+                    self.remember_v5_unsupported();
                 } else {
                     debug!(tries_left, "Server did not yet respond with upgrade code");
                     self.protocol_version = ProtocolVersion::V4UpgradingToV5 { tries_left };
@@ -632,30 +1115,27 @@ impl<Controller: SourceController> NtpSource<Controller> {
             debug!("Received old/unexpected packet from source");
             actions!()
         } else if message.is_kiss_rate(self.last_poll_interval) {
-            // KISS packets may not have correct timestamps at all, handle them anyway
-            self.remote_min_poll_interval = Ord::max(
-                self.remote_min_poll_interval
-                    .inc(self.source_config.poll_interval_limits),
-                self.last_poll_interval,
-            );
-            warn!(?self.remote_min_poll_interval, "Source requested rate limit");
-            actions!()
+            // NTPv5 signals rate limiting through the poll field instead
+            self.handle_kiss_rate()
         } else if message.is_kiss_rstr() || message.is_kiss_deny() {
+            self.record_kiss(message.kiss_code());
             warn!("Source denied service");
             // Handle the kiss if it was signed, otherwise ignore it
-            if self.nts.is_some() {
+            if self.nts.is_some() || self.symmetric_key.is_some() {
                 actions!(NtpSourceAction::Demobilize)
             } else {
                 // Not signed, so easily faked, but do register it for future reference
                 self.have_deny_rstr_response = true;
-                actions!()
+                self.stop_burst()
             }
         } else if message.is_kiss_ntsn() {
+            self.record_kiss(message.kiss_code());
             warn!("Received nts not-acknowledge");
             // as these can be easily faked, we dont immediately give up on receiving
             // a response.
             actions!()
         } else if message.is_kiss() {
+            self.record_kiss(message.kiss_code());
             warn!("Unrecognized KISS Message from source");
             // Ignore unrecognized control messages
             actions!()
@@ -671,13 +1151,14 @@ impl<Controller: SourceController> NtpSource<Controller> {
             warn!("Received packet with invalid mode");
             actions!()
         } else {
-            self.process_message(&message, send_time, recv_time)
+            self.process_message(&message, request_identifier, send_time, recv_time)
         }
     }
 
     fn process_message(
         &mut self,
         message: &NtpPacket,
+        request_identifier: RequestIdentifier,
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
     ) -> NtpSourceActionIterator {
@@ -687,6 +1168,7 @@ impl<Controller: SourceController> NtpSource<Controller> {
 
         // Clear received deny/rstr kod
         self.have_deny_rstr_response = false;
+        self.consecutive_authentication_failures = 0;
 
         // we received this packet, and don't want to accept future ones with this next_expected_origin
         self.current_request_identifier = None;
@@ -734,27 +1216,48 @@ impl<Controller: SourceController> NtpSource<Controller> {
                     warn!(?err, "Invalid ReferenceIdResponse from source, ignoring...");
                 }
             }
+
+            self.detect_remote_step(message);
         }
 
         let snapshot = NtpSourceSnapshot::from_source(self);
-        let usable = {
-            let source_info = self.source_info.read().unwrap();
-            snapshot
-                .accept_synchronization(
-                    source_info.local_stratum,
-                    &source_info.ip_list,
-                    source_info.server_id,
-                )
-                .is_ok()
-        };
+        let usable = self.usable(&snapshot);
         self.source_snapshots
             .lock()
             .unwrap()
             .insert(self.id, snapshot);
         self.controller.set_usable(usable);
 
-        let (measurement_outgoing, measurement_incoming) =
-            measurements_from_packet(message, self.id, send_time, recv_time);
+        let exchange = Exchange {
+            local_send: send_time,
+            remote_receive: message.receive_timestamp(),
+            local_receive: recv_time,
+        };
+        self.last_exchange = Some(exchange);
+
+        // In interleaved mode, the transmit timestamp of the response belongs
+        // to the previous exchange.
+        let measured_exchange = match self.interleaved_exchange.take() {
+            Some(previous) if message.is_interleaved_response(request_identifier) => {
+                self.interleaved_tries_left = INTERLEAVED_TRIES_THRESHOLD;
+                previous
+            }
+            Some(_) => {
+                self.interleaved_tries_left = self.interleaved_tries_left.saturating_sub(1);
+                if self.interleaved_tries_left == 0 {
+                    debug!("Server does not support interleaved mode, using basic mode");
+                }
+                exchange
+            }
+            None => exchange,
+        };
+
+        let (measurement_outgoing, measurement_incoming) = measurements_from_packet(
+            message,
+            self.id,
+            measured_exchange,
+            message.transmit_timestamp(),
+        );
         self.controller.handle_measurement(measurement_outgoing);
         self.controller.handle_measurement(measurement_incoming);
 
@@ -768,12 +1271,49 @@ impl<Controller: SourceController> NtpSource<Controller> {
         actions!()
     }
 
+    /// Compare the monotonic receive timestamp in the response with that of
+    /// the previous response to tell the controller about steps of the
+    /// server clock, which would otherwise look like a sudden offset change.
+    fn detect_remote_step(&mut self, message: &NtpPacket) {
+        let find = |ef: &ExtensionField| match ef {
+            ExtensionField::MonotonicReceiveTimestamp(mono) => Some(*mono),
+            _ => None,
+        };
+        let monotonic = if self.nts.is_some() {
+            message.authenticated_extension_fields().find_map(find)
+        } else {
+            message.untrusted_extension_fields().find_map(find)
+        };
+
+        let Some(monotonic) = monotonic else {
+            self.last_monotonic_receive = None;
+            return;
+        };
+        let receive = message.receive_timestamp();
+
+        if let Some((last_monotonic, last_receive)) = self.last_monotonic_receive
+            && last_monotonic.epoch == monotonic.epoch
+        {
+            let step = (receive - last_receive) - (monotonic.timestamp - last_monotonic.timestamp);
+            if step.abs() > REMOTE_STEP_THRESHOLD {
+                debug!(
+                    step = step.to_seconds(),
+                    "Source stepped its clock since the last measurement"
+                );
+                self.controller.handle_remote_step(step);
+            }
+        }
+
+        self.last_monotonic_receive = Some((monotonic, receive));
+    }
+
     #[cfg(test)]
     pub(crate) fn test_ntp_source(controller: Controller) -> Self {
         use std::net::Ipv4Addr;
 
         NtpSource {
             nts: None,
+            symmetric_key: None,
 
             last_poll_interval: PollInterval::default(),
             remote_min_poll_interval: PollInterval::default(),
@@ -781,12 +1321,24 @@ impl<Controller: SourceController> NtpSource<Controller> {
             current_request_identifier: None,
 
             have_deny_rstr_response: false,
+            last_kiss_code: None,
+
+            authentication_failures: 0,
+            consecutive_authentication_failures: 0,
+
+            security_events: vec![],
 
             source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
             source_id: ReferenceId::from_int(0),
             reach: Reach::never(),
             tries: 0,
 
+            burst_remaining: 0,
+
+            last_exchange: None,
+            interleaved_exchange: None,
+            interleaved_tries_left: INTERLEAVED_TRIES_THRESHOLD,
+
             stratum: 0,
             reference_id: ReferenceId::from_int(0),
 
@@ -799,6 +1351,8 @@ impl<Controller: SourceController> NtpSource<Controller> {
 
             bloom_filter: RemoteBloomFilter::new(16).unwrap(),
 
+            last_monotonic_receive: None,
+
             id: ClockId(1),
 
             source_info: Arc::default(),
@@ -811,27 +1365,29 @@ impl<Controller: SourceController> NtpSource<Controller> {
 fn measurements_from_packet(
     message: &NtpPacket,
     id: ClockId,
-    send_time: NtpTimestamp,
-    recv_time: NtpTimestamp,
+    exchange: Exchange,
+    remote_transmit: NtpTimestamp,
 ) -> (Measurement, Measurement) {
     (
         Measurement {
             sender_id: ClockId::SYSTEM,
             receiver_id: id,
-            sender_ts: send_time,
-            receiver_ts: message.receive_timestamp(),
+            sender_ts: exchange.local_send,
+            receiver_ts: exchange.remote_receive,
             root_delay: message.root_delay(),
             root_dispersion: message.root_dispersion(),
+            stratum: message.stratum(),
             leap: message.leap(),
             precision: message.precision(),
         },
         Measurement {
             sender_id: id,
             receiver_id: ClockId::SYSTEM,
-            sender_ts: message.transmit_timestamp(),
-            receiver_ts: recv_time,
+            sender_ts: remote_transmit,
+            receiver_ts: exchange.local_receive,
             root_delay: message.root_delay(),
             root_dispersion: message.root_dispersion(),
+            stratum: message.stratum(),
             leap: message.leap(),
             precision: message.precision(),
         },
@@ -846,8 +1402,8 @@ fn measurements_from_packet(
 mod test {
     use crate::{
         NtpClock, NtpDuration, NtpLeapIndicator, NtpSnapshot,
-        packet::{AesSivCmac256, NoCipher},
-        system::NtpServerInfo,
+        packet::{AesSivCmac256, MacAlgorithm, NoCipher},
+        system::{NtpServerInfo, TimeSnapshot},
         time_types::PollIntervalLimits,
     };
 
@@ -914,7 +1470,7 @@ mod test {
         }
 
         fn observe(&self) -> crate::ObservableSourceTimedata {
-            panic!("Not implemented on noop controller");
+            crate::ObservableSourceTimedata::default()
         }
     }
 
@@ -959,12 +1515,14 @@ mod test {
                 let snapshot = NtpSourceSnapshot::from_source(&source);
                 snapshot.accept_synchronization(
                     16,
+                    1..=15,
                     &["127.0.0.1".parse().unwrap()],
                     ServerId::default(),
                 )
             }};
         }
 
+        source.stratum = 2;
         source.source_id = ReferenceId::from_ip("127.0.0.1".parse().unwrap());
         assert_eq!(accept!(), Err(Loop));
 
@@ -977,6 +1535,34 @@ mod test {
 
         source.stratum = 42;
         assert_eq!(accept!(), Err(Stratum));
+
+        source.stratum = 15;
+        assert_eq!(accept!(), Ok(()));
+
+        // Below the configured minimum, which also rejects unspecified strata
+        source.stratum = 0;
+        assert_eq!(accept!(), Err(Stratum));
+    }
+
+    #[test]
+    fn test_noselect() {
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.source_id = ReferenceId::from_ip("127.0.1.1".parse().unwrap());
+        source.stratum = 2;
+        source.reach.received_packet();
+        *source.source_info.write().unwrap() = NtpSourceInfo {
+            local_stratum: 16,
+            minimum_source_stratum: 1,
+            maximum_source_stratum: 15,
+            ..Default::default()
+        };
+
+        let snapshot = NtpSourceSnapshot::from_source(&source);
+        assert!(source.usable(&snapshot));
+
+        // Monitored sources are never used, even when acceptable
+        source.source_config.noselect = true;
+        assert!(!source.usable(&snapshot));
     }
 
     #[test]
@@ -1025,6 +1611,13 @@ mod test {
             cookies: CookieStash::default(),
             c2s: Box::new(AesSivCmac256::new([0; 32].into())),
             s2c: Box::new(AesSivCmac256::new([0; 32].into())),
+            key_exchange: ObservableKeyExchange {
+                server: "localhost".into(),
+                time: SystemTime::now(),
+                algorithm: AeadAlgorithm::AeadAesSivCmac256,
+                certificate_expiry: None,
+                resumed: false,
+            },
         };
         ntsdata.cookies.store(vec![0; 2048]);
         ntsdata.cookies.store(vec![0; 2048]);
@@ -1081,6 +1674,122 @@ mod test {
         assert!(actions.next().is_none());
     }
 
+    #[test]
+    fn test_symmetric_key_authentication() {
+        let key = SymmetricKey::new(1, MacAlgorithm::Aes128Cmac, &[7; 16]).unwrap();
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.symmetric_key = Some(key.clone());
+        source.protocol_version = ProtocolVersion::V4;
+
+        let mut outgoingbuf = None;
+        for action in source.handle_timer() {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        assert!(key.verify(&outgoingbuf));
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+        assert_eq!(outgoing.mac_key_id(), Some(1));
+
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+
+        // Responses without a MAC are ignored
+        let _ = source.handle_incoming(
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(!source.reach.is_reachable());
+
+        let mut buf = [0; 1024];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        packet.serialize_with_mac(&mut cursor, &key, None).unwrap();
+        let used = cursor.position() as usize;
+        let _ = source.handle_incoming(
+            &buf[..used],
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(source.reach.is_reachable());
+        assert_eq!(source.authentication_failures, 1);
+        assert_eq!(
+            source.take_security_events(),
+            [SourceSecurityEvent::AuthenticationFailure]
+        );
+        assert!(source.take_security_events().is_empty());
+    }
+
+    #[test]
+    fn test_authentication_failure_limit() {
+        let key = SymmetricKey::new(1, MacAlgorithm::Aes128Cmac, &[7; 16]).unwrap();
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.symmetric_key = Some(key.clone());
+        source.protocol_version = ProtocolVersion::V4;
+        source.source_config.max_authentication_failures = 2;
+
+        let mut outgoingbuf = None;
+        for action in source.handle_timer() {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+        let forged = packet.serialize_without_encryption_vec(None).unwrap();
+
+        let mut actions = source.handle_incoming(
+            &forged,
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.next().is_none());
+
+        // A valid response in between resets the count
+        let mut buf = [0; 1024];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        packet.serialize_with_mac(&mut cursor, &key, None).unwrap();
+        let used = cursor.position() as usize;
+        let _ = source.handle_incoming(
+            &buf[..used],
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert_eq!(source.consecutive_authentication_failures, 0);
+
+        let mut actions = source.handle_incoming(
+            &forged,
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.next().is_none());
+        let mut actions = source.handle_incoming(
+            &forged,
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(matches!(actions.next(), Some(NtpSourceAction::Demobilize)));
+        assert_eq!(source.authentication_failures, 3);
+        assert_eq!(
+            source
+                .observe("test".into(), ClockId(1))
+                .authentication_failures,
+            3
+        );
+    }
+
     #[test]
     fn test_startup_unreachable() {
         let mut source = NtpSource::test_ntp_source(NoopController);
@@ -1109,6 +1818,65 @@ mod test {
         assert!(matches!(actions.next(), Some(NtpSourceAction::Reset)));
     }
 
+    #[test]
+    fn test_initial_burst_unreachable() {
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.source_config.initial_burst = crate::config::InitialBurst::default();
+        source.burst_remaining = 4;
+
+        let mut timeouts = vec![];
+        for _ in 0..7 {
+            for action in source.handle_timer() {
+                assert!(!matches!(
+                    action,
+                    NtpSourceAction::Reset | NtpSourceAction::Demobilize
+                ));
+                if let NtpSourceAction::SetTimer(timeout) = action {
+                    timeouts.push(timeout);
+                }
+            }
+        }
+
+        // Burst polls are spaced closely, and do not count as startup tries
+        assert_eq!(timeouts[..3], [Duration::from_secs(2); 3]);
+        for timeout in &timeouts[3..] {
+            assert!(*timeout > source.current_poll_interval().as_system_duration());
+        }
+
+        let mut actions = source.handle_timer();
+        assert!(matches!(actions.next(), Some(NtpSourceAction::Reset)));
+    }
+
+    #[test]
+    fn test_start_burst() {
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.source_config.initial_burst = InitialBurst::DISABLED;
+
+        // A disabled initial burst falls back to the default one
+        let actions: Vec<_> = source.start_burst().collect();
+        assert!(matches!(
+            actions[..],
+            [NtpSourceAction::SetTimer(Duration::ZERO)]
+        ));
+        assert_eq!(source.burst_remaining, InitialBurst::default().count);
+
+        let mut timeouts = vec![];
+        for _ in 0..InitialBurst::default().count {
+            for action in source.handle_timer() {
+                if let NtpSourceAction::SetTimer(timeout) = action {
+                    timeouts.push(timeout);
+                }
+            }
+        }
+        assert_eq!(timeouts[0], Duration::from_secs(2));
+        assert!(*timeouts.last().unwrap() > Duration::from_secs(2));
+
+        // Servers that asked to be polled less often don't get a burst
+        source.remote_min_poll_interval = source.source_config.poll_interval_limits.max;
+        assert!(source.start_burst().next().is_none());
+        assert_eq!(source.burst_remaining, 0);
+    }
+
     #[test]
     fn test_running_unreachable() {
         let mut source = NtpSource::test_ntp_source(NoopController);
@@ -1259,6 +2027,7 @@ mod test {
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(!source.have_deny_rstr_response);
+        assert_eq!(source.last_kiss_code, None);
         assert!(actions.next().is_none());
 
         let mut packet = NtpPacket::test();
@@ -1284,6 +2053,13 @@ mod test {
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(source.have_deny_rstr_response);
+        assert_eq!(source.last_kiss_code.as_deref(), Some("RSTR"));
+        assert_eq!(
+            source.take_security_events(),
+            [SourceSecurityEvent::KissOfDeath {
+                code: "RSTR".into()
+            }]
+        );
         source.have_deny_rstr_response = false;
         assert!(actions.next().is_none());
 
@@ -1321,6 +2097,7 @@ mod test {
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(source.have_deny_rstr_response);
+        assert_eq!(source.last_kiss_code.as_deref(), Some("DENY"));
         source.have_deny_rstr_response = false;
         assert!(actions.next().is_none());
 
@@ -1361,6 +2138,127 @@ mod test {
         );
         assert!(actions.next().is_none());
         assert!(source.remote_min_poll_interval >= old_remote_interval);
+        assert_eq!(source.last_kiss_code.as_deref(), Some("RATE"));
+
+        let observed = source.observe("test".into(), ClockId(1));
+        assert_eq!(observed.last_kiss_code.as_deref(), Some("RATE"));
+        assert_eq!(
+            observed.target_poll_interval,
+            Some(source.current_poll_interval())
+        );
+        assert_eq!(observed.reach, Some(source.reach.bits()));
+    }
+
+    #[test]
+    fn test_rate_kod_backoff() {
+        let source_info = Arc::<RwLock<NtpSourceInfo>>::default();
+        let source_config = SourceConfig {
+            rate_kod_backoff: 8,
+            ..SourceConfig::default()
+        };
+        let min_poll = source_config.poll_interval_limits.min;
+        let new_source = |addr: SocketAddr| {
+            NtpSource::new(
+                addr,
+                source_config,
+                ProtocolVersion::V4,
+                NoopController,
+                None,
+                None,
+                ClockId(1),
+                source_info.clone(),
+                Arc::default(),
+            )
+            .0
+        };
+
+        let addr = SocketAddr::from(([192, 0, 2, 1], 123));
+        let mut source = new_source(addr);
+        assert_eq!(source.remote_min_poll_interval, min_poll);
+        assert_eq!(source.burst_remaining, source_config.initial_burst.count);
+
+        let mut outgoingbuf = None;
+        for action in source.handle_timer() {
+            if let NtpSourceAction::Send(buf) = action {
+                outgoingbuf = Some(buf);
+            }
+        }
+        let outgoingbuf = outgoingbuf.unwrap();
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+
+        let mut packet = NtpPacket::test();
+        packet.set_reference_id(ReferenceId::KISS_RATE);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        packet.set_mode(NtpAssociationMode::Server);
+        let mut actions = source.handle_incoming(
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            NtpTimestamp::from_fixed_int(0),
+            NtpTimestamp::from_fixed_int(100),
+        );
+        // The kiss ends the initial burst
+        assert!(matches!(actions.next(), Some(NtpSourceAction::SetTimer(_))));
+        assert!(actions.next().is_none());
+        assert_eq!(source.burst_remaining, 0);
+        assert_eq!(
+            source.remote_min_poll_interval.as_log(),
+            min_poll.as_log() + 3
+        );
+
+        // A restarted source still respects the backoff
+        let restarted = new_source(addr);
+        assert_eq!(
+            restarted.remote_min_poll_interval,
+            source.remote_min_poll_interval
+        );
+        assert!(restarted.current_poll_interval() >= source.remote_min_poll_interval);
+        // and is not sent a new burst
+        assert_eq!(restarted.burst_remaining, 0);
+
+        // Other servers are not affected
+        let other = new_source(SocketAddr::from(([192, 0, 2, 2], 123)));
+        assert_eq!(other.remote_min_poll_interval, min_poll);
+        assert_eq!(other.burst_remaining, source_config.initial_burst.count);
+    }
+
+    #[test]
+    fn test_change_address() {
+        let source_info = Arc::<RwLock<NtpSourceInfo>>::default();
+        let source_config = SourceConfig::default();
+        let min_poll = source_config.poll_interval_limits.min;
+        let (mut source, _) = NtpSource::new(
+            SocketAddr::from(([192, 0, 2, 1], 123)),
+            source_config,
+            ProtocolVersion::V4,
+            NoopController,
+            None,
+            None,
+            ClockId(1),
+            source_info.clone(),
+            Arc::default(),
+        );
+        source.stratum = 2;
+        let _ = source.handle_timer();
+        assert!(source.current_request_identifier.is_some());
+
+        // The new server asked an earlier instance to slow down
+        let limited = SocketAddr::from(([192, 0, 2, 2], 123));
+        let slow = min_poll.inc(source_config.poll_interval_limits);
+        source_info
+            .write()
+            .unwrap()
+            .rate_limits
+            .insert(limited.ip(), slow);
+
+        source.change_address(limited);
+        assert_eq!(source.source_addr, limited);
+        assert_eq!(source.source_id, ReferenceId::from_ip(limited.ip()));
+        assert_eq!(source.stratum, 16);
+        assert_eq!(source.remote_min_poll_interval, slow);
+        assert!(source.current_request_identifier.is_none());
+
+        // Moving on to an unrestricted server lifts the limit
+        source.change_address(SocketAddr::from(([192, 0, 2, 3], 123)));
+        assert_eq!(source.remote_min_poll_interval, min_poll);
     }
 
     #[test]
@@ -1372,6 +2270,7 @@ mod test {
             source.protocol_version,
             ProtocolVersion::V4UpgradingToV5 { .. }
         ));
+        assert_eq!(source.protocol_version.current_version(), NtpVersion::V4);
 
         for _ in 0..8 {
             let actions = source.handle_timer();
@@ -1393,7 +2292,7 @@ mod test {
             assert!(poll.is_upgrade());
 
             let response = NtpPacket::timestamp_response(
-                NtpServerInfo::default(),
+                &NtpServerInfo::default(),
                 poll,
                 NtpTimestamp::default(),
                 &clock,
@@ -1430,6 +2329,34 @@ mod test {
         let (poll, _) = NtpPacket::deserialize(&poll, &NoCipher).unwrap();
         assert_eq!(poll.version(), NtpVersion::V4);
         assert!(!poll.is_upgrade());
+
+        // A restarted source for the same server doesn't try to upgrade again
+        let (restarted, _) = NtpSource::new(
+            source.source_addr,
+            SourceConfig::default(),
+            ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+            NoopController,
+            None,
+            None,
+            ClockId(2),
+            source.source_info.clone(),
+            Arc::default(),
+        );
+        assert_eq!(restarted.protocol_version, ProtocolVersion::V4);
+
+        // Unless the version was forced
+        let (forced, _) = NtpSource::new(
+            source.source_addr,
+            SourceConfig::default(),
+            ProtocolVersion::V5,
+            NoopController,
+            None,
+            None,
+            ClockId(3),
+            source.source_info.clone(),
+            Arc::default(),
+        );
+        assert_eq!(forced.protocol_version, ProtocolVersion::V5);
     }
 
     #[test]
@@ -1461,7 +2388,7 @@ mod test {
         assert!(poll.is_upgrade());
 
         let response = NtpPacket::timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             poll,
             NtpTimestamp::default(),
             &clock,
@@ -1484,6 +2411,7 @@ mod test {
             source.protocol_version,
             ProtocolVersion::UpgradedToV5
         ));
+        assert_eq!(source.protocol_version.current_version(), NtpVersion::V5);
 
         let actions = source.handle_timer();
         let mut outgoingbuf = None;
@@ -1497,11 +2425,12 @@ mod test {
             }
         }
         let poll = outgoingbuf.unwrap();
+        let poll_len = poll.len();
         let (poll, _) = NtpPacket::deserialize(&poll, &NoCipher).unwrap();
         assert_eq!(poll.version(), NtpVersion::V5);
 
         let response = NtpPacket::timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             poll,
             NtpTimestamp::default(),
             &clock,
@@ -1552,7 +2481,7 @@ mod test {
         assert!(poll.is_upgrade());
 
         let response = NtpPacket::timestamp_response(
-            NtpServerInfo::default(),
+            &NtpServerInfo::default(),
             poll,
             NtpTimestamp::default(),
             &clock,
@@ -1610,6 +2539,128 @@ mod test {
         assert_eq!(poll.version(), NtpVersion::V4);
     }
 
+    #[test]
+    fn interleaved_mode() {
+        struct RecordingController(Vec<Measurement>);
+        impl SourceController for RecordingController {
+            fn handle_measurement(&mut self, measurement: Measurement) {
+                self.0.push(measurement);
+            }
+
+            fn set_usable(&mut self, _: bool) {
+                // do nothing
+            }
+
+            fn desired_poll_interval(&self) -> PollInterval {
+                PollInterval::default()
+            }
+
+            fn observe(&self) -> crate::ObservableSourceTimedata {
+                unimplemented!()
+            }
+        }
+
+        fn poll(source: &mut NtpSource<RecordingController>) -> NtpPacket<'static> {
+            let mut outgoingbuf = None;
+            for action in source.handle_timer() {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            NtpPacket::deserialize(&outgoingbuf.unwrap(), &NoCipher)
+                .unwrap()
+                .0
+                .into_owned()
+        }
+
+        fn respond(
+            source: &mut NtpSource<RecordingController>,
+            origin: NtpTimestamp,
+            timestamps: [u64; 4],
+        ) -> Vec<(NtpTimestamp, NtpTimestamp)> {
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_origin_timestamp(origin);
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(timestamps[1]));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(timestamps[2]));
+
+            source.controller.0.clear();
+            let _ = source.handle_incoming(
+                &packet.serialize_without_encryption_vec(None).unwrap(),
+                NtpTimestamp::from_fixed_int(timestamps[0]),
+                NtpTimestamp::from_fixed_int(timestamps[3]),
+            );
+            source
+                .controller
+                .0
+                .iter()
+                .map(|m| (m.sender_ts, m.receiver_ts))
+                .collect()
+        }
+
+        let ts = NtpTimestamp::from_fixed_int;
+
+        let mut source = NtpSource::test_ntp_source(RecordingController(vec![]));
+        source.protocol_version = ProtocolVersion::V4;
+        source.source_config.interleaved = true;
+
+        // Without a previous exchange we can only ask for a basic response
+        let request = poll(&mut source);
+        assert_eq!(request.receive_timestamp(), NtpTimestamp::default());
+        let measurements = respond(
+            &mut source,
+            request.transmit_timestamp(),
+            [100, 200, 300, 400],
+        );
+        assert_eq!(measurements, vec![(ts(100), ts(200)), (ts(300), ts(400))]);
+
+        // The interleaved response completes the previous exchange with the
+        // more accurate transmit timestamp
+        let request = poll(&mut source);
+        assert_eq!(request.receive_timestamp(), ts(400));
+        let measurements = respond(&mut source, ts(400), [1100, 1200, 310, 1400]);
+        assert_eq!(measurements, vec![(ts(100), ts(200)), (ts(310), ts(400))]);
+
+        // Basic responses to interleaved requests are used as is
+        let request = poll(&mut source);
+        assert_eq!(request.receive_timestamp(), ts(1400));
+        let measurements = respond(
+            &mut source,
+            request.transmit_timestamp(),
+            [2100, 2200, 2300, 2400],
+        );
+        assert_eq!(
+            measurements,
+            vec![(ts(2100), ts(2200)), (ts(2300), ts(2400))]
+        );
+
+        // Until we conclude the server does not support interleaved mode
+        for _ in 1..INTERLEAVED_TRIES_THRESHOLD {
+            let request = poll(&mut source);
+            assert_ne!(request.receive_timestamp(), NtpTimestamp::default());
+            respond(
+                &mut source,
+                request.transmit_timestamp(),
+                [2100, 2200, 2300, 2400],
+            );
+        }
+        let request = poll(&mut source);
+        assert_eq!(request.receive_timestamp(), NtpTimestamp::default());
+
+        // Interleaved mode is not used unless configured
+        let mut source = NtpSource::test_ntp_source(RecordingController(vec![]));
+        source.protocol_version = ProtocolVersion::V4;
+        let request = poll(&mut source);
+        respond(
+            &mut source,
+            request.transmit_timestamp(),
+            [100, 200, 300, 400],
+        );
+        let request = poll(&mut source);
+        assert_eq!(request.receive_timestamp(), NtpTimestamp::default());
+    }
+
     #[test]
     fn bloom_filters_will_synchronize_at_some_point() {
         let mut server_filter = BloomFilter::new();
@@ -1646,7 +2697,7 @@ mod test {
 
             let (req, _) = NtpPacket::deserialize(&req, &NoCipher).unwrap();
             let response =
-                NtpPacket::timestamp_response(server_info, req, NtpTimestamp::default(), &clock);
+                NtpPacket::timestamp_response(&server_info, req, NtpTimestamp::default(), &clock);
             let resp_bytes = response.serialize_without_encryption_vec(None).unwrap();
 
             let actions = client.handle_incoming(
@@ -1666,4 +2717,87 @@ mod test {
 
         assert_eq!(Some(&server_filter), client.bloom_filter.full_filter());
     }
+
+    #[test]
+    fn remote_steps_are_detected() {
+        struct StepController(Vec<NtpDuration>);
+        impl SourceController for StepController {
+            fn handle_measurement(&mut self, _: Measurement) {
+                // do nothing
+            }
+
+            fn set_usable(&mut self, _: bool) {
+                // do nothing
+            }
+
+            fn handle_remote_step(&mut self, step: NtpDuration) {
+                self.0.push(step);
+            }
+
+            fn desired_poll_interval(&self) -> PollInterval {
+                PollInterval::default()
+            }
+
+            fn observe(&self) -> crate::ObservableSourceTimedata {
+                unimplemented!()
+            }
+        }
+
+        fn exchange(
+            client: &mut NtpSource<StepController>,
+            recv_timestamp: NtpTimestamp,
+            monotonic_epoch: u32,
+            monotonic_offset: f64,
+        ) {
+            let mut outgoingbuf = None;
+            for action in client.handle_timer() {
+                if let NtpSourceAction::Send(buf) = action {
+                    outgoingbuf = Some(buf);
+                }
+            }
+            let req = outgoingbuf.unwrap();
+            let (req, _) = NtpPacket::deserialize(&req, &NoCipher).unwrap();
+
+            let server_info = NtpServerInfo {
+                time_snapshot: TimeSnapshot {
+                    leap_indicator: NtpLeapIndicator::NoWarning,
+                    monotonic_epoch,
+                    monotonic_offset: NtpDuration::from_seconds(monotonic_offset),
+                    ..Default::default()
+                },
+                ntp_snapshot: NtpSnapshot {
+                    stratum: 1,
+                    ..Default::default()
+                },
+            };
+            let clock = TestClock::default();
+            let response = NtpPacket::timestamp_response(&server_info, req, recv_timestamp, &clock);
+            let resp_bytes = response.serialize_without_encryption_vec(None).unwrap();
+
+            let _ = client.handle_incoming(
+                &resp_bytes,
+                NtpTimestamp::default(),
+                NtpTimestamp::default(),
+            );
+        }
+
+        let mut client = NtpSource::test_ntp_source(StepController(vec![]));
+        client.protocol_version = ProtocolVersion::V5;
+
+        let base = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let at = |seconds| base + NtpDuration::from_seconds(seconds);
+
+        exchange(&mut client, at(0.0), 7, 0.0);
+        exchange(&mut client, at(1.0), 7, 0.0);
+        assert!(client.controller.0.is_empty());
+
+        // The server stepped its clock forward by 2 seconds
+        exchange(&mut client, at(4.0), 7, 2.0);
+        assert_eq!(client.controller.0.len(), 1);
+        assert!((client.controller.0[0].to_seconds() - 2.0).abs() < 1e-6);
+
+        // After a restart of the timescale nothing can be concluded
+        exchange(&mut client, at(10.0), 8, 0.0);
+        assert_eq!(client.controller.0.len(), 1);
+    }
 }
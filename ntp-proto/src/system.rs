@@ -1,9 +1,11 @@
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex, RwLock};
 
+use crate::broadcast::BroadcastSource;
 use crate::packet::v5::server_reference_id::{BloomFilter, ServerId};
 use crate::source::SourceSnapshot;
 use crate::{
@@ -12,12 +14,13 @@ use crate::{
 use crate::{
     config::{SourceConfig, SynchronizationConfig},
     identifiers::ReferenceId,
-    packet::NtpLeapIndicator,
+    packet::{NtpLeapIndicator, SymmetricKey},
     source::{NtpSource, NtpSourceActionIterator, ProtocolVersion, SourceNtsData},
-    time_types::NtpDuration,
+    time_types::{NtpDuration, PollInterval},
 };
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TimeSnapshot {
     /// Precision of the local clock
     pub precision: NtpDuration,
@@ -39,6 +42,73 @@ pub struct TimeSnapshot {
     pub accumulated_steps: NtpDuration,
     /// Crossing this amount of stepping will cause a Panic
     pub accumulated_steps_threshold: Option<NtpDuration>,
+    /// Progress of the leap second currently being smeared, if any
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leap_smear: Option<LeapSmearStatus>,
+    /// Identifies the timescale of our monotonic receive timestamps, a new
+    /// epoch is chosen whenever `monotonic_offset` starts over
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub monotonic_epoch: u32,
+    /// Sum of the clock steps since the start of the monotonic timescale.
+    /// Subtracting it from the current time gives the monotonic time.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub monotonic_offset: NtpDuration,
+    /// Frequency correction (in seconds per second) the clock needs to run
+    /// at the right rate, apart from any slewing. Only known once the clock
+    /// has been synchronized.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frequency_correction: Option<f64>,
+    /// Why the clock was not steered at the last update, if it wasn't
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub steering_withheld: Option<SteeringWithheld>,
+}
+
+/// Progress of smearing a leap second
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LeapSmearStatus {
+    /// The leap second being smeared, either `Leap61` or `Leap59`
+    pub leap: NtpLeapIndicator,
+    /// Fraction of the smear window that has passed (0-1)
+    pub progress: f64,
+    /// Amount by which the clock has been slowed down (inserted leap
+    /// second) or sped up (deleted leap second) so far
+    pub offset: NtpDuration,
+}
+
+/// Reason the clock algorithm did not steer the clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "reason", rename_all = "kebab-case"))]
+pub enum SteeringWithheld {
+    /// None of the sources currently provide usable time
+    NoUsableSources,
+    /// The largest group of sources agreeing on the time is not a majority
+    NoMajority { agreeing: usize, sources: usize },
+    /// Fewer sources agree on the time than are required
+    NotEnoughAgreeing { agreeing: usize, required: usize },
+    /// None of the sources marked as required survived selection
+    NoRequiredSource,
+}
+
+impl std::fmt::Display for SteeringWithheld {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SteeringWithheld::NoUsableSources => write!(f, "no usable sources"),
+            SteeringWithheld::NoMajority { agreeing, sources } => {
+                write!(
+                    f,
+                    "no majority agrees, only {agreeing} of {sources} sources"
+                )
+            }
+            SteeringWithheld::NotEnoughAgreeing { agreeing, required } => {
+                write!(f, "{agreeing} sources agree, {required} required")
+            }
+            SteeringWithheld::NoRequiredSource => {
+                write!(f, "none of the required sources survived selection")
+            }
+        }
+    }
 }
 
 impl TimeSnapshot {
@@ -68,28 +138,35 @@ impl Default for TimeSnapshot {
             leap_indicator: NtpLeapIndicator::Unknown,
             accumulated_steps: NtpDuration::ZERO,
             accumulated_steps_threshold: None,
+            leap_smear: None,
+            monotonic_epoch: 0,
+            monotonic_offset: NtpDuration::ZERO,
+            frequency_correction: None,
+            steering_withheld: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SystemSnapshot {
     /// Timekeeping data
-    #[serde(flatten)]
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub time_snapshot: TimeSnapshot,
     /// NTP specific data
-    #[serde(flatten)]
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub ntp_snapshot: NtpSnapshot,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NtpSnapshot {
     /// Log of the precision of the local clock
     pub stratum: u8,
     /// Reference ID of current primary time source
     pub reference_id: ReferenceId,
     /// Bloom filter that contains all currently used time sources
-    #[serde(skip)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub bloom_filter: BloomFilter,
 }
 
@@ -145,9 +222,14 @@ impl Default for NtpSnapshot {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SourceType {
-    Pps,
-    Sock,
+    /// An NTP server, which reports its own stratum and reference
     Ntp,
+    /// A reference clock, or another source that doesn't report a stratum,
+    /// with the stratum and reference id to advertise when synchronized to it
+    External {
+        stratum: u8,
+        reference_id: ReferenceId,
+    },
 }
 
 #[derive(Default, Copy, Clone)]
@@ -161,6 +243,15 @@ pub(crate) struct NtpSourceInfo {
     pub(crate) ip_list: Arc<[IpAddr]>,
     pub(crate) server_id: ServerId,
     pub(crate) local_stratum: u8,
+    // Range of strata of sources that may be used for synchronization
+    pub(crate) minimum_source_stratum: u8,
+    pub(crate) maximum_source_stratum: u8,
+    // Minimum poll interval per server imposed by RATE kiss codes, kept
+    // here so it survives restarting the source.
+    pub(crate) rate_limits: HashMap<IpAddr, PollInterval>,
+    // Servers that did not accept an upgrade to NTPv5, so new sources for
+    // them with automatic version negotiation start out at NTPv4.
+    pub(crate) v5_unsupported: HashSet<IpAddr>,
 }
 
 pub struct NtpManager {
@@ -179,6 +270,10 @@ impl NtpManager {
             ip_list,
             server_id,
             local_stratum: synchronization_config.local_stratum,
+            minimum_source_stratum: synchronization_config.minimum_source_stratum,
+            maximum_source_stratum: synchronization_config.maximum_source_stratum,
+            rate_limits: HashMap::new(),
+            v5_unsupported: HashSet::new(),
         };
         let mut server_info = NtpServerInfo {
             time_snapshot: TimeSnapshot::default(),
@@ -205,6 +300,10 @@ impl NtpManager {
         Server::new_internal(config, clock, self.server_info.clone(), keyset)
     }
 
+    #[expect(
+        clippy::too_many_arguments,
+        reason = "Mirrors the arguments of NtpSource::new"
+    )]
     pub fn new_source<Controller: SourceController>(
         &self,
         source_addr: SocketAddr,
@@ -212,6 +311,7 @@ impl NtpManager {
         protocol_version: ProtocolVersion,
         controller: Controller,
         nts: Option<Box<SourceNtsData>>,
+        symmetric_key: Option<SymmetricKey>,
         id: ClockId,
     ) -> (NtpSource<Controller>, NtpSourceActionIterator) {
         NtpSource::new(
@@ -220,6 +320,22 @@ impl NtpManager {
             protocol_version,
             controller,
             nts,
+            symmetric_key,
+            id,
+            self.source_info.clone(),
+            self.source_snapshots.clone(),
+        )
+    }
+
+    pub fn new_broadcast_source<Controller: SourceController>(
+        &self,
+        controller: Controller,
+        fixed_delay: Option<NtpDuration>,
+        id: ClockId,
+    ) -> BroadcastSource<Controller> {
+        BroadcastSource::new(
+            controller,
+            fixed_delay,
             id,
             self.source_info.clone(),
             self.source_snapshots.clone(),
@@ -237,15 +353,14 @@ impl NtpManager {
         let source_snapshots = self.source_snapshots.lock().unwrap();
         let sources: Option<Vec<_>> = sources
             .map(|(id, sourcetype)| match sourcetype {
-                SourceType::Pps => Some(SourceSnapshot::External {
-                    stratum: 0,
-                    source_id: ReferenceId::PPS,
-                }),
-                SourceType::Sock => Some(SourceSnapshot::External {
-                    stratum: 0,
-                    source_id: ReferenceId::SOCK,
-                }),
                 SourceType::Ntp => source_snapshots.get(&id).copied().map(SourceSnapshot::Ntp),
+                SourceType::External {
+                    stratum,
+                    reference_id,
+                } => Some(SourceSnapshot::External {
+                    stratum,
+                    source_id: reference_id,
+                }),
             })
             .collect();
         drop(source_snapshots);
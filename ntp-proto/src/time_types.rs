@@ -2,12 +2,17 @@ use rand::{
     Rng,
     distributions::{Distribution, Standard},
 };
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize, de::Unexpected};
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 use std::time::Duration;
 
 /// NtpTimestamp represents an ntp timestamp without the era number.
-#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+///
+/// With serde it is represented as `{ "timestamp": bits }`, where `bits` is
+/// the raw 64-bit NTP timestamp format value.
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NtpTimestamp {
     timestamp: u64,
 }
@@ -75,6 +80,177 @@ impl NtpTimestamp {
     }
 }
 
+// Seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+// Timestamps carry no era, so for conversion to and from dates they are
+// placed in the 136 year window starting at 1968-01-20T03:14:08Z, like
+// RFC 4330 does.
+const ERA_WINDOW_START: i64 = (1 << 31) - NTP_UNIX_OFFSET;
+const ERA_WINDOW_END: i64 = ERA_WINDOW_START + (1 << 32);
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Inverse of days_from_civil
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl std::fmt::Display for NtpTimestamp {
+    /// Format as an ISO-8601 UTC time, e.g. "2025-01-01T12:00:00.5Z"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = (self.timestamp >> 32) as i64;
+        let seconds = if seconds < (1 << 31) {
+            seconds + (1 << 32)
+        } else {
+            seconds
+        };
+        let unix_seconds = seconds - NTP_UNIX_OFFSET;
+        let nanos = ((self.timestamp & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+
+        let (year, month, day) = civil_from_days(unix_seconds.div_euclid(86400));
+        let second_of_day = unix_seconds.rem_euclid(86400);
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+            second_of_day / 3600,
+            second_of_day / 60 % 60,
+            second_of_day % 60
+        )?;
+        if nanos != 0 {
+            let fraction = format!("{nanos:09}");
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        f.write_str("Z")
+    }
+}
+
+fn parse_digits(input: &str, length: usize) -> Option<i64> {
+    if input.len() != length || !input.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    input.parse().ok()
+}
+
+impl std::str::FromStr for NtpTimestamp {
+    type Err = ParseTimeError;
+
+    /// Parse an ISO-8601 time with a UTC offset, e.g.
+    /// "2025-01-01T12:00:00.5Z" or "2025-01-01T13:00:00+01:00". Only times
+    /// between 1968 and 2104 can be represented.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const INVALID: ParseTimeError = ParseTimeError {
+            message: "expected an ISO-8601 time like 2025-01-01T12:00:00Z",
+        };
+
+        let (date, rest) = s.trim().split_once(['T', 't', ' ']).ok_or(INVALID)?;
+        let zone_start = rest.find(['Z', 'z', '+', '-']).ok_or(INVALID)?;
+        let (time, zone) = rest.split_at(zone_start);
+        let (time, fraction) = match time.split_once('.') {
+            Some((time, fraction)) => (time, Some(fraction)),
+            None => (time, None),
+        };
+
+        let mut date = date.split('-');
+        let (Some(year), Some(month), Some(day), None) =
+            (date.next(), date.next(), date.next(), date.next())
+        else {
+            return Err(INVALID);
+        };
+        let year = parse_digits(year, 4).ok_or(INVALID)?;
+        let month = parse_digits(month, 2).ok_or(INVALID)?;
+        let day = parse_digits(day, 2).ok_or(INVALID)?;
+
+        let mut time = time.split(':');
+        let (Some(hour), Some(minute), Some(second), None) =
+            (time.next(), time.next(), time.next(), time.next())
+        else {
+            return Err(INVALID);
+        };
+        let hour = parse_digits(hour, 2).ok_or(INVALID)?;
+        let minute = parse_digits(minute, 2).ok_or(INVALID)?;
+        let second = parse_digits(second, 2).ok_or(INVALID)?;
+
+        let nanos = match fraction {
+            Some(fraction) => {
+                if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(INVALID);
+                }
+                // Digits beyond nanosecond precision are ignored
+                let fraction = &fraction[..fraction.len().min(9)];
+                let padding = 10_u32.pow(9 - fraction.len() as u32);
+                fraction.parse::<u32>().map_err(|_| INVALID)? * padding
+            }
+            None => 0,
+        };
+
+        let offset = match zone {
+            "Z" | "z" => 0,
+            _ => {
+                let (sign, zone) = zone.split_at(1);
+                let (hours, minutes) = zone.split_once(':').ok_or(INVALID)?;
+                let hours = parse_digits(hours, 2).filter(|h| *h < 24).ok_or(INVALID)?;
+                let minutes = parse_digits(minutes, 2)
+                    .filter(|m| *m < 60)
+                    .ok_or(INVALID)?;
+                let offset = hours * 3600 + minutes * 60;
+                match sign {
+                    "+" => offset,
+                    "-" => -offset,
+                    _ => return Err(INVALID),
+                }
+            }
+        };
+
+        let days = days_from_civil(year, month, day);
+        if !(1..=12).contains(&month)
+            || civil_from_days(days) != (year, month, day)
+            || hour >= 24
+            || minute >= 60
+            || second >= 60
+        {
+            return Err(ParseTimeError {
+                message: "invalid date or time",
+            });
+        }
+
+        let unix_seconds = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+        if !(ERA_WINDOW_START..ERA_WINDOW_END).contains(&unix_seconds) {
+            return Err(ParseTimeError {
+                message: "time out of range, only times between 1968 and 2104 are supported",
+            });
+        }
+
+        // Truncating to 32 bits removes the era
+        let seconds = (unix_seconds + NTP_UNIX_OFFSET) as u32;
+        Ok(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+            seconds, nanos,
+        ))
+    }
+}
+
 // In order to provide increased entropy on origin timestamps,
 // we should generate these randomly. This helps avoid
 // attacks from attackers guessing our current time.
@@ -153,6 +329,8 @@ impl SubAssign<NtpDuration> for NtpTimestamp {
 /// A negative duration interval is interpreted to mean that the first
 /// timestamp used to define the interval represents a point in time after
 /// the second timestamp.
+///
+/// With serde it is represented as a floating point number of seconds.
 #[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default)]
 pub struct NtpDuration {
     duration: i64,
@@ -312,6 +490,7 @@ impl NtpDuration {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for NtpDuration {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -322,21 +501,152 @@ impl Serialize for NtpDuration {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for NtpDuration {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let seconds: f64 = Deserialize::deserialize(deserializer)?;
+        let seconds = deserialize_seconds(deserializer)?;
+        Ok(NtpDuration::from_seconds(seconds))
+    }
+}
+
+/// Deserialize a number of seconds, given either as a number or as a
+/// duration string like "1.5ms"
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_seconds<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct SecondsVisitor;
 
-        if seconds.is_nan() || seconds.is_infinite() {
-            return Err(serde::de::Error::invalid_value(
-                Unexpected::Float(seconds),
-                &"a valid number",
-            ));
+    impl serde::de::Visitor<'_> for SecondsVisitor {
+        type Value = f64;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a number of seconds or a duration like \"1.5ms\"")
         }
 
-        Ok(NtpDuration::from_seconds(seconds))
+        fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            if v.is_nan() || v.is_infinite() {
+                return Err(E::invalid_value(Unexpected::Float(v), &"a valid number"));
+            }
+            Ok(v)
+        }
+
+        fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            self.visit_f64(v as f64)
+        }
+
+        fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            self.visit_f64(v as f64)
+        }
+
+        fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            parse_seconds(v).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(SecondsVisitor)
+}
+
+/// Error returned when a duration or timestamp can't be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseTimeError {
+    message: &'static str,
+}
+
+impl std::fmt::Display for ParseTimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl std::error::Error for ParseTimeError {}
+
+/// Parse a number with an optional unit into seconds
+fn parse_seconds(input: &str) -> Result<f64, ParseTimeError> {
+    let input = input.trim();
+    let unit_start = input
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(unit_start);
+
+    let value: f64 = number.parse().map_err(|_| ParseTimeError {
+        message: "expected a number followed by a unit (ns, us, ms, s, min, h or d)",
+    })?;
+    let scale = match unit.trim_start() {
+        "ns" => 1e-9,
+        "us" | "µs" => 1e-6,
+        "ms" => 1e-3,
+        "" | "s" => 1.0,
+        "min" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => {
+            return Err(ParseTimeError {
+                message: "unknown unit, expected ns, us, ms, s, min, h or d",
+            });
+        }
+    };
+
+    let seconds = value * scale;
+    if !seconds.is_finite() || seconds.abs() >= i32::MAX as f64 {
+        return Err(ParseTimeError {
+            message: "duration out of range",
+        });
+    }
+
+    Ok(seconds)
+}
+
+impl std::str::FromStr for NtpDuration {
+    type Err = ParseTimeError;
+
+    /// Parse a duration like "30s" or "1.5ms". A number without unit is
+    /// interpreted as seconds.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_seconds(s).map(NtpDuration::from_seconds)
+    }
+}
+
+impl std::fmt::Display for NtpDuration {
+    /// Format with the largest unit (s, ms, us or ns) in which the duration
+    /// is at least 1, at nanosecond precision.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let nanos = ((self.duration as i128 * 1_000_000_000) + (1 << 31)) >> 32;
+        let sign = if nanos < 0 { "-" } else { "" };
+        let nanos = nanos.unsigned_abs();
+
+        let (unit, scale) = match nanos {
+            1_000_000_000.. => ("s", 1_000_000_000),
+            1_000_000.. => ("ms", 1_000_000),
+            1_000.. => ("us", 1_000),
+            _ => ("ns", 1),
+        };
+
+        let whole = nanos / scale;
+        let fraction = nanos % scale;
+        if fraction == 0 {
+            write!(f, "{sign}{whole}{unit}")
+        } else {
+            let digits = scale.ilog10() as usize;
+            let fraction = format!("{fraction:0digits$}");
+            write!(f, "{sign}{whole}.{}{unit}", fraction.trim_end_matches('0'))
+        }
     }
 }
 
@@ -488,10 +798,66 @@ ntp_duration_scalar_div!(u32);
 //
 // - a value of 4 means 2^4 = 16 seconds
 // - a value of 17 is 2^17 = ~36h
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+//
+// In configuration it can also be given as a duration that is a power of
+// two, e.g. "64s".
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PollInterval(i8);
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for PollInterval {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct PollIntervalVisitor;
+
+        impl serde::de::Visitor<'_> for PollIntervalVisitor {
+            type Value = PollInterval;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str(
+                    "the log2 of the interval in seconds, or a power of two duration like \"64s\"",
+                )
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                i8::try_from(v)
+                    .map(PollInterval)
+                    .map_err(|_| E::invalid_value(Unexpected::Signed(v), &self))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                i8::try_from(v)
+                    .map(PollInterval)
+                    .map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                let log = parse_seconds(v).map_err(E::custom)?.log2();
+                if log.fract() != 0.0 || !(i8::MIN as f64..=i8::MAX as f64).contains(&log) {
+                    return Err(E::invalid_value(Unexpected::Str(v), &self));
+                }
+                Ok(PollInterval(log as i8))
+            }
+        }
+
+        deserializer.deserialize_any(PollIntervalVisitor)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PollIntervalLimits {
     pub min: PollInterval,
     pub max: PollInterval,
@@ -589,6 +955,7 @@ pub struct FrequencyTolerance {
     ppm: u32,
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for FrequencyTolerance {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -625,6 +992,162 @@ pub fn fuzz_duration_from_seconds(v: f64) {
 mod tests {
     use super::*;
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_representation() {
+        let timestamp = NtpTimestamp::from_fixed_int(0x1234_5678_9abc_def0);
+        let json = serde_json::to_string(&timestamp).unwrap();
+        assert_eq!(json, r#"{"timestamp":1311768467463790320}"#);
+        assert_eq!(
+            serde_json::from_str::<NtpTimestamp>(&json).unwrap(),
+            timestamp
+        );
+
+        let duration = NtpDuration::from_seconds(-1.5);
+        let json = serde_json::to_string(&duration).unwrap();
+        let seconds: f64 = json.parse().unwrap();
+        assert!((seconds + 1.5).abs() < 1e-6);
+        assert_eq!(
+            serde_json::from_str::<NtpDuration>("-1.5").unwrap(),
+            duration
+        );
+
+        // Strings are parsed as durations with an optional unit
+        assert_eq!(
+            serde_json::from_str::<NtpDuration>("\"1.5s\"").unwrap(),
+            NtpDuration::from_seconds(1.5)
+        );
+        assert!(serde_json::from_str::<NtpDuration>("\"soon\"").is_err());
+    }
+
+    #[test]
+    fn test_duration_display() {
+        assert_eq!(NtpDuration::ZERO.to_string(), "0ns");
+        assert_eq!(NtpDuration::from_seconds(30.0).to_string(), "30s");
+        assert_eq!(NtpDuration::from_seconds(1.5).to_string(), "1.5s");
+        assert_eq!(NtpDuration::from_seconds(0.0015).to_string(), "1.5ms");
+        assert_eq!(NtpDuration::from_seconds(-0.000_25).to_string(), "-250us");
+        assert_eq!(NtpDuration::from_seconds(42e-9).to_string(), "42ns");
+    }
+
+    #[test]
+    fn test_duration_from_str() {
+        for (input, seconds) in [
+            ("30s", 30.0),
+            ("1.5ms", 0.0015),
+            ("250us", 0.000_25),
+            ("250µs", 0.000_25),
+            ("42ns", 42e-9),
+            ("2min", 120.0),
+            ("1h", 3600.0),
+            ("1d", 86400.0),
+            ("-2 s", -2.0),
+            ("0.5", 0.5),
+            ("1e-3s", 0.001),
+        ] {
+            assert_eq!(
+                input.parse::<NtpDuration>().unwrap(),
+                NtpDuration::from_seconds(seconds),
+                "{input}"
+            );
+        }
+
+        for input in ["", "s", "1.5parsec", "inf", "nans", "1e20s", "1.2.3s"] {
+            assert!(input.parse::<NtpDuration>().is_err(), "{input}");
+        }
+
+        for duration in [
+            NtpDuration::from_seconds(0.0015),
+            NtpDuration::from_seconds(-30.0),
+        ] {
+            let roundtrip: NtpDuration = duration.to_string().parse().unwrap();
+            assert!((roundtrip - duration).abs() < NtpDuration::from_seconds(1e-9));
+        }
+    }
+
+    #[test]
+    fn test_timestamp_iso8601() {
+        let timestamp = NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 0);
+        assert_eq!(timestamp.to_string(), "2036-02-07T06:28:16Z");
+
+        let timestamp = NtpTimestamp::from_seconds_nanos_since_ntp_era(
+            (NTP_UNIX_OFFSET + 1_735_732_800) as u32,
+            500_000_000,
+        );
+        assert_eq!(timestamp.to_string(), "2025-01-01T12:00:00.5Z");
+        assert_eq!(
+            "2025-01-01T12:00:00.5Z".parse::<NtpTimestamp>().unwrap(),
+            timestamp
+        );
+        assert_eq!(
+            "2025-01-01T13:30:00.500+01:30"
+                .parse::<NtpTimestamp>()
+                .unwrap(),
+            timestamp
+        );
+        assert_eq!(
+            "2025-01-01t06:00:00.5-06:00"
+                .parse::<NtpTimestamp>()
+                .unwrap(),
+            timestamp
+        );
+
+        let leap_day = "2024-02-29T23:59:59Z".parse::<NtpTimestamp>().unwrap();
+        assert_eq!(leap_day.to_string(), "2024-02-29T23:59:59Z");
+        assert_eq!(
+            "1968-01-20T03:14:08Z"
+                .parse::<NtpTimestamp>()
+                .unwrap()
+                .to_string(),
+            "1968-01-20T03:14:08Z"
+        );
+        assert_eq!(
+            "2104-02-26T09:42:23Z"
+                .parse::<NtpTimestamp>()
+                .unwrap()
+                .to_string(),
+            "2104-02-26T09:42:23Z"
+        );
+
+        for input in [
+            "",
+            "2025-01-01",
+            "2025-01-01T12:00:00",
+            "2025-13-01T12:00:00Z",
+            "2023-02-29T12:00:00Z",
+            "2025-01-01T24:00:00Z",
+            "2025-01-01T12:00:00.Z",
+            "2025-01-01T12:00:00Z05:00",
+            "25-01-01T12:00:00Z",
+            "1900-01-01T00:00:00Z",
+            "2104-02-26T09:42:24Z",
+        ] {
+            assert!(input.parse::<NtpTimestamp>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_poll_interval_from_duration() {
+        #[derive(Deserialize)]
+        struct Helper {
+            interval: PollInterval,
+        }
+
+        let parse = |input: &str| {
+            serde_json::from_str::<Helper>(&format!(r#"{{"interval": {input}}}"#))
+                .map(|helper| helper.interval.as_log())
+        };
+        assert_eq!(parse("6").unwrap(), 6);
+        assert_eq!(parse("-2").unwrap(), -2);
+        assert_eq!(parse(r#""64s""#).unwrap(), 6);
+        assert_eq!(parse(r#""0.5s""#).unwrap(), -1);
+        assert_eq!(parse(r#""1024""#).unwrap(), 10);
+        assert!(parse(r#""60s""#).is_err());
+        assert!(parse(r#""0s""#).is_err());
+        assert!(parse("200").is_err());
+    }
+
     #[test]
     fn test_timestamp_sub() {
         let a = NtpTimestamp::from_fixed_int(5);
@@ -1,14 +1,32 @@
 mod rustls23_shim {
+    pub use rustls23::CertificateError;
     pub use rustls23::ClientConfig;
     pub use rustls23::ClientConnection;
     pub use rustls23::ConnectionCommon;
+    pub use rustls23::DigitallySignedStruct;
     pub use rustls23::Error;
+    pub use rustls23::HandshakeKind;
+    pub use rustls23::InconsistentKeys;
     pub use rustls23::RootCertStore;
     pub use rustls23::ServerConfig;
     pub use rustls23::ServerConnection;
+    pub use rustls23::SignatureAlgorithm;
+    pub use rustls23::SignatureScheme;
+    pub use rustls23::SupportedCipherSuite;
+    pub use rustls23::client::Resumption;
+    pub use rustls23::client::WebPkiServerVerifier;
+    pub use rustls23::client::danger::{
+        HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+    };
+    pub use rustls23::crypto::CryptoProvider;
+    pub use rustls23::crypto::hash::{Hash, HashAlgorithm};
+    pub use rustls23::pki_types::CertificateDer;
     pub use rustls23::pki_types::InvalidDnsNameError;
     pub use rustls23::pki_types::ServerName;
+    pub use rustls23::pki_types::UnixTime;
     pub use rustls23::server::NoClientAuth;
+    pub use rustls23::server::{ClientHello, ResolvesServerCert};
+    pub use rustls23::sign::{CertifiedKey, Signer, SigningKey};
     pub use rustls23::version::TLS13;
 
     pub type Certificate = rustls23::pki_types::CertificateDer<'static>;
@@ -0,0 +1,123 @@
+//! The few certificate fields needed for NTS and ACME, read using
+//! x509-parser
+
+use std::time::{Duration, SystemTime};
+
+use x509_parser::{
+    certificate::X509Certificate, extensions::GeneralName, parse_x509_certificate, time::ASN1Time,
+};
+
+fn parse(certificate: &[u8]) -> Option<X509Certificate<'_>> {
+    let (_, certificate) = parse_x509_certificate(certificate).ok()?;
+    Some(certificate)
+}
+
+fn system_time(time: ASN1Time) -> Option<SystemTime> {
+    let seconds = u64::try_from(time.timestamp()).ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// The complete DER encoded SubjectPublicKeyInfo of an X.509 certificate
+pub fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    Some(parse(certificate)?.tbs_certificate.subject_pki.raw)
+}
+
+/// The start and end of the validity period of an X.509 certificate
+pub fn validity(certificate: &[u8]) -> Option<(SystemTime, SystemTime)> {
+    let validity = parse(certificate)?.tbs_certificate.validity;
+    Some((
+        system_time(validity.not_before)?,
+        system_time(validity.not_after)?,
+    ))
+}
+
+/// The end of the validity period of an X.509 certificate
+pub fn not_after(certificate: &[u8]) -> Option<SystemTime> {
+    validity(certificate).map(|(_, not_after)| not_after)
+}
+
+/// The DNS names in the subject alternative names of an X.509 certificate
+pub fn dns_names(certificate: &[u8]) -> Option<Vec<String>> {
+    let certificate = parse(certificate)?;
+    let Some(names) = certificate.subject_alternative_name().ok()? else {
+        return Some(vec![]);
+    };
+
+    Some(
+        names
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use x509_parser::{prelude::FromDer, x509::SubjectPublicKeyInfo};
+
+    use super::*;
+    use crate::tls_utils::{self, Certificate};
+
+    fn certificate(pem: &[u8]) -> Certificate {
+        tls_utils::pemfile::certs(&mut &*pem)
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_subject_public_key_info() {
+        let end = certificate(include_bytes!("../test-keys/end.pem"));
+        let spki = subject_public_key_info(&end).unwrap();
+        let (rest, _) = SubjectPublicKeyInfo::from_der(spki).unwrap();
+        assert!(rest.is_empty());
+
+        let ca = certificate(include_bytes!("../test-keys/testca.pem"));
+        assert_ne!(subject_public_key_info(&ca).unwrap(), spki);
+    }
+
+    #[test]
+    fn test_subject_public_key_info_invalid() {
+        assert_eq!(subject_public_key_info(&[]), None);
+        assert_eq!(subject_public_key_info(&[0x30, 0x03, 0x30, 0x01]), None);
+        assert_eq!(subject_public_key_info(&[0x30, 0x84, 0xff, 0xff]), None);
+
+        let end = certificate(include_bytes!("../test-keys/end.pem"));
+        assert_eq!(subject_public_key_info(&end[..end.len() / 2]), None);
+    }
+
+    #[test]
+    fn test_not_after() {
+        let end = certificate(include_bytes!("../test-keys/end.pem"));
+        let ca = certificate(include_bytes!("../test-keys/testca.pem"));
+        // 2027-02-27 10:11:32, as UTCTime
+        assert_eq!(
+            not_after(&end),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_803_723_092))
+        );
+        // 4760-12-17 09:43:37, as GeneralizedTime
+        assert_eq!(
+            not_after(&ca),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(88_074_207_817))
+        );
+
+        assert_eq!(not_after(&end[..end.len() / 2]), None);
+
+        let (not_before, not_after) = validity(&end).unwrap();
+        assert!(not_before < not_after);
+        assert!(not_before > SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+    }
+
+    #[test]
+    fn test_dns_names() {
+        let end = certificate(include_bytes!("../test-keys/end.pem"));
+        assert_eq!(dns_names(&end).unwrap(), ["localhost"]);
+
+        assert_eq!(dns_names(&end[..50]), None);
+    }
+}
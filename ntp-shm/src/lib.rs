@@ -0,0 +1,274 @@
+//! Shared memory segments of the SHM reference clock protocol of ntpd.
+//!
+//! A feeder, such as gpsd, writes the time of its reference clock together
+//! with the time of the system clock at that moment into a System V shared
+//! memory segment, and sets its valid flag. The reader takes the sample and
+//! clears the flag again. In mode 1 the feeder increments a counter before
+//! and after writing, so the reader can tell that its read overlapped a
+//! write.
+//!
+//! This is kept out of the daemon, as attaching the segment and reading
+//! memory that is written by another process needs unsafe code.
+
+use std::{
+    fmt::Display,
+    ptr::NonNull,
+    sync::atomic::{Ordering, fence},
+};
+
+use libc::{c_int, c_uint, time_t};
+
+/// Key of the segment of unit 0, the other units follow it
+const KEY_BASE: libc::key_t = 0x4e54_5030;
+
+/// Layout of the segment, as defined by ntpd
+#[repr(C)]
+struct ShmTime {
+    mode: c_int,
+    count: c_int,
+    clock_seconds: time_t,
+    clock_micros: c_int,
+    receive_seconds: time_t,
+    receive_micros: c_int,
+    leap: c_int,
+    precision: c_int,
+    samples: c_int,
+    valid: c_int,
+    clock_nanos: c_uint,
+    receive_nanos: c_uint,
+    dummy: [c_int; 8],
+}
+
+/// A sample written by the feeder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Time of the reference clock, seconds since the unix epoch
+    pub clock_seconds: i64,
+    pub clock_nanos: u32,
+    /// Time of the system clock at the same moment
+    pub receive_seconds: i64,
+    pub receive_nanos: u32,
+    /// Leap indicator as in NTP packets, 3 when the clock is not synchronized
+    pub leap: i32,
+    /// Precision of the reference clock as log2 seconds
+    pub precision: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadError {
+    /// The feeder wrote the segment while it was being read
+    Clash,
+    /// The segment uses a mode other than 0 or 1
+    UnknownMode(i32),
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Clash => f.write_str("segment was written while it was read"),
+            ReadError::UnknownMode(mode) => write!(f, "unknown segment mode {mode}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Feeders that know the nanoseconds write both fields, older ones only
+/// write the microseconds
+fn nanos(micros: c_int, nanos: c_uint) -> u32 {
+    match u32::try_from(micros) {
+        Ok(micros) if nanos / 1000 == micros => nanos,
+        Ok(micros) => micros.saturating_mul(1000),
+        Err(_) => 0,
+    }
+}
+
+/// An attached segment
+#[derive(Debug)]
+pub struct Segment {
+    time: NonNull<ShmTime>,
+}
+
+// SAFETY: the segment is only accessed through volatile reads and writes,
+// which may happen from any thread
+unsafe impl Send for Segment {}
+
+impl Segment {
+    /// Attach the segment of `unit`, creating it when the feeder has not done
+    /// so yet. Like ntpd, units 0 and 1 are only accessible to the user of the
+    /// daemon and root, the others to everyone.
+    pub fn attach(unit: u8) -> std::io::Result<Segment> {
+        let permissions = if unit < 2 { 0o600 } else { 0o666 };
+        Self::attach_key(KEY_BASE + libc::key_t::from(unit), permissions)
+    }
+
+    fn attach_key(key: libc::key_t, permissions: c_int) -> std::io::Result<Segment> {
+        // SAFETY: shmget takes no pointers
+        let id = unsafe {
+            libc::shmget(
+                key,
+                std::mem::size_of::<ShmTime>(),
+                libc::IPC_CREAT | permissions,
+            )
+        };
+        if id == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Self::attach_id(id)
+    }
+
+    fn attach_id(id: c_int) -> std::io::Result<Segment> {
+        // SAFETY: we let the system pick the address to attach at
+        let address = unsafe { libc::shmat(id, std::ptr::null(), 0) };
+        if address as isize == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // shmat returns page aligned addresses, and shmget made sure the
+        // segment is at least as large as ShmTime
+        match NonNull::new(address.cast::<ShmTime>()) {
+            Some(time) => Ok(Segment { time }),
+            None => Err(std::io::Error::other("segment attached at null")),
+        }
+    }
+
+    /// Take the sample in the segment, if the feeder wrote a new one
+    #[allow(
+        clippy::useless_conversion,
+        reason = "time_t is only 64 bits on some targets"
+    )]
+    pub fn read(&mut self) -> Result<Option<Sample>, ReadError> {
+        let time = self.time.as_ptr();
+
+        // SAFETY: the segment stays attached for as long as we exist, and
+        // each field is read and written on its own, as the feeder can change
+        // any of them at any moment
+        unsafe {
+            if (&raw const (*time).valid).read_volatile() == 0 {
+                return Ok(None);
+            }
+
+            let mode = (&raw const (*time).mode).read_volatile();
+            let count = (&raw const (*time).count).read_volatile();
+            fence(Ordering::SeqCst);
+            let sample = Sample {
+                clock_seconds: (&raw const (*time).clock_seconds).read_volatile().into(),
+                clock_nanos: nanos(
+                    (&raw const (*time).clock_micros).read_volatile(),
+                    (&raw const (*time).clock_nanos).read_volatile(),
+                ),
+                receive_seconds: (&raw const (*time).receive_seconds).read_volatile().into(),
+                receive_nanos: nanos(
+                    (&raw const (*time).receive_micros).read_volatile(),
+                    (&raw const (*time).receive_nanos).read_volatile(),
+                ),
+                leap: (&raw const (*time).leap).read_volatile(),
+                precision: (&raw const (*time).precision).read_volatile(),
+            };
+            fence(Ordering::SeqCst);
+            let clash = (&raw const (*time).count).read_volatile() != count;
+            (&raw mut (*time).valid).write_volatile(0);
+
+            match mode {
+                0 => Ok(Some(sample)),
+                1 if clash => Err(ReadError::Clash),
+                1 => Ok(Some(sample)),
+                mode => Err(ReadError::UnknownMode(mode)),
+            }
+        }
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        // SAFETY: the segment was attached at this address, and is not used
+        // after this
+        unsafe {
+            libc::shmdt(self.time.as_ptr().cast());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A segment only we can see, which is removed once we detach
+    fn private_segment() -> Segment {
+        // SAFETY: shmget takes no pointers
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, std::mem::size_of::<ShmTime>(), 0o600) };
+        assert_ne!(id, -1);
+        let segment = Segment::attach_id(id).unwrap();
+        // SAFETY: removing the segment needs no buffer
+        unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+        segment
+    }
+
+    /// Write a sample the way a feeder does
+    fn write(
+        segment: &mut Segment,
+        mode: c_int,
+        clock: (time_t, c_uint),
+        receive: (time_t, c_uint),
+    ) {
+        let time = segment.time.as_ptr();
+        // SAFETY: the segment is private to this test
+        unsafe {
+            (*time).mode = mode;
+            (*time).count += 1;
+            (*time).clock_seconds = clock.0;
+            (*time).clock_micros = (clock.1 / 1000) as c_int;
+            (*time).clock_nanos = clock.1;
+            (*time).receive_seconds = receive.0;
+            (*time).receive_micros = (receive.1 / 1000) as c_int;
+            (*time).receive_nanos = receive.1;
+            (*time).leap = 0;
+            (*time).precision = -20;
+            (*time).count += 1;
+            (*time).valid = 1;
+        }
+    }
+
+    #[test]
+    fn test_nanos() {
+        assert_eq!(nanos(123_456, 123_456_789), 123_456_789);
+        // Feeders that only know microseconds leave the nanoseconds alone
+        assert_eq!(nanos(123_456, 0), 123_456_000);
+        assert_eq!(nanos(123_456, 999_000_000), 123_456_000);
+        assert_eq!(nanos(-1, 0), 0);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut segment = private_segment();
+        assert_eq!(segment.read(), Ok(None));
+
+        write(
+            &mut segment,
+            1,
+            (1_700_000_000, 500),
+            (1_700_000_000, 1_000_500),
+        );
+        assert_eq!(
+            segment.read(),
+            Ok(Some(Sample {
+                clock_seconds: 1_700_000_000,
+                clock_nanos: 500,
+                receive_seconds: 1_700_000_000,
+                receive_nanos: 1_000_500,
+                leap: 0,
+                precision: -20,
+            }))
+        );
+        // Every sample is only used once
+        assert_eq!(segment.read(), Ok(None));
+
+        write(&mut segment, 0, (1_700_000_001, 0), (1_700_000_001, 0));
+        assert!(matches!(segment.read(), Ok(Some(_))));
+
+        write(&mut segment, 2, (1_700_000_002, 0), (1_700_000_002, 0));
+        assert_eq!(segment.read(), Err(ReadError::UnknownMode(2)));
+        assert_eq!(segment.read(), Ok(None));
+    }
+}
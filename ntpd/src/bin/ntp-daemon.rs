@@ -2,7 +2,11 @@
 
 use std::process;
 
+use ntpd::exitcode;
+
 fn main() {
-    let result = ntpd::daemon_main();
-    process::exit(if result.is_ok() { 0 } else { 1 });
+    if let Err(e) = ntpd::daemon_main() {
+        eprintln!("ntp-daemon: {e}");
+        process::exit(exitcode::SOFTWARE);
+    }
 }
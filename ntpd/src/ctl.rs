@@ -1,11 +1,21 @@
 use std::{
+    net::SocketAddr,
     path::{Path, PathBuf},
     process::ExitCode,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
-    daemon::{Config, ObservableState, config::CliArg, tracing::LogLevel},
-    force_sync,
+    daemon::{
+        Config, ObservableState,
+        config::CliArg,
+        control::{ControlRequest, ControlResponse},
+        tracing::LogLevel,
+    },
+    exitcode, force_sync,
+};
+use ntp_proto::{
+    AeadAlgorithm, ClockId, NtpLeapIndicator, NtpTimestamp, ObservableSourceState, ReferenceId,
 };
 use tokio::runtime::Builder;
 use tracing_subscriber::util::SubscriberInitExt;
@@ -13,14 +23,20 @@ use tracing_subscriber::util::SubscriberInitExt;
 const USAGE_MSG: &str = "\
 usage: ntp-ctl validate [-c PATH]
        ntp-ctl status [-f FORMAT] [-c PATH]
+       ntp-ctl measurements [-f FORMAT] [-c PATH] SOURCE
+       ntp-ctl nts status [-f FORMAT] [-c PATH]
        ntp-ctl force-sync [-c PATH]
+       ntp-ctl add-source [-m MODE] [-c PATH] ADDRESS
+       ntp-ctl remove-source [-c PATH] ID
+       ntp-ctl burst [-c PATH] [ID]
        ntp-ctl -h | ntp-ctl -v";
 
 const DESCRIPTOR: &str = "ntp-ctl - ntp-daemon monitoring";
 
 const HELP_MSG: &str = "Options:
-  -f, --format=FORMAT                  which format to use for printing statistics [plain, prometheus]
+  -f, --format=FORMAT                  which format to use for printing statistics [plain, prometheus, json]
   -c, --config=CONFIG                  which configuration file to read the socket paths from
+  -m, --mode=MODE                      which kind of source to add [server, pool, nts, nts-pool]
   -h, --help                           display this help text
   -v, --version                        display version information";
 
@@ -33,6 +49,27 @@ enum Format {
     #[default]
     Plain,
     Prometheus,
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum SourceMode {
+    #[default]
+    Server,
+    Pool,
+    Nts,
+    NtsPool,
+}
+
+impl SourceMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            SourceMode::Server => "server",
+            SourceMode::Pool => "pool",
+            SourceMode::Nts => "nts",
+            SourceMode::NtsPool => "nts-pool",
+        }
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -43,25 +80,38 @@ pub enum NtpCtlAction {
     Validate,
     Status,
     ForceSync,
+    AddSource,
+    RemoveSource,
+    Burst,
+    Measurements,
+    NtsStatus,
 }
 
 #[derive(Debug, Default)]
 pub(crate) struct NtpCtlOptions {
     config: Option<PathBuf>,
     format: Format,
+    mode: SourceMode,
     help: bool,
     version: bool,
     validate: bool,
     status: bool,
     force_sync: bool,
+    add_source: Option<String>,
+    remove_source: Option<ClockId>,
+    burst: bool,
+    burst_source: Option<ClockId>,
+    measurements: Option<String>,
+    nts_status: bool,
     action: NtpCtlAction,
 }
 
 impl NtpCtlOptions {
-    const TAKES_ARGUMENT: &'static [&'static str] = &["--config", "--format"];
-    const TAKES_ARGUMENT_SHORT: &'static [char] = &['c', 'f'];
+    const TAKES_ARGUMENT: &'static [&'static str] = &["--config", "--format", "--mode"];
+    const TAKES_ARGUMENT_SHORT: &'static [char] = &['c', 'f', 'm'];
 
     /// parse an iterator over command line arguments
+    #[expect(clippy::too_many_lines)]
     pub fn try_parse_from<I, T>(iter: I) -> Result<Self, String>
     where
         I: IntoIterator<Item = T>,
@@ -96,17 +146,25 @@ impl NtpCtlOptions {
                     "-f" | "--format" => match value.as_str() {
                         "plain" => options.format = Format::Plain,
                         "prometheus" => options.format = Format::Prometheus,
+                        "json" => options.format = Format::Json,
                         _ => Err(format!("invalid format option provided: {value}"))?,
                     },
+                    "-m" | "--mode" => match value.as_str() {
+                        "server" => options.mode = SourceMode::Server,
+                        "pool" => options.mode = SourceMode::Pool,
+                        "nts" => options.mode = SourceMode::Nts,
+                        "nts-pool" => options.mode = SourceMode::NtsPool,
+                        _ => Err(format!("invalid mode option provided: {value}"))?,
+                    },
                     option => {
                         Err(format!("invalid option provided: {option}"))?;
                     }
                 },
                 CliArg::Rest(rest) => {
-                    if rest.len() > 1 {
-                        eprintln!("Warning: Too many commands provided.");
-                    }
-                    for command in rest {
+                    let mut rest = rest.into_iter();
+                    let mut commands = 0;
+                    while let Some(command) = rest.next() {
+                        commands += 1;
                         match command.as_str() {
                             "validate" => {
                                 options.validate = true;
@@ -117,11 +175,55 @@ impl NtpCtlOptions {
                             "force-sync" => {
                                 options.force_sync = true;
                             }
+                            "add-source" => {
+                                let address = rest
+                                    .next()
+                                    .ok_or("add-source requires the address of the source")?;
+                                options.add_source = Some(address);
+                            }
+                            "remove-source" => {
+                                let id = rest
+                                    .next()
+                                    .ok_or("remove-source requires the id of the source")?;
+                                let id = id
+                                    .parse()
+                                    .map_err(|_| format!("invalid source id provided: {id}"))?;
+                                options.remove_source = Some(id);
+                            }
+                            "burst" => {
+                                options.burst = true;
+                                if let Some(id) = rest.next() {
+                                    let id = id
+                                        .parse()
+                                        .map_err(|_| format!("invalid source id provided: {id}"))?;
+                                    options.burst_source = Some(id);
+                                }
+                            }
+                            "measurements" => {
+                                let source = rest.next().ok_or(
+                                    "measurements requires the name, address or id of the source",
+                                )?;
+                                options.measurements = Some(source);
+                            }
+                            "nts" => match rest.next().as_deref() {
+                                Some("status") => {
+                                    options.nts_status = true;
+                                }
+                                Some(command) => {
+                                    Err(format!("invalid nts command provided: {command}"))?;
+                                }
+                                None => {
+                                    Err("nts requires a command: status")?;
+                                }
+                            },
                             unknown => {
                                 eprintln!("Warning: Unknown command {unknown}");
                             }
                         }
                     }
+                    if commands > 1 {
+                        eprintln!("Warning: Too many commands provided.");
+                    }
                 }
             }
         }
@@ -144,6 +246,16 @@ impl NtpCtlOptions {
             self.action = NtpCtlAction::Status;
         } else if self.force_sync {
             self.action = NtpCtlAction::ForceSync;
+        } else if self.add_source.is_some() {
+            self.action = NtpCtlAction::AddSource;
+        } else if self.remove_source.is_some() {
+            self.action = NtpCtlAction::RemoveSource;
+        } else if self.burst {
+            self.action = NtpCtlAction::Burst;
+        } else if self.measurements.is_some() {
+            self.action = NtpCtlAction::Measurements;
+        } else if self.nts_status {
+            self.action = NtpCtlAction::NtsStatus;
         } else {
             self.action = NtpCtlAction::Help;
         }
@@ -161,12 +273,12 @@ fn validate(config: Option<&Path>) -> std::io::Result<ExitCode> {
                 eprintln!("Config looks good");
                 Ok(ExitCode::SUCCESS)
             } else {
-                Ok(ExitCode::FAILURE)
+                Ok(exitcode::to_exit_code(exitcode::CONFIG))
             }
         }
         Err(e) => {
             eprintln!("Error: Could not load configuration: {e}");
-            Ok(ExitCode::FAILURE)
+            Ok(exitcode::to_exit_code(exitcode::CONFIG))
         }
     }
 }
@@ -176,7 +288,10 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub fn main() -> std::io::Result<ExitCode> {
     let options = match NtpCtlOptions::try_parse_from(std::env::args()) {
         Ok(options) => options,
-        Err(msg) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)),
+        Err(msg) => {
+            eprintln!("Error: {msg}");
+            return Ok(exitcode::to_exit_code(exitcode::USAGE));
+        }
     };
 
     match options.action {
@@ -191,6 +306,42 @@ pub fn main() -> std::io::Result<ExitCode> {
         NtpCtlAction::Validate => validate(options.config.as_deref()),
         NtpCtlAction::ForceSync => force_sync::force_sync(options.config.as_deref()),
         NtpCtlAction::Status => {
+            let observation = observation_path(options.config.as_ref());
+
+            Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(print_state(options.format, observation))
+        }
+        NtpCtlAction::Measurements => {
+            let observation = observation_path(options.config.as_ref());
+            // The action is only selected when a source was given
+            let source = options.measurements.unwrap_or_default();
+
+            Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(print_measurements(options.format, &source, observation))
+        }
+        NtpCtlAction::NtsStatus => {
+            let observation = observation_path(options.config.as_ref());
+
+            Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(print_nts_status(options.format, observation))
+        }
+        NtpCtlAction::AddSource | NtpCtlAction::RemoveSource | NtpCtlAction::Burst => {
+            let request = match (options.add_source, options.remove_source) {
+                (Some(address), _) => ControlRequest::AddSource {
+                    source: source_toml(options.mode, &address),
+                },
+                (None, Some(id)) => ControlRequest::RemoveSource { id },
+                (None, None) => ControlRequest::Burst {
+                    id: options.burst_source,
+                },
+            };
+
             let config = Config::from_args(options.config.as_ref(), vec![], vec![]);
 
             if let Err(ref e) = config {
@@ -199,49 +350,138 @@ pub fn main() -> std::io::Result<ExitCode> {
 
             let config = config.unwrap_or_default();
 
-            let observation = config
+            let control = config
                 .observability
-                .observation_path
-                .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/observe"));
+                .control_path
+                .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/control"));
 
             Builder::new_current_thread()
                 .enable_all()
                 .build()?
-                .block_on(async {
-                    match options.format {
-                        Format::Plain => print_state(Format::Plain, observation).await,
-                        Format::Prometheus => print_state(Format::Prometheus, observation).await,
-                    }
-                })
+                .block_on(send_control(request, control))
         }
     }
 }
 
-async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode, std::io::Error> {
-    let mut stream = match tokio::net::UnixStream::connect(&observe_socket).await {
+fn observation_path(config: Option<&PathBuf>) -> PathBuf {
+    let config = Config::from_args(config, vec![], vec![]);
+
+    // Keep stdout clean for machine readable output formats
+    if let Err(ref e) = config {
+        eprintln!("Warning: Unable to load configuration file: {e}");
+    }
+
+    let config = config.unwrap_or_default();
+
+    config
+        .observability
+        .observation_path
+        .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/observe"))
+}
+
+/// Describe a source in the same way as a `[[source]]` table in the configuration
+fn source_toml(mode: SourceMode, address: &str) -> String {
+    format!(
+        "mode = {}\naddress = {}\n",
+        toml_string(mode.as_str()),
+        toml_string(address)
+    )
+}
+
+/// Quote a value as a TOML basic string
+fn toml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+async fn send_control(
+    request: ControlRequest,
+    control_socket: PathBuf,
+) -> Result<ExitCode, std::io::Error> {
+    let mut stream = match tokio::net::UnixStream::connect(&control_socket).await {
         Ok(stream) => stream,
         Err(e) => {
-            eprintln!("Could not open socket at {}: {e}", observe_socket.display(),);
-            return Ok(ExitCode::FAILURE);
+            eprintln!("Could not open socket at {}: {e}", control_socket.display());
+            return Ok(exitcode::to_exit_code(exitcode::UNAVAILABLE));
         }
     };
 
-    let mut msg = Vec::with_capacity(16 * 1024);
-    let mut output =
-        match crate::daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
-            Ok(output) => output,
+    crate::daemon::sockets::write_json(&mut stream, &request).await?;
+
+    let mut msg = Vec::with_capacity(1024);
+    let response =
+        match crate::daemon::sockets::read_json::<ControlResponse>(&mut stream, &mut msg).await {
+            Ok(response) => response,
             Err(e) => {
-                eprintln!("Failed to read state from observation socket: {e}");
+                eprintln!("Failed to read response from control socket: {e}");
 
-                return Ok(ExitCode::FAILURE);
+                return Ok(exitcode::to_exit_code(exitcode::PROTOCOL));
             }
         };
 
+    match response {
+        ControlResponse::SourceAdded => {
+            println!("Source added");
+            Ok(ExitCode::SUCCESS)
+        }
+        ControlResponse::SourceRemoved => {
+            println!("Source removed");
+            Ok(ExitCode::SUCCESS)
+        }
+        ControlResponse::BurstStarted { sources } => {
+            println!("Burst requested from {sources} source(s)");
+            Ok(ExitCode::SUCCESS)
+        }
+        ControlResponse::Error { message } => {
+            eprintln!("Error: {message}");
+            Ok(exitcode::to_exit_code(exitcode::FAILURE))
+        }
+    }
+}
+
+async fn read_state(observe_socket: &Path) -> Result<ObservableState, ExitCode> {
+    let mut stream = match tokio::net::UnixStream::connect(observe_socket).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Could not open socket at {}: {e}", observe_socket.display(),);
+            return Err(exitcode::to_exit_code(exitcode::UNAVAILABLE));
+        }
+    };
+
+    let mut msg = Vec::with_capacity(16 * 1024);
+    match crate::daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
+        Ok(output) => Ok(output),
+        Err(e) => {
+            eprintln!("Failed to read state from observation socket: {e}");
+
+            Err(exitcode::to_exit_code(exitcode::PROTOCOL))
+        }
+    }
+}
+
+async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode, std::io::Error> {
+    let mut output = match read_state(&observe_socket).await {
+        Ok(output) => output,
+        Err(code) => return Ok(code),
+    };
+
     match print {
         Format::Plain => {
             // Sort sources by address and then id (to deal with pools), servers just by address
             output.sources.sort_by_key(|s| (s.name.clone(), s.id));
             output.servers.sort_by_key(|s| s.address);
+            output.key_exchange_servers.sort_by_key(|s| s.address);
+            output.spawners.sort_by_key(|s| s.address.clone());
             print_state_plain(&output);
         }
         Format::Prometheus => {
@@ -249,16 +489,241 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
             if let Err(e) = crate::metrics::format_state(&mut buf, &output) {
                 eprintln!("Failed to encode prometheus data: {e}");
 
-                return Ok(ExitCode::FAILURE);
+                return Ok(exitcode::to_exit_code(exitcode::SOFTWARE));
             }
 
             println!("{buf}");
         }
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Whether `selector` is the id, name or address of the source
+fn source_matches(source: &ObservableSourceState, selector: &str) -> bool {
+    source.id.to_string() == selector || source.name == selector || source.address == selector
+}
+
+async fn print_measurements(
+    print: Format,
+    selector: &str,
+    observe_socket: PathBuf,
+) -> Result<ExitCode, std::io::Error> {
+    let output = match read_state(&observe_socket).await {
+        Ok(output) => output,
+        Err(code) => return Ok(code),
+    };
+
+    // A pool name matches all sources of the pool
+    let mut sources: Vec<_> = output
+        .sources
+        .iter()
+        .filter(|source| source_matches(source, selector))
+        .collect();
+    if sources.is_empty() {
+        eprintln!("Error: No source matching {selector}");
+        return Ok(exitcode::to_exit_code(exitcode::FAILURE));
+    }
+    sources.sort_by_key(|s| (s.name.clone(), s.id));
+
+    match print {
+        Format::Plain => {
+            for source in sources {
+                print_measurements_plain(source, output.program.now);
+            }
+        }
+        Format::Json => {
+            let sources: Vec<_> = sources
+                .into_iter()
+                .map(|source| {
+                    serde_json::json!({
+                        "id": source.id,
+                        "name": source.name,
+                        "address": source.address,
+                        "measurements": source.measurements,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&sources)?);
+        }
+        Format::Prometheus => {
+            eprintln!("Error: Measurements can not be printed in the prometheus format");
+            return Ok(exitcode::to_exit_code(exitcode::USAGE));
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_measurements_plain(source: &ObservableSourceState, now: NtpTimestamp) {
+    println!("{} {} ({})", source.name, source.address, source.id);
+    if source.measurements.is_empty() {
+        println!("\tNo measurements");
+        println!();
+        return;
+    }
+    println!(
+        "\t{:>10} {:>12} {:>10} {:>10} {:>10} {:>7} {:>10} {:>8}",
+        "Age", "Offset", "Delay", "Root delay", "Root disp", "Stratum", "Leap", "Status"
+    );
+    // Most recent measurement first
+    for measurement in source.measurements.iter().rev() {
+        println!(
+            "\t{:>9.0}s {:>+11.6}s {:>10} {:>9.6}s {:>9.6}s {:>7} {:>10} {:>8}",
+            (now - measurement.local_receive).to_seconds(),
+            measurement.offset.to_seconds(),
+            measurement
+                .delay
+                .map_or_else(|| "-".into(), |delay| format!("{:.6}s", delay.to_seconds())),
+            measurement.root_delay.to_seconds(),
+            measurement.root_dispersion.to_seconds(),
+            measurement.stratum,
+            match measurement.leap {
+                NtpLeapIndicator::NoWarning => "none",
+                NtpLeapIndicator::Leap61 => "insert",
+                NtpLeapIndicator::Leap59 => "delete",
+                NtpLeapIndicator::Unknown | NtpLeapIndicator::Unsynchronized => "unknown",
+            },
+            if measurement.accepted {
+                "accepted"
+            } else {
+                "rejected"
+            },
+        );
+    }
+    println!();
+}
+
+async fn print_nts_status(
+    print: Format,
+    observe_socket: PathBuf,
+) -> Result<ExitCode, std::io::Error> {
+    let output = match read_state(&observe_socket).await {
+        Ok(output) => output,
+        Err(code) => return Ok(code),
+    };
+
+    let mut sources: Vec<_> = output
+        .sources
+        .iter()
+        .filter(|source| source.nts_cookies.is_some())
+        .collect();
+    sources.sort_by_key(|s| (s.name.clone(), s.id));
+
+    match print {
+        Format::Plain => {
+            if sources.is_empty() {
+                println!("No NTS sources");
+            }
+            for source in sources {
+                print_nts_status_plain(source, SystemTime::now());
+            }
+        }
+        Format::Json => {
+            let sources: Vec<_> = sources
+                .into_iter()
+                .map(|source| {
+                    serde_json::json!({
+                        "id": source.id,
+                        "name": source.name,
+                        "address": source.address,
+                        "nts_cookies": source.nts_cookies,
+                        "nts_key_exchange": source.nts_key_exchange,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&sources)?);
+        }
+        Format::Prometheus => {
+            eprintln!("Error: NTS status can not be printed in the prometheus format");
+            return Ok(exitcode::to_exit_code(exitcode::USAGE));
+        }
     }
 
     Ok(ExitCode::SUCCESS)
 }
 
+/// Approximate length of `duration` in its two largest units
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {}s", seconds % 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+fn print_nts_status_plain(source: &ObservableSourceState, now: SystemTime) {
+    println!("{} {} ({})", source.name, source.address, source.id);
+    if let Some(nts_cookies) = source.nts_cookies {
+        println!(
+            "\tCookies:\t\t{}/{} available{}",
+            nts_cookies,
+            ntp_proto::MAX_COOKIES,
+            if nts_cookies == 0 {
+                ", a new key exchange is needed"
+            } else {
+                ""
+            }
+        );
+    }
+    let Some(key_exchange) = &source.nts_key_exchange else {
+        println!();
+        return;
+    };
+    println!("\tKey exchange server:\t{}", key_exchange.server);
+    println!(
+        "\tLast key exchange:\t{} ago",
+        format_duration(now.duration_since(key_exchange.time).unwrap_or_default())
+    );
+    println!("\tAEAD algorithm:\t\t{}", key_exchange.algorithm);
+    println!(
+        "\tTLS handshake:\t\t{}",
+        if key_exchange.resumed {
+            "resumed"
+        } else {
+            "full"
+        }
+    );
+    match key_exchange.certificate_expiry {
+        Some(expiry) => match expiry.duration_since(now) {
+            Ok(remaining) => println!("\tCertificate expires:\tin {}", format_duration(remaining)),
+            Err(e) => println!(
+                "\tCertificate expired:\t{} ago",
+                format_duration(e.duration())
+            ),
+        },
+        None => println!("\tCertificate expires:\tunknown"),
+    }
+    println!();
+}
+
+/// Human readable reference id of the system. For IPv6 sources it only
+/// contains a hash of the address, so look for the matching source.
+fn system_reference(output: &ObservableState) -> String {
+    let snapshot = output.system.ntp_snapshot;
+    if (2..=15).contains(&snapshot.stratum) {
+        for source in &output.sources {
+            if let Ok(addr) = source.address.parse::<SocketAddr>()
+                && ReferenceId::from_ip(addr.ip()) == snapshot.reference_id
+            {
+                return addr.ip().to_string();
+            }
+        }
+    }
+
+    snapshot.reference_id.display(snapshot.stratum).to_string()
+}
+
+#[expect(clippy::too_many_lines)]
 fn print_state_plain(output: &ObservableState) {
     println!("Synchronization status:");
     println!(
@@ -274,6 +739,21 @@ fn print_state_plain(output: &ObservableState) {
         output.system.time_snapshot.root_delay.to_seconds()
     );
     println!("\tStratum:\t{}", output.system.ntp_snapshot.stratum);
+    println!("\tReference ID:\t{}", system_reference(output));
+    if let Some(smear) = output.system.time_snapshot.leap_smear {
+        println!(
+            "\tLeap smear:\t{} {:.1}% done, {:+.6}s",
+            match smear.leap {
+                NtpLeapIndicator::Leap59 => "deleting",
+                _ => "inserting",
+            },
+            smear.progress * 100.0,
+            smear.offset.to_seconds(),
+        );
+    }
+    if let Some(reason) = output.system.time_snapshot.steering_withheld {
+        println!("\tNot steering:\t{reason}");
+    }
     println!();
     println!();
     println!("Sources:");
@@ -284,7 +764,11 @@ fn print_state_plain(output: &ObservableState) {
             //source.id,
             source.name,
             source.address,
-            source.nts_cookies.map_or("", |_| " [NTS]"),
+            if source.nts_degraded {
+                " [NTS failed, unauthenticated]"
+            } else {
+                source.nts_cookies.map_or("", |_| " [NTS]")
+            },
             source.id,
         );
         println!("\tOffset:\t\t\t{:+.6}", source.timedata.offset.to_seconds());
@@ -293,12 +777,66 @@ fn print_state_plain(output: &ObservableState) {
             source.timedata.uncertainty.to_seconds()
         );
         println!("\tDelay:\t\t\t±{:.6}", source.timedata.delay.to_seconds());
+        if let Some(kalman) = source.timedata.kalman {
+            println!(
+                "\tFrequency:\t\t{:+.3}ppm ±{:.3}ppm",
+                kalman.state[1] * 1e6,
+                kalman.covariance[1][1].sqrt() * 1e6,
+            );
+            println!(
+                "\tMeasurement noise:\t±{:.6}",
+                kalman.measurement_noise.sqrt()
+            );
+            println!("\tWander:\t\t\t{:.3e}", kalman.wander);
+            if kalman.rejected_outliers > 0 {
+                println!("\tRejected outliers:\t{}", kalman.rejected_outliers);
+            }
+            if kalman.frequency_steps > 0 {
+                println!("\tFrequency steps:\t{}", kalman.frequency_steps);
+            }
+            if let Some(interval) = kalman.wander_poll_interval {
+                println!(
+                    "\tWander poll interval:\t{interval:.0}s (score {:+})",
+                    kalman.poll_score
+                );
+            }
+        }
 
+        match source.target_poll_interval {
+            Some(target) if target != source.poll_interval => println!(
+                "\tPoll interval:\t\t{:.0}s (next {:.0}s)",
+                source.poll_interval.as_duration().to_seconds(),
+                target.as_duration().to_seconds(),
+            ),
+            _ => println!(
+                "\tPoll interval:\t\t{:.0}s",
+                source.poll_interval.as_duration().to_seconds(),
+            ),
+        }
+        println!("\tMissing polls:\t\t{}", source.unanswered_polls,);
+        if let Some(reach) = source.reach {
+            // Octal, like the reach column of ntpq and chronyc
+            println!("\tReach:\t\t\t{reach:03o}");
+        }
+        if let Some(code) = &source.last_kiss_code {
+            println!("\tLast kiss code:\t\t{code}");
+        }
+        if source.authentication_failures > 0 {
+            println!("\tAuth failures:\t\t{}", source.authentication_failures);
+        }
+        if let Some(ntp_version) = source.ntp_version {
+            println!("\tNTP version:\t\t{ntp_version}");
+        }
+        if let (Some(stratum), Some(reference_id)) = (source.stratum, source.reference_id) {
+            println!("\tStratum:\t\t{stratum}");
+            println!("\tReference ID:\t\t{}", reference_id.display(stratum));
+        }
         println!(
-            "\tPoll interval:\t\t{:.0}s",
-            source.poll_interval.as_duration().to_seconds(),
+            "\tWeight:\t\t\t{}{}{}",
+            source.weight,
+            if source.prefer { " (preferred)" } else { "" },
+            if source.noselect { " (noselect)" } else { "" }
         );
-        println!("\tMissing polls:\t\t{}", source.unanswered_polls,);
         println!(
             "\tRoot dispersion:\t{:.6}s",
             source.timedata.remote_uncertainty.to_seconds(),
@@ -307,6 +845,10 @@ fn print_state_plain(output: &ObservableState) {
             "\tRoot delay:\t\t{:.6}s",
             source.timedata.remote_delay.to_seconds()
         );
+        println!(
+            "\tRoot distance:\t\t{:.6}s",
+            source.timedata.root_distance().to_seconds()
+        );
         if let Some(nts_cookies) = source.nts_cookies {
             println!(
                 "\tNTS cookies:\t\t{}/{} available",
@@ -315,6 +857,17 @@ fn print_state_plain(output: &ObservableState) {
             );
         }
     }
+    if !output.spawners.is_empty() {
+        println!();
+        println!();
+        println!("Waiting to retry:");
+    }
+    for spawner in &output.spawners {
+        println!();
+        println!("{} ({})", spawner.address, spawner.kind);
+        println!("\tFailures:\t\t{}", spawner.failures);
+        println!("\tNext attempt in:\t{:.0}s", spawner.retry_in);
+    }
     if !output.servers.is_empty() {
         println!();
         println!();
@@ -329,6 +882,10 @@ fn print_state_plain(output: &ObservableState) {
             server.stats.response_send_errors.get()
         );
         println!("\tNTS NAK\t\t\t{}", server.stats.nts_nak_packets.get());
+        println!(
+            "\tAuth failures\t\t{}",
+            server.stats.authentication_failures.get()
+        );
         println!("\tReceived\t\t{}", server.stats.received_packets.get());
         println!("\tAccepted\t\t{}", server.stats.accepted_packets.get());
         println!("\tDenied\t\t\t{}", server.stats.denied_packets.get());
@@ -349,6 +906,50 @@ fn print_state_plain(output: &ObservableState) {
             "\tNTS Rate limited\t{}",
             server.stats.nts_rate_limited_packets.get()
         );
+        println!(
+            "\tBroadcasts sent\t\t{}",
+            server.stats.broadcast_packets.get()
+        );
+        println!();
+    }
+    if !output.key_exchange_servers.is_empty() {
+        println!();
+        println!("Key exchange servers:");
+    }
+    for server in &output.key_exchange_servers {
+        println!();
+        println!("{}", server.address);
+        println!("\tAccepted\t\t{}", server.stats.accepted_connections.get());
+        println!(
+            "\tConnection limited\t{}",
+            server.stats.connection_limited_connections.get()
+        );
+        println!(
+            "\tRate limited\t\t{}",
+            server.stats.rate_limited_connections.get()
+        );
+        println!("\tCompleted\t\t{}", server.stats.completed_handshakes.get());
+        println!("\tFailed\t\t\t{}", server.stats.failed_handshakes.get());
+        println!("\tCookies issued\t\t{}", server.stats.cookies_issued.get());
+        for algorithm in AeadAlgorithm::SUPPORTED {
+            println!(
+                "\t{:<16}\t{}",
+                algorithm.to_string(),
+                server.stats.algorithms.get(algorithm)
+            );
+        }
+        let master_key_created = server.stats.master_key_created.get();
+        if master_key_created != 0 {
+            let created = SystemTime::UNIX_EPOCH + Duration::from_secs(master_key_created);
+            println!(
+                "\tMaster key age\t\t{}",
+                format_duration(
+                    SystemTime::now()
+                        .duration_since(created)
+                        .unwrap_or_default()
+                )
+            );
+        }
         println!();
     }
 }
@@ -375,6 +976,16 @@ mod tests {
         command: Format,
         value: T,
     ) -> std::io::Result<Result<ExitCode, std::io::Error>> {
+        observe_socket_helper(value, |path| super::print_state(command, path)).await
+    }
+
+    async fn observe_socket_helper<T: serde::Serialize, F>(
+        value: T,
+        print: impl FnOnce(PathBuf) -> F,
+    ) -> std::io::Result<Result<ExitCode, std::io::Error>>
+    where
+        F: Future<Output = Result<ExitCode, std::io::Error>> + Send + 'static,
+    {
         let config: ObservabilityConfig = ObservabilityConfig::default();
 
         // be careful with copying: tests run concurrently and should use a unique socket name!
@@ -388,7 +999,7 @@ mod tests {
 
         let sources_listener = create_unix_socket_with_permissions(&path, permissions)?;
 
-        let fut = super::print_state(command, path);
+        let fut = print(path);
         let handle = tokio::spawn(fut);
 
         let (mut stream, _addr) = sources_listener.accept().await?;
@@ -406,6 +1017,8 @@ mod tests {
             system: SystemSnapshot::default(),
             sources: vec![],
             servers: vec![],
+            key_exchange_servers: vec![],
+            spawners: vec![],
         };
         let result = write_socket_helper(Format::Plain, value).await?;
 
@@ -424,6 +1037,8 @@ mod tests {
             system: SystemSnapshot::default(),
             sources: vec![],
             servers: vec![],
+            key_exchange_servers: vec![],
+            spawners: vec![],
         };
         let result = write_socket_helper(Format::Prometheus, value).await?;
 
@@ -435,6 +1050,203 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_control_socket_json() -> std::io::Result<()> {
+        let value = ObservableState {
+            program: ProgramData::default(),
+            system: SystemSnapshot::default(),
+            sources: vec![],
+            servers: vec![],
+            key_exchange_servers: vec![],
+            spawners: vec![],
+        };
+        let result = write_socket_helper(Format::Json, value).await?;
+
+        assert_eq!(
+            format!("{:?}", result.unwrap()),
+            format!("{:?}", ExitCode::SUCCESS)
+        );
+
+        Ok(())
+    }
+
+    fn source_with_measurements() -> ObservableSourceState {
+        let measurement = ntp_proto::ObservableMeasurement {
+            local_send: None,
+            remote_receive: None,
+            remote_transmit: NtpTimestamp::default(),
+            local_receive: NtpTimestamp::default(),
+            offset: ntp_proto::NtpDuration::from_seconds(0.001),
+            delay: None,
+            root_delay: ntp_proto::NtpDuration::ZERO,
+            root_dispersion: ntp_proto::NtpDuration::ZERO,
+            stratum: 0,
+            leap: NtpLeapIndicator::NoWarning,
+            accepted: true,
+        };
+        ObservableSourceState {
+            timedata: ntp_proto::ObservableSourceTimedata::default(),
+            unanswered_polls: 0,
+            poll_interval: ntp_proto::PollIntervalLimits::default().min,
+            nts_cookies: None,
+            name: "Socket".into(),
+            address: "/run/gpsd.sock".into(),
+            id: "7".parse().unwrap(),
+            weight: 1.0,
+            prefer: false,
+            noselect: false,
+            ntp_version: None,
+            stratum: None,
+            reference_id: None,
+            reach: None,
+            target_poll_interval: None,
+            last_kiss_code: None,
+            measurements: vec![measurement; 3],
+            nts_key_exchange: None,
+            authentication_failures: 0,
+            nts_degraded: false,
+        }
+    }
+
+    fn nts_source() -> ObservableSourceState {
+        ObservableSourceState {
+            name: "time.example.com".into(),
+            address: "192.0.2.1:123".into(),
+            id: "8".parse().unwrap(),
+            nts_cookies: Some(6),
+            nts_key_exchange: Some(ntp_proto::ObservableKeyExchange {
+                server: "time.example.com".into(),
+                time: SystemTime::now(),
+                algorithm: ntp_proto::AeadAlgorithm::AeadAesSivCmac512,
+                certificate_expiry: Some(SystemTime::now() + Duration::from_secs(86400 * 30)),
+                resumed: false,
+            }),
+            measurements: vec![],
+            ..source_with_measurements()
+        }
+    }
+
+    #[test]
+    fn test_system_reference() {
+        let ipv6_source = ObservableSourceState {
+            address: "[2001:db8::1]:123".into(),
+            ..source_with_measurements()
+        };
+        let mut output = ObservableState {
+            program: ProgramData::default(),
+            system: SystemSnapshot::default(),
+            sources: vec![source_with_measurements(), ipv6_source],
+            servers: vec![],
+            key_exchange_servers: vec![],
+            spawners: vec![],
+        };
+
+        output.system.ntp_snapshot.stratum = 3;
+        output.system.ntp_snapshot.reference_id =
+            ReferenceId::from_ip("2001:db8::1".parse().unwrap());
+        assert_eq!(system_reference(&output), "2001:db8::1");
+
+        output.system.ntp_snapshot.reference_id =
+            ReferenceId::from_ip("192.0.2.1".parse().unwrap());
+        assert_eq!(system_reference(&output), "192.0.2.1");
+
+        output.system.ntp_snapshot.stratum = 1;
+        output.system.ntp_snapshot.reference_id = ReferenceId::PPS;
+        assert_eq!(system_reference(&output), "PPS");
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_measurements() -> std::io::Result<()> {
+        for (format, selector, expected) in [
+            (Format::Plain, "7", ExitCode::SUCCESS),
+            (Format::Json, "/run/gpsd.sock", ExitCode::SUCCESS),
+            (
+                Format::Plain,
+                "8",
+                exitcode::to_exit_code(exitcode::FAILURE),
+            ),
+            (
+                Format::Prometheus,
+                "7",
+                exitcode::to_exit_code(exitcode::USAGE),
+            ),
+        ] {
+            let value = ObservableState {
+                program: ProgramData::default(),
+                system: SystemSnapshot::default(),
+                sources: vec![source_with_measurements()],
+                servers: vec![],
+                key_exchange_servers: vec![],
+                spawners: vec![],
+            };
+            let result = observe_socket_helper(value, |path| {
+                super::print_measurements(format, selector, path)
+            })
+            .await?;
+
+            assert_eq!(format!("{:?}", result.unwrap()), format!("{:?}", expected));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_nts_status() -> std::io::Result<()> {
+        for (format, expected) in [
+            (Format::Plain, ExitCode::SUCCESS),
+            (Format::Json, ExitCode::SUCCESS),
+            (Format::Prometheus, exitcode::to_exit_code(exitcode::USAGE)),
+        ] {
+            let value = ObservableState {
+                program: ProgramData::default(),
+                system: SystemSnapshot::default(),
+                sources: vec![source_with_measurements(), nts_source()],
+                servers: vec![],
+                key_exchange_servers: vec![],
+                spawners: vec![],
+            };
+            let result =
+                observe_socket_helper(value, |path| super::print_nts_status(format, path)).await?;
+
+            assert_eq!(format!("{:?}", result.unwrap()), format!("{:?}", expected));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(12)), "12s");
+        assert_eq!(format_duration(Duration::from_secs(62)), "1m 2s");
+        assert_eq!(format_duration(Duration::from_secs(7320)), "2h 2m");
+        assert_eq!(
+            format_duration(Duration::from_secs(86400 * 3 + 3600)),
+            "3d 1h"
+        );
+    }
+
+    #[test]
+    fn test_nts_key_exchange_json_roundtrip() {
+        let source = nts_source();
+        let json = serde_json::to_string(&source).unwrap();
+        let parsed: ObservableSourceState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.nts_key_exchange, source.nts_key_exchange);
+
+        // Older daemons do not send any key exchange details
+        let json = serde_json::to_string(&source_with_measurements()).unwrap();
+        let parsed: ObservableSourceState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.nts_key_exchange, None);
+    }
+
+    #[test]
+    fn test_source_matches() {
+        let source = source_with_measurements();
+        assert!(source_matches(&source, "7"));
+        assert!(source_matches(&source, "Socket"));
+        assert!(source_matches(&source, "/run/gpsd.sock"));
+        assert!(!source_matches(&source, "8"));
+    }
+
     #[tokio::test]
     async fn test_control_socket_source_invalid_input() -> std::io::Result<()> {
         let value = 42u32;
@@ -442,7 +1254,7 @@ mod tests {
 
         assert_eq!(
             format!("{:?}", result.unwrap()),
-            format!("{:?}", ExitCode::FAILURE)
+            format!("{:?}", exitcode::to_exit_code(exitcode::PROTOCOL))
         );
 
         Ok(())
@@ -470,8 +1282,104 @@ mod tests {
         let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
         assert_eq!(options.format, Format::Prometheus);
 
+        let arguments = &[BINARY, "-f", "json"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.format, Format::Json);
+
         let arguments = &[BINARY, "-f", "yaml"];
         let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
         assert_eq!(err, "invalid format option provided: yaml");
     }
+
+    #[test]
+    fn cli_add_source() {
+        let arguments = &[BINARY, "add-source", "ntp.example.com"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::AddSource);
+        assert_eq!(options.add_source.as_deref(), Some("ntp.example.com"));
+        assert_eq!(options.mode, SourceMode::Server);
+
+        let arguments = &[BINARY, "add-source", "-m", "nts-pool", "pool.example.com"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::AddSource);
+        assert_eq!(options.mode, SourceMode::NtsPool);
+
+        let arguments = &[BINARY, "add-source"];
+        assert!(NtpCtlOptions::try_parse_from(arguments).is_err());
+
+        let arguments = &[BINARY, "-m", "sock", "add-source", "ntp.example.com"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid mode option provided: sock");
+    }
+
+    #[test]
+    fn cli_remove_source() {
+        let arguments = &[BINARY, "remove-source", "42"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::RemoveSource);
+        assert_eq!(options.remove_source, "42".parse().ok());
+
+        let arguments = &[BINARY, "remove-source", "foo"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid source id provided: foo");
+    }
+
+    #[test]
+    fn cli_burst() {
+        let arguments = &[BINARY, "burst"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Burst);
+        assert_eq!(options.burst_source, None);
+
+        let arguments = &[BINARY, "burst", "42"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Burst);
+        assert_eq!(options.burst_source, "42".parse().ok());
+
+        let arguments = &[BINARY, "burst", "foo"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid source id provided: foo");
+    }
+
+    #[test]
+    fn cli_measurements() {
+        let arguments = &[BINARY, "measurements", "-f", "json", "ntp.example.com:123"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Measurements);
+        assert_eq!(options.measurements.as_deref(), Some("ntp.example.com:123"));
+        assert_eq!(options.format, Format::Json);
+
+        let arguments = &[BINARY, "measurements"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(
+            err,
+            "measurements requires the name, address or id of the source"
+        );
+    }
+
+    #[test]
+    fn cli_nts_status() {
+        let arguments = &[BINARY, "nts", "status", "-f", "json"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::NtsStatus);
+        assert_eq!(options.format, Format::Json);
+
+        let arguments = &[BINARY, "nts"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "nts requires a command: status");
+
+        let arguments = &[BINARY, "nts", "cookies"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid nts command provided: cookies");
+    }
+
+    #[test]
+    fn source_toml_roundtrip() {
+        let source = source_toml(SourceMode::Nts, "ntp.example.com:4460");
+        let config: crate::daemon::config::NtpSourceConfig = toml::from_str(&source).unwrap();
+        assert!(matches!(
+            config,
+            crate::daemon::config::NtpSourceConfig::Nts(_)
+        ));
+    }
 }
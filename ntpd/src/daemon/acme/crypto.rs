@@ -0,0 +1,78 @@
+//! Keys and certificate signing requests for the certificates we order, and
+//! reading the validity period of the issued certificate
+
+use std::time::SystemTime;
+
+use ntp_proto::x509;
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+
+/// A certificate signing request for `domains` with a fresh ECDSA P-256
+/// key, returning the DER encoded request and the PEM encoded private key
+pub(super) fn certificate_request(domains: &[String]) -> std::io::Result<(Vec<u8>, String)> {
+    let key_pair = KeyPair::generate().map_err(std::io::Error::other)?;
+    let mut params = CertificateParams::new(domains).map_err(std::io::Error::other)?;
+    // The ACME server only looks at the subject alternative names
+    params.distinguished_name = DistinguishedName::new();
+    let csr = params
+        .serialize_request(&key_pair)
+        .map_err(std::io::Error::other)?;
+
+    Ok((csr.der().to_vec(), key_pair.serialize_pem()))
+}
+
+/// The parts of an issued certificate that determine when it must be
+/// replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct CertificateInfo {
+    pub(super) not_before: SystemTime,
+    pub(super) not_after: SystemTime,
+    /// DNS names from the subject alternative name extension
+    pub(super) names: Vec<String>,
+}
+
+/// Read the validity and names of the DER encoded `certificate`
+pub(super) fn certificate_info(certificate: &[u8]) -> Option<CertificateInfo> {
+    let (not_before, not_after) = x509::validity(certificate)?;
+    Some(CertificateInfo {
+        not_before,
+        not_after,
+        names: x509::dns_names(certificate)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_certificate_info() {
+        let certificate = ntp_proto::tls_utils::pemfile::certs(
+            &mut include_bytes!("../../../test-keys/end.pem").as_slice(),
+        )
+        .next()
+        .unwrap()
+        .unwrap();
+        let info = certificate_info(&certificate).unwrap();
+        assert!(info.not_before < info.not_after);
+        assert!(info.not_before > SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        assert_eq!(info.names, ["localhost"]);
+
+        assert!(certificate_info(&certificate[..50]).is_none());
+    }
+
+    #[test]
+    fn test_certificate_request() {
+        let (csr, private_key) = certificate_request(&["time.example.com".into()]).unwrap();
+        assert!(
+            csr.windows(b"time.example.com".len())
+                .any(|window| window == b"time.example.com")
+        );
+        assert!(ntp_proto::tls_utils::pemfile::private_key(&mut private_key.as_bytes()).is_ok());
+
+        // Every request gets a fresh key
+        let (_, other_key) = certificate_request(&["time.example.com".into()]).unwrap();
+        assert_ne!(private_key, other_key);
+    }
+}
@@ -0,0 +1,190 @@
+//! The HTTPS client used to talk to the ACME server, and just enough
+//! HTTP/1.1 to answer its HTTP-01 challenges
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use http::{
+    Request, Response,
+    header::{self, HeaderValue},
+};
+use http_body_util::{Full, Limited};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use instant_acme::{BytesResponse, HttpClient};
+use ntp_proto::tls_utils;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::debug;
+
+// Time after which we give up on a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Largest response we accept from the ACME server. Certificate chains are
+// the largest responses we expect, and are only a few kilobytes.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+// Largest request we expect from the ACME server when it validates a challenge
+const MAX_CHALLENGE_REQUEST_SIZE: usize = 4096;
+
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+const USER_AGENT: &str = concat!("ntpd-rs/", env!("CARGO_PKG_VERSION"));
+
+/// Client for the ACME server, which verifies the server using the
+/// platform's certificate store
+pub(super) struct HttpsClient {
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl HttpsClient {
+    pub(super) fn new() -> HttpsClient {
+        let builder = tls_utils::client_config_builder();
+        let verifier =
+            tls_utils::PlatformVerifier::new().with_provider(builder.crypto_provider().clone());
+        let config = builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(config)
+            .https_only()
+            .enable_http1()
+            .build();
+
+        HttpsClient {
+            client: Client::builder(TokioExecutor::new()).build(connector),
+        }
+    }
+}
+
+impl HttpClient for HttpsClient {
+    fn request(
+        &self,
+        mut request: Request<Full<Bytes>>,
+    ) -> Pin<Box<dyn Future<Output = Result<BytesResponse, instant_acme::Error>> + Send>> {
+        debug!(method = %request.method(), uri = %request.uri(), "ACME request");
+        request
+            .headers_mut()
+            .insert(header::USER_AGENT, HeaderValue::from_static(USER_AGENT));
+        let response = self.client.request(request);
+
+        Box::pin(async move {
+            let response = tokio::time::timeout(REQUEST_TIMEOUT, response)
+                .await
+                .map_err(|e| instant_acme::Error::Other(Box::new(e)))?
+                .map_err(|e| instant_acme::Error::Other(Box::new(e)))?;
+
+            // The body is read later on, and must not exhaust our memory
+            // when the server is broken or hostile
+            let (parts, body) = response.into_parts();
+            Ok(BytesResponse::from(Response::from_parts(
+                parts,
+                Limited::new(body, MAX_RESPONSE_SIZE),
+            )))
+        })
+    }
+}
+
+/// The answer to a request for an HTTP-01 challenge, given the key
+/// authorizations by token
+fn challenge_response(request: &[u8], challenges: &HashMap<String, String>) -> Vec<u8> {
+    let key_authorization = std::str::from_utf8(request)
+        .ok()
+        .and_then(|request| request.lines().next())
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|line| line.split(' ').next())
+        .and_then(|path| path.strip_prefix(CHALLENGE_PATH))
+        .and_then(|token| challenges.get(token));
+
+    match key_authorization {
+        Some(key_authorization) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{key_authorization}",
+            key_authorization.len()
+        )
+        .into_bytes(),
+        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+    }
+}
+
+async fn handle_challenge_request(
+    mut stream: TcpStream,
+    challenges: &HashMap<String, String>,
+) -> std::io::Result<()> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_CHALLENGE_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    stream
+        .write_all(&challenge_response(&request, challenges))
+        .await?;
+    stream.shutdown().await
+}
+
+/// Answer HTTP-01 challenge requests on `listener` until the task is aborted
+pub(super) async fn serve_challenges(
+    listener: TcpListener,
+    challenges: Arc<HashMap<String, String>>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!(error = ?e, "Could not accept ACME challenge connection");
+                continue;
+            }
+        };
+        let challenges = challenges.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(
+                REQUEST_TIMEOUT,
+                handle_challenge_request(stream, &challenges),
+            )
+            .await
+            {
+                Ok(Ok(())) => debug!(?peer, "Answered ACME challenge request"),
+                Ok(Err(e)) => debug!(?peer, error = ?e, "ACME challenge request failed"),
+                Err(_) => debug!(?peer, "ACME challenge request timed out"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_response() {
+        let challenges = HashMap::from([("token".to_string(), "token.thumbprint".to_string())]);
+
+        let response = challenge_response(
+            b"GET /.well-known/acme-challenge/token HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            &challenges,
+        );
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(
+            response.ends_with(b"Content-Length: 16\r\nConnection: close\r\n\r\ntoken.thumbprint")
+        );
+
+        let response = challenge_response(
+            b"GET /.well-known/acme-challenge/other HTTP/1.1\r\n\r\n",
+            &challenges,
+        );
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+        let response = challenge_response(b"GET /token HTTP/1.1\r\n\r\n", &challenges);
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+}
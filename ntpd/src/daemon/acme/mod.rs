@@ -0,0 +1,400 @@
+//! Obtains and renews the certificate of an NTS key exchange server from an
+//! ACME (RFC 8555) certificate authority, validating control over the
+//! domains using HTTP-01 challenges.
+
+mod crypto;
+mod http;
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use ntp_proto::tls_utils;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::watch};
+use tracing::{Instrument, Span, info, instrument, warn};
+
+use self::{
+    crypto::{certificate_info, certificate_request},
+    http::{HttpsClient, serve_challenges},
+};
+use super::{config::AcmeConfig, util::write_atomically};
+
+// Time to wait before trying again after obtaining a certificate failed
+const RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// Longest we sleep at once while waiting for a renewal, so that changes to
+// the system clock don't delay it for too long
+const MAX_SLEEP: Duration = Duration::from_secs(24 * 60 * 60);
+
+// How often, and how many times, to check on the progress of an order
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_POLLS: usize = 30;
+
+const ACCOUNT_FILE: &str = "account.json";
+
+/// The account registered with a certificate authority, which is kept so
+/// that we don't register a new account for every certificate
+#[derive(Serialize, Deserialize)]
+struct StoredAccount {
+    directory: String,
+    credentials: AccountCredentials,
+}
+
+async fn load_or_create_account(config: &AcmeConfig, path: &Path) -> std::io::Result<Account> {
+    match std::fs::read(path) {
+        Ok(contents) => {
+            let stored: StoredAccount = serde_json::from_slice(&contents)?;
+            // Accounts only exist at the certificate authority they were
+            // registered with
+            if stored.directory == config.directory {
+                return Account::from_credentials_and_http(
+                    stored.credentials,
+                    Box::new(HttpsClient::new()),
+                )
+                .await
+                .map_err(std::io::Error::other);
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let contact: Vec<_> = config
+        .email
+        .iter()
+        .map(|email| format!("mailto:{email}"))
+        .collect();
+    let contact: Vec<_> = contact.iter().map(String::as_str).collect();
+    let (account, credentials) = Account::create_with_http(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: config.accept_terms_of_service,
+            only_return_existing: false,
+        },
+        &config.directory,
+        None,
+        Box::new(HttpsClient::new()),
+    )
+    .await
+    .map_err(std::io::Error::other)?;
+
+    let stored = StoredAccount {
+        directory: config.directory.clone(),
+        credentials,
+    };
+    store(path, serde_json::to_string(&stored)?.as_bytes(), true)?;
+    Ok(account)
+}
+
+/// Wait until the server moved the order past the pending or processing
+/// state, which should bring it into the `expected` state
+async fn wait_for_order(order: &mut Order, expected: OrderStatus) -> std::io::Result<()> {
+    for _ in 0..MAX_POLLS {
+        let state = order.refresh().await.map_err(std::io::Error::other)?;
+        match state.status {
+            status if status == expected => return Ok(()),
+            OrderStatus::Pending | OrderStatus::Processing => {}
+            status => {
+                return Err(std::io::Error::other(format!(
+                    "order failed with status {status:?}: {:?}",
+                    state.error
+                )));
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    Err(std::io::Error::other(format!(
+        "order did not become {expected:?} in time"
+    )))
+}
+
+/// Answer the challenges of all authorizations of the order that are not
+/// yet valid, and wait for the server to validate them
+async fn authorize(order: &mut Order, challenge_listen: SocketAddr) -> std::io::Result<()> {
+    let authorizations = order
+        .authorizations()
+        .await
+        .map_err(std::io::Error::other)?;
+
+    let mut key_authorizations = HashMap::new();
+    let mut pending = vec![];
+    for authorization in &authorizations {
+        match authorization.status {
+            AuthorizationStatus::Valid => continue,
+            AuthorizationStatus::Pending => {}
+            status => {
+                return Err(std::io::Error::other(format!(
+                    "authorization for {:?} has status {status:?}",
+                    authorization.identifier
+                )));
+            }
+        }
+
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == ChallengeType::Http01)
+            .ok_or_else(|| {
+                std::io::Error::other(format!(
+                    "ACME server offers no HTTP-01 challenge for {:?}",
+                    authorization.identifier
+                ))
+            })?;
+        key_authorizations.insert(
+            challenge.token.clone(),
+            order.key_authorization(challenge).as_str().to_string(),
+        );
+        pending.push(challenge.url.clone());
+    }
+
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(challenge_listen).await.map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("could not listen on {challenge_listen} for ACME challenges: {e}"),
+        )
+    })?;
+    let server = tokio::spawn(serve_challenges(listener, Arc::new(key_authorizations)));
+    let result = validate(order, &pending).await;
+    server.abort();
+    result
+}
+
+async fn validate(order: &mut Order, challenge_urls: &[String]) -> std::io::Result<()> {
+    for url in challenge_urls {
+        order
+            .set_challenge_ready(url)
+            .await
+            .map_err(std::io::Error::other)?;
+    }
+    wait_for_order(order, OrderStatus::Ready).await
+}
+
+/// Order a certificate for `domains`, returning the certificate chain and
+/// the private key, both in PEM encoding
+async fn order_certificate(
+    account: &Account,
+    domains: &[String],
+    challenge_listen: SocketAddr,
+) -> std::io::Result<(String, String)> {
+    let identifiers: Vec<_> = domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect();
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+
+    authorize(&mut order, challenge_listen).await?;
+
+    // Every certificate gets a fresh key
+    let (csr, private_key) = certificate_request(domains)?;
+    order.finalize(&csr).await.map_err(std::io::Error::other)?;
+
+    for _ in 0..MAX_POLLS {
+        if let Some(certificate_chain) = order.certificate().await.map_err(std::io::Error::other)? {
+            return Ok((certificate_chain, private_key));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    Err(std::io::Error::other(
+        "ACME server did not issue the certificate in time",
+    ))
+}
+
+/// Write `contents` to `path`, so that the daemon never reads a partially
+/// written file
+fn store(path: &Path, contents: &[u8], private: bool) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mode = if private { 0o600 } else { 0o644 };
+    write_atomically(path, mode, |file| file.write_all(contents))
+}
+
+async fn obtain_certificate(
+    config: &AcmeConfig,
+    certificate_chain_path: &Path,
+    private_key_path: &Path,
+) -> std::io::Result<()> {
+    let account = load_or_create_account(config, &config.storage_path.join(ACCOUNT_FILE)).await?;
+    let (certificate_chain, private_key) =
+        order_certificate(&account, &config.domains, config.challenge_listen).await?;
+
+    let certificates = tls_utils::pemfile::certs(&mut certificate_chain.as_bytes())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if certificates.is_empty() {
+        return Err(std::io::Error::other(
+            "ACME server returned no certificates",
+        ));
+    }
+
+    store(private_key_path, private_key.as_bytes(), true)?;
+    store(certificate_chain_path, certificate_chain.as_bytes(), false)
+}
+
+/// When the certificate at `path` should be renewed, which is when a third of
+/// its lifetime remains, or right away when it can't be read or is not for
+/// the configured domains. Also returns whether it can still be used.
+fn renewal_time(path: &Path, domains: &[String]) -> (SystemTime, bool) {
+    let info = std::fs::File::open(path)
+        .ok()
+        .and_then(|file| {
+            tls_utils::pemfile::certs(&mut std::io::BufReader::new(file))
+                .next()?
+                .ok()
+        })
+        .and_then(|certificate| certificate_info(&certificate));
+    let Some(info) = info else {
+        return (SystemTime::UNIX_EPOCH, false);
+    };
+
+    let now = SystemTime::now();
+    let usable = info.not_before <= now && now < info.not_after;
+
+    let mut names: Vec<_> = info
+        .names
+        .iter()
+        .map(|name| name.to_ascii_lowercase())
+        .collect();
+    let mut expected: Vec<_> = domains
+        .iter()
+        .map(|name| name.to_ascii_lowercase())
+        .collect();
+    names.sort();
+    expected.sort();
+    if names != expected {
+        return (SystemTime::UNIX_EPOCH, usable);
+    }
+
+    let lifetime = info
+        .not_after
+        .duration_since(info.not_before)
+        .unwrap_or_default();
+    (info.not_after - lifetime / 3, usable)
+}
+
+/// Keep a certificate for the configured domains at the given paths,
+/// renewing it in time. The returned channel becomes `true` once a usable
+/// certificate is available, and is updated again after every renewal.
+#[instrument(level = tracing::Level::ERROR, name = "ACME", skip_all)]
+pub(crate) fn spawn(
+    config: AcmeConfig,
+    certificate_chain_path: PathBuf,
+    private_key_path: PathBuf,
+) -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+
+    tokio::spawn(
+        (async move {
+            loop {
+                let (renew_at, usable) = renewal_time(&certificate_chain_path, &config.domains);
+                if usable {
+                    sender.send_if_modified(|available| !std::mem::replace(available, true));
+                }
+
+                if let Ok(wait) = renew_at.duration_since(SystemTime::now())
+                    && !wait.is_zero()
+                {
+                    tokio::time::sleep(wait.min(MAX_SLEEP)).await;
+                    continue;
+                }
+
+                info!(domains = ?config.domains, "Requesting certificate");
+                match obtain_certificate(&config, &certificate_chain_path, &private_key_path).await
+                {
+                    Ok(()) => {
+                        info!("Obtained new certificate");
+                        sender.send_replace(true);
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "Could not obtain certificate, retrying later");
+                        tokio::time::sleep(RETRY_INTERVAL).await;
+                    }
+                }
+
+                if sender.is_closed() {
+                    break;
+                }
+            }
+        })
+        .instrument(Span::current()),
+    );
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_renewal_time() {
+        let domains = ["localhost".to_string()];
+
+        let (renew_at, usable) = renewal_time(Path::new("test-keys/end.pem"), &domains);
+        let info = certificate_info(
+            &tls_utils::pemfile::certs(
+                &mut include_bytes!("../../../test-keys/end.pem").as_slice(),
+            )
+            .next()
+            .unwrap()
+            .unwrap(),
+        )
+        .unwrap();
+        let lifetime = info.not_after.duration_since(info.not_before).unwrap();
+        assert_eq!(renew_at, info.not_after - lifetime / 3);
+        assert_eq!(
+            usable,
+            info.not_before <= SystemTime::now() && SystemTime::now() < info.not_after
+        );
+
+        // Certificates for other domains are replaced right away
+        let (renew_at, _) =
+            renewal_time(Path::new("test-keys/end.pem"), &["time.example.com".into()]);
+        assert_eq!(renew_at, SystemTime::UNIX_EPOCH);
+
+        assert_eq!(
+            renewal_time(Path::new("test-keys/does-not-exist.pem"), &domains),
+            (SystemTime::UNIX_EPOCH, false)
+        );
+    }
+
+    #[test]
+    fn test_store() {
+        let path = std::env::temp_dir().join(format!(
+            "ntpd-rs-acme-test-{}/private.key",
+            std::process::id()
+        ));
+        store(&path, b"secret", true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"secret");
+        let permissions = std::fs::metadata(&path).unwrap().permissions();
+        assert_eq!(permissions.mode() & 0o777, 0o600);
+
+        store(&path, b"replaced", true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"replaced");
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
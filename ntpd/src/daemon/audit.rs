@@ -0,0 +1,291 @@
+//! Audit log of security relevant events: NTS key exchanges, authentication
+//! failures, clock steps and jumps, and kiss-o'-death packets. Events are written to a
+//! file or Unix datagram socket of their own, one JSON object per line, so
+//! they can be collected and retained apart from the regular log.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::SocketAddr,
+    os::unix::{fs::OpenOptionsExt, net::UnixDatagram},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+use tracing::warn;
+
+use super::{config::ObservabilityConfig, jump_detection::JumpCause};
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// A security relevant event
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum AuditEvent<'a> {
+    /// A key exchange with the NTS-KE server of one of our sources
+    #[cfg(feature = "client")]
+    NtsKeClient {
+        server: &'a str,
+        port: u16,
+        success: bool,
+    },
+    /// A key exchange of a client with our NTS-KE server
+    #[cfg(feature = "server")]
+    NtsKeServer {
+        client: SocketAddr,
+        success: bool,
+        error: Option<&'a str>,
+    },
+    /// A response from a source failed NTS or MAC authentication
+    #[cfg(feature = "client")]
+    SourceAuthenticationFailure {
+        source: &'a str,
+        address: SocketAddr,
+    },
+    /// A request to our server failed NTS or MAC authentication
+    #[cfg(feature = "server")]
+    ClientAuthenticationFailure { client: SocketAddr },
+    /// The clock was stepped by `offset` seconds
+    ClockStep { offset: f64 },
+    /// The clock jumped by `offset` seconds without the daemon stepping it
+    ClockJump { offset: f64, cause: JumpCause },
+    /// A source sent a kiss-o'-death packet
+    #[cfg(feature = "client")]
+    KissOfDeath {
+        source: &'a str,
+        address: SocketAddr,
+        code: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    #[serde(flatten)]
+    event: &'a AuditEvent<'a>,
+}
+
+struct AuditLog {
+    file: Option<(PathBuf, Mutex<File>)>,
+    socket: Option<(PathBuf, UnixDatagram)>,
+}
+
+fn open_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path)
+}
+
+/// Set up the audit log configured in `config`, if any. Must be called from
+/// within the tokio runtime, which reopens the file on SIGHUP.
+pub(crate) fn init(config: &ObservabilityConfig) -> std::io::Result<()> {
+    let file = match &config.audit_log_path {
+        Some(path) => Some((path.clone(), Mutex::new(open_file(path)?))),
+        None => None,
+    };
+    let socket = match &config.audit_log_socket {
+        Some(path) => {
+            let socket = UnixDatagram::unbound()?;
+            // Rather drop events than stall the daemon on a slow reader
+            socket.set_nonblocking(true)?;
+            Some((path.clone(), socket))
+        }
+        None => None,
+    };
+
+    if file.is_none() && socket.is_none() {
+        return Ok(());
+    }
+
+    let reopen = file.is_some();
+    if AUDIT_LOG.set(AuditLog { file, socket }).is_ok() && reopen {
+        tokio::spawn(reopen_on_hangup());
+    }
+
+    Ok(())
+}
+
+async fn reopen_on_hangup() {
+    let Ok(mut stream) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+    else {
+        warn!("Could not listen for hangup signal, the audit log will not be reopened");
+        return;
+    };
+
+    while stream.recv().await.is_some() {
+        let Some((path, file)) = AUDIT_LOG.get().and_then(|log| log.file.as_ref()) else {
+            return;
+        };
+        match open_file(path) {
+            Ok(new_file) => *file.lock().expect("Unexpected poisoned mutex") = new_file,
+            Err(error) => warn!(
+                ?error,
+                "Could not reopen audit log, continuing with old handle"
+            ),
+        }
+    }
+}
+
+/// Write `event` to the audit log, if there is one
+pub(crate) fn record(event: &AuditEvent<'_>) {
+    if let Some(log) = AUDIT_LOG.get() {
+        log.write(event, SystemTime::now());
+    }
+}
+
+impl AuditLog {
+    fn write(&self, event: &AuditEvent<'_>, now: SystemTime) {
+        let record = Record {
+            timestamp: format_timestamp(now),
+            event,
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(error) => {
+                warn!(?error, "Could not serialize audit event");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Some((_, file)) = &self.file
+            && let Err(error) = file
+                .lock()
+                .expect("Unexpected poisoned mutex")
+                .write_all(&line)
+        {
+            warn!(?error, "Could not write to audit log");
+        }
+
+        if let Some((path, socket)) = &self.socket
+            && let Err(error) = socket.send_to(&line, path)
+        {
+            warn!(?error, "Could not send to audit log socket");
+        }
+    }
+}
+
+/// Format as an RFC 3339 timestamp in UTC, with microseconds
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let days = i64::try_from(seconds / 86400).expect("Days since the epoch fit in an i64");
+    let (year, month, day) = civil_from_days(days);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+/// The (proleptic Gregorian) date the given number of days after 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    #[cfg(feature = "client")]
+    use crate::test::alloc_port;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19783), (2024, 3, 1));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(
+            format_timestamp(SystemTime::UNIX_EPOCH),
+            "1970-01-01T00:00:00.000000Z"
+        );
+        assert_eq!(
+            format_timestamp(
+                SystemTime::UNIX_EPOCH + Duration::new(19782 * 86400 + 12 * 3600 + 754, 500_000)
+            ),
+            "2024-02-29T12:12:34.000500Z"
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_write() {
+        let path = std::env::temp_dir().join(format!("ntp-test-audit-{}", alloc_port()));
+        let socket_path = std::env::temp_dir().join(format!("ntp-test-audit-{}", alloc_port()));
+        let listener = UnixDatagram::bind(&socket_path).unwrap();
+
+        let log = AuditLog {
+            file: Some((path.clone(), Mutex::new(open_file(&path).unwrap()))),
+            socket: Some((socket_path.clone(), UnixDatagram::unbound().unwrap())),
+        };
+        log.write(
+            &AuditEvent::ClockStep { offset: 1.5 },
+            SystemTime::UNIX_EPOCH,
+        );
+        log.write(
+            &AuditEvent::ClockJump {
+                offset: -60.0,
+                cause: JumpCause::Suspend,
+            },
+            SystemTime::UNIX_EPOCH,
+        );
+        log.write(
+            &AuditEvent::KissOfDeath {
+                source: "pool.example.com",
+                address: "192.0.2.1:123".parse().unwrap(),
+                code: "DENY",
+            },
+            SystemTime::UNIX_EPOCH,
+        );
+
+        let expected = [
+            r#"{"timestamp":"1970-01-01T00:00:00.000000Z","event":"clock-step","offset":1.5}"#,
+            r#"{"timestamp":"1970-01-01T00:00:00.000000Z","event":"clock-jump","offset":-60.0,"cause":"suspend"}"#,
+            r#"{"timestamp":"1970-01-01T00:00:00.000000Z","event":"kiss-of-death","source":"pool.example.com","address":"192.0.2.1:123","code":"DENY"}"#,
+        ];
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().collect::<Vec<_>>(), expected);
+
+        let mut buf = [0; 1024];
+        for expected in expected {
+            let length = listener.recv(&mut buf).unwrap();
+            assert_eq!(
+                std::str::from_utf8(&buf[..length]).unwrap(),
+                format!("{expected}\n")
+            );
+        }
+
+        std::fs::remove_file(path).ok();
+        std::fs::remove_file(socket_path).ok();
+    }
+}
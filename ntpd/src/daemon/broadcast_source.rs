@@ -0,0 +1,344 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use ntp_proto::{BroadcastSource, ClockId, NtpClock, NtpTimestamp, SourceController};
+use tokio::{net::UdpSocket, sync::watch};
+use tracing::{Instrument, Span, debug, error, instrument, warn};
+
+use crate::exitcode;
+
+use super::{latency::SoftwareLatency, measurements_log, ntp_source::SourceChannels};
+
+// Time after which we give up on a single calibration exchange
+const CALIBRATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+pub(crate) struct BroadcastSourceTask<C: 'static + NtpClock + Send, Controller: SourceController> {
+    index: ClockId,
+    socket: UdpSocket,
+    clock: C,
+    listen: SocketAddr,
+    software_latency: watch::Receiver<SoftwareLatency>,
+    channels: SourceChannels,
+    source: BroadcastSource<Controller>,
+}
+
+async fn create_socket(
+    listen: SocketAddr,
+    multicast_group: Option<IpAddr>,
+) -> std::io::Result<UdpSocket> {
+    debug!(?listen, "Listening for broadcasts");
+    let socket = UdpSocket::bind(listen).await?;
+    match (multicast_group, listen) {
+        (None, _) => {}
+        (Some(IpAddr::V4(group)), SocketAddr::V4(_)) => {
+            debug!(?group, "Joining multicast group");
+            socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+        }
+        (Some(IpAddr::V6(group)), SocketAddr::V6(_)) => {
+            debug!(?group, "Joining multicast group");
+            socket.join_multicast_v6(&group, 0)?;
+        }
+        (Some(_), _) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "multicast group and listen address are of a different address family",
+            ));
+        }
+    }
+    Ok(socket)
+}
+
+/// Socket for the client/server exchanges with a broadcast server
+async fn calibration_socket(server: SocketAddr) -> std::io::Result<UdpSocket> {
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    Ok(socket)
+}
+
+impl<C, Controller: SourceController> BroadcastSourceTask<C, Controller>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
+    async fn run(&mut self) {
+        loop {
+            let mut buf = [0; 1024];
+
+            match self.socket.recv_from(&mut buf).await {
+                Ok((size, sender)) => {
+                    let recv_time = self.now();
+                    let recv_time = self.software_latency.borrow().compensate_recv(recv_time);
+
+                    if let Some(server) =
+                        self.source
+                            .handle_broadcast(&buf[..size], sender, recv_time)
+                    {
+                        self.calibrate(server).await;
+                    }
+
+                    let state = self.source.observe(
+                        "Broadcast".to_string(),
+                        self.listen.to_string(),
+                        self.index,
+                    );
+                    if let Some(measurement) = self.source.take_latest_measurement() {
+                        measurements_log::record(
+                            &state.name,
+                            &state.address,
+                            self.index,
+                            &measurement,
+                        );
+                    }
+                    self.channels
+                        .source_snapshots
+                        .write()
+                        .expect("Unexpected poisoned mutex")
+                        .insert(self.index, state);
+                }
+                Err(e) => {
+                    warn!(error = ?e, "Could not receive broadcast, recreating socket");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Estimate the delay to a new broadcast server
+    async fn calibrate(&mut self, server: SocketAddr) {
+        let socket = match calibration_socket(server).await {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                warn!(error = ?e, ?server, "Could not create calibration socket");
+                None
+            }
+        };
+
+        while let Some(request) = self.source.calibration_request() {
+            let Some(socket) = &socket else {
+                // Let the calibration run out without any responses
+                continue;
+            };
+
+            let send_time = self.now();
+            let send_time = self.software_latency.borrow().compensate_send(send_time);
+            if let Err(e) = socket.send(&request).await {
+                debug!(error = ?e, "Could not send calibration request");
+                continue;
+            }
+
+            let mut buf = [0; 1024];
+            match tokio::time::timeout(CALIBRATION_TIMEOUT, socket.recv(&mut buf)).await {
+                Ok(Ok(size)) => {
+                    let recv_time = self.now();
+                    let recv_time = self.software_latency.borrow().compensate_recv(recv_time);
+                    self.source
+                        .handle_calibration_response(&buf[..size], send_time, recv_time);
+                }
+                Ok(Err(e)) => debug!(error = ?e, "Could not receive calibration response"),
+                Err(_) => debug!("Calibration request timed out"),
+            }
+        }
+
+        // Broadcasts that queued up in the meantime no longer have an
+        // accurate receive time
+        let mut buf = [0; 1024];
+        while self.socket.try_recv_from(&mut buf).is_ok() {}
+    }
+
+    fn now(&self) -> NtpTimestamp {
+        match self.clock.now() {
+            Ok(time) => time,
+            Err(e) => {
+                error!(error = ?e, "There was an error retrieving the current time");
+                exitcode::exit(exitcode::NOPERM);
+            }
+        }
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "Broadcast Source", skip(clock, software_latency, channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        listen: SocketAddr,
+        multicast_group: Option<IpAddr>,
+        clock: C,
+        software_latency: watch::Receiver<SoftwareLatency>,
+        channels: SourceChannels,
+        source: BroadcastSource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let socket = match create_socket(listen, multicast_group).await {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        warn!(error = ?e, "Could not create socket");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
+                let mut process = BroadcastSourceTask {
+                    index,
+                    socket,
+                    clock,
+                    listen,
+                    software_latency,
+                    channels,
+                    source,
+                };
+
+                process.run().await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Cursor,
+        sync::{Arc, RwLock},
+    };
+
+    use ntp_proto::{
+        AlgorithmConfig, KalmanClockController, NoCipher, NtpAssociationMode, NtpDuration,
+        NtpLeapIndicator, NtpManager, NtpPacket, SourceConfig, SynchronizationConfig,
+        TimeSyncController, TimeSyncControllerWrapper,
+    };
+    use tokio::sync::mpsc;
+
+    use crate::{
+        daemon::{ntp_source::SourceChannels, util::EPOCH_OFFSET},
+        test::alloc_port,
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            let cur =
+                std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH)?;
+
+            Ok(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                EPOCH_OFFSET.wrapping_add(cur.as_secs() as u32),
+                cur.subsec_nanos(),
+            ))
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            self.now()
+            //ignore
+        }
+
+        fn get_frequency(&self) -> Result<f64, Self::Error> {
+            Ok(0.0)
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+            //ignore
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by source");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+            //ignore
+        }
+    }
+
+    fn broadcast(transmit: NtpTimestamp) -> Vec<u8> {
+        let mut packet = NtpPacket::test();
+        packet.set_mode(NtpAssociationMode::Broadcast);
+        packet.set_stratum(2);
+        packet.set_leap(NtpLeapIndicator::NoWarning);
+        packet.set_transmit_timestamp(transmit);
+
+        let mut buf = [0; 1024];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        packet.serialize(&mut cursor, &NoCipher, None).unwrap();
+        let used = cursor.position() as usize;
+        buf[..used].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_receive_broadcast() {
+        let (msg_for_system_sender, _msg_for_system_receiver) = mpsc::channel(1);
+        let source_snapshots = Arc::new(RwLock::new(HashMap::new()));
+
+        let index = ClockId::new();
+        let clock = TestClock {};
+        let controller = TimeSyncControllerWrapper::<KalmanClockController<_>>::new(
+            clock.clone(),
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        let manager = NtpManager::new(SynchronizationConfig::default(), Arc::new([]));
+        let source = manager.new_broadcast_source(
+            controller.add_one_way_source(index, SourceConfig::default(), 1e-6, 0.0, None),
+            Some(NtpDuration::from_seconds(0.001)),
+            index,
+        );
+
+        let listen: SocketAddr = format!("127.0.0.1:{}", alloc_port()).parse().unwrap();
+        let (_, software_latency) = watch::channel(SoftwareLatency::default());
+        let handle = BroadcastSourceTask::spawn(
+            index,
+            listen,
+            None,
+            clock.clone(),
+            software_latency,
+            SourceChannels {
+                msg_for_system_sender,
+                source_snapshots: source_snapshots.clone(),
+            },
+            source,
+        );
+
+        // Wait for the source to create its socket
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        server
+            .send_to(&broadcast(clock.now().unwrap()), listen)
+            .await
+            .unwrap();
+
+        // Wait for the broadcast to be processed
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let state = source_snapshots.read().unwrap()[&index].clone();
+        assert_eq!(state.address, server_addr.to_string());
+        assert_eq!(state.ntp_version, Some(4));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_multicast_family_mismatch() {
+        let listen = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let group = IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x101));
+        assert!(create_socket(listen, Some(group)).await.is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use ntp_proto::tls_utils::Certificate;
+
+pub(crate) fn certificates_from_file(path: &Path) -> std::io::Result<Vec<Certificate>> {
+    let file = std::fs::File::open(path)?;
+    let reader = BufReader::new(file);
+
+    certificates_from_bufread(reader)
+}
+
+pub(crate) fn certificates_from_bufread(
+    mut reader: impl BufRead,
+) -> std::io::Result<Vec<Certificate>> {
+    ntp_proto::tls_utils::pemfile::certs(&mut reader).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nos_nl_pem() {
+        let input = include_bytes!("../../testdata/certificates/nos-nl.pem");
+        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
+
+        assert_eq!(certificates.len(), 1);
+    }
+
+    #[test]
+    fn nos_nl_chain_pem() {
+        let input = include_bytes!("../../testdata/certificates/nos-nl-chain.pem");
+        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
+
+        assert_eq!(certificates.len(), 3);
+    }
+
+    #[test]
+    fn parse_private_keys() {
+        let input = include_bytes!("../../test-keys/end.key");
+        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+
+        let input = include_bytes!("../../test-keys/testca.key");
+        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+
+        // openssl does no longer seem to want to generate this format
+        // so we use https://github.com/rustls/pemfile/blob/main/tests/data/rsa1024.pkcs1.pem
+        let input = include_bytes!("../../test-keys/rsa_key.pem");
+        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+
+        // openssl ecparam -name prime256v1 -genkey -noout -out ec_key.pem
+        let input = include_bytes!("../../test-keys/ec_key.pem");
+        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+
+        // openssl genpkey -algorithm EC -out pkcs8_key.pem -pkeyopt ec_paramgen_curve:prime256v1
+        let input = include_bytes!("../../test-keys/pkcs8_key.pem");
+        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+    }
+}
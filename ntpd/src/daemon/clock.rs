@@ -1,7 +1,21 @@
+use std::sync::Mutex;
+
 use clock_steering::{Clock, TimeOffset, unix::UnixClock};
 use ntp_proto::NtpClock;
 
-use super::util::convert_clock_timestamp;
+use super::{
+    audit::{self, AuditEvent},
+    util::convert_clock_timestamp,
+};
+
+// Total of the steps the daemon made to the clock, which jump detection must
+// not mistake for jumps caused by others
+static OWN_STEPS: Mutex<ntp_proto::NtpDuration> = Mutex::new(ntp_proto::NtpDuration::ZERO);
+
+/// Total of the steps the daemon made to the clock so far
+pub(crate) fn own_steps() -> ntp_proto::NtpDuration {
+    *OWN_STEPS.lock().expect("Unexpected poisoned mutex")
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct NtpClockWrapper(UnixClock);
@@ -46,6 +60,12 @@ impl NtpClock for NtpClockWrapper {
                 nanos,
             })
             .map(convert_clock_timestamp)
+            .inspect(|_| {
+                *OWN_STEPS.lock().expect("Unexpected poisoned mutex") += offset;
+                audit::record(&AuditEvent::ClockStep {
+                    offset: offset.to_seconds(),
+                });
+            })
     }
 
     fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
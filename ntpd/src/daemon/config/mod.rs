@@ -1,11 +1,17 @@
 mod ntp_source;
+mod resolver;
 mod server;
+mod symmetric_key;
 
 use clock_steering::unix::UnixClock;
 use ntp_proto::{
-    AlgorithmConfig, NtpVersion, ProtocolVersion, SourceConfig, SynchronizationConfig,
+    AlgorithmConfig, NtpVersion, PiAlgorithmConfig, PollInterval, ProtocolVersion, SourceConfig,
+    SymmetricKey, SynchronizationConfig,
 };
 pub use ntp_source::*;
+pub use resolver::ResolverConfig;
+#[cfg(feature = "srv")]
+pub use resolver::ResolverMode;
 use serde::{Deserialize, Deserializer};
 pub use server::*;
 use std::io;
@@ -13,10 +19,14 @@ use std::{
     fmt::Display,
     io::ErrorKind,
     net::SocketAddr,
+    num::NonZeroU64,
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
 };
+pub use symmetric_key::LegacyAuthConfig;
+use symmetric_key::deserialize_symmetric_keys;
 use timestamped_socket::interface::InterfaceName;
 use tracing::{info, warn};
 
@@ -261,6 +271,7 @@ pub enum TimestampMode {
     Hardware,
 }
 
+#[cfg(feature = "client")]
 impl TimestampMode {
     #[cfg(target_os = "linux")]
     pub(crate) fn as_interface_mode(self) -> timestamped_socket::socket::InterfaceTimestampMode {
@@ -292,6 +303,10 @@ impl TimestampMode {
 
 #[derive(Deserialize, Debug, Copy, Clone, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+#[cfg_attr(
+    not(feature = "client"),
+    expect(dead_code, reason = "Every build accepts the options for sources")
+)]
 pub struct ClockConfig {
     #[serde(deserialize_with = "deserialize_ntp_clock", default)]
     pub clock: NtpClockWrapper,
@@ -300,8 +315,36 @@ pub struct ClockConfig {
     pub timestamp_mode: TimestampMode,
 }
 
+/// Steering of the PTP hardware clock of a network interface from the
+/// system clock
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PhcSteeringConfig {
+    pub interface: InterfaceName,
+    #[serde(default = "default_phc_steering_poll_interval")]
+    pub poll_interval: PollInterval,
+    /// Seconds the PHC should be ahead of the system clock, e.g. 37 for TAI
+    #[serde(default)]
+    pub offset: f64,
+    /// Offsets (in seconds) beyond which the PHC is stepped instead of slewed
+    #[serde(default = "default_phc_steering_step_threshold")]
+    pub step_threshold: f64,
+}
+
+fn default_phc_steering_poll_interval() -> PollInterval {
+    PollInterval::from_byte(0)
+}
+
+fn default_phc_steering_step_threshold() -> f64 {
+    1e-3
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+#[cfg_attr(
+    not(feature = "client"),
+    expect(dead_code, reason = "Every build accepts the options for sources")
+)]
 pub struct ObservabilityConfig {
     #[serde(default)]
     pub log_level: Option<LogLevel>,
@@ -315,8 +358,35 @@ pub struct ObservabilityConfig {
     pub observation_path: Option<PathBuf>,
     #[serde(default = "default_observation_permissions")]
     pub observation_permissions: u32,
+    #[serde(default)]
+    pub control_path: Option<PathBuf>,
+    #[serde(default = "default_control_permissions")]
+    pub control_permissions: u32,
     #[serde(default = "default_metrics_exporter_listen")]
     pub metrics_exporter_listen: SocketAddr,
+    #[serde(default)]
+    pub audit_log_path: Option<PathBuf>,
+    #[serde(default)]
+    pub audit_log_socket: Option<PathBuf>,
+    #[serde(default)]
+    pub measurements_log_path: Option<PathBuf>,
+    #[serde(default)]
+    pub measurements_log_format: MeasurementsLogFormat,
+    #[serde(default = "default_measurements_log_max_size")]
+    pub measurements_log_max_size: u64,
+    #[serde(default = "default_measurements_log_rotations")]
+    pub measurements_log_rotations: u32,
+}
+
+/// Format of the lines in the measurements log
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MeasurementsLogFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Comma separated values, with a header line
+    Csv,
 }
 
 impl Default for ObservabilityConfig {
@@ -328,7 +398,15 @@ impl Default for ObservabilityConfig {
             ansi_colors: None,
             observation_path: None,
             observation_permissions: default_observation_permissions(),
+            control_path: None,
+            control_permissions: default_control_permissions(),
             metrics_exporter_listen: default_metrics_exporter_listen(),
+            audit_log_path: None,
+            audit_log_socket: None,
+            measurements_log_path: None,
+            measurements_log_format: MeasurementsLogFormat::default(),
+            measurements_log_max_size: default_measurements_log_max_size(),
+            measurements_log_rotations: default_measurements_log_rotations(),
         }
     }
 }
@@ -337,18 +415,127 @@ const fn default_observation_permissions() -> u32 {
     0o666
 }
 
+const fn default_control_permissions() -> u32 {
+    0o600
+}
+
+const fn default_measurements_log_max_size() -> u64 {
+    10_000_000
+}
+
+const fn default_measurements_log_rotations() -> u32 {
+    5
+}
+
 fn default_metrics_exporter_listen() -> SocketAddr {
     "127.0.0.1:9975".parse().unwrap()
 }
 
+/// The algorithm that disciplines the system clock
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClockAlgorithm {
+    #[default]
+    Kalman,
+    Pi,
+}
+
 #[derive(Deserialize, Debug, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct DaemonSynchronizationConfig {
     #[serde(flatten)]
     pub synchronization_base: SynchronizationConfig,
 
+    #[serde(default)]
+    pub clock_algorithm: ClockAlgorithm,
+
+    /// Settings of the Kalman filter based algorithm
     #[serde(default)]
     pub algorithm: AlgorithmConfig,
+
+    #[serde(default)]
+    pub pi_algorithm: PiAlgorithmConfig,
+
+    #[serde(default)]
+    pub software_latency_compensation: bool,
+
+    #[serde(default)]
+    pub coordinated_polling: bool,
+
+    #[serde(default)]
+    pub max_sources: Option<usize>,
+
+    /// Sources of the preferred groups that must be in use before standby
+    /// groups are demobilized, by default minimum-agreeing-sources
+    #[serde(default)]
+    pub failover_threshold: Option<usize>,
+
+    #[serde(default)]
+    pub temperature: Option<TemperatureConfig>,
+
+    /// File in which the frequency correction of the clock is kept across
+    /// restarts
+    #[serde(default)]
+    pub drift_file: Option<PathBuf>,
+
+    /// File in which the state of the clock algorithm is saved when the
+    /// daemon is stopped, so that it can continue where it left off when
+    /// restarted shortly after
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+}
+
+impl DaemonSynchronizationConfig {
+    pub fn source_options(&self) -> SourceOptions {
+        SourceOptions {
+            software_latency_compensation: self.software_latency_compensation,
+            coordinated_polling: self.coordinated_polling,
+            max_sources: self.max_sources,
+            failover_threshold: self
+                .failover_threshold
+                .unwrap_or(self.synchronization_base.minimum_agreeing_sources),
+        }
+    }
+}
+
+/// Daemon specific options for running sources
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    not(feature = "client"),
+    expect(dead_code, reason = "Every build accepts the options for sources")
+)]
+pub struct SourceOptions {
+    pub software_latency_compensation: bool,
+    pub coordinated_polling: bool,
+    pub max_sources: Option<usize>,
+    pub failover_threshold: usize,
+}
+
+/// Where to read the temperature of the clock from
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum TemperatureSensor {
+    /// A file containing the temperature in degrees Celsius
+    File(PathBuf),
+    /// A Linux hwmon temperature input, in millidegrees Celsius
+    Hwmon(PathBuf),
+    /// A Unix datagram socket on which every datagram is a reading in
+    /// degrees Celsius
+    Socket(PathBuf),
+}
+
+/// Input for compensating the frequency of the clock for its temperature
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TemperatureConfig {
+    pub sensor: TemperatureSensor,
+    /// Seconds between reads of a file or hwmon sensor
+    #[serde(default = "default_temperature_interval")]
+    pub interval: NonZeroU64,
+}
+
+fn default_temperature_interval() -> NonZeroU64 {
+    NonZeroU64::new(60).unwrap()
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -368,6 +555,18 @@ pub struct Config {
     pub observability: ObservabilityConfig,
     #[serde(default)]
     pub keyset: KeysetConfig,
+    #[serde(
+        rename = "symmetric-key",
+        default,
+        deserialize_with = "deserialize_symmetric_keys"
+    )]
+    pub symmetric_keys: Arc<[SymmetricKey]>,
+    #[serde(default)]
+    pub legacy_auth: LegacyAuthConfig,
+    #[serde(rename = "phc-steering", default)]
+    pub phc_steering: Vec<PhcSteeringConfig>,
+    #[serde(default)]
+    pub resolver: ResolverConfig,
     #[serde(default)]
     #[cfg(feature = "hardware-timestamping")]
     pub clock: ClockConfig,
@@ -445,8 +644,20 @@ impl Config {
                 NtpSourceConfig::Pool(config) => count += config.first.count,
                 NtpSourceConfig::NtsPool(config) => count += config.first.count,
                 NtpSourceConfig::Sock(_) => count += 1,
+                NtpSourceConfig::Broadcast(_) => count += 1,
+                NtpSourceConfig::Roughtime(_) => count += 1,
+                NtpSourceConfig::Nmea(_) => count += 1,
+                NtpSourceConfig::Gpsd(_) => count += 1,
+                NtpSourceConfig::Phc(_) => count += 1,
+                NtpSourceConfig::Ptp(_) => count += 1,
+                NtpSourceConfig::Shm(_) => count += 1,
                 #[cfg(feature = "pps")]
-                NtpSourceConfig::Pps(_) => {} // PPS sources don't count
+                NtpSourceConfig::Pps(config) => {
+                    // Only PPS sources locked to another source give the full time
+                    if config.first.lock.is_some() {
+                        count += 1;
+                    }
+                }
             }
         }
         count
@@ -454,6 +665,7 @@ impl Config {
 
     /// Check that the config is reasonable. This function may panic if the
     /// configuration is egregious, although it doesn't do so currently.
+    #[expect(clippy::too_many_lines)]
     pub fn check(&self) -> bool {
         let mut ok = true;
 
@@ -461,10 +673,37 @@ impl Config {
         // using those fields should always work. This is also
         // probably a good policy in general (config should always work
         // but we may panic here to protect the user from themselves)
+        if !cfg!(feature = "client") && !self.sources.is_empty() {
+            warn!(
+                "Sources configured, but ntpd-rs was built without the `client` feature. The sources are ignored and the daemon will not change system time."
+            );
+            ok = false;
+        }
+
+        if !cfg!(feature = "server") && (!self.servers.is_empty() || !self.nts_ke.is_empty()) {
+            warn!(
+                "Servers configured, but ntpd-rs was built without the `server` feature. The servers and NTS key exchange servers are ignored."
+            );
+            ok = false;
+        }
+
+        if !cfg!(target_os = "linux") && self.servers.iter().any(|server| server.workers.get() > 1)
+        {
+            warn!(
+                "Multiple server workers are only supported on Linux. Every server uses a single worker."
+            );
+            ok = false;
+        }
+
         if self.sources.is_empty() {
             info!("No sources configured. Daemon will not change system time.");
         }
 
+        if let Err(error) = self.resolver.check() {
+            warn!("Invalid resolver configuration: {error}.");
+            ok = false;
+        }
+
         if !self.sources.is_empty()
             && self.count_sources()
                 < self
@@ -478,8 +717,48 @@ impl Config {
             ok = false;
         }
 
+        let synchronization_base = &self.synchronization.synchronization_base;
+        if synchronization_base
+            .maintain_agreeing_sources
+            .is_some_and(|maintain| maintain > synchronization_base.minimum_agreeing_sources)
+        {
+            warn!(
+                "maintain-agreeing-sources is larger than minimum-agreeing-sources, minimum-agreeing-sources is used instead."
+            );
+            ok = false;
+        }
+
+        if self
+            .synchronization
+            .failover_threshold
+            .is_some_and(|threshold| threshold < synchronization_base.minimum_agreeing_sources)
+        {
+            warn!(
+                "failover-threshold is smaller than minimum-agreeing-sources. Standby sources may be mobilized and demobilized over and over."
+            );
+            ok = false;
+        }
+
+        if self
+            .synchronization
+            .max_sources
+            .is_some_and(|max| max < synchronization_base.minimum_agreeing_sources)
+        {
+            warn!(
+                "max-sources is smaller than minimum-agreeing-sources. Daemon will not change system time."
+            );
+            ok = false;
+        }
+
         if self.sources.iter().any(|config| match config {
-            NtpSourceConfig::Sock(_) => false,
+            NtpSourceConfig::Sock(_)
+            | NtpSourceConfig::Broadcast(_)
+            | NtpSourceConfig::Roughtime(_)
+            | NtpSourceConfig::Nmea(_)
+            | NtpSourceConfig::Gpsd(_)
+            | NtpSourceConfig::Phc(_)
+            | NtpSourceConfig::Ptp(_)
+            | NtpSourceConfig::Shm(_) => false,
             #[cfg(feature = "pps")]
             NtpSourceConfig::Pps(_) => false,
             NtpSourceConfig::Standard(config) => {
@@ -501,11 +780,149 @@ impl Config {
             ok = false;
         }
 
+        for source in &self.sources {
+            if let NtpSourceConfig::Standard(config) = source
+                && let Some(key_id) = config.first.key_id
+            {
+                // Keys from the keys file are only known once it is read
+                if self.legacy_auth.keys_file.is_none()
+                    && !self.symmetric_keys.iter().any(|key| key.id() == key_id)
+                {
+                    warn!(
+                        "Source {} uses symmetric key {key_id}, which is not configured.",
+                        *config.first.address
+                    );
+                    ok = false;
+                }
+
+                if !matches!(config.first.ntp_version, ProtocolVersion::V4) {
+                    warn!(
+                        "Source {} uses a symmetric key, which is only supported with NTPv4. Set `ntp-version = 4` for this source.",
+                        *config.first.address
+                    );
+                    ok = false;
+                }
+            }
+        }
+
+        for source in &self.sources {
+            let (address, limits) = match source {
+                NtpSourceConfig::Standard(config) => (
+                    config.first.address.to_string(),
+                    &config.second.poll_interval_limits,
+                ),
+                NtpSourceConfig::Nts(config) => (
+                    config.first.address.to_string(),
+                    &config.second.poll_interval_limits,
+                ),
+                NtpSourceConfig::Pool(config) => (
+                    config.first.addr.to_string(),
+                    &config.second.poll_interval_limits,
+                ),
+                NtpSourceConfig::NtsPool(config) => (
+                    config.first.addr.to_string(),
+                    &config.second.poll_interval_limits,
+                ),
+                _ => continue,
+            };
+            if let (Some(min), Some(max)) = (limits.min, limits.max)
+                && min > max
+            {
+                warn!(
+                    "Source {address} has a minimum poll interval larger than its maximum poll interval."
+                );
+                ok = false;
+            }
+        }
+
+        let sock_sources: Vec<_> = self
+            .sources
+            .iter()
+            .filter_map(|source| match source {
+                NtpSourceConfig::Sock(config) => Some((&config.first, source.source_type())),
+                _ => None,
+            })
+            .collect();
+        for (index, (config, stype)) in sock_sources.iter().enumerate() {
+            for (other, other_stype) in &sock_sources[..index] {
+                if config.path == other.path {
+                    warn!(
+                        "Multiple sock sources use the socket {}, each needs its own.",
+                        config.path.display()
+                    );
+                    ok = false;
+                } else if stype == other_stype {
+                    warn!(
+                        "Sock sources {} and {} have the same reference id, clients can't tell which one is used.",
+                        other.path.display(),
+                        config.path.display()
+                    );
+                }
+            }
+        }
+
+        if !self.legacy_auth.insecure_enable
+            && self
+                .symmetric_keys
+                .iter()
+                .any(|key| key.algorithm().is_legacy())
+        {
+            warn!(
+                "Symmetric keys with an insecure algorithm configured. Set `insecure-enable = true` in the [legacy-auth] section to use them."
+            );
+            ok = false;
+        }
+
+        if self.legacy_auth.insecure_enable {
+            warn!(
+                "Legacy authentication is enabled. MD5, SHA-1 and SHA-256 MACs do not provide proper security, consider switching to NTS or AES-CMAC keys."
+            );
+        }
+
+        if self.sources.iter().any(|config| match config {
+            NtpSourceConfig::Nts(config) => {
+                config.first.client_certificate_chain_path.is_some()
+                    != config.first.client_private_key_path.is_some()
+            }
+            NtpSourceConfig::NtsPool(config) => {
+                config.first.client_certificate_chain_path.is_some()
+                    != config.first.client_private_key_path.is_some()
+            }
+            _ => false,
+        }) {
+            warn!(
+                "NTS sources need both `client-certificate-chain-path` and `client-private-key-path` to authenticate with a client certificate."
+            );
+            ok = false;
+        }
+
+        for acme in self
+            .nts_ke
+            .iter()
+            .filter_map(|ke_server| ke_server.acme.as_ref())
+        {
+            if acme.domains.is_empty() {
+                warn!("Obtaining a certificate using ACME requires at least one domain.");
+                ok = false;
+            }
+            if !acme.accept_terms_of_service {
+                warn!(
+                    "Obtaining a certificate using ACME requires accepting the terms of service of the certificate authority with `accept-terms-of-service = true`."
+                );
+                ok = false;
+            }
+        }
+
+        if self.keyset.follow_key_storage && self.keyset.key_storage_path.is_none() {
+            warn!("Following the NTS key storage requires setting `key-storage-path`.");
+            ok = false;
+        }
+
         // Check that the NTS configuration is consistent with the NTP configuration
         for ke_server in self
             .nts_ke
             .iter()
-            .filter(|ke_server| ke_server.ntp_server.is_none())
+            .filter(|ke_server| ke_server.ntp_server.is_none() && ke_server.backends.is_empty())
         {
             if ke_server.accept_ntp_versions.contains(&NtpVersion::V4)
                 && !self.servers.iter().any(|server| {
@@ -573,7 +990,10 @@ impl From<toml::de::Error> for ConfigError {
     reason = "Long tests are not really a big problem"
 )]
 mod tests {
-    use ntp_proto::{NtpDuration, ProtocolVersion, StepThreshold};
+    use ntp_proto::{
+        CombineMode, HoldoverConfig, LeapSmearConfig, LeapSmearCurve, NtpDuration, ProtocolVersion,
+        StepThreshold,
+    };
 
     use super::*;
 
@@ -587,8 +1007,11 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_from_parts("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key_id: None,
+                    resolve_interval: 3600,
                 },
-                second: PartialSourceConfig::default()
+                second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })]
         );
         assert!(config.observability.log_level.is_none());
@@ -604,8 +1027,11 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_from_parts("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key_id: None,
+                    resolve_interval: 3600,
                 },
-                second: PartialSourceConfig::default()
+                second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })]
         );
 
@@ -619,8 +1045,11 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_from_parts("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key_id: None,
+                    resolve_interval: 3600,
                 },
-                second: PartialSourceConfig::default()
+                second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })]
         );
         assert_eq!(
@@ -650,8 +1079,11 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_from_parts("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key_id: None,
+                    resolve_interval: 3600,
                 },
-                second: PartialSourceConfig::default()
+                second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })]
         );
         assert!(
@@ -679,10 +1111,14 @@ mod tests {
             [source-defaults]
             poll-interval-limits = { min = 5, max = 9 }
             initial-poll-interval = 5
+            measurement-history = 8
             [observability]
             log-level = "info"
             observation-path = "/foo/bar/observe"
             observation-permissions = 0o567
+            control-path = "/foo/bar/control"
+            measurements-log-path = "/foo/bar/measurements.csv"
+            measurements-log-format = "csv"
             "#,
         )
         .unwrap();
@@ -693,6 +1129,20 @@ mod tests {
             Some(PathBuf::from("/foo/bar/observe"))
         );
         assert_eq!(config.observability.observation_permissions, 0o567);
+        assert_eq!(
+            config.observability.control_path,
+            Some(PathBuf::from("/foo/bar/control"))
+        );
+        assert_eq!(config.observability.control_permissions, 0o600);
+        assert_eq!(
+            config.observability.measurements_log_path,
+            Some(PathBuf::from("/foo/bar/measurements.csv"))
+        );
+        assert_eq!(
+            config.observability.measurements_log_format,
+            MeasurementsLogFormat::Csv
+        );
+        assert_eq!(config.observability.measurements_log_rotations, 5);
 
         assert_eq!(
             config.sources,
@@ -700,8 +1150,11 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_from_parts("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key_id: None,
+                    resolve_interval: 3600,
                 },
-                second: PartialSourceConfig::default()
+                second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })]
         );
 
@@ -710,6 +1163,21 @@ mod tests {
         assert_eq!(poll_interval_limits.max.as_log(), 9);
 
         assert_eq!(config.source_defaults.initial_poll_interval.as_log(), 5);
+        assert_eq!(config.source_defaults.measurement_history, 8);
+
+        let config: Result<Config, _> =
+            toml::from_str("[source-defaults]\nmeasurement-history = 1000");
+        assert!(config.is_err());
+
+        let config: Config = toml::from_str("[source-defaults]\nrate-kod-backoff = 16").unwrap();
+        assert_eq!(config.source_defaults.rate_kod_backoff, 16);
+        assert_eq!(Config::default().source_defaults.rate_kod_backoff, 2);
+
+        for factor in [0, 1, 3, 512] {
+            let config: Result<Config, _> =
+                toml::from_str(&format!("[source-defaults]\nrate-kod-backoff = {factor}"));
+            assert!(config.is_err());
+        }
 
         let config: Config = toml::from_str(
             "[[source]]\nmode = \"server\"\naddress = \"example.com\"\nntp-version = \"auto\"",
@@ -721,8 +1189,11 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_from_parts("example.com", 123).into(),
                     ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                    key_id: None,
+                    resolve_interval: 3600,
                 },
-                second: PartialSourceConfig::default()
+                second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })]
         );
         assert!(config.observability.log_level.is_none());
@@ -793,7 +1264,174 @@ mod tests {
         );
 
         assert!(config.is_ok());
-        assert!(config.unwrap().check());
+        assert_eq!(config.unwrap().check(), cfg!(feature = "server"));
+    }
+
+    #[test]
+    fn toml_sources_without_client() {
+        let config: Config = toml::from_str(
+            r#"
+            [synchronization]
+            minimum-agreeing-sources = 1
+
+            [[source]]
+            mode = "server"
+            address = "example.com"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.check(), cfg!(feature = "client"));
+    }
+
+    #[test]
+    fn toml_source_poll_interval_limits() {
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "server"
+            address = "192.0.2.1"
+            poll-interval-limits = { min = 4, max = 6 }
+
+            [[source]]
+            mode = "pool"
+            address = "pool.example.com"
+            count = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.check(), cfg!(feature = "client"));
+
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "server"
+            address = "192.0.2.1"
+            poll-interval-limits = { min = 8, max = 6 }
+            "#,
+        )
+        .unwrap();
+        assert!(!config.check());
+    }
+
+    #[test]
+    fn toml_check_sock_sources() {
+        let config: Config = toml::from_str(
+            r#"
+            [synchronization]
+            minimum-agreeing-sources = 1
+
+            [[source]]
+            mode = "sock"
+            path = "/run/ntpd-rs/gps0"
+            precision = 0.001
+            reference-id = "GPS0"
+
+            [[source]]
+            mode = "sock"
+            path = "/run/ntpd-rs/gps1"
+            precision = 0.001
+            reference-id = "GPS1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.check(), cfg!(feature = "client"));
+
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "sock"
+            path = "/run/ntpd-rs/gps0"
+            precision = 0.001
+
+            [[source]]
+            mode = "sock"
+            path = "/run/ntpd-rs/gps0"
+            precision = 0.001
+            "#,
+        )
+        .unwrap();
+        assert!(!config.check());
+    }
+
+    #[test]
+    fn toml_check_failover_threshold() {
+        let config = |threshold: usize| -> Config {
+            toml::from_str(&format!(
+                r#"
+                [synchronization]
+                minimum-agreeing-sources = 2
+                failover-threshold = {threshold}
+
+                [[source]]
+                mode = "pool"
+                address = "internal.example.com"
+
+                [[source]]
+                mode = "pool"
+                address = "pool.ntp.org"
+                priority = 1
+                "#
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(config(3).check(), cfg!(feature = "client"));
+        assert!(!config(1).check());
+    }
+
+    #[test]
+    fn toml_check_max_sources() {
+        let config = |max: usize| -> Config {
+            toml::from_str(&format!(
+                r#"
+                [synchronization]
+                minimum-agreeing-sources = 3
+                max-sources = {max}
+
+                [[source]]
+                mode = "pool"
+                address = "pool.ntp.org"
+                count = 8
+                "#
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(config(4).check(), cfg!(feature = "client"));
+        assert!(!config(2).check());
+    }
+
+    #[test]
+    fn toml_clock_algorithm() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(
+            config.synchronization.clock_algorithm,
+            ClockAlgorithm::Kalman
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            [synchronization]
+            clock-algorithm = "pi"
+
+            [synchronization.pi-algorithm]
+            filter-length = 4
+            proportional-gain = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.synchronization.clock_algorithm, ClockAlgorithm::Pi);
+        assert_eq!(config.synchronization.pi_algorithm.filter_length, 4);
+        assert!((config.synchronization.pi_algorithm.proportional_gain - 0.5).abs() < 1e-12);
+
+        let config: Result<Config, _> = toml::from_str(
+            r#"
+            [synchronization]
+            clock-algorithm = "unknown"
+            "#,
+        );
+        assert!(config.is_err());
     }
 
     #[test]
@@ -820,6 +1458,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn system_config_step_limit() {
+        let config: SynchronizationConfig = toml::from_str("").unwrap();
+        assert_eq!(config.step_limit, None);
+        assert_eq!(config.maximum_slew_offset, None);
+
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            step-limit = 0
+            maximum-slew-offset = "500ms"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.step_limit, Some(0));
+        assert_eq!(
+            config.maximum_slew_offset,
+            Some(NtpDuration::from_seconds(0.5))
+        );
+    }
+
+    #[test]
+    fn system_config_frequency_limits() {
+        let config: SynchronizationConfig = toml::from_str("").unwrap();
+        assert_eq!(config.maximum_slew_rate, None);
+        assert_eq!(config.maximum_frequency_offset, None);
+
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            maximum-slew-rate = 100
+            maximum-frequency-offset = 250.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.maximum_slew_rate, Some(100.0));
+        assert_eq!(config.maximum_frequency_offset, Some(250.5));
+
+        let config: Result<SynchronizationConfig, _> = toml::from_str("maximum-slew-rate = 0");
+        assert!(config.is_err());
+    }
+
     #[test]
     fn system_config_startup_panic_threshold() {
         let config: Result<SynchronizationConfig, _> = toml::from_str(
@@ -839,6 +1517,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn durations_with_units() {
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            startup-step-panic-threshold = { forward = "500ms", backward = "inf" }
+            single-step-panic-threshold = "1h"
+            accumulated-step-panic-threshold = "1.5d"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.startup_step_panic_threshold.forward,
+            Some(NtpDuration::from_seconds(0.5))
+        );
+        assert_eq!(config.startup_step_panic_threshold.backward, None);
+        assert_eq!(
+            config.single_step_panic_threshold.forward,
+            Some(NtpDuration::from_seconds(3600.0))
+        );
+        assert_eq!(
+            config.accumulated_step_panic_threshold,
+            Some(NtpDuration::from_seconds(129600.0))
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            [source-defaults]
+            poll-interval-limits = { min = "16s", max = 10 }
+            initial-burst = { count = 2, spacing = "500ms" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.source_defaults.poll_interval_limits.min.as_log(), 4);
+        assert_eq!(config.source_defaults.poll_interval_limits.max.as_log(), 10);
+        assert_eq!(
+            config.source_defaults.initial_burst.spacing,
+            NtpDuration::from_seconds(0.5)
+        );
+
+        // Poll intervals must be a power of two
+        let config: Result<Config, _> = toml::from_str(
+            r#"
+            [source-defaults]
+            poll-interval-limits = { min = "1min", max = 10 }
+            "#,
+        );
+        assert!(config.is_err());
+
+        let config: Result<SynchronizationConfig, _> =
+            toml::from_str(r#"single-step-panic-threshold = "-5s""#);
+        assert!(config.is_err());
+
+        let config: Result<SynchronizationConfig, _> =
+            toml::from_str(r#"single-step-panic-threshold = "5 parsecs""#);
+        assert!(config.is_err());
+    }
+
     #[test]
     fn duration_not_nan() {
         #[derive(Debug, Deserialize)]
@@ -887,6 +1622,7 @@ mod tests {
         assert!(error.to_string().contains("unknown field"));
     }
 
+    #[cfg(feature = "client")]
     #[test]
     fn clock_config() {
         let config: Result<ClockConfig, _> = toml::from_str(
@@ -920,11 +1656,198 @@ mod tests {
 
             [algorithm]
             initial-wander = 1e-7
+            combine-mode = "median"
             "#,
         );
 
         let config = config.unwrap();
         assert_eq!(config.synchronization_base.minimum_agreeing_sources, 2);
         assert_eq!(config.algorithm.initial_wander, 1e-7);
+        assert_eq!(config.algorithm.combine_mode, CombineMode::Median);
+        assert!(!config.software_latency_compensation);
+        assert!(!config.coordinated_polling);
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            minimum-agreeing-sources = 2
+            software-latency-compensation = true
+            coordinated-polling = true
+            maximum-source-stratum = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.synchronization_base.minimum_agreeing_sources, 2);
+        assert_eq!(config.synchronization_base.maintain_agreeing_sources, None);
+        assert_eq!(config.synchronization_base.minimum_source_stratum, 1);
+        assert_eq!(config.synchronization_base.maximum_source_stratum, 4);
+        assert!(config.software_latency_compensation);
+        assert!(config.coordinated_polling);
+        assert_eq!(config.max_sources, None);
+        assert_eq!(config.failover_threshold, None);
+        assert_eq!(config.source_options().failover_threshold, 2);
+        assert_eq!(config.synchronization_base.leap_smear, None);
+        assert_eq!(config.temperature, None);
+        assert_eq!(config.drift_file, None);
+        assert_eq!(config.state_file, None);
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            minimum-agreeing-sources = 3
+            maintain-agreeing-sources = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.synchronization_base.maintain_agreeing_sources,
+            Some(2)
+        );
+        assert_eq!(
+            config.synchronization_base.required_agreeing_sources(false),
+            3
+        );
+        assert_eq!(
+            config.synchronization_base.required_agreeing_sources(true),
+            2
+        );
+
+        let config: DaemonSynchronizationConfig =
+            toml::from_str(r#"drift-file = "/var/lib/ntpd-rs/drift""#).unwrap();
+        assert_eq!(
+            config.drift_file,
+            Some(PathBuf::from("/var/lib/ntpd-rs/drift"))
+        );
+
+        let config: DaemonSynchronizationConfig =
+            toml::from_str(r#"state-file = "/var/lib/ntpd-rs/state.json""#).unwrap();
+        assert_eq!(
+            config.state_file,
+            Some(PathBuf::from("/var/lib/ntpd-rs/state.json"))
+        );
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            [temperature]
+            sensor = { hwmon = "/sys/class/hwmon/hwmon0/temp1_input" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.temperature,
+            Some(TemperatureConfig {
+                sensor: TemperatureSensor::Hwmon(PathBuf::from(
+                    "/sys/class/hwmon/hwmon0/temp1_input"
+                )),
+                interval: NonZeroU64::new(60).unwrap(),
+            })
+        );
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            [temperature]
+            sensor = { socket = "/run/ntpd-rs/temperature" }
+            interval = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.temperature.unwrap().sensor,
+            TemperatureSensor::Socket(PathBuf::from("/run/ntpd-rs/temperature"))
+        );
+
+        let config: Result<DaemonSynchronizationConfig, _> = toml::from_str(
+            r#"
+            [temperature]
+            sensor = { file = "/tmp/temperature" }
+            interval = 0
+            "#,
+        );
+        assert!(config.is_err());
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            [leap-smear]
+            window = 3600
+            curve = "cosine"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.synchronization_base.leap_smear,
+            Some(LeapSmearConfig {
+                window: 3600.0,
+                curve: LeapSmearCurve::Cosine,
+            })
+        );
+
+        let config: DaemonSynchronizationConfig = toml::from_str("[leap-smear]").unwrap();
+        assert_eq!(
+            config.synchronization_base.leap_smear,
+            Some(LeapSmearConfig::default())
+        );
+
+        let config: Result<DaemonSynchronizationConfig, _> =
+            toml::from_str("[leap-smear]\nwindow = 0");
+        assert!(config.is_err());
+
+        let config: DaemonSynchronizationConfig = toml::from_str("").unwrap();
+        assert_eq!(config.synchronization_base.holdover, None);
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            [holdover]
+            duration = 7200
+            dispersion-rate = 1e-5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.synchronization_base.holdover,
+            Some(HoldoverConfig {
+                duration: 7200.0,
+                dispersion_rate: 1e-5,
+            })
+        );
+
+        let config: Result<DaemonSynchronizationConfig, _> =
+            toml::from_str("[holdover]\nduration = 0");
+        assert!(config.is_err());
+        let config: Result<DaemonSynchronizationConfig, _> =
+            toml::from_str("[holdover]\ndispersion-rate = -1e-6");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_phc_steering_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.phc_steering.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [[phc-steering]]
+            interface = "eth0"
+
+            [[phc-steering]]
+            interface = "eth1"
+            poll-interval = 2
+            offset = 37
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.phc_steering.len(), 2);
+        assert_eq!(config.phc_steering[0].interface.to_string(), "eth0");
+        assert_eq!(
+            config.phc_steering[0].poll_interval,
+            PollInterval::from_byte(0)
+        );
+        assert_eq!(config.phc_steering[0].offset, 0.0);
+        assert_eq!(config.phc_steering[0].step_threshold, 1e-3);
+        assert_eq!(
+            config.phc_steering[1].poll_interval,
+            PollInterval::from_byte(2)
+        );
+        assert_eq!(config.phc_steering[1].offset, 37.0);
+
+        let config: Result<Config, _> = toml::from_str("[[phc-steering]]\noffset = 37");
+        assert!(config.is_err());
     }
 }
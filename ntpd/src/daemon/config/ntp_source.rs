@@ -1,4 +1,4 @@
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 use std::sync::Mutex;
 use std::{
     fmt,
@@ -8,14 +8,21 @@ use std::{
     sync::Arc,
 };
 
-use ntp_proto::{PollInterval, PollIntervalLimits, SourceConfig};
-use ntp_proto::{ProtocolVersion, tls_utils::Certificate};
+use ntp_proto::{
+    AeadAlgorithm, DelayAsymmetry, InitialBurst, PollInterval, ReferenceId, SourceType,
+};
+#[cfg(feature = "client")]
+use ntp_proto::{PollIntervalLimits, SourceConfig};
+use ntp_proto::{
+    ProtocolVersion, roughtime,
+    tls_utils::{Certificate, ServerName},
+};
 use serde::{
     Deserialize, Deserializer,
     de::{self, Visitor},
 };
 
-use super::super::keyexchange::certificates_from_file;
+use super::super::certificates::certificates_from_file;
 
 fn deserialize_ntp_version<'de, D>(deserializer: D) -> Result<ProtocolVersion, D::Error>
 where
@@ -66,6 +73,10 @@ fn default_ntp_version() -> ProtocolVersion {
     ProtocolVersion::V4
 }
 
+fn default_resolve_interval() -> u64 {
+    3600
+}
+
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct StandardSource {
@@ -75,6 +86,13 @@ pub struct StandardSource {
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    /// Id of the symmetric key used to authenticate the exchanges with this source
+    #[serde(default)]
+    pub key_id: Option<u32>,
+    /// Seconds after which the address is resolved again, 0 to keep the
+    /// first address found
+    #[serde(default = "default_resolve_interval")]
+    pub resolve_interval: u64,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -89,11 +107,75 @@ pub struct NtsSourceConfig {
         rename = "certificate-authority"
     )]
     pub certificate_authorities: Arc<[Certificate]>,
+    #[serde(default)]
+    pub client_certificate_chain_path: Option<PathBuf>,
+    #[serde(default)]
+    pub client_private_key_path: Option<PathBuf>,
     #[serde(
         default = "default_ntp_version",
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    /// AEAD algorithms offered during key exchange, in order of preference
+    #[serde(
+        default = "default_aead_algorithms",
+        deserialize_with = "deserialize_aead_algorithms"
+    )]
+    pub aead_algorithms: Vec<AeadAlgorithm>,
+    /// Only trust these certificate authorities for the key exchange server
+    #[serde(
+        default,
+        deserialize_with = "deserialize_pinned_certificate_authorities",
+        rename = "pin-certificate-authority"
+    )]
+    pub pinned_certificate_authorities: Vec<Certificate>,
+    /// SHA-256 hashes of public keys of which at least one must be in the key
+    /// exchange server's certificate chain
+    #[serde(default, deserialize_with = "deserialize_spki_pins")]
+    pub pin_spki_sha256: Vec<[u8; 32]>,
+    /// Resume earlier TLS sessions with the key exchange server
+    #[serde(default = "default_session_resumption")]
+    pub session_resumption: bool,
+    /// Name the certificate of the key exchange server must be valid for,
+    /// instead of the host in `address`
+    #[serde(default, deserialize_with = "deserialize_server_name")]
+    pub server_name: Option<String>,
+    /// NTP server to use instead of the one the key exchange server names
+    #[serde(default)]
+    pub ntp_address: Option<NtpAddress>,
+    /// What to do when the key exchange fails after it worked before
+    #[serde(default)]
+    pub nts_failure_policy: NtsFailurePolicy,
+}
+
+fn default_session_resumption() -> bool {
+    true
+}
+
+/// How an NTS source copes with a key exchange that fails after earlier
+/// ones succeeded
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NtsFailurePolicy {
+    /// Keep retrying the key exchange, without using the server meanwhile
+    #[default]
+    Retry,
+    /// Use the last known NTP server without authentication until a key
+    /// exchange succeeds again
+    Unauthenticated,
+}
+
+fn deserialize_server_name<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    if let Err(e) = ServerName::try_from(name.as_str()) {
+        return Err(de::Error::custom(format!(
+            "invalid server name {name}: {e}"
+        )));
+    }
+    Ok(Some(name))
 }
 
 fn deserialize_certificate_authorities<'de, D>(
@@ -117,6 +199,60 @@ fn default_certificate_authorities() -> Arc<[Certificate]> {
     Arc::from([])
 }
 
+fn deserialize_pinned_certificate_authorities<'de, D>(
+    deserializer: D,
+) -> Result<Vec<Certificate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let certificate_path: PathBuf = PathBuf::deserialize(deserializer)?;
+    match certificates_from_file(&certificate_path) {
+        Ok(certificates) if certificates.is_empty() => Err(de::Error::custom(format!(
+            "no certificates found in {certificate_path:?}"
+        ))),
+        Ok(certificates) => Ok(certificates),
+        Err(io_error) => Err(de::Error::custom(format!(
+            "error while parsing certificate file {certificate_path:?}: {io_error:?}"
+        ))),
+    }
+}
+
+fn deserialize_spki_pins<'de, D>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pins: Vec<String> = Deserialize::deserialize(deserializer)?;
+    pins.iter()
+        .map(|pin| {
+            decode_base64(pin)
+                .and_then(|hash| hash.try_into().ok())
+                .ok_or_else(|| {
+                    de::Error::invalid_value(
+                        de::Unexpected::Str(pin),
+                        &"a base64 encoded SHA-256 hash",
+                    )
+                })
+        })
+        .collect()
+}
+
+pub(crate) fn default_aead_algorithms() -> Vec<AeadAlgorithm> {
+    AeadAlgorithm::SUPPORTED.to_vec()
+}
+
+pub(crate) fn deserialize_aead_algorithms<'de, D>(
+    deserializer: D,
+) -> Result<Vec<AeadAlgorithm>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let algorithms = Vec::<AeadAlgorithm>::deserialize(deserializer)?;
+    if algorithms.is_empty() {
+        return Err(de::Error::custom("at least one AEAD algorithm is required"));
+    }
+    Ok(algorithms)
+}
+
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct PoolSourceConfig {
@@ -150,6 +286,10 @@ pub struct NtsPoolSourceConfig {
         rename = "certificate-authority"
     )]
     pub certificate_authorities: Arc<[Certificate]>,
+    #[serde(default)]
+    pub client_certificate_chain_path: Option<PathBuf>,
+    #[serde(default)]
+    pub client_private_key_path: Option<PathBuf>,
     #[serde(default = "max_sources_default")]
     pub count: usize,
     #[serde(
@@ -157,6 +297,26 @@ pub struct NtsPoolSourceConfig {
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    /// AEAD algorithms offered during key exchange, in order of preference
+    #[serde(
+        default = "default_aead_algorithms",
+        deserialize_with = "deserialize_aead_algorithms"
+    )]
+    pub aead_algorithms: Vec<AeadAlgorithm>,
+    /// Only trust these certificate authorities for the key exchange server
+    #[serde(
+        default,
+        deserialize_with = "deserialize_pinned_certificate_authorities",
+        rename = "pin-certificate-authority"
+    )]
+    pub pinned_certificate_authorities: Vec<Certificate>,
+    /// SHA-256 hashes of public keys of which at least one must be in the key
+    /// exchange server's certificate chain
+    #[serde(default, deserialize_with = "deserialize_spki_pins")]
+    pub pin_spki_sha256: Vec<[u8; 32]>,
+    /// Resume earlier TLS sessions with the key exchange servers
+    #[serde(default = "default_session_resumption")]
+    pub session_resumption: bool,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -164,9 +324,39 @@ pub struct SockSourceConfig {
     pub path: PathBuf,
     pub precision: f64,
     pub accuracy: f64,
+    pub socket: SockSocketOptions,
+}
+
+/// How the socket of a sock source is created, so gpsd running as another
+/// user can write to it
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SockSocketOptions {
+    /// Mode of the socket, by default that of any new file of the daemon
+    pub permissions: Option<u32>,
+    /// User owning the socket, by name or id
+    pub user: Option<String>,
+    /// Group owning the socket, by name or id
+    pub group: Option<String>,
+    /// Create the directory the socket is in when it does not exist
+    pub create_directory: bool,
+}
+
+/// Parse an owner in the form `user`, `user:group` or `:group`
+fn parse_socket_owner(owner: &str) -> Option<(Option<String>, Option<String>)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let user = (!user.is_empty()).then(|| user.to_string());
+    let group = match group {
+        Some("") => return None,
+        group => group.map(str::to_string),
+    };
+    (user.is_some() || group.is_some()).then_some((user, group))
 }
 
 impl<'de> Deserialize<'de> for SockSourceConfig {
+    #[expect(clippy::too_many_lines, reason = "Deserializers can be a bit wordy")]
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
@@ -178,6 +368,10 @@ impl<'de> Deserialize<'de> for SockSourceConfig {
             Precision,
             Accuracy,
             MeasurementNoiseEstimate,
+            Permissions,
+            Owner,
+            #[serde(rename = "create-directory")]
+            CreateDirectory,
         }
 
         struct SockSourceConfigVisitor;
@@ -196,6 +390,9 @@ impl<'de> Deserialize<'de> for SockSourceConfig {
                 let mut path = None;
                 let mut precision = None;
                 let mut accuracy = None;
+                let mut permissions = None;
+                let mut owner = None;
+                let mut create_directory = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Path => {
@@ -245,21 +442,52 @@ impl<'de> Deserialize<'de> for SockSourceConfig {
                             {
                                 return Err(de::Error::invalid_value(
                                     serde::de::Unexpected::Float(accuracy_raw),
-                                    &"precision should be positive",
+                                    &"accuracy should be positive",
                                 ));
                             }
                             accuracy = Some(accuracy_raw);
                         }
+                        Field::Permissions => {
+                            if permissions.is_some() {
+                                return Err(de::Error::duplicate_field("permissions"));
+                            }
+                            permissions = Some(map.next_value()?);
+                        }
+                        Field::Owner => {
+                            if owner.is_some() {
+                                return Err(de::Error::duplicate_field("owner"));
+                            }
+                            let owner_raw: String = map.next_value()?;
+                            owner = Some(parse_socket_owner(&owner_raw).ok_or_else(|| {
+                                de::Error::invalid_value(
+                                    serde::de::Unexpected::Str(&owner_raw),
+                                    &"user, user:group or :group",
+                                )
+                            })?);
+                        }
+                        Field::CreateDirectory => {
+                            if create_directory.is_some() {
+                                return Err(de::Error::duplicate_field("create-directory"));
+                            }
+                            create_directory = Some(map.next_value()?);
+                        }
                     }
                 }
                 let path = path.ok_or_else(|| serde::de::Error::missing_field("path"))?;
                 let precision =
                     precision.ok_or_else(|| serde::de::Error::missing_field("precision"))?;
                 let accuracy = accuracy.unwrap_or(0.0);
+                let (user, group) = owner.unwrap_or_default();
                 Ok(SockSourceConfig {
                     path,
                     precision,
                     accuracy,
+                    socket: SockSocketOptions {
+                        permissions,
+                        user,
+                        group,
+                        create_directory: create_directory.unwrap_or(false),
+                    },
                 })
             }
         }
@@ -269,99 +497,701 @@ impl<'de> Deserialize<'de> for SockSourceConfig {
             "precision",
             "accuracy",
             "measurement_noise_estimate",
+            "permissions",
+            "owner",
+            "create-directory",
         ];
         deserializer.deserialize_struct("SockSourceConfig", FIELDS, SockSourceConfigVisitor)
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BroadcastSourceConfig {
+    /// Address on which broadcast packets are received
+    #[serde(default = "default_broadcast_listen")]
+    pub listen: SocketAddr,
+    /// Multicast group to join, when listening for multicast packets
+    #[serde(default, deserialize_with = "deserialize_multicast_group")]
+    pub multicast_group: Option<IpAddr>,
+    /// One-way delay from the server in seconds, skipping the calibration
+    #[serde(default, deserialize_with = "deserialize_broadcast_delay")]
+    pub delay: Option<f64>,
+    #[serde(
+        default = "default_broadcast_precision",
+        deserialize_with = "deserialize_positive"
+    )]
+    pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
+    pub accuracy: f64,
+}
+
+fn default_broadcast_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 123))
+}
+
+fn default_broadcast_precision() -> f64 {
+    1e-3
+}
+
+fn deserialize_multicast_group<'de, D>(deserializer: D) -> Result<Option<IpAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_multicast_address(deserializer).map(Some)
+}
+
+fn deserialize_multicast_address<'de, D>(deserializer: D) -> Result<IpAddr, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let group: IpAddr = Deserialize::deserialize(deserializer)?;
+    if !group.is_multicast() {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Str(&group.to_string()),
+            &"a multicast address",
+        ));
+    }
+    Ok(group)
+}
+
+fn deserialize_broadcast_delay<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let delay: f64 = Deserialize::deserialize(deserializer)?;
+    if !(delay.is_finite() && delay >= 0.0) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Float(delay),
+            &"a non-negative delay",
+        ));
+    }
+    Ok(Some(delay))
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RoughtimeSourceConfig {
+    pub address: RoughtimeAddress,
+    /// Base64 encoded long-term public key of the server
+    #[serde(deserialize_with = "deserialize_roughtime_public_key")]
+    pub public_key: [u8; roughtime::PUBLIC_KEY_SIZE],
+    #[serde(default = "default_roughtime_poll_interval")]
+    pub poll_interval: PollInterval,
+    #[serde(
+        default = "default_roughtime_precision",
+        deserialize_with = "deserialize_positive"
+    )]
+    pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
+    pub accuracy: f64,
+}
+
+/// Sentences of a GPS receiver carrying the time
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NmeaSentence {
+    Rmc,
+    Gga,
+    Zda,
+}
+
+/// Delay in seconds between the start of the second and the end of each
+/// sentence arriving over the serial port
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
 #[serde(deny_unknown_fields)]
-pub struct PartialPollIntervalLimits {
-    pub min: Option<PollInterval>,
-    pub max: Option<PollInterval>,
+pub struct NmeaOffsets {
+    #[serde(default)]
+    pub rmc: f64,
+    #[serde(default)]
+    pub gga: f64,
+    #[serde(default)]
+    pub zda: f64,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+impl NmeaOffsets {
+    #[cfg(feature = "client")]
+    pub fn get(&self, sentence: NmeaSentence) -> f64 {
+        match sentence {
+            NmeaSentence::Rmc => self.rmc,
+            NmeaSentence::Gga => self.gga,
+            NmeaSentence::Zda => self.zda,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
-pub struct PartialSourceConfig {
-    /// Minima and maxima for the poll interval of clients
+pub struct NmeaSourceConfig {
+    /// Serial port the GPS receiver is connected to
+    pub path: PathBuf,
+    #[serde(
+        default = "default_nmea_baud_rate",
+        deserialize_with = "deserialize_baud_rate"
+    )]
+    pub baud_rate: u32,
+    /// Sentences used for their time, only the first of each second is used
+    #[serde(default = "default_nmea_sentences")]
+    pub sentences: Vec<NmeaSentence>,
     #[serde(default)]
-    pub poll_interval_limits: PartialPollIntervalLimits,
+    pub offsets: NmeaOffsets,
+    #[serde(
+        default = "default_nmea_precision",
+        deserialize_with = "deserialize_positive"
+    )]
+    pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
+    pub accuracy: f64,
+}
 
-    /// Initial poll interval of the system
-    pub initial_poll_interval: Option<PollInterval>,
+fn default_nmea_baud_rate() -> u32 {
+    9600
 }
 
-impl PartialSourceConfig {
-    pub fn with_defaults(self, defaults: SourceConfig) -> SourceConfig {
-        SourceConfig {
-            poll_interval_limits: PollIntervalLimits {
-                min: self
-                    .poll_interval_limits
-                    .min
-                    .unwrap_or(defaults.poll_interval_limits.min),
-                max: self
-                    .poll_interval_limits
-                    .max
-                    .unwrap_or(defaults.poll_interval_limits.max),
-            },
-            initial_poll_interval: self
-                .initial_poll_interval
-                .unwrap_or(defaults.initial_poll_interval),
-        }
+fn default_nmea_sentences() -> Vec<NmeaSentence> {
+    vec![NmeaSentence::Rmc, NmeaSentence::Gga, NmeaSentence::Zda]
+}
+
+fn default_nmea_precision() -> f64 {
+    0.05
+}
+
+fn deserialize_baud_rate<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    const BAUD_RATES: &[u32] = &[4800, 9600, 19200, 38400, 57600, 115_200, 230_400];
+
+    let baud_rate: u32 = Deserialize::deserialize(deserializer)?;
+    if !BAUD_RATES.contains(&baud_rate) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Unsigned(baud_rate.into()),
+            &"one of 4800, 9600, 19200, 38400, 57600, 115200 or 230400",
+        ));
     }
+    Ok(baud_rate)
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
-#[serde(deny_unknown_fields)]
-pub struct FlattenedPair<T, U> {
-    #[serde(flatten)]
-    pub first: T,
-    #[serde(flatten)]
-    pub second: U,
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct GpsdSourceConfig {
+    #[serde(default = "default_gpsd_address")]
+    pub address: GpsdAddress,
+    /// Only use reports of this device, by default those of all devices
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Also use the PPS reports, not just the time of the serial data
+    #[serde(default = "default_gpsd_pps")]
+    pub pps: bool,
+    #[serde(
+        default = "default_gpsd_precision",
+        deserialize_with = "deserialize_positive"
+    )]
+    pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
+    pub accuracy: f64,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct PpsSourceConfig {
+fn default_gpsd_address() -> GpsdAddress {
+    GpsdAddress(NormalizedAddress::new_from_parts(
+        "localhost",
+        NormalizedAddress::GPSD_DEFAULT_PORT,
+    ))
+}
+
+fn default_gpsd_pps() -> bool {
+    true
+}
+
+fn default_gpsd_precision() -> f64 {
+    0.01
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PhcSourceConfig {
+    /// PTP hardware clock device, such as `/dev/ptp0`
     pub path: PathBuf,
+    #[serde(default = "default_phc_poll_interval")]
+    pub poll_interval: PollInterval,
+    /// Seconds the clock is ahead of UTC, e.g. 37 for a clock running on TAI.
+    /// Deprecated in favor of `offset-correction`, which has the opposite sign
+    #[serde(default, deserialize_with = "deserialize_phc_offset")]
+    pub offset: f64,
+    #[serde(
+        default = "default_phc_precision",
+        deserialize_with = "deserialize_positive"
+    )]
     pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
     pub accuracy: f64,
-    pub period: f64,
 }
 
-impl<'de> Deserialize<'de> for PpsSourceConfig {
-    #[expect(clippy::too_many_lines, reason = "Deserializers can be a bit wordy")]
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "snake_case")]
-        enum Field {
-            Path,
-            Precision,
-            Accuracy,
-            MeasurementNoiseEstimate,
-            Period,
+fn deserialize_phc_offset<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    tracing::warn!(
+        "The offset field of phc sources is deprecated. Please switch to using the offset-correction field, e.g. offset-correction = -37 for a clock running on TAI"
+    );
+    deserialize_correction(deserializer).map(Option::unwrap_or_default)
+}
+
+fn default_phc_poll_interval() -> PollInterval {
+    // Reading the clock is cheap, and the more samples the better
+    PollInterval::from_byte(0)
+}
+
+fn default_phc_precision() -> f64 {
+    1e-6
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PtpSourceConfig {
+    /// Multicast group the masters send their messages to
+    #[serde(
+        default = "default_ptp_group",
+        deserialize_with = "deserialize_multicast_address"
+    )]
+    pub group: IpAddr,
+    /// Only masters in this PTP domain are used
+    #[serde(default)]
+    pub domain: u8,
+    #[serde(
+        default = "default_ptp_precision",
+        deserialize_with = "deserialize_positive"
+    )]
+    pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
+    pub accuracy: f64,
+}
+
+fn default_ptp_group() -> IpAddr {
+    IpAddr::from([224, 0, 1, 129])
+}
+
+fn default_ptp_precision() -> f64 {
+    // Timestamps are taken in software
+    1e-4
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ShmSourceConfig {
+    /// Number of the shared memory segment, as used by ntpd's SHM driver
+    pub unit: u8,
+    #[serde(default = "default_shm_poll_interval")]
+    pub poll_interval: PollInterval,
+    #[serde(
+        default = "default_shm_precision",
+        deserialize_with = "deserialize_positive"
+    )]
+    pub precision: f64,
+    #[serde(default, deserialize_with = "deserialize_positive")]
+    pub accuracy: f64,
+}
+
+fn default_shm_poll_interval() -> PollInterval {
+    // Feeders usually write a sample every second
+    PollInterval::from_byte(0)
+}
+
+fn default_shm_precision() -> f64 {
+    1e-3
+}
+
+fn default_roughtime_poll_interval() -> PollInterval {
+    // About once every 17 minutes, the time is coarse anyway
+    PollInterval::from_byte(10)
+}
+
+fn default_roughtime_precision() -> f64 {
+    1.0
+}
+
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim_end_matches('=');
+
+    let mut result = Vec::with_capacity(data.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in data.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
         }
+    }
 
-        struct PpsSourceConfigVisitor;
+    Some(result)
+}
 
-        impl<'de> serde::de::Visitor<'de> for PpsSourceConfigVisitor {
-            type Value = PpsSourceConfig;
+fn deserialize_roughtime_public_key<'de, D>(
+    deserializer: D,
+) -> Result<[u8; roughtime::PUBLIC_KEY_SIZE], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let key: String = Deserialize::deserialize(deserializer)?;
+    decode_base64(&key)
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| {
+            de::Error::invalid_value(
+                de::Unexpected::Str(&key),
+                &"a base64 encoded Ed25519 public key",
+            )
+        })
+}
 
-            fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
-                formatter.write_str("struct PpsSourceConfig")
-            }
+fn deserialize_positive<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: f64 = Deserialize::deserialize(deserializer)?;
+    if !(value.is_finite() && value > 0.0) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Float(value),
+            &"a positive value",
+        ));
+    }
+    Ok(value)
+}
 
-            fn visit_map<V>(self, mut map: V) -> Result<PpsSourceConfig, V::Error>
-            where
-                V: serde::de::MapAccess<'de>,
-            {
-                let mut path = None;
-                let mut precision = None;
-                let mut accuracy = None;
-                let mut period = None;
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PartialPollIntervalLimits {
+    pub min: Option<PollInterval>,
+    pub max: Option<PollInterval>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PartialSourceConfig {
+    /// Minima and maxima for the poll interval of clients
+    #[serde(default)]
+    pub poll_interval_limits: PartialPollIntervalLimits,
+
+    /// Initial poll interval of the system
+    pub initial_poll_interval: Option<PollInterval>,
+
+    /// Burst of polls sent when the source is first mobilized
+    pub initial_burst: Option<InitialBurst>,
+
+    /// Relative weight of the source when combining the selected sources
+    #[serde(default, deserialize_with = "deserialize_weight")]
+    pub weight: Option<f64>,
+
+    /// Use only this source for the time estimate when it is selected
+    pub prefer: Option<bool>,
+
+    /// Monitor this source without ever using it for synchronization
+    pub noselect: Option<bool>,
+
+    /// Ask the server to respond in interleaved mode
+    pub interleaved: Option<bool>,
+
+    /// Demobilize the source after this many responses in a row fail
+    /// authentication
+    pub max_authentication_failures: Option<u32>,
+
+    /// Asymmetry of the network path to the source
+    #[serde(default, deserialize_with = "deserialize_delay_asymmetry")]
+    pub delay_asymmetry: Option<DelayAsymmetry>,
+
+    /// Local address, and optionally port, to send requests from
+    #[serde(default, deserialize_with = "deserialize_local_address")]
+    pub local_address: Option<SocketAddr>,
+}
+
+fn deserialize_weight<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let weight: f64 = Deserialize::deserialize(deserializer)?;
+    if !(weight.is_finite() && weight > 0.0) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Float(weight),
+            &"a positive weight",
+        ));
+    }
+    Ok(Some(weight))
+}
+
+fn deserialize_delay_asymmetry<'de, D>(deserializer: D) -> Result<Option<DelayAsymmetry>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let asymmetry: DelayAsymmetry = Deserialize::deserialize(deserializer)?;
+    match asymmetry {
+        DelayAsymmetry::Fraction(fraction) if !(0.0..=1.0).contains(&fraction) => {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Float(fraction),
+                &"a fraction between 0 and 1",
+            ))
+        }
+        DelayAsymmetry::Absolute(asymmetry) if !asymmetry.is_finite() => {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Float(asymmetry),
+                &"a finite number of seconds",
+            ))
+        }
+        _ => Ok(Some(asymmetry)),
+    }
+}
+
+fn deserialize_correction<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let correction: f64 = Deserialize::deserialize(deserializer)?;
+    if !correction.is_finite() {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Float(correction),
+            &"a finite number of seconds",
+        ));
+    }
+    Ok(Some(correction))
+}
+
+/// Either an IP address, or an IP address and a port
+fn deserialize_local_address<'de, D>(deserializer: D) -> Result<Option<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let address: String = Deserialize::deserialize(deserializer)?;
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(Some(SocketAddr::new(ip, 0)));
+    }
+    address.parse().map(Some).map_err(|_| {
+        de::Error::invalid_value(
+            de::Unexpected::Str(&address),
+            &"an IP address, optionally with a port",
+        )
+    })
+}
+
+impl PartialSourceConfig {
+    #[cfg(feature = "client")]
+    pub fn with_defaults(self, defaults: SourceConfig) -> SourceConfig {
+        // A bound set for the source takes precedence over the inherited one,
+        // so raising only the minimum above the default maximum (or lowering
+        // only the maximum below the default minimum) still works
+        let poll_interval_limits = match self.poll_interval_limits {
+            PartialPollIntervalLimits {
+                min: Some(min),
+                max: Some(max),
+            } => PollIntervalLimits { min, max },
+            PartialPollIntervalLimits {
+                min: Some(min),
+                max: None,
+            } => PollIntervalLimits {
+                min,
+                max: defaults.poll_interval_limits.max.max(min),
+            },
+            PartialPollIntervalLimits {
+                min: None,
+                max: Some(max),
+            } => PollIntervalLimits {
+                min: defaults.poll_interval_limits.min.min(max),
+                max,
+            },
+            PartialPollIntervalLimits {
+                min: None,
+                max: None,
+            } => defaults.poll_interval_limits,
+        };
+
+        SourceConfig {
+            poll_interval_limits,
+            initial_poll_interval: self
+                .initial_poll_interval
+                .unwrap_or(defaults.initial_poll_interval),
+            initial_burst: self.initial_burst.unwrap_or(defaults.initial_burst),
+            weight: self.weight.unwrap_or(defaults.weight),
+            prefer: self.prefer.unwrap_or(defaults.prefer),
+            noselect: self.noselect.unwrap_or(defaults.noselect),
+            trust: defaults.trust,
+            require: defaults.require,
+            measurement_history: defaults.measurement_history,
+            interleaved: self.interleaved.unwrap_or(defaults.interleaved),
+            rate_kod_backoff: defaults.rate_kod_backoff,
+            max_authentication_failures: self
+                .max_authentication_failures
+                .unwrap_or(defaults.max_authentication_failures),
+            delay_asymmetry: self.delay_asymmetry.unwrap_or(defaults.delay_asymmetry),
+            offset_correction: defaults.offset_correction,
+            delay_correction: defaults.delay_correction,
+            local_address: self.local_address.or(defaults.local_address),
+        }
+    }
+}
+
+/// Options that can be set on sources of every mode
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommonSourceConfig {
+    /// Constant added to the offset of every measurement
+    #[serde(default, alias = "offset", deserialize_with = "deserialize_correction")]
+    pub offset_correction: Option<f64>,
+
+    /// Known delay of cables and processing in every measurement
+    #[serde(default, deserialize_with = "deserialize_correction")]
+    pub delay_correction: Option<f64>,
+
+    /// Assume this source is correct, so untrusted sources can't outvote it
+    pub trust: Option<bool>,
+
+    /// Don't update the clock unless this source survives selection
+    pub require: Option<bool>,
+
+    /// Failover group of the source, groups with a higher number are only
+    /// mobilized when the groups before them fall short
+    pub priority: Option<u8>,
+}
+
+impl CommonSourceConfig {
+    #[cfg(feature = "client")]
+    pub fn with_defaults(&self, defaults: SourceConfig) -> SourceConfig {
+        SourceConfig {
+            offset_correction: self.offset_correction.unwrap_or(defaults.offset_correction),
+            delay_correction: self.delay_correction.unwrap_or(defaults.delay_correction),
+            trust: self.trust.unwrap_or(defaults.trust),
+            require: self.require.unwrap_or(defaults.require),
+            ..defaults
+        }
+    }
+}
+
+/// How a reference clock is presented to our clients when we are
+/// synchronized to it. By default the stratum and reference id depend on the
+/// mode of the source.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReferenceClockConfig {
+    /// Stratum of the source, we advertise one more when synchronized to it
+    #[serde(default, deserialize_with = "deserialize_stratum")]
+    pub stratum: Option<u8>,
+
+    /// Reference id we advertise when synchronized to this source
+    #[serde(default, deserialize_with = "deserialize_reference_id")]
+    pub reference_id: Option<ReferenceId>,
+}
+
+fn deserialize_stratum<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let stratum: u8 = Deserialize::deserialize(deserializer)?;
+    if stratum > 15 {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Unsigned(stratum.into()),
+            &"stratum should be at most 15",
+        ));
+    }
+    Ok(Some(stratum))
+}
+
+fn deserialize_reference_id<'de, D>(deserializer: D) -> Result<Option<ReferenceId>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let code: String = Deserialize::deserialize(deserializer)?;
+    ReferenceId::from_code(&code).map(Some).ok_or_else(|| {
+        de::Error::invalid_value(de::Unexpected::Str(&code), &"one to four ASCII characters")
+    })
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FlattenedPair<T, U> {
+    #[serde(flatten)]
+    pub first: T,
+    #[serde(flatten)]
+    pub second: U,
+    #[serde(flatten)]
+    pub common: CommonSourceConfig,
+}
+
+#[cfg(feature = "client")]
+impl<T> FlattenedPair<T, PartialSourceConfig> {
+    /// The configuration of the source, with `defaults` filling in what is
+    /// not set for it
+    pub fn source_config(&self, defaults: SourceConfig) -> SourceConfig {
+        self.common
+            .with_defaults(self.second.clone().with_defaults(defaults))
+    }
+}
+
+/// Edge of the PPS signal that marks the start of a period
+#[cfg(feature = "pps")]
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PpsEdge {
+    #[default]
+    Assert,
+    Clear,
+}
+
+#[cfg(feature = "pps")]
+#[derive(Debug, PartialEq, Clone)]
+pub struct PpsSourceConfig {
+    pub path: PathBuf,
+    pub precision: f64,
+    pub accuracy: f64,
+    pub period: f64,
+    pub edge: PpsEdge,
+    /// Name or address of the source that numbers the seconds of the pulses
+    pub lock: Option<String>,
+}
+
+#[cfg(feature = "pps")]
+impl<'de> Deserialize<'de> for PpsSourceConfig {
+    #[expect(clippy::too_many_lines, reason = "Deserializers can be a bit wordy")]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "snake_case")]
+        enum Field {
+            Path,
+            Precision,
+            Accuracy,
+            MeasurementNoiseEstimate,
+            Period,
+            Edge,
+            Lock,
+        }
+
+        struct PpsSourceConfigVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PpsSourceConfigVisitor {
+            type Value = PpsSourceConfig;
+
+            fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+                formatter.write_str("struct PpsSourceConfig")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<PpsSourceConfig, V::Error>
+            where
+                V: serde::de::MapAccess<'de>,
+            {
+                let mut path = None;
+                let mut precision = None;
+                let mut accuracy = None;
+                let mut period = None;
+                let mut edge = None;
+                let mut lock = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Path => {
@@ -397,7 +1227,7 @@ impl<'de> Deserialize<'de> for PpsSourceConfig {
                             {
                                 return Err(de::Error::invalid_value(
                                     serde::de::Unexpected::Float(precision_raw),
-                                    &"measurement_noise_estimate should be positive",
+                                    &"precision should be positive",
                                 ));
                             }
                             precision = Some(precision_raw);
@@ -411,7 +1241,7 @@ impl<'de> Deserialize<'de> for PpsSourceConfig {
                             {
                                 return Err(de::Error::invalid_value(
                                     serde::de::Unexpected::Float(accuracy_raw),
-                                    &"precision should be positive",
+                                    &"accuracy should be positive",
                                 ));
                             }
                             accuracy = Some(accuracy_raw);
@@ -429,6 +1259,18 @@ impl<'de> Deserialize<'de> for PpsSourceConfig {
                             }
                             period = Some(period_raw);
                         }
+                        Field::Edge => {
+                            if edge.is_some() {
+                                return Err(de::Error::duplicate_field("edge"));
+                            }
+                            edge = Some(map.next_value()?);
+                        }
+                        Field::Lock => {
+                            if lock.is_some() {
+                                return Err(de::Error::duplicate_field("lock"));
+                            }
+                            lock = Some(map.next_value()?);
+                        }
                     }
                 }
                 let path = path.ok_or_else(|| serde::de::Error::missing_field("path"))?;
@@ -436,16 +1278,27 @@ impl<'de> Deserialize<'de> for PpsSourceConfig {
                     precision.ok_or_else(|| serde::de::Error::missing_field("precision"))?;
                 let accuracy = accuracy.unwrap_or(0.0);
                 let period = period.unwrap_or(1.0);
+                let edge = edge.unwrap_or_default();
                 Ok(PpsSourceConfig {
                     path,
                     precision,
                     accuracy,
                     period,
+                    edge,
+                    lock,
                 })
             }
         }
 
-        const FIELDS: &[&str] = &["path", "precision", "measurement_noise_estimate"];
+        const FIELDS: &[&str] = &[
+            "path",
+            "precision",
+            "accuracy",
+            "measurement_noise_estimate",
+            "period",
+            "edge",
+            "lock",
+        ];
         deserializer.deserialize_struct("PpsSourceConfig", FIELDS, PpsSourceConfigVisitor)
     }
 }
@@ -462,10 +1315,85 @@ pub enum NtpSourceConfig {
     #[serde(rename = "nts-pool")]
     NtsPool(FlattenedPair<NtsPoolSourceConfig, PartialSourceConfig>),
     #[serde(rename = "sock")]
-    Sock(SockSourceConfig),
+    Sock(FlattenedPair<SockSourceConfig, ReferenceClockConfig>),
+    #[serde(rename = "broadcast")]
+    Broadcast(FlattenedPair<BroadcastSourceConfig, ()>),
+    #[serde(rename = "roughtime")]
+    Roughtime(FlattenedPair<RoughtimeSourceConfig, ReferenceClockConfig>),
+    #[serde(rename = "nmea")]
+    Nmea(FlattenedPair<NmeaSourceConfig, ReferenceClockConfig>),
+    #[serde(rename = "gpsd")]
+    Gpsd(FlattenedPair<GpsdSourceConfig, ReferenceClockConfig>),
+    #[serde(rename = "phc")]
+    Phc(FlattenedPair<PhcSourceConfig, ReferenceClockConfig>),
+    #[serde(rename = "ptp")]
+    Ptp(FlattenedPair<PtpSourceConfig, ReferenceClockConfig>),
+    #[serde(rename = "shm")]
+    Shm(FlattenedPair<ShmSourceConfig, ReferenceClockConfig>),
     #[cfg(feature = "pps")]
     #[serde(rename = "pps")]
-    Pps(PpsSourceConfig),
+    Pps(FlattenedPair<PpsSourceConfig, ReferenceClockConfig>),
+}
+
+impl NtpSourceConfig {
+    /// The failover group of the source, 0 being the group that is always
+    /// mobilized
+    #[cfg(feature = "client")]
+    pub fn priority(&self) -> u8 {
+        self.common().priority.unwrap_or(0)
+    }
+
+    /// The options every mode of source has
+    #[cfg(feature = "client")]
+    pub fn common(&self) -> &CommonSourceConfig {
+        match self {
+            NtpSourceConfig::Standard(config) => &config.common,
+            NtpSourceConfig::Nts(config) => &config.common,
+            NtpSourceConfig::Pool(config) => &config.common,
+            NtpSourceConfig::NtsPool(config) => &config.common,
+            NtpSourceConfig::Sock(config) => &config.common,
+            NtpSourceConfig::Broadcast(config) => &config.common,
+            NtpSourceConfig::Roughtime(config) => &config.common,
+            NtpSourceConfig::Nmea(config) => &config.common,
+            NtpSourceConfig::Gpsd(config) => &config.common,
+            NtpSourceConfig::Phc(config) => &config.common,
+            NtpSourceConfig::Ptp(config) => &config.common,
+            NtpSourceConfig::Shm(config) => &config.common,
+            #[cfg(feature = "pps")]
+            NtpSourceConfig::Pps(config) => &config.common,
+        }
+    }
+
+    /// Whether the source is an NTP server, or a reference clock with the
+    /// stratum and reference id we advertise when synchronized to it
+    pub fn source_type(&self) -> SourceType {
+        let (reference, stratum, reference_id) = match self {
+            // Broadcasts are regular NTP packets, carrying a stratum and
+            // reference id
+            NtpSourceConfig::Standard(_)
+            | NtpSourceConfig::Nts(_)
+            | NtpSourceConfig::Pool(_)
+            | NtpSourceConfig::NtsPool(_)
+            | NtpSourceConfig::Broadcast(_) => return SourceType::Ntp,
+            NtpSourceConfig::Sock(config) => (config.second, 0, ReferenceId::SOCK),
+            // Roughtime servers don't report their stratum, but are expected
+            // to be synchronized to a primary reference
+            NtpSourceConfig::Roughtime(config) => (config.second, 1, ReferenceId::ROUGHTIME),
+            NtpSourceConfig::Nmea(config) => (config.second, 0, ReferenceId::GPS),
+            NtpSourceConfig::Gpsd(config) => (config.second, 0, ReferenceId::GPS),
+            // Usually synchronized to a PTP grandmaster, which is on par with
+            // a stratum 1 NTP server
+            NtpSourceConfig::Phc(config) => (config.second, 1, ReferenceId::PHC),
+            NtpSourceConfig::Ptp(config) => (config.second, 1, ReferenceId::PTP),
+            NtpSourceConfig::Shm(config) => (config.second, 0, ReferenceId::SHM),
+            #[cfg(feature = "pps")]
+            NtpSourceConfig::Pps(config) => (config.second, 0, ReferenceId::PPS),
+        };
+        SourceType::External {
+            stratum: reference.stratum.unwrap_or(stratum),
+            reference_id: reference.reference_id.unwrap_or(reference_id),
+        }
+    }
 }
 
 /// A normalized address has a host and a port part. However, the host may be
@@ -477,7 +1405,7 @@ pub struct NormalizedAddress {
     pub(crate) port: u16,
 
     /// Used to inject socket address into the DNS lookup result
-    #[cfg(test)]
+    #[cfg(all(test, feature = "client"))]
     #[serde(skip)]
     hardcoded_dns_resolve: Option<HardcodedDnsResolve>,
 }
@@ -490,13 +1418,13 @@ impl PartialEq for NormalizedAddress {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 #[derive(Debug, Clone, Default)]
 struct HardcodedDnsResolve {
     addresses: Arc<Mutex<Vec<SocketAddr>>>,
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 impl From<Vec<SocketAddr>> for HardcodedDnsResolve {
     fn from(value: Vec<SocketAddr>) -> Self {
         Self {
@@ -505,7 +1433,7 @@ impl From<Vec<SocketAddr>> for HardcodedDnsResolve {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 impl HardcodedDnsResolve {
     fn lookup_host(&self) -> std::vec::IntoIter<SocketAddr> {
         // We don't want to spam a real DNS server during testing. This is an attempt to randomize
@@ -528,6 +1456,12 @@ pub struct NtpAddress(pub NormalizedAddress);
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NtsKeAddress(pub NormalizedAddress);
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoughtimeAddress(pub NormalizedAddress);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpsdAddress(pub NormalizedAddress);
+
 impl<'de> Deserialize<'de> for NtpAddress {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -552,6 +1486,30 @@ impl<'de> Deserialize<'de> for NtsKeAddress {
     }
 }
 
+impl<'de> Deserialize<'de> for RoughtimeAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(RoughtimeAddress(
+            NormalizedAddress::from_string_roughtime(s).map_err(serde::de::Error::custom)?,
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for GpsdAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(GpsdAddress(
+            NormalizedAddress::from_string_gpsd(s).map_err(serde::de::Error::custom)?,
+        ))
+    }
+}
+
 impl From<NormalizedAddress> for NtpAddress {
     fn from(addr: NormalizedAddress) -> Self {
         Self(addr)
@@ -572,6 +1530,22 @@ impl Deref for NtsKeAddress {
     }
 }
 
+impl Deref for RoughtimeAddress {
+    type Target = NormalizedAddress;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Deref for GpsdAddress {
+    type Target = NormalizedAddress;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 impl Deref for NtpAddress {
     type Target = NormalizedAddress;
 
@@ -583,6 +1557,8 @@ impl Deref for NtpAddress {
 impl NormalizedAddress {
     const NTP_DEFAULT_PORT: u16 = 123;
     const NTS_KE_DEFAULT_PORT: u16 = 4460;
+    const ROUGHTIME_DEFAULT_PORT: u16 = 2002;
+    const GPSD_DEFAULT_PORT: u16 = 2947;
 
     /// Specifically, this adds the `:123` port if no port is specified
     pub(crate) fn from_string_ntp(address: String) -> std::io::Result<Self> {
@@ -592,7 +1568,7 @@ impl NormalizedAddress {
             server_name,
             port,
 
-            #[cfg(test)]
+            #[cfg(all(test, feature = "client"))]
             hardcoded_dns_resolve: None,
         })
     }
@@ -605,7 +1581,33 @@ impl NormalizedAddress {
             server_name,
             port,
 
-            #[cfg(test)]
+            #[cfg(all(test, feature = "client"))]
+            hardcoded_dns_resolve: None,
+        })
+    }
+
+    /// Specifically, this adds the `:2002` port if no port is specified
+    fn from_string_roughtime(address: String) -> std::io::Result<Self> {
+        let (server_name, port) = Self::from_string_help(address, Self::ROUGHTIME_DEFAULT_PORT)?;
+
+        Ok(Self {
+            server_name,
+            port,
+
+            #[cfg(all(test, feature = "client"))]
+            hardcoded_dns_resolve: None,
+        })
+    }
+
+    /// Specifically, this adds the `:2947` port if no port is specified
+    fn from_string_gpsd(address: String) -> std::io::Result<Self> {
+        let (server_name, port) = Self::from_string_help(address, Self::GPSD_DEFAULT_PORT)?;
+
+        Ok(Self {
+            server_name,
+            port,
+
+            #[cfg(all(test, feature = "client"))]
             hardcoded_dns_resolve: None,
         })
     }
@@ -645,12 +1647,12 @@ impl NormalizedAddress {
             server_name: server_name.to_string(),
             port,
 
-            #[cfg(test)]
+            #[cfg(all(test, feature = "client"))]
             hardcoded_dns_resolve: None,
         }
     }
 
-    #[cfg(test)]
+    #[cfg(all(test, feature = "client"))]
     pub(crate) fn with_hardcoded_dns(
         server_name: &str,
         port: u16,
@@ -663,10 +1665,19 @@ impl NormalizedAddress {
         }
     }
 
+    /// Change what this address and its clones resolve to
+    #[cfg(all(test, feature = "client"))]
+    pub(crate) fn set_hardcoded_dns(&self, addresses: Vec<SocketAddr>) {
+        if let Some(hardcoded_dns_resolve) = &self.hardcoded_dns_resolve {
+            *hardcoded_dns_resolve.addresses.lock().unwrap() = addresses;
+        }
+    }
+
+    #[cfg(feature = "client")]
     pub async fn lookup_host(&self) -> std::io::Result<impl Iterator<Item = SocketAddr> + '_> {
         enum Either<T> {
             Lookup(T),
-            #[cfg(test)]
+            #[cfg(all(test, feature = "client"))]
             Hardcoded(std::vec::IntoIter<SocketAddr>),
         }
 
@@ -676,18 +1687,18 @@ impl NormalizedAddress {
             fn next(&mut self) -> Option<Self::Item> {
                 match self {
                     Either::Lookup(lookup) => lookup.next(),
-                    #[cfg(test)]
+                    #[cfg(all(test, feature = "client"))]
                     Either::Hardcoded(hardcoded) => hardcoded.next(),
                 }
             }
         }
 
-        #[cfg(test)]
+        #[cfg(all(test, feature = "client"))]
         if let Some(hardcoded_dns_resolve) = &self.hardcoded_dns_resolve {
             return Ok(Either::Hardcoded(hardcoded_dns_resolve.lookup_host()));
         }
 
-        tokio::net::lookup_host((self.server_name.as_str(), self.port))
+        crate::daemon::dns::lookup_host(&self.server_name, self.port)
             .await
             .map(Either::Lookup)
     }
@@ -710,6 +1721,8 @@ impl TryFrom<&str> for StandardSource {
         Ok(Self {
             address: NormalizedAddress::from_string_ntp(value.to_string())?.into(),
             ntp_version: default_ntp_version(),
+            key_id: None,
+            resolve_interval: default_resolve_interval(),
         })
     }
 }
@@ -722,12 +1735,13 @@ impl<'a> TryFrom<&'a str> for NtpSourceConfig {
             Self::Standard(FlattenedPair {
                 first,
                 second: PartialSourceConfig::default(),
+                common: CommonSourceConfig::default(),
             })
         })
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client"))]
 #[expect(
     clippy::too_many_lines,
     reason = "Long tests are not really a big problem"
@@ -747,6 +1761,13 @@ mod tests {
             NtpSourceConfig::Pool(c) => c.first.addr.to_string(),
             NtpSourceConfig::NtsPool(c) => c.first.addr.to_string(),
             NtpSourceConfig::Sock(_c) => String::new(),
+            NtpSourceConfig::Broadcast(c) => c.first.listen.to_string(),
+            NtpSourceConfig::Roughtime(c) => c.first.address.to_string(),
+            NtpSourceConfig::Nmea(c) => c.first.path.display().to_string(),
+            NtpSourceConfig::Gpsd(c) => c.first.address.to_string(),
+            NtpSourceConfig::Phc(c) => c.first.path.display().to_string(),
+            NtpSourceConfig::Ptp(c) => c.first.group.to_string(),
+            NtpSourceConfig::Shm(c) => c.first.unit.to_string(),
             #[cfg(feature = "pps")]
             NtpSourceConfig::Pps(_c) => String::new(),
         }
@@ -842,55 +1863,116 @@ mod tests {
     }
 
     #[test]
-    fn test_deserialize_source_ntp_version() {
+    fn test_deserialize_source_key_id() {
         let test: TestConfig = toml::from_str(
             r#"
             [source]
             address = "example.com"
             mode = "server"
-            ntp-version = "auto"
+            key-id = 12
             "#,
         )
         .unwrap();
         let NtpSourceConfig::Standard(source) = test.source else {
             panic!("Invalid source type");
         };
-        assert_eq!(
-            source.first.ntp_version,
-            ProtocolVersion::v4_upgrading_to_v5_with_default_tries()
-        );
-
-        let test: TestConfig = toml::from_str(
-            r#"
-            [source]
-            address = "example.com"
-            mode = "pool"
-            ntp-version = 5
-            "#,
-        )
-        .unwrap();
-        let NtpSourceConfig::Pool(source) = test.source else {
-            panic!("Invalid source type");
-        };
-        assert_eq!(source.first.ntp_version, ProtocolVersion::V5);
+        assert_eq!(source.first.key_id, Some(12));
 
         let test: TestConfig = toml::from_str(
             r#"
             [source]
             address = "example.com"
             mode = "server"
-            ntp-version = 4
             "#,
         )
         .unwrap();
         let NtpSourceConfig::Standard(source) = test.source else {
             panic!("Invalid source type");
         };
-        assert_eq!(source.first.ntp_version, ProtocolVersion::V4);
+        assert_eq!(source.first.key_id, None);
 
-        let test: TestConfig = toml::from_str(
-            r#"
-            [source]
+        // Only sources polling a single server can use a symmetric key
+        assert!(
+            toml::from_str::<TestConfig>(
+                r#"
+                [source]
+                address = "example.com"
+                mode = "pool"
+                key-id = 12
+                "#,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_source_resolve_interval() {
+        let resolve_interval = |extra: &str| {
+            let test: TestConfig = toml::from_str(&format!(
+                "[source]\naddress = \"example.com\"\nmode = \"server\"\n{extra}"
+            ))
+            .unwrap();
+            let NtpSourceConfig::Standard(source) = test.source else {
+                panic!("Invalid source type");
+            };
+            source.first.resolve_interval
+        };
+
+        assert_eq!(resolve_interval(""), 3600);
+        assert_eq!(resolve_interval("resolve-interval = 300"), 300);
+        assert_eq!(resolve_interval("resolve-interval = 0"), 0);
+    }
+
+    #[test]
+    fn test_deserialize_source_ntp_version() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            ntp-version = "auto"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(
+            source.first.ntp_version,
+            ProtocolVersion::v4_upgrading_to_v5_with_default_tries()
+        );
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "pool"
+            ntp-version = 5
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.ntp_version, ProtocolVersion::V5);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            ntp-version = 4
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.ntp_version, ProtocolVersion::V4);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
             address = "example.com"
             mode = "nts"
             "#,
@@ -902,101 +1984,1225 @@ mod tests {
         assert_eq!(source.first.ntp_version, ProtocolVersion::V4);
     }
 
-    #[test]
-    fn test_deserialize_source_pem_certificate() {
-        let contents = include_bytes!("../../../testdata/certificates/nos-nl.pem");
-        let path = std::env::temp_dir().join("nos-nl.pem");
-        std::fs::write(&path, contents).unwrap();
+    #[test]
+    fn test_deserialize_source_aead_algorithms() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.aead_algorithms, AeadAlgorithm::SUPPORTED);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts-pool"
+            aead-algorithms = ["aes-128-gcm-siv", "aes-siv-cmac-256"]
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::NtsPool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(
+            source.first.aead_algorithms,
+            [
+                AeadAlgorithm::AeadAes128GcmSiv,
+                AeadAlgorithm::AeadAesSivCmac256
+            ]
+        );
+
+        for algorithms in ["[]", r#"["chacha20-poly1305"]"#] {
+            let test: Result<TestConfig, _> = toml::from_str(&format!(
+                r#"
+                [source]
+                address = "example.com"
+                mode = "nts"
+                aead-algorithms = {algorithms}
+                "#,
+            ));
+            assert!(test.is_err());
+        }
+    }
+
+    #[test]
+    fn test_deserialize_source_pins() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert!(source.first.pinned_certificate_authorities.is_empty());
+        assert!(source.first.pin_spki_sha256.is_empty());
+
+        let contents = include_bytes!("../../../testdata/certificates/nos-nl.pem");
+        let path = std::env::temp_dir().join("nos-nl-pinned.pem");
+        std::fs::write(&path, contents).unwrap();
+
+        let test: TestConfig = toml::from_str(&format!(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts-pool"
+            pin-certificate-authority = "{}"
+            pin-spki-sha256 = ["PU6aSm+CFkjfvTpkboydl62v5DgK1ZlwyFteb/zbNlw="]
+            "#,
+            path.display()
+        ))
+        .unwrap();
+        let NtpSourceConfig::NtsPool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert!(!source.first.pinned_certificate_authorities.is_empty());
+        assert_eq!(source.first.pin_spki_sha256.len(), 1);
+        assert_eq!(
+            source.first.pin_spki_sha256[0][..4],
+            [0x3d, 0x4e, 0x9a, 0x4a]
+        );
+
+        // Pins must be complete SHA-256 hashes
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            pin-spki-sha256 = ["PU6aSm+CFkjfvTpkboydl62v5DgK1Zlw"]
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_source_session_resumption() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            session-resumption = false
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert!(!source.first.session_resumption);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts-pool"
+            session-resumption = false
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::NtsPool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert!(!source.first.session_resumption);
+    }
+
+    #[test]
+    fn test_deserialize_source_nts_failure_policy() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.nts_failure_policy, NtsFailurePolicy::Retry);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            nts-failure-policy = "unauthenticated"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(
+            source.first.nts_failure_policy,
+            NtsFailurePolicy::Unauthenticated
+        );
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            nts-failure-policy = "ignore"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_source_ntp_address() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.server_name, None);
+        assert_eq!(source.first.ntp_address, None);
+        assert!(source.first.session_resumption);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "[2001:db8::1]:4460"
+            mode = "nts"
+            server-name = "time.example.com"
+            ntp-address = "2001:db8::2"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.address.server_name, "2001:db8::1");
+        assert_eq!(
+            source.first.server_name.as_deref(),
+            Some("time.example.com")
+        );
+        let ntp_address = source.first.ntp_address.unwrap();
+        assert_eq!(ntp_address.server_name, "2001:db8::2");
+        assert_eq!(ntp_address.port, 123);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            ntp-address = "ntp.internal.example.com:1123"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        let ntp_address = source.first.ntp_address.unwrap();
+        assert_eq!(ntp_address.server_name, "ntp.internal.example.com");
+        assert_eq!(ntp_address.port, 1123);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            server-name = "not a name"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_source_pem_certificate() {
+        let contents = include_bytes!("../../../testdata/certificates/nos-nl.pem");
+        let path = std::env::temp_dir().join("nos-nl.pem");
+        std::fs::write(&path, contents).unwrap();
+
+        let test: TestConfig = toml::from_str(&format!(
+            r#"
+                [source]
+                address = "example.com"
+                certificate-authority = "{}"
+                mode = "nts"
+                "#,
+            path.display()
+        ))
+        .unwrap();
+        assert!(matches!(test.source, NtpSourceConfig::Nts(_)));
+        assert_eq!(source_addr(&test.source), "example.com:4460");
+    }
+
+    #[test]
+    fn test_deserialize_source_client_certificate() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "nts"
+            client-certificate-chain-path = "/etc/ntpd-rs/client.pem"
+            client-private-key-path = "/etc/ntpd-rs/client.key"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Nts(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(
+            source.first.client_certificate_chain_path,
+            Some(PathBuf::from("/etc/ntpd-rs/client.pem"))
+        );
+        assert_eq!(
+            source.first.client_private_key_path,
+            Some(PathBuf::from("/etc/ntpd-rs/client.key"))
+        );
+    }
+
+    #[test]
+    fn test_source_from_string() {
+        let source = NtpSourceConfig::try_from("example.com").unwrap();
+        assert_eq!(source_addr(&source), "example.com:123");
+        assert!(matches!(source, NtpSourceConfig::Standard(_)));
+
+        let source = NtpSourceConfig::try_from("example.com:5678").unwrap();
+        assert_eq!(source_addr(&source), "example.com:5678");
+        assert!(matches!(source, NtpSourceConfig::Standard(_)));
+    }
+
+    #[test]
+    fn test_source_config_parsing() {
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                initial-poll-interval = 7
+            "#,
+        );
+        assert!(test.is_ok());
+
+        let test2: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                does-not-exist = 7
+            "#,
+        );
+        assert!(test2.is_err());
+    }
+
+    #[test]
+    fn test_initial_burst_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                initial-burst = { count = 8 }
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = source.second.with_defaults(SourceConfig::default());
+        assert_eq!(config.initial_burst.count, 8);
+        assert_eq!(
+            config.initial_burst.spacing,
+            InitialBurst::default().spacing
+        );
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                initial-burst = { count = 0 }
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = source.second.with_defaults(SourceConfig::default());
+        assert_eq!(config.initial_burst.count, 0);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                initial-burst = { count = 4, spacing = 0 }
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_weight_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                weight = 10
+                prefer = true
+                noselect = true
+                trust = true
+                require = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = source.source_config(SourceConfig::default());
+        assert_eq!(config.weight, 10.0);
+        assert!(config.prefer);
+        assert!(config.noselect);
+        assert!(config.trust);
+        assert!(config.require);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "pool"
+                address = "example.com"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = source.source_config(SourceConfig::default());
+        assert_eq!(config.weight, 1.0);
+        assert!(!config.prefer);
+        assert!(!config.noselect);
+        assert!(!config.trust);
+        assert!(!config.require);
+
+        let TestConfig {
+            source: NtpSourceConfig::Nmea(source),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "nmea"
+                path = "/dev/ttyS0"
+                precision = 0.01
+                trust = true
+                require = true
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        let config = source.common.with_defaults(SourceConfig::default());
+        assert!(config.trust);
+        assert!(config.require);
+
+        // Weights only make sense when voting against other NTP sources
+        let test: Result<TestConfig, _> = toml::from_str(
+            "[source]\nmode = \"nmea\"\npath = \"/dev/ttyS0\"\nprecision = 0.01\nweight = 10",
+        );
+        assert!(test.is_err());
+
+        for weight in ["0", "-1.0", "nan"] {
+            let test: Result<TestConfig, _> = toml::from_str(&format!(
+                "[source]\nmode = \"server\"\naddress = \"example.com\"\nweight = {weight}"
+            ));
+            assert!(test.is_err());
+        }
+    }
+
+    #[test]
+    fn test_correction_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                offset-correction = -0.0002
+                delay-correction = 0.00005
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = source.source_config(SourceConfig::default());
+        assert_eq!(config.offset_correction, -0.0002);
+        assert_eq!(config.delay_correction, 0.00005);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            "[source]\nmode = \"server\"\naddress = \"example.com\"\noffset-correction = inf",
+        );
+        assert!(test.is_err());
+
+        let TestConfig {
+            source: NtpSourceConfig::Sock(test),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+                delay-correction = 0.0001
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.common.offset_correction, None);
+        assert_eq!(test.common.delay_correction, Some(0.0001));
+
+        // Every mode takes the corrections
+        for source in [
+            "mode = \"broadcast\"",
+            "mode = \"roughtime\"\naddress = \"example.com\"\npublic-key = \"0GD7c3yP8xEc4Zl2zeuN2SlLvDVVocjsPSL8/Rl/7zg=\"",
+            "mode = \"nmea\"\npath = \"/dev/ttyS0\"",
+            "mode = \"gpsd\"",
+            "mode = \"ptp\"",
+            "mode = \"shm\"\nunit = 0",
+        ] {
+            let test: TestConfig = toml::from_str(&format!(
+                "[source]\n{source}\noffset-correction = 0.25\ndelay-correction = 0.5"
+            ))
+            .unwrap();
+            let common = match test.source {
+                NtpSourceConfig::Broadcast(test) => test.common,
+                NtpSourceConfig::Roughtime(test) => test.common,
+                NtpSourceConfig::Nmea(test) => test.common,
+                NtpSourceConfig::Gpsd(test) => test.common,
+                NtpSourceConfig::Ptp(test) => test.common,
+                NtpSourceConfig::Shm(test) => test.common,
+                _ => panic!("Unexpected source type"),
+            };
+            let config = common.with_defaults(SourceConfig::default());
+            assert_eq!(config.offset_correction, 0.25);
+            assert_eq!(config.delay_correction, 0.5);
+        }
+
+        // The deprecated offset of PHC sources has the opposite sign, and
+        // is not taken as the correction
+        let TestConfig {
+            source: NtpSourceConfig::Phc(test),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "phc"
+                path = "/dev/ptp0"
+                offset = 37
+                offset-correction = 0.001
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.first.offset, 37.0);
+        assert_eq!(test.common.offset_correction, Some(0.001));
+    }
+
+    #[test]
+    fn test_local_address_parsing() {
+        let parse = |local_address: &str| {
+            let test: TestConfig = toml::from_str(&format!(
+                "[source]\nmode = \"server\"\naddress = \"example.com\"\nlocal-address = \"{local_address}\""
+            ))?;
+            let NtpSourceConfig::Standard(source) = test.source else {
+                panic!("Unexpected source type");
+            };
+            Ok::<_, toml::de::Error>(source.second.with_defaults(SourceConfig::default()))
+        };
+
+        // Without a port the operating system picks one
+        let config = parse("192.0.2.1").unwrap();
+        assert_eq!(config.local_address, Some("192.0.2.1:0".parse().unwrap()));
+
+        let config = parse("[2001:db8::1]:123").unwrap();
+        assert_eq!(
+            config.local_address,
+            Some("[2001:db8::1]:123".parse().unwrap())
+        );
+
+        assert!(parse("eth0").is_err());
+        assert!(parse("192.0.2.1:123456").is_err());
+    }
+
+    #[test]
+    fn test_priority_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "pool"
+                address = "pool.ntp.org"
+                priority = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.source.priority(), 1);
+
+        // Sources are in the first group by default
+        let test: TestConfig =
+            toml::from_str("[source]\nmode = \"server\"\naddress = \"example.com\"").unwrap();
+        assert_eq!(test.source.priority(), 0);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "nmea"
+                path = "/dev/ttyS0"
+                precision = 0.01
+                priority = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.source.priority(), 2);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            "[source]\nmode = \"server\"\naddress = \"example.com\"\npriority = 256",
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_reference_clock_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "nmea"
+                path = "/dev/ttyS0"
+                precision = 0.01
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.source.source_type(),
+            SourceType::External {
+                stratum: 0,
+                reference_id: ReferenceId::GPS
+            }
+        );
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "phc"
+                path = "/dev/ptp0"
+                precision = 1e-7
+                stratum = 2
+                reference-id = "GNSS"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.source.source_type(),
+            SourceType::External {
+                stratum: 2,
+                reference_id: ReferenceId::from_code("GNSS").unwrap()
+            }
+        );
+
+        let test: TestConfig =
+            toml::from_str("[source]\nmode = \"server\"\naddress = \"example.com\"").unwrap();
+        assert_eq!(test.source.source_type(), SourceType::Ntp);
+
+        // Our clients see the upstream stratum and reference id of NTP sources
+        for mode in [
+            "mode = \"server\"\naddress = \"example.com\"",
+            "mode = \"pool\"\naddress = \"pool.ntp.org\"",
+            "mode = \"broadcast\"",
+        ] {
+            for option in ["stratum = 1", "reference-id = \"GPS\""] {
+                let test: Result<TestConfig, _> =
+                    toml::from_str(&format!("[source]\n{mode}\n{option}"));
+                assert!(test.is_err(), "{mode} {option}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_delay_asymmetry_parsing() {
+        let parse = |asymmetry: &str| {
+            let test: TestConfig = toml::from_str(&format!(
+                "[source]\nmode = \"server\"\naddress = \"example.com\"\ndelay-asymmetry = {asymmetry}"
+            ))?;
+            let NtpSourceConfig::Standard(source) = test.source else {
+                panic!("Unexpected source type");
+            };
+            Ok::<_, toml::de::Error>(
+                source
+                    .second
+                    .with_defaults(SourceConfig::default())
+                    .delay_asymmetry,
+            )
+        };
+
+        assert_eq!(parse("\"none\"").unwrap(), DelayAsymmetry::None);
+        assert_eq!(parse("\"auto\"").unwrap(), DelayAsymmetry::Auto);
+        assert_eq!(
+            parse("{ fraction = 0.6 }").unwrap(),
+            DelayAsymmetry::Fraction(0.6)
+        );
+        assert_eq!(
+            parse("{ absolute = -0.002 }").unwrap(),
+            DelayAsymmetry::Absolute(-0.002)
+        );
+        assert!(parse("{ fraction = 1.5 }").is_err());
+        assert!(parse("{ absolute = nan }").is_err());
+        assert!(parse("0.6").is_err());
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(
+            source
+                .second
+                .with_defaults(SourceConfig::default())
+                .delay_asymmetry,
+            DelayAsymmetry::None
+        );
+    }
+
+    #[test]
+    fn test_interleaved_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "example.com"
+                interleaved = true
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = source.second.with_defaults(SourceConfig::default());
+        assert!(config.interleaved);
+
+        // Falls back to the source defaults
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "pool"
+                address = "example.com"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(source) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let defaults = SourceConfig {
+            interleaved: true,
+            ..SourceConfig::default()
+        };
+        assert!(source.second.clone().with_defaults(defaults).interleaved);
+        assert!(
+            !source
+                .second
+                .with_defaults(SourceConfig::default())
+                .interleaved
+        );
+    }
+
+    #[test]
+    fn test_poll_interval_limits_parsing() {
+        let limits = |partial: &str, defaults: (u8, u8)| {
+            let test: TestConfig = toml::from_str(&format!(
+                "[source]\nmode = \"server\"\naddress = \"example.com\"\n{partial}"
+            ))
+            .unwrap();
+            let NtpSourceConfig::Standard(source) = test.source else {
+                panic!("Unexpected source type");
+            };
+            let defaults = SourceConfig {
+                poll_interval_limits: PollIntervalLimits {
+                    min: PollInterval::from_byte(defaults.0),
+                    max: PollInterval::from_byte(defaults.1),
+                },
+                ..SourceConfig::default()
+            };
+            let limits = source.second.with_defaults(defaults).poll_interval_limits;
+            (limits.min.as_log(), limits.max.as_log())
+        };
+
+        assert_eq!(limits("", (6, 10)), (6, 10));
+        assert_eq!(
+            limits("poll-interval-limits = { min = 4, max = 6 }", (6, 10)),
+            (4, 6)
+        );
+        assert_eq!(
+            limits("poll-interval-limits = { min = \"16s\" }", (6, 10)),
+            (4, 10)
+        );
+
+        // The inherited bound gives way to the one set for the source
+        assert_eq!(
+            limits("poll-interval-limits = { min = 12 }", (6, 10)),
+            (12, 12)
+        );
+        assert_eq!(
+            limits("poll-interval-limits = { max = 4 }", (6, 10)),
+            (4, 4)
+        );
+    }
+
+    #[test]
+    fn test_parse_socket_owner() {
+        assert_eq!(
+            parse_socket_owner("gpsd"),
+            Some((Some("gpsd".into()), None))
+        );
+        assert_eq!(parse_socket_owner(":20"), Some((None, Some("20".into()))));
+        assert_eq!(parse_socket_owner(""), None);
+        assert_eq!(parse_socket_owner(":"), None);
+    }
+
+    #[test]
+    fn test_sock_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Sock(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                measurement_noise_estimate = 0.0625
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.precision, 0.25);
+
+        let TestConfig {
+            source: NtpSourceConfig::Sock(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.precision, 0.25);
+        assert_eq!(test.socket, SockSocketOptions::default());
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.source.source_type(),
+            SourceType::External {
+                stratum: 0,
+                reference_id: ReferenceId::SOCK
+            }
+        );
+
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+                stratum = 1
+                reference-id = "GPS1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.source.source_type(),
+            SourceType::External {
+                stratum: 1,
+                reference_id: ReferenceId::from_code("GPS1").unwrap()
+            }
+        );
+
+        for invalid in [
+            "stratum = 16",
+            "reference-id = \"GNSS1\"",
+            "reference-id = \"\"",
+        ] {
+            let test: Result<TestConfig, _> = toml::from_str(&format!(
+                "[source]\nmode = \"sock\"\npath = \"/test/path\"\nprecision = 0.25\n{invalid}"
+            ));
+            assert!(test.is_err(), "{invalid}");
+        }
+
+        let TestConfig {
+            source: NtpSourceConfig::Sock(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/run/ntpd-rs/gpsd.sock"
+                precision = 0.25
+                permissions = 0o660
+                owner = "ntpd-rs:gpsd"
+                create-directory = true
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(
+            test.socket,
+            SockSocketOptions {
+                permissions: Some(0o660),
+                user: Some("ntpd-rs".into()),
+                group: Some("gpsd".into()),
+                create_directory: true,
+            }
+        );
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+                owner = "gpsd:"
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+                measurement_noise_estimate = 0.0625
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                precision = 0.25
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.25
+                unknown_field = 5
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = -0.25
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                measurement_noise_estimate = -0.0625
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                precision = 0.0
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "sock"
+                path = "/test/path"
+                measurement_noise_estimate = 0.0
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    fn parse_error(source: &str) -> String {
+        toml::from_str::<TestConfig>(&format!("[source]\n{source}"))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_sock_config_error_messages() {
+        for (fields, expected) in [
+            ("precision = 0.0", "precision should be positive"),
+            (
+                "precision = 0.25\naccuracy = 0.0",
+                "accuracy should be positive",
+            ),
+            (
+                "measurement_noise_estimate = 0.0",
+                "measurement_noise_estimate should be positive",
+            ),
+        ] {
+            let error = parse_error(&format!("mode = \"sock\"\npath = \"/test/path\"\n{fields}"));
+            assert!(error.contains(expected), "{fields}: {error}");
+        }
+    }
+
+    #[test]
+    fn test_broadcast_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Broadcast(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "broadcast"
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.listen, "0.0.0.0:123".parse().unwrap());
+        assert_eq!(test.multicast_group, None);
+        assert_eq!(test.delay, None);
+
+        let TestConfig {
+            source: NtpSourceConfig::Broadcast(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "broadcast"
+                listen = "[::]:123"
+                multicast-group = "ff02::101"
+                delay = 0.004
+                precision = 0.25
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.listen, "[::]:123".parse().unwrap());
+        assert_eq!(test.multicast_group, Some("ff02::101".parse().unwrap()));
+        assert_eq!(test.delay, Some(0.004));
+        assert_eq!(test.precision, 0.25);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "broadcast"
+                multicast-group = "192.168.1.1"
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "broadcast"
+                delay = -0.004
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "broadcast"
+                precision = 0.0
+            "#,
+        );
+        assert!(test.is_err());
 
-        let test: TestConfig = toml::from_str(&format!(
+        let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                address = "example.com"
-                certificate-authority = "{}"
-                mode = "nts"
-                "#,
-            path.display()
-        ))
-        .unwrap();
-        assert!(matches!(test.source, NtpSourceConfig::Nts(_)));
-        assert_eq!(source_addr(&test.source), "example.com:4460");
+                mode = "broadcast"
+                unknown-field = 5
+            "#,
+        );
+        assert!(test.is_err());
     }
 
     #[test]
-    fn test_source_from_string() {
-        let source = NtpSourceConfig::try_from("example.com").unwrap();
-        assert_eq!(source_addr(&source), "example.com:123");
-        assert!(matches!(source, NtpSourceConfig::Standard(_)));
+    fn test_roughtime_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Roughtime(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "roughtime"
+                address = "roughtime.example.com"
+                public-key = "0GD7c3yP8xEc4Zl2zeuN2SlLvDVVocjsPSL8/Rl/7zg="
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.address.to_string(), "roughtime.example.com:2002");
+        assert_eq!(test.public_key[..4], [0xd0, 0x60, 0xfb, 0x73]);
+        assert_eq!(test.poll_interval, PollInterval::from_byte(10));
+        assert_eq!(test.precision, 1.0);
 
-        let source = NtpSourceConfig::try_from("example.com:5678").unwrap();
-        assert_eq!(source_addr(&source), "example.com:5678");
-        assert!(matches!(source, NtpSourceConfig::Standard(_)));
-    }
+        let TestConfig {
+            source: NtpSourceConfig::Roughtime(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "roughtime"
+                address = "roughtime.example.com:2003"
+                public-key = "0GD7c3yP8xEc4Zl2zeuN2SlLvDVVocjsPSL8/Rl/7zg="
+                poll-interval = 12
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.address.to_string(), "roughtime.example.com:2003");
+        assert_eq!(test.poll_interval, PollInterval::from_byte(12));
 
-    #[test]
-    fn test_source_config_parsing() {
+        // Keys must be 32 bytes
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "server"
-                address = "example.com"
-                initial-poll-interval = 7
+                mode = "roughtime"
+                address = "roughtime.example.com"
+                public-key = "AQID"
             "#,
         );
-        assert!(test.is_ok());
+        assert!(test.is_err());
 
-        let test2: Result<TestConfig, _> = toml::from_str(
+        let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "server"
-                address = "example.com"
-                does-not-exist = 7
+                mode = "roughtime"
+                address = "roughtime.example.com"
+                public-key = "0GD7c3yP8xEc4Zl2zeuN2SlLvDVVocjsPSL8-Rl_7zg="
             "#,
         );
-        assert!(test2.is_err());
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "roughtime"
+                address = "roughtime.example.com"
+            "#,
+        );
+        assert!(test.is_err());
     }
 
     #[test]
-    fn test_sock_config_parsing() {
+    fn test_nmea_config_parsing() {
         let TestConfig {
-            source: NtpSourceConfig::Sock(test),
+            source: NtpSourceConfig::Nmea(FlattenedPair { first: test, .. }),
         } = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                measurement_noise_estimate = 0.0625
+                mode = "nmea"
+                path = "/dev/ttyS0"
             "#,
         )
         .unwrap()
         else {
             panic!("Unexpected source type");
         };
-        assert_eq!(test.precision, 0.25);
+        assert_eq!(test.path, PathBuf::from("/dev/ttyS0"));
+        assert_eq!(test.baud_rate, 9600);
+        assert_eq!(test.sentences, default_nmea_sentences());
+        assert_eq!(test.offsets, NmeaOffsets::default());
+        assert_eq!(test.precision, 0.05);
 
         let TestConfig {
-            source: NtpSourceConfig::Sock(test),
+            source: NtpSourceConfig::Nmea(FlattenedPair { first: test, .. }),
         } = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                precision = 0.25
+                mode = "nmea"
+                path = "/dev/ttyUSB0"
+                baud-rate = 115200
+                sentences = ["zda"]
+                offsets = { zda = 0.35 }
+                precision = 0.01
             "#,
         )
         .unwrap()
         else {
             panic!("Unexpected source type");
         };
-        assert_eq!(test.precision, 0.25);
+        assert_eq!(test.baud_rate, 115_200);
+        assert_eq!(test.sentences, vec![NmeaSentence::Zda]);
+        assert_eq!(test.offsets.get(NmeaSentence::Zda), 0.35);
+        assert_eq!(test.offsets.get(NmeaSentence::Rmc), 0.0);
+        assert_eq!(test.precision, 0.01);
 
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                precision = 0.25
-                measurement_noise_estimate = 0.0625
+                mode = "nmea"
+                path = "/dev/ttyS0"
+                baud-rate = 1234
             "#,
         );
         assert!(test.is_err());
@@ -1004,8 +3210,9 @@ mod tests {
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
+                mode = "nmea"
+                path = "/dev/ttyS0"
+                sentences = ["gsv"]
             "#,
         );
         assert!(test.is_err());
@@ -1013,39 +3220,153 @@ mod tests {
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                precision = 0.25
+                mode = "nmea"
+                path = "/dev/ttyS0"
+                offsets = { vtg = 0.1 }
             "#,
         );
         assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_gpsd_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Gpsd(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "gpsd"
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.address.to_string(), "localhost:2947");
+        assert_eq!(test.device, None);
+        assert!(test.pps);
+        assert_eq!(test.precision, 0.01);
+
+        let TestConfig {
+            source: NtpSourceConfig::Gpsd(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "gpsd"
+                address = "192.0.2.1"
+                device = "/dev/ttyACM0"
+                pps = false
+                precision = 0.001
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.address.to_string(), "192.0.2.1:2947");
+        assert_eq!(test.device.as_deref(), Some("/dev/ttyACM0"));
+        assert!(!test.pps);
+        assert_eq!(test.precision, 0.001);
 
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                precision = 0.25
-                unknown_field = 5
+                mode = "gpsd"
+                path = "/run/gpsd.sock"
             "#,
         );
         assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_phc_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Phc(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "phc"
+                path = "/dev/ptp0"
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.path, PathBuf::from("/dev/ptp0"));
+        assert_eq!(test.poll_interval, PollInterval::from_byte(0));
+        assert_eq!(test.offset, 0.0);
+        assert_eq!(test.precision, 1e-6);
+
+        let TestConfig {
+            source: NtpSourceConfig::Phc(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "phc"
+                path = "/dev/ptp1"
+                poll-interval = 2
+                offset = 37
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.poll_interval, PollInterval::from_byte(2));
+        assert_eq!(test.offset, 37.0);
 
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                precision = -0.25
+                mode = "phc"
             "#,
         );
         assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_shm_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Shm(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "shm"
+                unit = 1
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.unit, 1);
+        assert_eq!(test.poll_interval, PollInterval::from_byte(0));
+        assert_eq!(test.precision, 1e-3);
+
+        let TestConfig {
+            source: NtpSourceConfig::Shm(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "shm"
+                unit = 2
+                poll-interval = 2
+                precision = 1e-6
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.unit, 2);
+        assert_eq!(test.poll_interval, PollInterval::from_byte(2));
+        assert_eq!(test.precision, 1e-6);
 
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                measurement_noise_estimate = -0.0625
+                mode = "shm"
             "#,
         );
         assert!(test.is_err());
@@ -1053,29 +3374,73 @@ mod tests {
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                precision = 0.0
+                mode = "shm"
+                unit = 256
             "#,
         );
         assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_ptp_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::Ptp(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "ptp"
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.group, IpAddr::from([224, 0, 1, 129]));
+        assert_eq!(test.domain, 0);
+        assert_eq!(test.precision, 1e-4);
+
+        let TestConfig {
+            source: NtpSourceConfig::Ptp(FlattenedPair { first: test, .. }),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "ptp"
+                group = "ff0e::181"
+                domain = 24
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        assert_eq!(test.group, "ff0e::181".parse::<IpAddr>().unwrap());
+        assert_eq!(test.domain, 24);
 
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
                 [source]
-                mode = "sock"
-                path = "/test/path"
-                measurement_noise_estimate = 0.0
+                mode = "ptp"
+                group = "192.168.1.1"
             "#,
         );
         assert!(test.is_err());
     }
 
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64(""), Some(vec![]));
+        assert_eq!(decode_base64("AQID"), Some(vec![1, 2, 3]));
+        assert_eq!(decode_base64("AQI="), Some(vec![1, 2]));
+        assert_eq!(decode_base64("AQ=="), Some(vec![1]));
+        assert_eq!(decode_base64("+/+/"), Some(vec![0xfb, 0xff, 0xbf]));
+        assert_eq!(decode_base64("AQ-_"), None);
+    }
+
     #[cfg(feature = "pps")]
     #[test]
     fn test_pps_config_parsing() {
         let TestConfig {
-            source: NtpSourceConfig::Pps(test),
+            source: NtpSourceConfig::Pps(FlattenedPair { first: test, .. }),
         } = toml::from_str(
             r#"
             [source]
@@ -1093,7 +3458,7 @@ mod tests {
         assert_eq!(test.period, 1.5);
 
         let TestConfig {
-            source: NtpSourceConfig::Pps(test),
+            source: NtpSourceConfig::Pps(FlattenedPair { first: test, .. }),
         } = toml::from_str(
             r#"
             [source]
@@ -1108,6 +3473,52 @@ mod tests {
         };
         assert_eq!(test.precision, 0.25);
         assert_eq!(test.period, 1.0);
+        assert_eq!(test.edge, PpsEdge::Assert);
+        assert_eq!(test.lock, None);
+
+        let TestConfig {
+            source: NtpSourceConfig::Pps(test),
+        } = toml::from_str(
+            r#"
+            [source]
+            mode = "pps"
+            path = "/test/path"
+            precision = 0.25
+            edge = "clear"
+            offset = -0.0015
+            lock = "/run/chrony.ttyS0.sock"
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Unexpected source type");
+        };
+        // The old name of the offset correction of PPS sources
+        assert_eq!(test.common.offset_correction, Some(-0.0015));
+        assert_eq!(test.first.edge, PpsEdge::Clear);
+        assert_eq!(test.first.lock.as_deref(), Some("/run/chrony.ttyS0.sock"));
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            mode = "pps"
+            path = "/test/path"
+            precision = 0.25
+            edge = "both"
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            mode = "pps"
+            path = "/test/path"
+            precision = 0.25
+            offset = nan
+            "#,
+        );
+        assert!(test.is_err());
 
         let test: Result<TestConfig, _> = toml::from_str(
             r#"
@@ -1213,6 +3624,29 @@ mod tests {
         assert!(test.is_err());
     }
 
+    #[cfg(feature = "pps")]
+    #[test]
+    fn test_pps_config_error_messages() {
+        for (fields, expected) in [
+            ("precision = 0.0", "precision should be positive"),
+            (
+                "precision = 0.25\naccuracy = 0.0",
+                "accuracy should be positive",
+            ),
+            (
+                "measurement_noise_estimate = 0.0",
+                "measurement_noise_estimate should be positive",
+            ),
+            (
+                "precision = 0.25\nperiod = 0.0",
+                "period should be positive",
+            ),
+        ] {
+            let error = parse_error(&format!("mode = \"pps\"\npath = \"/test/path\"\n{fields}"));
+            assert!(error.contains(expected), "{fields}: {error}");
+        }
+    }
+
     #[test]
     fn test_normalize_addr() {
         let addr = NormalizedAddress::from_string_ntp("[::1]:456".into()).unwrap();
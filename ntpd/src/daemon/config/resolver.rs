@@ -0,0 +1,112 @@
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+/// How the names of sources are resolved
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResolverMode {
+    /// The resolver of the operating system
+    #[default]
+    System,
+    /// Plain DNS over UDP and TCP to the configured servers
+    Dns,
+    /// DNS-over-TLS (RFC 7858) to the configured servers
+    Tls,
+    /// DNS-over-HTTPS (RFC 8484) to the configured servers
+    Https,
+}
+
+impl ResolverMode {
+    /// Whether the upstream servers need to present a valid certificate
+    pub fn is_encrypted(self) -> bool {
+        matches!(self, ResolverMode::Tls | ResolverMode::Https)
+    }
+}
+
+/// Resolver used for the names of sources, and for SRV records
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ResolverConfig {
+    #[serde(default)]
+    pub mode: ResolverMode,
+    /// Upstream servers, used by all but the system resolver
+    #[serde(default)]
+    pub servers: Vec<IpAddr>,
+    /// Port of the upstream servers, when not the default for the mode
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Name the certificates of the upstream servers must be valid for
+    #[serde(default)]
+    pub server_name: Option<String>,
+    /// Path of the query endpoint of DNS-over-HTTPS servers
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl ResolverConfig {
+    /// Problems with the configuration that keep the resolver from working
+    pub fn check(&self) -> Result<(), String> {
+        if self.mode == ResolverMode::System {
+            if !self.servers.is_empty() {
+                return Err("servers can't be used with the system resolver".into());
+            }
+            return Ok(());
+        }
+
+        if !cfg!(feature = "srv") {
+            return Err(
+                "resolvers other than the system resolver need the srv feature of ntpd-rs".into(),
+            );
+        }
+        if self.servers.is_empty() {
+            return Err("at least one upstream server is needed".into());
+        }
+        if self.mode.is_encrypted() && self.server_name.is_none() {
+            return Err("server-name is needed to validate the certificates of servers".into());
+        }
+        if self.path.is_some() && self.mode != ResolverMode::Https {
+            return Err("path can only be used with the https mode".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolver_config() {
+        let config: ResolverConfig = toml::from_str("").unwrap();
+        assert_eq!(config.mode, ResolverMode::System);
+        assert!(config.check().is_ok());
+
+        let config: ResolverConfig = toml::from_str(
+            r#"
+            mode = "https"
+            servers = ["192.0.2.53", "2001:db8::53"]
+            server-name = "dns.example.com"
+            path = "/resolve"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, ResolverMode::Https);
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.check().is_ok(), cfg!(feature = "srv"));
+
+        let config: ResolverConfig =
+            toml::from_str("mode = \"system\"\nservers = [\"192.0.2.53\"]").unwrap();
+        assert!(config.check().is_err());
+
+        // Certificates can't be checked without a name
+        let config: ResolverConfig =
+            toml::from_str("mode = \"tls\"\nservers = [\"192.0.2.53\"]").unwrap();
+        assert!(config.check().is_err());
+
+        let config: ResolverConfig = toml::from_str("mode = \"dns\"").unwrap();
+        assert!(config.check().is_err());
+
+        assert!(toml::from_str::<ResolverConfig>("mode = \"quic\"").is_err());
+    }
+}
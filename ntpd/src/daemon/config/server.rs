@@ -1,13 +1,16 @@
 use std::{
     net::{AddrParseError, SocketAddr},
+    num::NonZeroUsize,
     path::PathBuf,
     str::FromStr,
     time::Duration,
 };
 
-use ntp_proto::{FilterAction, FilterList, NtpVersion};
+use ntp_proto::{AeadAlgorithm, FilterAction, FilterList, NtpVersion, PollInterval};
 use serde::{Deserialize, Deserializer};
 
+use super::ntp_source::{default_aead_algorithms, deserialize_aead_algorithms};
+
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct KeysetConfig {
@@ -19,6 +22,10 @@ pub struct KeysetConfig {
     pub key_rotation_interval: usize,
     #[serde(default)]
     pub key_storage_path: Option<String>,
+    /// Load the keys another server stores at `key_storage_path` instead of
+    /// generating them
+    #[serde(default)]
+    pub follow_key_storage: bool,
 }
 
 impl Default for KeysetConfig {
@@ -27,6 +34,7 @@ impl Default for KeysetConfig {
             stale_key_count: default_stale_key_count(),
             key_rotation_interval: default_key_rotation_interval(),
             key_storage_path: None,
+            follow_key_storage: false,
         }
     }
 }
@@ -64,6 +72,24 @@ pub struct ServerConfig {
         deserialize_with = "deserialize_accepted_ntp_versions"
     )]
     pub accept_ntp_versions: Vec<NtpVersion>,
+    /// Addresses to which broadcast or multicast packets are sent
+    #[serde(default)]
+    pub broadcast: Vec<SocketAddr>,
+    #[serde(default = "default_broadcast_interval")]
+    pub broadcast_interval: PollInterval,
+    /// Number of tasks answering requests on the listen address, each with
+    /// its own socket
+    #[serde(default = "default_workers")]
+    pub workers: NonZeroUsize,
+}
+
+fn default_workers() -> NonZeroUsize {
+    NonZeroUsize::MIN
+}
+
+fn default_broadcast_interval() -> PollInterval {
+    // 64 seconds
+    PollInterval::from_byte(6)
 }
 
 fn default_accepted_ntp_versions() -> Vec<NtpVersion> {
@@ -159,6 +185,9 @@ impl TryFrom<&str> for ServerConfig {
             rate_limiting_cutoff: Duration::default(),
             require_nts: None,
             accept_ntp_versions: default_accepted_ntp_versions(),
+            broadcast: vec![],
+            broadcast_interval: default_broadcast_interval(),
+            workers: default_workers(),
         })
     }
 }
@@ -174,6 +203,9 @@ impl From<SocketAddr> for ServerConfig {
             rate_limiting_cutoff: Duration::default(),
             require_nts: None,
             accept_ntp_versions: default_accepted_ntp_versions(),
+            broadcast: vec![],
+            broadcast_interval: default_broadcast_interval(),
+            workers: default_workers(),
         }
     }
 }
@@ -191,21 +223,118 @@ impl From<ServerConfig> for ntp_proto::ServerConfig {
     }
 }
 
+/// Certificate presented to clients that ask for a specific server name
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NtsKeCertificateConfig {
+    pub server_name: String,
+    pub certificate_chain_path: PathBuf,
+    pub private_key_path: PathBuf,
+}
+
+/// NTP server that a key exchange server sends clients to, instead of one
+/// running in this daemon
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NtsKeBackendConfig {
+    /// Host name or IP address clients are told to use
+    pub server: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Keys the backend stores, used for the cookies of its clients instead
+    /// of the keys of this daemon
+    #[serde(default)]
+    pub key_storage_path: Option<String>,
+}
+
+/// Obtain the certificate of an NTS KE server from an ACME certificate
+/// authority such as Let's Encrypt
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AcmeConfig {
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub accept_terms_of_service: bool,
+    #[serde(default = "default_acme_directory")]
+    pub directory: String,
+    /// Where the account key is kept, and by default also the certificate
+    #[serde(default = "default_acme_storage_path")]
+    pub storage_path: PathBuf,
+    /// Address on which HTTP-01 challenges are answered
+    #[serde(default = "default_acme_challenge_listen")]
+    pub challenge_listen: SocketAddr,
+}
+
+/// Private key of the default certificate of an NTS KE server
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PrivateKeyConfig {
+    /// PEM file containing the key
+    Path(PathBuf),
+    /// Key that stays on a PKCS#11 token
+    Pkcs11(Pkcs11KeyConfig),
+}
+
+/// Private key kept on a PKCS#11 token, such as a hardware security module
+/// or a TPM, which does the signing so the key is never stored on disk
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Pkcs11KeyConfig {
+    /// The PKCS#11 module (shared library) provided for the token
+    pub module_path: PathBuf,
+    pub token_label: String,
+    pub key_label: String,
+    /// File containing the user PIN, if the token requires logging in
+    #[serde(default)]
+    pub pin_path: Option<PathBuf>,
+}
+
+fn default_acme_directory() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".into()
+}
+
+fn default_acme_storage_path() -> PathBuf {
+    PathBuf::from("/var/lib/ntpd-rs/acme")
+}
+
+fn default_acme_challenge_listen() -> SocketAddr {
+    "[::]:80".parse().unwrap()
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct NtsKeConfig {
     pub certificate_chain_path: PathBuf,
-    pub private_key_path: PathBuf,
+    pub private_key: PrivateKeyConfig,
+    pub sni_certificates: Vec<NtsKeCertificateConfig>,
+    pub acme: Option<AcmeConfig>,
     pub accepted_pool_authentication_tokens: Vec<String>,
     pub key_exchange_timeout_ms: u64,
+    /// Seconds between checks for changed certificates, 0 disables them
+    pub certificate_reload_interval: u64,
     pub concurrent_connections: usize,
     pub longlived_connections: usize,
+    /// Maximum number of simultaneous connections from a single IP address
+    pub concurrent_connections_per_ip: usize,
+    /// New connections accepted per second on average, 0 disables the limit
+    pub handshake_rate: u32,
+    /// New connections accepted at once before `handshake_rate` applies
+    pub handshake_burst: u32,
+    /// Like `handshake_rate`, but for each IP address separately
+    pub handshake_rate_per_ip: u32,
+    /// Like `handshake_burst`, but for each IP address separately
+    pub handshake_burst_per_ip: u32,
     pub listen: SocketAddr,
     pub ntp_port: Option<u16>,
     pub ntp_server: Option<String>,
+    /// NTP servers clients are spread over, each client is sent to one of them
+    pub backends: Vec<NtsKeBackendConfig>,
     pub accept_ntp_versions: Vec<NtpVersion>,
+    pub accept_aead_algorithms: Vec<AeadAlgorithm>,
 }
 
 impl<'de> Deserialize<'de> for NtsKeConfig {
+    #[expect(clippy::too_many_lines, reason = "Deserializers can be a bit wordy")]
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
@@ -213,44 +342,125 @@ impl<'de> Deserialize<'de> for NtsKeConfig {
         #[derive(Deserialize)]
         #[serde(rename_all = "kebab-case", deny_unknown_fields)]
         struct NtsKeConfigRaw {
-            certificate_chain_path: PathBuf,
-            private_key_path: PathBuf,
+            certificate_chain_path: Option<PathBuf>,
+            private_key_path: Option<PathBuf>,
+            #[serde(default)]
+            pkcs11: Option<Pkcs11KeyConfig>,
+            #[serde(default, rename = "sni-certificate")]
+            sni_certificates: Vec<NtsKeCertificateConfig>,
+            #[serde(default)]
+            acme: Option<AcmeConfig>,
             #[serde(default)]
             accepted_pool_authentication_tokens: Vec<String>,
             #[serde(default = "default_nts_ke_timeout")]
             key_exchange_timeout_ms: u64,
+            #[serde(default = "default_certificate_reload_interval")]
+            certificate_reload_interval: u64,
             #[serde(default = "default_concurrent_connections")]
             concurrent_connections: usize,
             #[serde(default)]
             longlived_connections: Option<usize>,
+            #[serde(default = "default_concurrent_connections_per_ip")]
+            concurrent_connections_per_ip: usize,
+            #[serde(default = "default_handshake_rate")]
+            handshake_rate: u32,
+            #[serde(default = "default_handshake_burst")]
+            handshake_burst: u32,
+            #[serde(default = "default_handshake_rate_per_ip")]
+            handshake_rate_per_ip: u32,
+            #[serde(default = "default_handshake_burst_per_ip")]
+            handshake_burst_per_ip: u32,
             listen: SocketAddr,
             ntp_port: Option<u16>,
             ntp_server: Option<String>,
+            #[serde(default, rename = "backend")]
+            backends: Vec<NtsKeBackendConfig>,
             #[serde(
                 default = "default_accept_ntp_versions",
                 deserialize_with = "deserialize_accepted_ntp_versions_for_nts"
             )]
             accept_ntp_versions: Vec<NtpVersion>,
+            #[serde(
+                default = "default_aead_algorithms",
+                deserialize_with = "deserialize_aead_algorithms"
+            )]
+            accept_aead_algorithms: Vec<AeadAlgorithm>,
         }
 
         let raw = NtsKeConfigRaw::deserialize(deserializer)?;
+
+        if !raw.backends.is_empty() && (raw.ntp_server.is_some() || raw.ntp_port.is_some()) {
+            return Err(serde::de::Error::custom(
+                "ntp-server and ntp-port cannot be combined with backends",
+            ));
+        }
+
+        // Certificates obtained using ACME are stored next to the account key
+        // unless configured otherwise
+        let acme_storage = raw.acme.as_ref().map(|acme| &acme.storage_path);
+        let certificate_chain_path = raw
+            .certificate_chain_path
+            .or_else(|| acme_storage.map(|path| path.join("certificate-chain.pem")))
+            .ok_or_else(|| serde::de::Error::missing_field("certificate-chain-path"))?;
+        let private_key = match (raw.private_key_path, raw.pkcs11) {
+            (Some(_), Some(_)) => {
+                return Err(serde::de::Error::custom(
+                    "private-key-path cannot be combined with pkcs11",
+                ));
+            }
+            (None, Some(_)) if raw.acme.is_some() => {
+                return Err(serde::de::Error::custom(
+                    "pkcs11 cannot be combined with acme, which stores its key on disk",
+                ));
+            }
+            (None, Some(pkcs11)) => PrivateKeyConfig::Pkcs11(pkcs11),
+            (Some(path), None) => PrivateKeyConfig::Path(path),
+            (None, None) => PrivateKeyConfig::Path(
+                acme_storage
+                    .map(|path| path.join("private.key"))
+                    .ok_or_else(|| serde::de::Error::missing_field("private-key-path"))?,
+            ),
+        };
+
         Ok(NtsKeConfig {
-            certificate_chain_path: raw.certificate_chain_path,
-            private_key_path: raw.private_key_path,
+            certificate_chain_path,
+            private_key,
+            sni_certificates: raw.sni_certificates,
+            acme: raw.acme,
             accepted_pool_authentication_tokens: raw.accepted_pool_authentication_tokens,
             key_exchange_timeout_ms: raw.key_exchange_timeout_ms,
+            certificate_reload_interval: raw.certificate_reload_interval,
             concurrent_connections: raw.concurrent_connections,
             longlived_connections: raw
                 .longlived_connections
                 .unwrap_or(raw.concurrent_connections / 10),
+            concurrent_connections_per_ip: raw.concurrent_connections_per_ip,
+            handshake_rate: raw.handshake_rate,
+            handshake_burst: raw.handshake_burst,
+            handshake_rate_per_ip: raw.handshake_rate_per_ip,
+            handshake_burst_per_ip: raw.handshake_burst_per_ip,
             listen: raw.listen,
             ntp_port: raw.ntp_port,
             ntp_server: raw.ntp_server,
+            backends: raw.backends,
             accept_ntp_versions: raw.accept_ntp_versions,
+            accept_aead_algorithms: raw.accept_aead_algorithms,
         })
     }
 }
 
+impl NtsKeConfig {
+    /// The file containing the private key of the default certificate, if it
+    /// is not kept on a token
+    #[cfg(feature = "server")]
+    pub fn private_key_path(&self) -> Option<&std::path::Path> {
+        match &self.private_key {
+            PrivateKeyConfig::Path(path) => Some(path),
+            PrivateKeyConfig::Pkcs11(_) => None,
+        }
+    }
+}
+
 fn default_accept_ntp_versions() -> Vec<NtpVersion> {
     vec![NtpVersion::V4]
 }
@@ -278,11 +488,40 @@ fn default_nts_ke_timeout() -> u64 {
     1000
 }
 
+fn default_certificate_reload_interval() -> u64 {
+    // 1 minute in seconds
+    60
+}
+
 fn default_concurrent_connections() -> usize {
     512
 }
 
+fn default_concurrent_connections_per_ip() -> usize {
+    16
+}
+
+fn default_handshake_rate() -> u32 {
+    256
+}
+
+fn default_handshake_burst() -> u32 {
+    1024
+}
+
+fn default_handshake_rate_per_ip() -> u32 {
+    4
+}
+
+fn default_handshake_burst_per_ip() -> u32 {
+    32
+}
+
 #[cfg(test)]
+#[expect(
+    clippy::too_many_lines,
+    reason = "Long tests are not really a big problem"
+)]
 mod tests {
     use super::*;
 
@@ -385,6 +624,44 @@ mod tests {
             "#,
         );
         assert!(test.is_err());
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "192.168.1.2:123"
+            broadcast = ["192.168.1.255:123", "224.0.1.1:123"]
+            broadcast-interval = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.server.broadcast,
+            vec![
+                "192.168.1.255:123".parse().unwrap(),
+                "224.0.1.1:123".parse().unwrap()
+            ]
+        );
+        assert_eq!(test.server.broadcast_interval, PollInterval::from_byte(4));
+        assert_eq!(test.server.workers.get(), 1);
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "[::]:123"
+            workers = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.server.workers.get(), 4);
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "[::]:123"
+            workers = 0
+            "#,
+        );
+        assert!(test.is_err());
     }
 
     #[test]
@@ -406,6 +683,17 @@ mod tests {
         .unwrap();
 
         assert_ne!(test.keyset, KeysetConfig::default());
+        assert!(!test.keyset.follow_key_storage);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [keyset]
+            key-storage-path = "/shared/keys.key"
+            follow-key-storage = true
+            "#,
+        )
+        .unwrap();
+        assert!(test.keyset.follow_key_storage);
     }
 
     #[test]
@@ -429,11 +717,54 @@ mod tests {
         let pem = PathBuf::from("/foo/bar/baz.pem");
         assert_eq!(test.nts_ke_server.certificate_chain_path, pem);
         assert_eq!(
-            test.nts_ke_server.private_key_path,
-            PathBuf::from("spam.der")
+            test.nts_ke_server.private_key,
+            PrivateKeyConfig::Path(PathBuf::from("spam.der"))
         );
         assert_eq!(test.nts_ke_server.key_exchange_timeout_ms, 1000,);
+        assert_eq!(test.nts_ke_server.certificate_reload_interval, 60);
         assert_eq!(test.nts_ke_server.listen, "0.0.0.0:4460".parse().unwrap(),);
+        assert_eq!(
+            test.nts_ke_server.accept_aead_algorithms,
+            AeadAlgorithm::SUPPORTED
+        );
+        assert_eq!(test.nts_ke_server.concurrent_connections_per_ip, 16);
+        assert_eq!(test.nts_ke_server.handshake_rate, 256);
+        assert_eq!(test.nts_ke_server.handshake_rate_per_ip, 4);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+            private-key-path = "spam.der"
+            accept-aead-algorithms = ["aes-siv-cmac-256"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.nts_ke_server.accept_aead_algorithms,
+            [AeadAlgorithm::AeadAesSivCmac256]
+        );
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+            private-key-path = "spam.der"
+            concurrent-connections-per-ip = 2
+            handshake-rate = 0
+            handshake-burst = 10
+            handshake-rate-per-ip = 1
+            handshake-burst-per-ip = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.nts_ke_server.concurrent_connections_per_ip, 2);
+        assert_eq!(test.nts_ke_server.handshake_rate, 0);
+        assert_eq!(test.nts_ke_server.handshake_burst, 10);
+        assert_eq!(test.nts_ke_server.handshake_rate_per_ip, 1);
+        assert_eq!(test.nts_ke_server.handshake_burst_per_ip, 5);
     }
 
     #[test]
@@ -460,4 +791,210 @@ mod tests {
             vec!["a.test".to_string(), "b.test".to_string()]
         );
     }
+
+    #[test]
+    fn test_deserialize_nts_ke_acme() {
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        struct TestConfig {
+            nts_ke_server: NtsKeConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+
+            [nts-ke-server.acme]
+            domains = ["time.example.com"]
+            email = "admin@example.com"
+            accept-terms-of-service = true
+            "#,
+        )
+        .unwrap();
+
+        let acme = test.nts_ke_server.acme.unwrap();
+        assert_eq!(acme.domains, vec!["time.example.com".to_string()]);
+        assert_eq!(acme.directory, default_acme_directory());
+        assert_eq!(acme.challenge_listen, "[::]:80".parse().unwrap());
+        assert_eq!(
+            test.nts_ke_server.certificate_chain_path,
+            PathBuf::from("/var/lib/ntpd-rs/acme/certificate-chain.pem")
+        );
+        assert_eq!(
+            test.nts_ke_server.private_key,
+            PrivateKeyConfig::Path(PathBuf::from("/var/lib/ntpd-rs/acme/private.key"))
+        );
+
+        // Without ACME, the certificate must be configured
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            private-key-path = "spam.der"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_nts_ke_pkcs11() {
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        struct TestConfig {
+            nts_ke_server: NtsKeConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+
+            [nts-ke-server.pkcs11]
+            module-path = "/usr/lib/softhsm/libsofthsm2.so"
+            token-label = "ntpd-rs"
+            key-label = "nts-ke"
+            pin-path = "/etc/ntpd-rs/token.pin"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            test.nts_ke_server.private_key,
+            PrivateKeyConfig::Pkcs11(Pkcs11KeyConfig {
+                module_path: PathBuf::from("/usr/lib/softhsm/libsofthsm2.so"),
+                token_label: "ntpd-rs".into(),
+                key_label: "nts-ke".into(),
+                pin_path: Some(PathBuf::from("/etc/ntpd-rs/token.pin")),
+            })
+        );
+        #[cfg(feature = "server")]
+        assert_eq!(test.nts_ke_server.private_key_path(), None);
+
+        // The key is either in a file or on a token
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+            private-key-path = "spam.der"
+
+            [nts-ke-server.pkcs11]
+            module-path = "/usr/lib/softhsm/libsofthsm2.so"
+            token-label = "ntpd-rs"
+            key-label = "nts-ke"
+            "#,
+        );
+        assert!(test.is_err());
+
+        // ACME generates its own key on disk
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+
+            [nts-ke-server.acme]
+            domains = ["time.example.com"]
+
+            [nts-ke-server.pkcs11]
+            module-path = "/usr/lib/softhsm/libsofthsm2.so"
+            token-label = "ntpd-rs"
+            key-label = "nts-ke"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_nts_ke_sni_certificates() {
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        struct TestConfig {
+            nts_ke_server: NtsKeConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+            private-key-path = "spam.der"
+            certificate-reload-interval = 0
+
+            [[nts-ke-server.sni-certificate]]
+            server-name = "time.example.com"
+            certificate-chain-path = "/foo/bar/time.pem"
+            private-key-path = "time.der"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(test.nts_ke_server.certificate_reload_interval, 0);
+        assert_eq!(
+            test.nts_ke_server.sni_certificates,
+            vec![NtsKeCertificateConfig {
+                server_name: "time.example.com".into(),
+                certificate_chain_path: PathBuf::from("/foo/bar/time.pem"),
+                private_key_path: PathBuf::from("time.der"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_nts_ke_backends() {
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        struct TestConfig {
+            nts_ke_server: NtsKeConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+            private-key-path = "spam.der"
+
+            [[nts-ke-server.backend]]
+            server = "ntp1.example.com"
+
+            [[nts-ke-server.backend]]
+            server = "2001:db8::123"
+            port = 1123
+            key-storage-path = "/var/lib/ntpd-rs/ntp2.keys"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            test.nts_ke_server.backends,
+            vec![
+                NtsKeBackendConfig {
+                    server: "ntp1.example.com".into(),
+                    port: None,
+                    key_storage_path: None,
+                },
+                NtsKeBackendConfig {
+                    server: "2001:db8::123".into(),
+                    port: Some(1123),
+                    key_storage_path: Some("/var/lib/ntpd-rs/ntp2.keys".into()),
+                },
+            ]
+        );
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [nts-ke-server]
+            listen = "0.0.0.0:4460"
+            certificate-chain-path = "/foo/bar/baz.pem"
+            private-key-path = "spam.der"
+            ntp-server = "ntp.example.com"
+
+            [[nts-ke-server.backend]]
+            server = "ntp1.example.com"
+            "#,
+        );
+        assert!(test.is_err());
+    }
 }
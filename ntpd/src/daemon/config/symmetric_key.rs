@@ -0,0 +1,344 @@
+use std::{os::unix::fs::PermissionsExt, path::PathBuf, sync::Arc};
+
+use ntp_proto::{MacAlgorithm, SymmetricKey};
+use serde::{Deserialize, Deserializer, de};
+use tracing::warn;
+
+/// Support for the digest based MACs and keys file of older ntpd versions
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LegacyAuthConfig {
+    /// Allow the insecure MD5, SHA-1 and SHA-256 algorithms for symmetric keys
+    #[serde(default)]
+    pub insecure_enable: bool,
+    /// ntpd-style keys file with additional symmetric keys
+    #[serde(default)]
+    pub keys_file: Option<PathBuf>,
+}
+
+impl LegacyAuthConfig {
+    /// Combine the configured keys with those from the keys file, checking
+    /// that legacy algorithms are only used when explicitly enabled.
+    pub fn load_keys(
+        &self,
+        configured: &Arc<[SymmetricKey]>,
+    ) -> std::io::Result<Arc<[SymmetricKey]>> {
+        let mut keys = configured.to_vec();
+
+        if let Some(path) = &self.keys_file {
+            let meta = std::fs::metadata(path)?;
+            if meta.permissions().mode() as libc::mode_t & libc::S_IROTH != 0 {
+                warn!(
+                    ?path,
+                    "Unrestricted keys file permissions: Others can read."
+                );
+            }
+
+            let contents = std::fs::read_to_string(path)?;
+            for key in parse_keys_file(&contents).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid keys file {}: {e}", path.display()),
+                )
+            })? {
+                if keys.iter().any(|other| other.id() == key.id()) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("symmetric key {} is configured more than once", key.id()),
+                    ));
+                }
+                keys.push(key);
+            }
+        }
+
+        if !self.insecure_enable
+            && let Some(key) = keys.iter().find(|key| key.algorithm().is_legacy())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "symmetric key {} uses an insecure algorithm, set `insecure-enable = true` in the [legacy-auth] section to allow this",
+                    key.id()
+                ),
+            ));
+        }
+
+        Ok(keys.into())
+    }
+}
+
+fn parse_legacy_algorithm(name: &str) -> Option<MacAlgorithm> {
+    match name.to_ascii_uppercase().as_str() {
+        "M" | "MD5" => Some(MacAlgorithm::Md5),
+        "SHA1" => Some(MacAlgorithm::Sha1),
+        "SHA256" => Some(MacAlgorithm::Sha256),
+        "AES128CMAC" => Some(MacAlgorithm::Aes128Cmac),
+        _ => None,
+    }
+}
+
+/// Parse the contents of an ntpd-style keys file. Each line contains the key
+/// id, the algorithm and the key. Keys of up to 20 characters are used as is,
+/// longer keys are hex encoded.
+fn parse_keys_file(contents: &str) -> Result<Vec<SymmetricKey>, String> {
+    let mut keys: Vec<SymmetricKey> = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+
+        let Some(id) = fields.next() else {
+            continue;
+        };
+        let (Some(algorithm), Some(secret)) = (fields.next(), fields.next()) else {
+            return Err(format!(
+                "line {line_number}: expected a key id, type and key"
+            ));
+        };
+        if fields.next().is_some() {
+            return Err(format!(
+                "line {line_number}: address restrictions on keys are not supported"
+            ));
+        }
+
+        let id: u32 = id
+            .parse()
+            .map_err(|_| format!("line {line_number}: invalid key id {id}"))?;
+        let algorithm = parse_legacy_algorithm(algorithm)
+            .ok_or_else(|| format!("line {line_number}: unsupported key type {algorithm}"))?;
+        let secret = if secret.len() <= 20 {
+            secret.as_bytes().to_vec()
+        } else {
+            decode_hex(secret).ok_or_else(|| {
+                format!("line {line_number}: keys longer than 20 characters must be hex encoded")
+            })?
+        };
+
+        if keys.iter().any(|key| key.id() == id) {
+            return Err(format!(
+                "line {line_number}: key {id} is defined more than once"
+            ));
+        }
+        let key = SymmetricKey::new(id, algorithm, &secret)
+            .map_err(|_| format!("line {line_number}: invalid key {id}"))?;
+        keys.push(key);
+    }
+
+    Ok(keys)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct SymmetricKeyConfig {
+    id: u32,
+    #[serde(rename = "type", default = "default_mac_algorithm")]
+    algorithm: MacAlgorithm,
+    secret: String,
+}
+
+fn default_mac_algorithm() -> MacAlgorithm {
+    MacAlgorithm::Aes128Cmac
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) || !data.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub(super) fn deserialize_symmetric_keys<'de, D>(
+    deserializer: D,
+) -> Result<Arc<[SymmetricKey]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let configs: Vec<SymmetricKeyConfig> = Deserialize::deserialize(deserializer)?;
+
+    let mut keys: Vec<SymmetricKey> = Vec::with_capacity(configs.len());
+    for config in configs {
+        if keys.iter().any(|key| key.id() == config.id) {
+            return Err(de::Error::custom(format!(
+                "symmetric key {} is configured more than once",
+                config.id
+            )));
+        }
+
+        let secret = decode_hex(&config.secret)
+            .ok_or_else(|| de::Error::custom("symmetric key secret must be hex encoded"))?;
+        let key = SymmetricKey::new(config.id, config.algorithm, &secret).map_err(|_| {
+            de::Error::custom(format!(
+                "invalid symmetric key {}: the id must be non-zero and the secret must have a valid length for its type",
+                config.id
+            ))
+        })?;
+        keys.push(key);
+    }
+
+    Ok(keys.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "kebab-case")]
+    struct TestConfig {
+        #[serde(
+            rename = "symmetric-key",
+            deserialize_with = "deserialize_symmetric_keys"
+        )]
+        symmetric_keys: Arc<[SymmetricKey]>,
+    }
+
+    #[test]
+    fn test_symmetric_key_parsing() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [[symmetric-key]]
+            id = 1
+            type = "AES128CMAC"
+            secret = "000102030405060708090a0b0c0d0e0f"
+
+            [[symmetric-key]]
+            id = 2
+            secret = "00112233445566778899AABBCCDDEEFF"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.symmetric_keys.len(), 2);
+        assert_eq!(test.symmetric_keys[0].id(), 1);
+        assert_eq!(test.symmetric_keys[1].id(), 2);
+        assert_eq!(test.symmetric_keys[1].algorithm(), MacAlgorithm::Aes128Cmac);
+
+        // Duplicate key ids
+        assert!(
+            toml::from_str::<TestConfig>(
+                r#"
+                [[symmetric-key]]
+                id = 1
+                secret = "000102030405060708090a0b0c0d0e0f"
+
+                [[symmetric-key]]
+                id = 1
+                secret = "000102030405060708090a0b0c0d0e0f"
+                "#,
+            )
+            .is_err()
+        );
+
+        // Wrong secret length
+        assert!(
+            toml::from_str::<TestConfig>(
+                r#"
+                [[symmetric-key]]
+                id = 1
+                secret = "0001020304050607"
+                "#,
+            )
+            .is_err()
+        );
+
+        // Invalid hex
+        assert!(
+            toml::from_str::<TestConfig>(
+                r#"
+                [[symmetric-key]]
+                id = 1
+                secret = "000102030405060708090a0b0c0d0eXX"
+                "#,
+            )
+            .is_err()
+        );
+
+        // Reserved key id
+        assert!(
+            toml::from_str::<TestConfig>(
+                r#"
+                [[symmetric-key]]
+                id = 0
+                secret = "000102030405060708090a0b0c0d0e0f"
+                "#,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_legacy_key_types() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [[symmetric-key]]
+            id = 1
+            type = "MD5"
+            secret = "73656372657421"
+
+            [[symmetric-key]]
+            id = 2
+            type = "SHA256"
+            secret = "000102030405060708090a0b0c0d0e0f10111213"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.symmetric_keys[0].algorithm(), MacAlgorithm::Md5);
+        assert_eq!(test.symmetric_keys[1].algorithm(), MacAlgorithm::Sha256);
+
+        let disabled = LegacyAuthConfig::default();
+        assert!(disabled.load_keys(&test.symmetric_keys).is_err());
+
+        let enabled = LegacyAuthConfig {
+            insecure_enable: true,
+            keys_file: None,
+        };
+        assert_eq!(enabled.load_keys(&test.symmetric_keys).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_keys_file() {
+        let keys = parse_keys_file(
+            "# ntp.keys
+            1 M secret
+            2 md5 0123456789abcdef0123456789abcdef01234567 # hex key
+            3 SHA1 sha1secret
+
+            65535 SHA256 sha256secret
+            10 AES128CMAC 000102030405060708090a0b0c0d0e0f
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            keys.iter().map(SymmetricKey::id).collect::<Vec<_>>(),
+            [1, 2, 3, 65535, 10]
+        );
+        assert_eq!(keys[0].algorithm(), MacAlgorithm::Md5);
+        assert_eq!(keys[1].algorithm(), MacAlgorithm::Md5);
+        assert_eq!(keys[2].algorithm(), MacAlgorithm::Sha1);
+        assert_eq!(keys[3].algorithm(), MacAlgorithm::Sha256);
+        assert_eq!(keys[4].algorithm(), MacAlgorithm::Aes128Cmac);
+
+        assert!(parse_keys_file("").unwrap().is_empty());
+        assert!(parse_keys_file("1 M").is_err());
+        assert!(parse_keys_file("1 DES secret").is_err());
+        assert!(parse_keys_file("x M secret").is_err());
+        assert!(parse_keys_file("0 M secret").is_err());
+        assert!(parse_keys_file("1 M secret\n1 SHA1 secret").is_err());
+        assert!(parse_keys_file("1 M secret 192.0.2.1").is_err());
+        assert!(parse_keys_file("1 M 0123456789abcdef0123456789abcdef0123456z").is_err());
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("00ff7F"), Some(vec![0x00, 0xff, 0x7f]));
+        assert_eq!(decode_hex("0"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("é0"), None);
+    }
+}
@@ -0,0 +1,36 @@
+use ntp_proto::ClockId;
+use serde::{Deserialize, Serialize};
+
+// The daemon side of the control socket only exists when there are sources
+// to control.
+#[cfg(feature = "client")]
+mod socket;
+
+#[cfg(feature = "client")]
+pub use socket::{ControlCommand, ControlMessage, spawn};
+
+/// Request sent to the daemon over the control socket
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    /// Add a source. The source is described as a TOML table with the same
+    /// fields as a `[[source]]` entry in the configuration file.
+    AddSource { source: String },
+    /// Remove the source with the given id
+    RemoveSource { id: ClockId },
+    /// Poll the NTP source with the given id, or all of them, in a burst
+    Burst {
+        #[serde(default)]
+        id: Option<ClockId>,
+    },
+}
+
+/// Response of the daemon to a [`ControlRequest`]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum ControlResponse {
+    SourceAdded,
+    SourceRemoved,
+    BurstStarted { sources: usize },
+    Error { message: String },
+}
@@ -0,0 +1,312 @@
+use std::os::unix::fs::PermissionsExt;
+
+use libc::{ECONNABORTED, EMFILE, ENFILE, ENOBUFS, ENOMEM};
+use ntp_proto::ClockId;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{Instrument, Span, debug, error, instrument, trace, warn};
+
+use super::{ControlRequest, ControlResponse};
+use crate::daemon::{
+    config::{NormalizedAddress, NtpSourceConfig, ObservabilityConfig},
+    sockets::{create_unix_socket_with_permissions, read_json, write_json},
+};
+
+/// Command forwarded to the system task
+#[derive(Debug)]
+pub enum ControlCommand {
+    AddSource(Box<NtpSourceConfig>),
+    RemoveSource(ClockId),
+    Burst(Option<ClockId>),
+}
+
+#[derive(Debug)]
+pub struct ControlMessage {
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+// Adding a source can involve a DNS lookup, so be a bit more lenient than the
+// observation socket.
+const CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[instrument(level = tracing::Level::ERROR, skip_all, name = "Control", fields(path = debug(config.control_path.clone())))]
+pub fn spawn(
+    config: &ObservabilityConfig,
+    control_sender: mpsc::Sender<ControlMessage>,
+) -> JoinHandle<std::io::Result<()>> {
+    let config = config.clone();
+    tokio::spawn(
+        (async move {
+            let result = control(config, control_sender).await;
+            if let Err(ref e) = result {
+                warn!("Abnormal termination of the control socket: {e}");
+                warn!("Sources can not be changed at runtime");
+            }
+            result
+        })
+        .instrument(Span::current()),
+    )
+}
+
+async fn control(
+    config: ObservabilityConfig,
+    control_sender: mpsc::Sender<ControlMessage>,
+) -> std::io::Result<()> {
+    let Some(path) = config.control_path else {
+        return Ok(());
+    };
+
+    // Changing sources is a privileged operation, the permissions of the
+    // socket determine who is allowed to do so.
+    let permissions: std::fs::Permissions = PermissionsExt::from_mode(config.control_permissions);
+
+    let listener = create_unix_socket_with_permissions(&path, permissions)?;
+
+    loop {
+        let (mut stream, _addr) = match listener.accept().await {
+            Ok(a) => a,
+            Err(e) if matches!(e.raw_os_error(), Some(ECONNABORTED)) => {
+                debug!("Unexpectedly closed unix socket: {e}");
+                continue;
+            }
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(ENFILE) | Some(EMFILE) | Some(ENOMEM) | Some(ENOBUFS)
+                ) =>
+            {
+                error!("Not enough resources available to accept incoming control socket: {e}");
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Could not accept connection due to unexpected problem: {e}");
+                return Err(e);
+            }
+        };
+
+        let control_sender = control_sender.clone();
+        tokio::spawn(async move {
+            let fut = handle_connection(&mut stream, &control_sender);
+            match tokio::time::timeout(CONNECTION_TIMEOUT, fut).await {
+                Err(_) => debug!("Handling control request timed out"),
+                Ok(Err(err)) => warn!("error handling connection: {err}"),
+                Ok(_) => trace!("Handled control request"),
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: &mut (impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin),
+    control_sender: &mpsc::Sender<ControlMessage>,
+) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    let request: ControlRequest = read_json(stream, &mut buffer).await?;
+    debug!(?request, "control request");
+
+    let response = match request {
+        ControlRequest::AddSource { source } => match parse_source(&source).await {
+            Ok(config) => {
+                forward(control_sender, ControlCommand::AddSource(Box::new(config))).await
+            }
+            Err(message) => ControlResponse::Error { message },
+        },
+        ControlRequest::RemoveSource { id } => {
+            forward(control_sender, ControlCommand::RemoveSource(id)).await
+        }
+        ControlRequest::Burst { id } => forward(control_sender, ControlCommand::Burst(id)).await,
+    };
+
+    write_json(stream, &response).await
+}
+
+/// Parse and validate a source to be added at runtime
+async fn parse_source(source: &str) -> Result<NtpSourceConfig, String> {
+    let config: NtpSourceConfig =
+        toml::from_str(source).map_err(|e| format!("Invalid source: {e}"))?;
+
+    // Check the address resolves now, so the user gets feedback. The spawner
+    // will do its own lookups later on.
+    let address: &NormalizedAddress = match &config {
+        NtpSourceConfig::Standard(cfg) => &cfg.first.address,
+        NtpSourceConfig::Nts(cfg) => &cfg.first.address,
+        NtpSourceConfig::Pool(cfg) => &cfg.first.addr,
+        NtpSourceConfig::NtsPool(cfg) => &cfg.first.addr,
+        NtpSourceConfig::Roughtime(cfg) => &cfg.first.address,
+        NtpSourceConfig::Sock(_) => {
+            return Err("Sock sources can not be added at runtime".into());
+        }
+        NtpSourceConfig::Broadcast(_) => {
+            return Err("Broadcast sources can not be added at runtime".into());
+        }
+        NtpSourceConfig::Nmea(_) => {
+            return Err("NMEA sources can not be added at runtime".into());
+        }
+        NtpSourceConfig::Gpsd(_) => {
+            return Err("gpsd sources can not be added at runtime".into());
+        }
+        NtpSourceConfig::Phc(_) => {
+            return Err("PHC sources can not be added at runtime".into());
+        }
+        NtpSourceConfig::Ptp(_) => {
+            return Err("PTP sources can not be added at runtime".into());
+        }
+        NtpSourceConfig::Shm(_) => {
+            return Err("SHM sources can not be added at runtime".into());
+        }
+        #[cfg(feature = "pps")]
+        NtpSourceConfig::Pps(_) => {
+            return Err("PPS sources can not be added at runtime".into());
+        }
+    };
+
+    let found = match address.lookup_host().await {
+        Ok(mut addresses) => addresses.next().is_some(),
+        Err(e) => return Err(format!("Could not resolve {address}: {e}")),
+    };
+
+    if found {
+        Ok(config)
+    } else {
+        Err(format!("Could not resolve {address}: no addresses found"))
+    }
+}
+
+async fn forward(
+    control_sender: &mpsc::Sender<ControlMessage>,
+    command: ControlCommand,
+) -> ControlResponse {
+    let (reply, response) = oneshot::channel();
+    if control_sender
+        .send(ControlMessage { command, reply })
+        .await
+        .is_err()
+    {
+        return ControlResponse::Error {
+            message: "The daemon is shutting down".into(),
+        };
+    }
+
+    response.await.unwrap_or_else(|_| ControlResponse::Error {
+        message: "The daemon did not handle the request".into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UnixStream;
+
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    async fn request(
+        path: &std::path::Path,
+        request: &ControlRequest,
+    ) -> std::io::Result<ControlResponse> {
+        let mut stream = UnixStream::connect(path).await?;
+        write_json(&mut stream, request).await?;
+        let mut buffer = Vec::new();
+        read_json(&mut stream, &mut buffer).await
+    }
+
+    #[tokio::test]
+    async fn test_control() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join(format!("ntp-test-stream-{}", alloc_port()));
+        let config = ObservabilityConfig {
+            control_path: Some(path.clone()),
+            ..Default::default()
+        };
+        // A socket left behind by an earlier run would look available
+        // before the listener is created
+        let _ = std::fs::remove_file(&path);
+
+        let (control_sender, mut control_receiver) = mpsc::channel(1);
+        let handle = spawn(&config, control_sender);
+
+        let system = tokio::spawn(async move {
+            let mut commands = vec![];
+            while let Some(message) = control_receiver.recv().await {
+                let response = match message.command {
+                    ControlCommand::AddSource(_) => ControlResponse::SourceAdded,
+                    ControlCommand::RemoveSource(_) => ControlResponse::Error {
+                        message: "No such source".into(),
+                    },
+                    ControlCommand::Burst(_) => ControlResponse::BurstStarted { sources: 3 },
+                };
+                commands.push(message.command);
+                message.reply.send(response).unwrap();
+            }
+            commands
+        });
+
+        // Wait for the socket to become available
+        while !path.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let response = request(
+            &path,
+            &ControlRequest::AddSource {
+                source: "mode = \"server\"\naddress = \"127.0.0.1\"".into(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(response, ControlResponse::SourceAdded);
+
+        let response = request(
+            &path,
+            &ControlRequest::RemoveSource {
+                id: "12345".parse().unwrap(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Error {
+                message: "No such source".into()
+            }
+        );
+
+        let response = request(&path, &ControlRequest::Burst { id: None })
+            .await
+            .unwrap();
+        assert_eq!(response, ControlResponse::BurstStarted { sources: 3 });
+
+        // Invalid sources are rejected before reaching the system
+        let response = request(
+            &path,
+            &ControlRequest::AddSource {
+                source: "mode = \"server\"".into(),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(response, ControlResponse::Error { .. }));
+
+        let response = request(
+            &path,
+            &ControlRequest::AddSource {
+                source: "mode = \"sock\"\npath = \"/tmp/sock\"\nprecision = 1e-3".into(),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(response, ControlResponse::Error { .. }));
+
+        handle.abort();
+        let _ = handle.await;
+        let commands = system.await.unwrap();
+        assert_eq!(commands.len(), 3);
+        assert!(matches!(commands[0], ControlCommand::AddSource(_)));
+        assert!(matches!(commands[1], ControlCommand::RemoveSource(_)));
+        assert!(matches!(commands[2], ControlCommand::Burst(None)));
+    }
+}
@@ -1,29 +1,185 @@
 use std::net::SocketAddr;
+#[cfg(feature = "srv")]
+use std::sync::{Arc, OnceLock};
 
 #[cfg(feature = "srv")]
 use hickory_resolver::{
     TokioResolver,
-    net::NetError,
+    config::{ConnectionConfig, NameServerConfig},
+    net::{NetError, runtime::TokioRuntimeProvider},
     proto::rr::{IntoName, Name},
 };
-use tokio::net::lookup_host;
+#[cfg(feature = "srv")]
+use ntp_proto::tls_utils::{self, ClientConfig, PlatformVerifier};
 
-use crate::daemon::config::NormalizedAddress;
+#[cfg(feature = "srv")]
+use crate::daemon::config::ResolverMode;
+use crate::daemon::config::{NormalizedAddress, ResolverConfig};
 
-// We keep the resolver globally to avoid reloading its configuration constantly.
+// We keep the resolvers globally to avoid reloading their configuration
+// constantly. The one for SRV records validates DNSSEC signatures.
+#[cfg(feature = "srv")]
+static RESOLVER: OnceLock<TokioResolver> = OnceLock::new();
+#[cfg(feature = "srv")]
+static ADDRESS_RESOLVER: OnceLock<TokioResolver> = OnceLock::new();
 #[cfg(feature = "srv")]
-static RESOLVER: std::sync::OnceLock<TokioResolver> = std::sync::OnceLock::new();
+static RESOLVER_CONFIG: OnceLock<ResolverConfig> = OnceLock::new();
 
 pub(crate) struct KeResolutionResult {
     pub(crate) addr: SocketAddr,
     pub(crate) srv_record_name: Option<String>,
 }
 
+// Kludge allowing us to return two types of iterator.
+enum Either<A, B> {
+    A(A),
+    #[cfg_attr(not(feature = "srv"), expect(dead_code))]
+    B(B),
+}
+
+impl<T, A: Iterator<Item = T>, B: Iterator<Item = T>> Iterator for Either<A, B> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Either::A(a) => a.next(),
+            Either::B(b) => b.next(),
+        }
+    }
+}
+
+/// Set up the resolver configured in `config`, used for all later lookups.
+pub(crate) fn init(config: &ResolverConfig) -> Result<(), String> {
+    config.check()?;
+    #[cfg(feature = "srv")]
+    {
+        let resolver = build_resolver(config, true)?;
+        // Addresses are looked up with the system resolver of tokio when
+        // no other resolver is configured
+        if config.mode != ResolverMode::System {
+            let _ = ADDRESS_RESOLVER.set(build_resolver(config, false)?);
+        }
+        let _ = RESOLVER.set(resolver);
+        let _ = RESOLVER_CONFIG.set(config.clone());
+    }
+    Ok(())
+}
+
+/// Look up the addresses of `name`, using the configured resolver
+#[cfg(not(feature = "srv"))]
+pub(crate) async fn lookup_host(
+    name: &str,
+    port: u16,
+) -> std::io::Result<impl Iterator<Item = SocketAddr>> {
+    tokio::net::lookup_host((name, port))
+        .await
+        .map(Either::<_, std::iter::Empty<_>>::A)
+}
+
+/// Look up the addresses of `name`, using the configured resolver
+#[cfg(feature = "srv")]
+pub(crate) async fn lookup_host(
+    name: &str,
+    port: u16,
+) -> std::io::Result<impl Iterator<Item = SocketAddr>> {
+    let mode = RESOLVER_CONFIG
+        .get()
+        .map_or(ResolverMode::System, |config| config.mode);
+    if mode == ResolverMode::System {
+        return tokio::net::lookup_host((name, port)).await.map(Either::A);
+    }
+
+    let resolver = resolver(&ADDRESS_RESOLVER, false).map_err(std::io::Error::other)?;
+    let lookup = resolver
+        .lookup_ip(name)
+        .await
+        .map_err(std::io::Error::other)?;
+    let addresses: Vec<_> = lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect();
+    Ok(Either::B(addresses.into_iter()))
+}
+
+/// Upstream servers of a resolver other than the system resolver
+#[cfg(feature = "srv")]
+fn name_servers(config: &ResolverConfig) -> Vec<NameServerConfig> {
+    let server_name: Arc<str> = config.server_name.as_deref().unwrap_or_default().into();
+    config
+        .servers
+        .iter()
+        .map(|ip| {
+            let mut connections = match config.mode {
+                ResolverMode::System | ResolverMode::Dns => {
+                    vec![ConnectionConfig::udp(), ConnectionConfig::tcp()]
+                }
+                ResolverMode::Tls => vec![ConnectionConfig::tls(server_name.clone())],
+                ResolverMode::Https => vec![ConnectionConfig::https(
+                    server_name.clone(),
+                    config.path.as_deref().map(Arc::from),
+                )],
+            };
+            if let Some(port) = config.port {
+                for connection in &mut connections {
+                    connection.port = port;
+                }
+            }
+            NameServerConfig::new(*ip, true, connections)
+        })
+        .collect()
+}
+
+/// Certificates of encrypted upstream servers are validated like those of
+/// NTS key exchange servers
+#[cfg(feature = "srv")]
+fn tls_config() -> ClientConfig {
+    let builder = tls_utils::client_config_builder();
+    let verifier = PlatformVerifier::new().with_provider(builder.crypto_provider().clone());
+    builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth()
+}
+
+/// The resolver in `cell`, which is normally set up by [`init`] at startup
+#[cfg(feature = "srv")]
+fn resolver(
+    cell: &'static OnceLock<TokioResolver>,
+    validate: bool,
+) -> Result<&'static TokioResolver, String> {
+    if let Some(resolver) = cell.get() {
+        return Ok(resolver);
+    }
+
+    let config = RESOLVER_CONFIG.get_or_init(ResolverConfig::default);
+    let resolver = build_resolver(config, validate)?;
+    Ok(cell.get_or_init(|| resolver))
+}
+
+#[cfg(feature = "srv")]
+fn build_resolver(config: &ResolverConfig, validate: bool) -> Result<TokioResolver, String> {
+    use hickory_resolver::config::ResolverConfig as UpstreamConfig;
+
+    let mut builder = if config.mode == ResolverMode::System {
+        TokioResolver::builder_tokio()
+            .map_err(|e| format!("could not load system resolver configuration: {e}"))?
+    } else {
+        let upstream = UpstreamConfig::from_parts(None, vec![], name_servers(config));
+        let builder = TokioResolver::builder_with_config(upstream, TokioRuntimeProvider::default());
+        if config.mode.is_encrypted() {
+            builder.with_tls_config(tls_config())
+        } else {
+            builder
+        }
+    };
+    builder.options_mut().validate = validate;
+    builder
+        .build()
+        .map_err(|e| format!("could not build resolver: {e}"))
+}
+
 #[cfg(not(feature = "srv"))]
 pub(crate) async fn resolve_ke(
     addr: &NormalizedAddress,
 ) -> Result<impl Iterator<Item = KeResolutionResult>, std::io::Error> {
-    let lookup_result = lookup_host((addr.server_name.as_str(), addr.port))
+    let lookup_result = lookup_host(&addr.server_name, addr.port)
         .await?
         .map(|addr| KeResolutionResult {
             addr,
@@ -37,30 +193,11 @@ pub(crate) async fn resolve_ke(
 pub(crate) async fn resolve_ke(
     addr: &NormalizedAddress,
 ) -> Result<impl Iterator<Item = KeResolutionResult>, std::io::Error> {
-    // Kludge allowing us to return two types of iterator.
-    enum Either<A, B> {
-        A(A),
-        B(B),
-    }
-
-    impl<A: Iterator<Item = KeResolutionResult>, B: Iterator<Item = KeResolutionResult>> Iterator
-        for Either<A, B>
-    {
-        type Item = KeResolutionResult;
-
-        fn next(&mut self) -> Option<Self::Item> {
-            match self {
-                Either::A(a) => a.next(),
-                Either::B(b) => b.next(),
-            }
-        }
-    }
-
     // First try looking up SRV records
     if let Ok(srv_names) = resolve_srv(format!("_ntske._tcp.{}", addr.server_name)).await {
         let mut result = vec![];
         for name in srv_names.into_iter().map(|v| v.to_ascii()) {
-            if let Ok(lookup) = lookup_host((name.as_str(), 4460)).await {
+            if let Ok(lookup) = lookup_host(&name, 4460).await {
                 result.extend(lookup.map(|addr| KeResolutionResult {
                     addr,
                     srv_record_name: Some(name.clone()),
@@ -73,7 +210,7 @@ pub(crate) async fn resolve_ke(
     }
 
     // Otherwise do a direct name lookup
-    let lookup_result = lookup_host((addr.server_name.as_str(), addr.port))
+    let lookup_result = lookup_host(&addr.server_name, addr.port)
         .await?
         .map(|addr| KeResolutionResult {
             addr,
@@ -85,30 +222,9 @@ pub(crate) async fn resolve_ke(
 
 #[cfg(feature = "srv")]
 async fn resolve_srv<N: IntoName>(name: N) -> Result<Vec<Name>, NetError> {
-    use crate::daemon::exitcode;
     use rand::Rng;
-    use std::process::exit;
-
-    let resolver = RESOLVER.get_or_init(|| {
-        let mut builder = match TokioResolver::builder_tokio() {
-            Ok(builder) => builder,
-            Err(e) => {
-                // Abort when the resolver configuration cannot be loaded
-                // trying anything else is madness when the system we run
-                // on is this broken.
-                tracing::error!("Could not load resolver configuration, aborting: {e}.");
-                exit(exitcode::CONFIG);
-            }
-        };
-        builder.options_mut().validate = true;
-        match builder.build() {
-            Ok(resolver) => resolver,
-            Err(e) => {
-                tracing::error!("Could not build resolver, aborting: {e}.");
-                exit(exitcode::CONFIG);
-            }
-        }
-    });
+
+    let resolver = resolver(&RESOLVER, true)?;
 
     let lookup_result = resolver.srv_lookup(name).await?;
 
@@ -160,3 +276,70 @@ async fn resolve_srv<N: IntoName>(name: N) -> Result<Vec<Name>, NetError> {
 
     Ok(items.into_iter().map(|v| &v.1.target).cloned().collect())
 }
+
+#[cfg(all(test, feature = "srv"))]
+mod tests {
+    use hickory_resolver::config::ProtocolConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_name_servers() {
+        let config = ResolverConfig {
+            mode: ResolverMode::Https,
+            servers: vec![
+                "192.0.2.53".parse().unwrap(),
+                "2001:db8::53".parse().unwrap(),
+            ],
+            port: None,
+            server_name: Some("dns.example.com".into()),
+            path: Some("/resolve".into()),
+        };
+        let servers = name_servers(&config);
+        assert_eq!(servers.len(), 2);
+        assert_eq!(
+            servers[1].ip,
+            "2001:db8::53".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(servers[0].connections.len(), 1);
+        assert_eq!(servers[0].connections[0].port, 443);
+        assert_eq!(
+            servers[0].connections[0].protocol,
+            ProtocolConfig::Https {
+                server_name: "dns.example.com".into(),
+                path: "/resolve".into(),
+            }
+        );
+
+        // Plain DNS falls back to TCP, both on the configured port
+        let config = ResolverConfig {
+            mode: ResolverMode::Dns,
+            servers: vec!["192.0.2.53".parse().unwrap()],
+            port: Some(5353),
+            ..ResolverConfig::default()
+        };
+        let servers = name_servers(&config);
+        let protocols: Vec<_> = servers[0]
+            .connections
+            .iter()
+            .map(|connection| (connection.protocol.clone(), connection.port))
+            .collect();
+        assert_eq!(
+            protocols,
+            [(ProtocolConfig::Udp, 5353), (ProtocolConfig::Tcp, 5353)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_resolver() {
+        for mode in [ResolverMode::Dns, ResolverMode::Tls, ResolverMode::Https] {
+            let config = ResolverConfig {
+                mode,
+                servers: vec!["192.0.2.53".parse().unwrap()],
+                server_name: Some("dns.example.com".into()),
+                ..ResolverConfig::default()
+            };
+            assert!(build_resolver(&config, true).is_ok(), "{mode:?}");
+        }
+    }
+}
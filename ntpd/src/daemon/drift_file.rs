@@ -0,0 +1,134 @@
+//! Persistence of the frequency correction of the clock. After a restart the
+//! clock then starts out running at about the right rate, instead of the
+//! frequency error having to be estimated from scratch.
+//!
+//! The file contains the frequency correction in ppm, in the same format as
+//! the drift file of the reference ntpd implementation. Drift files written
+//! by chrony, which contain the frequency error of the clock followed by its
+//! uncertainty, can be read as well.
+
+use std::{
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use ntp_proto::{NtpClock, TimeSyncController};
+use tracing::{Instrument, Span, debug, info, instrument, warn};
+
+use super::util::write_atomically;
+
+/// Time between writes of the drift file
+const WRITE_INTERVAL: Duration = Duration::from_secs(3600);
+// Frequencies (in ppm) beyond this are taken to be a corrupted file
+const MAXIMUM_FREQUENCY: f64 = 500.0;
+
+/// Parse the contents of a drift file, returning the frequency correction in
+/// ppm
+fn parse(data: &str) -> Option<f64> {
+    let fields: Vec<&str> = data.split_whitespace().collect();
+    let correction = match fields.as_slice() {
+        [frequency] => frequency.parse::<f64>().ok()?,
+        // chrony stores how much the clock runs fast, which is the opposite
+        // of the correction it needs
+        [frequency, _skew] => -frequency.parse::<f64>().ok()?,
+        _ => return None,
+    };
+
+    (correction.is_finite() && correction.abs() <= MAXIMUM_FREQUENCY).then_some(correction)
+}
+
+/// Set the frequency of `clock` to the correction stored in the drift file
+pub(crate) fn restore(path: &Path, clock: &impl NtpClock) {
+    let frequency_ppm = match std::fs::read_to_string(path) {
+        Ok(data) => parse(&data),
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            info!(?path, "No drift file, estimating frequency from scratch");
+            return;
+        }
+        Err(error) => {
+            warn!(?error, ?path, "Could not read drift file");
+            return;
+        }
+    };
+
+    let Some(frequency_ppm) = frequency_ppm else {
+        warn!(?path, "Could not parse drift file");
+        return;
+    };
+
+    match clock.set_frequency(frequency_ppm * 1e-6) {
+        Ok(_) => info!(frequency_ppm, "Restored frequency from drift file"),
+        Err(error) => warn!(?error, "Could not set frequency from drift file"),
+    }
+}
+
+fn store(path: &Path, frequency: f64) -> std::io::Result<()> {
+    write_atomically(path, 0o644, |file| writeln!(file, "{:.3}", frequency * 1e6))
+}
+
+/// Periodically write the frequency correction `controller` settled on to
+/// the drift file
+#[instrument(level = tracing::Level::ERROR, name = "Drift file", skip_all, fields(path = debug(&path)))]
+pub(crate) fn spawn<Controller: TimeSyncController>(path: PathBuf, controller: Arc<Controller>) {
+    tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(WRITE_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately, before there is anything to store
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let Some(frequency) = controller.synchronization_state().0.frequency_correction
+                else {
+                    continue;
+                };
+
+                let store_path = path.clone();
+                let result = tokio::task::spawn_blocking(move || store(&store_path, frequency))
+                    .await
+                    .unwrap_or_else(|error| Err(std::io::Error::other(error)));
+                match result {
+                    Ok(()) => debug!(frequency_ppm = frequency * 1e6, "Wrote drift file"),
+                    Err(error) => warn!(?error, "Could not write drift file"),
+                }
+            }
+        }
+        .instrument(Span::current()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_port;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("12.5\n"), Some(12.5));
+        assert_eq!(parse("-3.250"), Some(-3.25));
+        // chrony format
+        assert_eq!(parse("-12.5 0.031\n"), Some(12.5));
+        assert_eq!(parse("4.0 0.5"), Some(-4.0));
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("fast"), None);
+        assert_eq!(parse("1.0 2.0 3.0"), None);
+        assert_eq!(parse("NaN"), None);
+        assert_eq!(parse("10000"), None);
+    }
+
+    #[test]
+    fn test_store() {
+        let path = std::env::temp_dir().join(format!("ntp-test-drift-{}", alloc_port()));
+
+        store(&path, -17.125e-6).unwrap();
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(data, "-17.125\n");
+        assert_eq!(parse(&data), Some(-17.125));
+
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,304 @@
+//! Source reading the time from gpsd over its JSON protocol. After a WATCH
+//! command, gpsd sends a TOFF report with the time of the serial data of
+//! each second, and a PPS report for each pulse of receivers that have one.
+//! TPV reports tell whether the receiver has a fix, without which its time
+//! is not used.
+
+use ntp_proto::{
+    ClockId, Measurement, NtpDuration, NtpLeapIndicator, OneWaySource, SourceController,
+};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tracing::{Instrument, Span, debug, instrument, warn};
+
+use super::{
+    config::GpsdAddress, measurements_log, ntp_source::SourceChannels,
+    sock_source::precision_exponent, util::convert_unix_timestamp,
+};
+
+// Reports with all satellites in view can get a few kilobytes long, anything
+// beyond this is not a report we are interested in
+const MAX_REPORT_LENGTH: usize = 16 * 1024;
+// Fix modes of 2D and 3D fixes in TPV reports
+const MINIMUM_FIX_MODE: u8 = 2;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "class")]
+enum Report {
+    #[serde(rename = "TPV")]
+    Tpv(FixReport),
+    #[serde(rename = "TOFF")]
+    Toff(TimeReport),
+    #[serde(rename = "PPS")]
+    Pps(TimeReport),
+    #[serde(other)]
+    Other,
+}
+
+impl Report {
+    fn device(&self) -> Option<&str> {
+        match self {
+            Report::Tpv(report) => report.device.as_deref(),
+            Report::Toff(report) | Report::Pps(report) => report.device.as_deref(),
+            Report::Other => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FixReport {
+    device: Option<String>,
+    #[serde(default)]
+    mode: u8,
+}
+
+/// The time according to the receiver at the moment gpsd read our clock
+#[derive(Debug, Deserialize)]
+struct TimeReport {
+    device: Option<String>,
+    real_sec: u64,
+    real_nsec: u32,
+    clock_sec: u64,
+    clock_nsec: u32,
+    /// Precision of the receiver as log2 seconds, if it knows
+    precision: Option<i8>,
+}
+
+/// The command to start receiving reports
+fn watch_command(device: Option<&str>) -> String {
+    // TOFF reports are only sent with PPS reports enabled
+    let mut watch = serde_json::json!({ "enable": true, "json": true, "pps": true });
+    if let Some(device) = device {
+        watch["device"] = device.into();
+    }
+    format!("?WATCH={watch};\n")
+}
+
+/// Turn a TOFF or PPS report into a measurement
+fn report_measurement(
+    report: &TimeReport,
+    pulse: bool,
+    index: ClockId,
+    precision: i8,
+) -> Measurement {
+    let real = convert_unix_timestamp(report.real_sec, report.real_nsec);
+    let clock = convert_unix_timestamp(report.clock_sec, report.clock_nsec);
+    let mut offset = (real - clock).to_seconds();
+    if pulse {
+        // Like for the sock source, gpsd numbers pulses using our clock, so
+        // only the fraction of the second is of use
+        offset -= offset.round();
+    }
+
+    Measurement {
+        sender_id: index,
+        receiver_id: ClockId::SYSTEM,
+        sender_ts: clock + NtpDuration::from_seconds(offset),
+        receiver_ts: clock,
+
+        root_delay: NtpDuration::ZERO,
+        root_dispersion: NtpDuration::ZERO,
+        stratum: 0,
+        leap: NtpLeapIndicator::NoWarning,
+        // Don't trust the source more than configured, but do trust it less
+        // when the receiver itself says so
+        precision: report
+            .precision
+            .map_or(precision, |reported| reported.max(precision)),
+    }
+}
+
+pub(crate) struct GpsdSourceTask<Controller: SourceController> {
+    index: ClockId,
+    address: GpsdAddress,
+    device: Option<String>,
+    pps: bool,
+    precision: i8,
+    channels: SourceChannels,
+    source: OneWaySource<Controller>,
+    fix_mode: u8,
+}
+
+impl<Controller: SourceController> GpsdSourceTask<Controller> {
+    async fn run(&mut self, mut stream: BufReader<TcpStream>) {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match stream.read_until(b'\n', &mut line).await {
+                Ok(0) => {
+                    warn!("gpsd closed the connection, reconnecting");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+                Ok(_) if line.len() > MAX_REPORT_LENGTH => {}
+                Ok(_) => match serde_json::from_slice::<Report>(&line) {
+                    Ok(report) => self.handle_report(&report),
+                    Err(e) => debug!(error = ?e, "Could not parse gpsd report"),
+                },
+                Err(e) => {
+                    warn!(error = ?e, "Could not read from gpsd, reconnecting");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_report(&mut self, report: &Report) {
+        if let Some(device) = &self.device
+            && report.device() != Some(device.as_str())
+        {
+            return;
+        }
+
+        let (report, pulse) = match report {
+            Report::Tpv(fix) => {
+                if (fix.mode >= MINIMUM_FIX_MODE) != (self.fix_mode >= MINIMUM_FIX_MODE) {
+                    debug!(mode = fix.mode, "Fix mode of the receiver changed");
+                }
+                self.fix_mode = fix.mode;
+                return;
+            }
+            Report::Toff(report) => (report, false),
+            Report::Pps(report) if self.pps => (report, true),
+            Report::Pps(_) | Report::Other => return,
+        };
+        debug!("received {:?}", report);
+
+        if self.fix_mode < MINIMUM_FIX_MODE {
+            return;
+        }
+
+        let measurement = report_measurement(report, pulse, self.index, self.precision);
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record("gpsd", &self.address.to_string(), self.index, &measurement);
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source
+                    .observe("gpsd".to_string(), self.address.to_string(), self.index),
+            );
+    }
+
+    async fn connect(address: &GpsdAddress, device: Option<&str>) -> std::io::Result<TcpStream> {
+        let addresses: Vec<_> = address.lookup_host().await?.collect();
+        let mut stream = TcpStream::connect(addresses.as_slice()).await?;
+        stream.write_all(watch_command(device).as_bytes()).await?;
+        Ok(stream)
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "gpsd Source", skip(channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        address: GpsdAddress,
+        device: Option<String>,
+        pps: bool,
+        precision: f64,
+        channels: SourceChannels,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let stream = match Self::connect(&address, device.as_deref()).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!(error = ?e, "Could not connect to gpsd");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
+                let mut process = GpsdSourceTask {
+                    index,
+                    address,
+                    device,
+                    pps,
+                    precision: precision_exponent(precision),
+                    channels,
+                    source,
+                    fix_mode: 0,
+                };
+
+                process.run(BufReader::new(stream)).await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reports() {
+        let report: Report = serde_json::from_str(
+            r#"{"class":"TOFF","device":"/dev/ttyACM0","real_sec":1704067200,"real_nsec":0,"clock_sec":1704067200,"clock_nsec":120000000,"precision":-20}"#,
+        )
+        .unwrap();
+        let Report::Toff(toff) = &report else {
+            panic!("Expected a TOFF report, got {report:?}");
+        };
+        assert_eq!(report.device(), Some("/dev/ttyACM0"));
+        assert_eq!(toff.clock_nsec, 120_000_000);
+        assert_eq!(toff.precision, Some(-20));
+
+        let report: Report = serde_json::from_str(
+            r#"{"class":"TPV","device":"/dev/ttyACM0","mode":3,"time":"2024-01-01T00:00:00.000Z","lat":52.0,"lon":5.0}"#,
+        )
+        .unwrap();
+        assert!(matches!(report, Report::Tpv(FixReport { mode: 3, .. })));
+
+        let report: Report =
+            serde_json::from_str(r#"{"class":"SKY","device":"/dev/ttyACM0","satellites":[]}"#)
+                .unwrap();
+        assert!(matches!(report, Report::Other));
+    }
+
+    #[test]
+    fn test_watch_command() {
+        assert_eq!(
+            watch_command(None),
+            "?WATCH={\"enable\":true,\"json\":true,\"pps\":true};\n"
+        );
+        assert!(watch_command(Some("/dev/ttyS0")).contains("\"device\":\"/dev/ttyS0\""));
+    }
+
+    #[test]
+    fn test_report_measurement() {
+        let report = TimeReport {
+            device: None,
+            real_sec: 1_704_067_200,
+            real_nsec: 0,
+            clock_sec: 1_704_067_200,
+            clock_nsec: 120_000_000,
+            precision: Some(-20),
+        };
+
+        // Serial data, the full offset is used
+        let measurement = report_measurement(&report, false, ClockId::new(), -7);
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset + 0.12).abs() < 1e-6);
+        assert_eq!(measurement.precision, -7);
+
+        // A pulse numbered a second off only contributes the fraction
+        let report = TimeReport {
+            real_sec: 1_704_067_201,
+            precision: Some(-3),
+            ..report
+        };
+        let measurement = report_measurement(&report, true, ClockId::new(), -7);
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset + 0.12).abs() < 1e-6);
+        assert_eq!(measurement.precision, -3);
+    }
+}
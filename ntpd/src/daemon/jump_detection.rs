@@ -0,0 +1,187 @@
+//! Detection of jumps of the clock the daemon didn't make itself, such as
+//! when the system resumes from suspend, the time is set by hand, or a
+//! virtual machine is paused. The time on the clock is compared with the
+//! monotonic clock, which keeps running at a steady pace whatever happens to
+//! the time, and which doesn't run while the system is suspended.
+
+use std::time::{Duration, Instant};
+
+use ntp_proto::{NtpClock, NtpDuration, NtpTimestamp};
+use serde::Serialize;
+use tracing::warn;
+
+use super::{
+    audit::{self, AuditEvent},
+    clock,
+};
+
+// Jumps smaller than this (in seconds) are not detected. Between two checks
+// frequency steering changes the time far less than this.
+const JUMP_THRESHOLD: f64 = 0.1;
+// Time since boot including time spent suspended, on Linux
+const UPTIME_PATH: &str = "/proc/uptime";
+
+/// What caused a jump of the clock, as far as we can tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum JumpCause {
+    /// The system was suspended, during which the monotonic clock stopped
+    Suspend,
+    /// Something else changed the time of the clock
+    Step,
+}
+
+/// A jump of the clock the daemon didn't make itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Jump {
+    /// Size of the jump, in seconds
+    pub offset: f64,
+    pub cause: JumpCause,
+}
+
+impl Jump {
+    /// Write the jump to the log and the audit log
+    pub(crate) fn report(&self) {
+        warn!(
+            offset = self.offset,
+            cause = ?self.cause,
+            "Detected a jump of the clock, starting synchronization over"
+        );
+        audit::record(&AuditEvent::ClockJump {
+            offset: self.offset,
+            cause: self.cause,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+    time: NtpTimestamp,
+    monotonic: Instant,
+    uptime: Option<f64>,
+    // Total of the steps the daemon itself made
+    own_steps: NtpDuration,
+}
+
+impl Reading {
+    fn take(clock: &impl NtpClock) -> Option<Reading> {
+        let own_steps = clock::own_steps();
+        let monotonic = Instant::now();
+        let time = clock.now().ok()?;
+        let uptime = uptime();
+
+        // Don't mistake a step the controller made right now for a jump, the
+        // next reading compares against the previous one instead
+        (clock::own_steps() == own_steps).then_some(Reading {
+            time,
+            monotonic,
+            uptime,
+            own_steps,
+        })
+    }
+}
+
+fn uptime() -> Option<f64> {
+    std::fs::read_to_string(UPTIME_PATH)
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn detect(last: &Reading, current: &Reading) -> Option<Jump> {
+    let elapsed = current
+        .monotonic
+        .checked_duration_since(last.monotonic)
+        .unwrap_or(Duration::ZERO)
+        .as_secs_f64();
+    let offset = (current.time - last.time).to_seconds()
+        - elapsed
+        - (current.own_steps - last.own_steps).to_seconds();
+    if offset.abs() < JUMP_THRESHOLD {
+        return None;
+    }
+
+    let suspended = match (last.uptime, current.uptime) {
+        (Some(last), Some(current)) => current - last - elapsed,
+        _ => 0.0,
+    };
+    let cause = if suspended > JUMP_THRESHOLD {
+        JumpCause::Suspend
+    } else {
+        JumpCause::Step
+    };
+
+    Some(Jump { offset, cause })
+}
+
+/// Checks for jumps of the clock each time it is polled
+#[derive(Debug, Default)]
+pub(crate) struct JumpDetector {
+    last: Option<Reading>,
+}
+
+impl JumpDetector {
+    /// Check whether `clock` jumped since the previous call
+    pub(crate) fn check(&mut self, clock: &impl NtpClock) -> Option<Jump> {
+        let reading = Reading::take(clock)?;
+        let last = self.last.replace(reading)?;
+        detect(&last, &reading)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading_at(
+        start: Instant,
+        time: f64,
+        monotonic: f64,
+        uptime: Option<f64>,
+        own_steps: f64,
+    ) -> Reading {
+        Reading {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(1_000_000, 0)
+                + NtpDuration::from_seconds(time),
+            monotonic: start + Duration::from_secs_f64(monotonic),
+            uptime,
+            own_steps: NtpDuration::from_seconds(own_steps),
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let start = Instant::now();
+        let reading = |time, monotonic, uptime, own_steps| {
+            reading_at(start, time, monotonic, uptime, own_steps)
+        };
+        let last = reading(0.0, 0.0, Some(100.0), 0.0);
+
+        // Time passing normally, with some frequency steering
+        let current = reading(1.0001, 1.0, Some(101.0), 0.0);
+        assert_eq!(detect(&last, &current), None);
+
+        // The daemon stepping the clock itself
+        let current = reading(6.0, 1.0, Some(101.0), 5.0);
+        assert_eq!(detect(&last, &current), None);
+
+        // Someone else setting the clock back
+        let current = reading(-9.0, 1.0, Some(101.0), 0.0);
+        let jump = detect(&last, &current).unwrap();
+        assert!((jump.offset + 10.0).abs() < 1e-6);
+        assert_eq!(jump.cause, JumpCause::Step);
+
+        // An hour of suspend
+        let current = reading(3601.0, 1.0, Some(3701.0), 0.0);
+        let jump = detect(&last, &current).unwrap();
+        assert!((jump.offset - 3600.0).abs() < 1e-6);
+        assert_eq!(jump.cause, JumpCause::Suspend);
+
+        // Without the uptime, a suspend can't be told apart from a step
+        let last = reading(0.0, 0.0, None, 0.0);
+        let current = reading(3601.0, 1.0, None, 0.0);
+        assert_eq!(detect(&last, &current).unwrap().cause, JumpCause::Step);
+    }
+}
@@ -1,32 +1,42 @@
 use std::{
-    io::{BufRead, BufReader},
-    path::Path,
-    sync::Arc,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 use libc::{ECONNABORTED, EMFILE, ENFILE, ENOBUFS, ENOMEM};
-use ntp_proto::{KeyExchangeServer, KeySet};
-use ntp_proto::{NtsServerConfig, tls_utils::Certificate};
+use ntp_proto::{
+    KeyExchangeServer, KeySet, NtsServerCertificate, NtsServerConfig, NtsServerKey,
+    tls_utils::{Certificate, PrivateKey},
+};
 use tokio::{net::TcpListener, task::JoinHandle};
-use tracing::{Instrument, Span, debug, error, instrument};
+use tracing::{Instrument, Span, debug, error, info, instrument, warn};
 
-use super::config::NtsKeConfig;
-use super::exitcode;
+use super::{
+    audit::{self, AuditEvent},
+    config::{NtsKeConfig, PrivateKeyConfig},
+    server::KeyExchangeStats,
+};
+use crate::exitcode;
 
 #[instrument(level = tracing::Level::ERROR, name = "Nts Server", skip_all, fields(address = debug(nts_ke_config.listen)))]
 pub fn spawn(
     nts_ke_config: NtsKeConfig,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    stats: KeyExchangeStats,
 ) -> JoinHandle<std::io::Result<()>> {
     tokio::spawn(
         (async move {
-            let result = run_nts_ke(nts_ke_config, keyset).await;
+            let result = run_nts_ke(nts_ke_config, keyset, stats).await;
 
             match result {
                 Ok(v) => Ok(v),
                 Err(e) => {
                     tracing::error!("Abnormal termination of NTS KE server: {e}");
-                    std::process::exit(exitcode::SOFTWARE)
+                    exitcode::exit(exitcode::SOFTWARE)
                 }
             }
         })
@@ -38,58 +48,504 @@ fn io_error(msg: &str) -> std::io::Error {
     std::io::Error::other(msg)
 }
 
-async fn run_nts_ke(
-    nts_ke_config: NtsKeConfig,
-    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
-) -> std::io::Result<()> {
-    let certificate_chain_file = std::fs::File::open(&nts_ke_config.certificate_chain_path)
-        .map_err(|e| {
-            io_error(&format!(
-                "error reading certificate_chain_path at `{:?}`: {:?}",
-                nts_ke_config.certificate_chain_path, e
-            ))
-        })?;
-
-    let private_key_file = std::fs::File::open(&nts_ke_config.private_key_path).map_err(|e| {
+fn load_certificate_chain(certificate_chain_path: &Path) -> std::io::Result<Vec<Certificate>> {
+    let certificate_chain_file = std::fs::File::open(certificate_chain_path).map_err(|e| {
+        io_error(&format!(
+            "error reading certificate_chain_path at `{certificate_chain_path:?}`: {e:?}"
+        ))
+    })?;
+
+    ntp_proto::tls_utils::pemfile::certs(&mut std::io::BufReader::new(certificate_chain_file))
+        .collect::<std::io::Result<Vec<Certificate>>>()
+}
+
+fn load_private_key(private_key_path: &Path) -> std::io::Result<PrivateKey> {
+    let private_key_file = std::fs::File::open(private_key_path).map_err(|e| {
         io_error(&format!(
-            "error reading key_der_path at `{:?}`: {:?}",
-            nts_ke_config.private_key_path, e
+            "error reading key_der_path at `{private_key_path:?}`: {e:?}"
         ))
     })?;
 
-    let certificate_chain: Vec<Certificate> =
-        ntp_proto::tls_utils::pemfile::certs(&mut std::io::BufReader::new(certificate_chain_file))
-            .collect::<std::io::Result<Vec<Certificate>>>()?;
+    ntp_proto::tls_utils::pemfile::private_key(&mut std::io::BufReader::new(private_key_file))
+}
+
+/// The private key of the default certificate. A key on a token is opened
+/// once, so the token is not logged into again on every reload.
+#[derive(Debug, Clone)]
+enum DefaultKey {
+    File(PathBuf),
+    #[cfg(feature = "pkcs11")]
+    Token(Arc<dyn ntp_proto::tls_utils::SigningKey>),
+}
+
+impl DefaultKey {
+    fn open(nts_ke_config: &NtsKeConfig) -> std::io::Result<Self> {
+        match &nts_ke_config.private_key {
+            PrivateKeyConfig::Path(path) => Ok(DefaultKey::File(path.clone())),
+            #[cfg(feature = "pkcs11")]
+            PrivateKeyConfig::Pkcs11(config) => super::pkcs11::open(config).map(DefaultKey::Token),
+            #[cfg(not(feature = "pkcs11"))]
+            PrivateKeyConfig::Pkcs11(_) => Err(io_error(
+                "a PKCS#11 private key is configured, but ntpd-rs was built without the pkcs11 feature",
+            )),
+        }
+    }
+
+    fn load(&self) -> std::io::Result<NtsServerKey> {
+        match self {
+            DefaultKey::File(path) => Ok(load_private_key(path)?.into()),
+            #[cfg(feature = "pkcs11")]
+            DefaultKey::Token(key) => Ok(NtsServerKey::External(key.clone())),
+        }
+    }
+}
+
+/// Load the default certificate and those selected by server name
+fn load_certificates(
+    nts_ke_config: &NtsKeConfig,
+    default_key: &DefaultKey,
+) -> std::io::Result<(Vec<Certificate>, NtsServerKey, Vec<NtsServerCertificate>)> {
+    let certificate_chain = load_certificate_chain(&nts_ke_config.certificate_chain_path)?;
+    let private_key = default_key.load()?;
+
+    let sni_certificates = nts_ke_config
+        .sni_certificates
+        .iter()
+        .map(|config| {
+            Ok(NtsServerCertificate {
+                server_name: config.server_name.clone(),
+                certificate_chain: load_certificate_chain(&config.certificate_chain_path)?,
+                private_key: load_private_key(&config.private_key_path)?.into(),
+            })
+        })
+        .collect::<std::io::Result<_>>()?;
+
+    Ok((certificate_chain, private_key, sni_certificates))
+}
+
+/// Modification times of all certificate and key files, used to detect when
+/// any of them was replaced
+fn certificate_modification_times(nts_ke_config: &NtsKeConfig) -> Vec<Option<SystemTime>> {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+
+    std::iter::once((
+        nts_ke_config.certificate_chain_path.as_path(),
+        nts_ke_config.private_key_path(),
+    ))
+    .chain(nts_ke_config.sni_certificates.iter().map(|config| {
+        (
+            config.certificate_chain_path.as_path(),
+            Some(config.private_key_path.as_path()),
+        )
+    }))
+    .flat_map(|(chain, key)| [modified(chain), key.and_then(modified)])
+    .collect()
+}
+
+/// Load the certificates again and start presenting them to new clients.
+/// When the new files can't be used the server keeps the certificates it has.
+fn reload_certificates(
+    key_exchange_server: &KeyExchangeServer,
+    nts_ke_config: &NtsKeConfig,
+    default_key: &DefaultKey,
+) {
+    let result = load_certificates(nts_ke_config, default_key).and_then(
+        |(certificate_chain, private_key, sni_certificates)| {
+            key_exchange_server
+                .update_certificates(certificate_chain, private_key, sni_certificates)
+                .map_err(std::io::Error::other)
+        },
+    );
+    match result {
+        Ok(()) => info!("Reloaded NTS KE server certificates"),
+        Err(e) => {
+            warn!(error = ?e, "Could not reload NTS KE server certificates, keeping the current ones");
+        }
+    }
+}
+
+/// Wait until the next periodic check, forever if those are disabled
+async fn certificate_check_due(interval: Option<std::time::Duration>) {
+    match interval {
+        Some(interval) => tokio::time::sleep(interval).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for a hangup signal, forever if we could not listen for it
+async fn hangup_received(hangup: &mut Option<tokio::signal::unix::Signal>) {
+    let Some(stream) = hangup else {
+        return std::future::pending().await;
+    };
+
+    if stream.recv().await.is_none() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Wait for the ACME client to renew the certificate, forever if it isn't used
+async fn certificate_renewed(renewals: &mut Option<tokio::sync::watch::Receiver<bool>>) {
+    let Some(renewals) = renewals else {
+        return std::future::pending().await;
+    };
+
+    if renewals.changed().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Reload the certificates when they changed on disk, checked every
+/// `certificate-reload-interval` seconds, when the daemon receives a hangup
+/// signal, or when they were renewed using ACME, so renewed certificates are
+/// picked up without a restart.
+fn spawn_certificate_reloader(
+    key_exchange_server: Arc<KeyExchangeServer>,
+    nts_ke_config: NtsKeConfig,
+    default_key: DefaultKey,
+    mut acme_renewals: Option<tokio::sync::watch::Receiver<bool>>,
+) -> JoinHandle<()> {
+    tokio::spawn(
+        (async move {
+            let interval = (nts_ke_config.certificate_reload_interval > 0).then(|| {
+                std::time::Duration::from_secs(nts_ke_config.certificate_reload_interval)
+            });
+            let mut hangup =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(stream) => Some(stream),
+                    Err(e) => {
+                        warn!(error = ?e, "Could not listen for hangup signal, NTS KE server certificates will not be reloaded on hangup");
+                        None
+                    }
+                };
+
+            let mut last_modified = certificate_modification_times(&nts_ke_config);
+            loop {
+                let forced = tokio::select! {
+                    () = certificate_check_due(interval) => false,
+                    () = hangup_received(&mut hangup) => true,
+                    () = certificate_renewed(&mut acme_renewals) => true,
+                };
+
+                let modified = certificate_modification_times(&nts_ke_config);
+                if !forced && modified == last_modified {
+                    continue;
+                }
+                // Also remember the new times when loading fails, a fixed
+                // file will have a newer modification time again.
+                last_modified = modified;
+
+                reload_certificates(&key_exchange_server, &nts_ke_config, &default_key);
+            }
+        })
+        .instrument(Span::current()),
+    )
+}
+
+async fn run_nts_ke(
+    nts_ke_config: NtsKeConfig,
+    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    stats: KeyExchangeStats,
+) -> std::io::Result<()> {
+    // A certificate that is obtained using ACME may not be there yet
+    let acme_renewals = match &nts_ke_config.acme {
+        Some(acme_config) => {
+            let private_key_path = nts_ke_config
+                .private_key_path()
+                .ok_or_else(|| io_error("ACME can't be used with a private key on a token"))?;
+            let mut renewals = super::acme::spawn(
+                acme_config.clone(),
+                nts_ke_config.certificate_chain_path.clone(),
+                private_key_path.to_path_buf(),
+            );
+            renewals
+                .wait_for(|available| *available)
+                .await
+                .map_err(std::io::Error::other)?;
+            Some(renewals)
+        }
+        None => None,
+    };
 
-    let private_key =
-        ntp_proto::tls_utils::pemfile::private_key(&mut std::io::BufReader::new(private_key_file))?;
+    let default_key = DefaultKey::open(&nts_ke_config)?;
+    let (certificate_chain, private_key, sni_certificates) =
+        load_certificates(&nts_ke_config, &default_key)?;
 
     let key_exchange_server = KeyExchangeServer::new(NtsServerConfig {
         certificate_chain,
         private_key,
+        sni_certificates,
         accepted_versions: nts_ke_config.accept_ntp_versions.clone(),
         server: nts_ke_config.ntp_server.clone(),
         port: nts_ke_config.ntp_port,
         pool_authentication_tokens: nts_ke_config.accepted_pool_authentication_tokens.clone(),
+        accepted_algorithms: nts_ke_config.accept_aead_algorithms.clone(),
     })
     .map_err(std::io::Error::other)?;
+    let key_exchange_server = Arc::new(key_exchange_server);
+
+    let _reloader = spawn_certificate_reloader(
+        key_exchange_server.clone(),
+        nts_ke_config.clone(),
+        default_key,
+        acme_renewals,
+    );
 
-    run_key_exchange_server(keyset, key_exchange_server, nts_ke_config).await
+    let backends = backends(&nts_ke_config, keyset);
+    run_key_exchange_server(backends, key_exchange_server, nts_ke_config, stats).await
 }
 
-async fn run_key_exchange_server(
+/// NTP server that clients are sent to, with the keys its cookies are made with
+struct Backend {
+    server: Option<String>,
+    port: Option<u16>,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
-    key_exchange_server: KeyExchangeServer,
+}
+
+fn backends(
+    nts_ke_config: &NtsKeConfig,
+    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+) -> Arc<[Backend]> {
+    if nts_ke_config.backends.is_empty() {
+        return Arc::new([Backend {
+            server: nts_ke_config.ntp_server.clone(),
+            port: nts_ke_config.ntp_port,
+            keyset,
+        }]);
+    }
+
+    nts_ke_config
+        .backends
+        .iter()
+        .map(|backend| Backend {
+            server: Some(backend.server.clone()),
+            port: backend.port,
+            keyset: match &backend.key_storage_path {
+                // Cookies are only made with the primary key, so how many
+                // old keys are kept doesn't matter here
+                Some(path) => super::nts_key_provider::spawn_follower(path.clone(), usize::MAX),
+                None => keyset.clone(),
+            },
+        })
+        .collect()
+}
+
+/// Pick the backend for a client, such that a client keeps being sent to the
+/// same backend as long as the configured backends don't change
+fn select_backend(backends: &[Backend], ip: IpAddr) -> &Backend {
+    let mut hasher = DefaultHasher::new();
+    ip.to_canonical().hash(&mut hasher);
+    &backends[(hasher.finish() % backends.len() as u64) as usize]
+}
+
+/// Keep the master key creation time in `stats` up to date, reporting the
+/// oldest primary key of all backends
+fn track_master_key(backends: &Arc<[Backend]>, stats: &KeyExchangeStats) {
+    for backend in backends.iter() {
+        let mut keyset = backend.keyset.clone();
+        let backends = backends.clone();
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            loop {
+                let oldest = backends
+                    .iter()
+                    .map(|backend| backend.keyset.borrow().created())
+                    .min()
+                    .and_then(|created| created.duration_since(std::time::UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                stats.master_key_created.set(oldest.as_secs());
+
+                if keyset.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// How often clients that are no longer limited are forgotten
+const CONNECTION_LIMITER_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::from(burst),
+            last_update: now,
+        }
+    }
+
+    fn refill(&mut self, rate: u32, burst: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * f64::from(rate)).min(f64::from(burst));
+        self.last_update = now;
+    }
+
+    /// Take a token if one is available, a rate of 0 means no limit
+    fn try_take(&mut self, rate: u32, burst: u32, now: Instant) -> bool {
+        if rate == 0 {
+            return true;
+        }
+
+        self.refill(rate, burst, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ClientConnections {
+    open: usize,
+    bucket: TokenBucket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionRejection {
+    TooManyConnections,
+    RateLimited,
+}
+
+/// Limits on the connections of every client and of all of them together,
+/// checked before spending any effort on the TLS handshake
+#[derive(Debug)]
+struct ConnectionLimiter {
+    concurrent_connections_per_ip: usize,
+    handshake_rate: u32,
+    handshake_burst: u32,
+    handshake_rate_per_ip: u32,
+    handshake_burst_per_ip: u32,
+    bucket: TokenBucket,
+    clients: HashMap<IpAddr, ClientConnections>,
+    last_cleanup: Instant,
+}
+
+impl ConnectionLimiter {
+    fn new(config: &NtsKeConfig, now: Instant) -> Self {
+        ConnectionLimiter {
+            concurrent_connections_per_ip: config.concurrent_connections_per_ip,
+            handshake_rate: config.handshake_rate,
+            handshake_burst: config.handshake_burst,
+            handshake_rate_per_ip: config.handshake_rate_per_ip,
+            handshake_burst_per_ip: config.handshake_burst_per_ip,
+            bucket: TokenBucket::new(config.handshake_burst, now),
+            clients: HashMap::new(),
+            last_cleanup: now,
+        }
+    }
+
+    fn try_open(&mut self, ip: IpAddr, now: Instant) -> Result<(), ConnectionRejection> {
+        if now.saturating_duration_since(self.last_cleanup) >= CONNECTION_LIMITER_CLEANUP_INTERVAL {
+            self.cleanup(now);
+        }
+
+        // Clients connecting over IPv4 to an IPv6 socket are limited together
+        // with their plain IPv4 connections
+        let client = self
+            .clients
+            .entry(ip.to_canonical())
+            .or_insert_with(|| ClientConnections {
+                open: 0,
+                bucket: TokenBucket::new(self.handshake_burst_per_ip, now),
+            });
+
+        if client.open >= self.concurrent_connections_per_ip {
+            return Err(ConnectionRejection::TooManyConnections);
+        }
+        // The client's own limit goes first, so that a single client cannot
+        // use up the tokens of everyone else
+        if !client
+            .bucket
+            .try_take(self.handshake_rate_per_ip, self.handshake_burst_per_ip, now)
+            || !self
+                .bucket
+                .try_take(self.handshake_rate, self.handshake_burst, now)
+        {
+            return Err(ConnectionRejection::RateLimited);
+        }
+
+        client.open += 1;
+        Ok(())
+    }
+
+    fn close(&mut self, ip: IpAddr) {
+        if let Some(client) = self.clients.get_mut(&ip.to_canonical()) {
+            client.open = client.open.saturating_sub(1);
+        }
+    }
+
+    /// Forget clients without open connections whose bucket is full again
+    fn cleanup(&mut self, now: Instant) {
+        let (rate, burst) = (self.handshake_rate_per_ip, self.handshake_burst_per_ip);
+        self.clients.retain(|_, client| {
+            client.bucket.refill(rate, burst, now);
+            client.open > 0 || (rate != 0 && client.bucket.tokens < f64::from(burst))
+        });
+        self.last_cleanup = now;
+    }
+}
+
+/// Counts as an open connection of `ip` until dropped
+struct ConnectionGuard {
+    limiter: Arc<Mutex<ConnectionLimiter>>,
+    ip: IpAddr,
+}
+
+impl ConnectionGuard {
+    fn new(
+        limiter: &Arc<Mutex<ConnectionLimiter>>,
+        ip: IpAddr,
+    ) -> Result<Self, ConnectionRejection> {
+        limiter
+            .lock()
+            .expect("Unexpected poisoned mutex")
+            .try_open(ip, Instant::now())?;
+        Ok(ConnectionGuard {
+            limiter: limiter.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Ok(mut limiter) = self.limiter.lock() {
+            limiter.close(self.ip);
+        }
+    }
+}
+
+#[expect(clippy::too_many_lines)]
+async fn run_key_exchange_server(
+    backends: Arc<[Backend]>,
+    key_exchange_server: Arc<KeyExchangeServer>,
     ke_config: NtsKeConfig,
+    stats: KeyExchangeStats,
 ) -> std::io::Result<()> {
     let timeout = std::time::Duration::from_millis(ke_config.key_exchange_timeout_ms);
-    let key_exchange_server = Arc::new(key_exchange_server);
+    let limiter = Arc::new(Mutex::new(ConnectionLimiter::new(
+        &ke_config,
+        Instant::now(),
+    )));
 
     // Long lived permits cannot be reinitialized. This means we do risk running out should error
     // conditions cause some to be lost. However, that is an acceptable risk as this is primarily
     // intended as an optimization, and not critical for functioning of the server.
     let longlivedpermits = Arc::new(tokio::sync::Semaphore::new(ke_config.longlived_connections));
 
+    track_master_key(&backends, &stats);
+
     loop {
         let listener = match TcpListener::bind(&ke_config.listen).await {
             Ok(listener) => listener,
@@ -141,28 +597,91 @@ async fn run_key_exchange_server(
                     break;
                 }
             };
-            let keyset = keyset.borrow().clone();
+            // Dropping the stream closes the connection before any TLS
+            // handshake is done
+            let guard = match ConnectionGuard::new(&limiter, source_addr.ip()) {
+                Ok(guard) => guard,
+                Err(ConnectionRejection::TooManyConnections) => {
+                    debug!(?source_addr, "Too many NTS KE connections from client");
+                    stats.connection_limited_connections.inc();
+                    continue;
+                }
+                Err(ConnectionRejection::RateLimited) => {
+                    debug!(?source_addr, "NTS KE connection rate limited");
+                    stats.rate_limited_connections.inc();
+                    continue;
+                }
+            };
+            stats.accepted_connections.inc();
+            let backend = select_backend(&backends, source_addr.ip());
+            let keyset = backend.keyset.borrow().clone();
+            let server = backend.server.clone();
+            let port = backend.port;
             let key_exchange_server = key_exchange_server.clone();
             let longlivedpermits = longlivedpermits.clone();
+            let handshake_stats = stats.clone();
 
             let fut = async move {
                 let result = key_exchange_server
-                    .handle_connection(stream, &keyset, || {
-                        longlivedpermits.try_acquire_owned().ok()
-                    })
+                    .handle_connection_for_server(
+                        stream,
+                        &keyset,
+                        server.as_deref(),
+                        port,
+                        &handshake_stats,
+                        || longlivedpermits.try_acquire_owned().ok(),
+                    )
                     .await;
                 // make key exchange server and keyset available if this has become a long-lived connection.
                 result.map(|result| result.map(|result| (result, (key_exchange_server, keyset))))
             };
 
+            let server = backend.server.clone();
+            let stats = stats.clone();
             tokio::spawn(async move {
                 match tokio::time::timeout(timeout, fut).await {
-                    Err(_) => tracing::debug!(?source_addr, "NTS KE timed out"),
-                    Ok(Err(err)) => tracing::debug!(?err, ?source_addr, "NTS KE failed"),
-                    Ok(Ok(None)) => tracing::debug!(?source_addr, "NTS KE completed"),
+                    Err(_) => {
+                        tracing::debug!(?source_addr, "NTS KE timed out");
+                        stats.failed_handshakes.inc();
+                        audit::record(&AuditEvent::NtsKeServer {
+                            client: source_addr,
+                            success: false,
+                            error: Some("timed out"),
+                        });
+                    }
+                    Ok(Err(err)) => {
+                        tracing::debug!(?err, ?source_addr, "NTS KE failed");
+                        stats.failed_handshakes.inc();
+                        audit::record(&AuditEvent::NtsKeServer {
+                            client: source_addr,
+                            success: false,
+                            error: Some(&err.to_string()),
+                        });
+                    }
+                    Ok(Ok(None)) => {
+                        tracing::debug!(?source_addr, "NTS KE completed");
+                        stats.completed_handshakes.inc();
+                        audit::record(&AuditEvent::NtsKeServer {
+                            client: source_addr,
+                            success: true,
+                            error: None,
+                        });
+                    }
                     Ok(Ok(Some(((longlived_permit, io), (key_exchange_server, keyset))))) => {
+                        stats.completed_handshakes.inc();
+                        audit::record(&AuditEvent::NtsKeServer {
+                            client: source_addr,
+                            success: true,
+                            error: None,
+                        });
                         if let Err(err) = key_exchange_server
-                            .handle_longterm(io, || keyset.clone())
+                            .handle_longterm_for_server(
+                                io,
+                                || keyset.clone(),
+                                server.as_deref(),
+                                port,
+                                &stats,
+                            )
                             .await
                         {
                             tracing::debug!(?err, ?source_addr, "Long term NTS KE failed");
@@ -172,72 +691,226 @@ async fn run_key_exchange_server(
                         drop(longlived_permit);
                     }
                 }
+                drop(guard);
                 drop(permit);
             });
         }
     }
 }
 
-pub(crate) fn certificates_from_file(path: &Path) -> std::io::Result<Vec<Certificate>> {
-    let file = std::fs::File::open(path)?;
-    let reader = BufReader::new(file);
-
-    certificates_from_bufread(reader)
-}
-
-fn certificates_from_bufread(mut reader: impl BufRead) -> std::io::Result<Vec<Certificate>> {
-    ntp_proto::tls_utils::pemfile::certs(&mut reader).collect()
-}
-
 #[cfg(test)]
 mod tests {
-    use std::{io::Cursor, net::SocketAddr, path::PathBuf};
+    use std::{
+        io::{BufReader, Cursor},
+        net::SocketAddr,
+    };
 
-    use ntp_proto::KeySetProvider;
-    use ntp_proto::{KeyExchangeClient, NtpVersion, NtsClientConfig};
+    use ntp_proto::{
+        AeadAlgorithm, KeyExchangeClient, KeySetProvider, NtpVersion, NtsCertificatePins,
+        NtsClientConfig,
+    };
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpStream;
 
-    use crate::test::alloc_port;
+    use crate::{
+        daemon::{certificates::certificates_from_bufread, config::NtsKeBackendConfig},
+        test::alloc_port,
+    };
 
     use super::*;
 
     #[test]
-    fn nos_nl_pem() {
-        let input = include_bytes!("../../testdata/certificates/nos-nl.pem");
-        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
+    fn load_sni_certificates() {
+        let mut nts_ke_config = NtsKeConfig {
+            certificate_chain_path: PathBuf::from("test-keys/end.fullchain.pem"),
+            private_key: PrivateKeyConfig::Path(PathBuf::from("test-keys/end.key")),
+            sni_certificates: vec![crate::daemon::config::NtsKeCertificateConfig {
+                server_name: "time.example.com".into(),
+                certificate_chain_path: PathBuf::from("test-keys/end.fullchain.pem"),
+                private_key_path: PathBuf::from("test-keys/end.key"),
+            }],
+            acme: None,
+            accepted_pool_authentication_tokens: vec![],
+            key_exchange_timeout_ms: 1000,
+            certificate_reload_interval: 60,
+            concurrent_connections: 1,
+            longlived_connections: 0,
+            concurrent_connections_per_ip: 16,
+            handshake_rate: 0,
+            handshake_burst: 0,
+            handshake_rate_per_ip: 0,
+            handshake_burst_per_ip: 0,
+            listen: "0.0.0.0:4460".parse().unwrap(),
+            ntp_port: None,
+            ntp_server: None,
+            backends: vec![],
+            accept_ntp_versions: vec![NtpVersion::V4],
+            accept_aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+        };
 
-        assert_eq!(certificates.len(), 1);
+        let default_key = DefaultKey::open(&nts_ke_config).unwrap();
+        let (certificate_chain, _, sni_certificates) =
+            load_certificates(&nts_ke_config, &default_key).unwrap();
+        assert_eq!(certificate_chain.len(), 2);
+        assert_eq!(sni_certificates.len(), 1);
+        assert_eq!(sni_certificates[0].server_name, "time.example.com");
+        assert_eq!(certificate_modification_times(&nts_ke_config).len(), 4);
+
+        nts_ke_config.sni_certificates[0].private_key_path =
+            PathBuf::from("test-keys/does-not-exist.key");
+        assert!(load_certificates(&nts_ke_config, &default_key).is_err());
+        assert_eq!(certificate_modification_times(&nts_ke_config)[3], None);
     }
 
     #[test]
-    fn nos_nl_chain_pem() {
-        let input = include_bytes!("../../testdata/certificates/nos-nl-chain.pem");
-        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
+    fn token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, start);
+
+        assert!(bucket.try_take(1, 2, start));
+        assert!(bucket.try_take(1, 2, start));
+        assert!(!bucket.try_take(1, 2, start));
 
-        assert_eq!(certificates.len(), 3);
+        // Refills at the given rate, up to the burst size
+        assert!(!bucket.try_take(1, 2, start + Duration::from_millis(500)));
+        assert!(bucket.try_take(1, 2, start + Duration::from_secs(1)));
+        assert!(bucket.try_take(1, 2, start + Duration::from_secs(10)));
+        assert!(bucket.try_take(1, 2, start + Duration::from_secs(10)));
+        assert!(!bucket.try_take(1, 2, start + Duration::from_secs(10)));
+
+        // A rate of 0 disables the limit
+        assert!(bucket.try_take(0, 0, start));
+    }
+
+    fn limiter_config(
+        concurrent_connections_per_ip: usize,
+        handshake_rate: u32,
+        handshake_rate_per_ip: u32,
+    ) -> NtsKeConfig {
+        NtsKeConfig {
+            certificate_chain_path: PathBuf::from("test-keys/end.fullchain.pem"),
+            private_key: PrivateKeyConfig::Path(PathBuf::from("test-keys/end.key")),
+            sni_certificates: vec![],
+            acme: None,
+            accepted_pool_authentication_tokens: vec![],
+            key_exchange_timeout_ms: 1000,
+            certificate_reload_interval: 60,
+            concurrent_connections: 512,
+            longlived_connections: 0,
+            concurrent_connections_per_ip,
+            handshake_rate,
+            handshake_burst: 3,
+            handshake_rate_per_ip,
+            handshake_burst_per_ip: 2,
+            listen: "0.0.0.0:4460".parse().unwrap(),
+            ntp_port: None,
+            ntp_server: None,
+            backends: vec![],
+            accept_ntp_versions: vec![NtpVersion::V4],
+            accept_aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+        }
+    }
+
+    #[test]
+    fn connection_limiter_per_ip() {
+        let now = Instant::now();
+        let mut limiter = ConnectionLimiter::new(&limiter_config(2, 0, 0), now);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(limiter.try_open(a, now), Ok(()));
+        assert_eq!(limiter.try_open(a, now), Ok(()));
+        assert_eq!(
+            limiter.try_open(a, now),
+            Err(ConnectionRejection::TooManyConnections)
+        );
+        // The same client connecting over IPv6
+        assert_eq!(
+            limiter.try_open("::ffff:192.0.2.1".parse().unwrap(), now),
+            Err(ConnectionRejection::TooManyConnections)
+        );
+        assert_eq!(limiter.try_open(b, now), Ok(()));
+
+        limiter.close(a);
+        assert_eq!(limiter.try_open(a, now), Ok(()));
+    }
+
+    #[test]
+    fn connection_limiter_rate() {
+        let now = Instant::now();
+        let mut limiter = ConnectionLimiter::new(&limiter_config(16, 1, 1), now);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        // Each client gets its own burst, until the global one is used up
+        assert_eq!(limiter.try_open(a, now), Ok(()));
+        assert_eq!(limiter.try_open(a, now), Ok(()));
+        assert_eq!(
+            limiter.try_open(a, now),
+            Err(ConnectionRejection::RateLimited)
+        );
+        assert_eq!(limiter.try_open(b, now), Ok(()));
+        assert_eq!(
+            limiter.try_open(b, now),
+            Err(ConnectionRejection::RateLimited)
+        );
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.try_open(a, later), Ok(()));
     }
 
     #[test]
-    fn parse_private_keys() {
-        let input = include_bytes!("../../test-keys/end.key");
-        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+    fn connection_limiter_cleanup() {
+        let now = Instant::now();
+        let mut limiter = ConnectionLimiter::new(&limiter_config(16, 0, 1), now);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(limiter.try_open(a, now), Ok(()));
+        assert_eq!(limiter.try_open(b, now), Ok(()));
+        limiter.close(b);
 
-        let input = include_bytes!("../../test-keys/testca.key");
-        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+        // Clients are kept while they have open connections
+        limiter.cleanup(now + CONNECTION_LIMITER_CLEANUP_INTERVAL);
+        assert_eq!(limiter.clients.len(), 1);
+        assert!(limiter.clients.contains_key(&a));
+    }
 
-        // openssl does no longer seem to want to generate this format
-        // so we use https://github.com/rustls/pemfile/blob/main/tests/data/rsa1024.pkcs1.pem
-        let input = include_bytes!("../../test-keys/rsa_key.pem");
-        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+    #[tokio::test]
+    async fn key_exchange_connection_limit_per_ip() {
+        let port = alloc_port();
 
-        // openssl ecparam -name prime256v1 -genkey -noout -out ec_key.pem
-        let input = include_bytes!("../../test-keys/ec_key.pem");
-        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+        let provider = KeySetProvider::new(1);
+        let (_sender, keyset) = tokio::sync::watch::channel(provider.get());
+        let nts_ke_config = NtsKeConfig {
+            listen: SocketAddr::new("0.0.0.0".parse().unwrap(), port),
+            ..limiter_config(1, 0, 0)
+        };
+
+        let stats = KeyExchangeStats::default();
+        let _join_handle = spawn(nts_ke_config, keyset, stats.clone());
+
+        // give the server some time to make the port available
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let _blocker = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        // The second connection is closed without a handshake
+        let mut second = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut buf = vec![];
+        tokio::time::timeout(
+            std::time::Duration::from_millis(750),
+            second.read_to_end(&mut buf),
+        )
+        .await
+        .unwrap()
+        .ok();
+        assert!(buf.is_empty());
 
-        // openssl genpkey -algorithm EC -out pkcs8_key.pem -pkeyopt ec_paramgen_curve:prime256v1
-        let input = include_bytes!("../../test-keys/pkcs8_key.pem");
-        let _ = ntp_proto::tls_utils::pemfile::private_key(&mut input.as_slice()).unwrap();
+        assert_eq!(stats.accepted_connections.get(), 1);
+        assert_eq!(stats.connection_limited_connections.get(), 1);
+        assert_eq!(stats.rate_limited_connections.get(), 0);
     }
 
     #[tokio::test]
@@ -253,18 +926,28 @@ mod tests {
         let (_sender, keyset) = tokio::sync::watch::channel(keyset);
         let nts_ke_config = NtsKeConfig {
             certificate_chain_path: PathBuf::from("test-keys/end.fullchain.pem"),
-            private_key_path: PathBuf::from("test-keys/end.key"),
+            private_key: PrivateKeyConfig::Path(PathBuf::from("test-keys/end.key")),
+            sni_certificates: vec![],
+            acme: None,
             accepted_pool_authentication_tokens: vec![],
             key_exchange_timeout_ms: 10000,
+            certificate_reload_interval: 60,
             concurrent_connections: 1,
             longlived_connections: 0,
+            concurrent_connections_per_ip: 16,
+            handshake_rate: 0,
+            handshake_burst: 0,
+            handshake_rate_per_ip: 0,
+            handshake_burst_per_ip: 0,
             listen: SocketAddr::new("0.0.0.0".parse().unwrap(), port),
             ntp_port: None,
             ntp_server: None,
+            backends: vec![],
             accept_ntp_versions: vec![NtpVersion::V4],
+            accept_aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
         };
 
-        let _join_handle = spawn(nts_ke_config, keyset);
+        let _join_handle = spawn(nts_ke_config, keyset, KeyExchangeStats::default());
 
         // give the server some time to make the port available
         tokio::time::sleep(std::time::Duration::from_millis(20)).await;
@@ -290,6 +973,10 @@ mod tests {
                         .unwrap()
                         .into(),
                     protocol_version: ntp_proto::ProtocolVersion::V4,
+                    identity: None,
+                    algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                    pins: NtsCertificatePins::default(),
+                    session_resumption: true,
                 })
                 .unwrap();
                 let io = TcpStream::connect(("localhost", port)).await.unwrap();
@@ -312,6 +999,10 @@ mod tests {
                         .unwrap()
                         .into(),
                     protocol_version: ntp_proto::ProtocolVersion::V4,
+                    identity: None,
+                    algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                    pins: NtsCertificatePins::default(),
+                    session_resumption: true,
                 })
                 .unwrap();
                 let io = TcpStream::connect(("localhost", port)).await.unwrap();
@@ -324,6 +1015,7 @@ mod tests {
 
         assert_eq!(result.remote, "localhost");
         assert_eq!(result.port, 123);
+        assert!(!result.remote_negotiated);
     }
 
     #[tokio::test]
@@ -339,18 +1031,28 @@ mod tests {
         let (_sender, keyset) = tokio::sync::watch::channel(keyset);
         let nts_ke_config = NtsKeConfig {
             certificate_chain_path: PathBuf::from("test-keys/end.fullchain.pem"),
-            private_key_path: PathBuf::from("test-keys/end.key"),
+            private_key: PrivateKeyConfig::Path(PathBuf::from("test-keys/end.key")),
+            sni_certificates: vec![],
+            acme: None,
             accepted_pool_authentication_tokens: vec![],
             key_exchange_timeout_ms: 1000,
+            certificate_reload_interval: 60,
             concurrent_connections: 512,
             longlived_connections: 5,
+            concurrent_connections_per_ip: 16,
+            handshake_rate: 0,
+            handshake_burst: 0,
+            handshake_rate_per_ip: 0,
+            handshake_burst_per_ip: 0,
             listen: SocketAddr::new("0.0.0.0".parse().unwrap(), port),
             ntp_port: Some(568),
             ntp_server: Some("jantje".into()),
+            backends: vec![],
             accept_ntp_versions: vec![NtpVersion::V4],
+            accept_aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
         };
 
-        let _join_handle = spawn(nts_ke_config, keyset);
+        let _join_handle = spawn(nts_ke_config, keyset, KeyExchangeStats::default());
 
         // give the server some time to make the port available
         tokio::time::sleep(std::time::Duration::from_millis(20)).await;
@@ -362,6 +1064,10 @@ mod tests {
                     .unwrap()
                     .into(),
                 protocol_version: ntp_proto::ProtocolVersion::V4,
+                identity: None,
+                algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pins: NtsCertificatePins::default(),
+                session_resumption: true,
             })
             .unwrap();
             let io = TcpStream::connect(("localhost", port)).await.unwrap();
@@ -372,5 +1078,166 @@ mod tests {
 
         assert_eq!(result.remote, "jantje");
         assert_eq!(result.port, 568);
+        assert!(result.remote_negotiated);
+    }
+
+    #[tokio::test]
+    async fn key_exchange_stats() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let port = alloc_port();
+
+        let provider = KeySetProvider::new(1);
+        let created = provider.get().created();
+
+        let (_sender, keyset) = tokio::sync::watch::channel(provider.get());
+        let nts_ke_config = NtsKeConfig {
+            certificate_chain_path: PathBuf::from("test-keys/end.fullchain.pem"),
+            private_key: PrivateKeyConfig::Path(PathBuf::from("test-keys/end.key")),
+            sni_certificates: vec![],
+            acme: None,
+            accepted_pool_authentication_tokens: vec![],
+            key_exchange_timeout_ms: 1000,
+            certificate_reload_interval: 60,
+            concurrent_connections: 512,
+            longlived_connections: 0,
+            concurrent_connections_per_ip: 16,
+            handshake_rate: 0,
+            handshake_burst: 0,
+            handshake_rate_per_ip: 0,
+            handshake_burst_per_ip: 0,
+            listen: SocketAddr::new("0.0.0.0".parse().unwrap(), port),
+            ntp_port: None,
+            ntp_server: None,
+            backends: vec![],
+            accept_ntp_versions: vec![NtpVersion::V4],
+            accept_aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+        };
+
+        let stats = KeyExchangeStats::default();
+        let _join_handle = spawn(nts_ke_config, keyset, stats.clone());
+
+        // give the server some time to make the port available
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let ca = include_bytes!("../../test-keys/testca.pem");
+        let kex = KeyExchangeClient::new(&NtsClientConfig {
+            certificates: certificates_from_bufread(BufReader::new(Cursor::new(ca)))
+                .unwrap()
+                .into(),
+            protocol_version: ntp_proto::ProtocolVersion::V4,
+            identity: None,
+            algorithms: vec![AeadAlgorithm::AeadAes128GcmSiv],
+            pins: NtsCertificatePins::default(),
+            session_resumption: true,
+        })
+        .unwrap();
+        let io = TcpStream::connect(("localhost", port)).await.unwrap();
+        kex.exchange_keys(io, "localhost".into(), []).await.unwrap();
+
+        // Not a TLS handshake
+        let mut garbage = TcpStream::connect(("localhost", port)).await.unwrap();
+        garbage.write_all(&[0; 64]).await.unwrap();
+        let mut buf = vec![];
+        let _ = garbage.read_to_end(&mut buf).await;
+
+        // give the server some time to finish both connections
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        assert_eq!(stats.accepted_connections.get(), 2);
+        assert_eq!(stats.completed_handshakes.get(), 1);
+        assert_eq!(stats.failed_handshakes.get(), 1);
+        assert_eq!(stats.cookies_issued.get(), 8);
+        assert_eq!(stats.algorithms.aes_128_gcm_siv.get(), 1);
+        assert_eq!(stats.algorithms.aes_siv_cmac_512.get(), 0);
+        assert_eq!(
+            stats.master_key_created.get(),
+            created
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        );
+    }
+
+    #[test]
+    fn select_backend_per_client() {
+        let (_sender, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let nts_ke_config = NtsKeConfig {
+            backends: (1..=4)
+                .map(|i| NtsKeBackendConfig {
+                    server: format!("ntp{i}.example.com"),
+                    port: None,
+                    key_storage_path: None,
+                })
+                .collect(),
+            ..limiter_config(16, 0, 0)
+        };
+        let backends = backends(&nts_ke_config, keyset);
+        assert_eq!(backends.len(), 4);
+
+        let ipv4: IpAddr = "192.0.2.1".parse().unwrap();
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        let selected = select_backend(&backends, ipv4);
+        assert!(std::ptr::eq(selected, select_backend(&backends, ipv4)));
+        assert!(std::ptr::eq(selected, select_backend(&backends, mapped)));
+
+        // Clients are spread over all backends
+        let mut used = std::collections::HashSet::new();
+        for i in 0..=255u8 {
+            let ip = IpAddr::from([198, 51, 100, i]);
+            used.insert(select_backend(&backends, ip).server.clone());
+        }
+        assert_eq!(used.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn key_exchange_roundtrip_with_backend() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        let port = alloc_port();
+        let key_storage_path = std::env::temp_dir().join(format!("ntp-test-backend-keyset-{port}"));
+        let backend_keys = KeySetProvider::new(1);
+        backend_keys
+            .store(&mut std::fs::File::create(&key_storage_path).unwrap())
+            .unwrap();
+
+        let (_sender, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+        let nts_ke_config = NtsKeConfig {
+            listen: SocketAddr::new("0.0.0.0".parse().unwrap(), port),
+            backends: vec![NtsKeBackendConfig {
+                server: "2001:db8::123".into(),
+                port: Some(1123),
+                key_storage_path: Some(key_storage_path.to_str().unwrap().into()),
+            }],
+            ..limiter_config(16, 0, 0)
+        };
+
+        let _join_handle = spawn(nts_ke_config, keyset, KeyExchangeStats::default());
+
+        // give the server some time to make the port available
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let ca = include_bytes!("../../test-keys/testca.pem");
+        let kex = KeyExchangeClient::new(&NtsClientConfig {
+            certificates: certificates_from_bufread(BufReader::new(Cursor::new(ca)))
+                .unwrap()
+                .into(),
+            protocol_version: ntp_proto::ProtocolVersion::V4,
+            identity: None,
+            algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+            pins: NtsCertificatePins::default(),
+            session_resumption: true,
+        })
+        .unwrap();
+        let io = TcpStream::connect(("localhost", port)).await.unwrap();
+        let result = kex.exchange_keys(io, "localhost".into(), []).await.unwrap();
+
+        assert_eq!(result.remote, "2001:db8::123");
+        assert_eq!(result.port, 1123);
+        assert!(result.remote_negotiated);
+
+        std::fs::remove_file(key_storage_path).unwrap();
     }
 }
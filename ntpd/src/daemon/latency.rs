@@ -0,0 +1,233 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use ntp_proto::{NtpClock, NtpDuration, NtpTimestamp};
+use timestamped_socket::socket::connect_address;
+use tokio::{net::UdpSocket, sync::watch};
+use tracing::{Instrument, Span, debug, instrument, warn};
+
+use super::{config::TimestampMode, util::convert_net_timestamp};
+
+// How often we remeasure the latencies
+const MEASUREMENT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(64);
+// Number of loopback packets exchanged per measurement
+const SAMPLES_PER_MEASUREMENT: usize = 9;
+// Time after which we give up on a single loopback packet
+const SAMPLE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+// Samples above this (about 8ms) are most likely the result of the system
+// being otherwise occupied, and are not representative.
+const MAX_LATENCY: NtpDuration = NtpDuration::from_exponent(-7);
+
+/// Estimated latency of userspace (software) timestamps with respect to
+/// the moment the packet actually passes through the network stack.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SoftwareLatency {
+    /// Time between taking the timestamp and the packet leaving the kernel
+    pub send: NtpDuration,
+    /// Time between the packet arriving in the kernel and taking the timestamp
+    pub recv: NtpDuration,
+}
+
+impl SoftwareLatency {
+    /// Correct a software timestamp taken just before sending a packet
+    pub(crate) fn compensate_send(self, timestamp: NtpTimestamp) -> NtpTimestamp {
+        timestamp + self.send
+    }
+
+    /// Correct a software timestamp taken just after receiving a packet
+    pub(crate) fn compensate_recv(self, timestamp: NtpTimestamp) -> NtpTimestamp {
+        timestamp - self.recv
+    }
+}
+
+/// Periodically measure software timestamping latency by exchanging packets
+/// over the loopback interface, comparing userspace timestamps against the
+/// kernel timestamps of the same packets.
+#[instrument(level = tracing::Level::ERROR, name = "Latency estimator", skip_all)]
+pub(crate) fn spawn<C: NtpClock + Sync>(clock: C) -> watch::Receiver<SoftwareLatency> {
+    let (sender, receiver) = watch::channel(SoftwareLatency::default());
+
+    tokio::spawn(
+        (async move {
+            loop {
+                match measure(&clock).await {
+                    Ok(Some(latency)) => {
+                        debug!(
+                            send = latency.send.to_seconds(),
+                            recv = latency.recv.to_seconds(),
+                            "Updated software timestamping latency"
+                        );
+                        if sender.send(latency).is_err() {
+                            // Nobody is interested anymore
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        debug!("No usable samples for software timestamping latency");
+                    }
+                    Err(error) => {
+                        warn!(?error, "Could not measure software timestamping latency");
+                    }
+                }
+
+                tokio::time::sleep(MEASUREMENT_INTERVAL).await;
+            }
+        })
+        .instrument(Span::current()),
+    );
+
+    receiver
+}
+
+async fn measure<C: NtpClock>(clock: &C) -> std::io::Result<Option<SoftwareLatency>> {
+    let now = || {
+        clock
+            .now()
+            .map_err(|e| std::io::Error::other(e.to_string()))
+    };
+
+    let echo = UdpSocket::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).await?;
+    let mut socket = connect_address(
+        echo.local_addr()?,
+        TimestampMode::KernelAll.as_general_mode(),
+    )?;
+
+    // Echo in a separate task, so that we are already waiting on the socket
+    // when the reply arrives, just like when waiting for a server response.
+    let echo_task = tokio::spawn(async move {
+        let mut buf = [0u8; 48];
+        while let Ok((size, remote)) = echo.recv_from(&mut buf).await {
+            if echo.send_to(&buf[..size], remote).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut send_samples = Vec::with_capacity(SAMPLES_PER_MEASUREMENT);
+    let mut recv_samples = Vec::with_capacity(SAMPLES_PER_MEASUREMENT);
+    let mut roundtrip_samples = Vec::with_capacity(SAMPLES_PER_MEASUREMENT);
+
+    let mut result = Ok(());
+    for _ in 0..SAMPLES_PER_MEASUREMENT {
+        let before_send = match now() {
+            Ok(ts) => ts,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        let kernel_send = match socket.send(&[0u8; 48]).await {
+            Ok(ts) => ts,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+
+        let mut buf = [0u8; 48];
+        let recv_result = match tokio::time::timeout(SAMPLE_TIMEOUT, socket.recv(&mut buf)).await {
+            Ok(Ok(recv_result)) => recv_result,
+            Ok(Err(e)) => {
+                result = Err(e);
+                break;
+            }
+            Err(_) => continue,
+        };
+        let after_recv = match now() {
+            Ok(ts) => ts,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+
+        if let Some(kernel_send) = kernel_send {
+            send_samples.push(convert_net_timestamp(kernel_send) - before_send);
+        }
+        if let Some(kernel_recv) = recv_result.timestamp {
+            recv_samples.push(after_recv - convert_net_timestamp(kernel_recv));
+        }
+        roundtrip_samples.push(after_recv - before_send);
+    }
+
+    echo_task.abort();
+    result?;
+
+    Ok(estimate(send_samples, recv_samples, roundtrip_samples))
+}
+
+fn median(mut samples: Vec<NtpDuration>) -> Option<NtpDuration> {
+    samples.retain(|v| *v >= NtpDuration::ZERO && *v <= MAX_LATENCY);
+    samples.sort_unstable();
+    samples.get(samples.len() / 2).copied()
+}
+
+fn estimate(
+    send_samples: Vec<NtpDuration>,
+    recv_samples: Vec<NtpDuration>,
+    roundtrip_samples: Vec<NtpDuration>,
+) -> Option<SoftwareLatency> {
+    // Without kernel timestamps we can only estimate the total latency. A
+    // roundtrip passes the network stack twice in both directions, so we
+    // attribute a quarter of it to each direction.
+    let fallback =
+        median(roundtrip_samples).map(|v| NtpDuration::from_seconds(v.to_seconds() / 4.0));
+
+    let send = median(send_samples).or(fallback)?;
+    let recv = median(recv_samples).or(fallback)?;
+
+    Some(SoftwareLatency { send, recv })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(v: f64) -> NtpDuration {
+        NtpDuration::from_seconds(v * 1e-3)
+    }
+
+    #[test]
+    fn test_compensation() {
+        let latency = SoftwareLatency {
+            send: ms(0.1),
+            recv: ms(0.2),
+        };
+        let base = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+
+        assert_eq!(latency.compensate_send(base) - base, ms(0.1));
+        assert_eq!(base - latency.compensate_recv(base), ms(0.2));
+        assert_eq!(
+            SoftwareLatency::default().compensate_send(base),
+            SoftwareLatency::default().compensate_recv(base)
+        );
+    }
+
+    #[test]
+    fn test_estimate() {
+        let latency = estimate(
+            vec![ms(0.01), ms(0.02), ms(0.03)],
+            vec![ms(0.05), ms(3.0), ms(0.04)],
+            vec![ms(0.5)],
+        )
+        .unwrap();
+        assert_eq!(latency.send, ms(0.02));
+        assert_eq!(latency.recv, ms(0.05));
+
+        // Outliers and negative values are ignored
+        let latency = estimate(
+            vec![ms(0.01), ms(100.0), ms(100.0), -ms(1.0)],
+            vec![ms(0.04)],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(latency.send, ms(0.01));
+        assert_eq!(latency.recv, ms(0.04));
+
+        // Fall back to the roundtrip when no kernel timestamps are available
+        let latency = estimate(vec![], vec![], vec![ms(0.4), ms(0.4), ms(0.8)]).unwrap();
+        assert!((latency.send.to_seconds() - 0.1e-3).abs() < 1e-9);
+        assert!((latency.recv.to_seconds() - 0.1e-3).abs() < 1e-9);
+
+        assert_eq!(estimate(vec![], vec![], vec![]), None);
+    }
+}
@@ -1,38 +1,148 @@
-use std::{net::IpAddr, sync::Arc};
+//! Tracking of the network configuration of the system. The addresses of the
+//! local interfaces are picked up by the change detector, which listens on
+//! rtnetlink on Linux. Default routes can change without any address
+//! changing, for example when a router fails over, so the routing tables are
+//! checked periodically as well.
+
+use std::{collections::BTreeSet, net::IpAddr, sync::Arc, time::Duration};
 
 use timestamped_socket::interface::{ChangeDetector, interfaces};
 use tokio::sync::watch;
 
-pub fn spawn() -> std::io::Result<watch::Receiver<Arc<[IpAddr]>>> {
-    let mut change_listener = ChangeDetector::new()?;
-    let local_ips: Arc<[IpAddr]> = interfaces()?
+/// Time between checks of the default routes
+const ROUTE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const IPV4_ROUTES_PATH: &str = "/proc/net/route";
+const IPV6_ROUTES_PATH: &str = "/proc/net/ipv6_route";
+
+/// Interface and gateway of the default routes in the contents of
+/// `/proc/net/route` and `/proc/net/ipv6_route`
+fn parse_default_routes(ipv4: &str, ipv6: &str) -> BTreeSet<(String, String)> {
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+    let ipv4_routes = ipv4.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [interface, "00000000", gateway, _, _, _, _, "00000000", ..] => {
+                Some(((*interface).to_owned(), (*gateway).to_owned()))
+            }
+            _ => None,
+        }
+    });
+
+    // Destination prefix-length source prefix-length next-hop metric
+    // refcount use flags interface
+    let ipv6_routes = ipv6.lines().filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [destination, "00", _, _, next_hop, _, _, _, _, interface]
+                if destination.bytes().all(|digit| digit == b'0') && *interface != "lo" =>
+            {
+                Some(((*interface).to_owned(), (*next_hop).to_owned()))
+            }
+            _ => None,
+        }
+    });
+
+    ipv4_routes.chain(ipv6_routes).collect()
+}
+
+fn default_routes() -> BTreeSet<(String, String)> {
+    // Without these files, as on systems other than Linux, there are no
+    // route changes to detect
+    let ipv4 = std::fs::read_to_string(IPV4_ROUTES_PATH).unwrap_or_default();
+    let ipv6 = std::fs::read_to_string(IPV6_ROUTES_PATH).unwrap_or_default();
+    parse_default_routes(&ipv4, &ipv6)
+}
+
+fn local_ips() -> std::io::Result<Arc<[IpAddr]>> {
+    Ok(interfaces()?
         .values()
         .flat_map(timestamped_socket::interface::InterfaceData::ips)
-        .collect();
+        .collect())
+}
 
-    let (writer, reader) = watch::channel(local_ips);
+/// Start tracking the network configuration. Returns the IP addresses of the
+/// local interfaces, and a receiver that is notified whenever the addresses
+/// or default routes change.
+#[expect(clippy::type_complexity)]
+pub fn spawn() -> std::io::Result<(watch::Receiver<Arc<[IpAddr]>>, watch::Receiver<()>)> {
+    let mut change_listener = ChangeDetector::new()?;
+    let (writer, reader) = watch::channel(local_ips()?);
+    let (change_writer, change_reader) = watch::channel(());
 
     tokio::spawn(async move {
+        let mut routes = default_routes();
+        let mut route_check = tokio::time::interval(ROUTE_CHECK_INTERVAL);
+        route_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
         loop {
-            change_listener.wait_for_change().await;
-            match interfaces() {
-                Ok(interfaces) => {
-                    let _ = writer.send(
-                        interfaces
-                            .values()
-                            .flat_map(timestamped_socket::interface::InterfaceData::ips)
-                            .collect(),
-                    );
-                }
-                Err(e) => {
-                    tracing::warn!(
-                        "Could not get new list of which ip addresses the interfaces in the system have: {}",
-                        e
-                    );
+            let mut changed = false;
+
+            tokio::select! {
+                _ = change_listener.wait_for_change() => {
+                    match local_ips() {
+                        Ok(ips) => {
+                            changed = writer.send_if_modified(|current| {
+                                let modified = *current != ips;
+                                *current = ips;
+                                modified
+                            });
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "Could not get new list of which ip addresses the interfaces in the system have: {}",
+                                e
+                            );
+                        }
+                    }
                 }
+                _ = route_check.tick() => {}
+            }
+
+            let new_routes = default_routes();
+            if new_routes != routes {
+                tracing::debug!(routes = ?new_routes, "Default routes changed");
+                routes = new_routes;
+                changed = true;
+            }
+
+            if changed {
+                let _ = change_writer.send(());
             }
         }
     });
 
-    Ok(reader)
+    Ok((reader, change_reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_routes() {
+        let ipv4 = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+        let ipv6 = "\
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000064 00000001 00000000 00000003 wlan0
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 wlan0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200 lo
+";
+
+        let routes = parse_default_routes(ipv4, ipv6);
+        assert_eq!(
+            routes,
+            BTreeSet::from([
+                ("wlan0".to_string(), "0101A8C0".to_string()),
+                (
+                    "wlan0".to_string(),
+                    "fe800000000000000000000000000001".to_string()
+                ),
+            ])
+        );
+
+        assert!(parse_default_routes("", "").is_empty());
+    }
 }
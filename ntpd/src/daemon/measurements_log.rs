@@ -0,0 +1,369 @@
+//! Log of every measurement of the sources, for analysis outside of the
+//! daemon. Each line records the raw timestamps of an exchange with a
+//! source, the offset and delay derived from them, and whether the
+//! measurement was used by the filter of the source and the source selected
+//! for synchronization. The log is rotated once it grows too large.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use ntp_proto::{ClockId, NtpDuration, NtpLeapIndicator, NtpTimestamp, ObservableMeasurement};
+use serde::Serialize;
+use tracing::warn;
+
+use super::config::{MeasurementsLogFormat, ObservabilityConfig};
+
+static MEASUREMENTS_LOG: OnceLock<MeasurementsLog> = OnceLock::new();
+
+const CSV_HEADER: &str = "source,address,id,local_send,remote_receive,remote_transmit,\
+    local_receive,offset,delay,root_delay,root_dispersion,stratum,leap,accepted,selected\n";
+
+/// A single line of the log
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    source: &'a str,
+    address: &'a str,
+    id: ClockId,
+    local_send: Option<String>,
+    remote_receive: Option<String>,
+    remote_transmit: String,
+    local_receive: String,
+    offset: f64,
+    delay: Option<f64>,
+    root_delay: f64,
+    root_dispersion: f64,
+    stratum: u8,
+    leap: NtpLeapIndicator,
+    accepted: bool,
+    selected: bool,
+}
+
+impl<'a> Entry<'a> {
+    fn new(
+        source: &'a str,
+        address: &'a str,
+        id: ClockId,
+        measurement: &ObservableMeasurement,
+        selected: bool,
+    ) -> Self {
+        let time = |timestamp: NtpTimestamp| timestamp.to_string();
+        Entry {
+            source,
+            address,
+            id,
+            local_send: measurement.local_send.map(time),
+            remote_receive: measurement.remote_receive.map(time),
+            remote_transmit: time(measurement.remote_transmit),
+            local_receive: time(measurement.local_receive),
+            offset: measurement.offset.to_seconds(),
+            delay: measurement.delay.map(NtpDuration::to_seconds),
+            root_delay: measurement.root_delay.to_seconds(),
+            root_dispersion: measurement.root_dispersion.to_seconds(),
+            stratum: measurement.stratum,
+            leap: measurement.leap,
+            accepted: measurement.accepted,
+            selected,
+        }
+    }
+
+    fn to_line(&self, format: MeasurementsLogFormat) -> Option<String> {
+        match format {
+            MeasurementsLogFormat::Jsonl => match serde_json::to_string(self) {
+                Ok(mut line) => {
+                    line.push('\n');
+                    Some(line)
+                }
+                Err(error) => {
+                    warn!(?error, "Could not serialize measurement");
+                    None
+                }
+            },
+            MeasurementsLogFormat::Csv => Some(self.to_csv()),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{:?},{},{}\n",
+            csv_field(self.source),
+            csv_field(self.address),
+            self.id,
+            self.local_send.as_deref().unwrap_or_default(),
+            self.remote_receive.as_deref().unwrap_or_default(),
+            self.remote_transmit,
+            self.local_receive,
+            self.offset,
+            self.delay
+                .map(|delay| delay.to_string())
+                .unwrap_or_default(),
+            self.root_delay,
+            self.root_dispersion,
+            self.stratum,
+            self.leap,
+            self.accepted,
+            self.selected,
+        )
+    }
+}
+
+/// Quote a field if it contains characters with a meaning in CSV
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+struct MeasurementsLog {
+    path: PathBuf,
+    format: MeasurementsLogFormat,
+    max_size: u64,
+    rotations: u32,
+    file: Mutex<LogFile>,
+    // Sources currently used for synchronization
+    selected: Mutex<Vec<ClockId>>,
+}
+
+fn open_file(path: &Path, format: MeasurementsLogFormat) -> std::io::Result<LogFile> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path)?;
+    let mut size = file.metadata()?.len();
+    if size == 0 && format == MeasurementsLogFormat::Csv {
+        file.write_all(CSV_HEADER.as_bytes())?;
+        size = CSV_HEADER.len() as u64;
+    }
+    Ok(LogFile { file, size })
+}
+
+/// Path of the `index`th older log file
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    rotated.into()
+}
+
+impl MeasurementsLog {
+    fn new(
+        path: PathBuf,
+        format: MeasurementsLogFormat,
+        max_size: u64,
+        rotations: u32,
+    ) -> std::io::Result<Self> {
+        Ok(MeasurementsLog {
+            file: Mutex::new(open_file(&path, format)?),
+            path,
+            format,
+            max_size,
+            rotations,
+            selected: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn write(&self, entry: &Entry<'_>) {
+        let Some(line) = entry.to_line(self.format) else {
+            return;
+        };
+
+        let mut file = self.file.lock().expect("Unexpected poisoned mutex");
+        if let Err(error) = file.file.write_all(line.as_bytes()) {
+            warn!(?error, "Could not write to measurements log");
+            return;
+        }
+        file.size += line.len() as u64;
+
+        if self.max_size > 0 && file.size >= self.max_size {
+            match self.rotate() {
+                Ok(new_file) => *file = new_file,
+                Err(error) => warn!(?error, "Could not rotate measurements log"),
+            }
+        }
+    }
+
+    /// Move the current log out of the way, keeping at most `rotations` old
+    /// logs, and open a new one
+    fn rotate(&self) -> std::io::Result<LogFile> {
+        if self.rotations == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.rotations).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    std::fs::rename(from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        open_file(&self.path, self.format)
+    }
+}
+
+/// Set up the measurements log configured in `config`, if any
+pub(crate) fn init(config: &ObservabilityConfig) -> std::io::Result<()> {
+    let Some(path) = &config.measurements_log_path else {
+        return Ok(());
+    };
+
+    let log = MeasurementsLog::new(
+        path.clone(),
+        config.measurements_log_format,
+        config.measurements_log_max_size,
+        config.measurements_log_rotations,
+    )?;
+    let _ = MEASUREMENTS_LOG.set(log);
+    Ok(())
+}
+
+/// Update which sources are used for synchronization, which is recorded
+/// along with their measurements
+pub(crate) fn update_selection(used_sources: &[ClockId]) {
+    if let Some(log) = MEASUREMENTS_LOG.get() {
+        let mut selected = log.selected.lock().expect("Unexpected poisoned mutex");
+        selected.clear();
+        selected.extend_from_slice(used_sources);
+    }
+}
+
+/// Write `measurement` of the source `source` to the measurements log, if
+/// there is one
+pub(crate) fn record(
+    source: &str,
+    address: &str,
+    id: ClockId,
+    measurement: &ObservableMeasurement,
+) {
+    if let Some(log) = MEASUREMENTS_LOG.get() {
+        let selected = log
+            .selected
+            .lock()
+            .expect("Unexpected poisoned mutex")
+            .contains(&id);
+        log.write(&Entry::new(source, address, id, measurement, selected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_port;
+
+    fn measurement(offset: f64) -> ObservableMeasurement {
+        let base = NtpTimestamp::from_seconds_nanos_since_ntp_era(3_913_056_000, 0);
+        ObservableMeasurement {
+            local_send: Some(base),
+            remote_receive: Some(base + NtpDuration::from_seconds(offset + 0.01)),
+            remote_transmit: base + NtpDuration::from_seconds(offset + 0.0125),
+            local_receive: base + NtpDuration::from_seconds(0.0225),
+            offset: NtpDuration::from_seconds(offset),
+            delay: Some(NtpDuration::from_seconds(0.02)),
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            stratum: 1,
+            leap: NtpLeapIndicator::NoWarning,
+            accepted: true,
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let path = std::env::temp_dir().join(format!("ntp-test-measurements-{}", alloc_port()));
+        let log = MeasurementsLog::new(path.clone(), MeasurementsLogFormat::Jsonl, 0, 0).unwrap();
+        let id = ClockId::new();
+        log.write(&Entry::new(
+            "pool.example.com",
+            "192.0.2.1:123",
+            id,
+            &measurement(0.5),
+            true,
+        ));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let value: serde_json::Value = serde_json::from_str(contents.trim_end()).unwrap();
+        assert_eq!(value["source"], "pool.example.com");
+        assert_eq!(value["address"], "192.0.2.1:123");
+        assert_eq!(value["local_send"], "2024-01-01T00:00:00Z");
+        assert!((value["offset"].as_f64().unwrap() - 0.5).abs() < 1e-6);
+        assert_eq!(value["leap"], "NoWarning");
+        assert_eq!(value["selected"], true);
+        std::fs::remove_file(&path).unwrap();
+
+        let log = MeasurementsLog::new(path.clone(), MeasurementsLogFormat::Csv, 0, 0).unwrap();
+        let mut sock = measurement(-0.25);
+        sock.local_send = None;
+        sock.remote_receive = None;
+        sock.delay = None;
+        log.write(&Entry::new(
+            "GPSd socket",
+            "/run/chrony.sock",
+            id,
+            &sock,
+            false,
+        ));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(lines.next().unwrap(), CSV_HEADER.trim_end());
+        let fields: Vec<_> = lines.next().unwrap().split(',').collect();
+        assert_eq!(fields.len(), 15);
+        assert_eq!(fields[0], "GPSd socket");
+        assert_eq!(fields[3], "");
+        assert!((fields[7].parse::<f64>().unwrap() + 0.25).abs() < 1e-6);
+        assert_eq!(fields[8], "");
+        assert_eq!(fields[14], "false");
+        assert!(lines.next().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("example.com"), "example.com");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_rotate() {
+        let path = std::env::temp_dir().join(format!("ntp-test-measurements-{}", alloc_port()));
+        let log = MeasurementsLog::new(path.clone(), MeasurementsLogFormat::Csv, 1, 2).unwrap();
+        let id = ClockId::new();
+
+        // Every line exceeds the maximum size, so each ends up in a file of
+        // its own, and only the two most recent old files are kept
+        for offset in [1.0, 2.0, 3.0, 4.0] {
+            log.write(&Entry::new(
+                "source",
+                "address",
+                id,
+                &measurement(offset),
+                false,
+            ));
+        }
+
+        let offset_in = |path: &Path| {
+            let contents = std::fs::read_to_string(path).unwrap();
+            let line = contents.lines().nth(1).unwrap().to_owned();
+            line.split(',').nth(7).unwrap().parse::<f64>().unwrap()
+        };
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), CSV_HEADER);
+        assert!((offset_in(&rotated_path(&path, 1)) - 4.0).abs() < 1e-6);
+        assert!((offset_in(&rotated_path(&path, 2)) - 3.0).abs() < 1e-6);
+        assert!(!rotated_path(&path, 3).exists());
+
+        for file in [path.clone(), rotated_path(&path, 1), rotated_path(&path, 2)] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+}
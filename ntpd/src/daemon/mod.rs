@@ -1,18 +1,53 @@
+#[cfg(feature = "server")]
+mod acme;
+mod audit;
+#[cfg(feature = "client")]
+mod broadcast_source;
+mod certificates;
 mod clock;
 pub mod config;
+pub mod control;
+#[cfg(feature = "client")]
 mod dns;
+mod drift_file;
+#[cfg(feature = "client")]
+mod gpsd_source;
+mod jump_detection;
+#[cfg(feature = "server")]
 pub mod keyexchange;
+#[cfg(feature = "client")]
+mod latency;
 mod local_ip_provider;
+#[cfg(feature = "client")]
+mod measurements_log;
+#[cfg(feature = "client")]
+mod nmea_source;
+#[cfg(feature = "client")]
 mod ntp_source;
 pub mod nts_key_provider;
 pub mod observer;
+#[cfg(feature = "client")]
+mod phc_source;
+mod phc_steering;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
 #[cfg(feature = "pps")]
 mod pps_source;
+#[cfg(feature = "client")]
+mod ptp_source;
+#[cfg(feature = "client")]
+mod roughtime_source;
 mod server;
+#[cfg(feature = "client")]
+mod shm_source;
+#[cfg(feature = "client")]
 mod sock_source;
 pub mod sockets;
+#[cfg(feature = "client")]
 pub mod spawn;
+mod state_file;
 mod system;
+mod temperature;
 pub mod tracing;
 mod util;
 
@@ -20,15 +55,16 @@ use std::{error::Error, io::IsTerminal, path::Path};
 
 use ::tracing::info;
 pub use config::Config;
-use ntp_proto::{KalmanClockController, TimeSyncControllerWrapper};
+use ntp_proto::{KalmanClockController, PiClockController, TimeSyncControllerWrapper};
 pub use observer::ObservableState;
 pub use system::spawn;
 use tokio::runtime::Builder;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use config::NtpDaemonOptions;
+use config::{ClockAlgorithm, NtpDaemonOptions};
 
 use crate::daemon::tracing::LogReloadTaskStarter;
+use crate::exitcode;
 use crate::notify::notify_ready;
 
 use self::tracing::LogLevel;
@@ -36,7 +72,13 @@ use self::tracing::LogLevel;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub fn main() -> Result<(), Box<dyn Error>> {
-    let options = NtpDaemonOptions::try_parse_from(std::env::args())?;
+    let options = match NtpDaemonOptions::try_parse_from(std::env::args()) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(exitcode::USAGE);
+        }
+    };
 
     #[cfg(feature = "openssl")]
     rustls_openssl::default_provider()
@@ -114,6 +156,26 @@ pub(crate) fn initialize_logging_parse_config(
     (config, task_starter)
 }
 
+/// Keep listening for hangup signals for the lifetime of the daemon. The
+/// default action of SIGHUP is to terminate, and the log files, audit log and
+/// key exchange servers that reload on it may not be configured. Must be
+/// called from within the tokio runtime.
+fn ignore_hangup() {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    ::tracing::debug!("Received hangup signal");
+                }
+            });
+        }
+        Err(error) => {
+            ::tracing::warn!(?error, "Could not listen for hangup signal");
+        }
+    }
+}
+
+#[expect(clippy::too_many_lines)]
 fn run(options: &NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
     let (config, task_starter) = initialize_logging_parse_config(
         options.log_level,
@@ -121,17 +183,37 @@ fn run(options: &NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
         Application::Deamon,
     );
 
-    let runtime = if config.servers.is_empty() && config.nts_ke.is_empty() {
-        Builder::new_current_thread().enable_all().build()?
-    } else {
-        Builder::new_multi_thread().enable_all().build()?
-    };
+    let runtime =
+        if !cfg!(feature = "server") || (config.servers.is_empty() && config.nts_ke.is_empty()) {
+            Builder::new_current_thread().enable_all().build()?
+        } else {
+            Builder::new_multi_thread().enable_all().build()?
+        };
 
     runtime.block_on(async move {
+        ignore_hangup();
+
         if let Some(task_starter) = task_starter {
             task_starter.start();
         }
 
+        if let Err(error) = audit::init(&config.observability) {
+            ::tracing::error!(?error, "Could not open audit log");
+            std::process::exit(exitcode::CANTCREAT);
+        }
+
+        #[cfg(feature = "client")]
+        if let Err(error) = measurements_log::init(&config.observability) {
+            ::tracing::error!(?error, "Could not open measurements log");
+            std::process::exit(exitcode::CANTCREAT);
+        }
+
+        #[cfg(feature = "client")]
+        if let Err(error) = dns::init(&config.resolver) {
+            ::tracing::error!(%error, "Invalid resolver configuration");
+            std::process::exit(exitcode::CONFIG);
+        }
+
         // give the user a warning that we use the command line option
         if config.observability.log_level.is_some() && options.log_level.is_some() {
             info!("Log level override from command line arguments is active");
@@ -144,6 +226,8 @@ fn run(options: &NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
         // we always generate the keyset (even if NTS is not used)
         let keyset = nts_key_provider::spawn(config.keyset).await;
 
+        let symmetric_keys = config.legacy_auth.load_keys(&config.symmetric_keys)?;
+
         #[cfg(feature = "hardware-timestamping")]
         let clock_config = config.clock;
 
@@ -152,48 +236,77 @@ fn run(options: &NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
 
         ::tracing::debug!("Configuration loaded, spawning daemon jobs");
         let clock = clock_config.clock;
-        let (main_loop_handle, channels) =
-            spawn::<TimeSyncControllerWrapper<KalmanClockController<_>>>(
-                config.synchronization.synchronization_base,
-                config.synchronization.algorithm,
-                config.source_defaults,
-                clock_config,
-                &config.sources,
-                &config.servers,
-                keyset.clone(),
-            )
-            .await?;
-
-        for nts_ke_config in config.nts_ke {
-            let _join_handle = keyexchange::spawn(nts_ke_config, keyset.clone());
-        }
+        let (main_loop_handle, channels) = match config.synchronization.clock_algorithm {
+            ClockAlgorithm::Kalman => {
+                spawn::<TimeSyncControllerWrapper<KalmanClockController<_>>>(
+                    config.synchronization.synchronization_base,
+                    config.synchronization.algorithm,
+                    config.synchronization.source_options(),
+                    config.synchronization.temperature,
+                    config.synchronization.drift_file,
+                    config.synchronization.state_file,
+                    config.source_defaults,
+                    clock_config,
+                    &config.sources,
+                    &config.servers,
+                    keyset.clone(),
+                    symmetric_keys,
+                )
+                .await?
+            }
+            ClockAlgorithm::Pi => {
+                spawn::<TimeSyncControllerWrapper<PiClockController<_>>>(
+                    config.synchronization.synchronization_base,
+                    config.synchronization.pi_algorithm,
+                    config.synchronization.source_options(),
+                    config.synchronization.temperature,
+                    config.synchronization.drift_file,
+                    config.synchronization.state_file,
+                    config.source_defaults,
+                    clock_config,
+                    &config.sources,
+                    &config.servers,
+                    keyset.clone(),
+                    symmetric_keys,
+                )
+                .await?
+            }
+        };
+
+        #[cfg(feature = "server")]
+        let key_exchange_servers = config
+            .nts_ke
+            .into_iter()
+            .map(|nts_ke_config| {
+                let state = observer::ObservableKeyExchangeServerState {
+                    address: nts_ke_config.listen,
+                    stats: server::KeyExchangeStats::default(),
+                };
+                let _join_handle =
+                    keyexchange::spawn(nts_ke_config, keyset.clone(), state.stats.clone());
+                state
+            })
+            .collect();
+        #[cfg(not(feature = "server"))]
+        let key_exchange_servers = vec![];
+
+        phc_steering::spawn(&config.phc_steering, &channels.system_snapshot_receiver);
 
         observer::spawn(
             &config.observability,
             channels.source_snapshots,
+            channels.spawners_reader,
             channels.server_data_receiver,
             channels.system_snapshot_receiver,
+            key_exchange_servers,
             clock,
         );
 
+        #[cfg(feature = "client")]
+        control::spawn(&config.observability, channels.control_sender);
+
         let _ = notify_ready().await;
 
         Ok(main_loop_handle.await??)
     })
 }
-
-pub(crate) mod exitcode {
-    /// An internal software error has been detected.  This
-    /// should be limited to non-operating system related
-    /// errors as possible.
-    pub const SOFTWARE: i32 = 70;
-
-    /// You did not have sufficient permission to perform
-    /// the operation.  This is not intended for file system
-    /// problems, which should use `NOINPUT` or `CANTCREAT`,
-    /// but rather for higher level permissions.
-    pub const NOPERM: i32 = 77;
-
-    /// Something was found in an unconfigured or misconfigured state.
-    pub const CONFIG: i32 = 78;
-}
@@ -0,0 +1,443 @@
+//! Reference clock reading the time from a GPS receiver that sends NMEA
+//! sentences over a serial port. Each sentence names the second at whose
+//! start it was sent, and arrives a roughly constant delay after that start,
+//! which is configured per sentence. The time is only as good as that delay
+//! is stable, so this is typically combined with the PPS output of the
+//! receiver.
+
+use std::path::{Path, PathBuf};
+
+use ntp_proto::{
+    ClockId, Measurement, NtpClock, NtpDuration, NtpLeapIndicator, NtpTimestamp, OneWaySource,
+    SourceController,
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{Instrument, Span, debug, error, instrument, warn};
+
+use crate::exitcode;
+
+use super::{
+    config::{NmeaOffsets, NmeaSentence},
+    measurements_log,
+    ntp_source::SourceChannels,
+    sock_source::precision_exponent,
+    util::convert_unix_timestamp,
+};
+
+// NMEA limits sentences to 82 characters, anything much longer is garbage,
+// for example due to a wrong baud rate
+const MAX_SENTENCE_LENGTH: usize = 256;
+const SECONDS_PER_DAY: u32 = 86400;
+// A recent midnight (2024-01-01), from which the day is determined for
+// sentences without a date
+const MIDNIGHT_ANCHOR: u64 = 1_704_067_200;
+
+/// The time carried by a sentence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SentenceTime {
+    sentence: NmeaSentence,
+    /// Days since 1970-01-01, absent for sentences without a date
+    days: Option<u64>,
+    /// Time of day
+    seconds: u32,
+    nanos: u32,
+}
+
+impl SentenceTime {
+    /// The time as a timestamp. Sentences without a date are placed on the
+    /// day that puts them closest to `now`.
+    fn timestamp(&self, now: NtpTimestamp) -> NtpTimestamp {
+        if let Some(days) = self.days {
+            return convert_unix_timestamp(
+                days * u64::from(SECONDS_PER_DAY) + u64::from(self.seconds),
+                self.nanos,
+            );
+        }
+
+        let (seconds, nanos) =
+            (now - convert_unix_timestamp(MIDNIGHT_ANCHOR, 0)).as_seconds_nanos();
+        let now_of_day = f64::from(seconds.rem_euclid(86400)) + f64::from(nanos) * 1e-9;
+        let of_day = f64::from(self.seconds) + f64::from(self.nanos) * 1e-9;
+        let day = f64::from(SECONDS_PER_DAY);
+        let difference = (of_day - now_of_day + day / 2.0).rem_euclid(day) - day / 2.0;
+        now + NtpDuration::from_seconds(difference)
+    }
+}
+
+/// Parse a field consisting of only decimal digits
+fn digits(field: &str) -> Option<u32> {
+    if field.is_empty() || !field.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    field.parse().ok()
+}
+
+/// Parse a time of day in the form `hhmmss.sss`, into seconds and nanoseconds
+fn parse_time_of_day(field: &str) -> Option<(u32, u32)> {
+    let (whole, fraction) = field.split_once('.').unwrap_or((field, ""));
+    if whole.len() != 6 || fraction.len() > 9 {
+        return None;
+    }
+
+    let hours = digits(&whole[0..2])?;
+    let minutes = digits(&whole[2..4])?;
+    // Allow for leap seconds
+    let seconds = digits(&whole[4..6])?;
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        digits(fraction)? * 10u32.pow(9 - fraction.len() as u32)
+    };
+
+    Some((hours * 3600 + minutes * 60 + seconds, nanos))
+}
+
+/// Days between 1970-01-01 and the given date
+fn days_since_epoch(year: u32, month: u32, day: u32) -> Option<u64> {
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Count years from March, so the leap day is at the end of the year
+    let year = u64::from(if month <= 2 { year - 1 } else { year });
+    let day_of_year = (153 * u64::from((month + 9) % 12) + 2) / 5 + u64::from(day) - 1;
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + day_of_year;
+    // Days from 0000-03-01 to 1970-01-01
+    Some(days - 719_468)
+}
+
+/// Check the checksum of a sentence, returning its fields
+fn sentence_fields(line: &str) -> Option<Vec<&str>> {
+    let (body, checksum) = line.trim_end().strip_prefix('$')?.split_once('*')?;
+    let checksum = u8::from_str_radix(checksum, 16).ok()?;
+    if body.bytes().fold(0, |sum, byte| sum ^ byte) != checksum {
+        return None;
+    }
+    Some(body.split(',').collect())
+}
+
+/// Extract the time from an RMC, GGA or ZDA sentence from any talker. Returns
+/// `None` for other sentences, and for those without a valid time.
+fn parse_sentence(line: &str) -> Option<SentenceTime> {
+    let fields = sentence_fields(line)?;
+    // The first two characters identify the talker, e.g. GP for GPS or GN
+    // for multiple satellite systems
+    let kind = fields.first()?.get(2..)?;
+
+    let (sentence, time, days) = match (kind, fields.as_slice()) {
+        ("RMC", [_, time, "A", _, _, _, _, _, _, date, ..]) if date.len() == 6 => {
+            let days = days_since_epoch(
+                2000 + digits(&date[4..6])?,
+                digits(&date[2..4])?,
+                digits(&date[0..2])?,
+            )?;
+            (NmeaSentence::Rmc, time, Some(days))
+        }
+        // Fix quality 0 means there is no fix
+        ("GGA", [_, time, _, _, _, _, quality, ..]) if digits(quality)? != 0 => {
+            (NmeaSentence::Gga, time, None)
+        }
+        ("ZDA", [_, time, day, month, year, ..]) => {
+            let days = days_since_epoch(digits(year)?, digits(month)?, digits(day)?)?;
+            (NmeaSentence::Zda, time, Some(days))
+        }
+        _ => return None,
+    };
+
+    let (seconds, nanos) = parse_time_of_day(time)?;
+    Some(SentenceTime {
+        sentence,
+        days,
+        seconds,
+        nanos,
+    })
+}
+
+/// Turn a sentence received at local time `received` into a measurement
+fn sentence_measurement(
+    time: &SentenceTime,
+    offsets: &NmeaOffsets,
+    received: NtpTimestamp,
+    index: ClockId,
+    precision: i8,
+) -> Measurement {
+    Measurement {
+        sender_id: index,
+        receiver_id: ClockId::SYSTEM,
+        sender_ts: time.timestamp(received) + NtpDuration::from_seconds(offsets.get(time.sentence)),
+        receiver_ts: received,
+
+        root_delay: NtpDuration::ZERO,
+        root_dispersion: NtpDuration::ZERO,
+        stratum: 0,
+        leap: NtpLeapIndicator::NoWarning,
+        precision,
+    }
+}
+
+/// Set the baud rate of the serial port, and turn off any processing of
+/// the data by the terminal driver
+fn configure_port(path: &Path, baud_rate: u32) -> std::io::Result<()> {
+    let status = std::process::Command::new("stty")
+        .arg("-F")
+        .arg(path)
+        .arg(baud_rate.to_string())
+        .args(["raw", "-echo"])
+        .status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("stty failed: {status}")));
+    }
+    Ok(())
+}
+
+pub(crate) struct NmeaSourceTask<C: 'static + NtpClock + Send, Controller: SourceController> {
+    index: ClockId,
+    clock: C,
+    path: PathBuf,
+    sentences: Vec<NmeaSentence>,
+    offsets: NmeaOffsets,
+    precision: i8,
+    channels: SourceChannels,
+    source: OneWaySource<Controller>,
+    // Time of day of the last sentence used, as only the first sentence of
+    // each second is used
+    last_time: Option<(u32, u32)>,
+}
+
+impl<C, Controller: SourceController> NmeaSourceTask<C, Controller>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
+    async fn run(&mut self, mut port: BufReader<tokio::fs::File>) {
+        let mut line = Vec::with_capacity(MAX_SENTENCE_LENGTH);
+        loop {
+            line.clear();
+            match port.read_until(b'\n', &mut line).await {
+                Ok(0) => {
+                    warn!("Serial port closed, reopening it");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+                Ok(_) => {
+                    let received = match self.clock.now() {
+                        Ok(time) => time,
+                        Err(e) => {
+                            error!(error = ?e, "There was an error retrieving the current time");
+                            exitcode::exit(exitcode::NOPERM);
+                        }
+                    };
+
+                    if line.len() <= MAX_SENTENCE_LENGTH
+                        && let Ok(line) = std::str::from_utf8(&line)
+                    {
+                        self.handle_sentence(line, received);
+                    }
+                }
+                Err(e) => {
+                    warn!(error = ?e, "Could not read from serial port, reopening it");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_sentence(&mut self, line: &str, received: NtpTimestamp) {
+        let Some(time) = parse_sentence(line) else {
+            return;
+        };
+        debug!("received {:?}", time);
+
+        if !self.sentences.contains(&time.sentence)
+            || self.last_time == Some((time.seconds, time.nanos))
+        {
+            return;
+        }
+        self.last_time = Some((time.seconds, time.nanos));
+
+        let measurement =
+            sentence_measurement(&time, &self.offsets, received, self.index, self.precision);
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record(
+                "NMEA device",
+                &self.path.display().to_string(),
+                self.index,
+                &measurement,
+            );
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source.observe(
+                    "NMEA device".to_string(),
+                    self.path.display().to_string(),
+                    self.index,
+                ),
+            );
+    }
+
+    #[expect(clippy::too_many_arguments)]
+    #[instrument(level = tracing::Level::ERROR, name = "NMEA Source", skip(clock, channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        path: PathBuf,
+        baud_rate: u32,
+        sentences: Vec<NmeaSentence>,
+        offsets: NmeaOffsets,
+        precision: f64,
+        clock: C,
+        channels: SourceChannels,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let port_path = path.clone();
+                let port = match tokio::task::spawn_blocking(move || {
+                    configure_port(&port_path, baud_rate)?;
+                    std::fs::File::open(&port_path)
+                })
+                .await
+                {
+                    Ok(Ok(port)) => port,
+                    Ok(Err(e)) => {
+                        warn!(error = ?e, "Could not open serial port");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                    Err(e) => {
+                        error!(error = ?e, "Could not open serial port");
+                        return;
+                    }
+                };
+
+                let mut process = NmeaSourceTask {
+                    index,
+                    clock,
+                    path,
+                    sentences,
+                    offsets,
+                    precision: precision_exponent(precision),
+                    channels,
+                    source,
+                    last_time: None,
+                };
+
+                process
+                    .run(BufReader::new(tokio::fs::File::from_std(port)))
+                    .await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(body: &str) -> String {
+        let checksum = body.bytes().fold(0, |sum, byte| sum ^ byte);
+        format!("${body}*{checksum:02X}\r\n")
+    }
+
+    #[test]
+    fn test_parse_sentence() {
+        // 2024-03-01 12:35:19.25 UTC
+        let days = (1_709_251_200 + 45319) / 86400;
+
+        let rmc = "$GPRMC,123519.25,A,4807.038,N,01131.000,E,022.4,084.4,010324,003.1,W*48\r\n";
+        assert_eq!(
+            parse_sentence(rmc),
+            Some(SentenceTime {
+                sentence: NmeaSentence::Rmc,
+                days: Some(days),
+                seconds: 45319,
+                nanos: 250_000_000,
+            })
+        );
+
+        let gga = sentence("GNGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,");
+        assert_eq!(
+            parse_sentence(&gga),
+            Some(SentenceTime {
+                sentence: NmeaSentence::Gga,
+                days: None,
+                seconds: 45319,
+                nanos: 0,
+            })
+        );
+
+        let zda = sentence("GPZDA,123519.00,01,03,2024,00,00");
+        assert_eq!(parse_sentence(&zda).unwrap().days, Some(days));
+
+        // No fix
+        let rmc = sentence("GPRMC,123519,V,,,,,,,010324,,,N");
+        assert_eq!(parse_sentence(&rmc), None);
+        let gga = sentence("GPGGA,123519,,,,,0,00,,,M,,M,,");
+        assert_eq!(parse_sentence(&gga), None);
+
+        // Wrong checksum, other sentences and garbage
+        assert_eq!(parse_sentence("$GPZDA,123519.00,01,03,2024,00,00*00"), None);
+        assert_eq!(
+            parse_sentence(&sentence("GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1")),
+            None
+        );
+        assert_eq!(
+            parse_sentence(&sentence("GPZDA,2519.00,01,03,2024,00,00")),
+            None
+        );
+        assert_eq!(parse_sentence("\u{0}\u{ff}garbage"), None);
+    }
+
+    #[test]
+    fn test_days_since_epoch() {
+        assert_eq!(days_since_epoch(1970, 1, 1), Some(0));
+        assert_eq!(days_since_epoch(2000, 3, 1), Some(11017));
+        assert_eq!(days_since_epoch(2024, 1, 1), Some(19723));
+        assert_eq!(days_since_epoch(2024, 12, 31), Some(20088));
+        assert_eq!(days_since_epoch(2024, 13, 1), None);
+        assert_eq!(days_since_epoch(1969, 12, 31), None);
+    }
+
+    #[test]
+    fn test_sentence_measurement() {
+        let offsets = NmeaOffsets {
+            rmc: 0.25,
+            ..NmeaOffsets::default()
+        };
+        let time = SentenceTime {
+            sentence: NmeaSentence::Rmc,
+            days: Some(19723),
+            seconds: 3600,
+            nanos: 0,
+        };
+
+        // Received 0.3 seconds after the start of the second, while the
+        // sentence is expected 0.25 seconds after it
+        let received = convert_unix_timestamp(MIDNIGHT_ANCHOR + 3600, 300_000_000);
+        let measurement = sentence_measurement(&time, &offsets, received, ClockId::new(), -7);
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset + 0.05).abs() < 1e-6);
+
+        // Without a date, the nearest day is used, also across midnight
+        let time = SentenceTime {
+            sentence: NmeaSentence::Gga,
+            days: None,
+            seconds: 86399,
+            nanos: 900_000_000,
+        };
+        let received = convert_unix_timestamp(MIDNIGHT_ANCHOR + 86400, 100_000_000);
+        let measurement = sentence_measurement(&time, &offsets, received, ClockId::new(), -7);
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset + 0.2).abs() < 1e-6);
+    }
+}
@@ -4,19 +4,30 @@ use std::{
 
 use ntp_proto::{
     ClockId, NtpClock, NtpSource, NtpSourceActionIterator, NtpTimestamp, ObservableSourceState,
-    SourceController,
+    SourceController, SourceSecurityEvent,
 };
 #[cfg(target_os = "linux")]
 use timestamped_socket::socket::open_interface_udp;
 use timestamped_socket::{
     interface::InterfaceName,
-    socket::{Connected, RecvResult, Socket, connect_address},
+    socket::{Connected, RecvResult, Socket, connect_address, open_ip},
 };
 use tracing::{Instrument, Span, debug, error, instrument, warn};
 
 use tokio::time::{Instant, Sleep};
 
-use super::{config::TimestampMode, exitcode, util::convert_net_timestamp};
+use super::{
+    audit::{self, AuditEvent},
+    config::TimestampMode,
+    latency::SoftwareLatency,
+    measurements_log,
+    util::convert_net_timestamp,
+};
+use crate::exitcode;
+
+/// How long an unauthenticated stand-in for an NTS source runs before it is
+/// demobilized, so that the key exchange is tried again
+const NTS_FALLBACK_LIFETIME: std::time::Duration = std::time::Duration::from_secs(15 * 60);
 
 /// Trait needed to allow injecting of futures other than `tokio::time::Sleep` for testing
 pub trait Wait: Future<Output = ()> {
@@ -29,6 +40,46 @@ impl Wait for Sleep {
     }
 }
 
+/// Shared schedule for sources that poll in coordinated mode
+///
+/// Polls are aligned to multiples of the poll interval since a common epoch,
+/// so sources with the same poll interval send their requests within
+/// milliseconds of each other. Their measurements then see nearly identical
+/// local clock state, which makes them more comparable when combined.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PollSchedule {
+    epoch: Instant,
+}
+
+impl PollSchedule {
+    pub(crate) fn new(epoch: Instant) -> Self {
+        PollSchedule { epoch }
+    }
+
+    fn deadline(self, now: Instant, timeout: std::time::Duration) -> Option<Instant> {
+        // Timeouts below a second are used for startup and bursts, those
+        // should not be delayed.
+        let secs = timeout.as_secs();
+        if secs == 0 {
+            return now.checked_add(timeout);
+        }
+
+        // The source randomizes its poll interval slightly, round it back
+        // down to the poll interval itself, which is a power of two.
+        let period = std::time::Duration::from_secs(1 << secs.ilog2());
+
+        // Pick the first slot at least half a period away, which keeps the
+        // average poll interval unchanged once aligned.
+        let earliest = now.checked_add(period / 2)?;
+        let slots = earliest
+            .saturating_duration_since(self.epoch)
+            .as_nanos()
+            .div_ceil(period.as_nanos());
+        self.epoch
+            .checked_add(period.checked_mul(u32::try_from(slots).ok()?)?)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum MsgForSystem {
     /// Received a Kiss-o'-Death and must demobilize
@@ -39,24 +90,51 @@ pub enum MsgForSystem {
     Unreachable(ClockId),
 }
 
+/// Request from the system to an NTP source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsgForSource {
+    /// Poll the source in a burst right away
+    Burst,
+    /// The name of the source now resolves to this address
+    ChangeAddress(SocketAddr),
+}
+
 #[derive(Debug)]
 pub struct SourceChannels {
     pub msg_for_system_sender: tokio::sync::mpsc::Sender<MsgForSystem>,
     pub source_snapshots: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
 }
 
+impl SourceChannels {
+    /// Let the system restart the source with the given index, the spawner
+    /// takes care of waiting a bit before we try again.
+    pub async fn report_network_issue(&self, index: ClockId) {
+        self.msg_for_system_sender
+            .send(MsgForSystem::NetworkIssue(index))
+            .await
+            .ok();
+    }
+}
+
 pub(crate) struct SourceTask<C: 'static + NtpClock + Send, Controller: SourceController, T: Wait> {
     _wait: PhantomData<T>,
     index: ClockId,
     clock: C,
     interface: Option<InterfaceName>,
+    local_address: Option<SocketAddr>,
     timestamp_mode: TimestampMode,
+    software_latency: tokio::sync::watch::Receiver<SoftwareLatency>,
+    poll_schedule: Option<PollSchedule>,
     name: String,
     source_addr: SocketAddr,
     socket: Option<Socket<SocketAddr, Connected>>,
     channels: SourceChannels,
+    msg_for_source_receiver: tokio::sync::mpsc::Receiver<MsgForSource>,
 
     source: NtpSource<Controller>,
+    /// Set for an unauthenticated stand-in for an NTS source, which is
+    /// demobilized once this has passed
+    nts_fallback_until: Option<Instant>,
 
     // we don't store the real origin timestamp in the packet, because that would leak our
     // system time to the network (and could make attacks easier). So instead there is some
@@ -78,17 +156,26 @@ where
     T: Wait,
 {
     async fn setup_socket(&mut self) -> SocketResult {
-        let socket_res = match self.interface {
+        // A fixed local port can only be bound once the previous socket is gone
+        self.socket = None;
+
+        let socket_res = match (self.interface, self.local_address) {
             #[cfg(target_os = "linux")]
-            Some(interface) => {
+            (Some(interface), local_address) => {
+                // The interface determines the local address, but a
+                // configured port is still used
                 open_interface_udp(
                     interface,
-                    0, /*lets os choose*/
+                    local_address.map_or(0, |addr| addr.port()),
                     self.timestamp_mode.as_interface_mode(),
                     None,
                 )
                 .and_then(|socket| socket.connect(self.source_addr))
             }
+            (_, Some(local_address)) => {
+                open_ip(local_address, self.timestamp_mode.as_general_mode())
+                    .and_then(|socket| socket.connect(self.source_addr))
+            }
             _ => connect_address(self.source_addr, self.timestamp_mode.as_general_mode()),
         };
 
@@ -103,6 +190,44 @@ where
         SocketResult::Ok
     }
 
+    fn record_security_events(&mut self) {
+        for event in self.source.take_security_events() {
+            match event {
+                SourceSecurityEvent::AuthenticationFailure => {
+                    audit::record(&AuditEvent::SourceAuthenticationFailure {
+                        source: &self.name,
+                        address: self.source_addr,
+                    });
+                }
+                SourceSecurityEvent::KissOfDeath { code } => {
+                    audit::record(&AuditEvent::KissOfDeath {
+                        source: &self.name,
+                        address: self.source_addr,
+                        code: &code,
+                    });
+                }
+            }
+        }
+    }
+
+    fn record_measurement(&mut self) {
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record(
+                &self.name,
+                &self.source_addr.to_string(),
+                self.index,
+                &measurement,
+            );
+        }
+    }
+
+    fn observe(&self) -> ObservableSourceState {
+        ObservableSourceState {
+            nts_degraded: self.nts_fallback_until.is_some(),
+            ..self.source.observe(self.name.clone(), self.index)
+        }
+    }
+
     // FIXME: Figure out reasonable ways to simplify and/or split this function
     #[expect(clippy::too_many_lines)]
     async fn run(&mut self, mut poll_wait: Pin<&mut T>) {
@@ -110,6 +235,7 @@ where
             enum SelectResult {
                 Timer,
                 Recv(Result<RecvResult<SocketAddr>, std::io::Error>),
+                Msg(MsgForSource),
             }
 
             let mut buf = [0_u8; 1024];
@@ -121,12 +247,25 @@ where
                 result = async { if let Some(ref mut socket) = self.socket { socket.recv(&mut buf).await } else { std::future::pending().await }} => {
                     SelectResult::Recv(result)
                 },
+                Some(msg) = self.msg_for_source_receiver.recv() => {
+                    SelectResult::Msg(msg)
+                },
             };
 
             let actions = match selected {
+                SelectResult::Msg(MsgForSource::Burst) => self.source.start_burst(),
+                SelectResult::Msg(MsgForSource::ChangeAddress(addr)) => {
+                    self.source_addr = addr;
+                    self.source.change_address(addr);
+                    // The next poll opens a socket to the new address
+                    self.socket = None;
+                    self.last_send_timestamp = None;
+                    NtpSourceActionIterator::default()
+                }
                 SelectResult::Recv(result) => {
                     tracing::debug!("accept packet");
-                    match accept_packet(result, &buf, &self.clock) {
+                    let software_latency = *self.software_latency.borrow();
+                    match accept_packet(result, &buf, &self.clock, software_latency) {
                         AcceptResult::Accept(packet, recv_timestamp) => {
                             let Some(send_timestamp) = self.last_send_timestamp else {
                                 debug!("we received a message without having sent one; discarding");
@@ -135,14 +274,13 @@ where
                             let actions =
                                 self.source
                                     .handle_incoming(packet, send_timestamp, recv_timestamp);
+                            self.record_security_events();
+                            self.record_measurement();
                             self.channels
                                 .source_snapshots
                                 .write()
                                 .expect("Unexpected poisoned mutex")
-                                .insert(
-                                    self.index,
-                                    self.source.observe(self.name.clone(), self.index),
-                                );
+                                .insert(self.index, self.observe());
                             actions
                         }
                         AcceptResult::NetworkGone => {
@@ -163,15 +301,30 @@ where
                 }
                 SelectResult::Timer => {
                     tracing::debug!("wait completed");
+                    if self
+                        .nts_fallback_until
+                        .is_some_and(|until| Instant::now() >= until)
+                    {
+                        debug!("Demobilizing unauthenticated stand-in to retry NTS");
+                        self.channels
+                            .msg_for_system_sender
+                            .send(MsgForSystem::MustDemobilize(self.index))
+                            .await
+                            .ok();
+                        self.channels
+                            .source_snapshots
+                            .write()
+                            .expect("Unexpected poisoned mutex")
+                            .remove(&self.index);
+                        return;
+                    }
+
                     let actions = self.source.handle_timer();
                     self.channels
                         .source_snapshots
                         .write()
                         .expect("Unexpected poisoned mutex")
-                        .insert(
-                            self.index,
-                            self.source.observe(self.name.clone(), self.index),
-                        );
+                        .insert(self.index, self.observe());
                     actions
                 }
             };
@@ -199,10 +352,12 @@ where
                                 error!(error = ?e, "There was an error retrieving the current time");
 
                                 // report as no permissions, since this seems the most likely
-                                std::process::exit(exitcode::NOPERM);
+                                exitcode::exit(exitcode::NOPERM);
                             }
                             Ok(ts) => {
-                                self.last_send_timestamp = Some(ts);
+                                let software_latency = *self.software_latency.borrow();
+                                self.last_send_timestamp =
+                                    Some(software_latency.compensate_send(ts));
                             }
                         }
 
@@ -239,7 +394,11 @@ where
                         }
                     }
                     ntp_proto::NtpSourceAction::SetTimer(timeout) => {
-                        if let Some(deadline) = Instant::now().checked_add(timeout) {
+                        let deadline = match self.poll_schedule {
+                            Some(schedule) => schedule.deadline(Instant::now(), timeout),
+                            None => Instant::now().checked_add(timeout),
+                        };
+                        if let Some(deadline) = deadline {
                             // If it overflows, it is so far in the future we may as well not set the timer.
                             poll_wait.as_mut().reset(deadline);
                         }
@@ -281,17 +440,22 @@ where
     C: 'static + NtpClock + Send + Sync,
 {
     #[expect(clippy::too_many_arguments)]
-    #[instrument(level = tracing::Level::ERROR, name = "Ntp Source", skip(timestamp_mode, clock, channels, source, initial_actions))]
+    #[instrument(level = tracing::Level::ERROR, name = "Ntp Source", skip(timestamp_mode, software_latency, poll_schedule, clock, channels, msg_for_source_receiver, source, initial_actions))]
     pub fn spawn(
         index: ClockId,
         name: String,
         source_addr: SocketAddr,
         interface: Option<InterfaceName>,
+        local_address: Option<SocketAddr>,
         clock: C,
         timestamp_mode: TimestampMode,
+        software_latency: tokio::sync::watch::Receiver<SoftwareLatency>,
+        poll_schedule: Option<PollSchedule>,
         channels: SourceChannels,
+        msg_for_source_receiver: tokio::sync::mpsc::Receiver<MsgForSource>,
         source: NtpSource<Controller>,
         initial_actions: NtpSourceActionIterator,
+        nts_fallback: bool,
     ) -> tokio::task::JoinHandle<()> {
         tokio::spawn(
             (async move {
@@ -321,11 +485,17 @@ where
                     name,
                     clock,
                     channels,
+                    msg_for_source_receiver,
                     interface,
+                    local_address,
                     timestamp_mode,
+                    software_latency,
+                    poll_schedule,
                     source_addr,
                     socket: None,
                     source,
+                    nts_fallback_until: nts_fallback
+                        .then(|| Instant::now() + NTS_FALLBACK_LIFETIME),
                     last_send_timestamp: None,
                 };
 
@@ -347,6 +517,7 @@ fn accept_packet<'a, C: NtpClock>(
     result: Result<RecvResult<SocketAddr>, std::io::Error>,
     buf: &'a [u8],
     clock: &C,
+    software_latency: SoftwareLatency,
 ) -> AcceptResult<'a> {
     match result {
         Ok(RecvResult {
@@ -358,7 +529,7 @@ fn accept_packet<'a, C: NtpClock>(
                 || match clock.now() {
                     Ok(now) => {
                         debug!(?size, "received a packet without a timestamp, substituting");
-                        now
+                        software_latency.compensate_recv(now)
                     }
                     _ => {
                         panic!("Received packet without timestamp and couldn't substitute");
@@ -403,12 +574,12 @@ mod tests {
     };
 
     use ntp_proto::{
-        AlgorithmConfig, KalmanClockController, NoCipher, NtpDuration, NtpLeapIndicator,
-        NtpManager, NtpPacket, NtpServerInfo, ProtocolVersion, SourceConfig, SynchronizationConfig,
-        TimeSnapshot, TimeSyncController, TimeSyncControllerWrapper, TwoWayKalmanSourceController,
-        TwoWaySourceControllerWrapper,
+        AlgorithmConfig, InitialBurst, KalmanClockController, NoCipher, NtpDuration,
+        NtpLeapIndicator, NtpManager, NtpPacket, NtpServerInfo, ProtocolVersion, SourceConfig,
+        SynchronizationConfig, TimeSnapshot, TimeSyncController, TimeSyncControllerWrapper,
+        TwoWayKalmanSourceController, TwoWaySourceControllerWrapper,
     };
-    use timestamped_socket::socket::{GeneralTimestampMode, Open, open_ip};
+    use timestamped_socket::socket::{GeneralTimestampMode, Open};
     use tokio::sync::mpsc;
 
     use crate::{daemon::util::EPOCH_OFFSET, test::alloc_port};
@@ -427,6 +598,11 @@ mod tests {
                 waker.wake();
             }
         }
+
+        /// Timeouts the source requested since the last call
+        fn take_timeouts(&self) -> Vec<Duration> {
+            std::mem::take(&mut self.state.lock().unwrap().timeouts)
+        }
     }
 
     struct TestWait {
@@ -436,6 +612,7 @@ mod tests {
     struct TestWaitState {
         waker: Option<std::task::Waker>,
         pending: bool,
+        timeouts: Vec<Duration>,
     }
 
     impl Future for TestWait {
@@ -459,7 +636,14 @@ mod tests {
     }
 
     impl Wait for TestWait {
-        fn reset(self: Pin<&mut Self>, _deadline: Instant) {}
+        fn reset(self: Pin<&mut Self>, deadline: Instant) {
+            // The timer never fires by itself, but record what was asked for
+            self.state
+                .lock()
+                .unwrap()
+                .timeouts
+                .push(deadline.saturating_duration_since(Instant::now()));
+        }
     }
 
     impl Drop for TestWait {
@@ -473,6 +657,7 @@ mod tests {
             let state = Arc::new(std::sync::Mutex::new(TestWaitState {
                 waker: None,
                 pending: false,
+                timeouts: vec![],
             }));
 
             (
@@ -536,6 +721,21 @@ mod tests {
         SourceTask<TestClock, TwoWaySourceControllerWrapper<TwoWayKalmanSourceController>, T>,
         Socket<SocketAddr, Open>,
         mpsc::Receiver<MsgForSystem>,
+    ) {
+        // Most tests are about the regular poll loop, don't let a burst interfere
+        test_startup_with_config(SourceConfig {
+            initial_burst: InitialBurst::DISABLED,
+            ..SourceConfig::default()
+        })
+        .await
+    }
+
+    async fn test_startup_with_config<T: Wait>(
+        source_config: SourceConfig,
+    ) -> (
+        SourceTask<TestClock, TwoWaySourceControllerWrapper<TwoWayKalmanSourceController>, T>,
+        Socket<SocketAddr, Open>,
+        mpsc::Receiver<MsgForSystem>,
     ) {
         let port_base = alloc_port();
         let test_socket = open_ip(
@@ -557,9 +757,10 @@ mod tests {
 
         let (source, _) = ntp_manager.new_source(
             SocketAddr::from((Ipv4Addr::LOCALHOST, port_base)),
-            SourceConfig::default(),
+            source_config,
             ProtocolVersion::V4,
-            controller.add_source(index, SourceConfig::default()),
+            controller.add_source(index, source_config),
+            None,
             None,
             index,
         );
@@ -573,11 +774,16 @@ mod tests {
                 msg_for_system_sender,
                 source_snapshots: Arc::new(RwLock::new(HashMap::new())),
             },
+            msg_for_source_receiver: mpsc::channel(1).1,
             source_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, port_base)),
             interface: None,
+            local_address: None,
             timestamp_mode: TimestampMode::KernelRecv,
+            software_latency: tokio::sync::watch::channel(SoftwareLatency::default()).1,
+            poll_schedule: None,
             socket: None,
             source,
+            nts_fallback_until: None,
             last_send_timestamp: None,
         };
 
@@ -649,7 +855,7 @@ mod tests {
 
         let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
         let send_packet = NtpPacket::timestamp_response(
-            server_info,
+            &server_info,
             rec_packet,
             convert_net_timestamp(timestamp),
             &clock,
@@ -715,4 +921,303 @@ mod tests {
 
         handle.abort();
     }
+
+    #[tokio::test]
+    async fn test_nts_fallback_demobilizes() {
+        let (mut process, socket, mut msg_recv) = test_startup().await;
+        assert!(!process.observe().nts_degraded);
+        process.nts_fallback_until = Some(Instant::now());
+        assert!(process.observe().nts_degraded);
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::MustDemobilize(_)));
+
+        // The source is retired without polling
+        let mut buf = [0; 48];
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {/*expected */},
+            _ = socket.recv(&mut buf) => { unreachable!("should not receive anything") }
+        }
+
+        handle.abort();
+    }
+
+    fn burst_config() -> SourceConfig {
+        SourceConfig {
+            initial_burst: InitialBurst {
+                count: 4,
+                spacing: NtpDuration::from_seconds(2.0),
+            },
+            ..SourceConfig::default()
+        }
+    }
+
+    fn assert_burst_timeout(timeout: Duration) {
+        assert!(
+            timeout > Duration::from_millis(1900) && timeout <= Duration::from_secs(2),
+            "{timeout:?} is not a burst timeout"
+        );
+    }
+
+    fn assert_poll_timeout(timeout: Duration, source_config: SourceConfig) {
+        let poll_interval = source_config.poll_interval_limits.min.as_system_duration();
+        assert!(
+            timeout > poll_interval,
+            "{timeout:?} is not a regular poll timeout"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_initial_burst() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup_with_config(burst_config()).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        let mut timeouts = vec![];
+        for _ in 0..6 {
+            poll_send.notify();
+
+            let mut buf = [0; 48];
+            let network = socket.recv(&mut buf).await.unwrap();
+            assert_eq!(network.bytes_read, 48);
+
+            // Wait for the timer to be set after sending
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            timeouts.extend(poll_send.take_timeouts());
+        }
+
+        assert_eq!(timeouts.len(), 6);
+        // The first three polls are followed quickly by the rest of the burst
+        for timeout in &timeouts[..3] {
+            assert_burst_timeout(*timeout);
+        }
+        // After which polling continues as usual
+        for timeout in &timeouts[3..] {
+            assert_poll_timeout(*timeout, burst_config());
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_initial_burst_disabled() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let source_config = SourceConfig {
+            initial_burst: InitialBurst::DISABLED,
+            ..SourceConfig::default()
+        };
+        let (mut process, socket, _) = test_startup_with_config(source_config).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        for _ in 0..2 {
+            poll_send.notify();
+
+            let mut buf = [0; 48];
+            socket.recv(&mut buf).await.unwrap();
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let timeouts = poll_send.take_timeouts();
+            assert_eq!(timeouts.len(), 1);
+            assert_poll_timeout(timeouts[0], source_config);
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rate_kiss_stops_burst() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, _) = test_startup_with_config(burst_config()).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let RecvResult {
+            bytes_read: size,
+            remote_addr,
+            ..
+        } = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let timeouts = poll_send.take_timeouts();
+        assert_eq!(timeouts.len(), 1);
+        assert_burst_timeout(timeouts[0]);
+
+        let rec_packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        let send_packet = NtpPacket::rate_limit_response(rec_packet);
+        let serialized = serialize_packet_unencrypted(&send_packet);
+        socket.send_to(&serialized, remote_addr).await.unwrap();
+
+        // The pending burst timer is replaced by a regular one
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let timeouts = poll_send.take_timeouts();
+        assert_eq!(timeouts.len(), 1);
+        assert_poll_timeout(timeouts[0], burst_config());
+
+        // And the burst does not continue on the next poll
+        poll_send.notify();
+        socket.recv(&mut buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let timeouts = poll_send.take_timeouts();
+        assert_eq!(timeouts.len(), 1);
+        assert_poll_timeout(timeouts[0], burst_config());
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_burst_on_request() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup().await;
+        let (msg_for_source_sender, msg_for_source_receiver) = mpsc::channel(1);
+        process.msg_for_source_receiver = msg_for_source_receiver;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        // The burst starts right away
+        msg_for_source_sender
+            .send(MsgForSource::Burst)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(poll_send.take_timeouts(), [Duration::ZERO]);
+
+        poll_send.notify();
+        let mut buf = [0; 48];
+        socket.recv(&mut buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let timeouts = poll_send.take_timeouts();
+        assert_eq!(timeouts.len(), 1);
+        assert_burst_timeout(timeouts[0]);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_local_address() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _) = test_startup().await;
+        let local_address = SocketAddr::from((Ipv4Addr::LOCALHOST, alloc_port()));
+        process.local_address = Some(local_address);
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        // Requests come from the configured address and port, every time
+        let mut buf = [0; 48];
+        for _ in 0..2 {
+            poll_send.notify();
+            let RecvResult { remote_addr, .. } = socket.recv(&mut buf).await.unwrap();
+            assert_eq!(remote_addr, local_address);
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_change_address() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _, _) = test_startup().await;
+        let (msg_for_source_sender, msg_for_source_receiver) = mpsc::channel(1);
+        process.msg_for_source_receiver = msg_for_source_receiver;
+
+        let new_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, alloc_port()));
+        let new_socket = open_ip(new_addr, GeneralTimestampMode::SoftwareRecv).unwrap();
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        msg_for_source_sender
+            .send(MsgForSource::ChangeAddress(new_addr))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Polls now go to the new address
+        poll_send.notify();
+        let mut buf = [0; 48];
+        let network = new_socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        handle.abort();
+    }
+
+    #[test]
+    fn test_poll_schedule() {
+        use std::time::Duration;
+
+        let epoch = Instant::now();
+        let schedule = PollSchedule::new(epoch);
+
+        // Short timeouts are not aligned
+        let now = epoch + Duration::from_millis(1234);
+        assert_eq!(
+            schedule.deadline(now, Duration::from_millis(500)),
+            Some(now + Duration::from_millis(500))
+        );
+
+        // Randomized poll intervals are aligned to the next slot
+        assert_eq!(
+            schedule.deadline(now, Duration::from_millis(16_400)),
+            Some(epoch + Duration::from_secs(16))
+        );
+        let now = epoch + Duration::from_millis(9_000);
+        assert_eq!(
+            schedule.deadline(now, Duration::from_millis(16_700)),
+            Some(epoch + Duration::from_secs(32))
+        );
+
+        // Once aligned, sources keep polling at their poll interval
+        let now = epoch + Duration::from_secs(32);
+        assert_eq!(
+            schedule.deadline(now, Duration::from_millis(16_200)),
+            Some(epoch + Duration::from_secs(48))
+        );
+        assert_eq!(
+            schedule.deadline(now, Duration::from_millis(65_000)),
+            Some(epoch + Duration::from_secs(64))
+        );
+    }
 }
@@ -1,17 +1,28 @@
 use std::{
-    fs::{File, OpenOptions},
-    os::unix::prelude::{OpenOptionsExt, PermissionsExt},
+    fs::File,
+    os::unix::prelude::PermissionsExt,
+    path::Path,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use ntp_proto::{KeySet, KeySetProvider};
 use tokio::sync::watch;
-use tracing::{Span, instrument, warn};
+use tracing::{Span, info, instrument, warn};
 
-use super::config::KeysetConfig;
+use super::{config::KeysetConfig, util::write_atomically};
+
+/// Time between checks whether a followed key file was updated
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(60);
 
 #[instrument(level = tracing::Level::ERROR, name = "KeySet Provider", skip_all, fields(path = debug(config.key_storage_path.clone())))]
 pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
+    if config.follow_key_storage
+        && let Some(path) = config.key_storage_path
+    {
+        return spawn_follower(path, config.stale_key_count);
+    }
+
     let (mut provider, mut next_interval) = match &config.key_storage_path {
         Some(path) => {
             let path = path.to_owned();
@@ -28,7 +39,7 @@ pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
                 }
             }
 
-            let (provider, time) = tokio::task::spawn_blocking(
+            let (mut provider, time) = tokio::task::spawn_blocking(
                 move || -> std::io::Result<(KeySetProvider, std::time::SystemTime)> {
                     let mut input = File::open(path)?;
                     KeySetProvider::load(&mut input, config.stale_key_count)
@@ -43,14 +54,19 @@ pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
                     std::time::SystemTime::now(),
                 )
             });
-            (
-                provider,
-                std::time::Duration::from_secs(config.key_rotation_interval as _).saturating_sub(
-                    std::time::SystemTime::now()
-                        .duration_since(time)
-                        .unwrap_or(std::time::Duration::from_secs(0)),
-                ),
-            )
+            let (missed, next_interval) = missed_rotations(
+                std::time::SystemTime::now()
+                    .duration_since(time)
+                    .unwrap_or(std::time::Duration::from_secs(0)),
+                std::time::Duration::from_secs(config.key_rotation_interval as _),
+            );
+            // Rotations missed while the daemon was down are caught up, so
+            // keys don't stay valid for longer than configured. After
+            // stale_key_count + 1 rotations no stored key is left anyway.
+            for _ in 0..missed.min(config.stale_key_count as u64 + 1) {
+                provider.rotate();
+            }
+            (provider, next_interval)
         }
         None => (
             KeySetProvider::new(config.stale_key_count),
@@ -64,15 +80,7 @@ pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
         loop {
             // First save, then sleep. Ensures new sets created at boot are also saved.
             if let Some(path) = &config.key_storage_path
-                && let Err(e) = (|| -> std::io::Result<()> {
-                    let mut output = OpenOptions::new()
-                        .create(true)
-                        .truncate(true)
-                        .write(true)
-                        .mode(0o600)
-                        .open(path)?;
-                    provider.store(&mut output)
-                })()
+                && let Err(e) = store(&provider, Path::new(path))
             {
                 if e.kind() == std::io::ErrorKind::NotFound
                     || e.kind() == std::io::ErrorKind::PermissionDenied
@@ -92,3 +100,132 @@ pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
     });
     rx
 }
+
+/// Use the keys another server stores at `path`, so cookies issued by any
+/// server sharing the file can be used with all of them.
+pub(super) fn spawn_follower(path: String, stale_key_count: usize) -> watch::Receiver<Arc<KeySet>> {
+    let mut last_modified = None;
+    let provider = reload_if_changed(Path::new(&path), stale_key_count, &mut last_modified)
+        .unwrap_or_else(|| {
+            warn!("Could not load shared nts server keys, using new keys until they are available");
+            KeySetProvider::new(stale_key_count)
+        });
+
+    let (tx, rx) = watch::channel(provider.get());
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _enter = span.enter();
+        loop {
+            std::thread::sleep(FOLLOW_INTERVAL);
+            if let Some(provider) =
+                reload_if_changed(Path::new(&path), stale_key_count, &mut last_modified)
+            {
+                info!("Loaded updated shared nts server keys");
+                if tx.send(provider.get()).is_err() {
+                    break;
+                }
+            } else if tx.is_closed() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Load the keys at `path` if the file was modified since `last_modified`.
+fn reload_if_changed(
+    path: &Path,
+    stale_key_count: usize,
+    last_modified: &mut Option<SystemTime>,
+) -> Option<KeySetProvider> {
+    let modified = match std::fs::metadata(path).and_then(|meta| meta.modified()) {
+        Ok(modified) => modified,
+        Err(e) => {
+            warn!(error = ?e, "Could not access shared nts server keys");
+            return None;
+        }
+    };
+    if *last_modified == Some(modified) {
+        return None;
+    }
+
+    match File::open(path).and_then(|mut input| KeySetProvider::load(&mut input, stale_key_count)) {
+        Ok((provider, _)) => {
+            *last_modified = Some(modified);
+            Some(provider)
+        }
+        Err(e) => {
+            warn!(error = ?e, "Could not load shared nts server keys");
+            None
+        }
+    }
+}
+
+fn store(provider: &KeySetProvider, path: &Path) -> std::io::Result<()> {
+    write_atomically(path, 0o600, |output| provider.store(output))
+}
+
+/// Number of rotations that should have happened in `elapsed` time since the
+/// keys were stored, and the time until the next one.
+fn missed_rotations(
+    elapsed: std::time::Duration,
+    interval: std::time::Duration,
+) -> (u64, std::time::Duration) {
+    let interval_secs = interval.as_secs();
+    if interval_secs == 0 {
+        return (0, interval);
+    }
+    let missed = elapsed.as_secs() / interval_secs;
+    let into_interval = std::time::Duration::from_secs(elapsed.as_secs() % interval_secs);
+    (missed, interval - into_interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ntp_proto::KeySetProvider;
+
+    use crate::test::alloc_port;
+
+    use super::{missed_rotations, reload_if_changed, store};
+
+    #[test]
+    fn test_reload_if_changed() {
+        let path = std::env::temp_dir().join(format!("ntp-test-keyset-{}", alloc_port()));
+        let mut last_modified = None;
+        assert!(reload_if_changed(&path, 7, &mut last_modified).is_none());
+
+        let mut provider = KeySetProvider::new(7);
+        provider.rotate();
+        store(&provider, &path).unwrap();
+
+        let loaded = reload_if_changed(&path, 7, &mut last_modified).unwrap();
+        assert_eq!(
+            format!("{:?}", loaded.get()),
+            format!("{:?}", provider.get())
+        );
+
+        // Only reloaded once the file changes
+        assert!(reload_if_changed(&path, 7, &mut last_modified).is_none());
+        last_modified = last_modified.map(|time| time - Duration::from_secs(1));
+        assert!(reload_if_changed(&path, 7, &mut last_modified).is_some());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missed_rotations() {
+        let day = Duration::from_secs(86400);
+        assert_eq!(
+            missed_rotations(Duration::from_secs(3600), day),
+            (0, day - Duration::from_secs(3600))
+        );
+        assert_eq!(
+            missed_rotations(day * 3 + Duration::from_secs(60), day),
+            (3, day - Duration::from_secs(60))
+        );
+        assert_eq!(missed_rotations(day, day), (1, day));
+        assert_eq!(missed_rotations(day, Duration::ZERO), (0, Duration::ZERO));
+    }
+}
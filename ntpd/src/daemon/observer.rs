@@ -1,4 +1,4 @@
-use super::server::ServerStats;
+use super::server::{KeyExchangeStats, ServerStats};
 use super::sockets::create_unix_socket_with_permissions;
 use super::system::ServerData;
 use libc::{ECONNABORTED, EMFILE, ENFILE, ENOBUFS, ENOMEM};
@@ -19,6 +19,11 @@ pub struct ObservableState {
     pub system: SystemSnapshot,
     pub sources: Vec<ObservableSourceState>,
     pub servers: Vec<ObservableServerState>,
+    #[serde(default)]
+    pub key_exchange_servers: Vec<ObservableKeyExchangeServerState>,
+    /// Sources that failed, waiting before they are tried again
+    #[serde(default)]
+    pub spawners: Vec<ObservableSpawnerState>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -58,6 +63,26 @@ pub struct ObservableServerState {
     pub stats: ServerStats,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservableKeyExchangeServerState {
+    pub address: SocketAddr,
+    pub stats: KeyExchangeStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservableSpawnerState {
+    /// The kind of source, such as pool or nts
+    pub kind: String,
+    pub address: String,
+    /// Attempts in a row that failed
+    pub failures: u32,
+    /// Seconds until the next attempt
+    pub retry_in: f64,
+}
+
+/// Gives the current state of the spawners
+pub type SpawnersReader = Arc<dyn Fn() -> Vec<ObservableSpawnerState> + Send + Sync>;
+
 impl From<&ServerData> for ObservableServerState {
     fn from(data: &ServerData) -> Self {
         ObservableServerState {
@@ -71,15 +96,25 @@ impl From<&ServerData> for ObservableServerState {
 pub fn spawn<C: 'static + NtpClock + Send>(
     config: &super::config::ObservabilityConfig,
     sources_reader: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
+    spawners_reader: SpawnersReader,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
     system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    key_exchange_servers: Vec<ObservableKeyExchangeServerState>,
     clock: C,
 ) -> JoinHandle<std::io::Result<()>> {
     let config = config.clone();
     tokio::spawn(
         (async move {
-            let result =
-                observer(config, sources_reader, server_reader, system_reader, clock).await;
+            let result = observer(
+                config,
+                sources_reader,
+                spawners_reader,
+                server_reader,
+                system_reader,
+                key_exchange_servers,
+                clock,
+            )
+            .await;
             if let Err(ref e) = result {
                 warn!("Abnormal termination of the state observer: {e}");
                 warn!("The state observer will not be available");
@@ -93,8 +128,10 @@ pub fn spawn<C: 'static + NtpClock + Send>(
 async fn observer<C: 'static + NtpClock + Send>(
     config: super::config::ObservabilityConfig,
     sources_reader: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
+    spawners_reader: SpawnersReader,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
     system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    key_exchange_servers: Vec<ObservableKeyExchangeServerState>,
     clock: C,
 ) -> std::io::Result<()> {
     let start_time = Instant::now();
@@ -143,8 +180,10 @@ async fn observer<C: 'static + NtpClock + Send>(
             }
         };
         let sources_reader = sources_reader.clone();
+        let spawners_reader = spawners_reader.clone();
         let server_reader = server_reader.clone();
         let system_reader = system_reader.clone();
+        let key_exchange_servers = key_exchange_servers.clone();
 
         let now = clock.now().expect("Unable to get current time");
         let fut = async move {
@@ -152,8 +191,10 @@ async fn observer<C: 'static + NtpClock + Send>(
                 &mut stream,
                 start_time,
                 &sources_reader,
+                &spawners_reader,
                 server_reader,
                 system_reader,
+                key_exchange_servers,
                 now,
             )
             .await
@@ -170,12 +211,15 @@ async fn observer<C: 'static + NtpClock + Send>(
     }
 }
 
+#[expect(clippy::too_many_arguments)]
 async fn handle_connection(
     stream: &mut (impl tokio::io::AsyncWrite + Unpin),
     start_time: Instant,
     sources_reader: &std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>,
+    spawners_reader: &SpawnersReader,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
     system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    key_exchange_servers: Vec<ObservableKeyExchangeServerState>,
     now: NtpTimestamp,
 ) -> std::io::Result<()> {
     let observe = ObservableState {
@@ -188,6 +232,8 @@ async fn handle_connection(
             .collect(),
         system: *system_reader.borrow(),
         servers: server_reader.borrow().iter().map(Into::into).collect(),
+        key_exchange_servers,
+        spawners: spawners_reader(),
     };
 
     super::sockets::write_json(stream, &observe).await?;
@@ -272,6 +318,19 @@ mod tests {
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id,
+                weight: 1.0,
+                prefer: false,
+                noselect: false,
+                ntp_version: Some(4),
+                stratum: Some(2),
+                reference_id: Some(ReferenceId::NONE),
+                reach: Some(Reach::never().bits()),
+                target_poll_interval: Some(PollIntervalLimits::default().min),
+                last_kiss_code: None,
+                measurements: vec![],
+                nts_key_exchange: None,
+                authentication_failures: 0,
+                nts_degraded: false,
             },
         );
 
@@ -296,6 +355,11 @@ mod tests {
                 leap_indicator: NtpLeapIndicator::Leap59,
                 accumulated_steps: NtpDuration::ZERO,
                 accumulated_steps_threshold: None,
+                leap_smear: None,
+                monotonic_epoch: 0,
+                monotonic_offset: NtpDuration::ZERO,
+                frequency_correction: None,
+                steering_withheld: None,
             },
         });
 
@@ -303,8 +367,10 @@ mod tests {
             observer(
                 config,
                 source_snapshots,
+                Arc::new(Vec::new),
                 servers_reader,
                 system_reader,
+                vec![],
                 TestClock,
             )
             .await
@@ -348,6 +414,19 @@ mod tests {
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id,
+                weight: 1.0,
+                prefer: false,
+                noselect: false,
+                ntp_version: Some(4),
+                stratum: Some(2),
+                reference_id: Some(ReferenceId::NONE),
+                reach: Some(Reach::never().bits()),
+                target_poll_interval: Some(PollIntervalLimits::default().min),
+                last_kiss_code: None,
+                measurements: vec![],
+                nts_key_exchange: None,
+                authentication_failures: 0,
+                nts_degraded: false,
             },
         );
 
@@ -373,6 +452,11 @@ mod tests {
                 leap_indicator: NtpLeapIndicator::Leap59,
                 accumulated_steps: NtpDuration::ZERO,
                 accumulated_steps_threshold: None,
+                leap_smear: None,
+                monotonic_epoch: 0,
+                monotonic_offset: NtpDuration::ZERO,
+                frequency_correction: None,
+                steering_withheld: None,
             },
         });
 
@@ -380,8 +464,10 @@ mod tests {
             observer(
                 config,
                 source_snapshots,
+                Arc::new(Vec::new),
                 servers_reader,
                 system_reader,
+                vec![],
                 TestClock,
             )
             .await
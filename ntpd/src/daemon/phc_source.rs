@@ -0,0 +1,155 @@
+//! Source reading a PTP hardware clock (PHC), such as that of a network card
+//! synchronized to a PTP grandmaster by ptp4l. The kernel provides
+//! cross-timestamps between the PHC and the system clock, using the most
+//! precise of the PTP_SYS_OFFSET_PRECISE, PTP_SYS_OFFSET_EXTENDED and
+//! PTP_SYS_OFFSET ioctls the driver supports.
+
+use std::path::PathBuf;
+
+use clock_steering::unix::UnixClock;
+use ntp_proto::{
+    ClockId, Measurement, NtpDuration, NtpLeapIndicator, NtpTimestamp, OneWaySource, PollInterval,
+    SourceController,
+};
+use tracing::{Instrument, Span, debug, instrument, warn};
+
+use super::{
+    measurements_log,
+    ntp_source::SourceChannels,
+    sock_source::precision_exponent,
+    util::{open_clock, read_offset},
+};
+
+/// Turn a cross-timestamp into a measurement, with the PHC read halfway
+/// between the two readings of the system clock
+fn offset_measurement(
+    before: NtpTimestamp,
+    phc: NtpTimestamp,
+    after: NtpTimestamp,
+    index: ClockId,
+    precision: i8,
+) -> Measurement {
+    Measurement {
+        sender_id: index,
+        receiver_id: ClockId::SYSTEM,
+        sender_ts: phc,
+        receiver_ts: before + (after - before) / 2,
+
+        root_delay: NtpDuration::ZERO,
+        root_dispersion: NtpDuration::ZERO,
+        stratum: 1,
+        leap: NtpLeapIndicator::NoWarning,
+        precision,
+    }
+}
+
+pub(crate) struct PhcSourceTask<Controller: SourceController> {
+    index: ClockId,
+    clock: UnixClock,
+    path: PathBuf,
+    poll_interval: PollInterval,
+    precision: i8,
+    channels: SourceChannels,
+    source: OneWaySource<Controller>,
+}
+
+impl<Controller: SourceController> PhcSourceTask<Controller> {
+    async fn run(&mut self) {
+        loop {
+            match read_offset(&self.clock) {
+                Ok((before, phc, after)) => self.handle_offset(before, phc, after),
+                Err(e) => {
+                    warn!(error = %e, "Could not read PHC, reopening it");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+            }
+
+            tokio::time::sleep(self.poll_interval.as_system_duration()).await;
+        }
+    }
+
+    fn handle_offset(&mut self, before: NtpTimestamp, phc: NtpTimestamp, after: NtpTimestamp) {
+        debug!(?before, ?phc, ?after, "read PHC");
+
+        let measurement = offset_measurement(before, phc, after, self.index, self.precision);
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record(
+                "PHC",
+                &self.path.display().to_string(),
+                self.index,
+                &measurement,
+            );
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source.observe(
+                    "PHC".to_string(),
+                    self.path.display().to_string(),
+                    self.index,
+                ),
+            );
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "PHC Source", skip(channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        path: PathBuf,
+        poll_interval: PollInterval,
+        precision: f64,
+        channels: SourceChannels,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let clock = match open_clock(&path) {
+                    Ok(clock) => clock,
+                    Err(e) => {
+                        warn!(error = %e, "Could not open PHC");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
+                let mut process = PhcSourceTask {
+                    index,
+                    clock,
+                    path,
+                    poll_interval,
+                    precision: precision_exponent(precision),
+                    channels,
+                    source,
+                };
+
+                process.run().await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_measurement() {
+        let before = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let after = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 2000);
+        // A PHC 1 microsecond ahead of the system clock
+        let phc = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 2000);
+
+        let measurement = offset_measurement(before, phc, after, ClockId::new(), -20);
+        let midpoint = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 1000);
+        assert!((measurement.receiver_ts - midpoint).to_seconds().abs() < 1e-9);
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset - 1e-6).abs() < 1e-9);
+        assert_eq!(measurement.stratum, 1);
+    }
+}
@@ -0,0 +1,167 @@
+//! Steering of the PTP hardware clocks (PHCs) of network interfaces from the
+//! system clock, so hardware timestamps taken by the network card match the
+//! synchronized time. Each PHC is compared with the system clock every poll
+//! interval, and steered with a PI servo. Large offsets, such as at startup,
+//! are stepped away.
+
+use std::path::PathBuf;
+
+use clock_steering::{Clock, TimeOffset, unix::UnixClock};
+use ntp_proto::{NtpDuration, SystemSnapshot};
+use timestamped_socket::interface::InterfaceName;
+use tokio::sync::watch;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use super::{
+    config::PhcSteeringConfig,
+    util::{open_clock, read_offset},
+};
+
+// Gains of the servo, as used by phc2sys
+const KP: f64 = 0.7;
+const KI: f64 = 0.3;
+// Most network cards can't adjust their frequency by more than this
+const MAX_FREQUENCY: f64 = 500e-6;
+
+/// PI servo, turning measured offsets into a frequency for the PHC
+#[derive(Debug)]
+struct Servo {
+    /// Frequency (as a fraction) needed to keep up with the system clock
+    frequency: f64,
+}
+
+impl Servo {
+    /// The frequency to set, given the offset of the PHC in seconds
+    fn update(&mut self, offset: f64, interval: f64) -> f64 {
+        self.frequency =
+            (self.frequency - KI * offset / interval).clamp(-MAX_FREQUENCY, MAX_FREQUENCY);
+        (self.frequency - KP * offset / interval).clamp(-MAX_FREQUENCY, MAX_FREQUENCY)
+    }
+}
+
+/// Device of the PHC of `interface`, as found in sysfs
+fn phc_path(interface: &InterfaceName) -> std::io::Result<PathBuf> {
+    let directory = PathBuf::from(format!("/sys/class/net/{interface}/device/ptp"));
+    for entry in std::fs::read_dir(directory)? {
+        let name = entry?.file_name();
+        if name.to_string_lossy().starts_with("ptp") {
+            return Ok(PathBuf::from("/dev").join(name));
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("interface {interface} has no PTP hardware clock"),
+    ))
+}
+
+struct PhcSteering {
+    config: PhcSteeringConfig,
+    clock: Option<(UnixClock, Servo)>,
+    system_snapshot_receiver: watch::Receiver<SystemSnapshot>,
+}
+
+impl PhcSteering {
+    fn open(&self) -> Result<(UnixClock, Servo), String> {
+        let path = phc_path(&self.config.interface).map_err(|e| e.to_string())?;
+        let clock = open_clock(&path)?;
+        let frequency = clock.get_frequency().map_err(|e| e.to_string())? * 1e-6;
+        info!(?path, "Steering PTP hardware clock");
+        Ok((clock, Servo { frequency }))
+    }
+
+    async fn run(&mut self) {
+        let interval = self.config.poll_interval.as_system_duration();
+        loop {
+            tokio::time::sleep(interval).await;
+
+            // Only pass on time we are confident about
+            if self.system_snapshot_receiver.borrow().ntp_snapshot.stratum >= 16 {
+                continue;
+            }
+
+            if self.clock.is_none() {
+                match self.open() {
+                    Ok(clock) => self.clock = Some(clock),
+                    Err(e) => {
+                        warn!(error = %e, "Could not open PTP hardware clock");
+                        continue;
+                    }
+                }
+            }
+
+            if let Err(e) = self.steer(interval.as_secs_f64()) {
+                warn!(error = %e, "Could not steer PTP hardware clock, reopening it");
+                self.clock = None;
+            }
+        }
+    }
+
+    fn steer(&mut self, interval: f64) -> Result<(), String> {
+        let Some((clock, servo)) = &mut self.clock else {
+            return Ok(());
+        };
+
+        let (before, phc, after) = read_offset(clock)?;
+        let offset = (phc - (before + (after - before) / 2)).to_seconds() - self.config.offset;
+        debug!(offset, "PTP hardware clock offset");
+
+        if offset.abs() > self.config.step_threshold {
+            info!(offset, "Stepping PTP hardware clock");
+            let (seconds, nanos) = NtpDuration::from_seconds(-offset).as_seconds_nanos();
+            clock
+                .step_clock(TimeOffset {
+                    seconds: seconds as _,
+                    nanos,
+                })
+                .map_err(|e| e.to_string())?;
+        } else {
+            let frequency = servo.update(offset, interval);
+            clock
+                .set_frequency(frequency * 1e6)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Start steering the configured PTP hardware clocks
+pub(crate) fn spawn(
+    configs: &[PhcSteeringConfig],
+    system_snapshot_receiver: &watch::Receiver<SystemSnapshot>,
+) {
+    for config in configs {
+        let span = info_span!("PHC steering", interface = %config.interface);
+        let mut steering = PhcSteering {
+            config: config.clone(),
+            clock: None,
+            system_snapshot_receiver: system_snapshot_receiver.clone(),
+        };
+        tokio::spawn(async move { steering.run().await }.instrument(span));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_servo() {
+        let mut servo = Servo { frequency: 10e-6 };
+
+        // A PHC 1 microsecond ahead is slowed down
+        let frequency = servo.update(1e-6, 1.0);
+        assert!((servo.frequency - 9.7e-6).abs() < 1e-12);
+        assert!((frequency - 9.0e-6).abs() < 1e-12);
+
+        // No offset keeps the frequency it needs
+        let frequency = servo.update(0.0, 1.0);
+        assert!((frequency - 9.7e-6).abs() < 1e-12);
+
+        // Longer intervals mean smaller corrections
+        let frequency = servo.update(-1e-6, 4.0);
+        assert!((frequency - 9.95e-6).abs() < 1e-12);
+
+        // Never beyond what network cards can do
+        assert!((servo.update(1.0, 1.0) + MAX_FREQUENCY).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,502 @@
+//! Signing TLS handshakes of the NTS KE server with a private key on a
+//! PKCS#11 token, such as a hardware security module. The key itself never
+//! leaves the token.
+
+use std::sync::{Arc, Mutex};
+
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::{
+        Mechanism, MechanismType,
+        rsa::{PkcsMgfType, PkcsPssParams},
+    },
+    object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use ntp_proto::tls_utils::{Error, SignatureAlgorithm, SignatureScheme, Signer, SigningKey};
+
+use super::config::Pkcs11KeyConfig;
+
+// DER encoded object identifiers of the named curves, as found in CKA_EC_PARAMS
+const EC_PARAMS_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const EC_PARAMS_P384: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_SEQUENCE: u8 = 0x30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyKind {
+    Rsa,
+    EcdsaP256,
+    EcdsaP384,
+}
+
+impl KeyKind {
+    /// Schemes this key can sign with, in order of preference
+    fn schemes(self) -> &'static [SignatureScheme] {
+        match self {
+            KeyKind::Rsa => &[
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+            ],
+            KeyKind::EcdsaP256 => &[SignatureScheme::ECDSA_NISTP256_SHA256],
+            KeyKind::EcdsaP384 => &[SignatureScheme::ECDSA_NISTP384_SHA384],
+        }
+    }
+}
+
+struct TokenKey {
+    session: Mutex<Session>,
+    handle: ObjectHandle,
+    kind: KeyKind,
+}
+
+/// A private key on a PKCS#11 token
+#[derive(Clone)]
+pub(crate) struct Pkcs11SigningKey {
+    key: Arc<TokenKey>,
+}
+
+impl std::fmt::Debug for Pkcs11SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pkcs11SigningKey")
+            .field("kind", &self.key.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Open the token and look up the configured private key
+pub(crate) fn open(config: &Pkcs11KeyConfig) -> std::io::Result<Arc<dyn SigningKey>> {
+    let pin = match &config.pin_path {
+        Some(path) => Some(AuthPin::new(
+            std::fs::read_to_string(path)?.trim_end().to_owned(),
+        )),
+        None => None,
+    };
+
+    let pkcs11 = Pkcs11::new(&config.module_path).map_err(std::io::Error::other)?;
+    pkcs11
+        .initialize(CInitializeArgs::OsThreads)
+        .map_err(std::io::Error::other)?;
+
+    let slot = pkcs11
+        .get_slots_with_token()
+        .map_err(std::io::Error::other)?
+        .into_iter()
+        .find(|slot| {
+            pkcs11
+                .get_token_info(*slot)
+                .is_ok_and(|info| info.label() == config.token_label)
+        })
+        .ok_or_else(|| {
+            std::io::Error::other(format!(
+                "no PKCS#11 token with label `{}`",
+                config.token_label
+            ))
+        })?;
+
+    let session = pkcs11
+        .open_ro_session(slot)
+        .map_err(std::io::Error::other)?;
+    if let Some(pin) = pin {
+        session
+            .login(UserType::User, Some(&pin))
+            .map_err(std::io::Error::other)?;
+    }
+
+    let handle = session
+        .find_objects(&[
+            Attribute::Class(ObjectClass::PRIVATE_KEY),
+            Attribute::Label(config.key_label.clone().into_bytes()),
+        ])
+        .map_err(std::io::Error::other)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::other(format!(
+                "no private key with label `{}` on PKCS#11 token `{}`",
+                config.key_label, config.token_label
+            ))
+        })?;
+
+    let kind = key_kind(&session, handle)?;
+
+    Ok(Arc::new(Pkcs11SigningKey {
+        key: Arc::new(TokenKey {
+            session: Mutex::new(session),
+            handle,
+            kind,
+        }),
+    }))
+}
+
+fn key_kind(session: &Session, handle: ObjectHandle) -> std::io::Result<KeyKind> {
+    let unsupported =
+        || std::io::Error::other("PKCS#11 keys must be RSA, ECDSA P-256 or ECDSA P-384 keys");
+
+    let attributes = session
+        .get_attributes(handle, &[AttributeType::KeyType])
+        .map_err(std::io::Error::other)?;
+    match attributes.as_slice() {
+        [Attribute::KeyType(KeyType::RSA)] => Ok(KeyKind::Rsa),
+        [Attribute::KeyType(KeyType::EC)] => {
+            let attributes = session
+                .get_attributes(handle, &[AttributeType::EcParams])
+                .map_err(std::io::Error::other)?;
+            match attributes.as_slice() {
+                [Attribute::EcParams(params)] if params == EC_PARAMS_P256 => Ok(KeyKind::EcdsaP256),
+                [Attribute::EcParams(params)] if params == EC_PARAMS_P384 => Ok(KeyKind::EcdsaP384),
+                _ => Err(unsupported()),
+            }
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+impl SigningKey for Pkcs11SigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let scheme = self
+            .key
+            .kind
+            .schemes()
+            .iter()
+            .find(|scheme| offered.contains(scheme))?;
+
+        Some(Box::new(Pkcs11Signer {
+            key: self.key.clone(),
+            scheme: *scheme,
+        }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.key.kind {
+            KeyKind::Rsa => SignatureAlgorithm::RSA,
+            KeyKind::EcdsaP256 | KeyKind::EcdsaP384 => SignatureAlgorithm::ECDSA,
+        }
+    }
+}
+
+struct Pkcs11Signer {
+    key: Arc<TokenKey>,
+    scheme: SignatureScheme,
+}
+
+impl std::fmt::Debug for Pkcs11Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pkcs11Signer")
+            .field("scheme", &self.scheme)
+            .finish_non_exhaustive()
+    }
+}
+
+fn pss(hash_alg: MechanismType, mgf: PkcsMgfType, salt_length: u64) -> PkcsPssParams {
+    PkcsPssParams {
+        hash_alg,
+        mgf,
+        s_len: salt_length.into(),
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mechanism = match self.scheme {
+            SignatureScheme::RSA_PSS_SHA256 => Mechanism::Sha256RsaPkcsPss(pss(
+                MechanismType::SHA256,
+                PkcsMgfType::MGF1_SHA256,
+                32,
+            )),
+            SignatureScheme::RSA_PSS_SHA384 => Mechanism::Sha384RsaPkcsPss(pss(
+                MechanismType::SHA384,
+                PkcsMgfType::MGF1_SHA384,
+                48,
+            )),
+            SignatureScheme::RSA_PSS_SHA512 => Mechanism::Sha512RsaPkcsPss(pss(
+                MechanismType::SHA512,
+                PkcsMgfType::MGF1_SHA512,
+                64,
+            )),
+            SignatureScheme::ECDSA_NISTP256_SHA256 => Mechanism::EcdsaSha256,
+            SignatureScheme::ECDSA_NISTP384_SHA384 => Mechanism::EcdsaSha384,
+            scheme => {
+                return Err(Error::General(format!(
+                    "unsupported signature scheme {scheme:?}"
+                )));
+            }
+        };
+
+        let signature = self
+            .key
+            .session
+            .lock()
+            .expect("Unexpected poisoned mutex")
+            .sign(&mechanism, self.key.handle, message)
+            .map_err(|e| Error::General(format!("PKCS#11 signing failed: {e}")))?;
+
+        match mechanism {
+            // PKCS#11 returns r || s, TLS expects an ASN.1 structure
+            Mechanism::EcdsaSha256 | Mechanism::EcdsaSha384 => ecdsa_signature_to_der(&signature)
+                .ok_or_else(|| Error::General("invalid ECDSA signature from token".into())),
+            _ => Ok(signature),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+fn der_length(out: &mut Vec<u8>, length: usize) {
+    if length < 0x80 {
+        out.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+/// Encode a raw ECDSA signature, the concatenation of r and s, as the
+/// ASN.1 DER `SEQUENCE { r INTEGER, s INTEGER }`
+fn ecdsa_signature_to_der(signature: &[u8]) -> Option<Vec<u8>> {
+    if signature.is_empty() || !signature.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let (r, s) = signature.split_at(signature.len() / 2);
+    let mut integers = Vec::with_capacity(signature.len() + 6);
+    for integer in [r, s] {
+        let skip = integer.iter().take_while(|&&b| b == 0).count();
+        let integer = &integer[skip..];
+        // Integers are signed, so a leading bit that is set needs a zero
+        // in front of it. Zero itself is encoded as a single zero byte.
+        let pad = integer.first().is_none_or(|b| b & 0x80 != 0);
+
+        integers.push(TAG_INTEGER);
+        der_length(&mut integers, integer.len() + usize::from(pad));
+        if pad {
+            integers.push(0);
+        }
+        integers.extend_from_slice(integer);
+    }
+
+    let mut out = Vec::with_capacity(integers.len() + 4);
+    out.push(TAG_SEQUENCE);
+    der_length(&mut out, integers.len());
+    out.extend_from_slice(&integers);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ecdsa_signature_to_der() {
+        let mut signature = [0u8; 64];
+        signature[31] = 1;
+        signature[32] = 0x80;
+        assert_eq!(
+            ecdsa_signature_to_der(&signature).unwrap(),
+            [
+                &[0x30, 0x26, 0x02, 0x01, 0x01, 0x02, 0x21, 0x00, 0x80][..],
+                &[0; 31]
+            ]
+            .concat()
+        );
+
+        // Zero is encoded as a single byte
+        assert_eq!(
+            ecdsa_signature_to_der(&[0; 4]).unwrap(),
+            [0x30, 0x06, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00]
+        );
+
+        // P-384 signatures still fit in a short length
+        let der = ecdsa_signature_to_der(&[0xff; 96]).unwrap();
+        assert_eq!(der[..2], [0x30, 102]);
+        assert_eq!(der.len(), 104);
+
+        assert_eq!(ecdsa_signature_to_der(&[]), None);
+        assert_eq!(ecdsa_signature_to_der(&[1; 63]), None);
+    }
+
+    const USER_PIN: &str = "1234";
+
+    /// Initialize a free slot of the module as a token with an RSA and an
+    /// ECDSA P-256 key, and return the public keys in the form rustls
+    /// verifies signatures with
+    fn create_token(module_path: &std::path::Path, token_label: &str) -> (Vec<u8>, Vec<u8>) {
+        let pkcs11 = Pkcs11::new(module_path).unwrap();
+        pkcs11.initialize(CInitializeArgs::OsThreads).unwrap();
+
+        let slot = pkcs11
+            .get_slots_with_token()
+            .unwrap()
+            .into_iter()
+            .find(|slot| !pkcs11.get_token_info(*slot).unwrap().token_initialized())
+            .expect("no free slot on the PKCS#11 module");
+        let so_pin = AuthPin::new("5678".into());
+        pkcs11.init_token(slot, &so_pin, token_label).unwrap();
+
+        let session = pkcs11.open_rw_session(slot).unwrap();
+        session.login(UserType::So, Some(&so_pin)).unwrap();
+        session.init_pin(&AuthPin::new(USER_PIN.into())).unwrap();
+        session.logout().unwrap();
+        session
+            .login(UserType::User, Some(&AuthPin::new(USER_PIN.into())))
+            .unwrap();
+
+        let private = |label: &str| {
+            vec![
+                Attribute::Token(true),
+                Attribute::Private(true),
+                Attribute::Sensitive(true),
+                Attribute::Sign(true),
+                Attribute::Label(label.into()),
+            ]
+        };
+
+        let (rsa_public, _) = session
+            .generate_key_pair(
+                &Mechanism::RsaPkcsKeyPairGen,
+                &[
+                    Attribute::Token(true),
+                    Attribute::Verify(true),
+                    Attribute::ModulusBits(2048.into()),
+                    Attribute::PublicExponent(vec![0x01, 0x00, 0x01]),
+                ],
+                &private("rsa"),
+            )
+            .unwrap();
+        let (ec_public, _) = session
+            .generate_key_pair(
+                &Mechanism::EccKeyPairGen,
+                &[
+                    Attribute::Token(true),
+                    Attribute::Verify(true),
+                    Attribute::EcParams(EC_PARAMS_P256.to_vec()),
+                ],
+                &private("ec"),
+            )
+            .unwrap();
+
+        let rsa_public = match session
+            .get_attributes(
+                rsa_public,
+                &[AttributeType::Modulus, AttributeType::PublicExponent],
+            )
+            .unwrap()
+            .as_slice()
+        {
+            [
+                Attribute::Modulus(modulus),
+                Attribute::PublicExponent(exponent),
+            ] => {
+                // The RSAPublicKey structure is a sequence of two integers,
+                // just like an ECDSA signature once both have the same length
+                let mut padded = vec![0; modulus.len() - exponent.len()];
+                padded.extend_from_slice(exponent);
+                ecdsa_signature_to_der(&[modulus.as_slice(), &padded].concat()).unwrap()
+            }
+            other => panic!("unexpected attributes {other:?}"),
+        };
+        let ec_public = match session
+            .get_attributes(ec_public, &[AttributeType::EcPoint])
+            .unwrap()
+            .as_slice()
+        {
+            // The point is wrapped in a DER octet string
+            [Attribute::EcPoint(point)] => point[2..].to_vec(),
+            other => panic!("unexpected attributes {other:?}"),
+        };
+
+        (rsa_public, ec_public)
+    }
+
+    fn verify(scheme: SignatureScheme, public_key: &[u8], message: &[u8], signature: &[u8]) {
+        let provider = ntp_proto::tls_utils::server_config_builder()
+            .crypto_provider()
+            .clone();
+        let (_, algorithms) = provider
+            .signature_verification_algorithms
+            .mapping
+            .iter()
+            .find(|(supported, _)| *supported == scheme)
+            .unwrap();
+        assert!(algorithms.iter().any(|algorithm| {
+            algorithm
+                .verify_signature(public_key, message, signature)
+                .is_ok()
+        }));
+    }
+
+    // Runs against a software token, such as SoftHSM, when the module is
+    // given in NTPD_RS_TEST_PKCS11_MODULE. The module needs a free slot.
+    #[test]
+    fn test_sign_with_token() {
+        let Some(module_path) = std::env::var_os("NTPD_RS_TEST_PKCS11_MODULE") else {
+            eprintln!("NTPD_RS_TEST_PKCS11_MODULE is not set, skipping");
+            return;
+        };
+        let module_path = std::path::PathBuf::from(module_path);
+
+        let token_label = format!("ntpd-rs-test-{}", std::process::id());
+        let (rsa_public, ec_public) = create_token(&module_path, &token_label);
+
+        let pin_path = std::env::temp_dir().join(format!("{token_label}.pin"));
+        std::fs::write(&pin_path, format!("{USER_PIN}\n")).unwrap();
+
+        let message = b"NTS key exchange handshake transcript";
+
+        let config = |key_label: &str| Pkcs11KeyConfig {
+            module_path: module_path.clone(),
+            token_label: token_label.clone(),
+            key_label: key_label.into(),
+            pin_path: Some(pin_path.clone()),
+        };
+
+        // Each key holds on to the initialized module, which can only be
+        // initialized once, so they are opened one at a time
+        let rsa = open(&config("rsa"));
+        let ec = rsa.and_then(|rsa| {
+            assert_eq!(rsa.algorithm(), SignatureAlgorithm::RSA);
+            assert!(
+                rsa.choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+                    .is_none()
+            );
+            let signer = rsa
+                .choose_scheme(&[
+                    SignatureScheme::RSA_PSS_SHA256,
+                    SignatureScheme::ECDSA_NISTP256_SHA256,
+                ])
+                .unwrap();
+            assert_eq!(signer.scheme(), SignatureScheme::RSA_PSS_SHA256);
+            let signature = signer.sign(message).unwrap();
+            verify(signer.scheme(), &rsa_public, message, &signature);
+
+            drop((signer, rsa));
+            open(&config("ec"))
+        });
+        std::fs::remove_file(&pin_path).unwrap();
+
+        let ec = ec.unwrap();
+        assert_eq!(ec.algorithm(), SignatureAlgorithm::ECDSA);
+        let signer = ec
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .unwrap();
+        assert_eq!(signer.scheme(), SignatureScheme::ECDSA_NISTP256_SHA256);
+        let signature = signer.sign(message).unwrap();
+        verify(signer.scheme(), &ec_public, message, &signature);
+    }
+
+    #[test]
+    fn test_der_length() {
+        let mut out = vec![];
+        der_length(&mut out, 0x7f);
+        der_length(&mut out, 0x80);
+        der_length(&mut out, 0x1234);
+        assert_eq!(out, [0x7f, 0x81, 0x80, 0x82, 0x12, 0x34]);
+    }
+}
@@ -1,15 +1,28 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use ntp_proto::{
-    ClockId, Measurement, NtpDuration, NtpLeapIndicator, OneWaySource, SourceController,
+    ClockId, Measurement, NtpClock, NtpDuration, NtpLeapIndicator, NtpTimestamp,
+    ObservableSourceState, OneWaySource, SourceController,
 };
 use pps_time::PpsDevice;
 use tokio::sync::mpsc;
-use tracing::{Instrument, Span, debug, error, instrument, warn};
+use tracing::{Instrument, Span, debug, error, info, instrument, warn};
 
 use crate::daemon::util::convert_unix_timestamp;
 
-use super::ntp_source::SourceChannels;
+use super::{config::PpsEdge, measurements_log, ntp_source::SourceChannels};
+
+// Pulses whose timestamp is further than this from the current time are
+// discarded. This happens when the clock was stepped between the kernel
+// capturing the pulse and us processing it.
+const MAX_PULSE_AGE: NtpDuration = NtpDuration::from_exponent(-1);
+
+// Minimum time without pulses before we warn about missing pulses
+const MIN_MISSED_PULSE_TIMEOUT: f64 = 2.0;
+
+// The source numbering the seconds of the pulses must know the time better
+// than this (in seconds), so pulses can't be assigned to the wrong second
+const MAX_LOCK_UNCERTAINTY: f64 = 0.2;
 
 struct PpsDeviceFetchTask {
     pps: PpsDevice,
@@ -20,81 +33,234 @@ impl PpsDeviceFetchTask {
     fn run(&self) {
         loop {
             match self.pps.fetch_blocking() {
-                Err(e) => error!("PPS error: {}", e),
-                Ok(data) => self.fetch_sender.blocking_send(data).unwrap(),
+                Err(e) => {
+                    error!("PPS error: {}", e);
+                    // Avoid spinning on a persistently failing device
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+                Ok(data) => {
+                    if self.fetch_sender.blocking_send(data).is_err() {
+                        // The source task is gone, nobody needs our data anymore
+                        break;
+                    }
+                }
             }
         }
     }
 }
 
-pub(crate) struct PpsSourceTask<Controller: SourceController> {
+/// Turn a pulse captured at local time `pulse` into a one-way measurement
+///
+/// The pulse marks the start of a second, and is assigned to the second
+/// nearest to it, as seen from the system clock corrected by
+/// `coarse_offset`. This requires the corrected time to be within half a
+/// second of the true time. The `correction` configured for the source is
+/// applied to the measurement by its controller, here it only decides which
+/// second the pulse belongs to. Returns `None` when the pulse is too far from
+/// `now`, as the timestamp is then unreliable.
+fn pulse_measurement(
+    pulse: NtpTimestamp,
+    pulse_nanos: u32,
+    now: NtpTimestamp,
+    correction: f64,
+    coarse_offset: f64,
+) -> Option<(NtpTimestamp, NtpTimestamp)> {
+    if (pulse - now).abs() > MAX_PULSE_AGE {
+        return None;
+    }
+
+    // Fraction of a second by which the corrected pulse is past the nearest
+    // second
+    let mut fraction = (f64::from(pulse_nanos) * 1e-9 - correction + coarse_offset).rem_euclid(1.0);
+    if fraction >= 0.5 {
+        fraction -= 1.0;
+    }
+    let sender_ts = pulse + NtpDuration::from_seconds(coarse_offset - fraction - correction);
+
+    Some((sender_ts, pulse))
+}
+
+/// Offset of the system clock to the source named `lock`, if that source
+/// knows the time well enough to number the pulses
+fn locked_offset(
+    snapshots: &HashMap<ClockId, ObservableSourceState>,
+    own_id: ClockId,
+    lock: &str,
+) -> Option<f64> {
+    let state = snapshots
+        .values()
+        .find(|state| state.id != own_id && (state.address == lock || state.name == lock))?;
+    (state.timedata.uncertainty.to_seconds() < MAX_LOCK_UNCERTAINTY)
+        .then(|| state.timedata.offset.to_seconds())
+}
+
+pub(crate) struct PpsSourceTask<C: 'static + NtpClock + Send, Controller: SourceController> {
     index: ClockId,
+    clock: C,
     channels: SourceChannels,
     path: PathBuf,
+    edge: PpsEdge,
+    correction: f64,
+    lock: Option<String>,
+    missed_pulse_timeout: std::time::Duration,
     source: OneWaySource<Controller>,
     fetch_receiver: mpsc::Receiver<pps_time::pps::pps_fdata>,
+    last_sequence: Option<u32>,
 }
 
-impl<Controller: SourceController> PpsSourceTask<Controller> {
+impl<C, Controller: SourceController> PpsSourceTask<C, Controller>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
     async fn run(&mut self) {
+        let missed_pulse_wait = tokio::time::sleep(self.missed_pulse_timeout);
+        tokio::pin!(missed_pulse_wait);
+        let mut missing_pulses = false;
+
         loop {
             enum SelectResult {
                 PpsRecv(Option<pps_time::pps::pps_fdata>),
+                MissedPulses,
             }
 
             let selected: SelectResult = tokio::select! {
                 result = self.fetch_receiver.recv() => {
                     SelectResult::PpsRecv(result)
                 },
+                () = &mut missed_pulse_wait => {
+                    SelectResult::MissedPulses
+                },
             };
 
             match selected {
-                SelectResult::PpsRecv(result) => match result {
-                    Some(data) => {
-                        debug!("received {:?}", data);
-
-                        let measurement = Measurement {
-                            sender_id: self.index,
-                            receiver_id: ClockId::SYSTEM,
-                            sender_ts: convert_unix_timestamp(data.info.assert_tu.sec as _, 0),
-                            receiver_ts: convert_unix_timestamp(
-                                data.info.assert_tu.sec as _,
-                                data.info.assert_tu.nsec as _,
-                            ),
-
-                            root_delay: NtpDuration::ZERO,
-                            root_dispersion: NtpDuration::ZERO,
-                            leap: NtpLeapIndicator::NoWarning,
-                            precision: 0,
-                        };
-
-                        self.source.handle_measurement(measurement);
-
-                        self.channels
-                            .source_snapshots
-                            .write()
-                            .expect("Unexpected poisoned mutex")
-                            .insert(
-                                self.index,
-                                self.source.observe(
-                                    "PPS device".to_string(),
-                                    self.path.display().to_string(),
-                                    self.index,
-                                ),
-                            );
+                SelectResult::PpsRecv(result) => {
+                    let Some(data) = result else {
+                        error!("PPS device can no longer be read, stopping source");
+                        return;
+                    };
+                    debug!("received {:?}", data);
+
+                    let (sequence, timestamp) = match self.edge {
+                        PpsEdge::Assert => (data.info.assert_sequence, data.info.assert_tu),
+                        PpsEdge::Clear => (data.info.clear_sequence, data.info.clear_tu),
+                    };
+
+                    // The device also reports events for the other edge,
+                    // those leave the sequence number for our edge unchanged.
+                    if self.last_sequence == Some(sequence) {
+                        continue;
                     }
-                    None => {
+                    self.last_sequence = Some(sequence);
+
+                    missed_pulse_wait
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + self.missed_pulse_timeout);
+                    if missing_pulses {
+                        info!("Receiving PPS pulses again");
+                        missing_pulses = false;
+                    }
+
+                    self.handle_pulse(timestamp.sec as _, timestamp.nsec as _);
+                }
+                SelectResult::MissedPulses => {
+                    if !missing_pulses {
                         warn!("Did not receive any new PPS data");
+                        missing_pulses = true;
                     }
-                },
+                    missed_pulse_wait
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + self.missed_pulse_timeout);
+                }
             }
         }
     }
 
-    #[instrument(level = tracing::Level::ERROR, name = "Pps Source", skip(channels, source))]
+    fn handle_pulse(&mut self, seconds: u64, nanos: u32) {
+        let pulse = convert_unix_timestamp(seconds, nanos);
+        let now = match self.clock.now() {
+            Ok(now) => now,
+            Err(e) => {
+                error!(error = ?e, "There was an error retrieving the current time");
+                return;
+            }
+        };
+
+        // Without a lock the system clock, kept close to the time by other
+        // sources, numbers the seconds
+        let coarse_offset = match &self.lock {
+            None => 0.0,
+            Some(lock) => {
+                let snapshots = self
+                    .channels
+                    .source_snapshots
+                    .read()
+                    .expect("Unexpected poisoned mutex");
+                if let Some(coarse_offset) = locked_offset(&snapshots, self.index, lock) {
+                    coarse_offset
+                } else {
+                    debug!(
+                        lock = lock.as_str(),
+                        "Ignoring PPS pulse, the source it is locked to has no usable time"
+                    );
+                    return;
+                }
+            }
+        };
+
+        let Some((sender_ts, receiver_ts)) =
+            pulse_measurement(pulse, nanos, now, self.correction, coarse_offset)
+        else {
+            debug!("Ignoring PPS pulse with a timestamp too far from the current time");
+            return;
+        };
+
+        let measurement = Measurement {
+            sender_id: self.index,
+            receiver_id: ClockId::SYSTEM,
+            sender_ts,
+            receiver_ts,
+
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            stratum: 0,
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+        };
+
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record(
+                "PPS device",
+                &self.path.display().to_string(),
+                self.index,
+                &measurement,
+            );
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source.observe(
+                    "PPS device".to_string(),
+                    self.path.display().to_string(),
+                    self.index,
+                ),
+            );
+    }
+
+    #[expect(clippy::too_many_arguments)]
+    #[instrument(level = tracing::Level::ERROR, name = "Pps Source", skip(clock, channels, source))]
     pub fn spawn(
         index: ClockId,
         device_path: PathBuf,
+        edge: PpsEdge,
+        correction: f64,
+        lock: Option<String>,
+        period: f64,
+        clock: C,
         channels: SourceChannels,
         source: OneWaySource<Controller>,
     ) -> tokio::task::JoinHandle<()> {
@@ -104,6 +270,14 @@ impl<Controller: SourceController> PpsSourceTask<Controller> {
             cap & pps_time::pps::PPS_CANWAIT != 0,
             "PPS device does not support blocking calls"
         );
+        let edge_cap = match edge {
+            PpsEdge::Assert => pps_time::pps::PPS_CAPTUREASSERT,
+            PpsEdge::Clear => pps_time::pps::PPS_CAPTURECLEAR,
+        };
+        assert!(
+            cap & edge_cap != 0,
+            "PPS device can not capture the configured edge"
+        );
 
         let (fetch_sender, fetch_receiver) = mpsc::channel(1);
 
@@ -117,10 +291,18 @@ impl<Controller: SourceController> PpsSourceTask<Controller> {
             (async move {
                 let mut process = PpsSourceTask {
                     index,
+                    clock,
                     channels,
                     path: device_path,
+                    edge,
+                    correction,
+                    lock,
+                    missed_pulse_timeout: std::time::Duration::from_secs_f64(
+                        (2.0 * period).max(MIN_MISSED_PULSE_TIMEOUT),
+                    ),
                     source,
                     fetch_receiver,
+                    last_sequence: None,
                 };
 
                 process.run().await;
@@ -129,3 +311,86 @@ impl<Controller: SourceController> PpsSourceTask<Controller> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement_offset(
+        seconds: u64,
+        nanos: u32,
+        now: NtpTimestamp,
+        correction: f64,
+    ) -> Option<f64> {
+        measurement_offset_locked(seconds, nanos, now, correction, 0.0)
+    }
+
+    /// The offset of the measurement, after the source controller applied
+    /// the correction
+    fn measurement_offset_locked(
+        seconds: u64,
+        nanos: u32,
+        now: NtpTimestamp,
+        correction: f64,
+        coarse_offset: f64,
+    ) -> Option<f64> {
+        let (sender_ts, receiver_ts) = pulse_measurement(
+            convert_unix_timestamp(seconds, nanos),
+            nanos,
+            now,
+            correction,
+            coarse_offset,
+        )?;
+        Some((sender_ts - receiver_ts).to_seconds() + correction)
+    }
+
+    #[test]
+    fn test_pulse_measurement() {
+        let now = convert_unix_timestamp(1000, 0);
+
+        // Clock slightly ahead
+        let offset = measurement_offset(1000, 1_000_000, now, 0.0).unwrap();
+        assert!((offset + 1e-3).abs() < 1e-9);
+
+        // Clock slightly behind, the pulse belongs to the next second
+        let offset = measurement_offset(999, 999_000_000, now, 0.0).unwrap();
+        assert!((offset - 1e-3).abs() < 1e-9);
+
+        // The static offset is compensated for
+        let offset = measurement_offset(1000, 1_000_000, now, 1.5e-3).unwrap();
+        assert!((offset - 0.5e-3).abs() < 1e-9);
+        let offset = measurement_offset(999, 999_000_000, now, -1.5e-3).unwrap();
+        assert!((offset + 0.5e-3).abs() < 1e-9);
+
+        // The corrected pulse decides the nearest second
+        let offset = measurement_offset(1000, 400_000_000, now, -0.2).unwrap();
+        assert!((offset - 0.4).abs() < 1e-9);
+
+        // Resolved against the nearest second
+        let offset = measurement_offset(1000, 400_000_000, now, 0.0).unwrap();
+        assert!((offset + 0.4).abs() < 1e-9);
+        let offset = measurement_offset(999, 600_000_000, now, 0.0).unwrap();
+        assert!((offset - 0.4).abs() < 1e-9);
+
+        // Pulses far from the current time, e.g. due to a clock step, are ignored
+        assert_eq!(measurement_offset(998, 1_000_000, now, 0.0), None);
+        assert_eq!(measurement_offset(1001, 1_000_000, now, 0.0), None);
+    }
+
+    #[test]
+    fn test_locked_pulse_measurement() {
+        let now = convert_unix_timestamp(1000, 0);
+
+        // The source the pulses are locked to puts the system clock 5 seconds
+        // behind, so the pulse belongs 5 seconds later than it seems
+        let offset = measurement_offset_locked(1000, 1_000_000, now, 0.0, 5.0).unwrap();
+        assert!((offset - (5.0 - 1e-3)).abs() < 1e-9);
+
+        // The pulse determines the fraction of the second, the locked source
+        // only which second it is
+        let offset = measurement_offset_locked(1000, 1_000_000, now, 0.0, -2.3).unwrap();
+        assert!((offset + 2.001).abs() < 1e-9);
+        let offset = measurement_offset_locked(999, 999_000_000, now, 0.0, 0.45).unwrap();
+        assert!((offset - 1e-3).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,634 @@
+//! Source acting as a PTP (IEEE 1588) ordinary clock that only ever listens,
+//! using the end-to-end delay mechanism over UDP. Of the masters announcing
+//! themselves in the configured domain, the best one is selected by
+//! comparing their datasets like the best master clock algorithm does. Its
+//! sync messages give the time, delay requests the delay to it. Timestamps
+//! are taken in software, like for broadcast sources.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use ntp_proto::{
+    ClockId, Measurement, NtpClock, NtpDuration, NtpLeapIndicator, NtpTimestamp, OneWaySource,
+    SourceController,
+};
+use statime_wire::{
+    AnnounceMessage, ClockIdentity, DelayReqMessage, DelayRespMessage, Header, Message,
+    MessageBody, PortIdentity, TlvSet,
+};
+use tokio::{net::UdpSocket, sync::watch};
+use tracing::{Instrument, Span, debug, error, info, instrument, warn};
+
+use crate::exitcode;
+
+use super::{
+    latency::SoftwareLatency, measurements_log, ntp_source::SourceChannels,
+    sock_source::precision_exponent, util::convert_unix_timestamp,
+};
+
+const EVENT_PORT: u16 = 319;
+const GENERAL_PORT: u16 = 320;
+// Masters are forgotten after missing this many announce messages
+const ANNOUNCE_RECEIPT_TIMEOUT: f64 = 3.0;
+// Seconds between two delay requests
+const DELAY_REQUEST_INTERVAL: f64 = 1.0;
+
+fn convert_ptp_timestamp(timestamp: statime_wire::Timestamp) -> NtpTimestamp {
+    convert_unix_timestamp(timestamp.seconds(), timestamp.nanos())
+}
+
+fn correction(header: &Header) -> NtpDuration {
+    NtpDuration::from_seconds(header.correction_field.to_nanos() * 1e-9)
+}
+
+/// What masters are compared on, lower is better
+fn dataset(announce: &AnnounceMessage) -> impl Ord {
+    let quality = announce.grandmaster_clock_quality;
+    (
+        announce.grandmaster_priority_1,
+        quality.clock_class,
+        quality.clock_accuracy,
+        quality.offset_scaled_log_variance,
+        announce.grandmaster_priority_2,
+        announce.grandmaster_identity,
+        announce.steps_removed,
+        announce.header.source_port_identity,
+    )
+}
+
+/// How far the timescale of the master is ahead of UTC, if known
+fn utc_offset(announce: &AnnounceMessage) -> Option<NtpDuration> {
+    if !announce.header.ptp_timescale {
+        // An arbitrary timescale, which in practice is UTC
+        Some(NtpDuration::ZERO)
+    } else if announce.header.current_utc_offset_valid {
+        Some(NtpDuration::from_seconds(
+            announce.current_utc_offset.into(),
+        ))
+    } else {
+        None
+    }
+}
+
+fn leap(header: &Header) -> NtpLeapIndicator {
+    if header.leap61 {
+        NtpLeapIndicator::Leap61
+    } else if header.leap59 {
+        NtpLeapIndicator::Leap59
+    } else {
+        NtpLeapIndicator::NoWarning
+    }
+}
+
+#[derive(Debug)]
+struct ForeignMaster {
+    announce: AnnounceMessage,
+    received: NtpTimestamp,
+}
+
+/// A two-step sync message waiting for its follow up
+#[derive(Debug, Clone, Copy)]
+struct PendingSync {
+    sequence_id: u16,
+    correction: NtpDuration,
+    received: NtpTimestamp,
+}
+
+/// The exchanges with the masters, apart from the actual sending and
+/// receiving
+#[derive(Debug)]
+struct PtpPort {
+    identity: PortIdentity,
+    domain: u8,
+    index: ClockId,
+    precision: i8,
+    masters: BTreeMap<PortIdentity, ForeignMaster>,
+    selected: Option<PortIdentity>,
+    pending_sync: Option<PendingSync>,
+    /// Send time at the master and receive time here of the last sync
+    last_sync: Option<(NtpTimestamp, NtpTimestamp)>,
+    /// Sequence id and send time of the last delay request
+    delay_request: Option<(u16, NtpTimestamp)>,
+    sequence_id: u16,
+    delay: Option<NtpDuration>,
+}
+
+impl PtpPort {
+    fn new(domain: u8, index: ClockId, precision: i8) -> Self {
+        PtpPort {
+            identity: PortIdentity {
+                clock_identity: ClockIdentity(rand::random()),
+                port_number: 1,
+            },
+            domain,
+            index,
+            precision,
+            masters: BTreeMap::new(),
+            selected: None,
+            pending_sync: None,
+            last_sync: None,
+            delay_request: None,
+            sequence_id: 0,
+            delay: None,
+        }
+    }
+
+    /// Handle a message, giving a measurement when it completes a sync
+    fn handle_message(&mut self, data: &[u8], recv_time: NtpTimestamp) -> Option<Measurement> {
+        let message = match Message::deserialize(data) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!(error = %e, "Could not parse PTP message");
+                return None;
+            }
+        };
+        let header = message.header;
+        if header.domain_number != self.domain {
+            return None;
+        }
+
+        self.forget_silent_masters(recv_time);
+        match message.body {
+            MessageBody::Announce(announce) => {
+                self.handle_announce(announce, recv_time);
+                None
+            }
+            _ if self.selected != Some(header.source_port_identity) => None,
+            MessageBody::Sync(_) if header.two_step_flag => {
+                self.pending_sync = Some(PendingSync {
+                    sequence_id: header.sequence_id,
+                    correction: correction(&header),
+                    received: recv_time,
+                });
+                None
+            }
+            MessageBody::Sync(sync) => self.complete_sync(
+                convert_ptp_timestamp(sync.origin_timestamp) + correction(&header),
+                recv_time,
+            ),
+            MessageBody::FollowUp(follow_up) => {
+                let pending = self
+                    .pending_sync
+                    .take_if(|pending| pending.sequence_id == header.sequence_id)?;
+                self.complete_sync(
+                    convert_ptp_timestamp(follow_up.precise_origin_timestamp)
+                        + pending.correction
+                        + correction(&header),
+                    pending.received,
+                )
+            }
+            MessageBody::DelayResp(response) => {
+                self.handle_delay_response(&header, &response);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_announce(&mut self, announce: AnnounceMessage, recv_time: NtpTimestamp) {
+        self.masters.insert(
+            announce.header.source_port_identity,
+            ForeignMaster {
+                announce,
+                received: recv_time,
+            },
+        );
+        self.select_master();
+    }
+
+    fn forget_silent_masters(&mut self, now: NtpTimestamp) {
+        let count = self.masters.len();
+        self.masters.retain(|_, master| {
+            let interval = 2f64.powi(master.announce.header.log_message_interval.into());
+            (now - master.received).to_seconds() < ANNOUNCE_RECEIPT_TIMEOUT * interval
+        });
+        if self.masters.len() != count {
+            self.select_master();
+        }
+    }
+
+    fn select_master(&mut self) {
+        let best = self
+            .masters
+            .iter()
+            .min_by(|(_, a), (_, b)| dataset(&a.announce).cmp(&dataset(&b.announce)))
+            .map(|(identity, _)| *identity);
+
+        if best != self.selected {
+            if let Some(identity) = best {
+                info!(master = %identity.clock_identity, "Selected PTP master");
+            } else {
+                warn!("No PTP master left");
+            }
+            self.selected = best;
+            // Exchanges with the previous master are of no use
+            self.pending_sync = None;
+            self.last_sync = None;
+            self.delay_request = None;
+            self.delay = None;
+        }
+    }
+
+    fn complete_sync(
+        &mut self,
+        master_send: NtpTimestamp,
+        received: NtpTimestamp,
+    ) -> Option<Measurement> {
+        self.last_sync = Some((master_send, received));
+
+        let delay = self.delay?;
+        let announce = &self.masters.get(&self.selected?)?.announce;
+        let Some(utc_offset) = utc_offset(announce) else {
+            debug!("Master does not know the offset between TAI and UTC");
+            return None;
+        };
+
+        Some(Measurement {
+            sender_id: self.index,
+            receiver_id: ClockId::SYSTEM,
+            // The time at the master when the sync reached us
+            sender_ts: master_send - utc_offset + delay,
+            receiver_ts: received,
+            root_delay: delay + delay,
+            root_dispersion: NtpDuration::ZERO,
+            stratum: 1,
+            leap: leap(&announce.header),
+            precision: self.precision,
+        })
+    }
+
+    /// Whether a delay request should be sent after the last sync
+    fn delay_request_due(&self) -> bool {
+        let Some((_, received)) = self.last_sync else {
+            return false;
+        };
+        self.delay_request
+            .is_none_or(|(_, sent)| (received - sent).to_seconds() >= DELAY_REQUEST_INTERVAL)
+    }
+
+    /// A new delay request, to be sent at `send_time`
+    fn new_delay_request(&mut self, send_time: NtpTimestamp) -> Vec<u8> {
+        self.sequence_id = self.sequence_id.wrapping_add(1);
+        self.delay_request = Some((self.sequence_id, send_time));
+
+        let mut header = Header::new(1);
+        header.domain_number = self.domain;
+        header.source_port_identity = self.identity;
+        header.sequence_id = self.sequence_id;
+        header.log_message_interval = 0x7f;
+        let message = Message {
+            header,
+            body: MessageBody::DelayReq(DelayReqMessage {
+                origin_timestamp: statime_wire::Timestamp::default(),
+            }),
+            suffix: TlvSet::default(),
+        };
+
+        let mut buf = [0; 64];
+        let size = message
+            .serialize(&mut buf)
+            .expect("Delay request does not fit in buffer");
+        buf[..size].to_vec()
+    }
+
+    fn handle_delay_response(&mut self, header: &Header, response: &DelayRespMessage) {
+        if response.requesting_port_identity != self.identity {
+            return;
+        }
+        let Some((sequence_id, sent)) = self.delay_request else {
+            return;
+        };
+        let Some((master_send, received)) = self.last_sync else {
+            return;
+        };
+        if header.sequence_id != sequence_id {
+            return;
+        }
+
+        let master_receive = convert_ptp_timestamp(response.receive_timestamp) - correction(header);
+        let delay: NtpDuration = ((received - master_send) + (master_receive - sent)) / 2;
+        debug!(?delay, "Measured delay to PTP master");
+        self.delay = Some(delay.max(NtpDuration::ZERO));
+    }
+}
+
+async fn create_socket(group: IpAddr, port: u16) -> std::io::Result<UdpSocket> {
+    match group {
+        IpAddr::V4(group) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).await?;
+            socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+            Ok(socket)
+        }
+        IpAddr::V6(group) => {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, port)).await?;
+            socket.join_multicast_v6(&group, 0)?;
+            Ok(socket)
+        }
+    }
+}
+
+pub(crate) struct PtpSourceTask<C: 'static + NtpClock + Send, Controller: SourceController> {
+    index: ClockId,
+    event_socket: UdpSocket,
+    general_socket: UdpSocket,
+    group: IpAddr,
+    clock: C,
+    software_latency: watch::Receiver<SoftwareLatency>,
+    channels: SourceChannels,
+    source: OneWaySource<Controller>,
+    port: PtpPort,
+}
+
+impl<C, Controller: SourceController> PtpSourceTask<C, Controller>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
+    async fn run(&mut self) {
+        let mut event_buf = [0; 1024];
+        let mut general_buf = [0; 1024];
+        loop {
+            let received = tokio::select! {
+                result = self.event_socket.recv(&mut event_buf) => result.map(|size| (size, true)),
+                result = self.general_socket.recv(&mut general_buf) => result.map(|size| (size, false)),
+            };
+            let recv_time = self.now();
+            let recv_time = self.software_latency.borrow().compensate_recv(recv_time);
+
+            let measurement = match received {
+                Ok((size, true)) => self.port.handle_message(&event_buf[..size], recv_time),
+                Ok((size, false)) => self.port.handle_message(&general_buf[..size], recv_time),
+                Err(e) => {
+                    warn!(error = ?e, "Could not receive PTP message, recreating sockets");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+            };
+
+            if let Some(measurement) = measurement {
+                self.handle_measurement(measurement);
+            }
+
+            if self.port.delay_request_due() {
+                self.send_delay_request().await;
+            }
+        }
+    }
+
+    fn handle_measurement(&mut self, measurement: Measurement) {
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record("PTP", &self.group.to_string(), self.index, &measurement);
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source
+                    .observe("PTP".to_string(), self.group.to_string(), self.index),
+            );
+    }
+
+    async fn send_delay_request(&mut self) {
+        let send_time = self.now();
+        let send_time = self.software_latency.borrow().compensate_send(send_time);
+        let request = self.port.new_delay_request(send_time);
+        if let Err(e) = self
+            .event_socket
+            .send_to(&request, (self.group, EVENT_PORT))
+            .await
+        {
+            debug!(error = ?e, "Could not send delay request");
+        }
+    }
+
+    fn now(&self) -> NtpTimestamp {
+        match self.clock.now() {
+            Ok(time) => time,
+            Err(e) => {
+                error!(error = ?e, "There was an error retrieving the current time");
+                exitcode::exit(exitcode::NOPERM);
+            }
+        }
+    }
+
+    #[expect(clippy::too_many_arguments)]
+    #[instrument(level = tracing::Level::ERROR, name = "PTP Source", skip(clock, software_latency, channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        group: IpAddr,
+        domain: u8,
+        precision: f64,
+        clock: C,
+        software_latency: watch::Receiver<SoftwareLatency>,
+        channels: SourceChannels,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let sockets = async {
+                    let event_socket = create_socket(group, EVENT_PORT).await?;
+                    let general_socket = create_socket(group, GENERAL_PORT).await?;
+                    std::io::Result::Ok((event_socket, general_socket))
+                };
+                let (event_socket, general_socket) = match sockets.await {
+                    Ok(sockets) => sockets,
+                    Err(e) => {
+                        warn!(error = ?e, "Could not create PTP sockets");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
+                let mut process = PtpSourceTask {
+                    index,
+                    event_socket,
+                    general_socket,
+                    group,
+                    clock,
+                    software_latency,
+                    channels,
+                    source,
+                    port: PtpPort::new(domain, index, precision_exponent(precision)),
+                };
+
+                process.run().await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use statime_wire::{
+        ClockAccuracy, ClockQuality, FollowUpMessage, SyncMessage, TimeInterval, TimeSource,
+        Timestamp,
+    };
+
+    use super::*;
+
+    const EPOCH: u64 = 1_704_067_200;
+
+    fn master(id: u8) -> PortIdentity {
+        PortIdentity {
+            clock_identity: ClockIdentity([id; 8]),
+            port_number: 1,
+        }
+    }
+
+    fn serialize(header: Header, body: MessageBody) -> Vec<u8> {
+        let message = Message {
+            header,
+            body,
+            suffix: TlvSet::default(),
+        };
+        let mut buf = [0; 128];
+        let size = message.serialize(&mut buf).unwrap();
+        buf[..size].to_vec()
+    }
+
+    fn header(sender: PortIdentity, sequence_id: u16) -> Header {
+        let mut header = Header::new(1);
+        header.source_port_identity = sender;
+        header.sequence_id = sequence_id;
+        header
+    }
+
+    fn announce(sender: PortIdentity, priority_1: u8) -> Vec<u8> {
+        let mut header = header(sender, 0);
+        header.ptp_timescale = true;
+        header.current_utc_offset_valid = true;
+        serialize(
+            header,
+            MessageBody::Announce(AnnounceMessage {
+                header,
+                origin_timestamp: Timestamp::default(),
+                current_utc_offset: 37,
+                grandmaster_priority_1: priority_1,
+                grandmaster_clock_quality: ClockQuality {
+                    clock_class: 6,
+                    clock_accuracy: ClockAccuracy::NS100,
+                    offset_scaled_log_variance: 0x4e5d,
+                },
+                grandmaster_priority_2: 128,
+                grandmaster_identity: sender.clock_identity,
+                steps_removed: 0,
+                time_source: TimeSource::Gnss,
+            }),
+        )
+    }
+
+    fn time(seconds: u64, nanos: u32) -> NtpTimestamp {
+        convert_unix_timestamp(seconds, nanos)
+    }
+
+    #[test]
+    fn test_select_master() {
+        let mut port = PtpPort::new(0, ClockId::new(), -20);
+        port.handle_message(&announce(master(1), 128), time(EPOCH, 0));
+        assert_eq!(port.selected, Some(master(1)));
+
+        // Lower priorities are preferred
+        port.handle_message(&announce(master(2), 100), time(EPOCH, 1000));
+        assert_eq!(port.selected, Some(master(2)));
+
+        // Masters in other domains are ignored
+        let mut data = announce(master(3), 10);
+        data[4] = 1;
+        port.handle_message(&data, time(EPOCH, 2000));
+        assert_eq!(port.selected, Some(master(2)));
+
+        // Until the best master goes silent
+        port.handle_message(&announce(master(1), 128), time(EPOCH + 4, 0));
+        assert_eq!(port.selected, Some(master(1)));
+    }
+
+    #[test]
+    fn test_two_step_exchange() {
+        let mut port = PtpPort::new(0, ClockId::new(), -20);
+        port.handle_message(&announce(master(1), 128), time(EPOCH, 0));
+
+        // The master is on TAI, 37 seconds ahead, and 10 microseconds ahead
+        // of us besides. Messages take 50 microseconds either way.
+        let mut sync = header(master(1), 1);
+        sync.two_step_flag = true;
+        sync.correction_field = TimeInterval::from_nanos(1000.0).unwrap();
+        let sync = serialize(
+            sync,
+            MessageBody::Sync(SyncMessage {
+                origin_timestamp: Timestamp::default(),
+            }),
+        );
+        assert!(port.handle_message(&sync, time(EPOCH, 41_000)).is_none());
+        let follow_up = serialize(
+            header(master(1), 1),
+            MessageBody::FollowUp(FollowUpMessage {
+                precise_origin_timestamp: Timestamp::new(EPOCH + 37, 0).unwrap(),
+            }),
+        );
+        // No measurement without a delay
+        assert!(
+            port.handle_message(&follow_up, time(EPOCH, 100_000))
+                .is_none()
+        );
+
+        assert!(port.delay_request_due());
+        let request = port.new_delay_request(time(EPOCH, 200_000));
+        let Ok(Message {
+            header: request_header,
+            body: MessageBody::DelayReq(_),
+            ..
+        }) = Message::deserialize(&request)
+        else {
+            panic!("Expected a delay request");
+        };
+        assert!(!port.delay_request_due());
+
+        // Responses to someone else are ignored
+        let mut response = DelayRespMessage {
+            receive_timestamp: Timestamp::new(EPOCH + 37, 260_000).unwrap(),
+            requesting_port_identity: master(9),
+        };
+        let data = serialize(
+            header(master(1), request_header.sequence_id),
+            MessageBody::DelayResp(response),
+        );
+        port.handle_message(&data, time(EPOCH, 300_000));
+        assert!(port.delay.is_none());
+
+        response.requesting_port_identity = request_header.source_port_identity;
+        let data = serialize(
+            header(master(1), request_header.sequence_id),
+            MessageBody::DelayResp(response),
+        );
+        port.handle_message(&data, time(EPOCH, 300_000));
+        assert!((port.delay.unwrap().to_seconds() - 50e-6).abs() < 1e-9);
+
+        let mut sync = header(master(1), 2);
+        sync.two_step_flag = true;
+        let sync = serialize(
+            sync,
+            MessageBody::Sync(SyncMessage {
+                origin_timestamp: Timestamp::default(),
+            }),
+        );
+        port.handle_message(&sync, time(EPOCH + 1, 40_000));
+        let follow_up = serialize(
+            header(master(1), 2),
+            MessageBody::FollowUp(FollowUpMessage {
+                precise_origin_timestamp: Timestamp::new(EPOCH + 38, 0).unwrap(),
+            }),
+        );
+        let measurement = port
+            .handle_message(&follow_up, time(EPOCH + 1, 100_000))
+            .unwrap();
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset - 10e-6).abs() < 1e-9);
+        assert!((measurement.root_delay.to_seconds() - 100e-6).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use ntp_proto::{
+    ClockId, Measurement, NtpClock, NtpDuration, NtpLeapIndicator, NtpTimestamp, OneWaySource,
+    PollInterval, SourceController, roughtime,
+};
+use tokio::net::UdpSocket;
+use tracing::{Instrument, Span, debug, error, info, instrument, warn};
+
+use crate::exitcode;
+
+use super::ntp_source::SourceChannels;
+
+// Time after which we give up on a single request
+const RESPONSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+pub(crate) struct RoughtimeSourceTask<C: 'static + NtpClock + Send, Controller: SourceController> {
+    index: ClockId,
+    socket: UdpSocket,
+    clock: C,
+    addr: SocketAddr,
+    public_key: [u8; roughtime::PUBLIC_KEY_SIZE],
+    poll_interval: PollInterval,
+    channels: SourceChannels,
+    source: OneWaySource<Controller>,
+}
+
+async fn create_socket(server: SocketAddr) -> std::io::Result<UdpSocket> {
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    Ok(socket)
+}
+
+/// Turn a verified response into a measurement. The midpoint is assumed to
+/// lie halfway between sending the request and receiving the response. Also
+/// returns whether the local clock is within the bounds given by the server.
+fn response_measurement(
+    result: roughtime::QueryResult,
+    send_time: NtpTimestamp,
+    recv_time: NtpTimestamp,
+    index: ClockId,
+) -> (Measurement, bool) {
+    let half_round_trip = (recv_time - send_time) / 2;
+    let local_time = send_time + half_round_trip;
+    let bound = result.radius + half_round_trip;
+
+    let measurement = Measurement {
+        sender_id: index,
+        receiver_id: ClockId::SYSTEM,
+        sender_ts: result.midpoint,
+        receiver_ts: local_time,
+
+        root_delay: NtpDuration::ZERO,
+        root_dispersion: result.radius,
+        stratum: 1,
+        leap: NtpLeapIndicator::NoWarning,
+        precision: bound.to_seconds().log2().ceil() as i8,
+    };
+
+    (measurement, (result.midpoint - local_time).abs() <= bound)
+}
+
+impl<C, Controller: SourceController> RoughtimeSourceTask<C, Controller>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
+    async fn run(&mut self) {
+        loop {
+            let request = roughtime::request();
+            let send_time = self.now();
+            if let Err(e) = self.socket.send(request.as_bytes()).await {
+                warn!(error = ?e, "Could not send request, recreating socket");
+                self.channels.report_network_issue(self.index).await;
+                return;
+            }
+
+            let mut buf = [0; 2048];
+            match tokio::time::timeout(RESPONSE_TIMEOUT, self.socket.recv(&mut buf)).await {
+                Ok(Ok(size)) => {
+                    let recv_time = self.now();
+                    self.handle_response(&request, &buf[..size], send_time, recv_time);
+                }
+                Ok(Err(e)) => {
+                    warn!(error = ?e, "Could not receive response, recreating socket");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
+                Err(_) => info!("Roughtime request timed out"),
+            }
+
+            tokio::time::sleep(self.poll_interval.as_system_duration()).await;
+        }
+    }
+
+    fn handle_response(
+        &mut self,
+        request: &roughtime::Request,
+        response: &[u8],
+        send_time: NtpTimestamp,
+        recv_time: NtpTimestamp,
+    ) {
+        let result = match roughtime::query(request, response, &self.public_key) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!(error = %e, "Rejected response from Roughtime server");
+                return;
+            }
+        };
+        debug!(midpoint = %result.midpoint, radius = %result.radius, "received response");
+
+        let (measurement, within_bounds) =
+            response_measurement(result, send_time, recv_time, self.index);
+        if !within_bounds {
+            warn!(
+                offset = (result.midpoint - measurement.receiver_ts).to_seconds(),
+                radius = result.radius.to_seconds(),
+                "System clock is outside the bounds given by Roughtime server"
+            );
+        }
+        self.source.handle_measurement(measurement);
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source
+                    .observe("Roughtime".to_string(), self.addr.to_string(), self.index),
+            );
+    }
+
+    fn now(&self) -> NtpTimestamp {
+        match self.clock.now() {
+            Ok(time) => time,
+            Err(e) => {
+                error!(error = ?e, "There was an error retrieving the current time");
+                exitcode::exit(exitcode::NOPERM);
+            }
+        }
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "Roughtime Source", skip(public_key, clock, channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        addr: SocketAddr,
+        public_key: [u8; roughtime::PUBLIC_KEY_SIZE],
+        poll_interval: PollInterval,
+        clock: C,
+        channels: SourceChannels,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let socket = match create_socket(addr).await {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        warn!(error = ?e, "Could not create socket");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
+                let mut process = RoughtimeSourceTask {
+                    index,
+                    socket,
+                    clock,
+                    addr,
+                    public_key,
+                    poll_interval,
+                    channels,
+                    source,
+                };
+
+                process.run().await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{ClockId, NtpDuration, NtpTimestamp, roughtime::QueryResult};
+
+    use super::response_measurement;
+
+    #[test]
+    fn test_response_measurement() {
+        let send_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let recv_time = NtpTimestamp::from_seconds_nanos_since_ntp_era(1002, 0);
+        let index = ClockId::new();
+
+        let result = QueryResult {
+            midpoint: NtpTimestamp::from_seconds_nanos_since_ntp_era(1006, 0),
+            radius: NtpDuration::from_seconds(4.0),
+        };
+        let (measurement, within_bounds) =
+            response_measurement(result, send_time, recv_time, index);
+        assert_eq!(measurement.sender_id, index);
+        assert_eq!(measurement.sender_ts, result.midpoint);
+        assert_eq!(
+            measurement.receiver_ts,
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(1001, 0)
+        );
+        assert_eq!(measurement.root_dispersion, result.radius);
+        assert!(within_bounds);
+
+        // The round trip adds to the uncertainty
+        let result = QueryResult {
+            midpoint: NtpTimestamp::from_seconds_nanos_since_ntp_era(996, 0),
+            radius: NtpDuration::from_seconds(4.0),
+        };
+        let (_, within_bounds) = response_measurement(result, send_time, recv_time, index);
+        assert!(within_bounds);
+
+        let result = QueryResult {
+            midpoint: NtpTimestamp::from_seconds_nanos_since_ntp_era(1007, 0),
+            radius: NtpDuration::from_seconds(4.0),
+        };
+        let (_, within_bounds) = response_measurement(result, send_time, recv_time, index);
+        assert!(!within_bounds);
+    }
+}
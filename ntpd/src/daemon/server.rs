@@ -1,22 +1,48 @@
-use std::{
-    sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering},
-    },
-    time::Duration,
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
 };
 
-use ntp_proto::{KeySet, NtpClock, Server, ServerReason, ServerResponse, ServerStatHandler};
+use ntp_proto::{
+    AeadAlgorithm, KeyExchangeStatHandler, ServerReason, ServerResponse, ServerStatHandler,
+};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use timestamped_socket::socket::{RecvResult, open_ip};
-use tokio::task::JoinHandle;
+
+#[cfg(feature = "server")]
+use std::{net::SocketAddr, time::Duration};
+
+#[cfg(all(feature = "server", target_os = "linux"))]
+use ntp_mmsg::{BatchSocket, RecvBatch};
+#[cfg(feature = "server")]
+use ntp_proto::{KeySet, NtpClock, Server};
+#[cfg(all(feature = "server", not(target_os = "linux")))]
+use timestamped_socket::socket::{Open, RecvResult, Socket, open_ip};
+#[cfg(feature = "server")]
+use tokio::{net::UdpSocket, task::JoinHandle};
+#[cfg(feature = "server")]
 use tracing::{Instrument, Span, debug, instrument, warn};
 
-use super::{config::ServerConfig, util::convert_net_timestamp};
+#[cfg(all(feature = "server", target_os = "linux"))]
+use super::util::convert_batch_timestamp;
+#[cfg(all(feature = "server", not(target_os = "linux")))]
+use super::util::convert_net_timestamp;
+#[cfg(feature = "server")]
+use super::{
+    audit::{self, AuditEvent},
+    config::ServerConfig,
+};
 
 // Maximum size of udp packet we handle
+#[cfg(feature = "server")]
 const MAX_PACKET_SIZE: usize = 1024;
 
+// Maximum number of packets handled each time the server task is woken up.
+// On Linux, these are received with a single system call.
+#[cfg(feature = "server")]
+const BATCH_SIZE: usize = 32;
+
+// The statistics are always available so that the observer and metrics keep
+// a stable format, even when the server itself is compiled out.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ServerStats {
     pub received_packets: Counter,
@@ -30,6 +56,10 @@ pub struct ServerStats {
     pub nts_denied_packets: Counter,
     pub nts_rate_limited_packets: Counter,
     pub nts_nak_packets: Counter,
+    pub broadcast_packets: Counter,
+    /// Requests with an invalid NTS cookie, authenticator or MAC
+    #[serde(default)]
+    pub authentication_failures: Counter,
 }
 
 impl ServerStatHandler for ServerStats {
@@ -50,6 +80,10 @@ impl ServerStatHandler for ServerStats {
             (ServerResponse::NTSNak, _) => self.nts_nak_packets.inc(),
         }
 
+        if reason == ServerReason::InvalidCrypto {
+            self.authentication_failures.inc();
+        }
+
         if nts {
             self.nts_received_packets.inc();
             match (response, reason) {
@@ -64,14 +98,96 @@ impl ServerStatHandler for ServerStats {
     }
 }
 
+/// Counts a single request in the server statistics, and records it in the
+/// audit log when it failed authentication
+#[cfg(feature = "server")]
+struct AuditedStats<'a> {
+    stats: &'a mut ServerStats,
+    client: SocketAddr,
+}
+
+#[cfg(feature = "server")]
+impl ServerStatHandler for AuditedStats<'_> {
+    fn register(&mut self, version: u8, nts: bool, reason: ServerReason, response: ServerResponse) {
+        if reason == ServerReason::InvalidCrypto {
+            audit::record(&AuditEvent::ClientAuthenticationFailure {
+                client: self.client,
+            });
+        }
+        self.stats.register(version, nts, reason, response);
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyExchangeStats {
+    pub accepted_connections: Counter,
+    /// Connections closed because their IP address had too many open already
+    pub connection_limited_connections: Counter,
+    /// Connections closed because of the handshake rate limits
+    pub rate_limited_connections: Counter,
+    #[serde(default)]
+    pub completed_handshakes: Counter,
+    /// Key exchanges that failed or timed out
+    #[serde(default)]
+    pub failed_handshakes: Counter,
+    #[serde(default)]
+    pub cookies_issued: Counter,
+    /// Responses with cookies, by the AEAD algorithm of the client's keys
+    #[serde(default)]
+    pub algorithms: AeadCounters,
+    /// When the oldest primary key used for new cookies came into use, in
+    /// seconds since the unix epoch
+    #[serde(default)]
+    pub master_key_created: Gauge,
+}
+
+impl KeyExchangeStatHandler for KeyExchangeStats {
+    fn register_cookies(&self, algorithm: AeadAlgorithm, count: usize) {
+        self.cookies_issued.add(count as u64);
+        if let Some(counter) = self.algorithms.counter(algorithm) {
+            counter.inc();
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[expect(
+    clippy::struct_field_names,
+    reason = "The counters are named after the algorithms"
+)]
+pub struct AeadCounters {
+    pub aes_siv_cmac_256: Counter,
+    pub aes_siv_cmac_512: Counter,
+    pub aes_128_gcm_siv: Counter,
+}
+
+impl AeadCounters {
+    pub fn get(&self, algorithm: AeadAlgorithm) -> u64 {
+        self.counter(algorithm).map_or(0, Counter::get)
+    }
+
+    fn counter(&self, algorithm: AeadAlgorithm) -> Option<&Counter> {
+        match algorithm {
+            AeadAlgorithm::AeadAesSivCmac256 => Some(&self.aes_siv_cmac_256),
+            AeadAlgorithm::AeadAesSivCmac512 => Some(&self.aes_siv_cmac_512),
+            AeadAlgorithm::AeadAes128GcmSiv => Some(&self.aes_128_gcm_siv),
+            AeadAlgorithm::Unknown(_) => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Counter {
     value: Arc<AtomicU64>,
 }
 
 impl Counter {
-    fn inc(&self) {
-        self.value.fetch_add(1, Ordering::Relaxed);
+    pub(crate) fn inc(&self) {
+        self.add(1);
+    }
+
+    pub(crate) fn add(&self, amount: u64) {
+        self.value.fetch_add(amount, Ordering::Relaxed);
     }
 
     pub fn get(&self) -> u64 {
@@ -79,6 +195,42 @@ impl Counter {
     }
 }
 
+/// Like a [`Counter`], but the value is replaced instead of counted up
+#[derive(Debug, Clone, Default)]
+pub struct Gauge {
+    value: Arc<AtomicU64>,
+}
+
+impl Gauge {
+    #[cfg(feature = "server")]
+    pub(crate) fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Serialize for Gauge {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(self.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for Gauge {
+    fn deserialize<D>(deserializer: D) -> Result<Gauge, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Arc::new(Deserialize::deserialize(deserializer)?);
+        Ok(Gauge { value })
+    }
+}
+
 impl Serialize for Counter {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -98,18 +250,123 @@ impl<'de> Deserialize<'de> for Counter {
     }
 }
 
+#[cfg(feature = "server")]
 pub struct ServerTask<C: 'static + NtpClock + Send> {
     config: ServerConfig,
     network_wait_period: std::time::Duration,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
     server: Server<C>,
+    clock: C,
     stats: ServerStats,
 }
 
+/// Socket from which broadcasts are sent. Clients also use it for the
+/// exchanges that calibrate their delay to us.
+#[cfg(feature = "server")]
+async fn open_broadcast_socket(listen: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(listen.ip(), 0)).await?;
+    if listen.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
+    Ok(socket)
+}
+
+#[cfg(feature = "server")]
+async fn recv_broadcast_socket(
+    socket: Option<&UdpSocket>,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Socket on which requests are received
+#[cfg(all(feature = "server", target_os = "linux"))]
+type ServerSocket = BatchSocket;
+#[cfg(all(feature = "server", not(target_os = "linux")))]
+type ServerSocket = Socket<SocketAddr, Open>;
+
+#[cfg(all(feature = "server", target_os = "linux"))]
+type RecvOutput = usize;
+#[cfg(all(feature = "server", not(target_os = "linux")))]
+type RecvOutput = RecvResult<SocketAddr>;
+
+#[cfg(all(feature = "server", target_os = "linux"))]
+fn open_server_socket(config: &ServerConfig) -> std::io::Result<ServerSocket> {
+    // Every worker binds its own socket to the listen address
+    BatchSocket::bind(config.listen, config.workers.get() > 1)
+}
+
+#[cfg(all(feature = "server", not(target_os = "linux")))]
+fn open_server_socket(config: &ServerConfig) -> std::io::Result<ServerSocket> {
+    open_ip(
+        config.listen,
+        timestamped_socket::socket::GeneralTimestampMode::SoftwareRecv,
+    )
+}
+
+/// Buffers for the requests handled each time the server task is woken up,
+/// and for their responses
+#[cfg(all(feature = "server", target_os = "linux"))]
+struct RequestBuffers {
+    recv: RecvBatch,
+    send: [u8; MAX_PACKET_SIZE],
+}
+
+#[cfg(all(feature = "server", not(target_os = "linux")))]
+struct RequestBuffers {
+    recv: [u8; MAX_PACKET_SIZE],
+    send: [u8; MAX_PACKET_SIZE],
+}
+
+#[cfg(feature = "server")]
+impl RequestBuffers {
+    #[cfg(target_os = "linux")]
+    fn new() -> RequestBuffers {
+        RequestBuffers {
+            recv: RecvBatch::new(BATCH_SIZE, MAX_PACKET_SIZE),
+            send: [0; MAX_PACKET_SIZE],
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new() -> RequestBuffers {
+        RequestBuffers {
+            recv: [0; MAX_PACKET_SIZE],
+            send: [0; MAX_PACKET_SIZE],
+        }
+    }
+
+    async fn recv(&mut self, socket: &mut ServerSocket) -> std::io::Result<RecvOutput> {
+        socket.recv(&mut self.recv).await
+    }
+}
+
+/// Log an error receiving requests, and return whether the network went
+/// down and the socket should be reopened
+#[cfg(feature = "server")]
+fn network_gone(receive_error: &std::io::Error) -> bool {
+    warn!(?receive_error, "could not receive packet");
+
+    // For a server, we only trigger NetworkGone restarts
+    // on ENETDOWN. ENETUNREACH, EHOSTDOWN and EHOSTUNREACH
+    // do not signal restart-worthy conditions for the a
+    // server (they essentially indicate problems with the
+    // remote network/host, which is not relevant for a server).
+    // Furthermore, they can conceivably be triggered by a
+    // malicious third party, and triggering restart on them
+    // would then result in a denial-of-service.
+    matches!(receive_error.raw_os_error(), Some(libc::ENETDOWN))
+}
+
+#[cfg(feature = "server")]
 impl<C: 'static + NtpClock + Send> ServerTask<C> {
     #[instrument(level = tracing::Level::ERROR, name = "Ntp Server", skip_all, fields(address = debug(config.listen)))]
     pub fn spawn(
         server: Server<C>,
+        clock: C,
         config: ServerConfig,
         stats: ServerStats,
         keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
@@ -122,6 +379,7 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                     network_wait_period,
                     keyset,
                     server,
+                    clock,
                     stats,
                 };
 
@@ -133,18 +391,21 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
 
     async fn serve(&mut self) {
         let mut cur_socket = None;
+        let mut broadcast_socket = None;
+        let mut broadcast_timer =
+            tokio::time::interval(self.config.broadcast_interval.as_system_duration());
+        broadcast_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The buffers are reused for every packet
+        let mut request_buffers = RequestBuffers::new();
+        let mut send_buf = [0_u8; MAX_PACKET_SIZE];
+        let mut broadcast_recv_buf = [0_u8; MAX_PACKET_SIZE];
         loop {
             // open socket if it is not already open
             let socket = if let Some(socket) = &mut cur_socket {
                 socket
             } else {
                 let new_socket = loop {
-                    let socket_res = open_ip(
-                        self.config.listen,
-                        timestamped_socket::socket::GeneralTimestampMode::SoftwareRecv,
-                    );
-
-                    match socket_res {
+                    match open_server_socket(&self.config) {
                         Ok(socket) => break socket,
                         Err(error) => {
                             warn!(?error, ?self.config.listen, "Could not open server socket");
@@ -160,64 +421,261 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                 cur_socket.insert(new_socket)
             };
 
-            let mut buf = [0_u8; MAX_PACKET_SIZE];
             tokio::select! {
-                recv_res = socket.recv(&mut buf) => {
+                recv_res = request_buffers.recv(socket) => {
+                    if self.handle_requests(socket, recv_res, &mut request_buffers).await {
+                        cur_socket = None;
+                    }
+                },
+                _ = self.keyset.changed(), if self.keyset.has_changed().is_ok() => {
+                    self.server.update_keyset(self.keyset.borrow_and_update().clone());
+                }
+                _ = broadcast_timer.tick(), if !self.config.broadcast.is_empty() => {
+                    if broadcast_socket.is_none() {
+                        match open_broadcast_socket(self.config.listen).await {
+                            Ok(socket) => broadcast_socket = Some(socket),
+                            Err(error) => warn!(?error, "Could not open broadcast socket"),
+                        }
+                    }
+                    if let Some(socket) = &broadcast_socket {
+                        self.send_broadcast(socket, &mut send_buf).await;
+                    }
+                },
+                recv_res = recv_broadcast_socket(broadcast_socket.as_ref(), &mut broadcast_recv_buf) => {
                     match recv_res {
-                        Ok(RecvResult {
-                            bytes_read: length,
-                            remote_addr: source_addr,
-                            timestamp: Some(timestamp),
-                        }) => {
-                            let mut send_buf = [0u8; MAX_PACKET_SIZE];
-                            match self.server.handle(source_addr.ip(), convert_net_timestamp(timestamp), &buf[..length], &mut send_buf[..length], &mut self.stats) {
-                                ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ },
-                                ntp_proto::ServerAction::Respond { message } => {
-                                    if let Err(send_err) = socket.send_to(message, source_addr).await {
-                                        self.stats.response_send_errors.inc();
-                                        debug!(error=?send_err, "Could not send response packet");
-                                    }
-                                },
+                        Ok((length, source_addr)) => {
+                            if let Some(socket) = &broadcast_socket {
+                                self.handle_broadcast_socket_recv(
+                                    socket,
+                                    source_addr,
+                                    &broadcast_recv_buf[..length],
+                                    &mut send_buf,
+                                )
+                                .await;
                             }
                         }
-                        Ok(_) => {
-                            debug!("received a packet without a timestamp");
-                            self.stats.register(0, false, ServerReason::InternalError, ServerResponse::Ignore);
-                        }
-                        Err(receive_error) => {
-                            warn!(?receive_error, "could not receive packet");
-
-                            // For a server, we only trigger NetworkGone restarts
-                            // on ENETDOWN. ENETUNREACH, EHOSTDOWN and EHOSTUNREACH
-                            // do not signal restart-worthy conditions for the a
-                            // server (they essentially indicate problems with the
-                            // remote network/host, which is not relevant for a server).
-                            // Furthermore, they can conceivably be triggered by a
-                            // malicious third party, and triggering restart on them
-                            // would then result in a denial-of-service.
-                            if matches!(receive_error.raw_os_error(), Some(libc::ENETDOWN)) {
-                                cur_socket = None;
-                            }
+                        Err(error) => {
+                            warn!(?error, "Could not receive on broadcast socket, reopening it");
+                            broadcast_socket = None;
                         }
                     }
+                }
+            }
+        }
+    }
+
+    async fn send_broadcast(&mut self, socket: &UdpSocket, send_buf: &mut [u8]) {
+        let Some(message) = self
+            .server
+            .broadcast(self.config.broadcast_interval, send_buf)
+        else {
+            debug!("Not synchronized, skipping broadcast");
+            return;
+        };
+
+        for target in &self.config.broadcast {
+            match socket.send_to(message, target).await {
+                Ok(_) => self.stats.broadcast_packets.inc(),
+                Err(error) => warn!(?error, ?target, "Could not send broadcast"),
+            }
+        }
+    }
+
+    /// Respond to a request from a broadcast client calibrating its delay.
+    /// Such requests arrive on the broadcast socket, which only provides
+    /// software timestamps.
+    async fn handle_broadcast_socket_recv(
+        &mut self,
+        socket: &UdpSocket,
+        source_addr: SocketAddr,
+        message: &[u8],
+        send_buf: &mut [u8],
+    ) {
+        let recv_timestamp = match self.clock.now() {
+            Ok(timestamp) => timestamp,
+            Err(error) => {
+                warn!(?error, "Could not read the current time");
+                return;
+            }
+        };
+
+        match self.server.handle(
+            source_addr.ip(),
+            recv_timestamp,
+            message,
+            &mut send_buf[..message.len()],
+            &mut AuditedStats {
+                stats: &mut self.stats,
+                client: source_addr,
+            },
+        ) {
+            ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ }
+            ntp_proto::ServerAction::Respond { message } => {
+                if let Err(send_err) = socket.send_to(message, source_addr).await {
+                    self.stats.response_send_errors.inc();
+                    debug!(error=?send_err, "Could not send response packet");
+                }
+            }
+        }
+    }
+
+    /// Handle a batch of requests. Returns whether the network went down and
+    /// the socket should be reopened.
+    #[cfg(target_os = "linux")]
+    async fn handle_requests(
+        &mut self,
+        socket: &mut ServerSocket,
+        recv_res: std::io::Result<RecvOutput>,
+        buffers: &mut RequestBuffers,
+    ) -> bool {
+        if let Err(receive_error) = recv_res {
+            return network_gone(&receive_error);
+        }
+
+        if buffers.recv.truncated() > 0 {
+            debug!(
+                count = buffers.recv.truncated(),
+                "dropped packets too large for the receive buffer"
+            );
+        }
+
+        // Every request is handled on its own, so filters and rate limits
+        // apply per packet, and a malformed request does not affect the rest
+        // of the batch. Each response is sent right away, as its transmit
+        // timestamp is taken while handling.
+        for (packet, request) in buffers.recv.iter() {
+            let Some(timestamp) = packet.timestamp else {
+                debug!("received a packet without a timestamp");
+                self.stats.register(
+                    0,
+                    false,
+                    ServerReason::InternalError,
+                    ServerResponse::Ignore,
+                );
+                continue;
+            };
+
+            match self.server.handle(
+                packet.remote_addr.ip(),
+                convert_batch_timestamp(timestamp),
+                request,
+                &mut buffers.send[..request.len()],
+                &mut AuditedStats {
+                    stats: &mut self.stats,
+                    client: packet.remote_addr,
                 },
-                _ = self.keyset.changed(), if self.keyset.has_changed().is_ok() => {
-                    self.server.update_keyset(self.keyset.borrow_and_update().clone());
+            ) {
+                ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ }
+                ntp_proto::ServerAction::Respond { message } => {
+                    if let Err(send_err) = socket.send_to(message, packet.remote_addr).await {
+                        self.stats.response_send_errors.inc();
+                        debug!(error=?send_err, "Could not send response packet");
+                    }
                 }
             }
         }
+
+        false
+    }
+
+    /// Handle a request, and any further requests that are already waiting.
+    /// Returns whether the network went down and the socket should be
+    /// reopened.
+    #[cfg(not(target_os = "linux"))]
+    async fn handle_requests(
+        &mut self,
+        socket: &mut ServerSocket,
+        recv_res: std::io::Result<RecvOutput>,
+        buffers: &mut RequestBuffers,
+    ) -> bool {
+        let mut network_gone = self
+            .handle_recv(socket, recv_res, &buffers.recv, &mut buffers.send)
+            .await;
+
+        // Serve any further requests that are already waiting, so that under
+        // load we don't go through the scheduler for every single packet.
+        // Each packet is still received with its own timestamp and handled on
+        // its own.
+        for _ in 1..BATCH_SIZE {
+            if network_gone {
+                break;
+            }
+
+            let recv_res = tokio::select! {
+                biased;
+                recv_res = socket.recv(&mut buffers.recv) => recv_res,
+                () = std::future::ready(()) => break,
+            };
+            network_gone = self
+                .handle_recv(socket, recv_res, &buffers.recv, &mut buffers.send)
+                .await;
+        }
+
+        network_gone
+    }
+
+    /// Handle the result of receiving a single packet, returns whether the
+    /// network went down and the socket should be reopened.
+    #[cfg(not(target_os = "linux"))]
+    async fn handle_recv(
+        &mut self,
+        socket: &mut ServerSocket,
+        recv_res: std::io::Result<RecvOutput>,
+        recv_buf: &[u8],
+        send_buf: &mut [u8],
+    ) -> bool {
+        match recv_res {
+            Ok(RecvResult {
+                bytes_read: length,
+                remote_addr: source_addr,
+                timestamp: Some(timestamp),
+            }) => {
+                // The response is sent right away, as its transmit timestamp
+                // is taken while handling.
+                match self.server.handle(
+                    source_addr.ip(),
+                    convert_net_timestamp(timestamp),
+                    &recv_buf[..length],
+                    &mut send_buf[..length],
+                    &mut AuditedStats {
+                        stats: &mut self.stats,
+                        client: source_addr,
+                    },
+                ) {
+                    ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ }
+                    ntp_proto::ServerAction::Respond { message } => {
+                        if let Err(send_err) = socket.send_to(message, source_addr).await {
+                            self.stats.response_send_errors.inc();
+                            debug!(error=?send_err, "Could not send response packet");
+                        }
+                    }
+                }
+                false
+            }
+            Ok(_) => {
+                debug!("received a packet without a timestamp");
+                self.stats.register(
+                    0,
+                    false,
+                    ServerReason::InternalError,
+                    ServerResponse::Ignore,
+                );
+                false
+            }
+            Err(receive_error) => network_gone(&receive_error),
+        }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "server"))]
 mod tests {
     use std::{convert::Infallible, io::Cursor, net::SocketAddr};
 
     use ntp_proto::{
-        KeySetProvider, NoCipher, NtpDuration, NtpLeapIndicator, NtpPacket, NtpTimestamp,
-        PollIntervalLimits,
+        KeySetProvider, NoCipher, NtpAssociationMode, NtpDuration, NtpLeapIndicator, NtpPacket,
+        NtpServerInfo, NtpTimestamp, PollIntervalLimits,
     };
-    use timestamped_socket::socket::GeneralTimestampMode;
+    use timestamped_socket::socket::{GeneralTimestampMode, open_ip};
 
     use crate::test::alloc_port;
 
@@ -288,13 +746,14 @@ mod tests {
 
         let server = Server::new_internal(
             config.clone().into(),
-            clock,
+            clock.clone(),
             server_info,
             keyset.borrow().clone(),
         );
 
         let join = ServerTask::spawn(
             server,
+            clock,
             config,
             ServerStats::default(),
             keyset,
@@ -325,4 +784,225 @@ mod tests {
 
         join.abort();
     }
+
+    #[tokio::test]
+    async fn test_server_serves_burst() {
+        let port = alloc_port();
+        let config = ServerConfig::from(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+
+        let server_info = Arc::default();
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let server = Server::new_internal(
+            config.clone().into(),
+            clock.clone(),
+            server_info,
+            keyset.borrow().clone(),
+        );
+
+        let stats = ServerStats::default();
+        let join = ServerTask::spawn(
+            server,
+            clock,
+            config,
+            stats.clone(),
+            keyset,
+            Duration::from_secs(0),
+        );
+
+        let socket = open_ip(
+            SocketAddr::new("127.0.0.1".parse().unwrap(), alloc_port()),
+            GeneralTimestampMode::SoftwareRecv,
+        )
+        .unwrap();
+        let mut socket = socket
+            .connect(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
+            .unwrap();
+
+        // Send more requests than fit in a single batch, with a malformed
+        // packet in between that should not affect the others.
+        let mut ids = vec![];
+        for i in 0..2 * BATCH_SIZE {
+            if i == BATCH_SIZE / 2 {
+                socket.send(&[0xff; 20]).await.unwrap();
+            }
+            let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+            socket
+                .send(&serialize_packet_unencrypted(&packet))
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+
+        let mut buf = [0; 48];
+        for _ in 0..ids.len() {
+            tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+            assert!(
+                ids.iter()
+                    .any(|id| packet.valid_server_response(*id, false))
+            );
+        }
+
+        assert_eq!(stats.received_packets.get(), ids.len() as u64 + 1);
+        assert_eq!(stats.accepted_packets.get(), ids.len() as u64);
+
+        join.abort();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_server_workers() {
+        let port = alloc_port();
+        let mut config = ServerConfig::from(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+        config.workers = std::num::NonZeroUsize::new(2).unwrap();
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+
+        let server_info = Arc::default();
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let stats = ServerStats::default();
+        let joins: Vec<_> = (0..2)
+            .map(|_| {
+                let server = Server::new_internal(
+                    config.clone().into(),
+                    clock.clone(),
+                    Arc::clone(&server_info),
+                    keyset.borrow().clone(),
+                );
+                ServerTask::spawn(
+                    server,
+                    clock.clone(),
+                    config.clone(),
+                    stats.clone(),
+                    keyset.clone(),
+                    Duration::from_secs(0),
+                )
+            })
+            .collect();
+
+        // Clients on different ports are spread over the workers, but all
+        // get an answer
+        for _ in 0..8 {
+            let socket = open_ip(
+                SocketAddr::new("127.0.0.1".parse().unwrap(), alloc_port()),
+                GeneralTimestampMode::SoftwareRecv,
+            )
+            .unwrap();
+            let mut socket = socket
+                .connect(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
+                .unwrap();
+            let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+            socket
+                .send(&serialize_packet_unencrypted(&packet))
+                .await
+                .unwrap();
+
+            let mut buf = [0; 48];
+            tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+            assert!(packet.valid_server_response(id, false));
+        }
+
+        assert_eq!(stats.accepted_packets.get(), 8);
+
+        for join in joins {
+            join.abort();
+        }
+    }
+
+    #[test]
+    fn test_authentication_failure_stats() {
+        let mut stats = ServerStats::default();
+        stats.register(4, true, ServerReason::InvalidCrypto, ServerResponse::NTSNak);
+        stats.register(
+            4,
+            false,
+            ServerReason::InvalidCrypto,
+            ServerResponse::Ignore,
+        );
+        stats.register(4, false, ServerReason::Policy, ServerResponse::ProvideTime);
+
+        assert_eq!(stats.received_packets.get(), 3);
+        assert_eq!(stats.nts_nak_packets.get(), 1);
+        assert_eq!(stats.authentication_failures.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_server_broadcasts() {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let port = alloc_port();
+        let mut config = ServerConfig::from(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+        config.broadcast = vec![client.local_addr().unwrap()];
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+
+        let server_info = Arc::new(std::sync::RwLock::new(NtpServerInfo::default()));
+        {
+            let mut server_info = server_info.write().unwrap();
+            server_info.ntp_snapshot.stratum = 2;
+            server_info.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+        }
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let server = Server::new_internal(
+            config.clone().into(),
+            clock.clone(),
+            server_info,
+            keyset.borrow().clone(),
+        );
+
+        let stats = ServerStats::default();
+        let join = ServerTask::spawn(
+            server,
+            clock,
+            config,
+            stats.clone(),
+            keyset,
+            Duration::from_secs(0),
+        );
+
+        // The first broadcast is sent right away
+        let mut buf = [0; 48];
+        let (_, broadcast_addr) =
+            tokio::time::timeout(Duration::from_millis(100), client.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+        let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        assert_eq!(packet.mode(), NtpAssociationMode::Broadcast);
+        assert_eq!(packet.stratum(), 2);
+
+        // Clients calibrate their delay through the broadcast socket
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        client
+            .send_to(&serialize_packet_unencrypted(&packet), broadcast_addr)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_millis(100), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert_eq!(stats.broadcast_packets.get(), 1);
+
+        join.abort();
+    }
 }
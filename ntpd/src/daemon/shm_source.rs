@@ -0,0 +1,171 @@
+//! Source reading the shared memory segments of the SHM reference clock
+//! protocol of ntpd, which is written by feeders such as gpsd. Each sample
+//! holds the time of the reference clock together with the time of the
+//! system clock at that moment. Segments are polled, and a sample is only
+//! used once.
+
+use ntp_proto::{
+    ClockId, Measurement, NtpDuration, NtpLeapIndicator, OneWaySource, PollInterval,
+    SourceController,
+};
+use ntp_shm::{Sample, Segment};
+use tracing::{Instrument, Span, debug, instrument, warn};
+
+use super::{
+    measurements_log, ntp_source::SourceChannels, sock_source::precision_exponent,
+    util::convert_unix_timestamp,
+};
+
+/// Turn a sample into a measurement, unless the feeder says its reference
+/// clock is not synchronized
+fn sample_measurement(sample: &Sample, index: ClockId, precision: i8) -> Option<Measurement> {
+    let leap = match sample.leap {
+        0 => NtpLeapIndicator::NoWarning,
+        1 => NtpLeapIndicator::Leap61,
+        2 => NtpLeapIndicator::Leap59,
+        _ => return None,
+    };
+
+    // Timestamps before the unix epoch are of no use to anyone
+    let clock = convert_unix_timestamp(
+        u64::try_from(sample.clock_seconds).ok()?,
+        sample.clock_nanos,
+    );
+    let receive = convert_unix_timestamp(
+        u64::try_from(sample.receive_seconds).ok()?,
+        sample.receive_nanos,
+    );
+
+    Some(Measurement {
+        sender_id: index,
+        receiver_id: ClockId::SYSTEM,
+        sender_ts: clock,
+        receiver_ts: receive,
+
+        root_delay: NtpDuration::ZERO,
+        root_dispersion: NtpDuration::ZERO,
+        stratum: 0,
+        leap,
+        // Like for gpsd, don't trust the source more than configured, but do
+        // trust it less when the feeder says so
+        precision: i8::try_from(sample.precision)
+            .map_or(precision, |reported| reported.max(precision)),
+    })
+}
+
+pub(crate) struct ShmSourceTask<Controller: SourceController> {
+    index: ClockId,
+    segment: Segment,
+    unit: u8,
+    poll_interval: PollInterval,
+    precision: i8,
+    channels: SourceChannels,
+    source: OneWaySource<Controller>,
+}
+
+impl<Controller: SourceController> ShmSourceTask<Controller> {
+    async fn run(&mut self) {
+        loop {
+            match self.segment.read() {
+                Ok(Some(sample)) => self.handle_sample(&sample),
+                Ok(None) => {}
+                Err(e) => debug!(error = %e, "Could not read sample from segment"),
+            }
+
+            tokio::time::sleep(self.poll_interval.as_system_duration()).await;
+        }
+    }
+
+    fn handle_sample(&mut self, sample: &Sample) {
+        debug!(?sample, "read SHM sample");
+
+        let Some(measurement) = sample_measurement(sample, self.index, self.precision) else {
+            debug!("Feeder is not synchronized, ignoring sample");
+            return;
+        };
+
+        let address = format!("SHM({})", self.unit);
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record("SHM", &address, self.index, &measurement);
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source.observe("SHM".to_string(), address, self.index),
+            );
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "SHM Source", skip(channels, source))]
+    pub fn spawn(
+        index: ClockId,
+        unit: u8,
+        poll_interval: PollInterval,
+        precision: f64,
+        channels: SourceChannels,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(
+            (async move {
+                let segment = match Segment::attach(unit) {
+                    Ok(segment) => segment,
+                    Err(e) => {
+                        warn!(error = ?e, "Could not attach shared memory segment");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
+                let mut process = ShmSourceTask {
+                    index,
+                    segment,
+                    unit,
+                    poll_interval,
+                    precision: precision_exponent(precision),
+                    channels,
+                    source,
+                };
+
+                process.run().await;
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(leap: i32, precision: i32) -> Sample {
+        Sample {
+            clock_seconds: 1_700_000_000,
+            clock_nanos: 1_000,
+            receive_seconds: 1_700_000_000,
+            receive_nanos: 0,
+            leap,
+            precision,
+        }
+    }
+
+    #[test]
+    fn test_sample_measurement() {
+        let measurement = sample_measurement(&sample(0, -20), ClockId::new(), -10).unwrap();
+        let offset = (measurement.sender_ts - measurement.receiver_ts).to_seconds();
+        assert!((offset - 1e-6).abs() < 1e-9);
+        assert_eq!(measurement.leap, NtpLeapIndicator::NoWarning);
+        // The configured precision is a lower bound
+        assert_eq!(measurement.precision, -10);
+
+        let measurement = sample_measurement(&sample(1, -5), ClockId::new(), -10).unwrap();
+        assert_eq!(measurement.leap, NtpLeapIndicator::Leap61);
+        assert_eq!(measurement.precision, -5);
+
+        // Not synchronized
+        assert!(sample_measurement(&sample(3, -20), ClockId::new(), -10).is_none());
+    }
+}
@@ -1,114 +1,264 @@
+use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use std::{fmt::Display, path::Path};
 
 use ntp_proto::{
-    ClockId, Measurement, NtpClock, NtpDuration, NtpLeapIndicator, OneWaySource, SourceController,
+    ClockId, Measurement, NtpDuration, NtpLeapIndicator, NtpTimestamp, OneWaySource,
+    SourceController,
 };
 use tracing::debug;
-use tracing::{Instrument, Span, error, instrument};
+use tracing::{Instrument, Span, error, instrument, warn};
 
 use tokio::net::UnixDatagram;
 
-use crate::daemon::exitcode;
-
-use super::ntp_source::SourceChannels;
+use super::{
+    config::SockSocketOptions,
+    measurements_log,
+    ntp_source::SourceChannels,
+    util::{convert_unix_timestamp, create_socket},
+};
 
 // Based on https://gitlab.com/gpsd/gpsd/-/blob/master/gpsd/timehint.c#L268
 #[derive(Debug)]
 struct SockSample {
-    // tv_sec: i64,
-    // tv_usec: i64,
+    /// Our time when gpsd took the sample, from its tv_sec and tv_usec
+    time: NtpTimestamp,
     offset: f64,
     pulse: i32,
     leap: i32,
-    magic: i32,
 }
 
 const SOCK_MAGIC: i32 = 0x534f434b;
 const SOCK_SAMPLE_SIZE: usize = 40;
+// Older versions of the sample struct lack the padding before the magic value
+const SOCK_SAMPLE_SIZE_UNPADDED: usize = 36;
+// Hosts with a 32 bit time_t and suseconds_t have a smaller timeval
+const SOCK_SAMPLE_SIZE_32BIT: usize = 32;
+const SOCK_SAMPLE_SIZE_32BIT_UNPADDED: usize = 28;
+
+// Seconds after the last pulse that the serial samples are left out
+const PULSE_TIMEOUT: f64 = 3.0;
 
 #[derive(Debug)]
 enum SampleError {
-    IOError(std::io::Error),
     SliceError(std::array::TryFromSliceError),
     WrongSize(usize),
     WrongMagic(i32),
-    WrongPulse(i32),
+    InvalidTime(i64, i64),
 }
 
 impl Display for SampleError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            SampleError::IOError(e) => f.write_str(&e.to_string()),
             SampleError::SliceError(e) => f.write_str(&e.to_string()),
             SampleError::WrongSize(s) => f.write_fmt(format_args!("Invalid size {s}")),
             SampleError::WrongMagic(m) => f.write_fmt(format_args!("Invalid magic {m}")),
-            SampleError::WrongPulse(p) => f.write_fmt(format_args!("Invalid pulse {p}")),
+            SampleError::InvalidTime(sec, usec) => {
+                f.write_fmt(format_args!("Invalid time {sec}s {usec}us"))
+            }
         }
     }
 }
 
-fn deserialize_sample(
-    result: Result<usize, std::io::Error>,
-    buf: [u8; SOCK_SAMPLE_SIZE],
-) -> Result<SockSample, SampleError> {
-    let size = result.map_err(SampleError::IOError)?;
-    if size != SOCK_SAMPLE_SIZE {
-        return Err(SampleError::WrongSize(size));
+fn read_i32(buf: &[u8], start: usize) -> Result<i32, SampleError> {
+    Ok(i32::from_ne_bytes(
+        buf[start..start + 4]
+            .try_into()
+            .map_err(SampleError::SliceError)?,
+    ))
+}
+
+fn read_i64(buf: &[u8], start: usize) -> Result<i64, SampleError> {
+    Ok(i64::from_ne_bytes(
+        buf[start..start + 8]
+            .try_into()
+            .map_err(SampleError::SliceError)?,
+    ))
+}
+
+/// Convert the timeval of a sample, which should be after the unix epoch
+fn timeval_timestamp(seconds: i64, micros: i64) -> Option<NtpTimestamp> {
+    let seconds = u64::try_from(seconds).ok()?;
+    let micros = u32::try_from(micros)
+        .ok()
+        .filter(|micros| *micros < 1_000_000)?;
+    Some(convert_unix_timestamp(seconds, micros * 1000))
+}
+
+fn deserialize_sample(buf: &[u8]) -> Result<SockSample, SampleError> {
+    // gpsd writes the struct as it is in its memory, so in the byte order of
+    // the host and with the size of its timeval
+    let (timeval_size, magic_start) = match buf.len() {
+        SOCK_SAMPLE_SIZE => (16, 36),
+        SOCK_SAMPLE_SIZE_UNPADDED => (16, 32),
+        SOCK_SAMPLE_SIZE_32BIT => (8, 28),
+        SOCK_SAMPLE_SIZE_32BIT_UNPADDED => (8, 24),
+        size => return Err(SampleError::WrongSize(size)),
+    };
+
+    let magic = read_i32(buf, magic_start)?;
+    if magic != SOCK_MAGIC {
+        return Err(SampleError::WrongMagic(magic));
     }
 
-    let sample = SockSample {
-        // tv_sec: i64::from_le_bytes(buf[0..8].try_into()?),
-        // tv_usec: i64::from_le_bytes(buf[8..16].try_into()?),
-        offset: f64::from_le_bytes(buf[16..24].try_into().map_err(SampleError::SliceError)?),
-        pulse: i32::from_le_bytes(buf[24..28].try_into().map_err(SampleError::SliceError)?),
-        leap: i32::from_le_bytes(buf[28..32].try_into().map_err(SampleError::SliceError)?),
-        // skip padding (4 bytes)
-        magic: i32::from_le_bytes(buf[36..40].try_into().map_err(SampleError::SliceError)?),
+    let (seconds, micros) = if timeval_size == 16 {
+        (read_i64(buf, 0)?, read_i64(buf, 8)?)
+    } else {
+        (i64::from(read_i32(buf, 0)?), i64::from(read_i32(buf, 4)?))
     };
 
-    if sample.magic != SOCK_MAGIC {
-        return Err(SampleError::WrongMagic(sample.magic));
+    Ok(SockSample {
+        time: timeval_timestamp(seconds, micros)
+            .ok_or(SampleError::InvalidTime(seconds, micros))?,
+        offset: f64::from_ne_bytes(
+            buf[timeval_size..timeval_size + 8]
+                .try_into()
+                .map_err(SampleError::SliceError)?,
+        ),
+        pulse: read_i32(buf, timeval_size + 8)?,
+        leap: read_i32(buf, timeval_size + 12)?,
+    })
+}
+
+/// Turn a sample into a measurement, at the time gpsd took it
+fn sample_measurement(sample: &SockSample, index: ClockId, precision: i8) -> Measurement {
+    let (offset, leap) = if sample.pulse != 0 {
+        // A pulse only marks the start of a second, gpsd assigns it to the
+        // second nearest to our clock. Like for PPS sources, we only use the
+        // fraction of the second, and pulses carry no leap second information.
+        (
+            sample.offset - sample.offset.round(),
+            NtpLeapIndicator::NoWarning,
+        )
+    } else {
+        let leap = match sample.leap {
+            0 => NtpLeapIndicator::NoWarning,
+            1 => NtpLeapIndicator::Leap61,
+            2 => NtpLeapIndicator::Leap59,
+            _ => NtpLeapIndicator::Unknown,
+        };
+        (sample.offset, leap)
+    };
+
+    Measurement {
+        sender_id: index,
+        receiver_id: ClockId::SYSTEM,
+        sender_ts: sample.time + NtpDuration::from_seconds(offset),
+        receiver_ts: sample.time,
+
+        root_delay: NtpDuration::ZERO,
+        root_dispersion: NtpDuration::ZERO,
+        stratum: 0,
+        leap,
+        precision,
     }
+}
 
-    if sample.pulse != 0 {
-        return Err(SampleError::WrongPulse(sample.pulse));
+/// Pulses are far more precise than the serial data they are numbered with,
+/// so while pulses keep coming in the serial samples are left out. Their leap
+/// second announcements are passed on with the pulses instead.
+#[derive(Debug, Default)]
+struct PulsePreference {
+    last_pulse: Option<NtpTimestamp>,
+    leap: Option<NtpLeapIndicator>,
+}
+
+impl PulsePreference {
+    fn filter(&mut self, mut measurement: Measurement, pulse: bool) -> Option<Measurement> {
+        let time = measurement.receiver_ts;
+        if pulse {
+            self.last_pulse = Some(time);
+            if let Some(leap) = self.leap {
+                measurement.leap = leap;
+            }
+            Some(measurement)
+        } else {
+            self.leap = Some(measurement.leap);
+            let pulses_active = self
+                .last_pulse
+                .is_some_and(|pulse| (time - pulse).to_seconds().abs() < PULSE_TIMEOUT);
+            (!pulses_active).then_some(measurement)
+        }
     }
+}
 
-    Ok(sample)
+/// Precision of a source as the log2 of its standard deviation in seconds
+pub(super) fn precision_exponent(precision: f64) -> i8 {
+    precision
+        .log2()
+        .round()
+        .clamp(f64::from(i8::MIN), f64::from(i8::MAX)) as i8
 }
 
-pub(crate) struct SockSourceTask<C: 'static + NtpClock + Send, Controller: SourceController> {
+pub(crate) struct SockSourceTask<Controller: SourceController> {
     index: ClockId,
     socket: UnixDatagram,
-    clock: C,
     path: PathBuf,
+    precision: i8,
+    pulse_preference: PulsePreference,
     channels: SourceChannels,
     source: OneWaySource<Controller>,
 }
 
-fn create_socket<T: AsRef<Path>>(path: T) -> std::io::Result<UnixDatagram> {
-    let path = path.as_ref();
-    if path.exists() {
-        debug!("Removing previous socket file");
-        std::fs::remove_file(path)?;
+/// Look up the id of a user or group in `database`, a file in the format of
+/// /etc/passwd or /etc/group. Numeric ids are used as they are.
+fn lookup_id(database: &Path, name: &str) -> std::io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    std::fs::read_to_string(database)?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2)?.parse().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{name} not found in {}", database.display()),
+            )
+        })
+}
+
+/// Create the socket of a sock source, with the configured permissions and
+/// owner so gpsd can write to it
+fn create_sock_socket(path: &Path, options: &SockSocketOptions) -> std::io::Result<UnixDatagram> {
+    let user = options
+        .user
+        .as_deref()
+        .map(|user| lookup_id(Path::new("/etc/passwd"), user))
+        .transpose()?;
+    let group = options
+        .group
+        .as_deref()
+        .map(|group| lookup_id(Path::new("/etc/group"), group))
+        .transpose()?;
+
+    if options.create_directory
+        && let Some(parent) = path.parent()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let socket = create_socket(path)?;
+    if let Some(permissions) = options.permissions {
+        std::fs::set_permissions(path, PermissionsExt::from_mode(permissions))?;
+    }
+    if user.is_some() || group.is_some() {
+        std::os::unix::fs::chown(path, user, group)?;
     }
-    debug!("Creating socket at {:?}", path);
-    let socket = UnixDatagram::bind(path)?;
     Ok(socket)
 }
 
-impl<C, Controller: SourceController> SockSourceTask<C, Controller>
-where
-    C: 'static + NtpClock + Send + Sync,
-{
+impl<Controller: SourceController> SockSourceTask<Controller> {
     async fn run(&mut self) {
         loop {
             enum SelectResult {
                 SockRecv(Result<usize, std::io::Error>),
             }
 
-            let mut buf = [0; SOCK_SAMPLE_SIZE];
+            // One byte extra, so we can detect samples that are too large
+            let mut buf = [0; SOCK_SAMPLE_SIZE + 1];
 
             let selected: SelectResult = tokio::select! {
                 result = self.socket.recv(&mut buf) => {
@@ -117,75 +267,80 @@ where
             };
 
             match selected {
-                SelectResult::SockRecv(result) => match deserialize_sample(result, buf) {
-                    Ok(sample) => {
-                        debug!("received {:?}", sample);
-                        let leap = match sample.leap {
-                            0 => NtpLeapIndicator::NoWarning,
-                            1 => NtpLeapIndicator::Leap61,
-                            2 => NtpLeapIndicator::Leap59,
-                            _ => NtpLeapIndicator::Unknown,
-                        };
-
-                        let time = match self.clock.now() {
-                            Ok(time) => time,
-                            Err(e) => {
-                                error!(error = ?e, "There was an error retrieving the current time");
-                                std::process::exit(exitcode::NOPERM);
-                            }
-                        };
-
-                        let measurement = Measurement {
-                            sender_id: self.index,
-                            receiver_id: ClockId::SYSTEM,
-                            sender_ts: time - NtpDuration::from_seconds(sample.offset),
-                            receiver_ts: time,
-
-                            root_delay: NtpDuration::ZERO,
-                            root_dispersion: NtpDuration::ZERO,
-                            leap,
-                            precision: 0, // TODO: compute on startup?
-                        };
-
-                        self.source.handle_measurement(measurement);
-
-                        self.channels
-                            .source_snapshots
-                            .write()
-                            .expect("Unexpected poisoned mutex")
-                            .insert(
-                                self.index,
-                                self.source.observe(
-                                    "GPSd socket".to_string(),
-                                    self.path.display().to_string(),
-                                    self.index,
-                                ),
-                            );
-                    }
+                SelectResult::SockRecv(Ok(size)) => match deserialize_sample(&buf[..size]) {
+                    Ok(sample) => self.handle_sample(&sample),
                     Err(e) => {
                         error!("Error deserializing sample: {}", e);
                     }
                 },
+                SelectResult::SockRecv(Err(e)) => {
+                    warn!(error = ?e, "Could not receive from socket, recreating it");
+                    self.channels.report_network_issue(self.index).await;
+                    return;
+                }
             }
         }
     }
 
-    #[instrument(level = tracing::Level::ERROR, name = "Sock Source", skip(clock, channels, source))]
+    fn handle_sample(&mut self, sample: &SockSample) {
+        debug!("received {:?}", sample);
+
+        let measurement = sample_measurement(sample, self.index, self.precision);
+        let Some(measurement) = self.pulse_preference.filter(measurement, sample.pulse != 0) else {
+            debug!("Leaving out serial sample, pulses are available");
+            return;
+        };
+
+        self.source.handle_measurement(measurement);
+        if let Some(measurement) = self.source.take_latest_measurement() {
+            measurements_log::record(
+                "GPSd socket",
+                &self.path.display().to_string(),
+                self.index,
+                &measurement,
+            );
+        }
+
+        self.channels
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source.observe(
+                    "GPSd socket".to_string(),
+                    self.path.display().to_string(),
+                    self.index,
+                ),
+            );
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "Sock Source", skip(socket_options, channels, source))]
     pub fn spawn(
         index: ClockId,
         socket_path: PathBuf,
-        clock: C,
+        socket_options: SockSocketOptions,
+        precision: f64,
         channels: SourceChannels,
         source: OneWaySource<Controller>,
     ) -> tokio::task::JoinHandle<()> {
-        let socket = create_socket(&socket_path).expect("Could not create socket");
         tokio::spawn(
             (async move {
+                let socket = match create_sock_socket(&socket_path, &socket_options) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        warn!(error = ?e, "Could not create socket");
+                        channels.report_network_issue(index).await;
+                        return;
+                    }
+                };
+
                 let mut process = SockSourceTask {
                     index,
                     socket,
-                    clock,
                     path: socket_path,
+                    precision: precision_exponent(precision),
+                    pulse_preference: PulsePreference::default(),
                     channels,
                     source,
                 };
@@ -214,9 +369,13 @@ mod tests {
 
     use crate::{
         daemon::{
-            ntp_source::SourceChannels,
-            sock_source::{SOCK_MAGIC, SampleError, SockSourceTask, create_socket},
-            util::EPOCH_OFFSET,
+            config::SockSocketOptions,
+            ntp_source::{MsgForSystem, SourceChannels},
+            sock_source::{
+                PulsePreference, SOCK_MAGIC, SOCK_SAMPLE_SIZE, SampleError, SockSourceTask,
+                create_sock_socket, lookup_id, precision_exponent, sample_measurement,
+            },
+            util::{EPOCH_OFFSET, convert_unix_timestamp, create_socket},
         },
         test::alloc_port,
     };
@@ -271,6 +430,24 @@ mod tests {
         }
     }
 
+    /// A sample taken at 1000.5 seconds after the unix epoch, with a 64 or
+    /// 32 bit timeval
+    fn sample_bytes(offset: f64, pulse: i32, leap: i32, padded: bool, time_64bit: bool) -> Vec<u8> {
+        let mut buf = if time_64bit {
+            [1000i64.to_ne_bytes(), 500_000i64.to_ne_bytes()].concat()
+        } else {
+            [1000i32.to_ne_bytes(), 500_000i32.to_ne_bytes()].concat()
+        };
+        buf.extend_from_slice(&offset.to_ne_bytes());
+        buf.extend_from_slice(&pulse.to_ne_bytes());
+        buf.extend_from_slice(&leap.to_ne_bytes());
+        if padded {
+            buf.extend_from_slice(&[0; 4]);
+        }
+        buf.extend_from_slice(&SOCK_MAGIC.to_ne_bytes());
+        buf
+    }
+
     #[tokio::test]
     async fn test_read_sock() {
         let (msg_for_system_sender, _) = mpsc::channel(1);
@@ -290,7 +467,8 @@ mod tests {
         let handle = SockSourceTask::spawn(
             index,
             socket_path.clone(),
-            clock,
+            SockSocketOptions::default(),
+            1e-3,
             SourceChannels {
                 msg_for_system_sender,
                 source_snapshots: Arc::new(RwLock::new(HashMap::new())),
@@ -304,6 +482,9 @@ mod tests {
             )),
         );
 
+        // Wait for the source to replace the socket
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
         // Send example data to socket
         let sock = UnixDatagram::unbound().unwrap();
         sock.connect(socket_path).unwrap();
@@ -316,6 +497,217 @@ mod tests {
         handle.abort();
     }
 
+    #[tokio::test]
+    async fn test_socket_measurements() {
+        let socket_path = std::env::temp_dir().join(format!("ntp-test-stream-{}", alloc_port()));
+        let socket = create_socket(&socket_path).unwrap();
+        let sender = UnixDatagram::unbound().unwrap();
+        sender.connect(&socket_path).unwrap();
+
+        let index = ClockId::new();
+        let time = convert_unix_timestamp(1000, 500_000_000);
+        let receive = async |data: &[u8]| {
+            sender.send(data).unwrap();
+            let mut buf = [0; SOCK_SAMPLE_SIZE + 1];
+            let size = socket.recv(&mut buf).await.unwrap();
+            deserialize_sample(&buf[..size]).map(|sample| sample_measurement(&sample, index, -10))
+        };
+
+        // Offset is the time of the source minus our time when gpsd took the sample
+        let measurement = receive(&sample_bytes(0.25, 0, 0, true, true))
+            .await
+            .unwrap();
+        assert_eq!(measurement.sender_id, index);
+        assert_eq!(measurement.receiver_id, ClockId::SYSTEM);
+        assert_eq!(measurement.receiver_ts, time);
+        assert!(((measurement.sender_ts - time).to_seconds() - 0.25).abs() < 1e-9);
+        assert_eq!(measurement.leap, NtpLeapIndicator::NoWarning);
+        assert_eq!(measurement.precision, -10);
+
+        // Leap second announcements
+        for (leap, expected) in [
+            (1, NtpLeapIndicator::Leap61),
+            (2, NtpLeapIndicator::Leap59),
+            (3, NtpLeapIndicator::Unknown),
+        ] {
+            let measurement = receive(&sample_bytes(0.0, 0, leap, true, true))
+                .await
+                .unwrap();
+            assert_eq!(measurement.leap, expected);
+        }
+
+        // Older layout without padding
+        let measurement = receive(&sample_bytes(-0.5, 0, 1, false, true))
+            .await
+            .unwrap();
+        assert!(((measurement.sender_ts - time).to_seconds() + 0.5).abs() < 1e-9);
+        assert_eq!(measurement.leap, NtpLeapIndicator::Leap61);
+
+        // Hosts with a 32 bit timeval, with and without padding
+        for padded in [true, false] {
+            let measurement = receive(&sample_bytes(0.125, 0, 2, padded, false))
+                .await
+                .unwrap();
+            assert_eq!(measurement.receiver_ts, time);
+            assert!(((measurement.sender_ts - time).to_seconds() - 0.125).abs() < 1e-9);
+            assert_eq!(measurement.leap, NtpLeapIndicator::Leap59);
+        }
+
+        // Pulses only determine the fraction of the second
+        let measurement = receive(&sample_bytes(2.001, 1, 1, true, true))
+            .await
+            .unwrap();
+        assert!(((measurement.sender_ts - time).to_seconds() - 0.001).abs() < 1e-9);
+        assert_eq!(measurement.leap, NtpLeapIndicator::NoWarning);
+        let measurement = receive(&sample_bytes(-0.999, 1, 0, false, false))
+            .await
+            .unwrap();
+        assert!(((measurement.sender_ts - time).to_seconds() - 0.001).abs() < 1e-9);
+
+        // Anything else is rejected
+        let mut data = sample_bytes(0.0, 0, 0, true, true);
+        data.push(0);
+        assert!(matches!(
+            receive(&data).await,
+            Err(SampleError::WrongSize(41))
+        ));
+        assert!(matches!(
+            receive(&data[..30]).await,
+            Err(SampleError::WrongSize(30))
+        ));
+        let mut data = sample_bytes(0.0, 0, 0, true, true);
+        data[8..16].copy_from_slice(&1_000_000i64.to_ne_bytes());
+        assert!(matches!(
+            receive(&data).await,
+            Err(SampleError::InvalidTime(1000, 1_000_000))
+        ));
+
+        std::fs::remove_file(socket_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_socket_creation_failure() {
+        let (msg_for_system_sender, mut msg_for_system_receiver) = mpsc::channel(1);
+
+        let index = ClockId::new();
+        let clock = TestClock {};
+        let controller = TimeSyncControllerWrapper::<KalmanClockController<_>>::new(
+            clock.clone(),
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        let socket_path = std::env::temp_dir()
+            .join(format!("ntp-test-missing-{}", alloc_port()))
+            .join("sock");
+
+        let handle = SockSourceTask::spawn(
+            index,
+            socket_path,
+            SockSocketOptions::default(),
+            1e-3,
+            SourceChannels {
+                msg_for_system_sender,
+                source_snapshots: Arc::new(RwLock::new(HashMap::new())),
+            },
+            OneWaySource::new(controller.add_one_way_source(
+                index,
+                SourceConfig::default(),
+                0.001,
+                1e-3,
+                None,
+            )),
+        );
+
+        // The directory does not exist, so the system should restart us later
+        let msg = msg_for_system_receiver.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::NetworkIssue(id) if id == index));
+        handle.await.unwrap();
+    }
+
+    #[test]
+    fn test_lookup_id() {
+        let database = std::env::temp_dir().join(format!("ntp-test-passwd-{}", alloc_port()));
+        std::fs::write(
+            &database,
+            "root:x:0:0:root:/root:/bin/sh\ngpsd:x:114:20::/run/gpsd:/usr/sbin/nologin\n",
+        )
+        .unwrap();
+
+        assert_eq!(lookup_id(&database, "gpsd").unwrap(), 114);
+        assert_eq!(lookup_id(&database, "root").unwrap(), 0);
+        assert_eq!(lookup_id(&database, "1000").unwrap(), 1000);
+        assert!(lookup_id(&database, "gps").is_err());
+
+        std::fs::remove_file(database).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_socket_options() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = std::env::temp_dir().join(format!("ntp-test-sock-{}", alloc_port()));
+        let socket_path = directory.join("gpsd.sock");
+
+        // The directory is only created when asked for
+        let mut options = SockSocketOptions {
+            permissions: Some(0o620),
+            ..Default::default()
+        };
+        assert!(create_sock_socket(&socket_path, &options).is_err());
+        options.create_directory = true;
+        let _socket = create_sock_socket(&socket_path, &options).unwrap();
+
+        let mode = std::fs::metadata(&socket_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o620);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_precision_exponent() {
+        assert_eq!(precision_exponent(1.0), 0);
+        assert_eq!(precision_exponent(1e-3), -10);
+        assert_eq!(precision_exponent(1e-6), -20);
+    }
+
+    #[test]
+    fn test_pulse_preference() {
+        let index = ClockId::new();
+        let mut preference = PulsePreference::default();
+        let serial = |seconds, leap| {
+            let mut bytes = sample_bytes(0.1, 0, leap, true, true);
+            bytes[0..8].copy_from_slice(&i64::to_ne_bytes(seconds));
+            sample_measurement(&deserialize_sample(&bytes).unwrap(), index, -10)
+        };
+        let pulse = |seconds| {
+            let mut bytes = sample_bytes(0.0001, 1, 0, true, true);
+            bytes[0..8].copy_from_slice(&i64::to_ne_bytes(seconds));
+            sample_measurement(&deserialize_sample(&bytes).unwrap(), index, -10)
+        };
+
+        // Without pulses, serial samples are used
+        assert!(preference.filter(serial(1000, 1), false).is_some());
+
+        // Pulses get the leap second announcement of the serial data
+        let measurement = preference.filter(pulse(1001), true).unwrap();
+        assert_eq!(measurement.leap, NtpLeapIndicator::Leap61);
+
+        // And take over while they keep coming in
+        assert!(preference.filter(serial(1002, 0), false).is_none());
+        let measurement = preference.filter(pulse(1002), true).unwrap();
+        assert_eq!(measurement.leap, NtpLeapIndicator::NoWarning);
+
+        // Serial samples are used again once the pulses stop
+        assert!(preference.filter(serial(1010, 0), false).is_some());
+    }
+
+    // Samples as sent by gpsd on a little endian host
+    #[cfg(target_endian = "little")]
     #[test]
     fn test_deserialize_sample() {
         // Example sock sample
@@ -323,11 +715,14 @@ mod tests {
             127, 136, 245, 102, 0, 0, 0, 0, 33, 129, 4, 0, 0, 0, 0, 0, 125, 189, 182, 209, 254,
             119, 19, 65, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 75, 67, 79, 83,
         ];
-        let sample = deserialize_sample(Ok(buf.len()), buf).unwrap();
+        let sample = deserialize_sample(&buf).unwrap();
+        assert_eq!(
+            sample.time,
+            convert_unix_timestamp(1_727_367_295, 295_201_000)
+        );
         assert_eq!(sample.offset, 318975.704798661);
         assert_eq!(sample.pulse, 0);
         assert_eq!(sample.leap, 0);
-        assert_eq!(sample.magic, SOCK_MAGIC);
 
         // Wrong magic value
         let buf = [
@@ -335,19 +730,26 @@ mod tests {
             119, 19, 65, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,
         ];
         assert!(matches!(
-            dbg!(deserialize_sample(Ok(buf.len()), buf)),
+            dbg!(deserialize_sample(&buf)),
             Err(SampleError::WrongMagic(_))
         ));
 
-        // Wrong pulse value
+        // Pulse samples are accepted
         let buf = [
             127, 136, 245, 102, 0, 0, 0, 0, 33, 129, 4, 0, 0, 0, 0, 0, 125, 189, 182, 209, 254,
             119, 19, 65, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 75, 67, 79, 83,
         ];
-        assert!(matches!(
-            dbg!(deserialize_sample(Ok(buf.len()), buf)),
-            Err(SampleError::WrongPulse(_))
-        ));
+        let sample = deserialize_sample(&buf).unwrap();
+        assert_eq!(sample.pulse, 1);
+
+        // Older layout without padding
+        let buf = [
+            127, 136, 245, 102, 0, 0, 0, 0, 33, 129, 4, 0, 0, 0, 0, 0, 125, 189, 182, 209, 254,
+            119, 19, 65, 0, 0, 0, 0, 2, 0, 0, 0, 75, 67, 79, 83,
+        ];
+        let sample = deserialize_sample(&buf).unwrap();
+        assert_eq!(sample.offset, 318975.704798661);
+        assert_eq!(sample.leap, 2);
 
         // Wrong data size
         let buf = [
@@ -355,7 +757,7 @@ mod tests {
             119, 19, 65, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 75, 67, 79, 0,
         ];
         assert!(matches!(
-            dbg!(deserialize_sample(Ok(buf.len() - 1), buf)),
+            dbg!(deserialize_sample(&buf[..buf.len() - 1])),
             Err(SampleError::WrongSize(_))
         ));
     }
@@ -0,0 +1,130 @@
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::config::GpsdSourceConfig;
+
+use super::{
+    ClockId, GpsdSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct GpsdSpawner {
+    config: GpsdSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    has_spawned: bool,
+}
+
+impl GpsdSpawner {
+    pub fn new(config: GpsdSourceConfig, source_config: SourceConfig) -> GpsdSpawner {
+        GpsdSpawner {
+            config,
+            source_config,
+            id: SpawnerId::new(),
+            has_spawned: false,
+        }
+    }
+}
+
+impl Spawner for GpsdSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Gpsd(GpsdSourceCreateParameters {
+                    id: ClockId::new(),
+                    address: self.config.address.clone(),
+                    device: self.config.device.clone(),
+                    pps: self.config.pps,
+                    config: self.source_config,
+                    precision: self.config.precision.powi(2),
+                    accuracy: self.config.accuracy,
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.address.to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "gpsd"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::SourceConfig;
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::{GpsdAddress, GpsdSourceConfig, NormalizedAddress},
+        spawn::{SourceCreateParameters, SpawnAction, Spawner, gpsd::GpsdSpawner},
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let address = GpsdAddress(NormalizedAddress::new_from_parts("localhost", 2947));
+        let precision = 1e-3;
+        let mut spawner = GpsdSpawner::new(
+            GpsdSourceConfig {
+                address: address.clone(),
+                device: Some("/dev/ttyACM0".into()),
+                pps: false,
+                precision,
+                accuracy: 0.0,
+            },
+            SourceConfig::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), "localhost:2947");
+
+        let SourceCreateParameters::Gpsd(params) = create_params else {
+            panic!("did not receive gpsd source create parameters!");
+        };
+        assert_eq!(params.address, address);
+        assert_eq!(params.device.as_deref(), Some("/dev/ttyACM0"));
+        assert!(!params.pps);
+        assert!((params.precision - precision.powi(2)).abs() < 1e-12);
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+    }
+}
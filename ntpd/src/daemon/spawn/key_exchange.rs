@@ -0,0 +1,341 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ntp_proto::{
+    AeadAlgorithm, KeyExchangeClient, NtsCertificatePins, NtsClientConfig, NtsClientIdentity,
+    NtsError, ProtocolVersion, tls_utils::Certificate,
+};
+use rand::Rng;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::daemon::{
+    audit::{self, AuditEvent},
+    certificates::certificates_from_file,
+};
+
+/// Delay before retrying a key exchange server after the first failure
+const BACKOFF_MIN: Duration = Duration::from_secs(2);
+/// The delay doubles on every consecutive failure up to this limit
+const BACKOFF_MAX: Duration = Duration::from_secs(15 * 60);
+
+/// Settings of a source that determine how its key exchange client is set up.
+/// Sources with the same settings share a client, and thereby its TLS session
+/// tickets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyExchangeClientSettings {
+    pub certificate_authorities: Arc<[Certificate]>,
+    pub client_certificate_chain_path: Option<PathBuf>,
+    pub client_private_key_path: Option<PathBuf>,
+    pub ntp_version: ProtocolVersion,
+    pub aead_algorithms: Vec<AeadAlgorithm>,
+    pub pins: NtsCertificatePins,
+    pub session_resumption: bool,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl ServerState {
+    fn record_failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let delay = backoff_delay(self.consecutive_failures);
+        // Spread the retries of sources that failed together
+        let delay = delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        self.retry_at = Some(Instant::now() + delay);
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.retry_at = None;
+    }
+}
+
+fn backoff_delay(consecutive_failures: u32) -> Duration {
+    BACKOFF_MIN
+        .saturating_mul(2u32.saturating_pow(consecutive_failures.saturating_sub(1)))
+        .min(BACKOFF_MAX)
+}
+
+/// Load the certificate chain and private key used to authenticate to NTS
+/// key exchange servers
+fn client_identity_from_files(
+    certificate_chain_path: &Path,
+    private_key_path: &Path,
+) -> std::io::Result<NtsClientIdentity> {
+    let certificate_chain = certificates_from_file(certificate_chain_path)?;
+    let private_key = ntp_proto::tls_utils::pemfile::private_key(&mut BufReader::new(
+        std::fs::File::open(private_key_path)?,
+    ))?;
+
+    Ok(NtsClientIdentity {
+        certificate_chain,
+        private_key,
+    })
+}
+
+/// Coordinates the key exchanges of all NTS sources.
+///
+/// Only one key exchange with a given server runs at a time, and when one
+/// fails, every source using that server waits with exponential backoff
+/// before trying again. This avoids a burst of handshakes to a provider that
+/// many sources point at.
+#[derive(Clone, Default)]
+pub struct KeyExchangeManager {
+    #[expect(clippy::type_complexity)]
+    clients: Arc<Mutex<Vec<(KeyExchangeClientSettings, Arc<KeyExchangeClient>)>>>,
+    #[expect(clippy::type_complexity)]
+    servers: Arc<Mutex<HashMap<(String, u16), Arc<tokio::sync::Mutex<ServerState>>>>>,
+}
+
+impl KeyExchangeManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the key exchange client for sources with the given settings
+    pub fn client(
+        &self,
+        settings: &KeyExchangeClientSettings,
+    ) -> Result<Arc<KeyExchangeClient>, NtsError> {
+        let mut clients = self.clients.lock().expect("Unexpected poisoned mutex");
+        if let Some((_, client)) = clients.iter().find(|(known, _)| known == settings) {
+            return Ok(client.clone());
+        }
+
+        let identity = match (
+            &settings.client_certificate_chain_path,
+            &settings.client_private_key_path,
+        ) {
+            (Some(chain), Some(key)) => Some(Arc::new(client_identity_from_files(chain, key)?)),
+            _ => None,
+        };
+        let client = Arc::new(KeyExchangeClient::new(&NtsClientConfig {
+            certificates: settings.certificate_authorities.clone(),
+            protocol_version: settings.ntp_version,
+            identity,
+            algorithms: settings.aead_algorithms.clone(),
+            pins: settings.pins.clone(),
+            session_resumption: settings.session_resumption,
+        })?);
+        clients.push((settings.clone(), client.clone()));
+        Ok(client)
+    }
+
+    /// Run a key exchange with the server at `server_name` and `port`, where
+    /// `exchange` returns `None` when the key exchange failed.
+    ///
+    /// Waits for any other key exchange with the same server to finish first.
+    /// Returns `None` without running `exchange` while backing off after a
+    /// failure.
+    pub async fn exchange<T>(
+        &self,
+        server_name: &str,
+        port: u16,
+        exchange: impl Future<Output = Option<T>>,
+    ) -> Option<T> {
+        let state = self
+            .servers
+            .lock()
+            .expect("Unexpected poisoned mutex")
+            .entry((server_name.to_owned(), port))
+            .or_default()
+            .clone();
+        let mut state = state.lock().await;
+
+        if let Some(retry_at) = state.retry_at
+            && Instant::now() < retry_at
+        {
+            debug!(
+                server_name,
+                port,
+                failures = state.consecutive_failures,
+                "Backing off from key exchange server"
+            );
+            return None;
+        }
+
+        let result = exchange.await;
+        audit::record(&AuditEvent::NtsKeClient {
+            server: server_name,
+            port,
+            success: result.is_some(),
+        });
+        if result.is_some() {
+            state.record_success();
+        } else {
+            state.record_failure();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl KeyExchangeManager {
+        fn server_state(
+            &self,
+            server_name: &str,
+            port: u16,
+        ) -> Arc<tokio::sync::Mutex<ServerState>> {
+            self.servers.lock().unwrap()[&(server_name.to_owned(), port)].clone()
+        }
+    }
+
+    #[test]
+    fn client_identity() {
+        let identity = client_identity_from_files(
+            Path::new("test-keys/end.fullchain.pem"),
+            Path::new("test-keys/end.key"),
+        )
+        .unwrap();
+        assert_eq!(identity.certificate_chain.len(), 2);
+
+        assert!(
+            client_identity_from_files(
+                Path::new("test-keys/end.fullchain.pem"),
+                Path::new("test-keys/does-not-exist.key"),
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1), BACKOFF_MIN);
+        assert_eq!(backoff_delay(2), BACKOFF_MIN * 2);
+        assert_eq!(backoff_delay(3), BACKOFF_MIN * 4);
+        assert_eq!(backoff_delay(20), BACKOFF_MAX);
+        assert_eq!(backoff_delay(u32::MAX), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_failure_jitter() {
+        let mut state = ServerState::default();
+        for failures in 1..=3 {
+            let before = Instant::now();
+            state.record_failure();
+            let retry_at = state.retry_at.unwrap();
+            let delay = backoff_delay(failures);
+            assert!(retry_at >= before + delay / 2);
+            assert!(retry_at <= Instant::now() + delay);
+        }
+
+        state.record_success();
+        assert_eq!(state.consecutive_failures, 0);
+        assert!(state.retry_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_backoff() {
+        let manager = KeyExchangeManager::new();
+
+        assert!(
+            manager
+                .exchange("a.test", 4460, async { None::<()> })
+                .await
+                .is_none()
+        );
+
+        // Backing off, the exchange is not attempted
+        let mut attempted = false;
+        let result = manager
+            .exchange("a.test", 4460, async {
+                attempted = true;
+                Some(())
+            })
+            .await;
+        assert!(result.is_none());
+        assert!(!attempted);
+
+        // Other servers are not affected
+        assert!(
+            manager
+                .exchange("b.test", 4460, async { Some(()) })
+                .await
+                .is_some()
+        );
+        assert!(
+            manager
+                .exchange("a.test", 123, async { Some(()) })
+                .await
+                .is_some()
+        );
+
+        // Once the backoff has passed, a success resets it
+        manager.server_state("a.test", 4460).lock().await.retry_at = Some(Instant::now());
+        assert!(
+            manager
+                .exchange("a.test", 4460, async { Some(()) })
+                .await
+                .is_some()
+        );
+        let state = manager.server_state("a.test", 4460);
+        assert_eq!(state.lock().await.consecutive_failures, 0);
+        assert!(state.lock().await.retry_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_serialized() {
+        let manager = KeyExchangeManager::new();
+        let order = Mutex::new(vec![]);
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+
+        let first = manager.exchange("a.test", 4460, async {
+            rx.await.ok();
+            order.lock().unwrap().push(1);
+            Some(())
+        });
+        let second = manager.exchange("a.test", 4460, async {
+            order.lock().unwrap().push(2);
+            Some(())
+        });
+
+        // The second exchange waits for the first one to complete, even
+        // though the first one is only allowed to finish after the second
+        // one started
+        tokio::join!(first, async {
+            tokio::task::yield_now().await;
+            tx.send(()).ok();
+            second.await
+        });
+        assert_eq!(*order.lock().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn test_client_shared() {
+        let manager = KeyExchangeManager::new();
+        let settings = KeyExchangeClientSettings {
+            certificate_authorities: Arc::default(),
+            client_certificate_chain_path: None,
+            client_private_key_path: None,
+            ntp_version: ProtocolVersion::V4,
+            aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+            pins: NtsCertificatePins::default(),
+            session_resumption: true,
+        };
+
+        let a = manager.client(&settings).unwrap();
+        let b = manager.client(&settings).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+
+        let c = manager
+            .client(&KeyExchangeClientSettings {
+                ntp_version: ProtocolVersion::V5,
+                ..settings
+            })
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+}
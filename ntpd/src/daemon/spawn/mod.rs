@@ -1,26 +1,53 @@
-use std::{future::Future, net::SocketAddr, path::PathBuf, sync::atomic::AtomicU64};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, RwLock, atomic::AtomicU64},
+    time::Duration,
+};
 
-use ntp_proto::{ClockId, ProtocolVersion, SourceConfig, SourceNtsData};
+use ntp_proto::{
+    ClockId, InitialBurst, PollInterval, ProtocolVersion, SourceConfig, SourceNtsData, SymmetricKey,
+};
+use rand::Rng;
 use tokio::{
     sync::mpsc,
     time::{Instant, timeout},
 };
 use tracing::warn;
 
-use crate::daemon::config::NtpAddress;
+#[cfg(feature = "pps")]
+use crate::daemon::config::PpsEdge;
+use crate::daemon::config::{
+    GpsdAddress, NmeaOffsets, NmeaSentence, NtpAddress, SockSocketOptions,
+};
 
-use super::{config::NormalizedAddress, system::NETWORK_WAIT_PERIOD};
+use super::{
+    config::NormalizedAddress, observer::ObservableSpawnerState, system::NETWORK_WAIT_PERIOD,
+};
 
+pub mod broadcast;
+pub mod gpsd;
+pub mod key_exchange;
+pub mod nmea;
 pub mod nts;
 pub mod nts_pool;
+pub mod phc;
 pub mod pool;
 #[cfg(feature = "pps")]
 pub mod pps;
+pub mod ptp;
+pub mod roughtime;
+pub mod shm;
 pub mod sock;
 pub mod standard;
 
 const NTS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
+// Longest time spawners wait before trying again after repeated failures
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
 /// Unique identifier for a spawner.
 /// This is used to identify which spawner was used to create a source
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -60,7 +87,11 @@ impl SpawnEvent {
 #[derive(Debug)]
 pub enum SystemEvent {
     SourceRemoved(SourceRemovedEvent),
-    SourceRegistered(SourceCreateParameters),
+    SourceRegistered(Box<SourceCreateParameters>),
+    /// The addresses or default routes of the system changed
+    NetworkChanged,
+    /// There is room again for a source that was trimmed earlier
+    CapacityAvailable,
     Idle,
 }
 
@@ -82,13 +113,20 @@ pub enum SourceRemovalReason {
     Demobilized,
     NetworkIssue,
     Unreachable,
+    /// The system has as many sources as it is allowed to run. The source
+    /// should not be replaced until the system says there is room again.
+    Trimmed,
 }
 
 /// The kind of action that the spawner requests to the system.
-/// Currently a spawner can only create sources
 #[derive(Debug)]
 pub enum SpawnAction {
     Create(SourceCreateParameters),
+    /// Continue polling a source at another address, keeping its state
+    ChangeAddress {
+        id: ClockId,
+        addr: SocketAddr,
+    },
     // Remove(()),
 }
 
@@ -100,6 +138,7 @@ impl SpawnAction {
         protocol_version: ProtocolVersion,
         config: SourceConfig,
         nts: Option<Box<SourceNtsData>>,
+        symmetric_key: Option<SymmetricKey>,
     ) -> SpawnAction {
         SpawnAction::Create(SourceCreateParameters::Ntp(NtpSourceCreateParameters {
             id,
@@ -108,14 +147,40 @@ impl SpawnAction {
             protocol_version,
             config,
             nts,
+            symmetric_key,
+            nts_fallback: false,
         }))
     }
 }
 
+/// Source configuration for a source about to be spawned
+///
+/// The initial burst is only meant to speed up synchronization when a source
+/// is first mobilized. A source replacing one that was lost due to a network
+/// issue skips it, so that a flaky network does not lead to bursts of
+/// requests to the server.
+fn spawn_source_config(source_config: SourceConfig, reconnect: bool) -> SourceConfig {
+    if reconnect {
+        SourceConfig {
+            initial_burst: InitialBurst::DISABLED,
+            ..source_config
+        }
+    } else {
+        source_config
+    }
+}
+
 #[derive(Debug)]
 pub enum SourceCreateParameters {
     Ntp(NtpSourceCreateParameters),
     Sock(SockSourceCreateParameters),
+    Broadcast(BroadcastSourceCreateParameters),
+    Roughtime(RoughtimeSourceCreateParameters),
+    Nmea(NmeaSourceCreateParameters),
+    Gpsd(GpsdSourceCreateParameters),
+    Phc(PhcSourceCreateParameters),
+    Ptp(PtpSourceCreateParameters),
+    Shm(ShmSourceCreateParameters),
     #[cfg(feature = "pps")]
     Pps(PpsSourceCreateParameters),
 }
@@ -125,6 +190,13 @@ impl SourceCreateParameters {
         match self {
             Self::Ntp(params) => params.id,
             Self::Sock(params) => params.id,
+            Self::Broadcast(params) => params.id,
+            Self::Roughtime(params) => params.id,
+            Self::Nmea(params) => params.id,
+            Self::Gpsd(params) => params.id,
+            Self::Phc(params) => params.id,
+            Self::Ptp(params) => params.id,
+            Self::Shm(params) => params.id,
             #[cfg(feature = "pps")]
             Self::Pps(params) => params.id,
         }
@@ -134,6 +206,13 @@ impl SourceCreateParameters {
         match self {
             Self::Ntp(params) => params.addr.to_string(),
             Self::Sock(params) => params.path.display().to_string(),
+            Self::Broadcast(params) => params.listen.to_string(),
+            Self::Roughtime(params) => params.addr.to_string(),
+            Self::Nmea(params) => params.path.display().to_string(),
+            Self::Gpsd(params) => params.address.to_string(),
+            Self::Phc(params) => params.path.display().to_string(),
+            Self::Ptp(params) => params.group.to_string(),
+            Self::Shm(params) => format!("SHM({})", params.unit),
             #[cfg(feature = "pps")]
             Self::Pps(params) => params.path.display().to_string(),
         }
@@ -148,12 +227,93 @@ pub struct NtpSourceCreateParameters {
     pub protocol_version: ProtocolVersion,
     pub config: SourceConfig,
     pub nts: Option<Box<SourceNtsData>>,
+    pub symmetric_key: Option<SymmetricKey>,
+    /// Whether this source stands in, without authentication, for an NTS
+    /// source whose key exchange failed. Such a source is demobilized after
+    /// a while, so that the key exchange is tried again.
+    pub nts_fallback: bool,
 }
 
 #[derive(Debug)]
 pub struct SockSourceCreateParameters {
     pub id: ClockId,
     pub path: PathBuf,
+    pub socket: SockSocketOptions,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct BroadcastSourceCreateParameters {
+    pub id: ClockId,
+    pub listen: SocketAddr,
+    pub multicast_group: Option<IpAddr>,
+    pub delay: Option<f64>,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct RoughtimeSourceCreateParameters {
+    pub id: ClockId,
+    pub addr: SocketAddr,
+    pub public_key: [u8; ntp_proto::roughtime::PUBLIC_KEY_SIZE],
+    pub poll_interval: PollInterval,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct NmeaSourceCreateParameters {
+    pub id: ClockId,
+    pub path: PathBuf,
+    pub baud_rate: u32,
+    pub sentences: Vec<NmeaSentence>,
+    pub offsets: NmeaOffsets,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct GpsdSourceCreateParameters {
+    pub id: ClockId,
+    pub address: GpsdAddress,
+    pub device: Option<String>,
+    pub pps: bool,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct PhcSourceCreateParameters {
+    pub id: ClockId,
+    pub path: PathBuf,
+    pub poll_interval: PollInterval,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct PtpSourceCreateParameters {
+    pub id: ClockId,
+    pub group: IpAddr,
+    pub domain: u8,
+    pub config: SourceConfig,
+    pub precision: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Debug)]
+pub struct ShmSourceCreateParameters {
+    pub id: ClockId,
+    pub unit: u8,
+    pub poll_interval: PollInterval,
     pub config: SourceConfig,
     pub precision: f64,
     pub accuracy: f64,
@@ -168,6 +328,8 @@ pub struct PpsSourceCreateParameters {
     pub precision: f64,
     pub accuracy: f64,
     pub period: f64,
+    pub edge: PpsEdge,
+    pub lock: Option<String>,
 }
 
 pub trait Spawner {
@@ -215,6 +377,27 @@ pub trait Spawner {
         async { Ok(()) }
     }
 
+    /// Event handler for when the network configuration of the system
+    /// changed. Addresses resolved before the change may no longer be
+    /// reachable, or no longer the closest ones.
+    ///
+    /// This should just do bookkeeping, any adding of sources should be done
+    /// in try_add.
+    fn handle_network_change(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Event handler for when the system has room again for a source that
+    /// was trimmed. This is called once for every trimmed source.
+    ///
+    /// This should just do bookkeeping, any adding of sources should be done
+    /// in try_add.
+    fn handle_capacity_available(
+        &mut self,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
     /// Get the id of the spawner
     fn get_id(&self) -> SpawnerId;
 
@@ -225,35 +408,107 @@ pub trait Spawner {
     fn get_description(&self) -> &str;
 }
 
+/// A spawner that failed to create its sources, waiting to try again
+#[derive(Debug, Clone)]
+pub struct SpawnerRetry {
+    pub description: String,
+    pub address: String,
+    pub failures: u32,
+    pub at: Instant,
+}
+
+impl From<&SpawnerRetry> for ObservableSpawnerState {
+    fn from(retry: &SpawnerRetry) -> Self {
+        ObservableSpawnerState {
+            kind: retry.description.clone(),
+            address: retry.address.clone(),
+            failures: retry.failures,
+            retry_in: retry
+                .at
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64(),
+        }
+    }
+}
+
+/// Exponential backoff for spawners, so that a server or DNS name that is
+/// down isn't tried again every second
+#[derive(Debug, Default)]
+struct Backoff {
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Backoff {
+    /// Count a failure, giving the time to wait before trying again
+    fn failed(&mut self) -> Duration {
+        // Failures long after the previous one start over
+        if self
+            .last_failure
+            .is_some_and(|last_failure| last_failure.elapsed() >= 2 * MAX_RETRY_DELAY)
+        {
+            self.failures = 0;
+        }
+        self.failures = self.failures.saturating_add(1);
+        self.last_failure = Some(Instant::now());
+
+        let delay = NETWORK_WAIT_PERIOD
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(MAX_RETRY_DELAY);
+        // Jitter keeps spawners that failed together from retrying together
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..1.0))
+    }
+}
+
 pub async fn spawner_task<S: Spawner + Send + 'static>(
     mut spawner: S,
     action_tx: mpsc::Sender<SpawnEvent>,
     mut system_notify: mpsc::Receiver<SystemEvent>,
+    retries: Arc<RwLock<HashMap<SpawnerId, SpawnerRetry>>>,
 ) -> Result<(), S::Error> {
-    let mut has_ticket = true;
-    let mut last_ticket_time = Instant::now();
+    let mut backoff = Backoff::default();
+    let mut next_attempt = Instant::now();
+
+    let retry_after = |backoff: &mut Backoff, spawner: &S| {
+        let at = Instant::now() + backoff.failed();
+        retries.write().expect("Unexpected poisoned mutex").insert(
+            spawner.get_id(),
+            SpawnerRetry {
+                description: spawner.get_description().to_owned(),
+                address: spawner.get_addr_description(),
+                failures: backoff.failures,
+                at,
+            },
+        );
+        at
+    };
+    let clear_retry = |spawner: &S| {
+        retries
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .remove(&spawner.get_id());
+    };
 
     loop {
-        if last_ticket_time.elapsed() >= NETWORK_WAIT_PERIOD {
-            has_ticket = true;
-        }
-
-        if has_ticket && !spawner.is_complete() {
+        if Instant::now() >= next_attempt && !spawner.is_complete() {
             spawner.try_spawn(&action_tx).await?;
-            has_ticket = false;
-            last_ticket_time = Instant::now();
+            if spawner.is_complete() {
+                clear_retry(&spawner);
+                next_attempt = Instant::now() + NETWORK_WAIT_PERIOD;
+            } else {
+                next_attempt = retry_after(&mut backoff, &spawner);
+            }
         }
 
-        let event = if has_ticket {
-            system_notify.recv().await
-        } else {
-            timeout(
-                NETWORK_WAIT_PERIOD - last_ticket_time.elapsed(),
-                system_notify.recv(),
-            )
-            .await
-            .unwrap_or(Some(SystemEvent::Idle))
+        // Spawners that are complete may still want to refresh their
+        // sources after a while, so check back every now and then
+        let wait = match next_attempt.saturating_duration_since(Instant::now()) {
+            Duration::ZERO => NETWORK_WAIT_PERIOD,
+            wait => wait,
         };
+        let event = timeout(wait, system_notify.recv())
+            .await
+            .unwrap_or(Some(SystemEvent::Idle));
 
         let Some(event) = event else {
             break;
@@ -261,15 +516,34 @@ pub async fn spawner_task<S: Spawner + Send + 'static>(
 
         match event {
             SystemEvent::SourceRegistered(source_params) => {
-                spawner.handle_registered(source_params).await?;
+                spawner.handle_registered(*source_params).await?;
             }
             SystemEvent::SourceRemoved(removed_source) => {
+                let failed = matches!(
+                    removed_source.reason,
+                    SourceRemovalReason::NetworkIssue | SourceRemovalReason::Unreachable
+                );
                 spawner.handle_source_removed(removed_source).await?;
+                if failed {
+                    next_attempt = retry_after(&mut backoff, &spawner);
+                }
+            }
+            SystemEvent::NetworkChanged => {
+                spawner.handle_network_change().await?;
+                // Sources that couldn't be created without a network may
+                // well work now
+                backoff = Backoff::default();
+                next_attempt = Instant::now();
+                clear_retry(&spawner);
+            }
+            SystemEvent::CapacityAvailable => {
+                spawner.handle_capacity_available().await?;
             }
             SystemEvent::Idle => {}
         }
     }
 
+    clear_retry(&spawner);
     Ok(())
 }
 
@@ -306,9 +580,36 @@ pub(super) async fn resolve_single_ntp_server(address: NtpAddress) -> Option<Soc
     }
 }
 
+/// Find the NTP server a key exchange directed us to. When the key exchange
+/// server did not name a server, RFC 8915 has us use the IP address of the key
+/// exchange server we talked to, rather than resolving its name again (which
+/// could give a different server of a load-balanced set).
+pub(super) async fn resolve_nts_ntp_server(
+    remote: &str,
+    port: u16,
+    remote_negotiated: bool,
+    ke_peer: Option<SocketAddr>,
+) -> Option<SocketAddr> {
+    if !remote_negotiated && let Some(ke_peer) = ke_peer {
+        return Some(SocketAddr::new(ke_peer.ip(), port));
+    }
+
+    // IPv6 addresses are sent without brackets, but accept them anyway
+    let remote = remote
+        .strip_prefix('[')
+        .and_then(|remote| remote.strip_suffix(']'))
+        .unwrap_or(remote);
+    resolve_single_ntp_server(NtpAddress(NormalizedAddress::new_from_parts(remote, port))).await
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{NtpSourceCreateParameters, SourceCreateParameters, SpawnAction, SpawnEvent};
+    use std::net::SocketAddr;
+
+    use super::{
+        Backoff, MAX_RETRY_DELAY, NETWORK_WAIT_PERIOD, NtpSourceCreateParameters,
+        SourceCreateParameters, SpawnAction, SpawnEvent, resolve_nts_ntp_server,
+    };
 
     pub fn get_ntp_create_params(res: SpawnEvent) -> Option<NtpSourceCreateParameters> {
         let SpawnAction::Create(SourceCreateParameters::Ntp(params)) = res.action else {
@@ -316,4 +617,50 @@ mod tests {
         };
         Some(params)
     }
+
+    #[test]
+    fn backoff_grows_with_jitter() {
+        let mut backoff = Backoff::default();
+
+        let delay = backoff.failed();
+        assert!(delay >= NETWORK_WAIT_PERIOD / 2 && delay <= NETWORK_WAIT_PERIOD);
+        let delay = backoff.failed();
+        assert!(delay >= NETWORK_WAIT_PERIOD && delay <= 2 * NETWORK_WAIT_PERIOD);
+        let delay = backoff.failed();
+        assert!(delay >= 2 * NETWORK_WAIT_PERIOD && delay <= 4 * NETWORK_WAIT_PERIOD);
+
+        // But never beyond the maximum
+        for _ in 0..100 {
+            let delay = backoff.failed();
+            assert!(delay <= MAX_RETRY_DELAY);
+        }
+        let delay = backoff.failed();
+        assert!(delay >= MAX_RETRY_DELAY / 2 && delay <= MAX_RETRY_DELAY);
+        assert_eq!(backoff.failures, 104);
+    }
+
+    #[tokio::test]
+    async fn nts_ntp_server_from_key_exchange() {
+        let ke_peer: SocketAddr = "192.0.2.1:4460".parse().unwrap();
+
+        // Without a server record we stay with the key exchange server
+        assert_eq!(
+            resolve_nts_ntp_server("example.com", 123, false, Some(ke_peer)).await,
+            Some("192.0.2.1:123".parse().unwrap())
+        );
+        assert_eq!(
+            resolve_nts_ntp_server("example.com", 1234, false, Some(ke_peer)).await,
+            Some("192.0.2.1:1234".parse().unwrap())
+        );
+
+        // A server record takes precedence
+        assert_eq!(
+            resolve_nts_ntp_server("127.0.0.1", 568, true, Some(ke_peer)).await,
+            Some("127.0.0.1:568".parse().unwrap())
+        );
+        assert_eq!(
+            resolve_nts_ntp_server("127.0.0.1", 568, false, None).await,
+            Some("127.0.0.1:568".parse().unwrap())
+        );
+    }
 }
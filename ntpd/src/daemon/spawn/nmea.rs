@@ -0,0 +1,138 @@
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::config::NmeaSourceConfig;
+
+use super::{
+    ClockId, NmeaSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct NmeaSpawner {
+    config: NmeaSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    has_spawned: bool,
+}
+
+impl NmeaSpawner {
+    pub fn new(config: NmeaSourceConfig, source_config: SourceConfig) -> NmeaSpawner {
+        NmeaSpawner {
+            config,
+            source_config,
+            id: SpawnerId::new(),
+            has_spawned: false,
+        }
+    }
+}
+
+impl Spawner for NmeaSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Nmea(NmeaSourceCreateParameters {
+                    id: ClockId::new(),
+                    path: self.config.path.clone(),
+                    baud_rate: self.config.baud_rate,
+                    sentences: self.config.sentences.clone(),
+                    offsets: self.config.offsets,
+                    config: self.source_config,
+                    precision: self.config.precision.powi(2),
+                    accuracy: self.config.accuracy,
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.path.display().to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "nmea"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ntp_proto::SourceConfig;
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::{NmeaOffsets, NmeaSentence, NmeaSourceConfig},
+        spawn::{SourceCreateParameters, SpawnAction, Spawner, nmea::NmeaSpawner},
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let path = PathBuf::from("/dev/ttyS0");
+        let precision = 1e-2;
+        let mut spawner = NmeaSpawner::new(
+            NmeaSourceConfig {
+                path: path.clone(),
+                baud_rate: 4800,
+                sentences: vec![NmeaSentence::Rmc],
+                offsets: NmeaOffsets {
+                    rmc: 0.2,
+                    ..NmeaOffsets::default()
+                },
+                precision,
+                accuracy: 0.0,
+            },
+            SourceConfig::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), "/dev/ttyS0");
+
+        let SourceCreateParameters::Nmea(params) = create_params else {
+            panic!("did not receive NMEA source create parameters!");
+        };
+        assert_eq!(params.path, path);
+        assert_eq!(params.baud_rate, 4800);
+        assert_eq!(params.sentences, vec![NmeaSentence::Rmc]);
+        assert!((params.offsets.rmc - 0.2).abs() < 1e-12);
+        assert!((params.precision - precision.powi(2)).abs() < 1e-9);
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+    }
+}
@@ -1,25 +1,38 @@
 use std::fmt::Display;
+use std::net::SocketAddr;
 use std::ops::Deref;
+use std::sync::Arc;
 
-use ntp_proto::{KeyExchangeClient, NtsClientConfig, NtsError, SourceConfig};
+use ntp_proto::{
+    KeyExchangeClient, KeyExchangeResult, NtsCertificatePins, NtsError, ProtocolVersion,
+    SourceConfig,
+};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
-use tracing::warn;
+use tracing::{debug, warn};
 
-use crate::daemon::config::{NormalizedAddress, NtpAddress};
 use crate::daemon::dns::resolve_ke;
-use crate::daemon::spawn::resolve_single_ntp_server;
+use crate::daemon::spawn::{resolve_nts_ntp_server, resolve_single_ntp_server};
 
-use super::super::config::NtsSourceConfig;
+use super::super::config::{NtsFailurePolicy, NtsSourceConfig};
 
-use super::{ClockId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId};
+use super::{
+    ClockId, NtpSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+    key_exchange::{KeyExchangeClientSettings, KeyExchangeManager},
+    spawn_source_config,
+};
 
 pub struct NtsSpawner {
     config: NtsSourceConfig,
-    key_exchange_client: KeyExchangeClient,
+    key_exchange_client: Arc<KeyExchangeClient>,
+    key_exchange_manager: KeyExchangeManager,
     source_config: SourceConfig,
     id: SpawnerId,
     has_spawned: bool,
+    reconnect: bool,
+    /// NTP server and version from the last successful key exchange
+    last_ntp_server: Option<(SocketAddr, ProtocolVersion)>,
 }
 
 #[derive(Debug)]
@@ -47,18 +60,30 @@ impl NtsSpawner {
     pub fn new(
         config: NtsSourceConfig,
         source_config: SourceConfig,
+        key_exchange_manager: KeyExchangeManager,
     ) -> Result<NtsSpawner, NtsError> {
-        let key_exchange_client = KeyExchangeClient::new(&NtsClientConfig {
-            certificates: config.certificate_authorities.clone(),
-            protocol_version: config.ntp_version,
+        let key_exchange_client = key_exchange_manager.client(&KeyExchangeClientSettings {
+            certificate_authorities: config.certificate_authorities.clone(),
+            client_certificate_chain_path: config.client_certificate_chain_path.clone(),
+            client_private_key_path: config.client_private_key_path.clone(),
+            ntp_version: config.ntp_version,
+            aead_algorithms: config.aead_algorithms.clone(),
+            pins: NtsCertificatePins {
+                certificate_authorities: config.pinned_certificate_authorities.clone(),
+                spki_sha256: config.pin_spki_sha256.clone(),
+            },
+            session_resumption: config.session_resumption,
         })?;
 
         Ok(NtsSpawner {
             config,
             key_exchange_client,
+            key_exchange_manager,
             source_config,
             id: SpawnerId::new(),
             has_spawned: false,
+            reconnect: false,
+            last_ntp_server: None,
         })
     }
 
@@ -115,18 +140,11 @@ impl NtsSpawner {
             Some((io, self.config.address.server_name.clone()))
         }
     }
-}
 
-impl Spawner for NtsSpawner {
-    type Error = NtsSpawnError;
-
-    async fn try_spawn(
-        &mut self,
-        action_tx: &mpsc::Sender<SpawnEvent>,
-    ) -> Result<(), NtsSpawnError> {
-        let Some((io, name)) = self.resolve_and_connect().await else {
-            return Ok(());
-        };
+    async fn key_exchange(&mut self) -> Option<(KeyExchangeResult, Option<SocketAddr>)> {
+        let (io, name) = self.resolve_and_connect().await?;
+        let ke_peer = io.peer_addr().ok();
+        let name = self.config.server_name.clone().unwrap_or(name);
 
         match tokio::time::timeout(
             super::NTS_TIMEOUT,
@@ -134,34 +152,108 @@ impl Spawner for NtsSpawner {
         )
         .await
         {
-            Ok(Ok(ke)) => {
-                if let Some(address) = resolve_single_ntp_server(NtpAddress(
-                    NormalizedAddress::new_from_parts(ke.remote.as_str(), ke.port),
-                ))
-                .await
-                {
-                    action_tx
-                        .send(SpawnEvent::new(
-                            self.id,
-                            SpawnAction::create_ntp(
-                                ClockId::new(),
-                                address,
-                                self.config.address.deref().clone(),
-                                ke.protocol_version,
-                                self.source_config,
-                                Some(ke.nts),
-                            ),
-                        ))
-                        .await?;
-                    self.has_spawned = true;
-                }
-            }
+            Ok(Ok(ke)) => Some((ke, ke_peer)),
             Ok(Err(e)) => {
                 warn!(error = ?e, "error while attempting key exchange");
+                None
             }
             Err(_) => {
                 warn!("timeout while attempting key exchange");
+                None
+            }
+        }
+    }
+
+    /// After a failed key exchange, keep using the NTP server we had keys for
+    /// without authentication, if so configured
+    async fn spawn_fallback(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), NtsSpawnError> {
+        let (NtsFailurePolicy::Unauthenticated, Some((address, protocol_version))) =
+            (self.config.nts_failure_policy, self.last_ntp_server)
+        else {
+            return Ok(());
+        };
+
+        warn!(
+            %address,
+            "NTS key exchange with {} failed, falling back to UNAUTHENTICATED time from its NTP server",
+            *self.config.address
+        );
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Ntp(NtpSourceCreateParameters {
+                    id: ClockId::new(),
+                    addr: address,
+                    normalized_addr: self.config.address.deref().clone(),
+                    protocol_version,
+                    config: spawn_source_config(self.source_config, true),
+                    nts: None,
+                    symmetric_key: None,
+                    nts_fallback: true,
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+
+        Ok(())
+    }
+}
+
+impl Spawner for NtsSpawner {
+    type Error = NtsSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), NtsSpawnError> {
+        let manager = self.key_exchange_manager.clone();
+        let server_name = self.config.address.server_name.clone();
+        let Some((ke, ke_peer)) = manager
+            .exchange(&server_name, self.config.address.port, self.key_exchange())
+            .await
+        else {
+            return self.spawn_fallback(action_tx).await;
+        };
+
+        // The keys only work with the NTP server that the key exchange server
+        // shares them with, so using another address is fine: that server
+        // still has to prove it knows them.
+        let address = match &self.config.ntp_address {
+            Some(ntp_address) => {
+                if ke.remote_negotiated {
+                    debug!(
+                        remote = ke.remote,
+                        port = ke.port,
+                        "Ignoring NTP server from key exchange in favor of configured address"
+                    );
+                }
+                resolve_single_ntp_server(ntp_address.clone()).await
             }
+            None => {
+                resolve_nts_ntp_server(&ke.remote, ke.port, ke.remote_negotiated, ke_peer).await
+            }
+        };
+
+        if let Some(address) = address {
+            action_tx
+                .send(SpawnEvent::new(
+                    self.id,
+                    SpawnAction::create_ntp(
+                        ClockId::new(),
+                        address,
+                        self.config.address.deref().clone(),
+                        ke.protocol_version,
+                        spawn_source_config(self.source_config, self.reconnect),
+                        Some(ke.nts),
+                        None,
+                    ),
+                ))
+                .await?;
+            self.has_spawned = true;
+            self.last_ntp_server = Some((address, ke.protocol_version));
         }
 
         Ok(())
@@ -173,8 +265,16 @@ impl Spawner for NtsSpawner {
 
     async fn handle_source_removed(
         &mut self,
-        _removed_source: SourceRemovedEvent,
+        removed_source: SourceRemovedEvent,
     ) -> Result<(), NtsSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Trimmed {
+            self.has_spawned = false;
+        }
+        self.reconnect = removed_source.reason == SourceRemovalReason::NetworkIssue;
+        Ok(())
+    }
+
+    async fn handle_capacity_available(&mut self) -> Result<(), NtsSpawnError> {
         self.has_spawned = false;
         Ok(())
     }
@@ -196,12 +296,15 @@ impl Spawner for NtsSpawner {
 mod tests {
     use std::sync::Arc;
 
-    use ntp_proto::SourceConfig;
+    use ntp_proto::{AeadAlgorithm, SourceConfig};
     use tokio::{io::AsyncReadExt, net::TcpListener};
 
     use crate::daemon::{
-        config::{NormalizedAddress, NtsKeAddress, NtsSourceConfig},
-        spawn::{Spawner, nts::NtsSpawner},
+        config::{NormalizedAddress, NtsFailurePolicy, NtsKeAddress, NtsSourceConfig},
+        spawn::{
+            Spawner, key_exchange::KeyExchangeManager, nts::NtsSpawner,
+            tests::get_ntp_create_params,
+        },
     };
 
     #[tokio::test]
@@ -222,9 +325,19 @@ mod tests {
                 address: NtsKeAddress(NormalizedAddress::new_from_parts("localhost", addr.port())),
                 enable_srv_resolution: false,
                 certificate_authorities: Arc::default(),
+                client_certificate_chain_path: None,
+                client_private_key_path: None,
                 ntp_version: ntp_proto::ProtocolVersion::V4,
+                aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pinned_certificate_authorities: vec![],
+                pin_spki_sha256: vec![],
+                session_resumption: true,
+                server_name: None,
+                ntp_address: None,
+                nts_failure_policy: NtsFailurePolicy::Retry,
             },
             SourceConfig::default(),
+            KeyExchangeManager::new(),
         )
         .unwrap();
 
@@ -255,9 +368,19 @@ mod tests {
                 address: NtsKeAddress(NormalizedAddress::new_from_parts("localhost.", addr.port())),
                 enable_srv_resolution: true,
                 certificate_authorities: Arc::default(),
+                client_certificate_chain_path: None,
+                client_private_key_path: None,
                 ntp_version: ntp_proto::ProtocolVersion::V4,
+                aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+                pinned_certificate_authorities: vec![],
+                pin_spki_sha256: vec![],
+                session_resumption: true,
+                server_name: None,
+                ntp_address: None,
+                nts_failure_policy: NtsFailurePolicy::Retry,
             },
             SourceConfig::default(),
+            KeyExchangeManager::new(),
         )
         .unwrap();
 
@@ -269,4 +392,64 @@ mod tests {
         assert!(server.is_finished());
         assert!(server.await.is_ok());
     }
+
+    #[tokio::test]
+    async fn fallback_to_unauthenticated() {
+        #[cfg(feature = "openssl")]
+        let _ = rustls_openssl::default_provider().install_default();
+
+        // Nothing listens on this port once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let config = |nts_failure_policy| NtsSourceConfig {
+            address: NtsKeAddress(NormalizedAddress::new_from_parts("127.0.0.1", port)),
+            enable_srv_resolution: false,
+            certificate_authorities: Arc::default(),
+            client_certificate_chain_path: None,
+            client_private_key_path: None,
+            ntp_version: ntp_proto::ProtocolVersion::V4,
+            aead_algorithms: AeadAlgorithm::SUPPORTED.to_vec(),
+            pinned_certificate_authorities: vec![],
+            pin_spki_sha256: vec![],
+            session_resumption: true,
+            server_name: None,
+            ntp_address: None,
+            nts_failure_policy,
+        };
+        let ntp_server = "192.0.2.1:123".parse().unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+
+        // By default, a failed key exchange leaves the source without a server
+        let mut spawner = NtsSpawner::new(
+            config(NtsFailurePolicy::Retry),
+            SourceConfig::default(),
+            KeyExchangeManager::new(),
+        )
+        .unwrap();
+        spawner.last_ntp_server = Some((ntp_server, ntp_proto::ProtocolVersion::V4));
+        spawner.try_spawn(&sender).await.unwrap();
+        assert!(!spawner.is_complete());
+        assert!(receiver.try_recv().is_err());
+
+        // Without an earlier key exchange there is nothing to fall back to
+        let mut spawner = NtsSpawner::new(
+            config(NtsFailurePolicy::Unauthenticated),
+            SourceConfig::default(),
+            KeyExchangeManager::new(),
+        )
+        .unwrap();
+        spawner.try_spawn(&sender).await.unwrap();
+        assert!(!spawner.is_complete());
+        assert!(receiver.try_recv().is_err());
+
+        spawner.last_ntp_server = Some((ntp_server, ntp_proto::ProtocolVersion::V4));
+        spawner.try_spawn(&sender).await.unwrap();
+        assert!(spawner.is_complete());
+        let params = get_ntp_create_params(receiver.recv().await.unwrap()).unwrap();
+        assert_eq!(params.addr, ntp_server);
+        assert!(params.nts.is_none());
+        assert!(params.nts_fallback);
+    }
 }
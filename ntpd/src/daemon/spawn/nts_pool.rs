@@ -1,21 +1,26 @@
 use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::fmt::Display;
+use std::net::SocketAddr;
 use std::ops::Deref;
+use std::sync::Arc;
 
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tracing::warn;
 
-use ntp_proto::{KeyExchangeClient, NtsClientConfig, NtsError, SourceConfig};
+use ntp_proto::{KeyExchangeClient, KeyExchangeResult, NtsCertificatePins, NtsError, SourceConfig};
 
-use crate::daemon::config::{NormalizedAddress, NtpAddress};
 use crate::daemon::dns::{KeResolutionResult, resolve_ke};
-use crate::daemon::spawn::resolve_single_ntp_server;
+use crate::daemon::spawn::resolve_nts_ntp_server;
 
 use super::super::config::NtsPoolSourceConfig;
 
-use super::{ClockId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId};
+use super::{
+    ClockId, SourceRemovalReason, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+    key_exchange::{KeyExchangeClientSettings, KeyExchangeManager},
+    spawn_source_config,
+};
 
 struct PoolSource {
     id: ClockId,
@@ -24,10 +29,16 @@ struct PoolSource {
 
 pub struct NtsPoolSpawner {
     config: NtsPoolSourceConfig,
-    key_exchange_client: KeyExchangeClient,
+    key_exchange_client: Arc<KeyExchangeClient>,
+    key_exchange_manager: KeyExchangeManager,
     source_config: SourceConfig,
     id: SpawnerId,
     current_sources: Vec<PoolSource>,
+    // Number of sources lost to network issues that have not been replaced yet
+    pending_reconnects: usize,
+    // Number of sources the system trimmed, which are not replaced until
+    // there is room for them again
+    trimmed: usize,
     known_resolutions: VecDeque<KeResolutionResult>,
 }
 
@@ -56,22 +67,39 @@ impl NtsPoolSpawner {
     pub fn new(
         config: NtsPoolSourceConfig,
         source_config: SourceConfig,
+        key_exchange_manager: KeyExchangeManager,
     ) -> Result<NtsPoolSpawner, NtsError> {
-        let key_exchange_client = KeyExchangeClient::new(&NtsClientConfig {
-            certificates: config.certificate_authorities.clone(),
-            protocol_version: config.ntp_version,
+        let key_exchange_client = key_exchange_manager.client(&KeyExchangeClientSettings {
+            certificate_authorities: config.certificate_authorities.clone(),
+            client_certificate_chain_path: config.client_certificate_chain_path.clone(),
+            client_private_key_path: config.client_private_key_path.clone(),
+            ntp_version: config.ntp_version,
+            aead_algorithms: config.aead_algorithms.clone(),
+            pins: NtsCertificatePins {
+                certificate_authorities: config.pinned_certificate_authorities.clone(),
+                spki_sha256: config.pin_spki_sha256.clone(),
+            },
+            session_resumption: config.session_resumption,
         })?;
 
         Ok(NtsPoolSpawner {
             config,
             key_exchange_client,
+            key_exchange_manager,
             source_config,
             id: SpawnerId::new(),
             current_sources: vec![],
+            pending_reconnects: 0,
+            trimmed: 0,
             known_resolutions: VecDeque::new(),
         })
     }
 
+    /// Number of sources to keep, leaving out those that were trimmed
+    fn wanted(&self) -> usize {
+        self.config.count.saturating_sub(self.trimmed)
+    }
+
     fn contains_source(&self, domain: &str) -> bool {
         self.current_sources
             .iter()
@@ -149,6 +177,36 @@ impl NtsPoolSpawner {
             Some((io, self.config.addr.server_name.clone(), None))
         }
     }
+
+    async fn key_exchange(
+        &mut self,
+    ) -> Option<(KeyExchangeResult, Option<SocketAddr>, Option<String>)> {
+        let (io, name, remote_name) = self.lookup().await?;
+        let ke_peer = io.peer_addr().ok();
+
+        match tokio::time::timeout(
+            super::NTS_TIMEOUT,
+            self.key_exchange_client.exchange_keys(
+                io,
+                name,
+                self.current_sources
+                    .iter()
+                    .map(|source| Cow::Borrowed(source.remote.as_str())),
+            ),
+        )
+        .await
+        {
+            Ok(Ok(ke)) => Some((ke, ke_peer, remote_name)),
+            Ok(Err(e)) => {
+                warn!(error = ?e, "error while attempting key exchange");
+                None
+            }
+            Err(_) => {
+                warn!("timeout while attempting key exchange");
+                None
+            }
+        }
+    }
 }
 
 impl Spawner for NtsPoolSpawner {
@@ -158,61 +216,45 @@ impl Spawner for NtsPoolSpawner {
         &mut self,
         action_tx: &mpsc::Sender<SpawnEvent>,
     ) -> Result<(), NtsPoolSpawnError> {
-        for _ in 0..self.config.count.saturating_sub(self.current_sources.len()) {
-            let Some((io, name, remote_name)) = self.lookup().await else {
+        let manager = self.key_exchange_manager.clone();
+        let server_name = self.config.addr.server_name.clone();
+        for _ in 0..self.wanted().saturating_sub(self.current_sources.len()) {
+            let Some((ke, ke_peer, remote_name)) = manager
+                .exchange(&server_name, self.config.addr.port, self.key_exchange())
+                .await
+            else {
                 return Ok(());
             };
 
-            match tokio::time::timeout(
-                super::NTS_TIMEOUT,
-                self.key_exchange_client.exchange_keys(
-                    io,
-                    name,
-                    self.current_sources
-                        .iter()
-                        .map(|source| Cow::Borrowed(source.remote.as_str())),
-                ),
-            )
-            .await
+            if self.contains_source(remote_name.as_deref().unwrap_or(&ke.remote)) {
+                warn!("received an address from pool-ke that we already had, ignoring");
+                continue;
+            }
+
+            if let Some(address) =
+                resolve_nts_ntp_server(&ke.remote, ke.port, ke.remote_negotiated, ke_peer).await
             {
-                Ok(Ok(ke))
-                    if !self.contains_source(remote_name.as_deref().unwrap_or(&ke.remote)) =>
-                {
-                    if let Some(address) = resolve_single_ntp_server(NtpAddress(
-                        NormalizedAddress::new_from_parts(ke.remote.as_str(), ke.port),
-                    ))
-                    .await
-                    {
-                        let id = ClockId::new();
-                        self.current_sources.push(PoolSource {
+                let id = ClockId::new();
+                self.current_sources.push(PoolSource {
+                    id,
+                    remote: remote_name.unwrap_or(ke.remote),
+                });
+                let reconnect = self.pending_reconnects > 0;
+                self.pending_reconnects = self.pending_reconnects.saturating_sub(1);
+                action_tx
+                    .send(SpawnEvent::new(
+                        self.id,
+                        SpawnAction::create_ntp(
                             id,
-                            remote: remote_name.unwrap_or(ke.remote),
-                        });
-                        action_tx
-                            .send(SpawnEvent::new(
-                                self.id,
-                                SpawnAction::create_ntp(
-                                    id,
-                                    address,
-                                    self.config.addr.deref().clone(),
-                                    ke.protocol_version,
-                                    self.source_config,
-                                    Some(ke.nts),
-                                ),
-                            ))
-                            .await?;
-                    }
-                }
-                Ok(Ok(_)) => {
-                    warn!("received an address from pool-ke that we already had, ignoring");
-                }
-                Ok(Err(e)) => {
-                    warn!(error = ?e, "error while attempting key exchange");
-                    break;
-                }
-                Err(_) => {
-                    warn!("timeout while attempting key exchange");
-                }
+                            address,
+                            self.config.addr.deref().clone(),
+                            ke.protocol_version,
+                            spawn_source_config(self.source_config, reconnect),
+                            Some(ke.nts),
+                            None,
+                        ),
+                    ))
+                    .await?;
             }
         }
 
@@ -220,7 +262,7 @@ impl Spawner for NtsPoolSpawner {
     }
 
     fn is_complete(&self) -> bool {
-        self.current_sources.len() >= self.config.count
+        self.current_sources.len() >= self.wanted()
     }
 
     async fn handle_source_removed(
@@ -228,6 +270,16 @@ impl Spawner for NtsPoolSpawner {
         removed_source: SourceRemovedEvent,
     ) -> Result<(), NtsPoolSpawnError> {
         self.current_sources.retain(|p| p.id != removed_source.id);
+        match removed_source.reason {
+            SourceRemovalReason::NetworkIssue => self.pending_reconnects += 1,
+            SourceRemovalReason::Trimmed => self.trimmed += 1,
+            SourceRemovalReason::Demobilized | SourceRemovalReason::Unreachable => {}
+        }
+        Ok(())
+    }
+
+    async fn handle_capacity_available(&mut self) -> Result<(), NtsPoolSpawnError> {
+        self.trimmed = self.trimmed.saturating_sub(1);
         Ok(())
     }
 
@@ -0,0 +1,141 @@
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::config::PhcSourceConfig;
+
+use super::{
+    ClockId, PhcSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct PhcSpawner {
+    config: PhcSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    has_spawned: bool,
+}
+
+impl PhcSpawner {
+    pub fn new(config: PhcSourceConfig, source_config: SourceConfig) -> PhcSpawner {
+        PhcSpawner {
+            config,
+            source_config,
+            id: SpawnerId::new(),
+            has_spawned: false,
+        }
+    }
+}
+
+impl Spawner for PhcSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Phc(PhcSourceCreateParameters {
+                    id: ClockId::new(),
+                    path: self.config.path.clone(),
+                    poll_interval: self.config.poll_interval,
+                    // The deprecated offset has the opposite sign of the
+                    // correction
+                    config: SourceConfig {
+                        offset_correction: self.source_config.offset_correction
+                            - self.config.offset,
+                        ..self.source_config
+                    },
+                    precision: self.config.precision.powi(2),
+                    accuracy: self.config.accuracy,
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.path.display().to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "phc"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ntp_proto::{PollInterval, SourceConfig};
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::PhcSourceConfig,
+        spawn::{SourceCreateParameters, SpawnAction, Spawner, phc::PhcSpawner},
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let path = PathBuf::from("/dev/ptp0");
+        let precision = 1e-7;
+        let mut spawner = PhcSpawner::new(
+            PhcSourceConfig {
+                path: path.clone(),
+                poll_interval: PollInterval::from_byte(1),
+                offset: 37.0,
+                precision,
+                accuracy: 0.0,
+            },
+            SourceConfig {
+                offset_correction: 1e-3,
+                ..SourceConfig::default()
+            },
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), "/dev/ptp0");
+
+        let SourceCreateParameters::Phc(params) = create_params else {
+            panic!("did not receive PHC source create parameters!");
+        };
+        assert_eq!(params.path, path);
+        assert_eq!(params.poll_interval, PollInterval::from_byte(1));
+        // A clock ahead of UTC needs a negative correction
+        assert!((params.config.offset_correction - (1e-3 - 37.0)).abs() < 1e-12);
+        assert!((params.precision - precision.powi(2)).abs() < 1e-20);
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+    }
+}
@@ -1,13 +1,25 @@
 use std::fmt::Display;
-use std::{net::SocketAddr, ops::Deref};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use std::{collections::HashSet, net::SocketAddr, ops::Deref};
 
 use ntp_proto::SourceConfig;
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, time::Instant};
 use tracing::warn;
 
 use super::super::config::PoolSourceConfig;
 
-use super::{ClockId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId};
+use super::{
+    ClockId, SourceRemovalReason, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+    spawn_source_config,
+};
+
+// Pools rotate their members, so addresses resolved longer ago than this are
+// resolved again before picking new members
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Members that were demobilized or became unreachable are not picked again
+// for this long
+const BAD_MEMBER_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
 
 struct PoolSource {
     id: ClockId,
@@ -19,7 +31,29 @@ pub struct PoolSpawner {
     source_config: SourceConfig,
     id: SpawnerId,
     current_sources: Vec<PoolSource>,
+    // Number of sources lost to network issues that have not been replaced yet
+    pending_reconnects: usize,
+    // Number of sources the system trimmed, which are not replaced until
+    // there is room for them again
+    trimmed: usize,
     known_ips: Vec<SocketAddr>,
+    resolved_at: Option<Instant>,
+    bad_members: Vec<(SocketAddr, Instant)>,
+}
+
+/// The /24 (IPv4) or /48 (IPv6) network of an address. Pool members in the
+/// same network are likely run by the same operator, and fail together.
+fn network(addr: SocketAddr) -> IpAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -40,9 +74,32 @@ impl PoolSpawner {
             source_config,
             id: SpawnerId::new(),
             current_sources: vec![],
+            pending_reconnects: 0,
+            trimmed: 0,
             known_ips: vec![],
+            resolved_at: None,
+            bad_members: vec![],
         }
     }
+
+    /// Number of members to keep, leaving out those that were trimmed
+    fn wanted(&self) -> usize {
+        self.config.count.saturating_sub(self.trimmed)
+    }
+
+    /// Index in `known_ips` of the next member to use, preferring members
+    /// outside the networks of the current ones
+    fn next_member(&self) -> Option<usize> {
+        let used: HashSet<_> = self
+            .current_sources
+            .iter()
+            .map(|source| network(source.addr))
+            .collect();
+        self.known_ips
+            .iter()
+            .rposition(|ip| !used.contains(&network(*ip)))
+            .or(self.known_ips.len().checked_sub(1))
+    }
 }
 
 impl Spawner for PoolSpawner {
@@ -53,39 +110,58 @@ impl Spawner for PoolSpawner {
         action_tx: &mpsc::Sender<SpawnEvent>,
     ) -> Result<(), PoolSpawnError> {
         // early return if there is nothing to do
-        if self.current_sources.len() >= self.config.count {
+        if self.current_sources.len() >= self.wanted() {
             return Ok(());
         }
 
-        if self.known_ips.len() < self.config.count - self.current_sources.len() {
+        self.bad_members
+            .retain(|(_, since)| since.elapsed() < BAD_MEMBER_TIMEOUT);
+
+        let stale = self
+            .resolved_at
+            .is_none_or(|resolved_at| resolved_at.elapsed() >= RESOLVE_INTERVAL);
+        if stale || self.known_ips.len() < self.wanted() - self.current_sources.len() {
             match self.config.addr.lookup_host().await {
                 Ok(addresses) => {
-                    // add the addresses looked up to our list of known ips
-                    self.known_ips.append(&mut addresses.collect());
-                    // remove known ips that we are already connected to or that we want to ignore
-                    self.known_ips.retain(|ip| {
-                        !self.current_sources.iter().any(|p| p.addr == *ip)
-                            && !self.config.ignore.iter().any(|ign| *ign == ip.ip())
-                    });
+                    // The pool's current members replace those we knew, as
+                    // members may have left the pool since
+                    self.known_ips = addresses.collect();
+                    self.resolved_at = Some(Instant::now());
                 }
                 Err(e) => {
                     warn!(error = ?e, "error while resolving source address, retrying");
-                    return Ok(());
+                    if self.known_ips.is_empty() {
+                        return Ok(());
+                    }
                 }
             }
         }
 
+        // remove known ips that we are already connected to, that we want to
+        // ignore, that misbehaved recently, or that we have seen already
+        let mut seen = HashSet::new();
+        self.known_ips.retain(|ip| {
+            !self.current_sources.iter().any(|p| p.addr == *ip)
+                && !self.config.ignore.iter().any(|ign| *ign == ip.ip())
+                && !self.bad_members.iter().any(|(bad, _)| bad == ip)
+                && seen.insert(*ip)
+        });
+
         // Try and add sources to our pool
-        while self.current_sources.len() < self.config.count {
-            if let Some(addr) = self.known_ips.pop() {
+        while self.current_sources.len() < self.wanted() {
+            if let Some(index) = self.next_member() {
+                let addr = self.known_ips.remove(index);
                 let id = ClockId::new();
                 self.current_sources.push(PoolSource { id, addr });
+                let reconnect = self.pending_reconnects > 0;
+                self.pending_reconnects = self.pending_reconnects.saturating_sub(1);
                 let action = SpawnAction::create_ntp(
                     id,
                     addr,
                     self.config.addr.deref().clone(),
                     self.config.ntp_version,
-                    self.source_config,
+                    spawn_source_config(self.source_config, reconnect),
+                    None,
                     None,
                 );
                 tracing::debug!(?action, "intending to spawn new pool source at");
@@ -103,17 +179,41 @@ impl Spawner for PoolSpawner {
     }
 
     fn is_complete(&self) -> bool {
-        self.current_sources.len() >= self.config.count
+        self.current_sources.len() >= self.wanted()
     }
 
     async fn handle_source_removed(
         &mut self,
         removed_source: SourceRemovedEvent,
     ) -> Result<(), PoolSpawnError> {
+        if let Some(source) = self
+            .current_sources
+            .iter()
+            .find(|p| p.id == removed_source.id)
+        {
+            match removed_source.reason {
+                SourceRemovalReason::NetworkIssue => self.pending_reconnects += 1,
+                SourceRemovalReason::Demobilized | SourceRemovalReason::Unreachable => {
+                    self.bad_members.push((source.addr, Instant::now()));
+                }
+                SourceRemovalReason::Trimmed => self.trimmed += 1,
+            }
+        }
         self.current_sources.retain(|p| p.id != removed_source.id);
         Ok(())
     }
 
+    async fn handle_capacity_available(&mut self) -> Result<(), PoolSpawnError> {
+        self.trimmed = self.trimmed.saturating_sub(1);
+        Ok(())
+    }
+
+    async fn handle_network_change(&mut self) -> Result<(), PoolSpawnError> {
+        // Resolve again, the pool may give us members closer to our new network
+        self.resolved_at = None;
+        Ok(())
+    }
+
     fn get_id(&self) -> SpawnerId {
         self.id
     }
@@ -363,6 +463,126 @@ mod tests {
         assert!(pool.is_complete());
     }
 
+    #[tokio::test]
+    async fn prefers_distinct_networks() {
+        let address_strings = ["192.0.2.1:123", "198.51.100.1:123", "192.0.2.2:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                ntp_version: ProtocolVersion::V4,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let addr1 = get_ntp_create_params(action_rx.try_recv().unwrap())
+            .unwrap()
+            .addr;
+        let addr2 = get_ntp_create_params(action_rx.try_recv().unwrap())
+            .unwrap()
+            .addr;
+
+        // One of the servers in 192.0.2.0/24 and the one in 198.51.100.0/24
+        let ips: Vec<_> = [addr1, addr2]
+            .iter()
+            .map(std::net::SocketAddr::ip)
+            .collect();
+        assert!(ips.contains(&"198.51.100.1".parse().unwrap()));
+        assert!(pool.is_complete());
+    }
+
+    #[tokio::test]
+    async fn replaces_bad_members() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 1,
+                ignore: vec![],
+                ntp_version: ProtocolVersion::V4,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        let addr1 = params.addr;
+        pool.handle_source_removed(SourceRemovedEvent {
+            id: params.id,
+            reason: SourceRemovalReason::Unreachable,
+        })
+        .await
+        .unwrap();
+
+        // An unreachable member is replaced by another one
+        assert!(!pool.is_complete());
+        pool.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_ne!(params.addr, addr1);
+        pool.handle_source_removed(SourceRemovedEvent {
+            id: params.id,
+            reason: SourceRemovalReason::Demobilized,
+        })
+        .await
+        .unwrap();
+
+        // And neither is used again, even when resolving the pool again
+        pool.handle_network_change().await.unwrap();
+        pool.try_spawn(&action_tx).await.unwrap();
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(!pool.is_complete());
+    }
+
+    #[tokio::test]
+    async fn waits_for_room_after_trimming() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                ntp_version: ProtocolVersion::V4,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        pool.handle_source_removed(SourceRemovedEvent {
+            id: params.id,
+            reason: SourceRemovalReason::Trimmed,
+        })
+        .await
+        .unwrap();
+
+        // A trimmed member is not replaced
+        assert!(pool.is_complete());
+        pool.try_spawn(&action_tx).await.unwrap();
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        // Until the system has room for it again
+        pool.handle_capacity_available().await.unwrap();
+        assert!(!pool.is_complete());
+        pool.try_spawn(&action_tx).await.unwrap();
+        get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert!(pool.is_complete());
+    }
+
     #[tokio::test]
     async fn works_if_address_does_not_resolve() {
         let mut pool = PoolSpawner::new(
@@ -43,6 +43,8 @@ impl Spawner for PpsSpawner {
                     precision: self.config.precision.powi(2),
                     accuracy: self.config.accuracy,
                     period: self.config.period,
+                    edge: self.config.edge,
+                    lock: self.config.lock.clone(),
                 })),
             ))
             .await?;
@@ -84,7 +86,7 @@ mod tests {
 
     use crate::{
         daemon::{
-            config::PpsSourceConfig,
+            config::{PpsEdge, PpsSourceConfig},
             spawn::{SourceCreateParameters, SpawnAction, Spawner, pps::PpsSpawner},
             system::MESSAGE_BUFFER_SIZE,
         },
@@ -102,6 +104,8 @@ mod tests {
                 precision,
                 accuracy,
                 period: 1.,
+                edge: PpsEdge::Clear,
+                lock: Some("gps.example.com".into()),
             },
             SourceConfig::default(),
         );
@@ -113,7 +117,9 @@ mod tests {
         let res = action_rx.try_recv().unwrap();
         assert_eq!(res.id, spawner_id);
 
-        let SpawnAction::Create(create_params) = res.action;
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
         assert_eq!(create_params.get_addr(), socket_path.display().to_string());
 
         let SourceCreateParameters::Pps(params) = create_params else {
@@ -121,6 +127,8 @@ mod tests {
         };
         assert_eq!(params.path, socket_path);
         assert!((params.precision - precision.powi(2)).abs() < 1e-9);
+        assert_eq!(params.edge, PpsEdge::Clear);
+        assert_eq!(params.lock.as_deref(), Some("gps.example.com"));
 
         // Should be complete after spawning
         assert!(spawner.is_complete());
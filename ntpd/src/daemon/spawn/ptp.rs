@@ -0,0 +1,129 @@
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::config::PtpSourceConfig;
+
+use super::{
+    ClockId, PtpSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct PtpSpawner {
+    config: PtpSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    has_spawned: bool,
+}
+
+impl PtpSpawner {
+    pub fn new(config: PtpSourceConfig, source_config: SourceConfig) -> PtpSpawner {
+        PtpSpawner {
+            config,
+            source_config,
+            id: SpawnerId::new(),
+            has_spawned: false,
+        }
+    }
+}
+
+impl Spawner for PtpSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Ptp(PtpSourceCreateParameters {
+                    id: ClockId::new(),
+                    group: self.config.group,
+                    domain: self.config.domain,
+                    config: self.source_config,
+                    precision: self.config.precision.powi(2),
+                    accuracy: self.config.accuracy,
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.group.to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "ptp"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use ntp_proto::SourceConfig;
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::PtpSourceConfig,
+        spawn::{SourceCreateParameters, SpawnAction, Spawner, ptp::PtpSpawner},
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let group = IpAddr::from([224, 0, 1, 129]);
+        let precision = 1e-5;
+        let mut spawner = PtpSpawner::new(
+            PtpSourceConfig {
+                group,
+                domain: 24,
+                precision,
+                accuracy: 0.0,
+            },
+            SourceConfig::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), "224.0.1.129");
+
+        let SourceCreateParameters::Ptp(params) = create_params else {
+            panic!("did not receive PTP source create parameters!");
+        };
+        assert_eq!(params.group, group);
+        assert_eq!(params.domain, 24);
+        assert!((params.precision - precision.powi(2)).abs() < 1e-20);
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+    }
+}
@@ -0,0 +1,172 @@
+use std::net::SocketAddr;
+
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::{
+    config::{NtpAddress, RoughtimeSourceConfig},
+    spawn::resolve_single_ntp_server,
+};
+
+use super::{
+    ClockId, RoughtimeSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct RoughtimeSpawner {
+    config: RoughtimeSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    resolved: Option<SocketAddr>,
+    has_spawned: bool,
+}
+
+impl RoughtimeSpawner {
+    pub fn new(config: RoughtimeSourceConfig, source_config: SourceConfig) -> RoughtimeSpawner {
+        RoughtimeSpawner {
+            config,
+            source_config,
+            id: SpawnerId::new(),
+            resolved: None,
+            has_spawned: false,
+        }
+    }
+
+    async fn do_resolve(&mut self) -> Option<SocketAddr> {
+        if self.resolved.is_none() {
+            self.resolved =
+                resolve_single_ntp_server(NtpAddress(self.config.address.0.clone())).await;
+        }
+        self.resolved
+    }
+}
+
+impl Spawner for RoughtimeSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        let Some(addr) = self.do_resolve().await else {
+            return Ok(());
+        };
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Roughtime(
+                    RoughtimeSourceCreateParameters {
+                        id: ClockId::new(),
+                        addr,
+                        public_key: self.config.public_key,
+                        poll_interval: self.config.poll_interval,
+                        config: self.source_config,
+                        precision: self.config.precision.powi(2),
+                        accuracy: self.config.accuracy,
+                    },
+                )),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            // The server may have moved, resolve it again
+            self.resolved = None;
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    async fn handle_network_change(&mut self) -> Result<(), StandardSpawnError> {
+        self.resolved = None;
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.address.to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "roughtime"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{PollInterval, SourceConfig};
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::{NormalizedAddress, RoughtimeAddress, RoughtimeSourceConfig},
+        spawn::{
+            SourceCreateParameters, SourceRemovalReason, SourceRemovedEvent, SpawnAction, Spawner,
+            roughtime::RoughtimeSpawner,
+        },
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let mut spawner = RoughtimeSpawner::new(
+            RoughtimeSourceConfig {
+                address: RoughtimeAddress(NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    2002,
+                    vec!["127.0.0.1:2002".parse().unwrap()],
+                )),
+                public_key: [1; 32],
+                poll_interval: PollInterval::from_byte(10),
+                precision: 1.0,
+                accuracy: 0.0,
+            },
+            SourceConfig::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), "127.0.0.1:2002");
+
+        let SourceCreateParameters::Roughtime(params) = create_params else {
+            panic!("did not receive roughtime source create parameters!");
+        };
+        assert_eq!(params.public_key, [1; 32]);
+        assert_eq!(params.poll_interval, PollInterval::from_byte(10));
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+
+        // And spawn again after the source is lost
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::NetworkIssue,
+            })
+            .await
+            .unwrap();
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        assert!(action_rx.try_recv().is_ok());
+    }
+}
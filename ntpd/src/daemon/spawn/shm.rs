@@ -0,0 +1,126 @@
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::config::ShmSourceConfig;
+
+use super::{
+    ClockId, ShmSourceCreateParameters, SourceCreateParameters, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct ShmSpawner {
+    config: ShmSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    has_spawned: bool,
+}
+
+impl ShmSpawner {
+    pub fn new(config: ShmSourceConfig, source_config: SourceConfig) -> ShmSpawner {
+        ShmSpawner {
+            config,
+            source_config,
+            id: SpawnerId::new(),
+            has_spawned: false,
+        }
+    }
+}
+
+impl Spawner for ShmSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::Shm(ShmSourceCreateParameters {
+                    id: ClockId::new(),
+                    unit: self.config.unit,
+                    poll_interval: self.config.poll_interval,
+                    config: self.source_config,
+                    precision: self.config.precision.powi(2),
+                    accuracy: self.config.accuracy,
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        format!("SHM({})", self.config.unit)
+    }
+
+    fn get_description(&self) -> &str {
+        "shm"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{PollInterval, SourceConfig};
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::ShmSourceConfig,
+        spawn::{SourceCreateParameters, SpawnAction, Spawner, shm::ShmSpawner},
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let precision = 1e-6;
+        let mut spawner = ShmSpawner::new(
+            ShmSourceConfig {
+                unit: 1,
+                poll_interval: PollInterval::from_byte(1),
+                precision,
+                accuracy: 0.0,
+            },
+            SourceConfig::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), "SHM(1)");
+
+        let SourceCreateParameters::Shm(params) = create_params else {
+            panic!("did not receive SHM source create parameters!");
+        };
+        assert_eq!(params.unit, 1);
+        assert_eq!(params.poll_interval, PollInterval::from_byte(1));
+        assert!((params.precision - precision.powi(2)).abs() < 1e-20);
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+    }
+}
@@ -39,6 +39,7 @@ impl Spawner for SockSpawner {
                 SpawnAction::Create(SourceCreateParameters::Sock(SockSourceCreateParameters {
                     id: ClockId::new(),
                     path: self.config.path.clone(),
+                    socket: self.config.socket.clone(),
                     config: self.source_config,
                     precision: self.config.precision.powi(2),
                     accuracy: self.config.accuracy,
@@ -83,7 +84,7 @@ mod tests {
 
     use crate::{
         daemon::{
-            config::SockSourceConfig,
+            config::{SockSocketOptions, SockSourceConfig},
             spawn::{SourceCreateParameters, SpawnAction, Spawner, sock::SockSpawner},
             system::MESSAGE_BUFFER_SIZE,
         },
@@ -100,6 +101,10 @@ mod tests {
                 path: socket_path.clone(),
                 precision,
                 accuracy,
+                socket: SockSocketOptions {
+                    permissions: Some(0o660),
+                    ..Default::default()
+                },
             },
             SourceConfig::default(),
         );
@@ -111,13 +116,16 @@ mod tests {
         let res = action_rx.try_recv().unwrap();
         assert_eq!(res.id, spawner_id);
 
-        let SpawnAction::Create(create_params) = res.action;
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("Expected a source to be created");
+        };
         assert_eq!(create_params.get_addr(), socket_path.display().to_string());
 
         let SourceCreateParameters::Sock(params) = create_params else {
             panic!("did not receive sock source create parameters!");
         };
         assert_eq!(params.path, socket_path);
+        assert_eq!(params.socket.permissions, Some(0o660));
         assert!((params.precision - precision.powi(2)).abs() < 1e-9);
 
         // Should be complete after spawning
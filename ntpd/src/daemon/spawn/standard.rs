@@ -1,8 +1,10 @@
 use std::fmt::Display;
+use std::time::Duration;
 use std::{net::SocketAddr, ops::Deref};
 
-use ntp_proto::SourceConfig;
-use tokio::sync::mpsc;
+use ntp_proto::{SourceConfig, SymmetricKey};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{info, warn};
 
 use crate::daemon::spawn::resolve_single_ntp_server;
 
@@ -10,14 +12,20 @@ use super::super::config::StandardSource;
 
 use super::{
     ClockId, SourceRemovalReason, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+    spawn_source_config,
 };
 
 pub struct StandardSpawner {
     id: SpawnerId,
     config: StandardSource,
     source_config: SourceConfig,
+    symmetric_key: Option<SymmetricKey>,
     resolved: Option<SocketAddr>,
+    resolved_at: Option<Instant>,
+    /// The source we spawned, while it is running
+    source: Option<ClockId>,
     has_spawned: bool,
+    reconnect: bool,
 }
 
 #[derive(Debug)]
@@ -42,13 +50,21 @@ impl From<mpsc::error::SendError<SpawnEvent>> for StandardSpawnError {
 impl std::error::Error for StandardSpawnError {}
 
 impl StandardSpawner {
-    pub fn new(config: StandardSource, source_config: SourceConfig) -> StandardSpawner {
+    pub fn new(
+        config: StandardSource,
+        source_config: SourceConfig,
+        symmetric_key: Option<SymmetricKey>,
+    ) -> StandardSpawner {
         StandardSpawner {
             id: SpawnerId::new(),
             config,
             source_config,
+            symmetric_key,
             resolved: None,
+            resolved_at: None,
+            source: None,
             has_spawned: false,
+            reconnect: false,
         }
     }
 
@@ -58,9 +74,65 @@ impl StandardSpawner {
         } else {
             let address = resolve_single_ntp_server(self.config.address.clone()).await?;
             self.resolved = Some(address);
+            self.resolved_at = Some(Instant::now());
             self.resolved
         }
     }
+
+    /// Whether the running source should check that its name still resolves
+    /// to the address it is polling
+    fn needs_refresh(&self) -> bool {
+        self.config.resolve_interval != 0
+            && self.source.is_some()
+            && self.resolved_at.is_some_and(|resolved_at| {
+                resolved_at.elapsed() >= Duration::from_secs(self.config.resolve_interval)
+            })
+    }
+
+    /// Move the running source to a new address when the address it polls
+    /// is no longer among those its name resolves to. The system resolver
+    /// doesn't tell us how long addresses stay valid, hence the fixed
+    /// interval.
+    async fn refresh(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        let (Some(id), Some(current)) = (self.source, self.resolved) else {
+            return Ok(());
+        };
+        self.resolved_at = Some(Instant::now());
+
+        match self.config.address.lookup_host().await {
+            Ok(mut addresses) => {
+                if addresses.any(|addr| addr == current) {
+                    return Ok(());
+                }
+            }
+            Err(error) => {
+                warn!(?error, "error while resolving {}", *self.config.address);
+                return Ok(());
+            }
+        }
+
+        // Keep polling the old address when there is nothing better
+        let Some(addr) = resolve_single_ntp_server(self.config.address.clone()).await else {
+            return Ok(());
+        };
+        info!(
+            old = %current,
+            new = %addr,
+            "{} no longer resolves to the address of the source",
+            *self.config.address
+        );
+        self.resolved = Some(addr);
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::ChangeAddress { id, addr },
+            ))
+            .await?;
+        Ok(())
+    }
 }
 
 impl Spawner for StandardSpawner {
@@ -70,41 +142,66 @@ impl Spawner for StandardSpawner {
         &mut self,
         action_tx: &mpsc::Sender<SpawnEvent>,
     ) -> Result<(), StandardSpawnError> {
+        if self.has_spawned {
+            return self.refresh(action_tx).await;
+        }
+
         let Some(addr) = self.do_resolve(false).await else {
             return Ok(());
         };
+        let id = ClockId::new();
         action_tx
             .send(SpawnEvent::new(
                 self.id,
                 SpawnAction::create_ntp(
-                    ClockId::new(),
+                    id,
                     addr,
                     self.config.address.deref().clone(),
                     self.config.ntp_version,
-                    self.source_config,
+                    spawn_source_config(self.source_config, self.reconnect),
                     None,
+                    self.symmetric_key.clone(),
                 ),
             ))
             .await?;
+        self.source = Some(id);
         self.has_spawned = true;
         Ok(())
     }
 
     fn is_complete(&self) -> bool {
-        self.has_spawned
+        self.has_spawned && !self.needs_refresh()
     }
 
     async fn handle_source_removed(
         &mut self,
         removed_source: SourceRemovedEvent,
     ) -> Result<(), StandardSpawnError> {
+        if self.source == Some(removed_source.id) {
+            self.source = None;
+        }
         if removed_source.reason == SourceRemovalReason::Unreachable {
             // force new resolution
             self.resolved = None;
         }
-        if removed_source.reason != SourceRemovalReason::Demobilized {
+        if !matches!(
+            removed_source.reason,
+            SourceRemovalReason::Demobilized | SourceRemovalReason::Trimmed
+        ) {
             self.has_spawned = false;
         }
+        self.reconnect = removed_source.reason == SourceRemovalReason::NetworkIssue;
+        Ok(())
+    }
+
+    async fn handle_capacity_available(&mut self) -> Result<(), StandardSpawnError> {
+        self.has_spawned = false;
+        Ok(())
+    }
+
+    async fn handle_network_change(&mut self) -> Result<(), StandardSpawnError> {
+        // The next source is created with a fresh resolution
+        self.resolved = None;
         Ok(())
     }
 
@@ -125,8 +222,10 @@ impl Spawner for StandardSpawner {
 mod tests {
     use ntp_proto::ProtocolVersion;
 
-    use ntp_proto::SourceConfig;
+    use ntp_proto::{InitialBurst, SourceConfig};
+    use std::time::Duration;
     use tokio::sync::mpsc::{self, error::TryRecvError};
+    use tokio::time::Instant;
 
     use crate::daemon::{
         config::{NormalizedAddress, StandardSource},
@@ -148,8 +247,11 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key_id: None,
+                resolve_interval: 3600,
             },
             SourceConfig::default(),
+            None,
         );
         let spawner_id = spawner.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
@@ -158,7 +260,9 @@ mod tests {
         spawner.try_spawn(&action_tx).await.unwrap();
         let res = action_rx.try_recv().unwrap();
         assert_eq!(res.id, spawner_id);
-        let SpawnAction::Create(create_params) = &res.action;
+        let SpawnAction::Create(create_params) = &res.action else {
+            panic!("Expected a source to be created");
+        };
         assert_eq!(create_params.get_addr(), "127.0.0.1:123");
         let params = get_ntp_create_params(res).unwrap();
         assert_eq!(params.addr.to_string(), "127.0.0.1:123");
@@ -182,8 +286,11 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::V5,
+                key_id: None,
+                resolve_interval: 3600,
             },
             SourceConfig::default(),
+            None,
         );
         let spawner_id = spawner.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
@@ -211,8 +318,11 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::V4,
+                key_id: None,
+                resolve_interval: 3600,
             },
             SourceConfig::default(),
+            None,
         );
         let spawner_id = spawner.get_id();
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
@@ -240,8 +350,11 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key_id: None,
+                resolve_interval: 3600,
             },
             SourceConfig::default(),
+            None,
         );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -249,6 +362,7 @@ mod tests {
         spawner.try_spawn(&action_tx).await.unwrap();
         let res = action_rx.try_recv().unwrap();
         let params = get_ntp_create_params(res).unwrap();
+        assert_eq!(params.config.initial_burst, InitialBurst::default());
         assert!(spawner.is_complete());
 
         spawner
@@ -264,7 +378,23 @@ mod tests {
         let res = action_rx.try_recv().unwrap();
         let params = get_ntp_create_params(res).unwrap();
         assert_eq!(params.addr.to_string(), "127.0.0.1:123");
+        // No new burst when reconnecting after a network issue
+        assert_eq!(params.config.initial_burst, InitialBurst::DISABLED);
         assert!(spawner.is_complete());
+
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::Unreachable,
+            })
+            .await
+            .unwrap();
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        let params = get_ntp_create_params(res).unwrap();
+        // But a reset source starts over with a burst
+        assert_eq!(params.config.initial_burst, InitialBurst::default());
     }
 
     #[tokio::test]
@@ -281,8 +411,11 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key_id: None,
+                resolve_interval: 3600,
             },
             SourceConfig::default(),
+            None,
         );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -327,6 +460,48 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn forgets_resolution_on_network_change() {
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    vec!["127.0.0.1:123".parse().unwrap()],
+                )
+                .into(),
+                ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key_id: None,
+                resolve_interval: 3600,
+            },
+            SourceConfig::default(),
+            None,
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert!(spawner.resolved.is_some());
+
+        // The running source is left alone
+        spawner.handle_network_change().await.unwrap();
+        assert!(spawner.resolved.is_none());
+        assert!(spawner.is_complete());
+
+        // But its replacement resolves the address again
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::NetworkIssue,
+            })
+            .await
+            .unwrap();
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(params.addr.to_string(), "127.0.0.1:123");
+        assert!(spawner.resolved.is_some());
+    }
+
     #[tokio::test]
     async fn works_if_address_does_not_resolve() {
         let mut spawner = StandardSpawner::new(
@@ -334,8 +509,11 @@ mod tests {
                 address: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![])
                     .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key_id: None,
+                resolve_interval: 3600,
             },
             SourceConfig::default(),
+            None,
         );
         let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
 
@@ -344,4 +522,59 @@ mod tests {
         let res = action_rx.try_recv().unwrap_err();
         assert_eq!(res, TryRecvError::Empty);
     }
+
+    #[tokio::test]
+    async fn moves_source_when_address_changes() {
+        let address = NormalizedAddress::with_hardcoded_dns(
+            "example.com",
+            123,
+            vec!["127.0.0.1:123".parse().unwrap()],
+        );
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: address.clone().into(),
+                ntp_version: ProtocolVersion::V4,
+                key_id: None,
+                resolve_interval: 60,
+            },
+            SourceConfig::default(),
+            None,
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        let params = get_ntp_create_params(res).unwrap();
+        assert!(spawner.is_complete());
+
+        // Nothing changes while the name still resolves to the address
+        spawner.resolved_at = Some(Instant::now() - Duration::from_secs(61));
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(spawner.is_complete());
+
+        // The running source is moved once the address is gone
+        address.set_hardcoded_dns(vec!["127.0.0.2:123".parse().unwrap()]);
+        spawner.resolved_at = Some(Instant::now() - Duration::from_secs(61));
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        let SpawnAction::ChangeAddress { id, addr } = res.action else {
+            panic!("Expected the source to be moved");
+        };
+        assert_eq!(id, params.id);
+        assert_eq!(addr.to_string(), "127.0.0.2:123");
+        assert!(spawner.is_complete());
+
+        // A removed source isn't refreshed anymore
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::Demobilized,
+            })
+            .await
+            .unwrap();
+        spawner.resolved_at = Some(Instant::now() - Duration::from_secs(61));
+        assert!(spawner.is_complete());
+    }
 }
@@ -0,0 +1,235 @@
+//! Saving the internal state of the clock algorithm when the daemon is
+//! stopped, and restoring it when it starts again. A restart, for example to
+//! apply an upgrade, then doesn't throw away the estimates of the clock and
+//! of its sources, so synchronization continues about as well as before
+//! instead of having to converge from scratch.
+//!
+//! The saved state describes the clock at one moment, so it is only used
+//! when it is recent, and not after a reboot in between. The file is removed
+//! once it has been read, so the same state is never restored twice.
+
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use ntp_proto::{
+    ClockId, NtpClock, NtpTimestamp, SavedControllerState, SavedSourceState, TimeSyncController,
+};
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tracing::{info, warn};
+
+use super::util::write_atomically;
+
+/// Saved state older than this (in seconds) no longer describes the clock
+const MAXIMUM_AGE: f64 = 3600.0;
+/// Changes on every boot on Linux. Elsewhere reboots are caught by the
+/// clock frequency the controller checks
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// When and where the state was saved
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Origin {
+    saved_at: NtpTimestamp,
+    boot_id: Option<String>,
+}
+
+impl Origin {
+    fn current(clock: &impl NtpClock) -> std::io::Result<Self> {
+        Ok(Origin {
+            saved_at: clock.now().map_err(std::io::Error::other)?,
+            boot_id: boot_id(),
+        })
+    }
+
+    /// Check that state saved at this origin can still be applied to the
+    /// clock at time `now`
+    fn is_usable(&self, now: NtpTimestamp, boot_id: Option<&str>) -> bool {
+        if self.boot_id.as_deref() != boot_id {
+            info!("System rebooted since the state was saved, not restoring it");
+            return false;
+        }
+
+        let age = (now - self.saved_at).to_seconds();
+        if !(0.0..=MAXIMUM_AGE).contains(&age) {
+            info!(age, "Saved state is too old, not restoring it");
+            return false;
+        }
+
+        true
+    }
+}
+
+/// State saved by a previous run of the daemon
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SavedState {
+    #[serde(flatten)]
+    origin: Origin,
+    controller: SavedControllerState,
+    /// State of the NTP sources, keyed by their address as source ids are
+    /// only meaningful within a single run
+    sources: HashMap<String, SavedSourceState>,
+}
+
+impl SavedState {
+    /// Restore the state into `controller`, returning the saved state of its
+    /// NTP sources by address
+    pub(crate) fn restore(
+        self,
+        controller: &impl TimeSyncController,
+    ) -> HashMap<String, SavedSourceState> {
+        // The controller logs why it rejects a state itself
+        if !controller.restore_state(&self.controller) {
+            return HashMap::new();
+        }
+
+        info!(sources = self.sources.len(), "Restored saved state");
+        self.sources
+    }
+}
+
+fn boot_id() -> Option<String> {
+    std::fs::read_to_string(BOOT_ID_PATH)
+        .ok()
+        .map(|id| id.trim().to_owned())
+}
+
+/// Load the state saved by a previous run, if it still applies to the clock
+pub(crate) fn load(path: &Path, clock: &impl NtpClock) -> Option<SavedState> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            info!(?path, "No saved state, starting from scratch");
+            return None;
+        }
+        Err(error) => {
+            warn!(?error, ?path, "Could not read state file");
+            return None;
+        }
+    };
+
+    if let Err(error) = std::fs::remove_file(path) {
+        warn!(?error, ?path, "Could not remove state file");
+    }
+
+    let state: SavedState = match serde_json::from_slice(&data) {
+        Ok(state) => state,
+        Err(error) => {
+            warn!(?error, ?path, "Could not parse state file");
+            return None;
+        }
+    };
+
+    let now = match clock.now() {
+        Ok(now) => now,
+        Err(error) => {
+            warn!(?error, "Could not read clock to check saved state");
+            return None;
+        }
+    };
+
+    state
+        .origin
+        .is_usable(now, boot_id().as_deref())
+        .then_some(state)
+}
+
+/// Save the state of `controller` and its sources, identifying the sources
+/// by the address `address` gives for them. Sources without an address
+/// can't be matched up with their successor after a restart and are skipped.
+pub(crate) fn save(
+    path: &Path,
+    clock: &impl NtpClock,
+    controller: &impl TimeSyncController,
+    address: impl Fn(ClockId) -> Option<String>,
+) -> std::io::Result<()> {
+    let Some((controller_state, sources)) = controller.save_state() else {
+        info!("The clock algorithm has no state to save");
+        return Ok(());
+    };
+
+    let state = SavedState {
+        origin: Origin::current(clock)?,
+        controller: controller_state,
+        sources: sources
+            .into_iter()
+            .filter_map(|(id, state)| Some((address(id)?, state)))
+            .collect(),
+    };
+
+    let contents = serde_json::to_vec(&state)?;
+    write_atomically(path, 0o644, |file| file.write_all(&contents))?;
+
+    info!(sources = state.sources.len(), "Saved state");
+    Ok(())
+}
+
+/// Listen for the signals that stop the daemon, so the state can be saved
+/// before exiting
+pub(crate) fn listen_for_stop() -> Option<[Signal; 2]> {
+    let listen = |kind| {
+        signal(kind)
+            .inspect_err(|error| {
+                warn!(
+                    ?error,
+                    "Could not listen for stop signals, the state will not be saved"
+                );
+            })
+            .ok()
+    };
+
+    Some([
+        listen(SignalKind::terminate())?,
+        listen(SignalKind::interrupt())?,
+    ])
+}
+
+/// Wait for a signal to stop the daemon, forever if we aren't listening for
+/// them
+pub(crate) async fn stop_requested(signals: &mut Option<[Signal; 2]>) {
+    match signals {
+        Some([terminate, interrupt]) => {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::NtpDuration;
+
+    use super::*;
+
+    #[test]
+    fn test_is_usable() {
+        let saved_at = NtpTimestamp::from_seconds_nanos_since_ntp_era(1000, 0);
+        let origin = Origin {
+            saved_at,
+            boot_id: Some("abc".into()),
+        };
+
+        let now = saved_at + NtpDuration::from_seconds(60.0);
+        assert!(origin.is_usable(now, Some("abc")));
+        assert!(!origin.is_usable(now, Some("def")));
+        assert!(!origin.is_usable(now, None));
+
+        let later = saved_at + NtpDuration::from_seconds(2.0 * MAXIMUM_AGE);
+        assert!(!origin.is_usable(later, Some("abc")));
+        let earlier = saved_at - NtpDuration::from_seconds(60.0);
+        assert!(!origin.is_usable(earlier, Some("abc")));
+
+        // Without a boot id, only the age can be checked
+        let origin = Origin {
+            saved_at,
+            boot_id: None,
+        };
+        assert!(origin.is_usable(now, None));
+    }
+}
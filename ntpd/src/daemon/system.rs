@@ -1,120 +1,186 @@
 #[cfg(feature = "pps")]
 use crate::daemon::pps_source::PpsSourceTask;
+#[cfg(feature = "client")]
 use crate::daemon::{
-    sock_source::SockSourceTask,
-    spawn::{SourceCreateParameters, spawner_task},
+    broadcast_source::BroadcastSourceTask, gpsd_source::GpsdSourceTask,
+    nmea_source::NmeaSourceTask, phc_source::PhcSourceTask, ptp_source::PtpSourceTask,
+    roughtime_source::RoughtimeSourceTask, shm_source::ShmSourceTask, sock_source::SockSourceTask,
 };
 
-use super::spawn::nts_pool::NtsPoolSpawner;
 use super::{
     clock::NtpClockWrapper,
-    config::{ClockConfig, NtpSourceConfig, ServerConfig, TimestampMode},
-    ntp_source::{MsgForSystem, SourceChannels, SourceTask},
-    server::{ServerStats, ServerTask},
+    config::{ClockConfig, NtpSourceConfig, ServerConfig, SourceOptions, TemperatureConfig},
+    jump_detection::JumpDetector,
+    observer::SpawnersReader,
+    server::ServerStats,
+};
+#[cfg(feature = "client")]
+use super::{
+    config::TimestampMode,
+    control::{ControlCommand, ControlMessage, ControlResponse},
+    latency::SoftwareLatency,
+    ntp_source::{MsgForSource, MsgForSystem, PollSchedule, SourceChannels, SourceTask},
     spawn::{
-        SourceRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId, SystemEvent,
-        nts::NtsSpawner, pool::PoolSpawner, sock::SockSpawner, standard::StandardSpawner,
+        SourceCreateParameters, SourceRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+        SpawnerRetry, SystemEvent, broadcast::BroadcastSpawner, gpsd::GpsdSpawner,
+        key_exchange::KeyExchangeManager, nmea::NmeaSpawner, nts::NtsSpawner,
+        nts_pool::NtsPoolSpawner, phc::PhcSpawner, pool::PoolSpawner, ptp::PtpSpawner,
+        roughtime::RoughtimeSpawner, shm::ShmSpawner, sock::SockSpawner, spawner_task,
+        standard::StandardSpawner,
     },
 };
 
+#[cfg(feature = "server")]
+use super::server::ServerTask;
+
 #[cfg(feature = "pps")]
 use super::spawn::pps::PpsSpawner;
 
+use crate::exitcode;
+
 use std::{
     collections::HashMap,
     net::IpAddr,
-    sync::{Arc, Mutex, RwLock},
+    path::PathBuf,
+    sync::{Arc, RwLock},
 };
+#[cfg(feature = "client")]
+use std::{collections::VecDeque, sync::Mutex};
 
 use ntp_proto::{
-    ClockId, KeySet, NtpClock, NtpManager, ObservableSourceState, OneWaySource, SourceConfig,
-    SourceType, SynchronizationConfig, SystemSnapshot, TimeSyncController,
+    ClockId, KeySet, NtpClock, NtpManager, ObservableSourceState, SourceConfig, SymmetricKey,
+    SynchronizationConfig, SystemSnapshot, TimeSyncController,
 };
+#[cfg(feature = "client")]
+use ntp_proto::{NtpDuration, OneWaySource, SavedSourceState, SourceController, SourceType};
+#[cfg(feature = "client")]
 use timestamped_socket::interface::InterfaceName;
-use tokio::{sync::mpsc, task::JoinHandle};
-use tracing::{debug, info};
+#[cfg(feature = "client")]
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::info;
+#[cfg(feature = "client")]
+use tracing::{debug, warn};
 
 pub const NETWORK_WAIT_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
 
+#[cfg(feature = "client")]
 pub const MESSAGE_BUFFER_SIZE: usize = 32;
 
+/// How often we check whether standby failover groups are needed, and
+/// whether trimmed sources can get their turn
+#[cfg(feature = "client")]
+const SOURCE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Time new sources get to be selected before they may make way for
+/// trimmed ones
+#[cfg(feature = "client")]
+const TRIM_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
 pub struct DaemonChannels {
     pub source_snapshots: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
+    pub spawners_reader: SpawnersReader,
     pub server_data_receiver: tokio::sync::watch::Receiver<Vec<ServerData>>,
     pub system_snapshot_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
+    #[cfg(feature = "client")]
+    pub control_sender: mpsc::Sender<ControlMessage>,
 }
 
 /// Spawn the NTP daemon
+///
+/// Sources are only started when the `client` feature is enabled, and
+/// servers only when the `server` feature is enabled. Configuration for
+/// a subsystem that was compiled out is ignored here, `Config::check`
+/// warns the user about it.
+#[cfg_attr(
+    not(all(feature = "client", feature = "server")),
+    expect(
+        unused_variables,
+        reason = "Configuration for compiled out subsystems is not used"
+    )
+)]
+#[expect(clippy::too_many_arguments)]
 pub async fn spawn<Controller: TimeSyncController<Clock = NtpClockWrapper>>(
     synchronization_config: SynchronizationConfig,
     algorithm_config: Controller::AlgorithmConfig,
+    source_options: SourceOptions,
+    temperature_config: Option<TemperatureConfig>,
+    drift_file: Option<PathBuf>,
+    state_file: Option<PathBuf>,
     source_defaults_config: SourceConfig,
     clock_config: ClockConfig,
     source_configs: &[NtpSourceConfig],
     server_configs: &[ServerConfig],
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    symmetric_keys: Arc<[SymmetricKey]>,
 ) -> std::io::Result<(JoinHandle<std::io::Result<()>>, DaemonChannels)> {
-    let ip_list = super::local_ip_provider::spawn()?;
+    let (ip_list, network_changes) = super::local_ip_provider::spawn()?;
+
+    let saved_state = state_file
+        .as_deref()
+        .and_then(|path| super::state_file::load(path, &clock_config.clock));
+
+    // Must happen before the controller reads the current frequency. A saved
+    // state is only usable when nothing changed the frequency since it was
+    // saved, so then the frequency is already right.
+    if saved_state.is_none()
+        && let Some(path) = &drift_file
+    {
+        super::drift_file::restore(path, &clock_config.clock);
+    }
 
     let (mut system, channels) = SystemTask::<_, Controller>::new(
         clock_config.clock,
-        clock_config.interface,
-        clock_config.timestamp_mode,
         synchronization_config,
         algorithm_config,
-        &keyset,
         ip_list,
-        !source_configs.is_empty(),
+        cfg!(feature = "client") && !source_configs.is_empty(),
     );
 
-    for source_config in source_configs {
-        match source_config {
-            NtpSourceConfig::Standard(cfg) => {
-                system.add_spawner(StandardSpawner::new(
-                    cfg.first.clone(),
-                    cfg.second.clone().with_defaults(source_defaults_config),
-                ));
-            }
-            NtpSourceConfig::Nts(cfg) => {
-                NtsSpawner::new(
-                    cfg.first.clone(),
-                    cfg.second.clone().with_defaults(source_defaults_config),
-                )
-                .map(|spawner| system.add_spawner(spawner))
-                .map_err(|e| {
-                    tracing::error!("Could not spawn source: {}", e);
-                    std::io::Error::other(e)
-                })?;
-            }
-            NtpSourceConfig::Pool(cfg) => {
-                system.add_spawner(PoolSpawner::new(
-                    cfg.first.clone(),
-                    cfg.second.clone().with_defaults(source_defaults_config),
-                ));
-            }
-            NtpSourceConfig::NtsPool(cfg) => {
-                NtsPoolSpawner::new(
-                    cfg.first.clone(),
-                    cfg.second.clone().with_defaults(source_defaults_config),
-                )
-                .map(|spawner| system.add_spawner(spawner))
-                .map_err(|e| {
-                    tracing::error!("Could not spawn source: {}", e);
-                    std::io::Error::other(e)
-                })?;
-            }
-            NtpSourceConfig::Sock(cfg) => {
-                system.add_spawner(SockSpawner::new(cfg.clone(), source_defaults_config));
-            }
-            #[cfg(feature = "pps")]
-            NtpSourceConfig::Pps(cfg) => {
-                system.add_spawner(PpsSpawner::new(cfg.clone(), source_defaults_config));
-            }
+    let saved_sources = saved_state.map(|saved_state| saved_state.restore(&*system.controller));
+    system.state_file = state_file;
+
+    if let Some(temperature_config) = temperature_config {
+        super::temperature::spawn(temperature_config, system.controller.clone())?;
+    }
+
+    if let Some(path) = drift_file {
+        super::drift_file::spawn(path, system.controller.clone());
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    {
+        system.symmetric_keys = symmetric_keys;
+    }
+
+    #[cfg(feature = "client")]
+    {
+        let client = &mut system.client;
+        client.interface = clock_config.interface;
+        client.timestamp_mode = clock_config.timestamp_mode;
+        client.network_changes = network_changes;
+        client.saved_sources = saved_sources.unwrap_or_default();
+
+        if source_options.software_latency_compensation {
+            client.software_latency = super::latency::spawn(clock_config.clock);
+        }
+
+        if source_options.coordinated_polling {
+            client.poll_schedule = Some(PollSchedule::new(tokio::time::Instant::now()));
+        }
+
+        client.source_defaults = source_defaults_config;
+        client.max_sources = source_options.max_sources;
+        client.failover_threshold = source_options.failover_threshold;
+
+        for source_config in source_configs {
+            system.add_source(source_config)?;
         }
     }
 
+    #[cfg(feature = "server")]
     for server_config in server_configs {
-        system.add_server(server_config.to_owned()).await;
+        system.add_server(server_config.to_owned(), &keyset).await;
     }
 
     let handle = tokio::spawn(async move { system.run().await });
@@ -122,31 +188,75 @@ pub async fn spawn<Controller: TimeSyncController<Clock = NtpClockWrapper>>(
     Ok((handle, channels))
 }
 
+#[cfg(feature = "client")]
 struct SystemSpawnerData {
     id: SpawnerId,
+    /// The kind of sources the spawner creates
+    stype: SourceType,
     notify_tx: mpsc::Sender<SystemEvent>,
 }
 
+/// A source in a failover group after the first, which is only mobilized
+/// while the groups before it have too few sources in use
+#[cfg(feature = "client")]
+struct StandbySource {
+    priority: u8,
+    config: NtpSourceConfig,
+    /// The spawner of the source while it is mobilized
+    spawner: Option<SpawnerId>,
+}
+
 struct SystemTask<C: NtpClock, Controller: TimeSyncController<Clock = C>> {
     controller: Arc<Controller>,
     ntp_manager: Arc<NtpManager>,
 
     system_snapshot_sender: tokio::sync::watch::Sender<SystemSnapshot>,
-    source_snapshots: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
+    #[cfg(feature = "server")]
     server_data_sender: tokio::sync::watch::Sender<Vec<ServerData>>,
-    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
     ip_list: tokio::sync::watch::Receiver<Arc<[IpAddr]>>,
+    #[cfg(any(feature = "client", feature = "server"))]
+    symmetric_keys: Arc<[SymmetricKey]>,
+
+    #[cfg(feature = "client")]
+    client: ClientState,
+    #[cfg(feature = "server")]
+    servers: Vec<ServerData>,
+
+    // where the state is saved when the daemon is stopped
+    state_file: Option<PathBuf>,
+
+    clock: C,
+}
+
+/// The sources of the system, and the spawners that create them
+#[cfg(feature = "client")]
+struct ClientState {
+    source_snapshots: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
+    spawner_retries: Arc<std::sync::RwLock<HashMap<SpawnerId, SpawnerRetry>>>,
+    network_changes: tokio::sync::watch::Receiver<()>,
+    software_latency: tokio::sync::watch::Receiver<SoftwareLatency>,
+    poll_schedule: Option<PollSchedule>,
+    source_defaults: SourceConfig,
+    max_sources: Option<usize>,
+    // spawners that had a source trimmed to stay within max_sources, in
+    // the order in which they get room again
+    trimmed: VecDeque<SpawnerId>,
+    failover_threshold: usize,
+    standby_sources: Vec<StandbySource>,
+    key_exchange_manager: KeyExchangeManager,
 
     msg_for_system_rx: mpsc::Receiver<MsgForSystem>,
     msg_for_system_tx: mpsc::Sender<MsgForSystem>,
     spawn_tx: mpsc::Sender<SpawnEvent>,
     spawn_rx: mpsc::Receiver<SpawnEvent>,
+    control_rx: mpsc::Receiver<ControlMessage>,
 
     sources: Arc<Mutex<HashMap<ClockId, SourceState>>>,
-    servers: Vec<ServerData>,
     spawners: Vec<SystemSpawnerData>,
 
-    clock: C,
+    // state of NTP sources from before a restart, by address, waiting for
+    // those sources to be created again
+    saved_sources: HashMap<String, SavedSourceState>,
 
     // which timestamps to use (this is a hint, OS or hardware may ignore)
     timestamp_mode: TimestampMode,
@@ -156,15 +266,59 @@ struct SystemTask<C: NtpClock, Controller: TimeSyncController<Clock = C>> {
     interface: Option<InterfaceName>,
 }
 
+#[cfg(feature = "client")]
+impl ClientState {
+    fn new(
+        source_snapshots: Arc<std::sync::RwLock<HashMap<ClockId, ObservableSourceState>>>,
+    ) -> (Self, mpsc::Sender<ControlMessage>) {
+        let (msg_for_system_tx, msg_for_system_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let (spawn_tx, spawn_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let (control_sender, control_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        let client = ClientState {
+            source_snapshots,
+            spawner_retries: Arc::default(),
+            network_changes: tokio::sync::watch::channel(()).1,
+            software_latency: tokio::sync::watch::channel(SoftwareLatency::default()).1,
+            poll_schedule: None,
+            source_defaults: SourceConfig::default(),
+            max_sources: None,
+            trimmed: VecDeque::new(),
+            failover_threshold: 0,
+            standby_sources: vec![],
+            key_exchange_manager: KeyExchangeManager::new(),
+            msg_for_system_rx,
+            msg_for_system_tx,
+            spawn_tx,
+            spawn_rx,
+            control_rx,
+            sources: Arc::default(),
+            spawners: vec![],
+            saved_sources: HashMap::new(),
+            timestamp_mode: TimestampMode::default(),
+            interface: None,
+        };
+        (client, control_sender)
+    }
+
+    fn spawners_reader(&self) -> SpawnersReader {
+        let spawner_retries = self.spawner_retries.clone();
+        Arc::new(move || {
+            spawner_retries
+                .read()
+                .expect("Unexpected poisoned mutex")
+                .values()
+                .map(Into::into)
+                .collect()
+        })
+    }
+}
+
 impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C, Controller> {
-    #[expect(clippy::too_many_arguments)]
     fn new(
         clock: C,
-        interface: Option<InterfaceName>,
-        timestamp_mode: TimestampMode,
         synchronization_config: SynchronizationConfig,
         algorithm_config: Controller::AlgorithmConfig,
-        keyset: &tokio::sync::watch::Receiver<Arc<KeySet>>,
         ip_list: tokio::sync::watch::Receiver<Arc<[IpAddr]>>,
         have_sources: bool,
     ) -> (Self, DaemonChannels) {
@@ -172,13 +326,13 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
             Controller::new(clock.clone(), synchronization_config, algorithm_config)
         else {
             tracing::error!("Could not create clock controller");
-            std::process::exit(70);
+            exitcode::exit(exitcode::SOFTWARE);
         };
         let ntp_manager = NtpManager::new(synchronization_config, ip_list.borrow().clone());
 
         if have_sources && let Err(e) = controller.take_control() {
             tracing::error!("Could not control clock: {}", e);
-            std::process::exit(70);
+            exitcode::exit(exitcode::NOPERM);
         }
 
         let system_snapshot = SystemSnapshot {
@@ -190,10 +344,18 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         let (system_snapshot_sender, system_snapshot_receiver) =
             tokio::sync::watch::channel(system_snapshot);
         let source_snapshots = Arc::new(RwLock::new(HashMap::new()));
+        #[cfg(feature = "server")]
         let (server_data_sender, server_data_receiver) = tokio::sync::watch::channel(vec![]);
-        let (msg_for_system_sender, msg_for_system_receiver) =
-            tokio::sync::mpsc::channel(MESSAGE_BUFFER_SIZE);
-        let (spawn_tx, spawn_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        // Without servers, the list of servers stays empty
+        #[cfg(not(feature = "server"))]
+        let server_data_receiver = tokio::sync::watch::channel(vec![]).1;
+
+        #[cfg(feature = "client")]
+        let (client, control_sender) = ClientState::new(source_snapshots.clone());
+        #[cfg(feature = "client")]
+        let spawners_reader = client.spawners_reader();
+        #[cfg(not(feature = "client"))]
+        let spawners_reader: SpawnersReader = Arc::new(Vec::new);
 
         // Build System and its channels
         (
@@ -202,43 +364,319 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
                 ntp_manager: Arc::new(ntp_manager),
 
                 system_snapshot_sender,
-                source_snapshots: source_snapshots.clone(),
+                #[cfg(feature = "server")]
                 server_data_sender,
-                keyset: keyset.clone(),
                 ip_list,
+                #[cfg(any(feature = "client", feature = "server"))]
+                symmetric_keys: Arc::new([]),
 
-                msg_for_system_rx: msg_for_system_receiver,
-                msg_for_system_tx: msg_for_system_sender,
-                spawn_rx,
-                spawn_tx,
-
-                sources: Arc::default(),
+                #[cfg(feature = "client")]
+                client,
+                #[cfg(feature = "server")]
                 servers: vec![],
-                spawners: vec![],
+                state_file: None,
                 clock,
-                timestamp_mode,
-                interface,
             },
             DaemonChannels {
                 source_snapshots,
+                spawners_reader,
                 server_data_receiver,
                 system_snapshot_receiver,
+                #[cfg(feature = "client")]
+                control_sender,
             },
         )
     }
 
-    fn add_spawner(&mut self, spawner: impl Spawner + Send + Sync + 'static) -> SpawnerId {
+    #[cfg(feature = "client")]
+    fn add_spawner(
+        &mut self,
+        spawner: impl Spawner + Send + Sync + 'static,
+        stype: SourceType,
+    ) -> SpawnerId {
         let (notify_tx, notify_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
         let id = spawner.get_id();
-        let spawner_data = SystemSpawnerData { id, notify_tx };
+        let spawner_data = SystemSpawnerData {
+            id,
+            stype,
+            notify_tx,
+        };
         debug!(id=?spawner_data.id, ty=spawner.get_description(), addr=spawner.get_addr_description(), "Running spawner");
-        self.spawners.push(spawner_data);
-        let spawn_tx = self.spawn_tx.clone();
+        self.client.spawners.push(spawner_data);
+        let spawn_tx = self.client.spawn_tx.clone();
         // tokio::spawn(async move { spawner.run(spawn_tx, notify_rx).await });
-        tokio::spawn(spawner_task(spawner, spawn_tx, notify_rx));
+        tokio::spawn(spawner_task(
+            spawner,
+            spawn_tx,
+            notify_rx,
+            self.client.spawner_retries.clone(),
+        ));
         id
     }
 
+    /// Add a source, keeping it on standby when it is in a failover group
+    /// other than the first. Those are mobilized once they are needed.
+    #[cfg(feature = "client")]
+    fn add_source(&mut self, source_config: &NtpSourceConfig) -> std::io::Result<()> {
+        match source_config.priority() {
+            0 => {
+                self.add_source_config(source_config)?;
+            }
+            priority => self.client.standby_sources.push(StandbySource {
+                priority,
+                config: source_config.clone(),
+                spawner: None,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Mobilize the failover groups that are needed, and demobilize those
+    /// that are no longer needed. A group is needed when the groups before
+    /// it have fewer sources in use than the failover threshold.
+    #[cfg(feature = "client")]
+    fn update_failover_groups(&mut self) {
+        let (_, used_sources) = self.controller.synchronization_state();
+        let mut used_by_priority = std::collections::BTreeMap::<u8, usize>::new();
+        {
+            let sources = self.client.sources.lock().unwrap();
+            for state in used_sources.iter().filter_map(|id| sources.get(id)) {
+                let priority = self
+                    .client
+                    .standby_sources
+                    .iter()
+                    .find(|standby| standby.spawner == Some(state.spawner_id))
+                    .map_or(0, |standby| standby.priority);
+                *used_by_priority.entry(priority).or_default() += 1;
+            }
+        }
+
+        // Groups after the one that brings us to the threshold aren't needed
+        let mut used = 0;
+        let needed = used_by_priority
+            .into_iter()
+            .find_map(|(priority, count)| {
+                used += count;
+                (used >= self.client.failover_threshold).then_some(priority)
+            })
+            .unwrap_or(u8::MAX);
+
+        let mut standby_sources = std::mem::take(&mut self.client.standby_sources);
+        for standby in &mut standby_sources {
+            let priority = standby.priority;
+            match standby.spawner {
+                None if priority <= needed => {
+                    info!(priority, "Mobilizing standby sources");
+                    // Failures are logged, we try again on the next check
+                    standby.spawner = self.add_source_config(&standby.config).ok();
+                }
+                Some(spawner_id) if priority > needed => {
+                    info!(priority, "Demobilizing standby sources");
+                    standby.spawner = None;
+                    self.remove_spawner(spawner_id);
+                }
+                _ => {}
+            }
+        }
+        self.client.standby_sources = standby_sources;
+    }
+
+    /// Stop a spawner together with all the sources it created
+    #[cfg(feature = "client")]
+    fn remove_spawner(&mut self, spawner_id: SpawnerId) {
+        // The spawner stops once its channel is closed
+        self.client
+            .spawners
+            .retain(|spawner| spawner.id != spawner_id);
+        self.client.trimmed.retain(|id| *id != spawner_id);
+
+        let mut snapshots = self
+            .client
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex");
+        self.client.sources.lock().unwrap().retain(|index, state| {
+            if state.spawner_id != spawner_id {
+                return true;
+            }
+            state.task.abort();
+            snapshots.remove(index);
+            false
+        });
+    }
+
+    /// Give room for more sources to spawners whose sources were trimmed.
+    /// Without room, the worst source that is not in use makes way for one
+    /// of them, so that over time we end up with the best of all sources.
+    #[cfg(feature = "client")]
+    async fn update_trimmed_sources(&mut self) {
+        let Some(max) = self.client.max_sources else {
+            return;
+        };
+        if self.client.trimmed.is_empty() {
+            return;
+        }
+
+        let mut room = max.saturating_sub(self.client.sources.lock().unwrap().len());
+        if room == 0
+            && let Some(state) = self.remove_worst_source()
+        {
+            info!(source_id=?state.source_id, "trimmed source");
+            self.trim_source(state.spawner_id, state.source_id).await;
+            room = 1;
+        }
+
+        while room > 0
+            && let Some(spawner_id) = self.client.trimmed.pop_front()
+        {
+            if let Some(spawner) = self.client.spawners.iter().find(|s| s.id == spawner_id)
+                && spawner
+                    .notify_tx
+                    .send(SystemEvent::CapacityAvailable)
+                    .await
+                    .is_ok()
+            {
+                room -= 1;
+            }
+        }
+    }
+
+    /// Remove the NTP source with the largest root distance among those
+    /// not in use that had time to get selected
+    #[cfg(feature = "client")]
+    fn remove_worst_source(&mut self) -> Option<SourceState> {
+        let (_, used_sources) = self.controller.synchronization_state();
+        let mut snapshots = self
+            .client
+            .source_snapshots
+            .write()
+            .expect("Unexpected poisoned mutex");
+        let mut sources = self.client.sources.lock().unwrap();
+
+        let worst = sources
+            .values()
+            .filter(|state| {
+                state.msg_for_source_sender.is_some()
+                    && state.created.elapsed() >= TRIM_GRACE_PERIOD
+                    && !used_sources.contains(&state.source_id)
+                    // Monitored sources are never used, on purpose
+                    && !snapshots
+                        .get(&state.source_id)
+                        .is_some_and(|snapshot| snapshot.noselect)
+            })
+            .max_by_key(|state| {
+                snapshots
+                    .get(&state.source_id)
+                    .map_or(NtpDuration::MAX, |snapshot| {
+                        snapshot.timedata.root_distance()
+                    })
+            })?
+            .source_id;
+
+        let state = sources.remove(&worst)?;
+        state.task.abort();
+        snapshots.remove(&worst);
+        Some(state)
+    }
+
+    #[cfg(feature = "client")]
+    fn add_source_config(&mut self, source_config: &NtpSourceConfig) -> std::io::Result<SpawnerId> {
+        let defaults = self.client.source_defaults;
+        let stype = source_config.source_type();
+        let id = match source_config {
+            NtpSourceConfig::Standard(cfg) => {
+                let symmetric_key = cfg
+                    .first
+                    .key_id
+                    .map(|key_id| {
+                        self.symmetric_keys
+                            .iter()
+                            .find(|key| key.id() == key_id)
+                            .cloned()
+                            .ok_or_else(|| {
+                                tracing::error!(key_id, "Could not spawn source: unknown key");
+                                std::io::Error::new(
+                                    std::io::ErrorKind::InvalidInput,
+                                    "unknown symmetric key",
+                                )
+                            })
+                    })
+                    .transpose()?;
+                self.add_spawner(
+                    StandardSpawner::new(
+                        cfg.first.clone(),
+                        cfg.source_config(defaults),
+                        symmetric_key,
+                    ),
+                    stype,
+                )
+            }
+            NtpSourceConfig::Nts(cfg) => NtsSpawner::new(
+                cfg.first.clone(),
+                cfg.source_config(defaults),
+                self.client.key_exchange_manager.clone(),
+            )
+            .map(|spawner| self.add_spawner(spawner, stype))
+            .map_err(|e| {
+                tracing::error!("Could not spawn source: {}", e);
+                std::io::Error::other(e)
+            })?,
+            NtpSourceConfig::Pool(cfg) => self.add_spawner(
+                PoolSpawner::new(cfg.first.clone(), cfg.source_config(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::NtsPool(cfg) => NtsPoolSpawner::new(
+                cfg.first.clone(),
+                cfg.source_config(defaults),
+                self.client.key_exchange_manager.clone(),
+            )
+            .map(|spawner| self.add_spawner(spawner, stype))
+            .map_err(|e| {
+                tracing::error!("Could not spawn source: {}", e);
+                std::io::Error::other(e)
+            })?,
+            NtpSourceConfig::Sock(cfg) => self.add_spawner(
+                SockSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Broadcast(cfg) => self.add_spawner(
+                BroadcastSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Roughtime(cfg) => self.add_spawner(
+                RoughtimeSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Nmea(cfg) => self.add_spawner(
+                NmeaSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Gpsd(cfg) => self.add_spawner(
+                GpsdSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Phc(cfg) => self.add_spawner(
+                PhcSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Ptp(cfg) => self.add_spawner(
+                PtpSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            NtpSourceConfig::Shm(cfg) => self.add_spawner(
+                ShmSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+            #[cfg(feature = "pps")]
+            NtpSourceConfig::Pps(cfg) => self.add_spawner(
+                PpsSpawner::new(cfg.first.clone(), cfg.common.with_defaults(defaults)),
+                stype,
+            ),
+        };
+        Ok(id)
+    }
+
+    #[expect(clippy::too_many_lines)]
     async fn run(&mut self) -> std::io::Result<()> {
         let controller = self.controller.clone();
         let controller_run = controller.run();
@@ -246,20 +684,31 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         let sender = self.system_snapshot_sender.clone();
         let controller = self.controller.clone();
         let ntp_manager = self.ntp_manager.clone();
-        let sources = self.sources.clone();
+        #[cfg(feature = "client")]
+        let sources = self.client.sources.clone();
+        let clock = self.clock.clone();
+        let mut jump_detector = JumpDetector::default();
         let timer_loop = async move {
             loop {
                 // Scope is needed to keep the future send.
                 {
                     let (time_snapshot, used_sources) = controller.synchronization_state();
-                    let sources = sources.lock().unwrap();
                     ntp_manager.update_time_snapshot(time_snapshot);
 
-                    if let Some(used_sources) = used_sources
-                        .into_iter()
-                        .map(|id| sources.get(&id).map(|state| (id, state.stype)))
-                        .collect::<Option<Vec<_>>>()
-                    {
+                    #[cfg(feature = "client")]
+                    let used_sources = {
+                        super::measurements_log::update_selection(&used_sources);
+                        let sources = sources.lock().unwrap();
+                        used_sources
+                            .into_iter()
+                            .map(|id| sources.get(&id).map(|state| (id, state.stype)))
+                            .collect::<Option<Vec<_>>>()
+                    };
+                    // Without sources, the controller never uses any
+                    #[cfg(not(feature = "client"))]
+                    let used_sources = used_sources.is_empty().then(Vec::new);
+
+                    if let Some(used_sources) = used_sources {
                         let ntp_snapshot =
                             ntp_manager.update_used_sources(used_sources.into_iter());
                         sender
@@ -271,6 +720,21 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
                     } else {
                         sender.send_modify(|v| v.time_snapshot = time_snapshot);
                     }
+
+                    if let Some(jump) = jump_detector.check(&clock) {
+                        jump.report();
+                        controller.clock_discontinuity();
+                        // Get back to good time quickly
+                        #[cfg(feature = "client")]
+                        for source_sender in sources
+                            .lock()
+                            .unwrap()
+                            .values()
+                            .filter_map(|state| state.msg_for_source_sender.as_ref())
+                        {
+                            let _ = source_sender.try_send(MsgForSource::Burst);
+                        }
+                    }
                 }
 
                 tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
@@ -278,10 +742,21 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         };
 
         let ntp_manager = self.ntp_manager.clone();
+        let mut stop_signals = self
+            .state_file
+            .is_some()
+            .then(super::state_file::listen_for_stop)
+            .flatten();
+        #[cfg(feature = "client")]
+        let mut source_check = tokio::time::interval_at(
+            tokio::time::Instant::now() + SOURCE_CHECK_INTERVAL,
+            SOURCE_CHECK_INTERVAL,
+        );
+        #[cfg(feature = "client")]
         let event_loop = async move {
             loop {
                 tokio::select! {
-                    opt_msg_for_system = self.msg_for_system_rx.recv() => {
+                    opt_msg_for_system = self.client.msg_for_system_rx.recv() => {
                         match opt_msg_for_system {
                             None => {
                                 // the channel closed and has no more messages in it
@@ -293,7 +768,7 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
                             }
                         }
                     }
-                    opt_spawn_event = self.spawn_rx.recv() => {
+                    opt_spawn_event = self.client.spawn_rx.recv() => {
                         match opt_spawn_event {
                             None => {
                                 let msg = "the spawn channel closed unexpectedly. ntpd-rs is likely in an invalid state!";
@@ -306,19 +781,90 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
                             }
                         }
                     }
+                    Some(message) = self.client.control_rx.recv() => {
+                        let response = self.handle_control(message.command).await;
+                        // The requester may have given up waiting
+                        let _ = message.reply.send(response);
+                    }
                     _ = self.ip_list.changed(), if self.ip_list.has_changed().is_ok() => {
                         ntp_manager.update_ip_list(self.ip_list.borrow_and_update().clone());
                     }
+                    _ = self.client.network_changes.changed(), if self.client.network_changes.has_changed().is_ok() => {
+                        self.handle_network_change().await;
+                    }
+                    _ = source_check.tick(), if !(self.client.standby_sources.is_empty() && self.client.trimmed.is_empty()) => {
+                        self.update_failover_groups();
+                        self.update_trimmed_sources().await;
+                    }
+                    () = super::state_file::stop_requested(&mut stop_signals) => {
+                        self.save_state();
+                        info!("ntpd-rs is stopping");
+                        std::process::exit(exitcode::OK);
+                    }
                 }
             }
 
             // the channel closed and has no more messages in it
             Ok(())
         };
+        #[cfg(not(feature = "client"))]
+        let event_loop = async move {
+            loop {
+                tokio::select! {
+                    _ = self.ip_list.changed(), if self.ip_list.has_changed().is_ok() => {
+                        ntp_manager.update_ip_list(self.ip_list.borrow_and_update().clone());
+                    }
+                    () = super::state_file::stop_requested(&mut stop_signals) => {
+                        self.save_state();
+                        info!("ntpd-rs is stopping");
+                        std::process::exit(exitcode::OK);
+                    }
+                }
+            }
+        };
 
         tokio::join!(event_loop, timer_loop, controller_run).0
     }
 
+    fn save_state(&self) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+
+        #[cfg(feature = "client")]
+        let sources = self.client.sources.lock().unwrap();
+        #[cfg(feature = "client")]
+        let address = |id: ClockId| sources.get(&id)?.address.clone();
+        // Without sources, there is no state of sources to save
+        #[cfg(not(feature = "client"))]
+        let address = |_| None;
+        if let Err(error) = super::state_file::save(path, &self.clock, &*self.controller, address) {
+            tracing::warn!(?error, "Could not save state");
+        }
+    }
+
+    /// Get back in touch with the sources right away after the network
+    /// changed, instead of waiting for polls to time out. Sources open a new
+    /// socket for every poll, so their polls go out over the new network.
+    #[cfg(feature = "client")]
+    async fn handle_network_change(&mut self) {
+        info!("Network changed, polling sources");
+
+        for spawner in &self.client.spawners {
+            let _ = spawner.notify_tx.send(SystemEvent::NetworkChanged).await;
+        }
+
+        let sources = self.client.sources.lock().unwrap();
+        for sender in sources
+            .values()
+            .filter_map(|state| state.msg_for_source_sender.as_ref())
+        {
+            // A full channel means a burst is already on its way
+            let _ = sender.try_send(MsgForSource::Burst);
+        }
+    }
+
+    #[cfg(feature = "client")]
     async fn handle_source_update(&mut self, msg: MsgForSystem) -> std::io::Result<()> {
         tracing::debug!(?msg, "updating source");
 
@@ -339,12 +885,16 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         Ok(())
     }
 
+    #[cfg(feature = "client")]
     async fn handle_source_network_issue(&mut self, index: ClockId) -> std::io::Result<()> {
         // Restart the source reusing its configuration.
-        let state = self.sources.lock().unwrap().remove(&index).unwrap();
+        let Some(state) = self.client.sources.lock().unwrap().remove(&index) else {
+            // The source was already removed through the control socket
+            return Ok(());
+        };
         let spawner_id = state.spawner_id;
         let source_id = state.source_id;
-        let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
+        let opt_spawner = self.client.spawners.iter().find(|s| s.id == spawner_id);
         if let Some(spawner) = opt_spawner {
             spawner
                 .notify_tx
@@ -359,12 +909,16 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         Ok(())
     }
 
+    #[cfg(feature = "client")]
     async fn handle_source_unreachable(&mut self, index: ClockId) -> std::io::Result<()> {
         // Restart the source reusing its configuration.
-        let state = self.sources.lock().unwrap().remove(&index).unwrap();
+        let Some(state) = self.client.sources.lock().unwrap().remove(&index) else {
+            // The source was already removed through the control socket
+            return Ok(());
+        };
         let spawner_id = state.spawner_id;
         let source_id = state.source_id;
-        let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
+        let opt_spawner = self.client.spawners.iter().find(|s| s.id == spawner_id);
         if let Some(spawner) = opt_spawner {
             spawner
                 .notify_tx
@@ -379,12 +933,16 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         Ok(())
     }
 
+    #[cfg(feature = "client")]
     async fn handle_source_demobilize(&mut self, index: ClockId) -> Result<(), C::Error> {
         // Restart the source reusing its configuration.
-        let state = self.sources.lock().unwrap().remove(&index).unwrap();
+        let Some(state) = self.client.sources.lock().unwrap().remove(&index) else {
+            // The source was already removed through the control socket
+            return Ok(());
+        };
         let spawner_id = state.spawner_id;
         let source_id = state.source_id;
-        let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
+        let opt_spawner = self.client.spawners.iter().find(|s| s.id == spawner_id);
         if let Some(spawner) = opt_spawner {
             spawner
                 .notify_tx
@@ -398,53 +956,155 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
         Ok(())
     }
 
+    #[cfg(feature = "client")]
+    async fn handle_control(&mut self, command: ControlCommand) -> ControlResponse {
+        match command {
+            ControlCommand::AddSource(config) => {
+                let additional = match &*config {
+                    NtpSourceConfig::Pool(cfg) => cfg.first.count,
+                    NtpSourceConfig::NtsPool(cfg) => cfg.first.count,
+                    _ => 1,
+                };
+                if let Some(max) = self.client.max_sources {
+                    let current = self.client.sources.lock().unwrap().len();
+                    if current + additional > max {
+                        return ControlResponse::Error {
+                            message: format!(
+                                "Adding the source would exceed the maximum of {max} sources"
+                            ),
+                        };
+                    }
+                }
+
+                match self.add_source(&config) {
+                    Ok(()) => ControlResponse::SourceAdded,
+                    Err(e) => ControlResponse::Error {
+                        message: format!("Could not add source: {e}"),
+                    },
+                }
+            }
+            ControlCommand::RemoveSource(index) => {
+                let Some(state) = self.client.sources.lock().unwrap().remove(&index) else {
+                    return ControlResponse::Error {
+                        message: format!("No source with id {index}"),
+                    };
+                };
+
+                state.task.abort();
+                self.client
+                    .source_snapshots
+                    .write()
+                    .expect("Unexpected poisoned mutex")
+                    .remove(&index);
+
+                if let Some(spawner) = self
+                    .client
+                    .spawners
+                    .iter()
+                    .find(|s| s.id == state.spawner_id)
+                {
+                    let _ = spawner
+                        .notify_tx
+                        .send(SystemEvent::source_removed(
+                            state.source_id,
+                            SourceRemovalReason::Demobilized,
+                        ))
+                        .await;
+                }
+
+                info!(source_id=?index, "removed source");
+                ControlResponse::SourceRemoved
+            }
+            ControlCommand::Burst(index) => {
+                let sources = self.client.sources.lock().unwrap();
+                let senders: Vec<_> = match index {
+                    Some(index) => {
+                        let Some(state) = sources.get(&index) else {
+                            return ControlResponse::Error {
+                                message: format!("No source with id {index}"),
+                            };
+                        };
+                        let Some(sender) = &state.msg_for_source_sender else {
+                            return ControlResponse::Error {
+                                message: format!("Source {index} is not an NTP server"),
+                            };
+                        };
+                        vec![sender]
+                    }
+                    None => sources
+                        .values()
+                        .filter_map(|state| state.msg_for_source_sender.as_ref())
+                        .collect(),
+                };
+
+                // A full channel means a burst is already on its way
+                for sender in &senders {
+                    let _ = sender.try_send(MsgForSource::Burst);
+                }
+
+                info!(sources = senders.len(), "requested burst");
+                ControlResponse::BurstStarted {
+                    sources: senders.len(),
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[expect(clippy::too_many_lines)]
     async fn create_source(
         &mut self,
         spawner_id: SpawnerId,
+        stype: SourceType,
         mut params: SourceCreateParameters,
     ) -> Result<ClockId, C::Error> {
         let source_id = params.get_id();
         info!(source_id=?source_id, addr=?params.get_addr(), spawner=?spawner_id, "new source");
-        self.sources.lock().unwrap().insert(
-            source_id,
-            SourceState {
-                source_id,
-                spawner_id,
-                stype: match &params {
-                    SourceCreateParameters::Ntp(_) => SourceType::Ntp,
-                    SourceCreateParameters::Sock(_) => SourceType::Sock,
-                    #[cfg(feature = "pps")]
-                    SourceCreateParameters::Pps(_) => SourceType::Pps,
-                },
-            },
-        );
 
-        match params {
+        // Only NTP sources take requests from the system, and have an address
+        // identifying them across restarts
+        let mut msg_for_source_sender = None;
+        let mut address = None;
+        let task = match params {
             SourceCreateParameters::Ntp(ref mut params) => {
-                let source_controller = self.controller.add_source(source_id, params.config);
+                let mut source_controller = self.controller.add_source(source_id, params.config);
+                let source_address = params.addr.to_string();
+                if let Some(state) = self.client.saved_sources.remove(&source_address) {
+                    source_controller.restore_state(&state);
+                }
+                address = Some(source_address);
+
                 let (source, initial_actions) = self.ntp_manager.new_source(
                     params.addr,
                     params.config,
                     params.protocol_version,
                     source_controller,
                     params.nts.take(),
+                    params.symmetric_key.clone(),
                     source_id,
                 );
+                let (sender, msg_for_source_receiver) = mpsc::channel(1);
+                msg_for_source_sender = Some(sender);
 
                 SourceTask::spawn(
                     source_id,
                     params.normalized_addr.to_string(),
                     params.addr,
-                    self.interface,
+                    self.client.interface,
+                    params.config.local_address,
                     self.clock.clone(),
-                    self.timestamp_mode,
+                    self.client.timestamp_mode,
+                    self.client.software_latency.clone(),
+                    self.client.poll_schedule,
                     SourceChannels {
-                        msg_for_system_sender: self.msg_for_system_tx.clone(),
-                        source_snapshots: self.source_snapshots.clone(),
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
                     },
+                    msg_for_source_receiver,
                     source,
                     initial_actions,
-                );
+                    params.nts_fallback,
+                )
             }
             SourceCreateParameters::Sock(ref params) => {
                 let source_controller = self.controller.add_one_way_source(
@@ -458,85 +1118,355 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C>> SystemTask<C
                 SockSourceTask::spawn(
                     source_id,
                     params.path.clone(),
+                    params.socket.clone(),
+                    // The controller wants a variance, the task a standard deviation
+                    params.precision.sqrt(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
+            }
+            SourceCreateParameters::Broadcast(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
+                );
+                let source = self.ntp_manager.new_broadcast_source(
+                    source_controller,
+                    params.delay.map(NtpDuration::from_seconds),
+                    source_id,
+                );
+                BroadcastSourceTask::spawn(
+                    source_id,
+                    params.listen,
+                    params.multicast_group,
                     self.clock.clone(),
+                    self.client.software_latency.clone(),
                     SourceChannels {
-                        msg_for_system_sender: self.msg_for_system_tx.clone(),
-                        source_snapshots: self.source_snapshots.clone(),
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
                     },
                     source,
+                )
+            }
+            SourceCreateParameters::Roughtime(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
                 );
+                let source = OneWaySource::new(source_controller);
+                RoughtimeSourceTask::spawn(
+                    source_id,
+                    params.addr,
+                    params.public_key,
+                    params.poll_interval,
+                    self.clock.clone(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
+            }
+            SourceCreateParameters::Nmea(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
+                );
+                let source = OneWaySource::new(source_controller);
+                NmeaSourceTask::spawn(
+                    source_id,
+                    params.path.clone(),
+                    params.baud_rate,
+                    params.sentences.clone(),
+                    params.offsets,
+                    // The controller wants a variance, the task a standard deviation
+                    params.precision.sqrt(),
+                    self.clock.clone(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
+            }
+            SourceCreateParameters::Gpsd(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
+                );
+                let source = OneWaySource::new(source_controller);
+                GpsdSourceTask::spawn(
+                    source_id,
+                    params.address.clone(),
+                    params.device.clone(),
+                    params.pps,
+                    params.precision.sqrt(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
+            }
+            SourceCreateParameters::Phc(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
+                );
+                let source = OneWaySource::new(source_controller);
+                PhcSourceTask::spawn(
+                    source_id,
+                    params.path.clone(),
+                    params.poll_interval,
+                    params.precision.sqrt(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
+            }
+            SourceCreateParameters::Ptp(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
+                );
+                let source = OneWaySource::new(source_controller);
+                PtpSourceTask::spawn(
+                    source_id,
+                    params.group,
+                    params.domain,
+                    params.precision.sqrt(),
+                    self.clock.clone(),
+                    self.client.software_latency.clone(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
+            }
+            SourceCreateParameters::Shm(ref params) => {
+                let source_controller = self.controller.add_one_way_source(
+                    source_id,
+                    params.config,
+                    params.precision,
+                    params.accuracy,
+                    None,
+                );
+                let source = OneWaySource::new(source_controller);
+                ShmSourceTask::spawn(
+                    source_id,
+                    params.unit,
+                    params.poll_interval,
+                    params.precision.sqrt(),
+                    SourceChannels {
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
+                    },
+                    source,
+                )
             }
             #[cfg(feature = "pps")]
             SourceCreateParameters::Pps(ref params) => {
+                // Pulses locked to a source are numbered by it, so they give
+                // the full time rather than just the phase of the second
                 let source_controller = self.controller.add_one_way_source(
                     source_id,
                     params.config,
                     params.precision,
                     params.accuracy,
-                    Some(params.period),
+                    if params.lock.is_some() {
+                        None
+                    } else {
+                        Some(params.period)
+                    },
                 );
                 let source = OneWaySource::new(source_controller);
                 PpsSourceTask::spawn(
                     source_id,
                     params.path.clone(),
+                    params.edge,
+                    params.config.offset_correction + params.config.delay_correction,
+                    params.lock.clone(),
+                    params.period,
+                    self.clock.clone(),
                     SourceChannels {
-                        msg_for_system_sender: self.msg_for_system_tx.clone(),
-                        source_snapshots: self.source_snapshots.clone(),
+                        msg_for_system_sender: self.client.msg_for_system_tx.clone(),
+                        source_snapshots: self.client.source_snapshots.clone(),
                     },
                     source,
-                );
+                )
             }
-        }
+        };
+
+        self.client.sources.lock().unwrap().insert(
+            source_id,
+            SourceState {
+                source_id,
+                spawner_id,
+                stype,
+                task: task.abort_handle(),
+                msg_for_source_sender,
+                address,
+                created: tokio::time::Instant::now(),
+            },
+        );
 
         // Try and find a related spawner and notify that spawner.
         // This makes sure that the spawner that initially sent the create event
         // is now aware that the source was added to the system.
-        if let Some(s) = self.spawners.iter().find(|s| s.id == spawner_id) {
+        if let Some(s) = self.client.spawners.iter().find(|s| s.id == spawner_id) {
             let _ = s
                 .notify_tx
-                .send(SystemEvent::SourceRegistered(params))
+                .send(SystemEvent::SourceRegistered(Box::new(params)))
                 .await;
         }
 
         Ok(source_id)
     }
 
+    #[cfg(feature = "client")]
     async fn handle_spawn_event(&mut self, event: SpawnEvent) -> Result<(), C::Error> {
         match event.action {
             SpawnAction::Create(params) => {
-                self.create_source(event.id, params).await?;
+                // Spawners of standby sources may still have been busy
+                // creating a source when they were stopped
+                let Some(stype) = self
+                    .client
+                    .spawners
+                    .iter()
+                    .find(|spawner| spawner.id == event.id)
+                    .map(|spawner| spawner.stype)
+                else {
+                    return Ok(());
+                };
+
+                // Reference clocks are always welcome, NTP sources only when
+                // there is room for them
+                let full = self
+                    .client
+                    .max_sources
+                    .is_some_and(|max| self.client.sources.lock().unwrap().len() >= max);
+                if full && matches!(params, SourceCreateParameters::Ntp(_)) {
+                    debug!(source_id=?params.get_id(), "no room for source, trimming it");
+                    self.trim_source(event.id, params.get_id()).await;
+                } else {
+                    self.create_source(event.id, stype, params).await?;
+                }
+            }
+            SpawnAction::ChangeAddress { id, addr } => {
+                let mut sources = self.client.sources.lock().unwrap();
+                // The source may have been removed in the meantime
+                if let Some(state) = sources
+                    .get_mut(&id)
+                    .filter(|state| state.spawner_id == event.id)
+                    && let Some(sender) = &state.msg_for_source_sender
+                {
+                    if sender.try_send(MsgForSource::ChangeAddress(addr)).is_ok() {
+                        state.address = Some(addr.to_string());
+                    } else {
+                        warn!(%addr, "Could not move source to its new address");
+                    }
+                }
             }
         }
         Ok(())
     }
 
-    async fn add_server(&mut self, config: ServerConfig) {
+    /// Let a spawner know its source was trimmed, it gets room for the
+    /// source again later
+    #[cfg(feature = "client")]
+    async fn trim_source(&mut self, spawner_id: SpawnerId, source_id: ClockId) {
+        self.client.trimmed.push_back(spawner_id);
+        if let Some(spawner) = self.client.spawners.iter().find(|s| s.id == spawner_id) {
+            let _ = spawner
+                .notify_tx
+                .send(SystemEvent::source_removed(
+                    source_id,
+                    SourceRemovalReason::Trimmed,
+                ))
+                .await;
+        }
+    }
+
+    #[cfg(feature = "server")]
+    async fn add_server(
+        &mut self,
+        config: ServerConfig,
+        keyset: &tokio::sync::watch::Receiver<Arc<KeySet>>,
+    ) {
         let stats = ServerStats::default();
         self.servers.push(ServerData {
             stats: stats.clone(),
             config: config.clone(),
         });
-        let server = self.ntp_manager.new_server(
-            config.clone().into(),
-            self.clock.clone(),
-            self.keyset.borrow().clone(),
-        );
-        ServerTask::spawn(
-            server,
-            config,
-            stats,
-            self.keyset.clone(),
-            NETWORK_WAIT_PERIOD,
-        );
+
+        // Workers share the listen address through SO_REUSEPORT, which is
+        // only used on Linux. The kernel sends the requests from an address
+        // and port to the same worker, which rate limits them on its own.
+        let workers = if cfg!(target_os = "linux") {
+            config.workers.get()
+        } else {
+            1
+        };
+        for worker in 0..workers {
+            let mut config = config.clone();
+            // Broadcasts are only sent once
+            if worker > 0 {
+                config.broadcast.clear();
+            }
+
+            let mut server = self.ntp_manager.new_server(
+                config.clone().into(),
+                self.clock.clone(),
+                keyset.borrow().clone(),
+            );
+            server.update_symmetric_keys(self.symmetric_keys.clone());
+            ServerTask::spawn(
+                server,
+                self.clock.clone(),
+                config,
+                stats.clone(),
+                keyset.clone(),
+                NETWORK_WAIT_PERIOD,
+            );
+        }
         let _ = self.server_data_sender.send(self.servers.clone());
     }
 }
 
+#[cfg(feature = "client")]
 #[derive(Debug)]
 struct SourceState {
     spawner_id: SpawnerId,
     source_id: ClockId,
     stype: SourceType,
+    task: tokio::task::AbortHandle,
+    msg_for_source_sender: Option<mpsc::Sender<MsgForSource>>,
+    address: Option<String>,
+    created: tokio::time::Instant,
 }
 
 #[derive(Debug, Clone)]
@@ -544,3 +1474,382 @@ pub struct ServerData {
     pub stats: ServerStats,
     pub config: ServerConfig,
 }
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use ntp_proto::{
+        Measurement, NtpLeapIndicator, NtpTimestamp, ObservableSourceTimedata, PollInterval,
+        ProtocolVersion, ReferenceId, TimeSnapshot,
+    };
+    use serde::Deserialize;
+
+    use crate::daemon::{config::NormalizedAddress, spawn::PhcSourceCreateParameters};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            self.now()
+        }
+
+        fn get_frequency(&self) -> Result<f64, Self::Error> {
+            Ok(0.0)
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by the system");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    struct TestSourceController;
+
+    impl SourceController for TestSourceController {
+        fn handle_measurement(&mut self, _measurement: Measurement) {}
+
+        fn set_usable(&mut self, _usable: bool) {}
+
+        fn desired_poll_interval(&self) -> PollInterval {
+            PollInterval::default()
+        }
+
+        fn observe(&self) -> ObservableSourceTimedata {
+            ObservableSourceTimedata::default()
+        }
+    }
+
+    /// Controller that uses the sources a test tells it to use
+    struct TestController {
+        used_sources: Mutex<Vec<ClockId>>,
+    }
+
+    impl TimeSyncController for TestController {
+        type Clock = TestClock;
+        type AlgorithmConfig = ();
+        type NtpSourceController = TestSourceController;
+        type OneWaySourceController = TestSourceController;
+
+        fn new(
+            _clock: TestClock,
+            _synchronization_config: SynchronizationConfig,
+            _algorithm_config: (),
+        ) -> Result<Self, std::io::Error> {
+            Ok(TestController {
+                used_sources: Mutex::new(vec![]),
+            })
+        }
+
+        fn take_control(&self) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn add_source(&self, _id: ClockId, _source_config: SourceConfig) -> TestSourceController {
+            TestSourceController
+        }
+
+        fn add_one_way_source(
+            &self,
+            _id: ClockId,
+            _source_config: SourceConfig,
+            _measurement_noise_estimate: f64,
+            _measurement_accuracy_estimate: f64,
+            _period: Option<f64>,
+        ) -> TestSourceController {
+            TestSourceController
+        }
+
+        fn synchronization_state(&self) -> (TimeSnapshot, Vec<ClockId>) {
+            (
+                TimeSnapshot::default(),
+                self.used_sources.lock().unwrap().clone(),
+            )
+        }
+
+        async fn run(&self) {}
+    }
+
+    fn test_system() -> SystemTask<TestClock, TestController> {
+        let (_, ip_list) = tokio::sync::watch::channel(Arc::<[IpAddr]>::from([]));
+        let (system, _) = SystemTask::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            (),
+            ip_list,
+            false,
+        );
+        system
+    }
+
+    const REFERENCE_CLOCK: SourceType = SourceType::External {
+        stratum: 1,
+        reference_id: ReferenceId::PHC,
+    };
+
+    /// A spawner of NTP sources that only records what the system tells it
+    fn add_test_spawner(
+        system: &mut SystemTask<TestClock, TestController>,
+    ) -> (SpawnerId, mpsc::Receiver<SystemEvent>) {
+        add_test_spawner_of(system, SourceType::Ntp)
+    }
+
+    fn add_test_spawner_of(
+        system: &mut SystemTask<TestClock, TestController>,
+        stype: SourceType,
+    ) -> (SpawnerId, mpsc::Receiver<SystemEvent>) {
+        let id = SpawnerId::new();
+        let (notify_tx, notify_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        system.client.spawners.push(SystemSpawnerData {
+            id,
+            stype,
+            notify_tx,
+        });
+        (id, notify_rx)
+    }
+
+    /// A running source, which is an NTP source or a reference clock
+    fn add_test_source(
+        system: &mut SystemTask<TestClock, TestController>,
+        spawner_id: SpawnerId,
+        ntp: bool,
+    ) -> ClockId {
+        let source_id = ClockId::new();
+        let stype = if ntp {
+            SourceType::Ntp
+        } else {
+            REFERENCE_CLOCK
+        };
+        let task = tokio::spawn(std::future::pending::<()>());
+        system.client.sources.lock().unwrap().insert(
+            source_id,
+            SourceState {
+                spawner_id,
+                source_id,
+                stype,
+                task: task.abort_handle(),
+                msg_for_source_sender: ntp.then(|| mpsc::channel(1).0),
+                address: None,
+                created: tokio::time::Instant::now(),
+            },
+        );
+        source_id
+    }
+
+    fn assert_trimmed(notify_rx: &mut mpsc::Receiver<SystemEvent>, source_id: ClockId) {
+        let Ok(SystemEvent::SourceRemoved(event)) = notify_rx.try_recv() else {
+            panic!("Expected the spawner to be told its source was removed");
+        };
+        assert_eq!(event.id, source_id);
+        assert_eq!(event.reason, SourceRemovalReason::Trimmed);
+    }
+
+    #[tokio::test]
+    async fn test_create_over_limit_is_trimmed() {
+        let mut system = test_system();
+        system.client.max_sources = Some(1);
+        let (spawner_id, mut notify_rx) = add_test_spawner(&mut system);
+        add_test_source(&mut system, spawner_id, true);
+
+        let action = SpawnAction::create_ntp(
+            ClockId::new(),
+            "127.0.0.1:123".parse().unwrap(),
+            NormalizedAddress::with_hardcoded_dns(
+                "example.com",
+                123,
+                vec!["127.0.0.1:123".parse().unwrap()],
+            ),
+            ProtocolVersion::V4,
+            SourceConfig::default(),
+            None,
+            None,
+        );
+        let SpawnAction::Create(params) = &action else {
+            unreachable!();
+        };
+        let source_id = params.get_id();
+        system
+            .handle_spawn_event(SpawnEvent::new(spawner_id, action))
+            .await
+            .unwrap();
+
+        assert_eq!(system.client.sources.lock().unwrap().len(), 1);
+        assert!(
+            !system
+                .client
+                .sources
+                .lock()
+                .unwrap()
+                .contains_key(&source_id)
+        );
+        assert_eq!(system.client.trimmed, [spawner_id]);
+        assert_trimmed(&mut notify_rx, source_id);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reference_clocks_are_not_trimmed() {
+        let mut system = test_system();
+        system.client.max_sources = Some(1);
+        let (spawner_id, _notify_rx) = add_test_spawner(&mut system);
+        let (trimmed_id, _trimmed_rx) = add_test_spawner(&mut system);
+        let (reference_spawner, _reference_rx) = add_test_spawner_of(&mut system, REFERENCE_CLOCK);
+        let source = add_test_source(&mut system, spawner_id, true);
+
+        // Reference clocks are welcome even when there is no room
+        let params = SourceCreateParameters::Phc(PhcSourceCreateParameters {
+            id: ClockId::new(),
+            path: "/dev/ptp-does-not-exist".into(),
+            poll_interval: PollInterval::default(),
+            config: SourceConfig::default(),
+            precision: 1e-9,
+            accuracy: 0.0,
+        });
+        let reference_clock = params.get_id();
+        system
+            .handle_spawn_event(SpawnEvent::new(
+                reference_spawner,
+                SpawnAction::Create(params),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(system.client.sources.lock().unwrap().len(), 2);
+        assert!(system.client.trimmed.is_empty());
+
+        // And they never make way for trimmed sources, even when not in use
+        system.client.trimmed.push_back(trimmed_id);
+        tokio::time::advance(TRIM_GRACE_PERIOD).await;
+        assert_eq!(
+            system.remove_worst_source().map(|state| state.source_id),
+            Some(source)
+        );
+        assert!(system.remove_worst_source().is_none());
+        let sources = system.client.sources.lock().unwrap();
+        assert!(sources.contains_key(&reference_clock));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unused_source_makes_way_after_grace_period() {
+        let mut system = test_system();
+        system.client.max_sources = Some(3);
+        let (spawner_id, mut notify_rx) = add_test_spawner(&mut system);
+        let (trimmed_id, mut trimmed_rx) = add_test_spawner(&mut system);
+        let used = add_test_source(&mut system, spawner_id, true);
+        let unused = add_test_source(&mut system, spawner_id, true);
+        let reference_clock = add_test_source(&mut system, spawner_id, false);
+        *system.controller.used_sources.lock().unwrap() = vec![used];
+        system.client.trimmed.push_back(trimmed_id);
+
+        // New sources get time to be selected first
+        system.update_trimmed_sources().await;
+        assert_eq!(system.client.sources.lock().unwrap().len(), 3);
+        assert!(trimmed_rx.try_recv().is_err());
+
+        tokio::time::advance(TRIM_GRACE_PERIOD).await;
+        system.update_trimmed_sources().await;
+        {
+            let sources = system.client.sources.lock().unwrap();
+            assert!(sources.contains_key(&used));
+            assert!(!sources.contains_key(&unused));
+            assert!(sources.contains_key(&reference_clock));
+        }
+        assert!(matches!(
+            trimmed_rx.try_recv(),
+            Ok(SystemEvent::CapacityAvailable)
+        ));
+
+        // The spawner of the removed source now waits for its turn
+        assert_trimmed(&mut notify_rx, unused);
+        assert_eq!(system.client.trimmed, [spawner_id]);
+    }
+
+    #[derive(Deserialize)]
+    struct TestConfig {
+        source: NtpSourceConfig,
+    }
+
+    #[tokio::test]
+    async fn test_failover_groups() {
+        let mut system = test_system();
+        system.client.failover_threshold = 2;
+        let (spawner_id, _notify_rx) = add_test_spawner(&mut system);
+        let first = add_test_source(&mut system, spawner_id, true);
+        let second = add_test_source(&mut system, spawner_id, true);
+
+        let standby: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "127.0.0.1"
+                priority = 1
+            "#,
+        )
+        .unwrap();
+        system.add_source(&standby.source).unwrap();
+
+        // Enough sources in use in the first group
+        *system.controller.used_sources.lock().unwrap() = vec![first, second];
+        system.update_failover_groups();
+        assert_eq!(system.client.standby_sources[0].spawner, None);
+        assert_eq!(system.client.spawners.len(), 1);
+
+        // Too few, so the second group is needed
+        *system.controller.used_sources.lock().unwrap() = vec![first];
+        system.update_failover_groups();
+        let mobilized = system.client.standby_sources[0].spawner.unwrap();
+        assert!(
+            system
+                .client
+                .spawners
+                .iter()
+                .any(|spawner| spawner.id == mobilized)
+        );
+
+        // Its sources count towards the threshold, keeping it mobilized
+        let standby_source = add_test_source(&mut system, mobilized, true);
+        *system.controller.used_sources.lock().unwrap() = vec![first, standby_source];
+        system.update_failover_groups();
+        assert_eq!(system.client.standby_sources[0].spawner, Some(mobilized));
+
+        // Once the first group suffices again, the second returns to standby
+        *system.controller.used_sources.lock().unwrap() = vec![first, second, standby_source];
+        system.update_failover_groups();
+        assert_eq!(system.client.standby_sources[0].spawner, None);
+        assert!(
+            !system
+                .client
+                .spawners
+                .iter()
+                .any(|spawner| spawner.id == mobilized)
+        );
+        assert!(
+            !system
+                .client
+                .sources
+                .lock()
+                .unwrap()
+                .contains_key(&standby_source)
+        );
+    }
+}
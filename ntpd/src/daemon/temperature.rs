@@ -0,0 +1,183 @@
+//! Temperature input for compensating the frequency of the clock. Readings
+//! are taken from a file, such as a Linux hwmon sensor, at a fixed interval,
+//! or received on a Unix datagram socket, and passed on to the clock
+//! controller.
+
+use std::{
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use ntp_proto::TimeSyncController;
+use tokio::net::UnixDatagram;
+use tracing::{Instrument, Span, debug, instrument, warn};
+
+use super::{
+    config::{TemperatureConfig, TemperatureSensor},
+    util::create_socket,
+};
+
+// Readings outside this range (in degrees Celsius) are taken to be sensor errors
+const PLAUSIBLE_TEMPERATURE: RangeInclusive<f64> = -100.0..=200.0;
+// hwmon reports temperatures in millidegrees
+const HWMON_SCALE: f64 = 0.001;
+// Longer datagrams are not a single reading
+const MAX_DATAGRAM_SIZE: usize = 64;
+
+fn parse_reading(data: &str, scale: f64) -> Option<f64> {
+    let temperature = data.trim().parse::<f64>().ok()? * scale;
+    PLAUSIBLE_TEMPERATURE
+        .contains(&temperature)
+        .then_some(temperature)
+}
+
+/// Start passing the readings of the configured sensor to `controller`
+#[instrument(level = tracing::Level::ERROR, name = "Temperature sensor", skip_all)]
+pub(crate) fn spawn<Controller: TimeSyncController>(
+    config: TemperatureConfig,
+    controller: Arc<Controller>,
+) -> std::io::Result<()> {
+    let update = move |temperature| controller.temperature_update(temperature);
+    let interval = Duration::from_secs(config.interval.get());
+
+    match config.sensor {
+        TemperatureSensor::File(path) => {
+            tokio::spawn(read_file(path, 1.0, interval, update).instrument(Span::current()));
+        }
+        TemperatureSensor::Hwmon(path) => {
+            tokio::spawn(
+                read_file(path, HWMON_SCALE, interval, update).instrument(Span::current()),
+            );
+        }
+        TemperatureSensor::Socket(path) => {
+            let socket = create_socket(&path)?;
+            tokio::spawn(receive(socket, update).instrument(Span::current()));
+        }
+    }
+
+    Ok(())
+}
+
+async fn read_file(
+    path: PathBuf,
+    scale: f64,
+    interval: Duration,
+    update: impl Fn(f64) + Send + 'static,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Some(temperature) = read_reading(&path, scale).await {
+            debug!(temperature, "Read temperature");
+            update(temperature);
+        }
+    }
+}
+
+async fn read_reading(path: &Path, scale: f64) -> Option<f64> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(data) => {
+            let temperature = parse_reading(&data, scale);
+            if temperature.is_none() {
+                warn!(?path, "Could not parse temperature reading");
+            }
+            temperature
+        }
+        Err(error) => {
+            warn!(?error, ?path, "Could not read temperature sensor");
+            None
+        }
+    }
+}
+
+async fn receive(socket: UnixDatagram, update: impl Fn(f64) + Send + 'static) {
+    // One byte extra, so we can detect datagrams that are too large
+    let mut buf = [0; MAX_DATAGRAM_SIZE + 1];
+
+    loop {
+        let size = match socket.recv(&mut buf).await {
+            Ok(size) => size,
+            Err(error) => {
+                warn!(?error, "Could not receive temperature reading");
+                continue;
+            }
+        };
+
+        let Some(temperature) = std::str::from_utf8(&buf[..size.min(MAX_DATAGRAM_SIZE)])
+            .ok()
+            .filter(|_| size <= MAX_DATAGRAM_SIZE)
+            .and_then(|data| parse_reading(data, 1.0))
+        else {
+            warn!("Received invalid temperature reading");
+            continue;
+        };
+
+        debug!(temperature, "Received temperature");
+        update(temperature);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::test::alloc_port;
+
+    #[test]
+    fn test_parse_reading() {
+        assert_eq!(parse_reading("21.5\n", 1.0), Some(21.5));
+        assert_eq!(parse_reading("42000\n", HWMON_SCALE), Some(42.0));
+        assert_eq!(parse_reading("-5", 1.0), Some(-5.0));
+        assert_eq!(parse_reading("42000", 1.0), None);
+        assert_eq!(parse_reading("warm", 1.0), None);
+        assert_eq!(parse_reading("", 1.0), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_file() {
+        let path = std::env::temp_dir().join(format!("ntp-test-temperature-{}", alloc_port()));
+        std::fs::write(&path, "35250\n").unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(read_file(
+            path.clone(),
+            HWMON_SCALE,
+            Duration::from_secs(60),
+            move |temperature| sender.send(temperature).unwrap(),
+        ));
+
+        assert_eq!(receiver.recv().await, Some(35.25));
+
+        task.abort();
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_receive() {
+        let path = std::env::temp_dir().join(format!("ntp-test-temperature-{}", alloc_port()));
+        let socket = create_socket(&path).unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(receive(socket, move |temperature| {
+            sender.send(temperature).unwrap();
+        }));
+
+        let client = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        client.send_to(b"not a temperature", &path).unwrap();
+        client
+            .send_to(&[b'1'; MAX_DATAGRAM_SIZE + 1], &path)
+            .unwrap();
+        client.send_to(b"18.75\n", &path).unwrap();
+
+        // Invalid readings are skipped
+        assert_eq!(receiver.recv().await, Some(18.75));
+
+        task.abort();
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -140,7 +140,7 @@ pub fn tracing_init(
             Ok(writer) => writer,
             Err(e) => {
                 tracing::error!("Could not open logfile {}, exiting: {e}", path.display());
-                std::process::exit(70);
+                crate::exitcode::exit(crate::exitcode::CANTCREAT);
             }
         };
         (
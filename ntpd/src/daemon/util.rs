@@ -1,8 +1,18 @@
+use std::{
+    fs::{File, OpenOptions},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
+use clock_steering::unix::UnixClock;
 use ntp_proto::NtpTimestamp;
+use tokio::net::UnixDatagram;
+use tracing::debug;
 
 // Epoch offset between NTP and UNIX timescales
 pub(crate) const EPOCH_OFFSET: u32 = (70 * 365 + 17) * 86400;
 
+#[cfg(feature = "client")]
 pub(crate) fn convert_net_timestamp(ts: timestamped_socket::socket::Timestamp) -> NtpTimestamp {
     NtpTimestamp::from_seconds_nanos_since_ntp_era(
         EPOCH_OFFSET.wrapping_add(ts.seconds as _),
@@ -10,6 +20,14 @@ pub(crate) fn convert_net_timestamp(ts: timestamped_socket::socket::Timestamp) -
     )
 }
 
+#[cfg(all(feature = "server", target_os = "linux"))]
+pub(crate) fn convert_batch_timestamp(ts: ntp_mmsg::Timestamp) -> NtpTimestamp {
+    NtpTimestamp::from_seconds_nanos_since_ntp_era(
+        EPOCH_OFFSET.wrapping_add(ts.seconds as _),
+        ts.nanos,
+    )
+}
+
 pub(crate) fn convert_clock_timestamp(ts: clock_steering::Timestamp) -> NtpTimestamp {
     NtpTimestamp::from_seconds_nanos_since_ntp_era(
         EPOCH_OFFSET.wrapping_add(ts.seconds as _),
@@ -17,6 +35,108 @@ pub(crate) fn convert_clock_timestamp(ts: clock_steering::Timestamp) -> NtpTimes
     )
 }
 
+#[cfg(feature = "client")]
 pub(crate) fn convert_unix_timestamp(seconds: u64, nanos: u32) -> NtpTimestamp {
     NtpTimestamp::from_seconds_nanos_since_ntp_era(EPOCH_OFFSET.wrapping_add(seconds as _), nanos)
 }
+
+#[cfg(target_os = "linux")]
+pub(crate) fn open_clock(path: &Path) -> Result<UnixClock, String> {
+    UnixClock::open(path).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn open_clock(_path: &Path) -> Result<UnixClock, String> {
+    Err("PTP hardware clocks are only supported on Linux".into())
+}
+
+/// Read the system clock before and after reading the PHC, for the precise
+/// ioctl these are the same
+#[cfg(target_os = "linux")]
+pub(crate) fn read_offset(
+    clock: &UnixClock,
+) -> Result<(NtpTimestamp, NtpTimestamp, NtpTimestamp), String> {
+    let (before, phc, after) = clock.system_offset().map_err(|e| e.to_string())?;
+    Ok((
+        convert_clock_timestamp(before),
+        convert_clock_timestamp(phc),
+        convert_clock_timestamp(after),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn read_offset(
+    _clock: &UnixClock,
+) -> Result<(NtpTimestamp, NtpTimestamp, NtpTimestamp), String> {
+    Err("PTP hardware clocks are only supported on Linux".into())
+}
+
+pub(crate) fn create_socket<T: AsRef<Path>>(path: T) -> std::io::Result<UnixDatagram> {
+    let path = path.as_ref();
+    if path.exists() {
+        debug!("Removing previous socket file");
+        std::fs::remove_file(path)?;
+    }
+    debug!("Creating socket at {:?}", path);
+    let socket = UnixDatagram::bind(path)?;
+    Ok(socket)
+}
+
+/// Replace the file at `path` with what `write` writes, through a temporary
+/// file that is moved in place once complete. The file and its directory are
+/// synced, so neither a crash nor a power loss can leave a truncated file
+/// behind. A newly created file gets permissions `mode`.
+pub(crate) fn write_atomically(
+    path: &Path,
+    mode: u32,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(mode)
+        .open(&tmp_path)?;
+    write(&mut file)?;
+    file.sync_all()?;
+    std::fs::rename(tmp_path, path)?;
+
+    // The rename itself is only durable once the directory is synced
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::test::alloc_port;
+
+    #[test]
+    fn test_write_atomically() {
+        let path = std::env::temp_dir().join(format!("ntp-test-atomic-{}", alloc_port()));
+
+        write_atomically(&path, 0o600, |file| file.write_all(b"first")).unwrap();
+        write_atomically(&path, 0o600, |file| file.write_all(b"second")).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // A failed write leaves the previous contents in place
+        let error = write_atomically(&path, 0o600, |_| Err(std::io::Error::other("failed")));
+        assert!(error.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::remove_file(tmp_path).ok();
+        std::fs::remove_file(path).ok();
+    }
+}
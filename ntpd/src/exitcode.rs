@@ -0,0 +1,119 @@
+//! Exit codes used by `ntp-daemon`, `ntp-ctl` and `ntp-metrics-exporter`.
+//!
+//! These values follow the BSD `sysexits.h` conventions and are part of the
+//! stable interface of ntpd-rs: supervisors and scripts may rely on them to
+//! distinguish between categories of failures. Each code also has a short
+//! machine-readable identifier that is included in the log message emitted
+//! when the daemon terminates. The codes and identifiers are documented in
+//! ntp-daemon(8) and ntp-ctl(8); any change here must be reflected there.
+
+/// Successful termination.
+pub const OK: i32 = 0;
+
+/// Unspecified failure, used when no more specific code applies.
+pub const FAILURE: i32 = 1;
+
+/// The command was used incorrectly, e.g. with the wrong number of
+/// arguments, a bad flag or bad syntax in a parameter.
+pub const USAGE: i32 = 64;
+
+/// A service required for the operation is unavailable. This is used
+/// for network resources that cannot be reached and for an ntp-daemon
+/// that cannot be contacted over its observation socket.
+pub const UNAVAILABLE: i32 = 69;
+
+/// An internal software error has been detected.  This
+/// should be limited to non-operating system related
+/// errors as possible.
+pub const SOFTWARE: i32 = 70;
+
+/// A (user specified) output file cannot be created.
+pub const CANTCREAT: i32 = 73;
+
+/// The remote system returned something that was
+/// "not possible" during a protocol exchange.
+pub const PROTOCOL: i32 = 76;
+
+/// You did not have sufficient permission to perform
+/// the operation.  This is not intended for file system
+/// problems, which should use `NOINPUT` or `CANTCREAT`,
+/// but rather for higher level permissions.
+pub const NOPERM: i32 = 77;
+
+/// Something was found in an unconfigured or misconfigured state.
+pub const CONFIG: i32 = 78;
+
+/// The clock is further off than the configured panic thresholds allow, and
+/// the daemon refuses to correct it. This has no `sysexits.h` counterpart.
+pub const PANIC: i32 = 79;
+
+/// Machine-readable identifier for an exit code.
+#[must_use]
+pub fn identifier(code: i32) -> &'static str {
+    match code {
+        OK => "OK",
+        USAGE => "USAGE",
+        UNAVAILABLE => "UNAVAILABLE",
+        SOFTWARE => "SOFTWARE",
+        CANTCREAT => "CANTCREAT",
+        PROTOCOL => "PROTOCOL",
+        NOPERM => "NOPERM",
+        CONFIG => "CONFIG",
+        PANIC => "PANIC",
+        _ => "FAILURE",
+    }
+}
+
+/// Convert one of the codes above into an exit code that can be returned from `main`.
+#[must_use]
+pub fn to_exit_code(code: i32) -> std::process::ExitCode {
+    u8::try_from(code).map_or(
+        std::process::ExitCode::FAILURE,
+        std::process::ExitCode::from,
+    )
+}
+
+/// Terminate the process with the given exit code, logging its identifier
+/// so the reason for termination can be found in the logs.
+pub(crate) fn exit(code: i32) -> ! {
+    tracing::error!(
+        exit_code = code,
+        reason = identifier(code),
+        "ntpd-rs is terminating"
+    );
+    std::process::exit(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers_are_unique() {
+        let codes = [
+            OK,
+            FAILURE,
+            USAGE,
+            UNAVAILABLE,
+            SOFTWARE,
+            CANTCREAT,
+            PROTOCOL,
+            NOPERM,
+            CONFIG,
+            PANIC,
+        ];
+
+        for (i, a) in codes.iter().enumerate() {
+            for b in &codes[i + 1..] {
+                assert_ne!(a, b);
+                assert_ne!(identifier(*a), identifier(*b));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_code_is_failure() {
+        assert_eq!(identifier(FAILURE), "FAILURE");
+        assert_eq!(identifier(42), "FAILURE");
+    }
+}
@@ -134,7 +134,14 @@ pub(crate) fn force_sync(config: Option<&Path>) -> std::io::Result<ExitCode> {
                 match source {
                     config::NtpSourceConfig::Standard(_)
                     | config::NtpSourceConfig::Nts(_)
-                    | config::NtpSourceConfig::Sock(_) => total_sources += 1,
+                    | config::NtpSourceConfig::Sock(_)
+                    | config::NtpSourceConfig::Broadcast(_)
+                    | config::NtpSourceConfig::Roughtime(_)
+                    | config::NtpSourceConfig::Nmea(_)
+                    | config::NtpSourceConfig::Gpsd(_)
+                    | config::NtpSourceConfig::Phc(_)
+                    | config::NtpSourceConfig::Ptp(_)
+                    | config::NtpSourceConfig::Shm(_) => total_sources += 1,
                     #[cfg(feature = "pps")]
                     config::NtpSourceConfig::Pps(_) => {} // PPS sources don't count
                     config::NtpSourceConfig::Pool(cfg) => total_sources += cfg.first.count,
@@ -144,6 +151,7 @@ pub(crate) fn force_sync(config: Option<&Path>) -> std::io::Result<ExitCode> {
 
             // We will need to have a keyset for the daemon
             let keyset = nts_key_provider::spawn(config.keyset).await;
+            let symmetric_keys = config.legacy_auth.load_keys(&config.symmetric_keys)?;
 
             #[cfg(feature = "hardware-timestamping")]
             let clock_config = config.clock;
@@ -157,11 +165,16 @@ pub(crate) fn force_sync(config: Option<&Path>) -> std::io::Result<ExitCode> {
                 SingleShotControllerConfig {
                     expected_sources: total_sources,
                 },
+                config.synchronization.source_options(),
+                None, // The clock is only set once, so its temperature is irrelevant
+                None, // Nor is there a frequency estimate to persist
+                None, // Or an algorithm state to save
                 config.source_defaults,
                 clock_config,
                 &config.sources,
                 &[], // No serving when operating in force sync mode
                 keyset.clone(),
+                symmetric_keys,
             )
             .await?;
 
@@ -134,6 +134,7 @@
 
 mod ctl;
 mod daemon;
+pub mod exitcode;
 mod force_sync;
 mod metrics;
 mod notify;
@@ -142,7 +142,7 @@ fn run(options: &NtpMetricsExporterOptions) -> Result<(), Box<dyn std::error::Er
 
         let Some(observation_socket_path) = config.observability.observation_path.map(Arc::new) else {
             eprintln!("An observation socket path must be configured using the observation-path option in the [observability] section of the configuration");
-            std::process::exit(1);
+            std::process::exit(crate::exitcode::CONFIG);
         };
 
         println!(
@@ -1,6 +1,8 @@
 pub mod exporter;
 
-use ntp_proto::{NtpDuration, PollIntervalLimits};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ntp_proto::{AeadAlgorithm, NtpDuration, PollIntervalLimits};
 
 use crate::daemon::ObservableState;
 
@@ -139,6 +141,18 @@ macro_rules! collect_servers {
     }};
 }
 
+macro_rules! collect_key_exchange_servers {
+    ($from: expr, |$ident: ident| $value: expr $(,)?) => {{
+        let mut data = vec![];
+        for $ident in &$from.key_exchange_servers {
+            let labels = vec![("listen_address", format!("{}", $ident.address))];
+            let value = $value;
+            data.push(Measurement { labels, value })
+        }
+        data
+    }};
+}
+
 // Allow this function to be oversized as it is otherwise straightforward
 // and has no reasonable way to be split.
 #[expect(clippy::too_many_lines)]
@@ -210,6 +224,21 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         Measurement::simple(state.system.time_snapshot.leap_indicator as i64),
     )?;
 
+    format_metric(
+        w,
+        "ntp_system_leap_smear_offset",
+        "Part of the leap second currently smeared into our clock",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        Measurement::simple(
+            state
+                .system
+                .time_snapshot
+                .leap_smear
+                .map_or(0.0, |smear| smear.offset.to_seconds()),
+        ),
+    )?;
+
     format_metric(
         w,
         "ntp_system_root_delay",
@@ -261,6 +290,77 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_sources!(state, |p| p.unanswered_polls),
     )?;
 
+    format_metric(
+        w,
+        "ntp_source_reach",
+        "Reachability register of the source, the lowest bit is the most recent poll",
+        &MetricType::Gauge,
+        None,
+        collect_some_sources!(state, |p| p.reach),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_target_poll_interval",
+        "Poll interval the next request to the source will be sent with",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_some_sources!(state, |p| p
+            .target_poll_interval
+            .map(|interval| interval.as_duration().to_seconds())),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_last_kiss_code",
+        "Code of the last kiss-o'-death packet received from the source",
+        &MetricType::Gauge,
+        None,
+        state
+            .sources
+            .iter()
+            .filter_map(|p| {
+                let code = p.last_kiss_code.clone()?;
+                Some(Measurement {
+                    labels: vec![
+                        ("name", p.name.clone()),
+                        ("address", p.address.clone()),
+                        ("id", format!("{}", p.id)),
+                        ("code", code),
+                    ],
+                    value: 1,
+                })
+            })
+            .collect(),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_authentication_failures_total",
+        "Number of responses from the source that failed NTS or MAC authentication",
+        &MetricType::Counter,
+        None,
+        collect_sources!(state, |p| p.authentication_failures),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_nts_degraded",
+        "Whether the source is used without authentication because its NTS key exchange failed",
+        &MetricType::Gauge,
+        None,
+        collect_sources!(state, |p| u8::from(p.nts_degraded)),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_ntp_version",
+        "NTP version currently used to communicate with the source",
+        &MetricType::Gauge,
+        None,
+        collect_some_sources!(state, |p| p.ntp_version),
+    )?;
+
     format_metric(
         w,
         "ntp_source_nts_cookies_available",
@@ -315,6 +415,112 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_sources!(state, |p| p.timedata.remote_uncertainty.to_seconds()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_source_root_distance",
+        "Estimated maximum error of the source with respect to the root time source",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_sources!(state, |p| p.timedata.root_distance().to_seconds()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_frequency",
+        "Frequency difference between the upstream source and system clock estimated by the kalman filter",
+        &MetricType::Gauge,
+        None,
+        collect_some_sources!(state, |p| p.timedata.kalman.map(|k| k.state[1])),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_frequency_uncertainty",
+        "Estimated error of the frequency difference with the upstream source",
+        &MetricType::Gauge,
+        None,
+        collect_some_sources!(state, |p| p
+            .timedata
+            .kalman
+            .map(|k| k.covariance[1][1].sqrt())),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_offset_frequency_covariance",
+        "Covariance between the offset and frequency estimates of the kalman filter",
+        &MetricType::Gauge,
+        None,
+        collect_some_sources!(state, |p| p.timedata.kalman.map(|k| k.covariance[0][1])),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_measurement_noise",
+        "Estimated standard deviation of the offset measurements of the upstream source",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_some_sources!(state, |p| p
+            .timedata
+            .kalman
+            .map(|k| k.measurement_noise.sqrt())),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_wander",
+        "Estimated rate at which the frequency variance of the upstream source grows",
+        &MetricType::Gauge,
+        None,
+        collect_some_sources!(state, |p| p.timedata.kalman.map(|k| k.wander)),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_rejected_outliers_total",
+        "Number of measurements of the upstream source rejected as outliers",
+        &MetricType::Counter,
+        None,
+        collect_some_sources!(state, |p| p.timedata.kalman.map(|k| k.rejected_outliers)),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_frequency_steps_total",
+        "Number of sudden changes in frequency detected for the upstream source",
+        &MetricType::Counter,
+        None,
+        collect_some_sources!(state, |p| p.timedata.kalman.map(|k| k.frequency_steps)),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_wander_poll_interval",
+        "Poll interval suggested by the wander and measurement noise of the upstream source",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_some_sources!(state, |p| p
+            .timedata
+            .kalman
+            .and_then(|k| k.wander_poll_interval)),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_retry_delay",
+        "Time until a source that failed is tried again",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        state
+            .spawners
+            .iter()
+            .map(|s| Measurement {
+                labels: vec![("kind", s.kind.clone()), ("address", s.address.clone())],
+                value: s.retry_in,
+            })
+            .collect(),
+    )?;
+
     format_metric(
         w,
         "ntp_server_received_packets_total",
@@ -414,6 +620,137 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_servers!(state, |s| s.stats.nts_nak_packets.get()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_server_authentication_failures_total",
+        "Number of requests with an invalid NTS cookie, authenticator or MAC",
+        &MetricType::Counter,
+        None,
+        collect_servers!(state, |s| s.stats.authentication_failures.get()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_server_broadcast_packets_total",
+        "Number of broadcast packets sent",
+        &MetricType::Counter,
+        None,
+        collect_servers!(state, |s| s.stats.broadcast_packets.get()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_nts_ke_accepted_connections_total",
+        "Number of connections accepted by the NTS key exchange server",
+        &MetricType::Counter,
+        None,
+        collect_key_exchange_servers!(state, |s| s.stats.accepted_connections.get()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_nts_ke_rejected_handshakes_total",
+        "Number of NTS key exchange connections closed before the handshake",
+        &MetricType::Counter,
+        None,
+        state
+            .key_exchange_servers
+            .iter()
+            .flat_map(|s| {
+                [
+                    (
+                        "connection_limit",
+                        s.stats.connection_limited_connections.get(),
+                    ),
+                    ("rate_limit", s.stats.rate_limited_connections.get()),
+                ]
+                .map(|(reason, value)| Measurement {
+                    labels: vec![
+                        ("listen_address", format!("{}", s.address)),
+                        ("reason", reason.to_owned()),
+                    ],
+                    value,
+                })
+            })
+            .collect(),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_nts_ke_handshakes_total",
+        "Number of NTS key exchanges that completed or failed",
+        &MetricType::Counter,
+        None,
+        state
+            .key_exchange_servers
+            .iter()
+            .flat_map(|s| {
+                [
+                    ("completed", s.stats.completed_handshakes.get()),
+                    ("failed", s.stats.failed_handshakes.get()),
+                ]
+                .map(|(result, value)| Measurement {
+                    labels: vec![
+                        ("listen_address", format!("{}", s.address)),
+                        ("result", result.to_owned()),
+                    ],
+                    value,
+                })
+            })
+            .collect(),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_nts_ke_cookies_issued_total",
+        "Number of cookies handed out by the NTS key exchange server",
+        &MetricType::Counter,
+        None,
+        collect_key_exchange_servers!(state, |s| s.stats.cookies_issued.get()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_nts_ke_algorithm_usage_total",
+        "Number of NTS key exchanges per negotiated AEAD algorithm",
+        &MetricType::Counter,
+        None,
+        state
+            .key_exchange_servers
+            .iter()
+            .flat_map(|s| {
+                AeadAlgorithm::SUPPORTED.map(|algorithm| Measurement {
+                    labels: vec![
+                        ("listen_address", format!("{}", s.address)),
+                        ("algorithm", algorithm.to_string()),
+                    ],
+                    value: s.stats.algorithms.get(algorithm),
+                })
+            })
+            .collect(),
+    )?;
+
+    let now = SystemTime::now();
+    format_metric(
+        w,
+        "ntp_nts_ke_master_key_age",
+        "Time since the master key used for new NTS cookies came into use",
+        &MetricType::Gauge,
+        Some(Unit::Seconds),
+        state
+            .key_exchange_servers
+            .iter()
+            .filter(|s| s.stats.master_key_created.get() != 0)
+            .map(|s| {
+                let created = UNIX_EPOCH + Duration::from_secs(s.stats.master_key_created.get());
+                Measurement {
+                    labels: vec![("listen_address", format!("{}", s.address))],
+                    value: now.duration_since(created).unwrap_or_default().as_secs(),
+                }
+            })
+            .collect(),
+    )?;
+
     w.write_str("# EOF\n")?;
     Ok(())
 }
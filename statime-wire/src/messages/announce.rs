@@ -4,16 +4,29 @@ use crate::{
     common::{ClockIdentity, ClockQuality, TimeSource, Timestamp},
 };
 
+/// An announce message, describing the grandmaster a master distributes the
+/// time of. These are used to select the best master.
+///
+/// For more details, see *IEEE1588-2019 section 13.5*
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AnnounceMessage {
+    /// The header of the message, whose flags describe the timescale.
     pub header: Header,
+    /// An estimate of the time the message was sent, may be zero.
     pub origin_timestamp: Timestamp,
+    /// Offset between TAI and UTC in seconds.
     pub current_utc_offset: i16,
+    /// First priority of the grandmaster, lower values are preferred.
     pub grandmaster_priority_1: u8,
+    /// Quality of the clock of the grandmaster.
     pub grandmaster_clock_quality: ClockQuality,
+    /// Second priority of the grandmaster, lower values are preferred.
     pub grandmaster_priority_2: u8,
+    /// Identity of the grandmaster.
     pub grandmaster_identity: ClockIdentity,
+    /// Number of boundary clocks between the grandmaster and the sender.
     pub steps_removed: u16,
+    /// The source of the time of the grandmaster.
     pub time_source: TimeSource,
 }
 
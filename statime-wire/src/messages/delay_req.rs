@@ -1,7 +1,11 @@
 use crate::{Error, common::Timestamp};
 
+/// A delay request message, sent to a master to measure the delay to it.
+///
+/// For more details, see *IEEE1588-2019 section 13.6*
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DelayReqMessage {
+    /// An estimate of the time the message was sent, may be zero.
     pub origin_timestamp: Timestamp,
 }
 
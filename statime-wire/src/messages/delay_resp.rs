@@ -3,9 +3,14 @@ use crate::{
     common::{PortIdentity, Timestamp},
 };
 
+/// A delay response message, the answer of a master to a delay request.
+///
+/// For more details, see *IEEE1588-2019 section 13.8*
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DelayRespMessage {
+    /// The time the delay request was received by the master.
     pub receive_timestamp: Timestamp,
+    /// Identity of the port that sent the delay request.
     pub requesting_port_identity: PortIdentity,
 }
 
@@ -1,7 +1,12 @@
 use crate::{Error, common::Timestamp};
 
+/// A follow up message, carrying the precise send time of the preceding
+/// sync message in two-step time transmission.
+///
+/// For more details, see *IEEE1588-2019 section 13.7*
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FollowUpMessage {
+    /// The time the corresponding sync message was sent.
     pub precise_origin_timestamp: Timestamp,
 }
 
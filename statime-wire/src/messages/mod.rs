@@ -1,14 +1,14 @@
 //! Ptp network messages
 
-pub(crate) use announce::*;
-pub(crate) use delay_req::*;
-pub(crate) use delay_resp::*;
-pub(crate) use follow_up::*;
+pub use announce::*;
+pub use delay_req::*;
+pub use delay_resp::*;
+pub use follow_up::*;
 pub use header::*;
 pub(crate) use p_delay_req::*;
 pub(crate) use p_delay_resp::*;
 pub(crate) use p_delay_resp_follow_up::*;
-pub(crate) use sync::*;
+pub use sync::*;
 
 use self::{management::ManagementMessage, signalling::SignalingMessage};
 use super::{Error, common::TlvSet};
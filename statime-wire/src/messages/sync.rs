@@ -1,7 +1,12 @@
 use crate::{Error, common::Timestamp};
 
+/// A sync message, sent by a master to distribute its time.
+///
+/// For more details, see *IEEE1588-2019 section 13.6*
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SyncMessage {
+    /// The time the message was sent, or an estimate of it when the
+    /// precise time follows in a follow up message.
     pub origin_timestamp: Timestamp,
 }
 
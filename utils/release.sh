@@ -1,4 +1,6 @@
 #!/usr/bin/env bash
 
 cargo publish -p ntp-proto
+cargo publish -p ntp-shm
+cargo publish -p ntp-mmsg
 cargo publish -p ntpd